@@ -0,0 +1,16 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // gRPC 管理接口：未设置 PROTOC 时使用随附的 protoc
+    // gRPC admin API: use the bundled protoc unless PROTOC is set
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/admin.proto");
+        if std::env::var_os("PROTOC").is_none() {
+            let protoc = protoc_bin_vendored::protoc_bin_path().expect("bundled protoc");
+            // SAFETY: 构建脚本是单线程的 / the build script is single-threaded
+            unsafe { std::env::set_var("PROTOC", protoc) };
+        }
+        tonic_prost_build::compile_protos("proto/admin.proto").expect("compile proto/admin.proto");
+    }
+}
@@ -0,0 +1,19 @@
+//! 请求快速解析的模糊测试 / Fuzz target for the quick query parser
+//!
+//! 运行 / Run: `cargo +nightly fuzz run parse_quick`
+
+#![no_main]
+
+use kixdns::proto_utils::{parse_quick, question_bytes, udp_payload_limit};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buf = [0u8; 256];
+    if let Some(q) = parse_quick(data, &mut buf) {
+        // 快速解析得到的 qname 必须是不含空标签的 ASCII / The quick qname must be ASCII without empty labels
+        assert!(q.qname_bytes.is_ascii());
+        assert!(q.qname_bytes.is_empty() || q.qname_bytes.split(|b| *b == b'.').all(|l| !l.is_empty()));
+    }
+    let _ = question_bytes(data);
+    let _ = udp_payload_limit(data);
+});
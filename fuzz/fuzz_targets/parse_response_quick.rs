@@ -0,0 +1,26 @@
+//! 应答快速解析与 TTL 改写的模糊测试 / Fuzz target for quick response parsing and TTL rewriting
+//!
+//! 运行 / Run: `cargo +nightly fuzz run parse_response_quick`
+
+#![no_main]
+
+use kixdns::proto_utils::{
+    cap_all_ttls, echo_client_opt, negative_ttl, pad_edns, parse_response_quick, patch_all_ttls, set_all_ttls,
+    truncate_for_udp,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(r) = parse_response_quick(data) {
+        assert!(r.min_ttl <= r.max_ttl);
+    }
+    let _ = negative_ttl(data);
+    let _ = truncate_for_udp(data, data);
+    let _ = echo_client_opt(data, true, true);
+    let _ = pad_edns(data, 128);
+    let mut packet = data.to_vec();
+    patch_all_ttls(&mut packet, 30);
+    cap_all_ttls(&mut packet, 60);
+    set_all_ttls(&mut packet, 5);
+    assert_eq!(packet.len(), data.len());
+});
@@ -0,0 +1,460 @@
+//! 管理 HTTP 接口 / Admin HTTP API
+//!
+//! 一个极简的 HTTP/1.1 服务器（每个连接处理一个请求），返回 JSON，用于运行时查询和控制。
+//! 仅应绑定在可信地址上（如 127.0.0.1），本身不做认证。
+//! A minimal HTTP/1.1 server (one request per connection) returning JSON for runtime
+//! inspection and control. It performs no authentication, so bind it to a trusted address
+//! (e.g. 127.0.0.1) only.
+
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use ipnet::IpNet;
+use serde_json::{Value, json};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, info};
+
+use crate::cluster::{self, ClusterEvent};
+use crate::engine::Engine;
+use crate::engine::live_queries::QueryFilter;
+use crate::engine::runtime_rules::RuntimeRuleSpec;
+
+/// 请求头最大长度 / Max request head size
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+/// 请求体最大长度 / Max request body size
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+/// 单个连接的读取超时 / Read timeout per connection
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 解析后的管理请求 / Parsed admin request
+#[derive(Debug)]
+pub struct AdminRequest {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl AdminRequest {
+    /// 获取查询参数 / Get a query parameter
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// 是否为其他节点复制来的操作（不再继续复制）/ Whether the operation was replicated from another node (and is not forwarded again)
+    fn replicated(&self) -> bool {
+        matches!(self.param("replicated"), Some("1") | Some("true"))
+    }
+}
+
+/// 管理接口响应 / Admin response
+#[derive(Debug)]
+pub struct AdminResponse {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl AdminResponse {
+    pub fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    pub fn error(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message.into() }),
+        }
+    }
+}
+
+/// 在指定地址启动管理接口；config_path 供 POST /reload 使用
+/// Start the admin API on the given address; config_path is used by POST /reload
+pub async fn serve(bind: SocketAddr, engine: Engine, config_path: PathBuf) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("bind admin api {}", bind))?;
+    info!(bind = %bind, "admin api started");
+    run(listener, engine, config_path).await
+}
+
+/// 接受连接循环 / Accept loop
+pub async fn run(listener: TcpListener, engine: Engine, config_path: PathBuf) -> anyhow::Result<()> {
+    let config_path: Arc<Path> = Arc::from(config_path);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let engine = engine.clone();
+        let config_path = Arc::clone(&config_path);
+        tokio::spawn(async move {
+            if let Err(e) = handle_conn(stream, &engine, &config_path).await {
+                debug!(client_ip = %peer.ip(), error = %e, "admin connection error");
+            }
+        });
+    }
+}
+
+async fn handle_conn(mut stream: TcpStream, engine: &Engine, config_path: &Path) -> anyhow::Result<()> {
+    let req = match tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(req)) => req,
+        Ok(Err(e)) => {
+            write_response(&mut stream, &AdminResponse::error(400, e.to_string())).await?;
+            return Ok(());
+        }
+        Err(_) => return Ok(()),
+    };
+    if req.method == "GET" && req.path == "/queries/stream" {
+        return stream_queries(stream, engine, &req).await;
+    }
+    let resp = route(engine, config_path, &req).await;
+    write_response(&mut stream, &resp).await
+}
+
+/// SSE 保活注释的间隔，也用于发现已断开的客户端 / Interval of SSE keep-alive comments, which also detect disconnected clients
+const SSE_KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// GET /queries/stream?client=10.0.0.0/8&domain=example.com&sample=10
+///
+/// 以 SSE（text/event-stream）持续推送实时查询事件，每个事件为一行 JSON；连接保持到客户端断开。
+/// 订阅者跟不上时丢弃的事件数以 `dropped` 事件告知。
+/// Streams live query events as SSE (text/event-stream), one JSON object per event, until the
+/// client disconnects. Events dropped because the subscriber fell behind are reported with a
+/// `dropped` event.
+async fn stream_queries(mut stream: TcpStream, engine: &Engine, req: &AdminRequest) -> anyhow::Result<()> {
+    let client = match req.param("client").map(parse_client_filter).transpose() {
+        Ok(client) => client,
+        Err(e) => return write_response(&mut stream, &AdminResponse::error(400, e.to_string())).await,
+    };
+    let filter = QueryFilter {
+        client,
+        domain: req.param("domain").filter(|d| !d.is_empty()).map(str::to_string),
+        sample: req
+            .param("sample")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(1)
+            .max(1),
+    };
+    let mut events = engine.live_queries.subscribe();
+    stream
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n")
+        .await?;
+    debug!(filter = ?filter, "live query stream opened");
+
+    let mut matched: u64 = 0;
+    loop {
+        let chunk = match tokio::time::timeout(SSE_KEEPALIVE, events.recv()).await {
+            Ok(Ok(event)) => {
+                if !filter.matches(&event) {
+                    continue;
+                }
+                let keep = matched.is_multiple_of(filter.sample);
+                matched += 1;
+                if !keep {
+                    continue;
+                }
+                format!("data: {}\n\n", event.to_json(&engine.privacy))
+            }
+            Ok(Err(RecvError::Lagged(n))) => format!("event: dropped\ndata: {}\n\n", n),
+            Ok(Err(RecvError::Closed)) => return Ok(()),
+            Err(_) => ": keepalive\n\n".to_string(),
+        };
+        if stream.write_all(chunk.as_bytes()).await.is_err() {
+            debug!("live query stream closed");
+            return Ok(());
+        }
+    }
+}
+
+/// 单个地址或网段 / A single address or a network
+fn parse_client_filter(value: &str) -> anyhow::Result<IpNet> {
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Ok(IpNet::from(ip));
+    }
+    value.parse::<IpNet>().with_context(|| format!("invalid client filter {}", value))
+}
+
+async fn read_request(stream: &mut TcpStream) -> anyhow::Result<AdminRequest> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 4096];
+    let head_end = loop {
+        if let Some(pos) = find_head_end(&buf) {
+            break pos;
+        }
+        if buf.len() > MAX_HEAD_SIZE {
+            anyhow::bail!("request head too large");
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            anyhow::bail!("connection closed before request head");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+
+    let head = std::str::from_utf8(&buf[..head_end]).context("request head is not utf-8")?;
+    let mut req = parse_head(head)?;
+
+    let content_length = head
+        .lines()
+        .skip(1)
+        .filter_map(|l| l.split_once(':'))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("content-length"))
+        .map(|(_, v)| v.trim().parse::<usize>())
+        .transpose()
+        .context("invalid content-length")?
+        .unwrap_or(0);
+    if content_length > MAX_BODY_SIZE {
+        anyhow::bail!("request body too large");
+    }
+
+    let mut body = buf[head_end + 4..].to_vec();
+    while body.len() < content_length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            anyhow::bail!("connection closed before request body");
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(content_length);
+    req.body = body;
+    Ok(req)
+}
+
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n")
+}
+
+/// 解析请求行 / Parse the request line
+fn parse_head(head: &str) -> anyhow::Result<AdminRequest> {
+    let line = head.lines().next().context("empty request")?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().context("missing method")?.to_ascii_uppercase();
+    let target = parts.next().context("missing request target")?;
+    let (path, query) = match target.split_once('?') {
+        Some((p, q)) => (p, q),
+        None => (target, ""),
+    };
+    let query = url::form_urlencoded::parse(query.as_bytes())
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    Ok(AdminRequest {
+        method,
+        path: path.trim_end_matches('/').to_string(),
+        query,
+        body: Vec::new(),
+    })
+}
+
+async fn write_response(stream: &mut TcpStream, resp: &AdminResponse) -> anyhow::Result<()> {
+    let body = serde_json::to_vec_pretty(&resp.body)?;
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        resp.status,
+        reason_phrase(resp.status),
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+/// 路由分发 / Route dispatch
+pub async fn route(engine: &Engine, config_path: &Path, req: &AdminRequest) -> AdminResponse {
+    match (req.method.as_str(), req.path.as_str()) {
+        ("POST", "/reload") => reload(engine, config_path, req).await,
+        (_, "/reload") => AdminResponse::error(405, "method not allowed"),
+        ("GET", "/healthz") => AdminResponse::ok(json!({ "status": "ok" })),
+        (_, "/healthz") => AdminResponse::error(405, "method not allowed"),
+        ("GET", "/readyz") => readyz(engine).await,
+        (_, "/readyz") => AdminResponse::error(405, "method not allowed"),
+        ("GET", "/stats/domains") => domain_stats(engine, req),
+        (_, "/stats/domains") => AdminResponse::error(405, "method not allowed"),
+        ("GET", "/stats") => AdminResponse::ok(engine.stats_snapshot()),
+        ("POST", "/stats/dump") => match engine.dump_stats() {
+            Ok(()) => AdminResponse::ok(json!({ "dumped": true })),
+            Err(e) => AdminResponse::error(500, e.to_string()),
+        },
+        (_, "/stats") | (_, "/stats/dump") => AdminResponse::error(405, "method not allowed"),
+        ("GET", "/stats/cache") => AdminResponse::ok(json!(engine.cache_metrics.snapshot(&engine.cache))),
+        (_, "/stats/cache") => AdminResponse::error(405, "method not allowed"),
+        ("GET", "/stats/upstreams") => upstream_pool_stats(engine),
+        (_, "/stats/upstreams") => AdminResponse::error(405, "method not allowed"),
+        (_, "/queries/stream") => AdminResponse::error(405, "method not allowed"),
+        ("GET", "/config") => match engine.reload_events.current() {
+            Some(cfg) => AdminResponse::ok(json!(cfg)),
+            None => AdminResponse::error(409, "current config is not known yet"),
+        },
+        (_, "/config") => AdminResponse::error(405, "method not allowed"),
+        ("POST", "/cache/purge") => purge_cache(engine, req),
+        (_, "/cache/purge") => AdminResponse::error(405, "method not allowed"),
+        ("GET", "/rules/runtime") => list_runtime_rules(engine),
+        ("POST", "/rules/runtime") => add_runtime_rule(engine, req),
+        ("DELETE", "/rules/runtime") => remove_runtime_rule(engine, req),
+        (_, "/rules/runtime") => AdminResponse::error(405, "method not allowed"),
+        _ => AdminResponse::error(404, format!("no route for {} {}", req.method, req.path)),
+    }
+}
+
+/// GET /readyz
+///
+/// 全部检查通过时返回 200，否则返回 503；响应体列出每项检查。配置在引擎创建前已编译，重载失败时保留旧配置，
+/// 因此 config 一项恒为 true。
+/// 200 when every check passes, 503 otherwise, with each check listed in the body. The config
+/// is compiled before the engine exists and a failed reload keeps the old one, so the config
+/// check is always true.
+async fn readyz(engine: &Engine) -> AdminResponse {
+    let readiness = engine.state.load().pipeline.settings.readiness.clone();
+    let listeners = engine.health.listeners_bound();
+    let upstream = engine
+        .health
+        .upstream_healthy(Duration::from_secs(readiness.upstream_window_secs));
+    let mut checks = json!({ "config": true, "listeners": listeners, "upstream": upstream });
+    let mut ready = listeners && upstream;
+
+    if let Some(domain) = readiness.probe_domain.as_deref() {
+        let timeout = Duration::from_millis(readiness.probe_timeout_ms);
+        let bind = engine.watch_listen_addrs().borrow().udp.clone();
+        let probe = match bind.parse::<SocketAddr>() {
+            Ok(bind) => crate::engine::health::self_query(bind, domain, timeout).await,
+            Err(e) => Err(anyhow::Error::new(e).context("parse bind addr")),
+        };
+        checks["probe"] = match probe {
+            Ok(rcode) => json!({ "ok": true, "rcode": rcode.to_string() }),
+            Err(e) => {
+                ready = false;
+                json!({ "ok": false, "error": format!("{:#}", e) })
+            }
+        };
+    }
+
+    let body = json!({ "ready": ready, "checks": checks });
+    if ready {
+        AdminResponse::ok(body)
+    } else {
+        AdminResponse { status: 503, body }
+    }
+}
+
+/// POST /reload
+///
+/// 重新读取配置文件及其引用的列表文件；失败时保留旧配置并返回 409。
+/// Re-read the config file and the list files it references; on failure the old config is
+/// kept and 409 is returned.
+async fn reload(engine: &Engine, config_path: &Path, req: &AdminRequest) -> AdminResponse {
+    let path = config_path.to_path_buf();
+    let reloader = engine.clone();
+    let loaded = match tokio::task::spawn_blocking(move || crate::watcher::reload_from_file(&path, &reloader)).await {
+        Ok(loaded) => loaded,
+        Err(e) => return AdminResponse::error(500, e.to_string()),
+    };
+    match loaded {
+        Ok((cfg, diff)) => {
+            info!(event = "config_reloaded", source = "admin", "config reloaded");
+            if !req.replicated() {
+                cluster::replicate(engine, ClusterEvent::Reload);
+            }
+            AdminResponse::ok(json!({ "version": cfg.version, "diff": diff }))
+        }
+        Err(err) => {
+            engine.reload_events.record_failure(&err);
+            AdminResponse::error(409, format!("{:#}", err))
+        }
+    }
+}
+
+/// GET /stats/domains?flagged=true&limit=100
+fn domain_stats(engine: &Engine, req: &AdminRequest) -> AdminResponse {
+    let Some(stats) = engine.domain_stats.as_ref() else {
+        return AdminResponse::error(409, "domain_stats_enabled is false");
+    };
+    let flagged_only = matches!(req.param("flagged"), Some("true") | Some("1"));
+    let limit = req
+        .param("limit")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(100);
+    AdminResponse::ok(json!({ "domains": stats.report(flagged_only, limit, engine.privacy.min_qname_count()) }))
+}
+
+/// GET /stats/upstreams
+///
+/// TCP/DoT/DoQ 连接池状态；DoH 连接由 HTTP 客户端内部管理，不在此列出。
+/// TCP/DoT/DoQ pool state; DoH connections are managed inside the HTTP client and not listed.
+fn upstream_pool_stats(engine: &Engine) -> AdminResponse {
+    let mut pools = engine.tcp_mux.pool_stats();
+    pools.extend(engine.dot_mux.pool_stats());
+    pools.extend(engine.doq_client.pool_stats());
+    AdminResponse::ok(json!({ "pools": pools, "traffic": engine.upstream_stats.report() }))
+}
+
+/// POST /cache/purge?domain=example.com（缺省 domain 时清空缓存 / clears the whole cache without domain）
+fn purge_cache(engine: &Engine, req: &AdminRequest) -> AdminResponse {
+    let domain = req.param("domain").filter(|d| !d.is_empty());
+    let removed = crate::cache::purge(&engine.cache, domain);
+    info!(event = "cache_purged", domain = domain.unwrap_or("*"), removed, "cache purged");
+    AdminResponse::ok(json!({ "removed": removed }))
+}
+
+/// GET /rules/runtime
+fn list_runtime_rules(engine: &Engine) -> AdminResponse {
+    AdminResponse::ok(json!({ "rules": engine.runtime_rules.list() }))
+}
+
+/// POST /rules/runtime  {"domain": "...", "action": {...}, "ttl_secs": 7200, "until_reload": false}
+fn add_runtime_rule(engine: &Engine, req: &AdminRequest) -> AdminResponse {
+    let spec: RuntimeRuleSpec = match serde_json::from_slice(&req.body) {
+        Ok(spec) => spec,
+        Err(e) => return AdminResponse::error(400, format!("invalid rule: {}", e)),
+    };
+    // 指定 ID 只用于集群复制 / Chosen IDs are only for cluster replication
+    if !req.replicated() && (spec.id.is_some() || spec.origin.is_some()) {
+        return AdminResponse::error(400, "id and origin are only accepted on replicated requests");
+    }
+    let domain = spec.domain.clone();
+    match engine.runtime_rules.add(spec.clone()) {
+        Ok(id) => {
+            info!(event = "runtime_rule_added", id, domain = %domain, "runtime rule added");
+            if !req.replicated() {
+                cluster::replicate(engine, ClusterEvent::RuleAdded { id, spec });
+            }
+            AdminResponse::ok(json!({ "id": id }))
+        }
+        Err(e) => AdminResponse::error(400, e.to_string()),
+    }
+}
+
+/// DELETE /rules/runtime?id=N[&origin=NODE]
+fn remove_runtime_rule(engine: &Engine, req: &AdminRequest) -> AdminResponse {
+    let Some(id) = req.param("id").and_then(|v| v.parse::<u64>().ok()) else {
+        return AdminResponse::error(400, "missing or invalid id");
+    };
+    let origin = req.param("origin");
+    if engine.runtime_rules.remove_from(origin, id) {
+        info!(event = "runtime_rule_removed", id, origin, "runtime rule removed");
+        // 复制来的规则由来源节点负责复制删除 / Deleting replicated rules is replicated by their origin node
+        if !req.replicated() && origin.is_none() {
+            cluster::replicate(engine, ClusterEvent::RuleRemoved { id });
+        }
+        AdminResponse::ok(json!({ "removed": id }))
+    } else {
+        AdminResponse::error(404, format!("no runtime rule with id {}", id))
+    }
+}
@@ -0,0 +1,297 @@
+//! gRPC 管理接口 / gRPC admin API
+//!
+//! 与管理 HTTP 接口对应的 gRPC 服务（定义见 `proto/admin.proto`），便于编排环境中使用类型化客户端，
+//! 并额外提供统计推送流。仅在 `grpc` feature 下编译；本身不做认证，仅应绑定在可信地址上。
+//! A gRPC counterpart of the admin HTTP API (defined in `proto/admin.proto`) for orchestrated
+//! environments that want typed clients, plus a stats stream. Only built with the `grpc`
+//! feature; it performs no authentication, so bind it to a trusted address only.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::time::Duration;
+
+use anyhow::Context;
+use futures::Stream;
+use serde_json::{Value, json};
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use crate::engine::Engine;
+use crate::engine::runtime_rules::RuntimeRuleSpec;
+
+pub mod pb {
+    tonic::include_proto!("kixdns.admin.v1");
+}
+
+use pb::admin_server::{Admin, AdminServer};
+
+/// 统计推送的默认与最小间隔 / Default and minimum stats stream interval
+const DEFAULT_STREAM_INTERVAL_MS: u32 = 1000;
+const MIN_STREAM_INTERVAL_MS: u32 = 100;
+
+/// gRPC 管理服务 / gRPC admin service
+pub struct AdminService {
+    engine: Engine,
+    /// 配置文件路径，供 Reload 使用 / Config file path used by Reload
+    config_path: PathBuf,
+}
+
+impl AdminService {
+    pub fn new(engine: Engine, config_path: PathBuf) -> Self {
+        Self { engine, config_path }
+    }
+}
+
+/// 在指定地址启动 gRPC 管理接口 / Start the gRPC admin API on the given address
+pub async fn serve(bind: SocketAddr, engine: Engine, config_path: PathBuf) -> anyhow::Result<()> {
+    info!(bind = %bind, "grpc admin api started");
+    tonic::transport::Server::builder()
+        .add_service(AdminServer::new(AdminService::new(engine, config_path)))
+        .serve(bind)
+        .await
+        .with_context(|| format!("serve grpc admin api {}", bind))
+}
+
+fn cache_stats(v: &Value) -> pb::CacheStats {
+    let u = |key: &str| v[key].as_u64().unwrap_or(0);
+    pb::CacheStats {
+        capacity: u("capacity"),
+        entries: u("entries"),
+        insertions: u("insertions"),
+        insertions_per_sec: v["insertions_per_sec"].as_f64().unwrap_or(0.0),
+        evicted_size: u("evicted_size"),
+        evicted_expired: u("evicted_expired"),
+        replaced: u("replaced"),
+        invalidated: u("invalidated"),
+        entries_per_pipeline: v["entries_per_pipeline"]
+            .as_object()
+            .map(|m| m.iter().map(|(k, n)| (k.clone(), n.as_u64().unwrap_or(0))).collect())
+            .unwrap_or_default(),
+    }
+}
+
+/// 由统计快照 JSON 生成消息 / Build the message from the stats snapshot JSON
+fn stats_snapshot(engine: &Engine) -> pb::StatsSnapshot {
+    let v = engine.stats_snapshot();
+    pb::StatsSnapshot {
+        timestamp: v["timestamp"].as_u64().unwrap_or(0),
+        requests_total: v["requests"]["total"].as_u64().unwrap_or(0),
+        requests_inflight: v["requests"]["inflight"].as_u64().unwrap_or(0),
+        fastpath_hits: v["requests"]["fastpath_hits"].as_u64().unwrap_or(0),
+        upstream_calls: v["upstream"]["calls"].as_u64().unwrap_or(0),
+        upstream_avg_latency_us: v["upstream"]["avg_latency_us"].as_u64().unwrap_or(0),
+        dropped_requests: v["flow_control"]["dropped_requests"].as_u64().unwrap_or(0),
+        cache: Some(cache_stats(&v["cache"])),
+        json: v.to_string(),
+    }
+}
+
+type StatsStream = Pin<Box<dyn Stream<Item = Result<pb::StatsSnapshot, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn reload(&self, _req: Request<pb::ReloadRequest>) -> Result<Response<pb::ReloadReply>, Status> {
+        let engine = self.engine.clone();
+        let path = self.config_path.clone();
+        let loaded = tokio::task::spawn_blocking(move || crate::watcher::reload_from_file(&path, &engine))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let (cfg, diff) = match loaded {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                self.engine.reload_events.record_failure(&err);
+                return Err(Status::failed_precondition(format!("{:#}", err)));
+            }
+        };
+        info!(event = "config_reloaded", source = "grpc", "config reloaded");
+        crate::cluster::replicate(&self.engine, crate::cluster::ClusterEvent::Reload);
+        let diff = diff.unwrap_or_default();
+        Ok(Response::new(pb::ReloadReply {
+            version: cfg.version.unwrap_or_default(),
+            pipelines_added: diff.pipelines_added,
+            pipelines_removed: diff.pipelines_removed,
+            pipelines_changed: diff.pipelines_changed,
+            settings_changed: diff.settings_changed,
+        }))
+    }
+
+    async fn get_stats(&self, _req: Request<pb::GetStatsRequest>) -> Result<Response<pb::StatsSnapshot>, Status> {
+        Ok(Response::new(stats_snapshot(&self.engine)))
+    }
+
+    type StreamStatsStream = StatsStream;
+
+    async fn stream_stats(
+        &self,
+        req: Request<pb::StreamStatsRequest>,
+    ) -> Result<Response<Self::StreamStatsStream>, Status> {
+        let interval_ms = match req.into_inner().interval_ms {
+            0 => DEFAULT_STREAM_INTERVAL_MS,
+            ms => ms.max(MIN_STREAM_INTERVAL_MS),
+        };
+        let mut ticker = tokio::time::interval(Duration::from_millis(interval_ms as u64));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let stream = futures::stream::unfold((self.engine.clone(), ticker), |(engine, mut ticker)| async move {
+            ticker.tick().await;
+            let snapshot = stats_snapshot(&engine);
+            Some((Ok(snapshot), (engine, ticker)))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn get_cache_stats(&self, _req: Request<pb::GetCacheStatsRequest>) -> Result<Response<pb::CacheStats>, Status> {
+        let snapshot = json!(self.engine.cache_metrics.snapshot(&self.engine.cache));
+        Ok(Response::new(cache_stats(&snapshot)))
+    }
+
+    async fn purge_cache(&self, req: Request<pb::PurgeCacheRequest>) -> Result<Response<pb::PurgeCacheReply>, Status> {
+        let domain = req.into_inner().domain;
+        let domain = Some(domain.as_str()).filter(|d| !d.is_empty());
+        let removed = crate::cache::purge(&self.engine.cache, domain);
+        info!(event = "cache_purged", domain = domain.unwrap_or("*"), removed, "cache purged");
+        Ok(Response::new(pb::PurgeCacheReply { removed }))
+    }
+
+    async fn list_runtime_rules(
+        &self,
+        _req: Request<pb::ListRuntimeRulesRequest>,
+    ) -> Result<Response<pb::ListRuntimeRulesReply>, Status> {
+        let rules = self
+            .engine
+            .runtime_rules
+            .list()
+            .into_iter()
+            .map(|r| pb::RuntimeRule {
+                id: r.id,
+                domain: r.domain,
+                action_json: r.action.to_string(),
+                age_secs: r.age_secs,
+                expires_in_secs: r.expires_in_secs,
+                until_reload: r.until_reload,
+                note: r.note,
+            })
+            .collect();
+        Ok(Response::new(pb::ListRuntimeRulesReply { rules }))
+    }
+
+    async fn add_runtime_rule(
+        &self,
+        req: Request<pb::AddRuntimeRuleRequest>,
+    ) -> Result<Response<pb::AddRuntimeRuleReply>, Status> {
+        let req = req.into_inner();
+        let action = serde_json::from_str(&req.action_json)
+            .map_err(|e| Status::invalid_argument(format!("invalid action_json: {}", e)))?;
+        let spec = RuntimeRuleSpec {
+            id: None,
+            origin: None,
+            domain: req.domain,
+            action,
+            ttl_secs: req.ttl_secs,
+            until_reload: req.until_reload,
+            note: req.note,
+        };
+        let domain = spec.domain.clone();
+        let id = self
+            .engine
+            .runtime_rules
+            .add(spec.clone())
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        info!(event = "runtime_rule_added", id, domain = %domain, "runtime rule added");
+        crate::cluster::replicate(&self.engine, crate::cluster::ClusterEvent::RuleAdded { id, spec });
+        Ok(Response::new(pb::AddRuntimeRuleReply { id }))
+    }
+
+    async fn remove_runtime_rule(
+        &self,
+        req: Request<pb::RemoveRuntimeRuleRequest>,
+    ) -> Result<Response<pb::RemoveRuntimeRuleReply>, Status> {
+        let id = req.into_inner().id;
+        if !self.engine.runtime_rules.remove(id) {
+            return Err(Status::not_found(format!("no runtime rule with id {}", id)));
+        }
+        info!(event = "runtime_rule_removed", id, "runtime rule removed");
+        crate::cluster::replicate(&self.engine, crate::cluster::ClusterEvent::RuleRemoved { id });
+        Ok(Response::new(pb::RemoveRuntimeRuleReply {}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use crate::cache::CacheEntry;
+    use crate::matcher::RuntimePipelineConfig;
+
+    fn entry(qname: &str) -> Arc<CacheEntry> {
+        Arc::new(CacheEntry {
+            bytes: bytes::Bytes::from_static(b"x"),
+            compressed: false,
+            rcode: hickory_proto::op::ResponseCode::NoError,
+            source: Arc::from("test"),
+            upstream: None,
+            qname: Arc::from(qname),
+            pipeline_id: Arc::from("main"),
+            qtype: 1,
+            qclass: 1,
+            inserted_at: Instant::now(),
+            original_ttl: 60,
+            refresh_ttl: 60,
+            aged: Default::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_cache_and_runtime_rule_rpcs() {
+        // Arrange
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(json!({ "pipelines": [] })).unwrap();
+        let engine = Engine::new(RuntimePipelineConfig::from_config(cfg).unwrap(), "lbl".to_string());
+        engine.cache.insert(1, entry("a.example.com"));
+        engine.cache.insert(2, entry("Example.com."));
+        engine.cache.insert(3, entry("notexample.com"));
+        let service = AdminService::new(engine.clone(), PathBuf::from("unused.json"));
+
+        // Act
+        let purged = service
+            .purge_cache(Request::new(pb::PurgeCacheRequest { domain: "example.com".to_string() }))
+            .await
+            .unwrap()
+            .into_inner();
+        let added = service
+            .add_runtime_rule(Request::new(pb::AddRuntimeRuleRequest {
+                domain: "ads.test".to_string(),
+                action_json: r#"{"type":"deny"}"#.to_string(),
+                ttl_secs: Some(60),
+                until_reload: false,
+                note: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let listed = service
+            .list_runtime_rules(Request::new(pb::ListRuntimeRulesRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        let bad_action = service
+            .add_runtime_rule(Request::new(pb::AddRuntimeRuleRequest {
+                domain: "x.test".to_string(),
+                action_json: "{".to_string(),
+                ..Default::default()
+            }))
+            .await;
+        let stats = service.get_stats(Request::new(pb::GetStatsRequest {})).await.unwrap().into_inner();
+
+        // Assert
+        assert_eq!(purged.removed, 2);
+        assert!(engine.cache.get(&3).is_some());
+        assert_eq!(listed.rules.len(), 1);
+        assert_eq!(listed.rules[0].id, added.id);
+        assert_eq!(listed.rules[0].action_json, r#"{"type":"deny"}"#);
+        assert_eq!(bad_action.unwrap_err().code(), tonic::Code::InvalidArgument);
+        assert_eq!(stats.cache.unwrap().entries, 1);
+    }
+}
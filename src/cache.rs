@@ -1,13 +1,21 @@
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 
+use arc_swap::ArcSwapOption;
 use bytes::Bytes;
 use hickory_proto::op::ResponseCode;
+use moka::notification::RemovalCause;
 use moka::sync::Cache;
+use serde::Serialize;
 
 #[derive(Debug, Clone)]
 pub struct CacheEntry {
+    /// 响应报文；compressed 为 true 时为 LZ4 压缩数据，读取请用 payload()
+    /// Response packet; LZ4-compressed when compressed is true, read it via payload()
     pub bytes: Bytes,
+    /// bytes 是否经过 LZ4 压缩 / Whether bytes is LZ4-compressed
+    pub compressed: bool,
     pub rcode: ResponseCode,
     pub source: Arc<str>,
     /// Upstream that provided this response / 提供此响应的上游服务器
@@ -16,6 +24,7 @@ pub struct CacheEntry {
     pub qname: Arc<str>,
     pub pipeline_id: Arc<str>,
     pub qtype: u16,
+    pub qclass: u16,
     /// RFC 1035 §5.2: Record insertion time for TTL decrement / RFC 1035 §5.2：记录插入时间用于TTL递减
     pub inserted_at: Instant,
     /// Original minimum TTL from upstream response / 上游响应的原始最小TTL
@@ -24,6 +33,101 @@ pub struct CacheEntry {
     /// Original maximum TTL from upstream response / 上游响应的原始最大TTL
     /// Used for background refresh decisions / 用于后台刷新决策
     pub refresh_ttl: u32,
+    /// 按停留秒数递减 TTL 后的报文 / Packet with TTLs decremented by the residence seconds
+    pub aged: AgedPayload,
+}
+
+/// 最近一次按停留时间修正 TTL 的报文，同一秒内 EDNS 相同的缓存命中共享这一份拷贝
+/// The latest packet with TTLs aged by residence time; cache hits within the same second and
+/// with the same EDNS share this one copy
+#[derive(Debug, Default)]
+pub struct AgedPayload(ArcSwapOption<(AgedKey, Bytes)>);
+
+/// 修正后报文的适用条件：(停留秒数, 客户端带 EDNS, DO 位)
+/// What an aged packet was built for: (residence seconds, client sent EDNS, DO bit)
+type AgedKey = (u32, bool, bool);
+
+impl Clone for AgedPayload {
+    fn clone(&self) -> Self {
+        Self(ArcSwapOption::new(self.0.load_full()))
+    }
+}
+
+impl CacheEntry {
+    /// 报文超过阈值时压缩（0 表示不压缩），压缩无收益时保留原文
+    /// Compress the packet when it exceeds the threshold (0 disables); kept as-is when compression does not help
+    pub fn compress_above(mut self, threshold: usize) -> Self {
+        if threshold == 0 || self.compressed || self.bytes.len() < threshold {
+            return self;
+        }
+        let packed = lz4_flex::compress_prepend_size(&self.bytes);
+        if packed.len() < self.bytes.len() {
+            self.bytes = Bytes::from(packed);
+            self.compressed = true;
+        }
+        self
+    }
+
+    /// 取出原始响应报文（必要时解压）/ Get the original response packet (decompressing if needed)
+    #[inline]
+    pub fn payload(&self) -> Bytes {
+        if !self.compressed {
+            return self.bytes.clone();
+        }
+        match lz4_flex::decompress_size_prepended(&self.bytes) {
+            Ok(raw) => Bytes::from(raw),
+            // 压缩数据由本进程写入，解压失败说明内存损坏；返回空报文让调用方当作异常响应
+            // Compressed data is written by this process, so failure means corruption; an empty packet is treated as malformed
+            Err(_) => Bytes::new(),
+        }
+    }
+
+    /// 取出响应报文并按当前客户端的 EDNS 重建 OPT / Get the response packet with its OPT rebuilt for the current client's EDNS
+    #[inline]
+    pub fn payload_for_client(&self, edns_present: bool, dnssec_flags: u8) -> Bytes {
+        let payload = self.payload();
+        let dnssec_ok = dnssec_flags & crate::proto_utils::DNSSEC_FLAG_DO != 0;
+        match crate::proto_utils::echo_client_opt(&payload, edns_present, dnssec_ok) {
+            Some(rebuilt) => Bytes::from(rebuilt),
+            None => payload,
+        }
+    }
+
+    /// 按当前客户端重建 OPT 并按停留时间递减 TTL 的报文（RFC 1035 §5.2）。同一秒内 EDNS 相同的命中
+    /// 复用同一份报文，不再解压，发送时只需另外写入事务 ID，无需拷贝。
+    /// The packet with OPT rebuilt for the current client and TTLs decremented by residence time
+    /// (RFC 1035 §5.2). Hits within the same second and with the same EDNS reuse one packet without
+    /// decompressing, so sending it only needs the transaction ID written separately, without a copy.
+    pub fn aged_payload_for_client(&self, edns_present: bool, dnssec_flags: u8) -> Bytes {
+        let elapsed = self.inserted_at.elapsed().as_secs() as u32;
+        let dnssec_ok = dnssec_flags & crate::proto_utils::DNSSEC_FLAG_DO != 0;
+        let key = (elapsed, edns_present, dnssec_ok);
+        if let Some(aged) = self.aged.0.load().as_deref()
+            && aged.0 == key
+        {
+            return aged.1.clone();
+        }
+        let payload = self.payload();
+        let aged = match crate::proto_utils::echo_client_opt(&payload, edns_present, dnssec_ok) {
+            Some(mut rebuilt) => {
+                crate::proto_utils::patch_all_ttls(&mut rebuilt, elapsed);
+                Bytes::from(rebuilt)
+            }
+            None if elapsed == 0 => payload,
+            None => {
+                let mut aged = payload.to_vec();
+                crate::proto_utils::patch_all_ttls(&mut aged, elapsed);
+                Bytes::from(aged)
+            }
+        };
+        self.aged.0.store(Some(Arc::new((key, aged.clone()))));
+        aged
+    }
+
+    /// 按字节计容量时条目的权重：存储的报文长度 / Weight of an entry when capacity is counted in bytes: the stored packet length
+    fn weight(&self) -> u32 {
+        u32::try_from(self.bytes.len()).unwrap_or(u32::MAX).max(1)
+    }
 }
 
 /// Use u64 hash as key to avoid allocation during lookup / 使用 u64 哈希作为键以避免查找时的内存分配
@@ -31,11 +135,242 @@ pub struct CacheEntry {
 ///  性能优化：使用 Arc 包裹，将缓存命中的原子操作从 5 次减少到 1 次
 pub type DnsCache = Cache<u64, Arc<CacheEntry>>;
 
+/// 缓存插入与淘汰计数 / Cache insertion and eviction counters
+#[derive(Debug)]
+pub struct CacheMetrics {
+    started_at: Instant,
+    insertions: AtomicU64,
+    /// 因容量不足被淘汰 / Evicted because the cache was full
+    evicted_size: AtomicU64,
+    /// 因 cache_max_ttl 到期被移除 / Removed when cache_max_ttl expired
+    evicted_expired: AtomicU64,
+    /// 被同键新条目替换 / Replaced by a new entry with the same key
+    replaced: AtomicU64,
+    /// 主动失效 / Explicitly invalidated
+    invalidated: AtomicU64,
+}
+
+/// 缓存指标快照 / Cache metrics snapshot
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheMetricsSnapshot {
+    pub capacity: u64,
+    pub entries: u64,
+    pub weighted_size: u64,
+    pub insertions: u64,
+    pub insertions_per_sec: f64,
+    pub evicted_size: u64,
+    pub evicted_expired: u64,
+    pub replaced: u64,
+    pub invalidated: u64,
+    /// 各 pipeline 的条目数 / Entry count per pipeline
+    pub entries_per_pipeline: std::collections::BTreeMap<String, u64>,
+}
+
+impl Default for CacheMetrics {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            insertions: AtomicU64::new(0),
+            evicted_size: AtomicU64::new(0),
+            evicted_expired: AtomicU64::new(0),
+            replaced: AtomicU64::new(0),
+            invalidated: AtomicU64::new(0),
+        }
+    }
+}
+
+impl CacheMetrics {
+    #[inline]
+    pub fn record_insert(&self) {
+        self.insertions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_removal(&self, cause: RemovalCause) {
+        let counter = match cause {
+            RemovalCause::Size => &self.evicted_size,
+            RemovalCause::Expired => &self.evicted_expired,
+            RemovalCause::Replaced => &self.replaced,
+            RemovalCause::Explicit => &self.invalidated,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 生成快照；按 pipeline 统计需遍历缓存，仅供按需查询
+    /// Build a snapshot; per-pipeline counts walk the cache, so call on demand only
+    pub fn snapshot(&self, cache: &DnsCache) -> CacheMetricsSnapshot {
+        cache.run_pending_tasks();
+        let mut entries_per_pipeline = std::collections::BTreeMap::new();
+        for (_, entry) in cache.iter() {
+            *entries_per_pipeline
+                .entry(entry.pipeline_id.to_string())
+                .or_insert(0u64) += 1;
+        }
+        let insertions = self.insertions.load(Ordering::Relaxed);
+        let uptime = self.started_at.elapsed().as_secs_f64();
+        CacheMetricsSnapshot {
+            capacity: cache.policy().max_capacity().unwrap_or(0),
+            entries: cache.entry_count(),
+            weighted_size: cache.weighted_size(),
+            insertions,
+            insertions_per_sec: if uptime > 0.0 { insertions as f64 / uptime } else { 0.0 },
+            evicted_size: self.evicted_size.load(Ordering::Relaxed),
+            evicted_expired: self.evicted_expired.load(Ordering::Relaxed),
+            replaced: self.replaced.load(Ordering::Relaxed),
+            invalidated: self.invalidated.load(Ordering::Relaxed),
+            entries_per_pipeline,
+        }
+    }
+}
+
 /// 创建带 TTL 的 DNS 缓存 / Create DNS cache with TTL
 #[inline]
-pub fn new_cache(max_capacity: u64, ttl_secs: u64) -> DnsCache {
-    Cache::builder()
-        .max_capacity(max_capacity)
+/// max_bytes 大于 0 时按存储的报文字节数计容量（压缩后的条目占用更少），否则按条目数计
+/// With max_bytes above 0 capacity is counted in stored packet bytes (compressed entries take
+/// less of it), otherwise in entries
+pub fn new_cache(max_capacity: u64, max_bytes: u64, ttl_secs: u64, metrics: Arc<CacheMetrics>) -> DnsCache {
+    let builder = Cache::builder()
         .time_to_live(Duration::from_secs(ttl_secs))
-        .build()
+        .eviction_listener(move |_key, _value, cause| metrics.record_removal(cause));
+    if max_bytes > 0 {
+        builder
+            .max_capacity(max_bytes)
+            .weigher(|_key, entry: &Arc<CacheEntry>| entry.weight())
+            .build()
+    } else {
+        builder.max_capacity(max_capacity).build()
+    }
+}
+
+/// 删除 qname 等于 domain 或为其子域名的缓存条目（忽略大小写与结尾的点），domain 为 None 时清空缓存；
+/// 返回删除的条目数
+/// Remove cache entries whose qname is domain or a subdomain of it (ignoring case and the
+/// trailing dot), or every entry when domain is None; returns the number removed
+pub fn purge(cache: &DnsCache, domain: Option<&str>) -> u64 {
+    cache.run_pending_tasks();
+    let Some(domain) = domain else {
+        let removed = cache.entry_count();
+        cache.invalidate_all();
+        return removed;
+    };
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let mut removed = 0;
+    for (key, entry) in cache.iter() {
+        let qname = entry.qname.trim_end_matches('.').to_ascii_lowercase();
+        let matches = qname == domain
+            || (qname.len() > domain.len()
+                && qname.ends_with(domain.as_str())
+                && qname.as_bytes()[qname.len() - domain.len() - 1] == b'.');
+        if matches {
+            cache.invalidate(&*key);
+            removed += 1;
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(bytes: Bytes) -> CacheEntry {
+        CacheEntry {
+            bytes,
+            compressed: false,
+            rcode: ResponseCode::NoError,
+            source: Arc::from("test"),
+            upstream: None,
+            qname: Arc::from("example.com"),
+            pipeline_id: Arc::from("main"),
+            qtype: 16,
+            qclass: 1,
+            inserted_at: Instant::now(),
+            original_ttl: 60,
+            refresh_ttl: 60,
+            aged: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_compress_above_threshold_round_trips() {
+        // Arrange
+        let raw = Bytes::from(vec![b'a'; 2048]);
+        let small = Bytes::from_static(b"short");
+
+        // Act
+        let big = entry(raw.clone()).compress_above(512);
+        let kept = entry(small.clone()).compress_above(512);
+
+        // Assert
+        assert!(big.compressed);
+        assert!(big.bytes.len() < raw.len());
+        assert_eq!(big.payload(), raw);
+        assert!(!kept.compressed);
+        assert_eq!(kept.payload(), small);
+    }
+
+    #[test]
+    fn test_aged_payload_shared_within_a_second() {
+        // Arrange
+        use hickory_proto::op::Message;
+        use hickory_proto::rr::{Name, RData, Record, rdata::A};
+        let mut msg = Message::new();
+        msg.add_answer(Record::from_rdata(Name::from_ascii("example.com.").unwrap(), 60, RData::A(A::new(192, 0, 2, 1))));
+        let mut aged_entry = entry(Bytes::from(msg.to_vec().unwrap()));
+        aged_entry.inserted_at = Instant::now() - Duration::from_secs(10);
+        let fresh_entry = entry(aged_entry.bytes.clone());
+
+        // Act
+        let first = aged_entry.aged_payload_for_client(false, 0);
+        let second = aged_entry.aged_payload_for_client(false, 0);
+        let fresh = fresh_entry.aged_payload_for_client(false, 0);
+        let with_opt = aged_entry.aged_payload_for_client(true, 0);
+        let with_opt_again = aged_entry.aged_payload_for_client(true, 0);
+
+        // Assert
+        assert_eq!(Message::from_vec(&first).unwrap().answers()[0].ttl(), 50);
+        assert_eq!(first.as_ptr(), second.as_ptr());
+        assert_eq!(fresh.as_ptr(), fresh_entry.bytes.as_ptr());
+        assert_eq!(Message::from_vec(&with_opt).unwrap().answers()[0].ttl(), 50);
+        assert_eq!(with_opt.as_ptr(), with_opt_again.as_ptr());
+    }
+
+    #[test]
+    fn test_cache_max_bytes_weighs_stored_bytes() {
+        // Arrange
+        let metrics = Arc::new(CacheMetrics::default());
+        let cache = new_cache(100, 4096, 60, Arc::clone(&metrics));
+        let packed = entry(Bytes::from(vec![b'a'; 2048])).compress_above(512);
+        let plain = entry(Bytes::from(vec![b'b'; 1000]));
+        let expected = (packed.bytes.len() + plain.bytes.len()) as u64;
+
+        // Act
+        cache.insert(1, Arc::new(packed));
+        cache.insert(2, Arc::new(plain));
+        cache.run_pending_tasks();
+
+        // Assert
+        assert_eq!(cache.weighted_size(), expected);
+        assert!(expected < 2048);
+    }
+
+    #[test]
+    fn test_cache_metrics_counts_insertions_and_removals() {
+        // Arrange
+        let metrics = Arc::new(CacheMetrics::default());
+        let cache = new_cache(100, 0, 60, Arc::clone(&metrics));
+
+        // Act
+        for key in 0..3u64 {
+            metrics.record_insert();
+            cache.insert(key, Arc::new(entry(Bytes::from_static(b"resp"))));
+        }
+        cache.invalidate(&0);
+        let snapshot = metrics.snapshot(&cache);
+
+        // Assert
+        assert_eq!(snapshot.insertions, 3);
+        assert_eq!(snapshot.invalidated, 1);
+        assert_eq!(snapshot.entries, 2);
+        assert_eq!(snapshot.entries_per_pipeline.get("main"), Some(&2));
+    }
 }
@@ -0,0 +1,160 @@
+//! 管理操作的集群复制 / Cluster replication of admin operations
+//!
+//! 多个实例共享同一配置来源时，临时规则与列表刷新（配置重载）需要在所有节点上一致。本节点的管理接口
+//! 成功执行这些操作后，把同一操作以 HTTP 发给 `cluster.replicas` 中每个副本的管理接口，并带上
+//! `replicated=1` 防止副本继续转发。临时规则沿用本节点分配的 ID 并带上本节点的随机标识，副本按
+//! （来源节点, ID）保存，与副本自己的规则互不覆盖，按 ID 删除在各节点上指向同一条规则。
+//! When several instances share one config source, runtime rules and list refreshes (config
+//! reloads) must stay consistent across nodes. After this node's admin API performs one of
+//! them, the same operation is sent over HTTP to the admin API of every replica in
+//! `cluster.replicas`, tagged `replicated=1` so replicas do not forward it further. Runtime
+//! rules keep the ID assigned here together with this node's random identifier; replicas key
+//! them by (origin node, ID), apart from their own rules, so deleting by ID targets the same
+//! rule on every node.
+
+use std::sync::{LazyLock, OnceLock};
+use std::time::Duration;
+
+use crypto_box::aead::OsRng;
+use crypto_box::aead::rand_core::RngCore;
+use tracing::{debug, warn};
+
+use crate::engine::Engine;
+use crate::engine::runtime_rules::RuntimeRuleSpec;
+
+/// 需要复制的管理操作 / An admin operation to replicate
+#[derive(Debug, Clone)]
+pub enum ClusterEvent {
+    RuleAdded { id: u64, spec: RuntimeRuleSpec },
+    RuleRemoved { id: u64 },
+    Reload,
+}
+
+/// 本节点在复制中的标识（每次启动随机生成）/ This node's identifier in replication (random per process start)
+static NODE_ID: LazyLock<String> = LazyLock::new(|| format!("{:016x}", OsRng.next_u64()));
+
+/// 本节点的复制标识 / This node's replication identifier
+pub fn node_id() -> &'static str {
+    &NODE_ID
+}
+
+impl ClusterEvent {
+    /// 对应的 HTTP 方法、路径（含查询串）与请求体 / Matching HTTP method, path with query string, and body
+    fn request(&self) -> (reqwest::Method, String, Option<Vec<u8>>) {
+        match self {
+            ClusterEvent::RuleAdded { id, spec } => {
+                let spec = RuntimeRuleSpec {
+                    id: Some(*id),
+                    origin: Some(node_id().to_string()),
+                    ..spec.clone()
+                };
+                let body = serde_json::to_vec(&spec).unwrap_or_default();
+                (reqwest::Method::POST, "/rules/runtime?replicated=1".to_string(), Some(body))
+            }
+            ClusterEvent::RuleRemoved { id } => (
+                reqwest::Method::DELETE,
+                format!("/rules/runtime?id={}&origin={}&replicated=1", id, node_id()),
+                None,
+            ),
+            ClusterEvent::Reload => (reqwest::Method::POST, "/reload?replicated=1".to_string(), None),
+        }
+    }
+}
+
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new)
+}
+
+/// 把操作异步发给所有副本，失败只记录日志 / Send the operation to every replica in the background, failures are only logged
+pub fn replicate(engine: &Engine, event: ClusterEvent) {
+    let settings = engine.state.load().pipeline.settings.cluster.clone();
+    if settings.replicas.is_empty() {
+        return;
+    }
+    let (method, path, body) = event.request();
+    let timeout = Duration::from_millis(settings.timeout_ms);
+    for replica in settings.replicas {
+        let url = format!("http://{}{}", replica, path);
+        let mut req = client().request(method.clone(), &url).timeout(timeout);
+        if let Some(body) = &body {
+            req = req.header("content-type", "application/json").body(body.clone());
+        }
+        tokio::spawn(async move {
+            match req.send().await {
+                Ok(resp) if resp.status().is_success() => debug!(replica = %replica, url = %url, "admin operation replicated"),
+                Ok(resp) => warn!(replica = %replica, url = %url, status = %resp.status(), "replica rejected admin operation"),
+                Err(e) => warn!(replica = %replica, url = %url, error = %e, "admin operation replication failed"),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    use crate::admin::{AdminRequest, route};
+    use crate::matcher::RuntimePipelineConfig;
+
+    fn engine(settings: serde_json::Value) -> Engine {
+        let cfg: crate::config::PipelineConfig =
+            serde_json::from_value(serde_json::json!({ "settings": settings, "pipelines": [] })).unwrap();
+        Engine::new(RuntimePipelineConfig::from_config(cfg).unwrap(), "lbl".to_string())
+    }
+
+    fn request(method: &str, path: &str, query: &[(&str, &str)], body: &str) -> AdminRequest {
+        AdminRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    async fn wait_for(mut done: impl FnMut() -> bool) -> bool {
+        for _ in 0..100 {
+            if done() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn test_runtime_rules_replicate_with_primary_ids() {
+        // Arrange: A replica serving the admin API and a primary pointing at it
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let replica = engine(serde_json::json!({}));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let replica_addr = listener.local_addr().unwrap();
+        tokio::spawn(crate::admin::run(listener, replica.clone(), PathBuf::from("unused.json")));
+        let primary = engine(serde_json::json!({ "cluster": { "replicas": [replica_addr.to_string()] } }));
+        let config = Path::new("unused.json");
+        let body = r#"{"domain":"ads.test","action":{"type":"deny"}}"#;
+        route(&primary, config, &request("POST", "/rules/runtime", &[], body)).await;
+
+        // Act
+        let added = route(&primary, config, &request("POST", "/rules/runtime", &[], body)).await;
+        let id = added.body["id"].as_u64().unwrap();
+        let replicated = wait_for(|| {
+            replica.runtime_rules.list().iter().any(|r| r.id == id && r.origin.as_deref() == Some(node_id()))
+        })
+        .await;
+        let local = route(&replica, config, &request("POST", "/rules/runtime", &[], body)).await;
+        let chosen_id = route(&replica, config, &request("POST", "/rules/runtime", &[], r#"{"id":2,"origin":"x","domain":"a.test","action":{"type":"deny"}}"#)).await;
+        route(&primary, config, &request("DELETE", "/rules/runtime", &[("id", &id.to_string())], "")).await;
+        let removed = wait_for(|| replica.runtime_rules.list().iter().all(|r| r.id != id || r.origin.is_none())).await;
+
+        // Assert
+        assert_eq!(id, 2);
+        assert!(replicated);
+        assert!(removed);
+        assert_eq!(chosen_id.status, 400);
+        let listed = replica.runtime_rules.list();
+        assert_eq!(listed.len(), 2);
+        assert!(listed.iter().any(|r| r.id == local.body["id"].as_u64().unwrap()));
+    }
+}
@@ -1,16 +1,21 @@
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
 use anyhow::Context;
 use anyhow::Result;
 use ipnet::IpNet;
-use serde::Deserialize;
-use tracing::info;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PipelineConfig {
     #[serde(default)]
     pub version: Option<String>,
+    /// 严格模式：存在未知字段（如拼写错误的 matcher_opertor）时拒绝加载，缺省仅告警。 / Strict mode: reject the config when it has unknown fields (e.g. a misspelled matcher_opertor); by default they are only warned about
+    #[serde(default)]
+    pub strict: bool,
     #[serde(default)]
     pub settings: GlobalSettings,
     /// 多维优先级的 pipeline 选择规则（按顺序评估）。 / Multi-dimensional priority selection rules (evaluated in order)
@@ -18,6 +23,12 @@ pub struct PipelineConfig {
     pub pipeline_select: Vec<PipelineSelectRule>,
     #[serde(default)]
     pub pipelines: Vec<Pipeline>,
+    /// 客户端分组：组名 -> CIDR/IP 列表，供 client_group 匹配器引用。 / Client groups: name -> CIDR/IP list, referenced by the client_group matcher
+    #[serde(default)]
+    pub client_groups: HashMap<String, Vec<String>>,
+    /// 命名域名集合：集合名 -> 条目与文件，供 domain_set_ref 匹配器引用，只编译一次。 / Named domain sets: name -> entries and files, referenced by the domain_set_ref matcher and compiled once
+    #[serde(default)]
+    pub sets: HashMap<String, DomainSetConfig>,
 
     /// 后台刷新专用规则（可选）。如果未配置，将使用默认规则（Any 匹配 + Forward 到原始 upstream）。
     /// Background refresh dedicated rule (optional). If not configured, will use default rule (Any matcher + Forward to original upstream).
@@ -70,20 +81,53 @@ pub struct PipelineConfig {
     pub background_refresh_rule: Option<Rule>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct GlobalSettings {
     /// 最小TTL秒数，缺省0。 / Minimum TTL in seconds, defaults to 0
     #[serde(default = "default_min_ttl")]
     pub min_ttl: u32,
-    /// UDP监听地址，缺省0.0.0.0:5353，避免1024以下端口权限问题。 / UDP listen address, defaults to 0.0.0.0:5353, avoiding port permission issues below 1024
+    /// 最大TTL秒数，上游响应中更长的 TTL 会被截断到此值，缺省0表示不限制。 / Maximum TTL in seconds; longer upstream TTLs are capped to it, defaults to 0 (no cap)
+    #[serde(default)]
+    pub max_ttl: u32,
+    /// UDP监听地址（可用逗号分隔多个），缺省0.0.0.0:5353，避免1024以下端口权限问题。 / UDP listen address (comma-separated for several), defaults to 0.0.0.0:5353, avoiding port permission issues below 1024
     #[serde(default = "default_bind_udp")]
     pub bind_udp: String,
-    /// TCP监听地址，缺省0.0.0.0:5353。 / TCP listen address, defaults to 0.0.0.0:5353
+    /// TCP监听地址（可用逗号分隔多个），缺省0.0.0.0:5353。 / TCP listen address (comma-separated for several), defaults to 0.0.0.0:5353
     #[serde(default = "default_bind_tcp")]
     pub bind_tcp: String,
+    /// 监听 `[::]` 时的 IPv6 模式，缺省分别监听 IPv4 与仅 IPv6。 / IPv6 mode when listening on `[::]`, separate IPv4 and IPv6-only sockets by default
+    #[serde(default)]
+    pub ipv6_listen_mode: Ipv6ListenMode,
+    /// DNS-over-TLS 监听地址（可用逗号分隔多个，如 0.0.0.0:853），需同时配置证书与私钥，缺省不启用。 / DNS-over-TLS listen address (comma-separated for several, e.g. 0.0.0.0:853), needs the certificate and key, disabled by default
+    #[serde(default)]
+    pub bind_dot: Option<String>,
+    /// DNS-over-HTTPS（HTTP/2）监听地址（可用逗号分隔多个，如 0.0.0.0:443），需同时配置证书与私钥，缺省不启用。 / DNS-over-HTTPS (HTTP/2) listen address (comma-separated for several, e.g. 0.0.0.0:443), needs the certificate and key, disabled by default
+    #[serde(default)]
+    pub bind_doh: Option<String>,
+    /// DoH 查询的 URL 路径（默认 /dns-query）。 / URL path of DoH queries (default /dns-query)
+    #[serde(default = "default_doh_path")]
+    pub doh_path: String,
+    /// DoH 请求体与查询报文的最大字节数（默认 65535），超过时返回 413。 / Max bytes of a DoH request body and query (default 65535); larger requests get 413
+    #[serde(default = "default_doh_max_body_bytes")]
+    pub doh_max_body_bytes: usize,
+    /// DoH 每个连接的最大并发 HTTP/2 流（默认 100）。 / Max concurrent HTTP/2 streams per DoH connection (default 100)
+    #[serde(default = "default_doh_max_concurrent_streams")]
+    pub doh_max_concurrent_streams: u32,
+    /// DoH 错误应答附带 JSON 错误体（默认关闭，只返回状态码）。 / Attach a JSON body to DoH error responses (off by default, status code only)
+    #[serde(default)]
+    pub doh_json_errors: bool,
+    /// TLS 监听器的 PEM 证书链路径。 / PEM certificate chain path for the TLS listeners
+    #[serde(default)]
+    pub tls_cert_path: Option<String>,
+    /// TLS 监听器的 PEM 私钥路径。 / PEM private key path for the TLS listeners
+    #[serde(default)]
+    pub tls_key_path: Option<String>,
     /// Moka 缓存最大条目数（默认 10000） / Moka cache max entries (default 10000)
     #[serde(default = "default_cache_capacity")]
     pub cache_capacity: u64,
+    /// 按存储字节数限制缓存容量（默认 0 按条目数），设置后 cache_capacity 不再生效，压缩的条目占用更少容量。 / Cache capacity in stored bytes (default 0 = count entries); when set, cache_capacity is ignored and compressed entries take less room
+    #[serde(default)]
+    pub cache_max_bytes: u64,
     /// Moka 缓存最大生存时间（秒，默认 86400） / Moka cache max TTL (seconds, default 86400)
     #[serde(default = "default_cache_max_ttl")]
     pub cache_max_ttl: u64,
@@ -100,6 +144,9 @@ pub struct GlobalSettings {
     /// 单次上游请求的超时时间
     #[serde(default = "default_upstream_timeout_ms")]
     pub upstream_timeout_ms: u64,
+    /// 并发转发到多个上游时，NXDOMAIN 应答先保留，等待其余上游给出 NOERROR（默认 false）。 / When racing several upstreams, hold NXDOMAIN answers back while the others may still answer NOERROR (default false)
+    #[serde(default)]
+    pub race_prefer_noerror: bool,
     /// 整体请求超时（毫秒）。 / Overall request timeout (milliseconds)
     /// 包含 hedge + TCP fallback 的总超时时间。如果未设置，自动计算为 upstream_timeout_ms * 2.5
     /// Total timeout including hedge + TCP fallback. If not set, auto-calculated as upstream_timeout_ms * 2.5
@@ -166,6 +213,24 @@ pub struct GlobalSettings {
     /// 流控调整间隔（秒，仅在flow_control_enabled=true时有效） / Flow control adjustment interval (seconds, only effective when flow_control_enabled=true)
     #[serde(default = "default_flow_control_adjustment_interval_secs")]
     pub flow_control_adjustment_interval_secs: u64,
+    /// UDP 慢速路径同时进行的查询任务上限（默认 65536，0 表示不限制），流控关闭时同样生效
+    /// Max UDP slow-path query tasks in flight at once (default 65536, 0 = unlimited), enforced even with flow control disabled
+    #[serde(default = "default_max_outstanding_queries")]
+    pub max_outstanding_queries: usize,
+    /// 超出并发上限时的处理方式，缺省丢弃不回复。 / What happens to queries over the concurrency limit, dropped without a reply by default
+    #[serde(default)]
+    pub overload_reply: OverloadReply,
+    /// UDP worker 每次唤醒最多连续处理的报文数（默认 32，1 表示逐个处理）
+    /// Max packets a UDP worker handles back to back per wakeup (default 32, 1 = one at a time)
+    #[serde(default = "default_udp_batch_max")]
+    pub udp_batch_max: usize,
+    /// 是否按 socket 积压与处理耗时在 1 与 udp_batch_max 之间自动调整批量（默认 true）
+    /// Adapt the batch between 1 and udp_batch_max from socket backlog and processing time (default true)
+    #[serde(default = "default_udp_batch_adaptive")]
+    pub udp_batch_adaptive: bool,
+    /// UDP socket 缓冲区与单个报文缓冲区大小。 / UDP socket buffer and per-packet buffer sizes
+    #[serde(default)]
+    pub udp_buffers: UdpBufferSettings,
     /// RFC 8767: 上游不可用时返回过期缓存（默认 false）/ RFC 8767: Serve stale cached data when upstream is unavailable (default false)
     #[serde(default = "default_serve_stale")]
     pub serve_stale: bool,
@@ -177,8 +242,8 @@ pub struct GlobalSettings {
     /// 当缓存过期超过此时间后不再返回 stale 数据。0 表示无限制。
     /// RFC 8767: Maximum time window for serving stale cache (seconds, default 86400)
     /// Don't serve stale data after the entry has been expired for longer than this. 0 means no limit.
-    /// 对应 Unbound serve-expired-ttl / Corresponds to Unbound serve-expired-ttl
-    #[serde(default = "default_serve_stale_expire_ttl")]
+    /// 对应 Unbound serve-expired-ttl，也可写作 serve_stale_max_secs / Corresponds to Unbound serve-expired-ttl, also accepted as serve_stale_max_secs
+    #[serde(default = "default_serve_stale_expire_ttl", alias = "serve_stale_max_secs")]
     pub serve_stale_expire_ttl: u64,
     /// RFC 8767: 提供过期缓存后重置过期计时器（默认 true）
     /// 启用后，每次返回 stale 数据时重置过期时间窗口，确保频繁访问的域名不会因 serve_stale_expire_ttl 超时。
@@ -203,6 +268,21 @@ pub struct GlobalSettings {
     /// 缓存后台刷新最小TTL（秒，默认5）。防止TTL过短导致无限循环刷新 / Cache background refresh minimum TTL (seconds, default 5). Prevent infinite refresh loop for very short TTLs
     #[serde(default = "default_cache_refresh_min_ttl")]
     pub cache_refresh_min_ttl: u32,
+    /// 执行后台刷新的工作任务数（默认4） / Worker tasks running background refreshes (default 4)
+    #[serde(default = "default_prefetch_workers")]
+    pub prefetch_workers: usize,
+    /// 后台刷新队列长度（默认1024），队列满时丢弃新任务 / Background refresh queue length (default 1024), new jobs are dropped when full
+    #[serde(default = "default_prefetch_queue_size")]
+    pub prefetch_queue_size: usize,
+    /// 按查询类型（如 `A`、`HTTPS`、`TYPE65`）覆盖后台刷新行为 / Per-qtype (e.g. `A`, `HTTPS`, `TYPE65`) background refresh overrides
+    #[serde(default)]
+    pub prefetch_qtypes: HashMap<String, PrefetchQtypePolicy>,
+    /// 检测 IPv6 连通性时连接的地址（UDP connect，不发送数据） / Address connected to when probing IPv6 connectivity (UDP connect, nothing is sent)
+    #[serde(default = "default_ipv6_probe_addr")]
+    pub ipv6_probe_addr: String,
+    /// 探测不到 IPv6 连通时以 NODATA 应答 AAAA 查询，pipeline 中可覆盖 / Answer AAAA queries with NODATA while IPv6 is unreachable; pipelines can override it
+    #[serde(default)]
+    pub suppress_aaaa_without_ipv6: bool,
     /// GeoIP 数据库文件路径（MMDB 格式） / GeoIP database file path (MMDB format)
     #[serde(default)]
     pub geoip_db_path: Option<String>,
@@ -221,17 +301,817 @@ pub struct GlobalSettings {
     /// UDP 失败时是否自动 fallback 到 TCP（默认 true）。 / UDP failure automatically fallbacks to TCP (default true)
     #[serde(default = "default_enable_tcp_fallback")]
     pub enable_tcp_fallback: bool,
+    /// 上游 UDP 响应被截断（TC=1）时自动改用 TCP 重新查询（默认 true）。 / Automatically re-query over TCP when an upstream UDP response is truncated (TC=1, default true)
+    #[serde(default = "default_tcp_retry_on_truncation")]
+    pub tcp_retry_on_truncation: bool,
+    /// 按上游地址（不含协议前缀）的传输限制，如 `{"10.0.0.1:53": {"tcp_only": true}}`。 / Transport limits by upstream address (without protocol prefix), e.g. `{"10.0.0.1:53": {"tcp_only": true}}`
+    #[serde(default)]
+    pub upstream_options: HashMap<String, UpstreamOptions>,
+    /// 上游交互日志的输出目标（每次上游查询一行 JSON），缺省不记录。 / Output target of the upstream exchange log (one JSON line per upstream query), off by default
+    #[serde(default)]
+    pub upstream_log: Option<LogTarget>,
+    /// 管理 HTTP 接口监听地址（如 127.0.0.1:9053），缺省不启用。 / Admin HTTP API listen address (e.g. 127.0.0.1:9053), disabled by default
+    #[serde(default)]
+    pub admin_bind: Option<String>,
+    /// gRPC 管理接口监听地址（需以 `--features grpc` 构建），缺省不启用。 / gRPC admin API listen address (needs a build with `--features grpc`), disabled by default
+    #[serde(default)]
+    pub grpc_admin_bind: Option<String>,
+    /// 是否启用按注册域名的查询统计（默认 false）。 / Enable per-registered-domain query statistics (default false)
+    #[serde(default)]
+    pub domain_stats_enabled: bool,
+    /// 查询统计最多跟踪的域名数（默认 10000）。 / Max domains tracked by query statistics (default 10000)
+    #[serde(default = "default_domain_stats_capacity")]
+    pub domain_stats_capacity: u64,
+    /// 查询统计窗口（秒，默认 3600），域名条目在此时间后重置。 / Query statistics window (seconds, default 3600), domain entries reset after this
+    #[serde(default = "default_domain_stats_window_secs")]
+    pub domain_stats_window_secs: u64,
+    /// 参与异常标记的最小查询数（默认 50）。 / Minimum queries before a domain can be flagged (default 50)
+    #[serde(default = "default_domain_stats_min_queries")]
+    pub domain_stats_min_queries: u64,
+    /// 快速路径（缓存命中与静态应答）每 N 次查询记录一次并按 N 加权（默认 1，逐次记录）。 / Fast-path queries (cache hits and static answers) are recorded 1 in N and weighted by N (default 1, every query)
+    #[serde(default = "default_domain_stats_sample_rate")]
+    pub domain_stats_sample_rate: u32,
+    /// 全局放行名单（内联条目），在拦截类动作生效前检查。 / Global allowlist (inline entries), checked before block actions take effect
+    #[serde(default)]
+    pub allowlist: Vec<String>,
+    /// 放行名单文件路径列表（每行一个条目）。 / Allowlist file paths (one entry per line)
+    #[serde(default)]
+    pub allowlist_files: Vec<String>,
+    /// 启用 .local 的 mDNS 桥接（默认 false），启用后 .local 查询不再转发到上游。 / Enable the mDNS bridge for .local (default false); .local queries are then never forwarded upstream
+    #[serde(default)]
+    pub mdns_bridge: bool,
+    /// mDNS 查询等待时间（毫秒，默认 1000）。 / mDNS query wait time (milliseconds, default 1000)
+    #[serde(default = "default_mdns_timeout_ms")]
+    pub mdns_timeout_ms: u64,
+    /// 发送 mDNS 组播使用的本地 IPv4 地址（缺省由系统路由决定）。 / Local IPv4 address used to send mDNS multicast (system routing if absent)
+    #[serde(default)]
+    pub mdns_interface: Option<String>,
+    /// 启动时预热加密上游（DoT/DoH/DoQ）连接（默认 false）。 / Prewarm encrypted upstream (DoT/DoH/DoQ) connections at startup (default false)
+    #[serde(default)]
+    pub upstream_prewarm: bool,
+    /// 预热重复间隔（秒，默认 0 仅启动时执行一次），用于保持空闲连接温热。 / Prewarm repeat interval (seconds, default 0 = only once at startup), keeps idle connections warm
+    #[serde(default)]
+    pub upstream_prewarm_interval_secs: u64,
+    /// 启用 TCP Fast Open（默认 false）：TCP 监听器及出站 TCP/DoT 连接，系统不支持时自动忽略。 / Enable TCP Fast Open (default false) on the TCP listener and outbound TCP/DoT connections; ignored where unsupported
+    #[serde(default)]
+    pub tcp_fast_open: bool,
+    /// 缓存报文压缩阈值（字节，默认 0 不压缩），超过此大小的响应以 LZ4 压缩存储。 / Cache compression threshold (bytes, default 0 = disabled); larger responses are stored LZ4-compressed
+    #[serde(default)]
+    pub cache_compress_threshold: usize,
+    /// 否定响应（NXDOMAIN/NODATA/SERVFAIL）的缓存策略。 / Caching policy for negative responses (NXDOMAIN/NODATA/SERVFAIL)
+    #[serde(default)]
+    pub negative_cache: NegativeCacheSettings,
+    /// 统计快照输出文件（SIGUSR1 或管理接口触发），缺省写入日志。 / Stats snapshot output file (triggered by SIGUSR1 or the admin API), logged when absent
+    #[serde(default)]
+    pub stats_dump_path: Option<String>,
+    /// 发往 DoT/DoH/DoQ 上游的查询按此块大小做 EDNS 填充（RFC 8467，默认 128，0 关闭）。 / EDNS padding block size for queries to DoT/DoH/DoQ upstreams (RFC 8467, default 128, 0 = disabled)
+    #[serde(default = "default_edns_padding_block_size")]
+    pub edns_padding_block_size: u16,
+    /// 每个上游每秒转发的不同 qname 上限（默认 0 不限制），超出的查询返回过期缓存或 SERVFAIL。 / Max distinct qnames forwarded per upstream per second (default 0 = unlimited); excess queries get stale cache or SERVFAIL
+    #[serde(default)]
+    pub upstream_unique_qname_limit: u32,
+    /// 隐私模式：日志中的客户端地址与统计中的低频域名。 / Privacy mode for client addresses in logs and rare domains in statistics
+    #[serde(default)]
+    pub privacy: PrivacySettings,
+    /// 告警日志按类别限速与抽样，缺省不限速。 / Per-category rate limits and sampling of warning logs, unlimited by default
+    #[serde(default)]
+    pub log_rate_limit: LogRateLimitSettings,
+    /// 私有地址（RFC 1918、100.64.0.0/10 等）反向查询的本地应答，缺省关闭。 / Local answers for reverse lookups of private space (RFC 1918, 100.64.0.0/10, ...), off by default
+    #[serde(default)]
+    pub private_ptr: PrivatePtrSettings,
+    /// 非 IN 类（CH/HS 等）查询的处理方式，缺省照常转发。 / How non-IN class (CH/HS, ...) queries are handled, forwarded as usual by default
+    #[serde(default)]
+    pub non_in_qclass: QclassPolicy,
+    /// 含多个问题（QDCOUNT > 1）的查询的处理方式，缺省只处理第一个问题。 / How queries with several questions (QDCOUNT > 1) are handled, only the first one is processed by default
+    #[serde(default)]
+    pub multi_question: MultiQuestionPolicy,
+    /// 无法解析的请求的回复方式（按监听器），缺省丢弃不回复。 / Reply to unparseable requests (per listener), dropped without a reply by default
+    #[serde(default)]
+    pub malformed_query: MalformedQuerySettings,
+    /// 管理接口 /readyz 的就绪判断配置。 / Readiness settings for the admin /readyz endpoint
+    #[serde(default)]
+    pub readiness: ReadinessSettings,
+    /// 热备实例间的缓存与热点域名同步，缺省关闭。 / Cache and hot-domain sync between hot-standby instances, off by default
+    #[serde(default)]
+    pub peer_sync: PeerSyncSettings,
+    /// 上游后台健康检查，缺省关闭。 / Background upstream health checks, off by default
+    #[serde(default)]
+    pub upstream_health_check: UpstreamHealthCheckSettings,
+    /// 管理操作向副本节点的复制，缺省不复制。 / Replication of admin operations to replica nodes, none by default
+    #[serde(default)]
+    pub cluster: ClusterSettings,
+    /// 指定解析器发现（DDR，RFC 9462）宣告的加密端点，缺省不应答。 / Encrypted endpoints advertised for Discovery of Designated Resolvers (DDR, RFC 9462), not answered by default
+    #[serde(default)]
+    pub ddr: DdrSettings,
+    /// 直接在配置中声明的本地记录，缺省为空。 / Local records declared directly in the configuration, none by default
+    #[serde(default)]
+    pub local_records: Vec<LocalRecord>,
+    /// 每次应答轮换本地记录中同类多条记录的顺序（默认 false）。 / Rotate the order of same-type local records on every answer (default false)
+    #[serde(default)]
+    pub local_records_rotate: bool,
+    /// 本地记录 TCP 探测的间隔（秒，默认 10）。 / Interval of the TCP probes of local records (seconds, default 10)
+    #[serde(default = "default_local_records_probe_interval_secs")]
+    pub local_records_probe_interval_secs: u64,
+    /// 客户端与上游之间放行的 EDNS 选项（按选项码），缺省只把扩展错误返回给客户端。 / EDNS options let through between clients and upstreams (by option code), by default only Extended DNS Errors reach clients
+    #[serde(default)]
+    pub edns_options: EdnsOptionPolicy,
+    /// 按地址段改写上游 PTR 应答中的名称，缺省不改写。 / Rewrites of the names in upstream PTR answers by address range, none by default
+    #[serde(default)]
+    pub ptr_rewrite: Vec<PtrRewrite>,
+    /// 上游应答只保留查询名的 CNAME 链与查询类型的记录（默认 false）。 / Keep only the CNAME chain of the query name and records of the query type in upstream answers (default false)
+    #[serde(default)]
+    pub minimal_answers: bool,
+    /// TTL 为 0 的应答的缓存策略（默认 min_ttl）；pipeline 中可覆盖。 / Caching policy for answers with TTL 0 (default min_ttl); pipelines may override it
+    #[serde(default)]
+    pub zero_ttl: ZeroTtlPolicy,
+}
+
+/// PTR 应答改写 / PTR answer rewrite
+///
+/// 反向查询的地址落在 cidr 内且上游应答了 PTR 记录时，记录的目标名称替换为 name（按列表顺序取第一条
+/// 匹配项），便于监控系统显示运维定义的名称而不是云厂商的通用反向名称。name 中的 `{ip}` 替换为以 `-`
+/// 连接的地址，如 `203-0-113-7`。
+/// When the address of a reverse lookup is inside cidr and the upstream answered with PTR
+/// records, their target is replaced with name (the first matching entry in list order), so
+/// monitoring systems show operator-defined names instead of a cloud provider's generic reverse
+/// names. `{ip}` in name becomes the address joined by `-`, e.g. `203-0-113-7`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PtrRewrite {
+    pub cidr: String,
+    pub name: String,
+}
+
+/// EDNS 选项放行策略 / EDNS option pass-through policy
+///
+/// 大多数 EDNS 选项只在一跳内有意义（Cookie、TCP Keepalive、Padding），ECS 会把客户端网段泄露给
+/// 上游，因此默认不把客户端选项转发给上游，上游应答中只保留 Extended DNS Errors（15）。OPT 记录本身
+/// （负载大小、DO 位）不受影响；发往加密上游的 Padding 在过滤后重新添加。
+/// Most EDNS options only make sense within one hop (cookies, TCP keepalive, padding) and ECS
+/// leaks the client's subnet upstream, so by default no client option is forwarded upstream
+/// and only Extended DNS Errors (15) are kept in upstream answers. The OPT record itself
+/// (payload size, DO bit) is untouched; padding for encrypted upstreams is re-added after
+/// filtering.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct EdnsOptionPolicy {
+    /// 转发给上游的客户端选项码（如 8 = ECS、3 = NSID）/ Client option codes forwarded upstream (e.g. 8 = ECS, 3 = NSID)
+    #[serde(default)]
+    pub to_upstream: Vec<u16>,
+    /// 返回给客户端的上游选项码（默认 [15]）/ Upstream option codes returned to clients (default [15])
+    #[serde(default = "default_edns_to_client")]
+    pub to_client: Vec<u16>,
+}
+
+impl Default for EdnsOptionPolicy {
+    fn default() -> Self {
+        Self {
+            to_upstream: Vec::new(),
+            to_client: default_edns_to_client(),
+        }
+    }
+}
+
+/// UDP 缓冲区配置 / UDP buffer settings
+///
+/// recv_bytes / send_bytes 为监听 socket 的内核缓冲区（SO_RCVBUF / SO_SNDBUF），设置失败时回退到
+/// 1 MiB，0 表示保持系统默认；packet_bytes 为每个报文的接收缓冲区，超出的 UDP 报文会被截断，需要
+/// 接收超大 EDNS 报文时可调至 65535，小内存设备可调低。
+/// recv_bytes / send_bytes are the kernel buffers of the listening sockets (SO_RCVBUF /
+/// SO_SNDBUF), falling back to 1 MiB when they cannot be set, 0 keeps the system default;
+/// packet_bytes is the receive buffer of each packet, longer UDP datagrams are cut short, so
+/// raise it up to 65535 for jumbo EDNS packets or lower it on low-memory devices.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct UdpBufferSettings {
+    #[serde(default = "default_udp_socket_buffer_bytes")]
+    pub recv_bytes: usize,
+    #[serde(default = "default_udp_socket_buffer_bytes")]
+    pub send_bytes: usize,
+    #[serde(default = "default_udp_packet_bytes")]
+    pub packet_bytes: usize,
+}
+
+impl Default for UdpBufferSettings {
+    fn default() -> Self {
+        Self {
+            recv_bytes: default_udp_socket_buffer_bytes(),
+            send_bytes: default_udp_socket_buffer_bytes(),
+            packet_bytes: default_udp_packet_bytes(),
+        }
+    }
+}
+
+/// 指定解析器发现配置 / Discovery of Designated Resolvers settings
+///
+/// designations 非空时，`_dns.resolver.arpa` 的 SVCB 查询在本地应答，每个端点一条记录（按列表顺序
+/// 决定优先级），局域网客户端据此从 Do53 自动升级到加密端点；该名称的其他类型返回 NODATA。
+/// 端点可以是任何持有 target 证书的 DoT/DoH/DoQ 服务，例如前置的反向代理。
+/// When designations is non-empty, SVCB queries for `_dns.resolver.arpa` are answered locally
+/// with one record per endpoint (priority follows list order) so LAN clients can upgrade from
+/// Do53 to the encrypted endpoints; other types for that name get NODATA. An endpoint can be
+/// any DoT/DoH/DoQ service holding a certificate for target, such as a fronting reverse proxy.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DdrSettings {
+    #[serde(default)]
+    pub designations: Vec<DdrDesignation>,
+    /// 记录 TTL（秒，默认 300）/ Record TTL (seconds, default 300)
+    #[serde(default = "default_ddr_ttl")]
+    pub ttl: u32,
+}
+
+impl Default for DdrSettings {
+    fn default() -> Self {
+        Self {
+            designations: Vec::new(),
+            ttl: default_ddr_ttl(),
+        }
+    }
+}
+
+/// 配置中声明的一条本地记录 / A local record declared in the configuration
+///
+/// 查询名称与某条记录的 name 完全相同时在本地应答，不再转发：有该类型的记录时返回这些记录，
+/// 只有 CNAME 时返回 CNAME（目标同样是本地名称时一并附上其记录），否则返回 NODATA。value 为记录的
+/// 文本形式：A/AAAA 为地址，CNAME/NS/PTR 为名称，MX 为 `优先级 名称`，SRV 为 `优先级 权重 端口 名称`，
+/// TXT 为原文。
+/// Queries whose name equals the name of a record are answered locally instead of forwarded:
+/// with the records of the queried type when there are any, with the CNAME when only one
+/// exists (plus the records of its target when that is a local name too), and with NODATA
+/// otherwise. value is the record in text form: an address for A/AAAA, a name for
+/// CNAME/NS/PTR, `preference name` for MX, `priority weight port name` for SRV and the raw
+/// text for TXT.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LocalRecord {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub value: String,
+    /// 记录 TTL（秒，默认 300）/ Record TTL (seconds, default 300)
+    #[serde(default = "default_local_record_ttl")]
+    pub ttl: u32,
+    /// A/AAAA 记录的 TCP 探测端口，探测失败的地址在同名仍有可用地址时不返回
+    /// TCP probe port of A/AAAA records; addresses failing the probe are left out while the name has other usable ones
+    #[serde(default)]
+    pub probe_port: Option<u16>,
+}
+
+/// DDR 宣告的一个加密端点 / One encrypted endpoint advertised by DDR
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DdrDesignation {
+    pub protocol: DdrProtocol,
+    /// 端点主机名，须与其 TLS 证书一致 / Endpoint host name, matching its TLS certificate
+    pub target: String,
+    /// 端点端口，缺省为协议默认端口 / Endpoint port, the protocol's default when absent
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// DoH URI 模板（仅 doh，默认 "/dns-query{?dns}"）/ DoH URI template (doh only, default "/dns-query{?dns}")
+    #[serde(default)]
+    pub dohpath: Option<String>,
+    #[serde(default)]
+    pub ipv4hint: Vec<std::net::Ipv4Addr>,
+    #[serde(default)]
+    pub ipv6hint: Vec<std::net::Ipv6Addr>,
+}
+
+/// DDR 端点的协议 / Protocol of a DDR endpoint
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DdrProtocol {
+    Dot,
+    Doh,
+    Doq,
+}
+
+/// 集群复制配置 / Cluster replication settings
+///
+/// 本节点的管理接口（HTTP 或 gRPC）成功执行临时规则增删或配置重载后，按相同操作调用每个副本的 HTTP
+/// 管理接口（带 `replicated=1`，副本不再继续转发）。复制为尽力而为：离线期间的操作不会补发。
+/// After this node's admin API (HTTP or gRPC) adds or removes a runtime rule or reloads the
+/// config, the same operation is sent to every replica's HTTP admin API (with `replicated=1`
+/// so replicas do not forward it further). Replication is best effort: operations missed while
+/// a replica is offline are not replayed.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ClusterSettings {
+    /// 副本的管理接口地址（host:port）/ Admin API addresses of the replicas (host:port)
+    #[serde(default)]
+    pub replicas: Vec<String>,
+    /// 每次复制请求的超时（毫秒，默认 2000）/ Timeout of each replication request (milliseconds, default 2000)
+    #[serde(default = "default_cluster_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl Default for ClusterSettings {
+    fn default() -> Self {
+        Self {
+            replicas: Vec::new(),
+            timeout_ms: default_cluster_timeout_ms(),
+        }
+    }
+}
+
+/// 热备同步配置 / Hot-standby sync settings
+///
+/// listen 接收对端推送（修改后需重启），peers 为定时推送的目标；两端通常互为对方的 peer。
+/// 设置 listen 时必须配置 token 或 tls：token 只校验对端，通道仍为明文 TCP；tls 为双向 TLS，
+/// 两端以同一 CA 签发的证书互相校验。
+/// listen accepts pushes from peers (changing it needs a restart) and peers are the targets
+/// of the periodic push; two instances usually list each other. listen requires token or tls:
+/// token only checks the peer over plain TCP, while tls is mutual TLS where both sides verify
+/// each other's certificate against the same CA.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PeerSyncSettings {
+    /// 接收推送的地址（如 10.0.0.2:5355），缺省不接收 / Address accepting pushes (e.g. 10.0.0.2:5355), none by default
+    #[serde(default)]
+    pub listen: Option<String>,
+    /// 推送目标（host:port）/ Push targets (host:port)
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// 共享 token / Shared token
+    #[serde(default)]
+    pub token: Option<String>,
+    /// 双向 TLS，缺省为明文 TCP / Mutual TLS, plain TCP by default
+    #[serde(default)]
+    pub tls: Option<PeerSyncTls>,
+    /// 推送间隔（秒，默认 30）/ Push interval (seconds, default 30)
+    #[serde(default = "default_peer_sync_interval_secs")]
+    pub interval_secs: u64,
+    /// 每次推送的最多缓存条目数（默认 10000）/ Max cache entries per push (default 10000)
+    #[serde(default = "default_peer_sync_max_entries")]
+    pub max_entries: usize,
+}
+
+impl Default for PeerSyncSettings {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            peers: Vec::new(),
+            token: None,
+            tls: None,
+            interval_secs: default_peer_sync_interval_secs(),
+            max_entries: default_peer_sync_max_entries(),
+        }
+    }
+}
+
+/// 热备同步的双向 TLS / Mutual TLS of the hot-standby sync
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PeerSyncTls {
+    /// 本端证书链（PEM），接收时作为服务端证书、推送时作为客户端证书 / Own certificate chain (PEM), the server certificate when receiving and the client certificate when pushing
+    pub cert_path: String,
+    /// 本端私钥（PEM）/ Own private key (PEM)
+    pub key_path: String,
+    /// 校验对端证书的 CA（PEM）/ CA verifying the peer's certificate (PEM)
+    pub ca_path: String,
+}
+
+/// 单个上游的传输限制 / Transport limits of one upstream
+///
+/// 只影响以 UDP（含 tcp_udp）发送的查询：tcp_only 时改用 TCP；设置 max_udp_size 时查询报文超过该
+/// 大小改用 TCP，否则把 EDNS 声明的 UDP 负载压到该值，更大的应答由上游截断后经 TCP 重新查询。
+/// Only queries sent over UDP (tcp_udp included) are affected: with tcp_only they go over TCP;
+/// with max_udp_size, queries larger than it go over TCP and otherwise the EDNS UDP payload
+/// they advertise is lowered to it, so larger answers come back truncated and are re-queried
+/// over TCP.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct UpstreamOptions {
+    /// 只用 TCP 访问 / Reach the upstream over TCP only
+    pub tcp_only: bool,
+    /// UDP 报文大小上限（字节，不小于 512）/ Max UDP message size (bytes, at least 512)
+    pub max_udp_size: Option<u16>,
+}
+
+/// 告警日志限速配置 / Warning log rate limit settings
+///
+/// 请求超时、上游失败、连接重试等告警按类别每秒最多输出 per_category_per_sec 条（0 为不限），
+/// 超出部分每 sample_every 条输出一条（0 为全部省略）。
+/// Warnings such as request timeouts, upstream failures and connection retries are capped at
+/// per_category_per_sec lines per category per second (0 = unlimited); beyond the cap one line
+/// in sample_every is still written (0 omits them all).
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct LogRateLimitSettings {
+    /// 每类每秒最多输出的条数 / Max lines per category per second
+    pub per_category_per_sec: u32,
+    /// 超出上限后每 N 条输出一条 / One line in N is written beyond the cap
+    pub sample_every: u32,
+}
+
+/// 上游健康检查配置 / Upstream health check settings
+///
+/// 启用后每隔 interval_secs 向配置中出现的每个上游发送 `query_name IN NS` 探测查询，超时或应答为
+/// SERVFAIL/REFUSED 算作失败。连续失败 failure_threshold 次的上游被标记为不健康，转发时跳过，
+/// 直到连续成功 success_threshold 次；一组上游全部不健康时仍照常使用。
+/// When enabled, every upstream found in the configuration gets a `query_name IN NS` probe every
+/// interval_secs; a timeout or a SERVFAIL/REFUSED answer counts as a failure. An upstream is
+/// marked unhealthy after failure_threshold failures in a row and skipped when forwarding until
+/// success_threshold probes in a row succeed; a group whose upstreams are all unhealthy is still used.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UpstreamHealthCheckSettings {
+    /// 是否启用（默认 false）/ Whether enabled (default false)
+    #[serde(default)]
+    pub enabled: bool,
+    /// 探测间隔（秒，默认 10）/ Probe interval (seconds, default 10)
+    #[serde(default = "default_health_check_interval_secs")]
+    pub interval_secs: u64,
+    /// 单次探测超时（毫秒，默认 2000）/ Timeout of one probe (milliseconds, default 2000)
+    #[serde(default = "default_health_check_timeout_ms")]
+    pub timeout_ms: u64,
+    /// 标记为不健康所需的连续失败次数（默认 3）/ Failures in a row that mark an upstream unhealthy (default 3)
+    #[serde(default = "default_health_check_failure_threshold")]
+    pub failure_threshold: u32,
+    /// 恢复为健康所需的连续成功次数（默认 1）/ Successes in a row that mark an upstream healthy again (default 1)
+    #[serde(default = "default_health_check_success_threshold")]
+    pub success_threshold: u32,
+    /// 探测查询的名称（默认根域 `.`）/ Name of the probe query (default the root `.`)
+    #[serde(default = "default_health_check_query_name")]
+    pub query_name: String,
+}
+
+impl Default for UpstreamHealthCheckSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_health_check_interval_secs(),
+            timeout_ms: default_health_check_timeout_ms(),
+            failure_threshold: default_health_check_failure_threshold(),
+            success_threshold: default_health_check_success_threshold(),
+            query_name: default_health_check_query_name(),
+        }
+    }
+}
+
+/// 就绪检查配置 / Readiness check settings
+///
+/// 上游视为可用的条件：尚无失败、最近一次成功晚于最近一次失败，或 upstream_window_secs 内有过成功。
+/// 设置 probe_domain 后 /readyz 还会经由本机 UDP 监听器解析该域名（NOERROR 或 NXDOMAIN 视为成功）。
+/// Upstreams count as usable when none has failed yet, the last success is newer than the last
+/// failure, or a success happened within upstream_window_secs. With probe_domain set, /readyz
+/// also resolves that name through this process's UDP listener (NOERROR or NXDOMAIN pass).
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ReadinessSettings {
+    /// 上游成功的有效窗口（秒，默认 60）/ How long an upstream success counts (seconds, default 60)
+    #[serde(default = "default_readiness_upstream_window_secs")]
+    pub upstream_window_secs: u64,
+    /// 自查询探测的域名，缺省不探测 / Domain of the self-query probe, no probe by default
+    #[serde(default)]
+    pub probe_domain: Option<String>,
+    /// 自查询探测超时（毫秒，默认 2000）/ Self-query probe timeout (milliseconds, default 2000)
+    #[serde(default = "default_readiness_probe_timeout_ms")]
+    pub probe_timeout_ms: u64,
+}
+
+impl Default for ReadinessSettings {
+    fn default() -> Self {
+        Self {
+            upstream_window_secs: default_readiness_upstream_window_secs(),
+            probe_domain: None,
+            probe_timeout_ms: default_readiness_probe_timeout_ms(),
+        }
+    }
+}
+
+/// 无法解析的请求的回复配置 / Reply settings for unparseable requests
+///
+/// 部分监控系统依赖应答判断存活，可让监听器对无法解析的请求回复仅含头部的 FORMERR 或 REFUSED
+/// （回显事务 ID）。QR 位已置位的报文（即响应）始终丢弃，避免与其他服务器互相回复。
+/// Some monitoring systems rely on an answer to detect liveness, so listeners can reply to
+/// unparseable requests with a header-only FORMERR or REFUSED echoing the transaction ID.
+/// Packets with the QR bit set (responses) are always dropped so two servers never bounce
+/// replies off each other.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct MalformedQuerySettings {
+    #[serde(default)]
+    pub udp: MalformedQueryReply,
+    #[serde(default)]
+    pub tcp: MalformedQueryReply,
+}
+
+/// 无法解析的请求的回复方式 / How an unparseable request is answered
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MalformedQueryReply {
+    /// 丢弃不回复 / Drop without a reply
+    #[default]
+    Drop,
+    Formerr,
+    Refused,
+}
+
+/// 监听 `[::]` 时的 IPv6 模式 / IPv6 mode when listening on `[::]`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Ipv6ListenMode {
+    /// 另建 `0.0.0.0` socket，`[::]` 设置 IPV6_V6ONLY=1 / An extra `0.0.0.0` socket, `[::]` with IPV6_V6ONLY=1
+    #[default]
+    Split,
+    /// 单个 IPV6_V6ONLY=0 的 socket，IPv4 客户端以映射地址到达 / One IPV6_V6ONLY=0 socket, IPv4 clients arrive as mapped addresses
+    DualStack,
+    /// 只监听 IPv6 / IPv6 only
+    V6Only,
+}
+
+/// 某一查询类型何时后台刷新 / When a query type is refreshed in the background
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PrefetchMode {
+    /// 总是刷新 / Always refresh
+    #[default]
+    Always,
+    /// 从不刷新 / Never refresh
+    Never,
+    /// 仅在探测到 IPv6 连通时刷新 / Only refresh while IPv6 connectivity is detected
+    Ipv6Reachable,
+}
+
+/// 单个查询类型的后台刷新策略 / Background refresh policy of one query type
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct PrefetchQtypePolicy {
+    pub mode: PrefetchMode,
+    /// 覆盖 cache_refresh_min_ttl。 / Overrides cache_refresh_min_ttl
+    pub min_ttl: Option<u32>,
+    /// 刷新本类型时一并刷新同名的这些类型（各自的 mode 仍然生效）。 / Types of the same name refreshed along with this one (their own mode still applies)
+    pub with: Vec<String>,
+}
+
+/// 客户端查询到达的监听器传输协议 / Listener transport a client query arrived on
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenerTransport {
+    #[default]
+    Udp,
+    Tcp,
+    /// DNS-over-TLS
+    Dot,
+    /// DNS-over-HTTPS
+    Doh,
+}
+
+/// 过载（超出并发上限）时查询的回复方式 / How queries are answered while overloaded (over the concurrency limit)
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OverloadReply {
+    /// 丢弃不回复 / Drop without a reply
+    #[default]
+    Drop,
+    Servfail,
+    Refused,
+}
+
+/// 多问题查询的处理方式 / Handling of multi-question queries
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiQuestionPolicy {
+    /// 只处理第一个问题，其余忽略 / Process the first question and ignore the rest
+    #[default]
+    FirstOnly,
+    /// 返回 FORMERR / Answer FORMERR
+    Formerr,
+}
+
+/// TTL 为 0 的应答的缓存策略 / Caching policy for answers with TTL 0
+///
+/// 动态 DNS 一类环境希望 0 TTL 严格不缓存，广告与追踪域名多的环境则希望短暂缓存以吸收突发查询。
+/// DDNS-heavy environments want TTL 0 honored strictly, while ad-heavy ones want a brief cache to
+/// absorb query bursts.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ZeroTtlPolicy {
+    /// 按 min_ttl 缓存，min_ttl 为 0 时不缓存 / Cache for min_ttl, not at all when min_ttl is 0
+    #[default]
+    MinTtl,
+    /// 不缓存（RFC 1035）/ Never cache (RFC 1035)
+    NoCache,
+    /// 缓存 ms 毫秒 / Cache for ms milliseconds
+    MicroCache { ms: u32 },
+}
+
+/// 非 IN 类查询的处理方式 / Handling of non-IN class queries
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QclassPolicy {
+    /// 照常执行规则并转发，缓存按 QCLASS 区分 / Run rules and forward as usual, cached per QCLASS
+    #[default]
+    Forward,
+    /// 直接返回 REFUSED / Answer REFUSED
+    Refuse,
+}
+
+/// 私有地址反向查询配置 / Private-space reverse lookup settings
+///
+/// 命中 10/8、172.16/12、192.168/16、100.64/10、127/8、169.254/16、::1、fc00::/7、fe80::/10
+/// 的 in-addr.arpa / ip6.arpa 查询不再转发，按 mode 在本地应答。
+/// in-addr.arpa / ip6.arpa queries within 10/8, 172.16/12, 192.168/16, 100.64/10, 127/8,
+/// 169.254/16, ::1, fc00::/7 or fe80::/10 are no longer forwarded and are answered locally per mode.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PrivatePtrSettings {
+    #[serde(default)]
+    pub mode: PrivatePtrMode,
+    /// 合成名称的后缀（默认 "internal."）/ Suffix of synthesized names (default "internal.")
+    #[serde(default = "default_private_ptr_suffix")]
+    pub suffix: String,
+    /// 合成记录的 TTL（秒，默认 300）/ TTL of synthesized records (seconds, default 300)
+    #[serde(default = "default_private_ptr_ttl")]
+    pub ttl: u32,
+}
+
+impl Default for PrivatePtrSettings {
+    fn default() -> Self {
+        Self {
+            mode: PrivatePtrMode::default(),
+            suffix: default_private_ptr_suffix(),
+            ttl: default_private_ptr_ttl(),
+        }
+    }
+}
+
+/// 私有地址反向查询的应答方式 / How private-space reverse lookups are answered
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivatePtrMode {
+    /// 照常转发 / Forwarded as usual
+    #[default]
+    Off,
+    /// 返回 NXDOMAIN / Answer NXDOMAIN
+    Nxdomain,
+    /// 为完整地址合成 `ip-10-0-0-1.<suffix>`，其余返回 NXDOMAIN
+    /// Synthesize `ip-10-0-0-1.<suffix>` for complete addresses, NXDOMAIN otherwise
+    Synthesize,
+}
+
+/// 隐私模式配置 / Privacy mode settings
+///
+/// 客户端地址在查询日志、规则日志和其他含 client_ip 的日志中按 client_ip 模式输出；
+/// 按域名的统计报告省略查询数低于 min_qname_count 的域名。
+/// Client addresses in query logs, rule logs and other logs carrying client_ip are rendered
+/// per the client_ip mode; per-domain statistics reports omit domains queried fewer than
+/// min_qname_count times.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PrivacySettings {
+    #[serde(default)]
+    pub client_ip: ClientIpPrivacy,
+    /// truncate 模式下 IPv4 保留的前缀长度（默认 24）/ IPv4 prefix kept in truncate mode (default 24)
+    #[serde(default = "default_privacy_ipv4_prefix")]
+    pub ipv4_prefix: u8,
+    /// truncate 模式下 IPv6 保留的前缀长度（默认 56）/ IPv6 prefix kept in truncate mode (default 56)
+    #[serde(default = "default_privacy_ipv6_prefix")]
+    pub ipv6_prefix: u8,
+    /// 统计报告中域名的最小查询数（默认 0 不省略）/ Minimum queries for a domain to appear in statistics reports (default 0 = keep all)
+    #[serde(default)]
+    pub min_qname_count: u64,
+}
+
+impl PipelineConfig {
+    /// 规则引用的 CIDR 列表文件（去重）/ CIDR list files referenced by rules (deduplicated)
+    pub fn cidr_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = self
+            .pipelines
+            .iter()
+            .flat_map(|p| &p.rules)
+            .flat_map(|r| &r.response_matchers)
+            .filter_map(|m| match &m.matcher {
+                ResponseMatcher::ResponseAnswerIp { file: Some(file), .. } => Some(file.clone()),
+                _ => None,
+            })
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// 请求阶段 jump_to_pipeline 构成的环（按跳转顺序列出，首尾相同），无环时返回 None；
+    /// 禁用的 pipeline 与规则不参与
+    /// A cycle formed by request-phase jump_to_pipeline actions, listed in jump order with the
+    /// first pipeline repeated at the end, or None when there is none; disabled pipelines and
+    /// rules are left out
+    pub fn jump_cycle(&self) -> Option<Vec<String>> {
+        let enabled: Vec<&Pipeline> = self.pipelines.iter().filter(|p| p.enabled).collect();
+        let graph: HashMap<&str, Vec<&str>> = enabled
+            .iter()
+            .map(|p| {
+                let targets = p
+                    .rules
+                    .iter()
+                    .filter(|r| r.enabled)
+                    .flat_map(|r| &r.actions)
+                    .filter_map(|a| match a {
+                        Action::JumpToPipeline { pipeline, .. } => Some(pipeline.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+                (p.id.as_str(), targets)
+            })
+            .collect();
+
+        // 深度优先搜索：false 表示在当前路径上，true 表示已检查完 / DFS: false = on the current path, true = finished
+        fn visit<'a>(
+            id: &'a str,
+            graph: &HashMap<&'a str, Vec<&'a str>>,
+            marks: &mut HashMap<&'a str, bool>,
+            path: &mut Vec<&'a str>,
+        ) -> Option<Vec<String>> {
+            match marks.get(id) {
+                Some(true) => return None,
+                Some(false) => {
+                    let start = path.iter().position(|p| *p == id).unwrap_or(0);
+                    let mut cycle: Vec<String> = path[start..].iter().map(|p| p.to_string()).collect();
+                    cycle.push(id.to_string());
+                    return Some(cycle);
+                }
+                None => {}
+            }
+            let targets = graph.get(id)?;
+            marks.insert(id, false);
+            path.push(id);
+            for next in targets {
+                if let Some(cycle) = visit(next, graph, marks, path) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            marks.insert(id, true);
+            None
+        }
+
+        let mut marks = HashMap::new();
+        let mut path = Vec::new();
+        enabled
+            .iter()
+            .find_map(|p| visit(p.id.as_str(), &graph, &mut marks, &mut path))
+    }
+}
+
+impl Default for PrivacySettings {
+    fn default() -> Self {
+        Self {
+            client_ip: ClientIpPrivacy::default(),
+            ipv4_prefix: default_privacy_ipv4_prefix(),
+            ipv6_prefix: default_privacy_ipv6_prefix(),
+            min_qname_count: 0,
+        }
+    }
+}
+
+/// 日志中客户端地址的输出方式 / How client addresses are written to logs
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientIpPrivacy {
+    /// 原样输出 / As is
+    #[default]
+    Full,
+    /// 截断为网段（如 192.0.2.0/24）/ Truncated to a prefix (e.g. 192.0.2.0/24)
+    Truncate,
+    /// 加盐哈希，盐值在引擎创建时随机生成、重载时保留 / Salted hash; the salt is random per engine and kept across reloads
+    Hash,
+}
+
+/// 否定响应缓存配置 / Negative response caching settings
+///
+/// NXDOMAIN/NODATA 按 RFC 2308 取 SOA 的否定 TTL 并限制在 [min_ttl, max_ttl] 内，
+/// 未配置时沿用默认行为（不缓存）；SERVFAIL 按 RFC 2308 §7.1 作短时惩罚缓存，0 表示不缓存。
+/// NXDOMAIN/NODATA use the SOA negative TTL per RFC 2308, clamped to [min_ttl, max_ttl];
+/// when unset the default behavior (not cached) applies. SERVFAIL gets a short penalty
+/// cache per RFC 2308 §7.1, 0 meaning not cached.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct NegativeCacheSettings {
+    #[serde(default)]
+    pub nxdomain: Option<TtlBounds>,
+    #[serde(default)]
+    pub nodata: Option<TtlBounds>,
+    /// SERVFAIL 缓存秒数（默认 0 不缓存）/ SERVFAIL cache seconds (default 0 = not cached)
+    #[serde(default)]
+    pub servfail_ttl: u32,
+}
+
+/// 缓存 TTL 上下限 / Cache TTL bounds
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct TtlBounds {
+    /// 下限，响应不带 SOA 时也使用此值 / Lower bound, also used when the response has no SOA
+    #[serde(default)]
+    pub min_ttl: u32,
+    #[serde(default = "default_negative_max_ttl")]
+    pub max_ttl: u32,
 }
 
 impl Default for GlobalSettings {
     fn default() -> Self {
         Self {
             min_ttl: default_min_ttl(),
+            max_ttl: 0,
             bind_udp: default_bind_udp(),
             bind_tcp: default_bind_tcp(),
+            ipv6_listen_mode: Ipv6ListenMode::default(),
+            bind_dot: None,
+            bind_doh: None,
+            doh_path: default_doh_path(),
+            doh_max_body_bytes: default_doh_max_body_bytes(),
+            doh_max_concurrent_streams: default_doh_max_concurrent_streams(),
+            doh_json_errors: false,
+            tls_cert_path: None,
+            tls_key_path: None,
             default_upstream: default_upstream(),
             default_upstream_pre_split: None,
             upstream_timeout_ms: default_upstream_timeout_ms(),
+            race_prefer_noerror: false,
             request_timeout_ms: None, // 默认自动计算 / Auto-calculated by default
             response_jump_limit: default_response_jump_limit(),
             udp_pool_size: default_udp_pool_size(),
@@ -251,7 +1131,13 @@ impl Default for GlobalSettings {
             flow_control_max_permits: default_flow_control_max_permits(),
             flow_control_latency_threshold_ms: default_flow_control_latency_threshold_ms(),
             flow_control_adjustment_interval_secs: default_flow_control_adjustment_interval_secs(),
+            max_outstanding_queries: default_max_outstanding_queries(),
+            overload_reply: OverloadReply::default(),
+            udp_batch_max: default_udp_batch_max(),
+            udp_batch_adaptive: default_udp_batch_adaptive(),
+            udp_buffers: UdpBufferSettings::default(),
             cache_capacity: default_cache_capacity(),
+            cache_max_bytes: 0,
             cache_max_ttl: default_cache_max_ttl(),
             dashmap_shards: default_dashmap_shards(),
             serve_stale: default_serve_stale(),
@@ -262,12 +1148,58 @@ impl Default for GlobalSettings {
             cache_background_refresh: default_cache_background_refresh(),
             cache_refresh_threshold_percent: default_cache_refresh_threshold_percent(),
             cache_refresh_min_ttl: default_cache_refresh_min_ttl(),
+            prefetch_workers: default_prefetch_workers(),
+            prefetch_queue_size: default_prefetch_queue_size(),
+            prefetch_qtypes: HashMap::new(),
+            ipv6_probe_addr: default_ipv6_probe_addr(),
+            suppress_aaaa_without_ipv6: false,
             geoip_db_path: None,
             geoip_dat_path: None,
             geoip_auto_convert: false,
             geoip_filter_countries: Vec::new(),
             geosite_data_paths: Vec::new(),
             enable_tcp_fallback: default_enable_tcp_fallback(),
+            tcp_retry_on_truncation: default_tcp_retry_on_truncation(),
+            upstream_options: HashMap::new(),
+            upstream_log: None,
+            admin_bind: None,
+            grpc_admin_bind: None,
+            domain_stats_enabled: false,
+            domain_stats_capacity: default_domain_stats_capacity(),
+            domain_stats_window_secs: default_domain_stats_window_secs(),
+            domain_stats_min_queries: default_domain_stats_min_queries(),
+            domain_stats_sample_rate: default_domain_stats_sample_rate(),
+            allowlist: Vec::new(),
+            allowlist_files: Vec::new(),
+            mdns_bridge: false,
+            mdns_timeout_ms: default_mdns_timeout_ms(),
+            mdns_interface: None,
+            upstream_prewarm: false,
+            upstream_prewarm_interval_secs: 0,
+            tcp_fast_open: false,
+            cache_compress_threshold: 0,
+            negative_cache: NegativeCacheSettings::default(),
+            stats_dump_path: None,
+            edns_padding_block_size: default_edns_padding_block_size(),
+            upstream_unique_qname_limit: 0,
+            privacy: PrivacySettings::default(),
+            log_rate_limit: LogRateLimitSettings::default(),
+            private_ptr: PrivatePtrSettings::default(),
+            non_in_qclass: QclassPolicy::default(),
+            multi_question: MultiQuestionPolicy::default(),
+            malformed_query: MalformedQuerySettings::default(),
+            readiness: ReadinessSettings::default(),
+            peer_sync: PeerSyncSettings::default(),
+            upstream_health_check: UpstreamHealthCheckSettings::default(),
+            cluster: ClusterSettings::default(),
+            ddr: DdrSettings::default(),
+            local_records: Vec::new(),
+            local_records_rotate: false,
+            local_records_probe_interval_secs: default_local_records_probe_interval_secs(),
+            edns_options: EdnsOptionPolicy::default(),
+            ptr_rewrite: Vec::new(),
+            minimal_answers: false,
+            zero_ttl: ZeroTtlPolicy::default(),
         }
     }
 }
@@ -284,16 +1216,59 @@ fn default_dashmap_shards() -> usize {
     0 // 0 means use DashMap default (num_cpus * 4)
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Pipeline {
     pub id: String,
+    /// 是否启用（默认 true）；禁用的 pipeline 仍会校验，但不参与选择与跳转。 / Whether enabled (default true); disabled pipelines are still validated but never selected or jumped to
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// 说明文字，仅供阅读。 / Free-form description, for readers only
+    #[serde(default)]
+    pub description: Option<String>,
+    /// 覆盖全局 max_ttl（0 表示此 pipeline 不限制）。 / Overrides the global max_ttl (0 = no cap for this pipeline)
+    #[serde(default)]
+    pub max_ttl: Option<u32>,
+    /// 覆盖全局 response_jump_limit，从此 pipeline 开始的跳转链使用该上限。 / Overrides the global response_jump_limit for jump chains starting in this pipeline
+    #[serde(default)]
+    pub response_jump_limit: Option<u32>,
+    /// 覆盖全局 edns_options。 / Overrides the global edns_options
+    #[serde(default)]
+    pub edns_options: Option<EdnsOptionPolicy>,
+    /// 覆盖全局 suppress_aaaa_without_ipv6。 / Overrides the global suppress_aaaa_without_ipv6
+    #[serde(default)]
+    pub suppress_aaaa_without_ipv6: Option<bool>,
+    /// 覆盖全局 zero_ttl。 / Overrides the global zero_ttl
+    #[serde(default)]
+    pub zero_ttl: Option<ZeroTtlPolicy>,
+    /// 本 pipeline 的查询日志隐私策略。 / Query log privacy policy of this pipeline
+    #[serde(default)]
+    pub query_log: QueryLogPolicy,
     #[serde(default)]
     pub rules: Vec<Rule>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// pipeline 的查询日志策略，作用于输出到 `query` 与 `file` 目标的 Log 动作
+/// Query log policy of a pipeline, applied to Log actions writing to the `query` and `file` targets
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct QueryLogPolicy {
+    /// 不记录的域名，条目格式同 allowlist。 / Names never logged, entries use the allowlist format
+    pub exclude_domains: Vec<String>,
+    /// 记录时省略 qname 的客户端分组（client_groups 中定义）。 / Client groups (defined in client_groups) whose qnames are left out of the log
+    pub redact_client_groups: Vec<String>,
+    /// 只记录拦截规则（动作含 deny 等拦截动作）的日志。 / Only log from blocking rules (whose actions include deny or another block action)
+    pub blocked_only: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Rule {
     pub name: String,
+    /// 是否启用（默认 true）；禁用的规则仍会校验，但不参与匹配。 / Whether enabled (default true); disabled rules are still validated but never matched
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// 说明文字，仅供阅读。 / Free-form description, for readers only
+    #[serde(default)]
+    pub description: Option<String>,
     #[serde(default)]
     pub matchers: Vec<MatcherWithOp>,
     #[serde(default = "default_match_operator")]
@@ -313,7 +1288,7 @@ pub struct Rule {
     pub response_actions_on_miss: Vec<Action>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Matcher {
     Any,
@@ -325,10 +1300,14 @@ pub enum Matcher {
     DomainRegex {
         value: String,
     },
-    /// 匹配客户端IP的CIDR。 / Match client IP CIDR
+    /// 匹配客户端IP的CIDR（逗号分隔多个）。 / Match client IP CIDR (comma-separated for several)
     ClientIp {
         cidr: String,
     },
+    /// 匹配客户端所属分组（client_groups 中定义）。 / Match the client group (defined in client_groups)
+    ClientGroup {
+        name: String,
+    },
     /// 匹配客户端IP的GeoIP国家代码（大小写不敏感）。 / Match client IP GeoIP country code (case insensitive)
     GeoipCountry {
         country_codes: Vec<String>,
@@ -357,15 +1336,77 @@ pub enum Matcher {
     Qtype {
         value: String,
     },
+    /// DGA / DNS 隧道启发式评分（熵、标签长度、数字比例、唯一子域名速率），得分达到阈值即匹配。 / DGA / DNS-tunneling heuristic score (entropy, label length, digit ratio, unique-subdomain rate), matches when the score reaches the threshold
+    DomainHeuristics {
+        #[serde(default = "default_heuristics_threshold")]
+        threshold: f64,
+        /// 仅对该后缀下的子域名评分，同时作为速率统计的父域。 / Only score names under this suffix; also used as the parent for rate tracking
+        #[serde(default)]
+        suffix: Option<String>,
+        /// 窗口内同一父域的唯一子域名上限，0 表示不计算速率分量。 / Unique subdomains per parent within the window, 0 disables the rate component
+        #[serde(default)]
+        max_unique_subdomains: u32,
+        #[serde(default = "default_heuristics_window_secs")]
+        window_secs: u64,
+    },
+    /// 请求域名属于命名域名集合（sets 中定义）。 / Request name belongs to a named domain set (defined in sets)
+    DomainSetRef {
+        name: String,
+    },
+    /// 客户端在窗口内收到的 NXDOMAIN 数达到阈值即匹配（随机子域名攻击 / 配置错误的设备）。 / Matches once a client received at least threshold NXDOMAIN responses within the window (random-subdomain attacks / misconfigured devices)
+    NxdomainBurst {
+        threshold: u32,
+        /// 统计窗口（秒，1~60）。 / Counting window in seconds (1~60)
+        #[serde(default = "default_nxdomain_burst_window_secs")]
+        window_secs: u64,
+    },
 }
 
-#[derive(Debug, Clone, Deserialize)]
+fn default_heuristics_threshold() -> f64 {
+    0.7
+}
+
+fn default_heuristics_window_secs() -> u64 {
+    60
+}
+
+fn default_doh_path() -> String {
+    "/dns-query".to_string()
+}
+
+fn default_doh_max_body_bytes() -> usize {
+    65535
+}
+
+fn default_doh_max_concurrent_streams() -> u32 {
+    100
+}
+
+fn default_nxdomain_burst_window_secs() -> u64 {
+    10
+}
+
+/// 命名域名集合，条目格式同 allowlist（`example.com` / `full:` / `domain:`）。 / Named domain set, entries use the allowlist format (`example.com` / `full:` / `domain:`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DomainSetConfig {
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// 每行一个条目的文件，`#` 之后为注释。 / Files with one entry per line, `#` starts a comment
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum PipelineSelectorMatcher {
     /// 入口标签匹配（来自启动参数 listener_label）。 / Entry label matching (from listener_label startup parameter)
     ListenerLabel { value: String },
-    /// 客户端IP CIDR。 / Client IP CIDR
+    /// 查询到达的监听器传输协议（udp/tcp/dot/doh）。 / Listener transport the query arrived on (udp/tcp/dot/doh)
+    Transport { value: ListenerTransport },
+    /// 客户端IP CIDR（逗号分隔多个）。 / Client IP CIDR (comma-separated for several)
     ClientIp { cidr: String },
+    /// 客户端分组（client_groups 中定义）。 / Client group (defined in client_groups)
+    ClientGroup { name: String },
     /// 请求域名后缀。 / Request domain suffix
     DomainSuffix { value: String },
     /// 请求域名正则。 / Request domain regex
@@ -386,9 +1427,11 @@ pub enum PipelineSelectorMatcher {
     GeoipPrivate { expect: bool },
     /// 请求 QTYPE（如 A/AAAA/CNAME/TXT/MX 等）。 / Request QTYPE (e.g., A/AAAA/CNAME/TXT/MX, etc.)
     Qtype { value: String },
+    /// 命名域名集合（sets 中定义）。 / Named domain set (defined in sets)
+    DomainSetRef { name: String },
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PipelineSelectRule {
     pub pipeline: String,
     #[serde(default)]
@@ -397,7 +1440,7 @@ pub struct PipelineSelectRule {
     pub matcher_operator: MatchOperator,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MatcherWithOp {
     #[serde(default = "default_match_operator")]
     pub operator: MatchOperator,
@@ -405,7 +1448,7 @@ pub struct MatcherWithOp {
     pub matcher: Matcher,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PipelineSelectorMatcherWithOp {
     #[serde(default = "default_match_operator")]
     pub operator: MatchOperator,
@@ -413,7 +1456,7 @@ pub struct PipelineSelectorMatcherWithOp {
     pub matcher: PipelineSelectorMatcher,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ResponseMatcherWithOp {
     #[serde(default = "default_match_operator")]
     pub operator: MatchOperator,
@@ -421,7 +1464,7 @@ pub struct ResponseMatcherWithOp {
     pub matcher: ResponseMatcher,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ResponseMatcher {
     /// 匹配使用的上游（字符串相等）。 / Match the upstream used (string equality)
@@ -433,7 +1476,13 @@ pub enum ResponseMatcher {
     /// 匹配响应所来自的上游 IP（支持 CIDR）。 / Match the upstream IP from which the response originated (supports CIDR)
     ResponseUpstreamIp { cidr: String },
     /// 匹配响应 Answer 中的 IP 地址（A/AAAA 记录，支持 CIDR）。 / Match IP addresses in response Answer (A/AAAA records, supports CIDR)
-    ResponseAnswerIp { cidr: String },
+    ResponseAnswerIp {
+        #[serde(default)]
+        cidr: String,
+        /// CIDR 列表文件（每行一个，如 chnroute.txt），修改后自动重载。 / CIDR list file (one per line, e.g. chnroute.txt), reloaded on change
+        #[serde(default)]
+        file: Option<String>,
+    },
     /// 匹配响应记录类型（如 A/AAAA/CNAME/TXT/MX 等）。 / Match response record type (e.g., A/AAAA/CNAME/TXT/MX, etc.)
     ResponseType { value: String },
     /// 匹配响应的RCode（如 NOERROR/NXDOMAIN/SERVFAIL）。 / Match response RCode (e.g., NOERROR/NXDOMAIN/SERVFAIL)
@@ -452,18 +1501,42 @@ pub enum ResponseMatcher {
     ResponseRequestDomainGeoSiteNot { value: String },
     /// 匹配响应中 TXT 记录的内容 / Match TXT record content in response
     ResponseTxtContent {
-        /// 匹配模式: exact(精确), prefix(前缀), regex(正则) / Match mode: exact, prefix, or regex
+        /// 匹配模式: exact(精确), prefix(前缀), contains(包含), regex(正则) / Match mode: exact, prefix, contains, or regex
         mode: String,
         /// 要匹配的文本 / Text to match
         value: String,
     },
+    /// 匹配 Authority 段中 NS 记录指向的名称服务器后缀 / Match the suffix of nameservers named by NS records in the Authority section
+    ResponseAuthorityNsSuffix { value: String },
+    /// Authority 段是否携带 SOA（真实的 NXDOMAIN/NODATA 通常带 SOA）/ Whether the Authority section carries an SOA (genuine NXDOMAIN/NODATA usually does)
+    ResponseAuthoritySoa { expect: bool },
+    /// 匹配 Answer 中 SVCB/HTTPS 记录的参数（key 如 alpn/port/ipv4hint/ipv6hint，value 省略时只要求参数存在）
+    /// Match a parameter of SVCB/HTTPS records in the Answer (key such as alpn/port/ipv4hint/ipv6hint; without value the parameter only has to be present)
+    ResponseSvcParam {
+        key: String,
+        /// alpn 为协议标识（如 h3），port 为端口，ipv4hint/ipv6hint 为 CIDR 列表 / A protocol id (e.g. h3) for alpn, a port for port, a CIDR list for ipv4hint/ipv6hint
+        #[serde(default)]
+        value: String,
+    },
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Action {
     /// 记录日志，level可选：trace/debug/info/warn/error / Log action, level options: trace/debug/info/warn/error
-    Log { level: Option<String> },
+    Log {
+        level: Option<String>,
+        /// 日志模板，支持 {qname} {qtype} {client} {upstream} {rcode} {latency} {mark} {matched_rule} 占位符
+        /// Log template with {qname} {qtype} {client} {upstream} {rcode} {latency} {mark} {matched_rule} placeholders
+        #[serde(default)]
+        template: Option<String>,
+        /// 输出目标（默认主日志）/ Output target (main log by default)
+        #[serde(default)]
+        target: LogTarget,
+        /// 自定义标记，填入 {mark} 占位符 / Custom mark filled into the {mark} placeholder
+        #[serde(default)]
+        mark: Option<String>,
+    },
     /// 固定响应rcode（如 NXDOMAIN/NOERROR）。 / Static response rcode (e.g., NXDOMAIN/NOERROR)
     StaticResponse { rcode: String },
     /// 返回固定 IP (A/AAAA)。 / Return static IP (A/AAAA)
@@ -477,8 +1550,16 @@ pub enum Action {
         #[serde(default)]
         ttl: Option<u32>,
     },
-    /// 跳转到指定 Pipeline 继续处理。 / Jump to specified Pipeline to continue processing
-    JumpToPipeline { pipeline: String },
+    /// 跳转到指定 Pipeline 继续处理。响应阶段跳转时，目标 pipeline 命中的转发规则直接使用已取得的响应执行其响应匹配器与动作，
+    /// reforward 为 true 时改为重新转发。 / Jump to specified Pipeline to continue processing. On a response-phase
+    /// jump the forwarding rule hit in the target pipeline runs its response matchers and actions on the response
+    /// already obtained, or forwards again when reforward is true.
+    JumpToPipeline {
+        pipeline: String,
+        /// 响应阶段跳转后重新转发而不沿用已取得的响应 / Forward again after a response-phase jump instead of reusing the response
+        #[serde(default)]
+        reforward: bool,
+    },
     /// 终止匹配。请求阶段使用默认上游，响应阶段使用当前响应。 / Terminate matching. Request phase uses default upstream, response phase uses current response
     Allow,
     /// 终止并丢弃（返回 REFUSED）。 / Terminate and drop (return REFUSED)
@@ -502,10 +1583,91 @@ pub enum Action {
         #[serde(deserialize_with = "deserialize_txt_text")]
         text: Vec<String>,
     },
+    /// 改写响应 rcode（仅响应阶段），from 不匹配时继续执行后续动作
+    /// Rewrite the response rcode (response phase only); when `from` does not match, later actions run
+    RewriteRcode { from: String, to: String },
+    /// 上游 UDP 响应被截断（TC=1）时，通过 TCP 向同一上游重新查询并使用新响应（仅响应阶段）
+    /// Re-query the same upstream over TCP when its UDP response is truncated (TC=1) and use that answer (response phase only)
+    RetryTcp,
+    /// 延迟 ms 毫秒后再继续执行后续动作（tarpit）/ Wait ms milliseconds before running later actions (tarpit)
+    Delay { ms: u64 },
+    /// 将（qname, 给出响应的上游）隔离 minutes 分钟，期间该 qname 的转发跳过此上游，然后继续执行后续动作（仅响应阶段）
+    /// Quarantine the (qname, answering upstream) pair for minutes so forwards of the qname skip
+    /// that upstream meanwhile, then run later actions (response phase only)
+    QuarantineUpstream { minutes: u64 },
+    /// 置位或清除响应头部的 AA/RA/AD/CD 标志，然后继续执行后续动作（仅响应阶段）
+    /// Set or clear the AA/RA/AD/CD bits of the response header, then run later actions (response phase only)
+    SetHeaderFlags {
+        #[serde(default)]
+        set: Vec<HeaderFlag>,
+        #[serde(default)]
+        clear: Vec<HeaderFlag>,
+    },
+    /// 以给定的 listener_label 重新执行 pipeline_select 并跳转到选出的 pipeline（仅请求阶段）；
+    /// 选出的仍是当前 pipeline 时忽略，继续执行后续动作
+    /// Re-run pipeline_select with the given listener_label and jump to the selected pipeline
+    /// (request phase only); when the current pipeline is selected again the action is ignored
+    /// and later actions run
+    ReselectPipeline { listener_label: String },
+}
+
+/// 可由 set_header_flags 修改的响应头部标志 / Response header flags set_header_flags can change
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaderFlag {
+    /// 权威应答 / Authoritative answer
+    Aa,
+    /// 可递归 / Recursion available
+    Ra,
+    /// 数据已验证 / Authentic data
+    Ad,
+    /// 禁用检查 / Checking disabled
+    Cd,
+}
+
+impl HeaderFlag {
+    /// 标志所在的头部字节与掩码 / Header byte and mask holding the flag
+    pub fn position(self) -> (usize, u8) {
+        match self {
+            HeaderFlag::Aa => (2, 0x04),
+            HeaderFlag::Ra => (3, 0x80),
+            HeaderFlag::Ad => (3, 0x20),
+            HeaderFlag::Cd => (3, 0x10),
+        }
+    }
+}
+
+/// Log 动作的输出目标 / Output target of the Log action
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LogTarget {
+    /// 主日志 / Main log
+    #[default]
+    Main,
+    /// 查询日志（tracing target `query_log`）/ Query log (tracing target `query_log`)
+    Query,
+    /// 追加写入单独文件 / Append to a separate file
+    File { path: String },
 }
 
 /// Action 辅助函数 / Action helper functions
 impl Action {
+    /// 是否为拦截类动作（受全局放行名单约束）/ Whether this is a block action (subject to the global allowlist)
+    ///
+    /// Deny、StaticResponse，以及返回 0.0.0.0 / :: 的 StaticIpResponse。
+    /// Deny, StaticResponse, and StaticIpResponse returning 0.0.0.0 / ::.
+    #[inline]
+    pub fn is_block(&self) -> bool {
+        match self {
+            Action::Deny | Action::StaticResponse { .. } => true,
+            Action::StaticIpResponse { ip } => ip
+                .parse::<std::net::IpAddr>()
+                .map(|ip| ip.is_unspecified())
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+
     /// 预分割 upstream 字符串以优化性能（在配置加载时调用）/ Pre-split upstream string for performance (call during config loading)
     #[inline]
     pub fn pre_split_upstreams(&mut self) {
@@ -528,6 +1690,25 @@ impl Action {
 }
 
 impl GlobalSettings {
+    /// 实际生效的请求超时（毫秒）/ Effective request timeout in milliseconds
+    pub fn effective_request_timeout_ms(&self) -> u64 {
+        // 如果用户显式配置了 request_timeout，使用配置值
+        // If user explicitly configured request_timeout, use that value
+        if let Some(timeout) = self.request_timeout_ms {
+            timeout
+        } else {
+            // 自动计算：hedge(1/3) + full(1x) + tcp_fallback(1x) + 余量
+            // - hedge 通常提前返回，不计入最大时间
+            // - 实际路径：hedge 尝试 → full 尝试 → tcp fallback
+            // - 最大时间：upstream * 2.5（保守估计）
+            // Auto-calculate: hedge(1/3) + full(1x) + tcp_fallback(1x) + margin
+            // - hedge usually returns early, not counted in max time
+            // - Actual path: hedge attempt → full attempt → tcp fallback
+            // - Max time: upstream * 2.5 (conservative estimate)
+            self.upstream_timeout_ms * 5 / 2  // * 2.5
+        }
+    }
+
     /// 预分割默认 upstream 字符串以优化性能（在配置加载时调用）/ Pre-split default upstream string for performance (call during config loading)
     #[inline]
     pub fn pre_split_default_upstream(&mut self) {
@@ -576,7 +1757,7 @@ impl GlobalSettings {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq, Eq, Hash, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Transport {
     Udp,
@@ -593,9 +1774,18 @@ pub enum Transport {
     /// DNS over QUIC (DoQ)
     /// DNS over QUIC（DoQ）
     Doq,
+    /// DNSCrypt v2
+    /// DNSCrypt v2（`sdns://` 印章或 `dnscrypt://` 参数形式）
+    Dnscrypt,
+    /// Oblivious DoH (ODoH) via a relay
+    /// 经中继转发的 Oblivious DoH（ODoH）
+    Odoh,
+    /// Built-in mock upstream synthesizing answers for load and integration tests
+    /// 内置模拟上游，为压测与集成测试合成应答（`mock://a=1.2.3.4&ttl=60`）
+    Mock,
 }
 
-#[derive(Debug, Clone, Deserialize, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Copy, PartialEq, Eq, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum MatchOperator {
     And,
@@ -617,8 +1807,30 @@ fn default_match_operator() -> MatchOperator {
 pub fn load_config(path: &Path) -> Result<PipelineConfig> {
     let raw = fs::read_to_string(path)
         .with_context(|| format!("read config file: {}", path.display()))?;
-    let mut cfg: PipelineConfig = serde_json::from_str(&raw)
-        .with_context(|| format!("parse config file: {}", path.display()))?;
+    parse_config(&raw, &path.display().to_string())
+}
+
+/// 解析并校验内存中的配置 JSON；source 仅用于错误信息
+/// Parse and validate config JSON held in memory; source only labels error messages
+pub fn parse_config(raw: &str, source: &str) -> Result<PipelineConfig> {
+    let value: serde_json::Value = serde_json::from_str(raw)
+        .with_context(|| format!("parse config file: {}", source))?;
+    let mut cfg: PipelineConfig = serde_json::from_value(value.clone())
+        .with_context(|| format!("parse config file: {}", source))?;
+
+    let unknown = unknown_fields(&value, &cfg);
+    if !unknown.is_empty() {
+        if cfg.strict {
+            anyhow::bail!(
+                "config {} has unknown fields: {}",
+                source,
+                unknown.join(", ")
+            );
+        }
+        for field in &unknown {
+            warn!(target = "config", field = %field, "unknown config field ignored");
+        }
+    }
 
     if let Some(version) = cfg.version.as_ref() {
         info!(target = "config", version = %version, "config loaded");
@@ -629,53 +1841,155 @@ pub fn load_config(path: &Path) -> Result<PipelineConfig> {
     // 预分割默认 upstream 以支持并发查询 / Pre-split default upstream for concurrent queries
     cfg.settings.pre_split_default_upstream();
 
+    // 收集全部错误后一起返回 / Collect every error and return them together
+    let mut errors: Vec<String> = Vec::new();
+    let check_cidrs = |cidr: &str, at: &str, errors: &mut Vec<String>| {
+        for part in cidr.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            if let Err(e) = part.parse::<IpNet>() {
+                errors.push(format!("{}: invalid cidr {}: {}", at, part, e));
+            }
+        }
+    };
+
+    for (name, entries) in &cfg.client_groups {
+        for entry in entries {
+            if let Err(e) = parse_client_net(entry) {
+                errors.push(format!("client_groups.{}: {:#}", name, e));
+            }
+        }
+    }
+
     for pipeline in &mut cfg.pipelines {
         for rule in &mut pipeline.rules {
             for action in &mut rule.actions {
                 action.pre_split_upstreams();
             }
+            let at = format!("rule {}", rule.name);
             for matcher in &rule.matchers {
                 if let Matcher::ClientIp { cidr } = &matcher.matcher {
-                    let _parsed: IpNet = cidr.parse()?;
+                    check_cidrs(cidr, &at, &mut errors);
+                }
+                if let Matcher::ClientGroup { name } = &matcher.matcher
+                    && !cfg.client_groups.contains_key(name)
+                {
+                    errors.push(format!("rule {} references unknown client group {}", rule.name, name));
+                }
+                if let Matcher::DomainSetRef { name } = &matcher.matcher
+                    && !cfg.sets.contains_key(name)
+                {
+                    errors.push(format!("rule {} references unknown domain set {}", rule.name, name));
                 }
             }
             for matcher in &rule.response_matchers {
-                if let ResponseMatcher::RequestDomainSuffix { value } = &matcher.matcher {
-                    if value.is_empty() {
-                        anyhow::bail!("response_matcher request_domain_suffix empty");
-                    }
+                if let ResponseMatcher::RequestDomainSuffix { value } = &matcher.matcher
+                    && value.is_empty()
+                {
+                    errors.push(format!("rule {}: response_matcher request_domain_suffix empty", rule.name));
                 }
                 if let ResponseMatcher::ResponseUpstreamIp { cidr } = &matcher.matcher {
-                    for part in cidr.split(',') {
-                        let s = part.trim();
-                        if !s.is_empty() {
-                            let _parsed: IpNet = s.parse()?;
-                        }
-                    }
+                    check_cidrs(cidr, &at, &mut errors);
                 }
-                if let ResponseMatcher::ResponseAnswerIp { cidr } = &matcher.matcher {
-                    for part in cidr.split(',') {
-                        let s = part.trim();
-                        if !s.is_empty() {
-                            let _parsed: IpNet = s.parse()?;
-                        }
+                if let ResponseMatcher::ResponseAnswerIp { cidr, file } = &matcher.matcher {
+                    if cidr.trim().is_empty() && file.is_none() {
+                        errors.push(format!("rule {}: response_answer_ip needs cidr or file", rule.name));
                     }
+                    check_cidrs(cidr, &at, &mut errors);
                 }
             }
         }
     }
 
     for sel in &cfg.pipeline_select {
+        let at = format!("pipeline_select {}", sel.pipeline);
         for m in &sel.matchers {
             if let PipelineSelectorMatcher::ClientIp { cidr } = &m.matcher {
-                let _parsed: IpNet = cidr.parse()?;
+                check_cidrs(cidr, &at, &mut errors);
+            }
+            if let PipelineSelectorMatcher::ClientGroup { name } = &m.matcher
+                && !cfg.client_groups.contains_key(name)
+            {
+                errors.push(format!("pipeline_select {} references unknown client group {}", sel.pipeline, name));
+            }
+            if let PipelineSelectorMatcher::DomainSetRef { name } = &m.matcher
+                && !cfg.sets.contains_key(name)
+            {
+                errors.push(format!("pipeline_select {} references unknown domain set {}", sel.pipeline, name));
             }
         }
     }
 
+    if !errors.is_empty() {
+        anyhow::bail!("config has {} error(s):\n  {}", errors.len(), errors.join("\n  "));
+    }
+
     Ok(cfg)
 }
 
+/// 配置字段的 serde 别名与正式名称，新增 `#[serde(alias)]` 字段时需同步添加
+/// Serde aliases of config fields and their canonical names; add new `#[serde(alias)]` fields here too
+const FIELD_ALIASES: &[(&str, &str)] = &[("serve_stale_max_secs", "serve_stale_expire_ttl")];
+
+/// 找出配置中未被识别的字段（以路径表示，如 `pipelines[0].rules[1].matcher_opertor`）
+/// Find config fields that were not recognized (as paths such as `pipelines[0].rules[1].matcher_opertor`)
+///
+/// 将解析结果重新序列化后与原始 JSON 比较：原始 JSON 中存在、而序列化结果中没有的键即为未知字段。
+/// 这对扁平化与带标签的枚举同样有效，而 `deny_unknown_fields` 无法与 `flatten` 共用。
+/// 序列化结果只含字段的正式名称，`#[serde(alias)]` 的别名按 [`FIELD_ALIASES`] 对应到正式名称。
+/// The parsed config is serialized again and compared with the raw JSON: keys present in the
+/// raw JSON but missing from the serialized form are unknown. This also works through flattened
+/// and tagged enums, which `deny_unknown_fields` cannot be combined with. The serialized form
+/// only has the canonical field names, so `#[serde(alias)]` keys are mapped to them through
+/// [`FIELD_ALIASES`].
+pub fn unknown_fields(raw: &serde_json::Value, cfg: &PipelineConfig) -> Vec<String> {
+    fn walk(raw: &serde_json::Value, known: &serde_json::Value, path: &str, out: &mut Vec<String>) {
+        use serde_json::Value;
+        match (raw, known) {
+            (Value::Object(raw), Value::Object(known)) => {
+                for (key, value) in raw {
+                    let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    let canonical = FIELD_ALIASES
+                        .iter()
+                        .find(|(alias, _)| alias == key)
+                        .map_or(key.as_str(), |(_, canonical)| canonical);
+                    match known.get(key).or_else(|| known.get(canonical)) {
+                        Some(known) => walk(value, known, &child, out),
+                        None => out.push(child),
+                    }
+                }
+            }
+            (Value::Array(raw), Value::Array(known)) if raw.len() == known.len() => {
+                for (i, (raw, known)) in raw.iter().zip(known).enumerate() {
+                    walk(raw, known, &format!("{}[{}]", path, i), out);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut out = Vec::new();
+    if let Ok(known) = serde_json::to_value(cfg) {
+        walk(raw, &known, "", &mut out);
+    }
+    out
+}
+
+/// 配置文件的 JSON Schema / JSON Schema of the config file
+pub fn schema() -> serde_json::Value {
+    serde_json::to_value(schemars::schema_for!(PipelineConfig)).unwrap_or_default()
+}
+
+/// 解析客户端分组条目：CIDR 或单个 IP / Parse a client group entry: CIDR or a single IP
+pub fn parse_client_net(entry: &str) -> Result<IpNet> {
+    let entry = entry.trim();
+    if let Ok(net) = entry.parse::<IpNet>() {
+        return Ok(net);
+    }
+    let ip: std::net::IpAddr = entry
+        .parse()
+        .with_context(|| format!("invalid CIDR or IP: {}", entry))?;
+    Ok(IpNet::from(ip))
+}
+
 fn default_min_ttl() -> u32 {
     0
 }
@@ -765,6 +2079,26 @@ fn default_flow_control_max_permits() -> usize {
     800
 }
 
+fn default_max_outstanding_queries() -> usize {
+    65536
+}
+
+fn default_udp_batch_max() -> usize {
+    32
+}
+
+fn default_udp_batch_adaptive() -> bool {
+    true
+}
+
+fn default_udp_socket_buffer_bytes() -> usize {
+    4 * 1024 * 1024
+}
+
+fn default_udp_packet_bytes() -> usize {
+    4096
+}
+
 fn default_flow_control_latency_threshold_ms() -> u64 {
     100
 }
@@ -805,6 +2139,18 @@ fn default_cache_refresh_min_ttl() -> u32 {
     5
 }
 
+fn default_prefetch_workers() -> usize {
+    4
+}
+
+fn default_prefetch_queue_size() -> usize {
+    1024
+}
+
+fn default_ipv6_probe_addr() -> String {
+    "[2001:4860:4860::8888]:53".to_string()
+}
+
 /// 反序列化 upstream 字段，支持字符串、逗号分隔字符串或数组格式
 /// Deserialize upstream field, supports string, comma-separated string, or array format
 fn deserialize_upstream<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
@@ -858,6 +2204,116 @@ where
 }
 
 
+fn default_enabled() -> bool {
+    true
+}
+
 fn default_enable_tcp_fallback() -> bool {
     true
 }
+
+fn default_tcp_retry_on_truncation() -> bool {
+    true
+}
+
+fn default_domain_stats_capacity() -> u64 {
+    10_000
+}
+
+fn default_domain_stats_window_secs() -> u64 {
+    3600
+}
+
+fn default_domain_stats_min_queries() -> u64 {
+    50
+}
+
+fn default_domain_stats_sample_rate() -> u32 {
+    1
+}
+
+fn default_privacy_ipv4_prefix() -> u8 {
+    24
+}
+
+fn default_privacy_ipv6_prefix() -> u8 {
+    56
+}
+
+fn default_edns_padding_block_size() -> u16 {
+    // RFC 8467 推荐的查询块大小 / Query block size recommended by RFC 8467
+    128
+}
+
+fn default_private_ptr_suffix() -> String {
+    "internal.".to_string()
+}
+
+fn default_private_ptr_ttl() -> u32 {
+    300
+}
+
+fn default_edns_to_client() -> Vec<u16> {
+    // Extended DNS Errors（RFC 8914）/ Extended DNS Errors (RFC 8914)
+    vec![15]
+}
+
+fn default_ddr_ttl() -> u32 {
+    300
+}
+
+fn default_local_record_ttl() -> u32 {
+    300
+}
+
+fn default_local_records_probe_interval_secs() -> u64 {
+    10
+}
+
+fn default_readiness_upstream_window_secs() -> u64 {
+    60
+}
+
+fn default_readiness_probe_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_peer_sync_interval_secs() -> u64 {
+    30
+}
+
+fn default_peer_sync_max_entries() -> usize {
+    10_000
+}
+
+fn default_health_check_interval_secs() -> u64 {
+    10
+}
+
+fn default_health_check_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_health_check_failure_threshold() -> u32 {
+    3
+}
+
+fn default_health_check_success_threshold() -> u32 {
+    1
+}
+
+fn default_health_check_query_name() -> String {
+    ".".to_string()
+}
+
+fn default_cluster_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_mdns_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_negative_max_ttl() -> u32 {
+    3600
+}
@@ -0,0 +1,369 @@
+//! DNS-over-HTTPS 监听器 / DNS-over-HTTPS listener
+//!
+//! RFC 8484 服务端：TLS（ALPN `h2`）之上的 HTTP/2，在配置的路径上接受 `GET ?dns=<base64url>` 与
+//! `POST application/dns-message` 两种请求。查询与 UDP/TCP 一样先走快速路径与缓存，再进入 pipeline，
+//! 应答的 `cache-control: max-age` 取应答中的最小 TTL。错误请求按 RFC 返回 4xx 状态码，可选附带 JSON
+//! 错误体；请求体上限与每连接并发流上限可配置。
+//! An RFC 8484 server: HTTP/2 over TLS (ALPN `h2`) accepting `GET ?dns=<base64url>` and
+//! `POST application/dns-message` on the configured path. Queries take the fast path and the
+//! cache first and then the pipelines, exactly like UDP/TCP, and `cache-control: max-age` is the
+//! smallest TTL of the answer. Bad requests get the 4xx status codes from the RFC, optionally with
+//! a JSON error body; the body size limit and the concurrent streams per connection are configurable.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use bytes::{Bytes, BytesMut};
+use http::{Method, Request, Response, StatusCode, header};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tracing::debug;
+
+use crate::config::{GlobalSettings, ListenerTransport};
+use crate::engine::{Engine, FastPathResponse, MalformedQuery};
+use crate::socket_utils::canonical_peer;
+use crate::tls_server::HANDSHAKE_TIMEOUT;
+
+/// DoH 的 ALPN 协议标识 / ALPN protocol id of DoH
+pub const DOH_ALPN: &[u8] = b"h2";
+/// DNS 报文的媒体类型 / Media type of DNS messages
+const DNS_MESSAGE: &str = "application/dns-message";
+/// JSON 错误体的媒体类型 / Media type of JSON error bodies
+const JSON: &str = "application/json";
+
+/// DoH 监听器的选项，启动时从 settings 读取 / DoH listener options, read from the settings at startup
+#[derive(Debug, Clone)]
+pub struct DohOptions {
+    /// 查询的 URL 路径 / URL path of queries
+    pub path: Arc<str>,
+    /// 请求体与查询报文的最大字节数 / Max bytes of a request body and query
+    pub max_body_bytes: usize,
+    /// 每个连接的最大并发流 / Max concurrent streams per connection
+    pub max_concurrent_streams: u32,
+    /// 错误应答是否附带 JSON 错误体 / Whether error responses carry a JSON body
+    pub json_errors: bool,
+}
+
+impl DohOptions {
+    pub fn from_settings(s: &GlobalSettings) -> Self {
+        Self {
+            path: Arc::from(s.doh_path.as_str()),
+            max_body_bytes: s.doh_max_body_bytes,
+            max_concurrent_streams: s.doh_max_concurrent_streams,
+            json_errors: s.doh_json_errors,
+        }
+    }
+}
+
+/// 从 HTTP 请求中取出 DNS 查询报文，失败时返回应答的状态码
+/// Extract the DNS query from an HTTP request, or the status code to answer with
+fn parse_query(method: &Method, uri: &http::Uri, content_type: Option<&str>, body: &[u8], opts: &DohOptions) -> Result<Bytes, StatusCode> {
+    if uri.path() != &*opts.path {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let query = match *method {
+        Method::GET => {
+            let dns = uri
+                .query()
+                .and_then(|q| q.split('&').find_map(|kv| kv.strip_prefix("dns=")))
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            Bytes::from(URL_SAFE_NO_PAD.decode(dns.trim_end_matches('=')).map_err(|_| StatusCode::BAD_REQUEST)?)
+        }
+        Method::POST => {
+            if content_type.is_none_or(|ct| !ct.eq_ignore_ascii_case(DNS_MESSAGE)) {
+                return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            }
+            Bytes::copy_from_slice(body)
+        }
+        _ => return Err(StatusCode::METHOD_NOT_ALLOWED),
+    };
+    if query.len() < 12 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if query.len() > opts.max_body_bytes {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    Ok(query)
+}
+
+/// 与 TCP 监听器相同的处理路径：快速路径、缓存，再到完整处理
+/// The same path as the TCP listener: fast path and cache, then full processing
+async fn answer(engine: &Engine, packet: &Bytes, peer: SocketAddr) -> Option<Bytes> {
+    let timeout_dur = Duration::from_millis(engine.get_request_timeout_ms());
+    match engine.handle_packet_fast(packet, peer) {
+        Ok(Some(FastPathResponse::Direct(bytes))) => Some(bytes),
+        Ok(Some(FastPathResponse::CacheHit { cached, tx_id })) => {
+            let mut resp = BytesMut::from(&cached[..]);
+            if resp.len() >= 2 {
+                resp[..2].copy_from_slice(&tx_id.to_be_bytes());
+            }
+            Some(resp.freeze())
+        }
+        Ok(Some(FastPathResponse::AsyncNeeded { qname, qtype, qclass, tx_id, edns_present, dnssec_flags, pipeline_id })) => {
+            let fut = engine.handle_packet_internal_with_pre_parsed(
+                packet,
+                peer,
+                false,
+                qname,
+                qtype,
+                qclass,
+                tx_id,
+                edns_present,
+                dnssec_flags,
+                pipeline_id,
+            );
+            tokio::time::timeout(timeout_dur, fut).await.ok()?.ok()
+        }
+        Ok(None) => match tokio::time::timeout(timeout_dur, engine.handle_packet(packet, peer)).await {
+            Ok(Ok(resp)) => Some(resp),
+            Ok(Err(e)) => error_reply(engine, packet, &e),
+            Err(_) => None,
+        },
+        Err(_) => engine.malformed_reply(packet, true),
+    }
+}
+
+/// 处理失败时的应答：只有无法解析的查询按 malformed_query 回复，其余错误（如上游超时）返回 503
+/// Reply for a failed query: only unparseable queries get the malformed_query reply; other errors (e.g. upstream timeouts) get 503
+fn error_reply(engine: &Engine, packet: &[u8], err: &anyhow::Error) -> Option<Bytes> {
+    err.downcast_ref::<MalformedQuery>()?;
+    engine.malformed_reply(packet, true)
+}
+
+fn status(code: StatusCode) -> Response<()> {
+    let mut resp = Response::new(());
+    *resp.status_mut() = code;
+    resp
+}
+
+/// JSON 错误体 / JSON error body
+fn error_body(code: StatusCode) -> Bytes {
+    let body = serde_json::json!({
+        "status": code.as_u16(),
+        "error": code.canonical_reason().unwrap_or("error"),
+    });
+    Bytes::from(body.to_string())
+}
+
+/// 发送错误应答，按配置附带 JSON 错误体 / Send an error response, with a JSON body when configured
+fn send_error(mut respond: h2::server::SendResponse<Bytes>, code: StatusCode, opts: &DohOptions) -> anyhow::Result<()> {
+    if !opts.json_errors {
+        respond.send_response(status(code), true)?;
+        return Ok(());
+    }
+    let body = error_body(code);
+    let head = Response::builder()
+        .status(code)
+        .header(header::CONTENT_TYPE, JSON)
+        .header(header::CONTENT_LENGTH, body.len())
+        .body(())?;
+    let mut stream = respond.send_response(head, false)?;
+    stream.send_data(body, true)?;
+    Ok(())
+}
+
+/// 处理一个 HTTP/2 流 / Serve one HTTP/2 stream
+async fn serve_stream(
+    req: Request<h2::RecvStream>,
+    mut respond: h2::server::SendResponse<Bytes>,
+    engine: Engine,
+    opts: Arc<DohOptions>,
+    peer: SocketAddr,
+) -> anyhow::Result<()> {
+    let (parts, mut body) = req.into_parts();
+    let mut data = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        if data.len() + chunk.len() > opts.max_body_bytes {
+            return send_error(respond, StatusCode::PAYLOAD_TOO_LARGE, &opts);
+        }
+        data.extend_from_slice(&chunk);
+    }
+    let content_type = parts.headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    let query = match parse_query(&parts.method, &parts.uri, content_type, &data, &opts) {
+        Ok(query) => query,
+        Err(code) => return send_error(respond, code, &opts),
+    };
+
+    let Some(resp) = answer(&engine, &query, peer).await else {
+        return send_error(respond, StatusCode::SERVICE_UNAVAILABLE, &opts);
+    };
+    let max_age = crate::proto_utils::parse_response_quick(&resp).map_or(0, |qr| qr.min_ttl);
+    let head = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, DNS_MESSAGE)
+        .header(header::CONTENT_LENGTH, resp.len())
+        .header(header::CACHE_CONTROL, format!("max-age={}", max_age))
+        .body(())?;
+    let mut stream = respond.send_response(head, false)?;
+    stream.send_data(resp, true)?;
+    Ok(())
+}
+
+/// 运行 DoH 监听器，直到监听 socket 出错 / Run the DoH listener until the listening socket fails
+pub async fn run(listener: TcpListener, acceptor: TlsAcceptor, engine: Engine, opts: Arc<DohOptions>) -> anyhow::Result<()> {
+    let engine = engine.for_transport(ListenerTransport::Doh);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let peer = canonical_peer(peer);
+        let acceptor = acceptor.clone();
+        let engine = engine.clone();
+        let opts = Arc::clone(&opts);
+        tokio::spawn(async move {
+            let tls = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(tls)) => tls,
+                Ok(Err(err)) => {
+                    debug!(peer = %peer, error = %err, "doh handshake failed");
+                    return;
+                }
+                Err(_) => {
+                    debug!(peer = %peer, "doh handshake timeout");
+                    return;
+                }
+            };
+            serve_conn(tls, engine, opts, peer).await;
+        });
+    }
+}
+
+/// 在已建立的连接上提供 HTTP/2 服务 / Serve HTTP/2 on an established connection
+async fn serve_conn<T>(io: T, engine: Engine, opts: Arc<DohOptions>, peer: SocketAddr)
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let handshake = h2::server::Builder::new()
+        .max_concurrent_streams(opts.max_concurrent_streams)
+        .handshake::<_, Bytes>(io);
+    let mut conn = match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+        Ok(Ok(conn)) => conn,
+        _ => {
+            debug!(peer = %peer, "doh http/2 handshake failed");
+            return;
+        }
+    };
+    while let Some(request) = conn.accept().await {
+        let Ok((req, respond)) = request else {
+            break;
+        };
+        let engine = engine.clone();
+        let opts = Arc::clone(&opts);
+        tokio::spawn(async move {
+            if let Err(err) = serve_stream(req, respond, engine, opts, peer).await {
+                debug!(peer = %peer, error = %err, "doh stream failed");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(max_body_bytes: usize, max_concurrent_streams: u32, json_errors: bool) -> DohOptions {
+        DohOptions { path: Arc::from("/dns-query"), max_body_bytes, max_concurrent_streams, json_errors }
+    }
+
+    #[test]
+    fn test_doh_requests_follow_rfc8484() {
+        // Arrange
+        let query = [0u8, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0, 0, 1, 0, 1];
+        let get_uri: http::Uri = format!("/dns-query?dns={}", URL_SAFE_NO_PAD.encode(query)).parse().unwrap();
+        let post_uri: http::Uri = "/dns-query".parse().unwrap();
+        let opts = options(65535, 100, false);
+        let resolve = DohOptions { path: Arc::from("/resolve"), ..opts.clone() };
+
+        // Act
+        let get = parse_query(&Method::GET, &get_uri, None, &[], &opts);
+        let post = parse_query(&Method::POST, &post_uri, Some(DNS_MESSAGE), &query, &opts);
+        let json = parse_query(&Method::POST, &post_uri, Some("application/json"), &query, &opts);
+        let missing = parse_query(&Method::GET, &post_uri, None, &[], &opts);
+        let other_path = parse_query(&Method::GET, &get_uri, None, &[], &resolve);
+        let put = parse_query(&Method::PUT, &post_uri, Some(DNS_MESSAGE), &query, &opts);
+        let too_large = parse_query(&Method::GET, &get_uri, None, &[], &options(20, 100, false));
+
+        // Assert
+        assert_eq!(get.as_deref(), Ok(&query[..]));
+        assert_eq!(post.as_deref(), Ok(&query[..]));
+        assert_eq!(json, Err(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+        assert_eq!(missing, Err(StatusCode::BAD_REQUEST));
+        assert_eq!(other_path, Err(StatusCode::NOT_FOUND));
+        assert_eq!(put, Err(StatusCode::METHOD_NOT_ALLOWED));
+        assert_eq!(too_large, Err(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    #[tokio::test]
+    async fn test_doh_connection_applies_configured_limits_and_json_errors() {
+        // Arrange: A listener limited to 16-byte bodies and 3 streams, with JSON errors
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(serde_json::json!({ "pipelines": [] })).unwrap();
+        let engine = Engine::new(crate::matcher::RuntimePipelineConfig::from_config(cfg).unwrap(), "lbl".to_string());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let opts = Arc::new(options(16, 3, true));
+        tokio::spawn(async move {
+            let (stream, peer) = listener.accept().await.unwrap();
+            serve_conn(stream, engine, opts, peer).await;
+        });
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut client, mut conn) = h2::client::handshake(tcp).await.unwrap();
+        let exchange = async {
+            let req = Request::post(format!("https://{}/dns-query", addr))
+                .header(header::CONTENT_TYPE, DNS_MESSAGE)
+                .body(())
+                .unwrap();
+            let (resp, mut send) = client.send_request(req, false).unwrap();
+            send.send_data(Bytes::from_static(&[0u8; 25]), true).unwrap();
+            let resp = resp.await.unwrap();
+            let content_type = resp.headers().get(header::CONTENT_TYPE).cloned();
+            let status = resp.status();
+            let mut body = resp.into_body();
+            let mut data = BytesMut::new();
+            while let Some(chunk) = body.data().await {
+                data.extend_from_slice(&chunk.unwrap());
+            }
+            (status, content_type, data)
+        };
+
+        // Act
+        let (status, content_type, body) = tokio::select! {
+            r = exchange => r,
+            _ = &mut conn => panic!("connection closed early"),
+        };
+
+        // Assert
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(content_type.as_ref().and_then(|v| v.to_str().ok()), Some(JSON));
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, serde_json::json!({ "status": 413, "error": "Payload Too Large" }));
+        assert_eq!(conn.max_concurrent_send_streams(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_only_malformed_queries_get_the_malformed_reply() {
+        // Arrange: FORMERR for malformed queries over TCP-like transports
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(serde_json::json!({
+            "settings": { "malformed_query": { "tcp": "formerr" } },
+            "pipelines": []
+        }))
+        .unwrap();
+        let engine = Engine::new(crate::matcher::RuntimePipelineConfig::from_config(cfg).unwrap(), "lbl".to_string());
+        let packet = [0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 3, b'w'];
+        let malformed = anyhow::Error::new(MalformedQuery::new(anyhow::anyhow!("truncated question")));
+        let timeout = anyhow::anyhow!("upstream timeout");
+
+        // Act
+        let formerr = error_reply(&engine, &packet, &malformed);
+        let unavailable = error_reply(&engine, &packet, &timeout);
+
+        // Assert
+        let formerr = formerr.expect("malformed query gets the configured reply");
+        assert_eq!(formerr[3] & 0x0F, u16::from(hickory_proto::op::ResponseCode::FormErr) as u8);
+        assert!(unavailable.is_none(), "other errors fall through to 503");
+    }
+}
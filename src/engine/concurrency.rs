@@ -1,18 +1,45 @@
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, AtomicU64, AtomicBool, Ordering};
 
+use crate::config::GlobalSettings;
+
 /// 动态信号量调整的自适应流控状态 / Adaptive flow control state for dynamic semaphore adjustment
+/// 限制参数均为原子变量，配置重载时可直接更新 / Limits are atomics so config reloads can update them in place
 pub struct FlowControlState {
     pub max_permits: AtomicUsize,
-    pub min_permits: usize,
+    pub min_permits: AtomicUsize,
     /// 自UNIX_EPOCH以来的最后调整时间戳（毫秒） / Last adjustment timestamp in milliseconds since UNIX_EPOCH
     /// 时钟回滚通过 adjust_flow_control 中的 saturating_sub 处理 / Clock rollback is handled by saturating_sub in adjust_flow_control
     pub last_adjustment_ms: AtomicU64,
-    pub critical_latency_threshold_ns: u64,
-    pub adjustment_interval_ms: u64,
+    pub critical_latency_threshold_ns: AtomicU64,
+    pub adjustment_interval_ms: AtomicU64,
 }
 
 impl FlowControlState {
+    pub fn new(settings: &GlobalSettings) -> Self {
+        Self {
+            max_permits: AtomicUsize::new(settings.flow_control_max_permits),
+            min_permits: AtomicUsize::new(settings.flow_control_min_permits),
+            last_adjustment_ms: AtomicU64::new(0),
+            critical_latency_threshold_ns: AtomicU64::new(settings.flow_control_latency_threshold_ms * 1_000_000),
+            adjustment_interval_ms: AtomicU64::new(settings.flow_control_adjustment_interval_secs * 1000),
+        }
+    }
+
+    /// 应用重载后的限制，当前 permits 收敛到新的 [min, max] 区间
+    /// Apply reloaded limits; current permits are clamped into the new [min, max] range
+    pub fn apply(&self, settings: &GlobalSettings, permit_manager: &PermitManager) {
+        let max = settings.flow_control_max_permits;
+        let min = settings.flow_control_min_permits.min(max);
+        self.max_permits.store(max, Ordering::Relaxed);
+        self.min_permits.store(min, Ordering::Relaxed);
+        self.critical_latency_threshold_ns
+            .store(settings.flow_control_latency_threshold_ms * 1_000_000, Ordering::Relaxed);
+        self.adjustment_interval_ms
+            .store(settings.flow_control_adjustment_interval_secs * 1000, Ordering::Relaxed);
+        permit_manager.set_max_permits(permit_manager.max_permits().clamp(min, max));
+    }
+
     /// 动态调整 flow control permits 基于系统负载和延迟 / Adaptively adjust flow control permits based on system load and latency
     pub fn adjust(&self, permit_manager: &PermitManager, latest_latency: u64) {
         // Get current time in milliseconds
@@ -26,7 +53,7 @@ impl FlowControlState {
         let mut last_ms = self.last_adjustment_ms.load(Ordering::Acquire);
         loop {
             // Check if adjustment interval has passed
-            if now_ms.saturating_sub(last_ms) < self.adjustment_interval_ms {
+            if now_ms.saturating_sub(last_ms) < self.adjustment_interval_ms.load(Ordering::Relaxed) {
                 return;
             }
 
@@ -63,14 +90,16 @@ impl FlowControlState {
 
         // 决策逻辑：如果延迟高或进行中请求多，减少 permits
         // Decision logic: reduce permits if latency is high or inflight requests are many
-        let should_reduce = latest_latency > self.critical_latency_threshold_ns
+        let critical_latency_threshold_ns = self.critical_latency_threshold_ns.load(Ordering::Relaxed);
+        let min_permits = self.min_permits.load(Ordering::Relaxed);
+        let should_reduce = latest_latency > critical_latency_threshold_ns
             || inflight > current_permits * 2 / 3;
 
-        let should_increase = latest_latency < self.critical_latency_threshold_ns / 2
+        let should_increase = latest_latency < critical_latency_threshold_ns / 2
             && inflight < current_permits / 3;
 
-        if should_reduce && current_permits > self.min_permits {
-            let new_permits = (current_permits * 9 / 10).max(self.min_permits);
+        if should_reduce && current_permits > min_permits {
+            let new_permits = (current_permits * 9 / 10).max(min_permits);
             permit_manager.set_max_permits(new_permits);
             tracing::info!(
                 event = "flow_control_reduce",
@@ -104,6 +133,8 @@ pub struct PermitManager {
     pub(crate) active_permits: AtomicUsize,
     // Maximum permits that can be granted / 可授予的最大 permits
     max_permits: AtomicUsize,
+    // Hard cap from max_outstanding_queries, applies on top of max_permits / 来自 max_outstanding_queries 的硬上限，叠加在 max_permits 之上
+    hard_limit: AtomicUsize,
     // Last recovery timestamp (ms) / 上次恢复时间戳（毫秒）
     last_recovery_ms: AtomicU64,
     // Count of dropped requests due to pool exhaustion / 因pool耗尽而丢弃的请求计数
@@ -115,6 +146,7 @@ impl PermitManager {
         Self {
             active_permits: AtomicUsize::new(0),
             max_permits: AtomicUsize::new(initial_permits),
+            hard_limit: AtomicUsize::new(usize::MAX),
             last_recovery_ms: AtomicU64::new(0),
             dropped_requests: AtomicU64::new(0),
         }
@@ -126,6 +158,7 @@ impl PermitManager {
         Self {
             active_permits: AtomicUsize::new(0),
             max_permits: AtomicUsize::new(usize::MAX),
+            hard_limit: AtomicUsize::new(usize::MAX),
             last_recovery_ms: AtomicU64::new(0),
             dropped_requests: AtomicU64::new(0),
         }
@@ -136,7 +169,7 @@ impl PermitManager {
     pub fn try_acquire(self: &Arc<Self>) -> Option<PermitGuard> {
         loop {
             let active = self.active_permits.load(Ordering::Acquire);
-            let max = self.max_permits.load(Ordering::Acquire);
+            let max = self.max_permits.load(Ordering::Acquire).min(self.hard_limit.load(Ordering::Acquire));
             
             if active >= max {
                 // Pool exhausted: increment counter and log / Pool耗尽：增加计数器并记录
@@ -181,6 +214,13 @@ impl PermitManager {
         self.max_permits.store(new_max, Ordering::Release);
     }
 
+    /// 设置不随流控调整的硬上限，0 表示不限制 / Set the hard cap that flow control never adjusts, 0 = unlimited
+    #[inline]
+    pub fn set_hard_limit(&self, limit: usize) {
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        self.hard_limit.store(limit, Ordering::Release);
+    }
+
     /// Get current max permits / 获取当前最大 permits
     #[inline]
     pub fn max_permits(&self) -> usize {
@@ -321,3 +361,22 @@ impl Drop for PermitGuard {
         }
     }
 }
+
+tokio::task_local! {
+    /// 当前请求任务持有的流控 permit / Flow control permit held by the current request task
+    static REQUEST_PERMIT: Arc<PermitGuard>;
+}
+
+/// 在请求任务内持有 permit，使 tarpit 可以提前归还
+/// Hold the permit for a request task so a tarpit can hand it back early
+pub async fn with_request_permit<F: std::future::Future>(permit: PermitGuard, fut: F) -> F::Output {
+    REQUEST_PERMIT.scope(Arc::new(permit), fut).await
+}
+
+/// 提前归还当前请求的 permit，不在请求任务内时什么也不做
+/// Hand back the current request's permit early; does nothing outside a request task
+pub fn release_request_permit() {
+    if let Ok(permit) = REQUEST_PERMIT.try_with(Arc::clone) {
+        permit.release();
+    }
+}
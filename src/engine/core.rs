@@ -1,6 +1,6 @@
 use std::sync::Arc;
 use std::time::Duration;
-use std::sync::atomic::{AtomicUsize, AtomicU64};
+use std::sync::atomic::{AtomicBool, AtomicUsize, AtomicU64};
 
 use dashmap::DashMap;
 use arc_swap::ArcSwap;
@@ -8,7 +8,8 @@ use moka::sync::Cache;
 use rustc_hash::FxBuildHasher;
 use tracing::{info, warn};
 
-use crate::cache::{DnsCache, new_cache};
+use crate::cache::{CacheMetrics, DnsCache, new_cache};
+use crate::config::ListenerTransport;
 use crate::lock::RwLock;
 use crate::matcher::RuntimePipelineConfig;
 use crate::matcher::geoip::GeoIpManager;
@@ -17,6 +18,25 @@ use crate::matcher::advanced_rule::compile_pipelines;
 use super::utils::{extract_geosite_tags_from_config, uses_geoip_matchers};
 
 use super::concurrency::{PermitManager, FlowControlState};
+use super::dnscrypt::DnscryptClient;
+use super::domain_stats::DomainStats;
+use super::health::Health;
+use super::live_queries::LiveQueries;
+use super::privacy::Privacy;
+use super::mdns::MdnsBridge;
+use super::ipv6_probe::Ipv6Probe;
+use super::netns::NetnsSockets;
+use super::odoh::OdohClient;
+use super::prefetch::PrefetchExecutor;
+use super::qname_limit::UniqueQnameLimiter;
+use super::quarantine::UpstreamQuarantine;
+use super::udp_batch::UdpWorkerStats;
+use super::upstream_stats::UpstreamStats;
+use super::upstream_health::UpstreamHealth;
+use super::local_records::LocalRecordHealth;
+use super::reload_events::ReloadEvents;
+use super::tunables::{ListenAddrs, RuntimeTunables};
+use super::runtime_rules::RuntimeRules;
 use super::types::{EngineInner, InflightMap};
 use super::rules::RuleCacheEntry;
 use super::transport::{UdpClient, TcpMultiplexer, DohClient, DotMultiplexer, DoqClient};
@@ -25,15 +45,29 @@ use super::transport::{UdpClient, TcpMultiplexer, DohClient, DotMultiplexer, Doq
 pub struct Engine {
     pub(crate) state: Arc<ArcSwap<EngineInner>>,
     pub(crate) cache: DnsCache,
+    // Cache insertion/eviction counters / 缓存插入与淘汰计数
+    pub cache_metrics: Arc<CacheMetrics>,
     pub(crate) udp_client: Arc<UdpClient>,
     pub(crate) tcp_mux: Arc<TcpMultiplexer>,
     pub(crate) doh_client: Arc<DohClient>,
     pub(crate) dot_mux: Arc<DotMultiplexer>,
     pub(crate) doq_client: Arc<DoqClient>,
+    pub(crate) dnscrypt_client: Arc<DnscryptClient>,
+    pub(crate) odoh_client: Arc<OdohClient>,
+    // Socket factories for upstreams inside network namespaces / 网络命名空间内上游的 socket 创建线程
+    pub(crate) netns: Arc<NetnsSockets>,
+    /// IPv6 连通性探测，供 AAAA 抑制与预取策略使用 / IPv6 connectivity probe used by AAAA suppression and prefetch policies
+    pub(crate) ipv6_probe: Arc<Ipv6Probe>,
     pub listener_label: Arc<str>,
+    // Listener transport of the queries handled by this handle / 本句柄处理的查询所到达的监听器传输协议
+    pub listener_transport: ListenerTransport,
     // Rule execution result cache: Hash -> (Key, Decision) / 规则执行结果缓存：哈希 -> (键, 决策)
     // Key is stored to verify collisions / 存储键以验证冲突
     pub(crate) rule_cache: Cache<u64, RuleCacheEntry>,
+    // Delay action: cache_hash -> delay ms, so cache hits are delayed as well / Delay 动作：缓存哈希 -> 延迟毫秒，缓存命中同样延迟
+    pub(crate) tarpit: Cache<u64, u64>,
+    // Whether any Delay has been recorded since the last reload / 上次重载后是否记录过 Delay
+    pub(crate) tarpit_active: Arc<AtomicBool>,
     // Runtime metrics for diagnosing concurrency and upstream latency / 运行时指标，用于诊断并发和上游延迟
     pub metrics_inflight: Arc<AtomicUsize>,
     pub metrics_total_requests: Arc<AtomicU64>,
@@ -41,6 +75,22 @@ pub struct Engine {
     pub metrics_parse_quick_failures: Arc<AtomicU64>,
     pub metrics_upstream_ns_total: Arc<AtomicU64>,
     pub metrics_upstream_calls: Arc<AtomicU64>,
+    // UDP responses with TC=1 re-queried over TCP / TC=1 的 UDP 响应改用 TCP 重新查询的次数
+    pub metrics_tc_retries: Arc<AtomicU64>,
+    // Queries answered by joining an identical in-flight upstream exchange / 加入相同的进行中上游交换而得到应答的查询数
+    pub metrics_coalesced: Arc<AtomicU64>,
+    // Distinct qnames forwarded per upstream per second / 每个上游每秒转发的不同 qname
+    pub(crate) qname_limiter: Arc<UniqueQnameLimiter>,
+    // (qname, upstream) pairs quarantined by the quarantine_upstream action / 由 quarantine_upstream 动作隔离的（qname, 上游）
+    pub(crate) quarantine: Arc<UpstreamQuarantine>,
+    // Forwards and cache hits per upstream / 按上游统计的转发与缓存命中
+    pub(crate) upstream_stats: Arc<UpstreamStats>,
+    // Health-check state of each upstream / 各上游的健康检查状态
+    pub(crate) upstream_health: Arc<UpstreamHealth>,
+    // Local record addresses failing their TCP probe / TCP 探测失败的本地记录地址
+    pub(crate) local_record_health: Arc<LocalRecordHealth>,
+    // Batch and drop counters per UDP worker / 按 UDP worker 统计的批次与丢弃
+    pub(crate) udp_worker_stats: Arc<UdpWorkerStats>,
     // Per-request id generator for tracing / 每个请求的 ID 生成器用于追踪
     pub request_id_counter: Arc<AtomicU64>,
     // In-flight dedupe map: cache_hash -> waiters / 进行中的去重映射：缓存哈希 -> 等待者
@@ -68,16 +118,11 @@ pub struct Engine {
     pub metrics_last_upstream_latency_ns: Arc<AtomicU64>,
     // Adaptive flow control state (None when flow control is disabled) / 自适应流控状态（禁用流控时为None）
     pub flow_control_state: Option<Arc<FlowControlState>>,
-    // Cache background refresh settings / 缓存后台刷新设置
-    pub(crate) cache_background_refresh: bool,
-    pub(crate) cache_refresh_threshold_percent: u8,
-    pub(crate) cache_refresh_min_ttl: u32,
-    // RFC 8767: Serve stale cache on upstream failure / RFC 8767: 上游失败时提供过期缓存
-    pub(crate) serve_stale: bool,
-    pub(crate) serve_stale_ttl: u32,
-    pub(crate) serve_stale_expire_ttl: u64,
-    pub(crate) serve_stale_ttl_reset: bool,
-    pub(crate) serve_stale_client_timeout_ms: u64,
+    // Cache background refresh, serve-stale (RFC 8767) and compression settings, updated on reload
+    // 缓存后台刷新、过期缓存 (RFC 8767) 与压缩设置，重载时更新
+    pub(crate) tunables: Arc<RuntimeTunables>,
+    // Listen addresses, changed on reload to trigger a rebind / 监听地址，重载变更时触发重新绑定
+    pub(crate) listen_addrs: tokio::sync::watch::Sender<ListenAddrs>,
     // GeoIP manager for geographic IP-based routing / GeoIP 管理器用于基于地理位置的 IP 路由
     pub geoip_manager: Arc<RwLock<GeoIpManager>>,
     // GeoSite manager for domain category-based routing / GeoSite 管理器用于域名分类路由
@@ -91,6 +136,22 @@ pub struct Engine {
     // Note: Currently reserved for future implementation
     #[allow(dead_code)]
     pub(crate) background_refresh_rule: std::sync::OnceLock<Arc<crate::matcher::RuntimeRule>>,
+    // Bounded worker pool that runs background refresh jobs / 执行后台刷新任务的有界工作池
+    pub prefetch: Arc<PrefetchExecutor>,
+    // Recent config reload events and diffs / 最近的配置重载事件与差异
+    pub reload_events: Arc<ReloadEvents>,
+    // Per-registered-domain query statistics (None when disabled) / 按注册域名的查询统计（禁用时为 None）
+    pub domain_stats: Option<Arc<DomainStats>>,
+    // Temporary rules added via the admin API, kept across reloads / 通过管理接口添加的临时规则，重载后保留
+    pub runtime_rules: Arc<RuntimeRules>,
+    /// 实时查询事件 / Live query events
+    pub live_queries: Arc<LiveQueries>,
+    /// 日志中客户端地址的隐私配置 / Privacy settings for client addresses in logs
+    pub privacy: Arc<Privacy>,
+    /// 存活与就绪状态 / Liveness and readiness state
+    pub health: Arc<Health>,
+    // mDNS bridge for .local names (None when disabled) / .local 名称的 mDNS 桥接（禁用时为 None）
+    pub(crate) mdns: Option<Arc<MdnsBridge>>,
 }
 
 impl Engine {
@@ -99,13 +160,19 @@ impl Engine {
         // moka cache capacity and max TTL are configurable via settings
         let cache_capacity = cfg.settings.cache_capacity;
         let cache_max_ttl = cfg.settings.cache_max_ttl;
-        let cache = new_cache(cache_capacity, cache_max_ttl);
+        let cache_metrics = Arc::new(CacheMetrics::default());
+        let cache = new_cache(cache_capacity, cfg.settings.cache_max_bytes, cache_max_ttl, Arc::clone(&cache_metrics));
         // Rule cache: 10k entries, long default TTL (managed manually per-entry)
         // 规则缓存：1万条，默认长 TTL（通过条目内部 expires_at 手动管理）
         let rule_cache = Cache::builder()
             .max_capacity(10_000)
             .time_to_live(Duration::from_secs(60))
             .build();
+        // Tarpit keys live as long as the cached responses they delay / tarpit 键与被延迟的缓存响应同寿命
+        let tarpit = Cache::builder()
+            .max_capacity(cache_capacity)
+            .time_to_live(Duration::from_secs(cache_max_ttl))
+            .build();
 
         // Extract flow control settings before moving cfg / 在 move cfg 之前提取流控设置
         let udp_pool_size = cfg.settings.udp_pool_size;
@@ -118,24 +185,45 @@ impl Engine {
         let doq_enable_0rtt = cfg.settings.doq_enable_0rtt;
         let flow_control_enabled = cfg.settings.flow_control_enabled;
         let flow_control_initial_permits = cfg.settings.flow_control_initial_permits;
-        let flow_control_min_permits = cfg.settings.flow_control_min_permits;
         let flow_control_max_permits = cfg.settings.flow_control_max_permits;
-        let flow_control_latency_threshold_ms = cfg.settings.flow_control_latency_threshold_ms;
-        let flow_control_adjustment_interval_secs = cfg.settings.flow_control_adjustment_interval_secs;
+        let max_outstanding_queries = cfg.settings.max_outstanding_queries;
+        let flow_control = FlowControlState::new(&cfg.settings);
+        let tunables = Arc::new(RuntimeTunables::new(&cfg.settings));
+        let (listen_addrs, _) = tokio::sync::watch::channel(ListenAddrs::from_settings(&cfg.settings));
         let dashmap_shards = cfg.settings.dashmap_shards;
-        let cache_background_refresh = cfg.settings.cache_background_refresh;
-        let cache_refresh_threshold_percent = cfg.settings.cache_refresh_threshold_percent;
-        let cache_refresh_min_ttl = cfg.settings.cache_refresh_min_ttl;
-        let serve_stale = cfg.settings.serve_stale;
-        let serve_stale_ttl = cfg.settings.serve_stale_ttl;
-        let serve_stale_expire_ttl = cfg.settings.serve_stale_expire_ttl;
-        let serve_stale_ttl_reset = cfg.settings.serve_stale_ttl_reset;
-        let serve_stale_client_timeout_ms = cfg.settings.serve_stale_client_timeout_ms;
+        let prefetch = Arc::new(PrefetchExecutor::new(
+            cfg.settings.prefetch_workers,
+            cfg.settings.prefetch_queue_size,
+        ));
+        let domain_stats = if cfg.settings.domain_stats_enabled {
+            Some(Arc::new(DomainStats::new(
+                cfg.settings.domain_stats_capacity,
+                cfg.settings.domain_stats_window_secs,
+                cfg.settings.domain_stats_min_queries,
+            ).with_sample_rate(cfg.settings.domain_stats_sample_rate)))
+        } else {
+            None
+        };
+        let mdns = if cfg.settings.mdns_bridge {
+            match MdnsBridge::new(cfg.settings.mdns_timeout_ms, cfg.settings.mdns_interface.as_deref()) {
+                Ok(bridge) => Some(Arc::new(bridge)),
+                Err(e) => {
+                    tracing::warn!(error = %e, "mdns bridge disabled");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let privacy = Arc::new(Privacy::new(&cfg.settings.privacy));
+        super::log_limit::set_log_rate_limit(&cfg.settings.log_rate_limit);
 
         // Extract TCP health check settings / 提取 TCP 健康检查配置
         let tcp_health_error_threshold = cfg.settings.tcp_health_check_error_threshold;
         let tcp_max_age_secs = cfg.settings.tcp_connection_max_age_seconds;
         let tcp_idle_timeout_secs = cfg.settings.tcp_connection_idle_timeout_seconds;
+        let tcp_fast_open = cfg.settings.tcp_fast_open;
 
         // Extract GeoIP settings before moving cfg / 在 move cfg 之前提取 GeoIP 设置
         let uses_geoip = uses_geoip_matchers(&cfg);
@@ -293,19 +381,15 @@ impl Engine {
         let (permit_manager, flow_control_state) = if flow_control_enabled {
             let pm = Arc::new(PermitManager::new(flow_control_initial_permits));
             pm.set_max_permits(flow_control_max_permits);
-            (pm, Some(Arc::new(FlowControlState {
-                max_permits: AtomicUsize::new(flow_control_max_permits),
-                min_permits: flow_control_min_permits,
-                last_adjustment_ms: AtomicU64::new(0),
-                critical_latency_threshold_ns: flow_control_latency_threshold_ms * 1_000_000,
-                adjustment_interval_ms: flow_control_adjustment_interval_secs * 1000,
-            })))
+            (pm, Some(Arc::new(flow_control)))
         } else {
             // 无限制模式：创建一个max_permits=usize::MAX的PermitManager
             // Unlimited mode: create a PermitManager with max_permits=usize::MAX
             let pm = Arc::new(PermitManager::new_unlimited());
             (pm, None)
         };
+        // 无论是否启用流控都限制同时进行的慢速路径任务 / Bound in-flight slow-path tasks whether or not flow control is on
+        permit_manager.set_hard_limit(max_outstanding_queries);
 
         // TCP pool size is per-upstream; each upstream gets its own permit manager
         // TCP 连接池大小为"每个 upstream"独立配置；每个 upstream 有各自的 permit manager
@@ -318,16 +402,17 @@ impl Engine {
             tcp_health_error_threshold,
             tcp_max_age_secs,
             tcp_idle_timeout_secs,
-        ));
+        ).with_fast_open(tcp_fast_open));
 
         let dot_mux = Arc::new(DotMultiplexer::new(
             dot_pool_size,
             tcp_health_error_threshold,
             tcp_max_age_secs,
             tcp_idle_timeout_secs,
-        ).expect("initialize DoT multiplexer"));
+        ).expect("initialize DoT multiplexer").with_fast_open(tcp_fast_open));
 
         let doh_client = Arc::new(DohClient::new(doh_pool_size).expect("initialize DoH client"));
+        let odoh_client = Arc::new(OdohClient::new(doh_pool_size).expect("initialize ODoH client"));
         let doq_client = Arc::new(DoqClient::new(
             doq_pool_size,
             doq_idle_timeout_secs,
@@ -342,19 +427,35 @@ impl Engine {
         Self {
             state,
             cache,
+            cache_metrics,
             udp_client: Arc::new(UdpClient::new(udp_pool_size)),
             tcp_mux,
             doh_client,
             dot_mux,
             doq_client,
+            dnscrypt_client: Arc::new(DnscryptClient::new()),
+            odoh_client,
+            netns: Arc::new(NetnsSockets::new()),
+            ipv6_probe: Arc::new(Ipv6Probe::new()),
             listener_label: Arc::from(listener_label),
+            listener_transport: ListenerTransport::Udp,
             rule_cache,
+            tarpit,
+            tarpit_active: Arc::new(AtomicBool::new(false)),
             metrics_inflight: Arc::new(AtomicUsize::new(0)),
             metrics_total_requests: Arc::new(AtomicU64::new(0)),
             metrics_fastpath_hits: Arc::new(AtomicU64::new(0)),
             metrics_parse_quick_failures: Arc::new(AtomicU64::new(0)),
             metrics_upstream_ns_total: Arc::new(AtomicU64::new(0)),
             metrics_upstream_calls: Arc::new(AtomicU64::new(0)),
+            metrics_tc_retries: Arc::new(AtomicU64::new(0)),
+            metrics_coalesced: Arc::new(AtomicU64::new(0)),
+            qname_limiter: Arc::new(UniqueQnameLimiter::new()),
+            quarantine: Arc::new(UpstreamQuarantine::new()),
+            upstream_stats: Arc::new(UpstreamStats::new()),
+            upstream_health: Arc::new(UpstreamHealth::new()),
+            local_record_health: Arc::new(LocalRecordHealth::new()),
+            udp_worker_stats: Arc::new(UdpWorkerStats::new()),
             metrics_last_upstream_latency_ns: Arc::new(AtomicU64::new(0)),
             request_id_counter: Arc::new(AtomicU64::new(1)),
             // DashMap configuration: shard count and initial capacity
@@ -375,22 +476,22 @@ impl Engine {
             refreshing_bitmap: Arc::new(AtomicU64::new(0)),
             permit_manager,
             flow_control_state,
-            // Cache background refresh settings / 缓存后台刷新设置
-            cache_background_refresh,
-            cache_refresh_threshold_percent,
-            cache_refresh_min_ttl,
-            // RFC 8767: Serve stale cache settings / RFC 8767: 过期缓存设置
-            serve_stale,
-            serve_stale_ttl,
-            serve_stale_expire_ttl,
-            serve_stale_ttl_reset,
-            serve_stale_client_timeout_ms,
+            tunables,
+            listen_addrs,
             // GeoIP manager / GeoIP 管理器
             geoip_manager,
             // GeoSite manager / GeoSite 管理器
             geosite_manager,
             // Background refresh dedicated rule (lazy initialization) / 后台刷新专用规则（延迟初始化）
             background_refresh_rule: std::sync::OnceLock::new(),
+            prefetch,
+            reload_events: Arc::new(ReloadEvents::new()),
+            domain_stats,
+            runtime_rules: Arc::new(RuntimeRules::new()),
+            live_queries: Arc::new(LiveQueries::new()),
+            privacy,
+            health: Arc::new(Health::new()),
+            mdns,
         }
     }
 }
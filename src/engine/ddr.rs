@@ -0,0 +1,148 @@
+//! 指定解析器发现（DDR）/ Discovery of Designated Resolvers (DDR)
+//!
+//! 按 RFC 9462 在本地应答 `_dns.resolver.arpa` 的 SVCB 查询，记录格式遵循 RFC 9461：alpn 为
+//! dot / h2 / doq，DoH 端点附带 dohpath。hickory 不认识 dohpath，因此 RDATA 在这里直接编码。
+//! Answers SVCB queries for `_dns.resolver.arpa` locally per RFC 9462, with records shaped
+//! per RFC 9461: alpn is dot / h2 / doq and DoH endpoints carry dohpath. hickory does not know
+//! dohpath, so the RDATA is encoded here directly.
+
+use std::str::FromStr;
+
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::NULL;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use hickory_proto::serialize::binary::BinEncodable;
+
+use crate::config::{DdrDesignation, DdrProtocol, DdrSettings};
+
+/// DDR 查询的名称 / Name queried by DDR
+const RESOLVER_NAME: &str = "_dns.resolver.arpa";
+/// 默认 DoH URI 模板 / Default DoH URI template
+const DEFAULT_DOHPATH: &str = "/dns-query{?dns}";
+
+const KEY_ALPN: u16 = 1;
+const KEY_PORT: u16 = 3;
+const KEY_IPV4HINT: u16 = 4;
+const KEY_IPV6HINT: u16 = 6;
+const KEY_DOHPATH: u16 = 7;
+
+fn push_param(out: &mut Vec<u8>, key: u16, value: &[u8]) -> Option<()> {
+    out.extend_from_slice(&key.to_be_bytes());
+    out.extend_from_slice(&u16::try_from(value.len()).ok()?.to_be_bytes());
+    out.extend_from_slice(value);
+    Some(())
+}
+
+/// 一个端点的 SVCB RDATA（参数按键升序）/ SVCB RDATA of one endpoint (params in ascending key order)
+fn svcb_rdata(priority: u16, d: &DdrDesignation) -> Option<Vec<u8>> {
+    let target = Name::from_str(&d.target).ok()?.to_bytes().ok()?;
+    let mut out = Vec::with_capacity(64);
+    out.extend_from_slice(&priority.to_be_bytes());
+    out.extend_from_slice(&target);
+
+    let alpn: &[u8] = match d.protocol {
+        DdrProtocol::Dot => b"dot",
+        DdrProtocol::Doh => b"h2",
+        DdrProtocol::Doq => b"doq",
+    };
+    let mut alpn_value = vec![alpn.len() as u8];
+    alpn_value.extend_from_slice(alpn);
+    push_param(&mut out, KEY_ALPN, &alpn_value)?;
+    if let Some(port) = d.port {
+        push_param(&mut out, KEY_PORT, &port.to_be_bytes())?;
+    }
+    if !d.ipv4hint.is_empty() {
+        let hints: Vec<u8> = d.ipv4hint.iter().flat_map(|ip| ip.octets()).collect();
+        push_param(&mut out, KEY_IPV4HINT, &hints)?;
+    }
+    if !d.ipv6hint.is_empty() {
+        let hints: Vec<u8> = d.ipv6hint.iter().flat_map(|ip| ip.octets()).collect();
+        push_param(&mut out, KEY_IPV6HINT, &hints)?;
+    }
+    if d.protocol == DdrProtocol::Doh {
+        let path = d.dohpath.as_deref().unwrap_or(DEFAULT_DOHPATH);
+        push_param(&mut out, KEY_DOHPATH, path.as_bytes())?;
+    }
+    Some(out)
+}
+
+/// 端点配置是否可编码，不可编码时返回原因 / Whether a designation can be encoded, with the reason when it cannot
+pub(crate) fn check_designation(d: &DdrDesignation) -> Result<(), String> {
+    Name::from_str(&d.target).map_err(|e| format!("invalid target {:?}: {}", d.target, e))?;
+    if d.dohpath.as_deref().is_some_and(|p| !p.starts_with('/') || !p.contains("{?dns}")) {
+        return Err(format!("dohpath {:?} must be a relative URI template with {{?dns}}", d.dohpath));
+    }
+    Ok(())
+}
+
+/// DDR 查询的本地应答，未启用或不是 DDR 查询时返回 None
+/// Local answer for a DDR query, or None when DDR is off or the query is not one
+pub(crate) fn answer(settings: &DdrSettings, qname: &str, qtype: u16) -> Option<(ResponseCode, Vec<Record>)> {
+    if settings.designations.is_empty() || !qname.trim_end_matches('.').eq_ignore_ascii_case(RESOLVER_NAME) {
+        return None;
+    }
+    if RecordType::from(qtype) != RecordType::SVCB {
+        return Some((ResponseCode::NoError, Vec::new()));
+    }
+    let owner = Name::from_str(qname).ok()?;
+    let records = settings
+        .designations
+        .iter()
+        .zip(1u16..)
+        .filter_map(|(d, priority)| svcb_rdata(priority, d))
+        .map(|rdata| {
+            let rdata = RData::Unknown { code: RecordType::SVCB, rdata: NULL::with(rdata) };
+            Record::from_rdata(owner.clone(), settings.ttl, rdata)
+        })
+        .collect();
+    Some((ResponseCode::NoError, records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::Message;
+    use hickory_proto::rr::rdata::svcb::{SvcParamKey, SvcParamValue};
+
+    #[test]
+    fn test_resolver_arpa_svcb_lists_designations() {
+        // Arrange
+        let settings: DdrSettings = serde_json::from_value(serde_json::json!({
+            "designations": [
+                { "protocol": "dot", "target": "dns.example.net.", "ipv4hint": ["192.0.2.53"] },
+                { "protocol": "doh", "target": "dns.example.net.", "port": 8443 }
+            ]
+        }))
+        .unwrap();
+        let svcb = u16::from(RecordType::SVCB);
+
+        // Act
+        let (rcode, records) = answer(&settings, "_DNS.resolver.arpa.", svcb).unwrap();
+        let nodata = answer(&settings, "_dns.resolver.arpa", u16::from(RecordType::A)).unwrap();
+        let other = answer(&settings, "example.com.", svcb);
+        let disabled = answer(&DdrSettings::default(), "_dns.resolver.arpa.", svcb);
+
+        // Assert: The encoded records parse back as SVCB
+        let mut msg = Message::new();
+        msg.add_answers(records);
+        let parsed = Message::from_vec(&msg.to_vec().unwrap()).unwrap();
+        let svcbs: Vec<_> = parsed
+            .answers()
+            .iter()
+            .map(|r| match r.data() {
+                Some(RData::SVCB(svcb)) => svcb.clone(),
+                other => panic!("expected SVCB, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(rcode, ResponseCode::NoError);
+        assert_eq!(svcbs.len(), 2);
+        assert_eq!(svcbs[0].svc_priority(), 1);
+        assert_eq!(svcbs[0].target_name().to_ascii(), "dns.example.net.");
+        assert!(matches!(&svcbs[0].svc_params()[0], (SvcParamKey::Alpn, SvcParamValue::Alpn(a)) if a.0 == ["dot"]));
+        assert!(svcbs[1].svc_params().contains(&(SvcParamKey::Port, SvcParamValue::Port(8443))));
+        assert!(svcbs[1].svc_params().iter().any(|(k, _)| *k == SvcParamKey::Unknown(KEY_DOHPATH)));
+        assert!(nodata.1.is_empty());
+        assert!(other.is_none());
+        assert!(disabled.is_none());
+    }
+}
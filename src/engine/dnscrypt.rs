@@ -0,0 +1,533 @@
+//! DNSCrypt v2 上游客户端 / DNSCrypt v2 upstream client
+//!
+//! 上游可写为 `sdns://` 服务器印章（dnscrypt-proxy 解析器列表中的格式），或
+//! `dnscrypt://地址:端口?provider=2.dnscrypt-cert.example&pk=<十六进制公钥>`。
+//! 首次查询前通过 TXT 查询获取解析器证书并用提供者公钥校验，选取有效期内序号最大的证书；
+//! 证书每小时或过期后重新获取，以跟随解析器的证书轮换。每个证书会话使用新的临时密钥。
+//! Upstreams are written either as an `sdns://` server stamp (the format used by
+//! dnscrypt-proxy resolver lists) or as
+//! `dnscrypt://host:port?provider=2.dnscrypt-cert.example&pk=<hex public key>`.
+//! Before the first query the resolver certificate is fetched with a TXT query and verified
+//! against the provider public key, picking the valid certificate with the highest serial;
+//! certificates are refetched hourly or once expired to follow the resolver's rotation.
+//! Each certificate session uses a fresh ephemeral key pair.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use bytes::Bytes;
+use crypto_box::aead::rand_core::RngCore;
+use crypto_box::aead::{AeadInPlace, OsRng, generic_array::GenericArray};
+use crypto_box::{ChaChaBox, PublicKey, SalsaBox, SecretKey};
+use dashmap::DashMap;
+use ed25519_dalek::{Signature, VerifyingKey};
+use hickory_proto::op::{Message, MessageType, OpCode, Query};
+use hickory_proto::rr::{Name, RData, RecordType};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+/// 证书魔数 / Certificate magic
+const CERT_MAGIC: &[u8; 4] = b"DNSC";
+/// 响应魔数 / Response magic
+const RESOLVER_MAGIC: &[u8; 8] = b"r6fnvWj8";
+/// 证书最小长度 / Minimum certificate length
+const CERT_LEN: usize = 124;
+/// UDP 查询最小长度 / Minimum UDP query length
+const MIN_QUERY_LEN: usize = 256;
+/// 填充块大小 / Padding block size
+const PADDING_BLOCK: usize = 64;
+/// 认证标签长度 / Authentication tag length
+const TAG_LEN: usize = 16;
+/// 证书重新获取间隔 / Certificate refetch interval
+const CERT_REFRESH: Duration = Duration::from_secs(3600);
+/// 印章协议号 / Stamp protocol identifier
+const STAMP_PROTO_DNSCRYPT: u8 = 0x01;
+/// 默认端口 / Default port
+const DEFAULT_PORT: u16 = 443;
+
+/// 解析后的 DNSCrypt 服务器 / Parsed DNSCrypt server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerSpec {
+    pub addr: String,
+    pub provider_name: String,
+    pub provider_pk: [u8; 32],
+}
+
+impl ServerSpec {
+    /// 解析印章或 `地址?provider=..&pk=..` 形式 / Parse a stamp or the `addr?provider=..&pk=..` form
+    pub fn parse(spec: &str) -> Result<Self> {
+        if let Some(stamp) = spec.strip_prefix("sdns://") {
+            return Self::parse_stamp(stamp);
+        }
+        let spec = spec.strip_prefix("dnscrypt://").unwrap_or(spec);
+        let (addr, query) = spec
+            .split_once('?')
+            .ok_or_else(|| anyhow!("dnscrypt upstream requires ?provider=..&pk=.. or an sdns:// stamp"))?;
+        let mut provider_name = None;
+        let mut provider_pk = None;
+        for pair in query.split('&') {
+            match pair.split_once('=') {
+                Some(("provider", v)) => provider_name = Some(v.to_string()),
+                Some(("pk", v)) => provider_pk = Some(parse_hex_key(v)?),
+                _ => bail!("unknown dnscrypt upstream parameter: {}", pair),
+            }
+        }
+        Ok(Self {
+            addr: with_default_port(addr),
+            provider_name: provider_name.ok_or_else(|| anyhow!("dnscrypt upstream missing provider"))?,
+            provider_pk: provider_pk.ok_or_else(|| anyhow!("dnscrypt upstream missing pk"))?,
+        })
+    }
+
+    /// 解析 sdns 印章 / Parse an sdns stamp
+    fn parse_stamp(stamp: &str) -> Result<Self> {
+        let bin = URL_SAFE_NO_PAD
+            .decode(stamp.trim_end_matches('='))
+            .context("decode sdns stamp")?;
+        if bin.first() != Some(&STAMP_PROTO_DNSCRYPT) {
+            bail!("sdns stamp is not a DNSCrypt stamp");
+        }
+        // 1 字节协议 + 8 字节属性 / 1 byte protocol + 8 bytes properties
+        let mut rest = bin.get(9..).ok_or_else(|| anyhow!("sdns stamp too short"))?;
+        let addr = take_lp(&mut rest)?;
+        let pk = take_lp(&mut rest)?;
+        let provider = take_lp(&mut rest)?;
+        let provider_pk: [u8; 32] = pk.try_into().map_err(|_| anyhow!("sdns stamp public key must be 32 bytes"))?;
+        Ok(Self {
+            addr: with_default_port(std::str::from_utf8(addr).context("sdns stamp address")?),
+            provider_name: std::str::from_utf8(provider).context("sdns stamp provider name")?.to_string(),
+            provider_pk,
+        })
+    }
+}
+
+/// 读取长度前缀字段 / Read a length-prefixed field
+fn take_lp<'a>(rest: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let (&len, tail) = rest.split_first().ok_or_else(|| anyhow!("sdns stamp truncated"))?;
+    let len = len as usize;
+    if tail.len() < len {
+        bail!("sdns stamp truncated");
+    }
+    let (field, tail) = tail.split_at(len);
+    *rest = tail;
+    Ok(field)
+}
+
+/// 解析十六进制公钥，允许 `:` 分隔 / Parse a hex public key, `:` separators allowed
+fn parse_hex_key(s: &str) -> Result<[u8; 32]> {
+    let digits: Vec<u8> = s.bytes().filter(|b| *b != b':').collect();
+    if digits.len() != 64 {
+        bail!("dnscrypt provider public key must be 32 hex bytes");
+    }
+    let mut out = [0u8; 32];
+    for (i, pair) in digits.chunks(2).enumerate() {
+        let hex = std::str::from_utf8(pair).context("dnscrypt provider public key")?;
+        out[i] = u8::from_str_radix(hex, 16).context("dnscrypt provider public key")?;
+    }
+    Ok(out)
+}
+
+/// 补全默认端口 / Append the default port when missing
+fn with_default_port(addr: &str) -> String {
+    if addr.parse::<SocketAddr>().is_ok() {
+        return addr.to_string();
+    }
+    if addr.starts_with('[') || addr.parse::<std::net::Ipv4Addr>().is_ok() || !addr.contains(':') {
+        format!("{}:{}", addr, DEFAULT_PORT)
+    } else if addr.parse::<std::net::Ipv6Addr>().is_ok() {
+        format!("[{}]:{}", addr, DEFAULT_PORT)
+    } else {
+        addr.to_string()
+    }
+}
+
+/// 加密算法 / Encryption construction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EsVersion {
+    XSalsa20Poly1305,
+    XChaCha20Poly1305,
+}
+
+/// 已校验的解析器证书 / Verified resolver certificate
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cert {
+    es_version: EsVersion,
+    resolver_pk: [u8; 32],
+    client_magic: [u8; 8],
+    serial: u32,
+    ts_end: u32,
+}
+
+/// 解析并校验证书 / Parse and verify a certificate
+fn parse_cert(bin: &[u8], provider_pk: &VerifyingKey, now: u32) -> Option<Cert> {
+    if bin.len() < CERT_LEN || &bin[..4] != CERT_MAGIC {
+        return None;
+    }
+    let es_version = match u16::from_be_bytes([bin[4], bin[5]]) {
+        1 => EsVersion::XSalsa20Poly1305,
+        2 => EsVersion::XChaCha20Poly1305,
+        _ => return None,
+    };
+    let signature = Signature::from_bytes(bin[8..72].try_into().ok()?);
+    let signed = &bin[72..];
+    provider_pk.verify_strict(signed, &signature).ok()?;
+    let u32_at = |i: usize| u32::from_be_bytes([bin[i], bin[i + 1], bin[i + 2], bin[i + 3]]);
+    let (ts_start, ts_end) = (u32_at(116), u32_at(120));
+    if now < ts_start || now > ts_end {
+        return None;
+    }
+    Some(Cert {
+        es_version,
+        resolver_pk: bin[72..104].try_into().ok()?,
+        client_magic: bin[104..112].try_into().ok()?,
+        serial: u32_at(112),
+        ts_end,
+    })
+}
+
+/// 共享密钥 / Shared key
+enum Cipher {
+    Salsa(SalsaBox),
+    ChaCha(ChaChaBox),
+}
+
+impl Cipher {
+    fn new(cert: &Cert, secret: &SecretKey) -> Self {
+        let resolver_pk = PublicKey::from(cert.resolver_pk);
+        match cert.es_version {
+            EsVersion::XSalsa20Poly1305 => Cipher::Salsa(SalsaBox::new(&resolver_pk, secret)),
+            EsVersion::XChaCha20Poly1305 => Cipher::ChaCha(ChaChaBox::new(&resolver_pk, secret)),
+        }
+    }
+
+    /// 原地加密，返回 tag||密文 / Encrypt in place, returning tag||ciphertext
+    fn seal(&self, nonce: &[u8; 24], mut buf: Vec<u8>) -> Result<Vec<u8>> {
+        let nonce = GenericArray::from_slice(nonce);
+        let tag = match self {
+            Cipher::Salsa(b) => b.encrypt_in_place_detached(nonce, b"", &mut buf),
+            Cipher::ChaCha(b) => b.encrypt_in_place_detached(nonce, b"", &mut buf),
+        }
+        .map_err(|_| anyhow!("dnscrypt encrypt failed"))?;
+        let mut out = Vec::with_capacity(TAG_LEN + buf.len());
+        out.extend_from_slice(&tag);
+        out.extend_from_slice(&buf);
+        Ok(out)
+    }
+
+    /// 解密 tag||密文 / Decrypt tag||ciphertext
+    fn open(&self, nonce: &[u8; 24], sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < TAG_LEN {
+            bail!("dnscrypt response too short");
+        }
+        let (tag, ct) = sealed.split_at(TAG_LEN);
+        let mut buf = ct.to_vec();
+        let nonce = GenericArray::from_slice(nonce);
+        let tag = GenericArray::from_slice(tag);
+        match self {
+            Cipher::Salsa(b) => b.decrypt_in_place_detached(nonce, b"", &mut buf, tag),
+            Cipher::ChaCha(b) => b.decrypt_in_place_detached(nonce, b"", &mut buf, tag),
+        }
+        .map_err(|_| anyhow!("dnscrypt response authentication failed"))?;
+        Ok(buf)
+    }
+}
+
+/// 证书会话 / Certificate session
+struct Session {
+    cert: Cert,
+    cipher: Cipher,
+    client_pk: [u8; 32],
+    fetched_at: Instant,
+}
+
+impl Session {
+    fn new(cert: Cert) -> Self {
+        let secret = SecretKey::generate(&mut OsRng);
+        let client_pk = *secret.public_key().as_bytes();
+        Self {
+            cipher: Cipher::new(&cert, &secret),
+            cert,
+            client_pk,
+            fetched_at: Instant::now(),
+        }
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.fetched_at.elapsed() < CERT_REFRESH && unix_now() <= self.cert.ts_end
+    }
+
+    /// 构造加密查询 / Build an encrypted query
+    fn encrypt_query(&self, packet: &[u8], min_len: usize) -> Result<([u8; 12], Vec<u8>)> {
+        let mut half = [0u8; 12];
+        OsRng.fill_bytes(&mut half);
+        let mut nonce = [0u8; 24];
+        nonce[..12].copy_from_slice(&half);
+        let sealed = self.cipher.seal(&nonce, pad(packet, min_len))?;
+        let mut wire = Vec::with_capacity(8 + 32 + 12 + sealed.len());
+        wire.extend_from_slice(&self.cert.client_magic);
+        wire.extend_from_slice(&self.client_pk);
+        wire.extend_from_slice(&half);
+        wire.extend_from_slice(&sealed);
+        Ok((half, wire))
+    }
+
+    /// 解密响应并校验 nonce / Decrypt a response and check its nonce
+    fn decrypt_response(&self, half: &[u8; 12], wire: &[u8]) -> Result<Bytes> {
+        if wire.len() < 8 + 24 + TAG_LEN || &wire[..8] != RESOLVER_MAGIC {
+            bail!("invalid dnscrypt response");
+        }
+        let nonce: [u8; 24] = wire[8..32].try_into()?;
+        if &nonce[..12] != half {
+            bail!("dnscrypt response nonce mismatch");
+        }
+        let plain = self.cipher.open(&nonce, &wire[32..])?;
+        unpad(plain).map(Bytes::from)
+    }
+}
+
+/// ISO/IEC 7816-4 填充 / ISO/IEC 7816-4 padding
+fn pad(packet: &[u8], min_len: usize) -> Vec<u8> {
+    let len = (packet.len() + 1).max(min_len).div_ceil(PADDING_BLOCK) * PADDING_BLOCK;
+    let mut buf = Vec::with_capacity(len);
+    buf.extend_from_slice(packet);
+    buf.push(0x80);
+    buf.resize(len, 0);
+    buf
+}
+
+/// 去除填充 / Strip padding
+fn unpad(mut buf: Vec<u8>) -> Result<Vec<u8>> {
+    let end = buf
+        .iter()
+        .rposition(|b| *b != 0)
+        .filter(|&i| buf[i] == 0x80)
+        .ok_or_else(|| anyhow!("invalid dnscrypt padding"))?;
+    buf.truncate(end);
+    Ok(buf)
+}
+
+fn unix_now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+/// 解析服务器地址 / Resolve the server address
+async fn resolve(addr: &str) -> Result<SocketAddr> {
+    if let Ok(sa) = addr.parse() {
+        return Ok(sa);
+    }
+    tokio::net::lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow!("dnscrypt server {} resolved to no addresses", addr))
+}
+
+/// 发送 UDP 数据报并等待一个响应 / Send a UDP datagram and await one reply
+async fn udp_exchange(server: SocketAddr, wire: &[u8]) -> Result<Vec<u8>> {
+    let bind: SocketAddr = if server.is_ipv4() {
+        "0.0.0.0:0".parse()?
+    } else {
+        "[::]:0".parse()?
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+    socket.send(wire).await?;
+    let mut buf = vec![0u8; 65535];
+    let n = socket.recv(&mut buf).await?;
+    buf.truncate(n);
+    Ok(buf)
+}
+
+/// 以 2 字节长度前缀通过 TCP 交换 / Exchange over TCP with a 2-byte length prefix
+async fn tcp_exchange(server: SocketAddr, wire: &[u8]) -> Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(server).await?;
+    let mut out = Vec::with_capacity(2 + wire.len());
+    out.extend_from_slice(&(wire.len() as u16).to_be_bytes());
+    out.extend_from_slice(wire);
+    stream.write_all(&out).await?;
+    let len = stream.read_u16().await? as usize;
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// 响应是否被截断 / Whether a response is truncated
+fn is_truncated(bytes: &[u8]) -> bool {
+    bytes.len() > 2 && bytes[2] & 0x02 != 0
+}
+
+/// DNSCrypt 客户端，按上游缓存证书会话 / DNSCrypt client caching certificate sessions per upstream
+#[derive(Default)]
+pub struct DnscryptClient {
+    sessions: DashMap<String, Arc<Session>>,
+}
+
+impl DnscryptClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 发送查询 / Send a query
+    pub async fn send(&self, packet: &[u8], upstream: &str, timeout_dur: Duration) -> Result<Bytes> {
+        timeout(timeout_dur, async {
+            let spec = ServerSpec::parse(upstream)?;
+            let server = resolve(&spec.addr).await?;
+            let session = self.session(upstream, &spec, server).await?;
+
+            let (half, wire) = session.encrypt_query(packet, MIN_QUERY_LEN)?;
+            let reply = udp_exchange(server, &wire).await?;
+            let bytes = session.decrypt_response(&half, &reply)?;
+            if !is_truncated(&bytes) {
+                return Ok(bytes);
+            }
+            // 截断时改用 TCP / Retry over TCP when truncated
+            let (half, wire) = session.encrypt_query(packet, 0)?;
+            let reply = tcp_exchange(server, &wire).await?;
+            session.decrypt_response(&half, &reply)
+        })
+        .await
+        .map_err(|_| anyhow!("dnscrypt upstream timeout"))?
+    }
+
+    /// 预先获取证书 / Fetch the certificate ahead of time
+    pub async fn prewarm(&self, upstream: &str, timeout_dur: Duration) -> Result<()> {
+        timeout(timeout_dur, async {
+            let spec = ServerSpec::parse(upstream)?;
+            let server = resolve(&spec.addr).await?;
+            self.session(upstream, &spec, server).await.map(|_| ())
+        })
+        .await
+        .map_err(|_| anyhow!("dnscrypt certificate fetch timeout"))?
+    }
+
+    /// 取得有效会话，必要时重新获取证书 / Get a valid session, refetching the certificate when needed
+    async fn session(&self, upstream: &str, spec: &ServerSpec, server: SocketAddr) -> Result<Arc<Session>> {
+        let cached = self.sessions.get(upstream).map(|s| s.clone());
+        if let Some(session) = &cached
+            && session.is_fresh()
+        {
+            return Ok(session.clone());
+        }
+        match fetch_cert(spec, server).await {
+            Ok(cert) => {
+                let session = Arc::new(Session::new(cert));
+                self.sessions.insert(upstream.to_string(), session.clone());
+                Ok(session)
+            }
+            Err(e) => match cached {
+                // 证书仍在有效期内时继续使用 / Keep using a certificate that has not expired yet
+                Some(old) if unix_now() <= old.cert.ts_end => {
+                    tracing::debug!(upstream = %upstream, error = %e, "dnscrypt certificate refresh failed");
+                    Ok(old)
+                }
+                _ => Err(e),
+            },
+        }
+    }
+}
+
+/// 获取并校验解析器证书 / Fetch and verify the resolver certificate
+async fn fetch_cert(spec: &ServerSpec, server: SocketAddr) -> Result<Cert> {
+    let provider_pk = VerifyingKey::from_bytes(&spec.provider_pk).context("invalid dnscrypt provider public key")?;
+    let mut msg = Message::new();
+    msg.set_id(OsRng.next_u32() as u16)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true)
+        .add_query(Query::query(Name::from_ascii(&spec.provider_name)?, RecordType::TXT));
+    let query = msg.to_vec()?;
+
+    let mut reply = udp_exchange(server, &query).await?;
+    if is_truncated(&reply) {
+        reply = tcp_exchange(server, &query).await?;
+    }
+    let reply = Message::from_vec(&reply).context("parse dnscrypt certificate response")?;
+
+    let now = unix_now();
+    reply
+        .answers()
+        .iter()
+        .filter_map(|r| match r.data() {
+            Some(RData::TXT(txt)) => Some(txt.txt_data().concat()),
+            _ => None,
+        })
+        .filter_map(|bin| parse_cert(&bin, &provider_pk, now))
+        .max_by_key(|c| (c.es_version == EsVersion::XChaCha20Poly1305, c.serial))
+        .ok_or_else(|| anyhow!("no valid dnscrypt certificate for {}", spec.provider_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_stamp_and_params_parse_to_same_server() {
+        // Arrange
+        let pk = [0xabu8; 32];
+        let mut bin = vec![STAMP_PROTO_DNSCRYPT];
+        bin.extend_from_slice(&[0u8; 8]);
+        for field in [&b"192.0.2.53:5443"[..], &pk[..], &b"2.dnscrypt-cert.example"[..]] {
+            bin.push(field.len() as u8);
+            bin.extend_from_slice(field);
+        }
+        let stamp = format!("sdns://{}", URL_SAFE_NO_PAD.encode(&bin));
+        let hex: String = pk.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":");
+
+        // Act
+        let from_stamp = ServerSpec::parse(&stamp).unwrap();
+        let from_params = ServerSpec::parse(&format!("192.0.2.53:5443?provider=2.dnscrypt-cert.example&pk={}", hex)).unwrap();
+
+        // Assert
+        assert_eq!(from_stamp, from_params);
+        assert!(ServerSpec::parse("192.0.2.53?provider=p&pk=").is_err());
+    }
+
+    #[test]
+    fn test_signed_cert_session_round_trip() {
+        // Arrange
+        let provider = SigningKey::from_bytes(&[7u8; 32]);
+        let resolver_secret = SecretKey::from([9u8; 32]);
+        let now = unix_now();
+        let mut signed = Vec::new();
+        signed.extend_from_slice(resolver_secret.public_key().as_bytes());
+        signed.extend_from_slice(b"clmagic!");
+        signed.extend_from_slice(&5u32.to_be_bytes());
+        signed.extend_from_slice(&(now - 60).to_be_bytes());
+        signed.extend_from_slice(&(now + 3600).to_be_bytes());
+        let mut bin = b"DNSC\x00\x02\x00\x00".to_vec();
+        bin.extend_from_slice(&provider.sign(&signed).to_bytes());
+        bin.extend_from_slice(&signed);
+
+        // Act
+        let cert = parse_cert(&bin, &provider.verifying_key(), now).unwrap();
+        let session = Session::new(cert);
+        let (half, wire) = session.encrypt_query(b"query", MIN_QUERY_LEN).unwrap();
+        // 解析器侧：解密查询并用同一 nonce 前半部分加密响应
+        // Resolver side: decrypt the query and seal a reply with the same nonce half
+        let server = Cipher::new(
+            &Cert { resolver_pk: session.client_pk, ..session.cert.clone() },
+            &resolver_secret,
+        );
+        let mut nonce = [0u8; 24];
+        nonce[..12].copy_from_slice(&wire[40..52]);
+        let query = unpad(server.open(&nonce, &wire[52..]).unwrap()).unwrap();
+        nonce[12..].copy_from_slice(&[1u8; 12]);
+        let mut reply = RESOLVER_MAGIC.to_vec();
+        reply.extend_from_slice(&nonce);
+        reply.extend_from_slice(&server.seal(&nonce, pad(b"answer", 0)).unwrap());
+
+        // Assert
+        assert_eq!(wire.len() - 52, TAG_LEN + MIN_QUERY_LEN);
+        assert_eq!(query, b"query");
+        assert_eq!(session.decrypt_response(&half, &reply).unwrap().as_ref(), b"answer");
+        assert!(parse_cert(&bin, &provider.verifying_key(), now + 7200).is_none());
+    }
+}
@@ -0,0 +1,321 @@
+//! 按注册域名聚合的查询统计与异常标记 / Per-registered-domain query statistics with anomaly flags
+//!
+//! 统计每个注册域名（最后两个标签）的查询量、唯一子域名数、NXDOMAIN 比例和 QTYPE 分布，
+//! 并标记疑似 DNS 隧道或随机子域名攻击的域名，供运维通过管理接口审查。
+//! Tracks query volume, unique subdomains, NXDOMAIN ratio and qtype mix per registered
+//! domain (last two labels), and flags domains that look like DNS tunneling or
+//! random-subdomain attacks for operator review through the admin API.
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::RecordType;
+use parking_lot::Mutex;
+use rustc_hash::{FxHashMap, FxHashSet, FxHasher};
+use serde::Serialize;
+
+/// 每个域名最多跟踪的唯一子域名数量（限制内存）/ Max unique subdomains tracked per domain (bounds memory)
+const MAX_TRACKED_SUBDOMAINS: usize = 4096;
+/// 唯一子域名占比阈值 / Unique-subdomain ratio threshold
+const UNIQUE_RATIO_THRESHOLD: f64 = 0.5;
+/// NXDOMAIN 占比阈值 / NXDOMAIN ratio threshold
+const NXDOMAIN_RATIO_THRESHOLD: f64 = 0.5;
+/// 平均子域名长度阈值（隧道载荷通常很长）/ Average subdomain length threshold (tunnel payloads are long)
+const TUNNEL_SUBDOMAIN_LEN_THRESHOLD: f64 = 24.0;
+/// 隧道常用 QTYPE 占比阈值 / Tunnel-prone qtype ratio threshold
+const TUNNEL_QTYPE_RATIO_THRESHOLD: f64 = 0.3;
+
+/// 单个注册域名的计数器 / Counters for a single registered domain
+#[derive(Default)]
+struct DomainCounters {
+    queries: AtomicU64,
+    /// 对端最近一次同步的查询量 / Query count in the peer's latest sync
+    peer_queries: AtomicU64,
+    nxdomain: AtomicU64,
+    subdomain_len_total: AtomicU64,
+    qtypes: Mutex<FxHashMap<u16, u64>>,
+    subdomains: Mutex<FxHashSet<u64>>,
+}
+
+/// 单个域名的统计报告 / Statistics report for a single domain
+#[derive(Debug, Clone, Serialize)]
+pub struct DomainStatsReport {
+    pub domain: String,
+    pub queries: u64,
+    /// 对端（热备同步）报告的查询量 / Query count reported by the sync peer
+    pub peer_queries: u64,
+    pub unique_subdomains: u64,
+    pub nxdomain_ratio: f64,
+    pub avg_subdomain_len: f64,
+    pub qtypes: BTreeMap<String, u64>,
+    /// 异常标记：random_subdomain_attack / tunneling_suspect / Anomaly flags
+    pub flags: Vec<&'static str>,
+}
+
+/// 按注册域名聚合的查询统计 / Query statistics aggregated by registered domain
+pub struct DomainStats {
+    entries: moka::sync::Cache<Arc<str>, Arc<DomainCounters>>,
+    min_queries: u64,
+    /// 快速路径每 N 次查询记录一次 / Fast-path queries are recorded 1 in N
+    sample_rate: u32,
+}
+
+thread_local! {
+    /// 每个线程独立的快速路径查询计数，采样决策不写共享状态
+    /// Per-thread fast-path query count, so sampling decisions never write shared state
+    static FAST_PATH_QUERIES: Cell<u32> = const { Cell::new(0) };
+}
+
+impl DomainStats {
+    /// capacity: 最多跟踪的域名数；window_secs: 统计窗口（条目写入后过期）
+    /// capacity: max tracked domains; window_secs: statistics window (entries expire after write)
+    pub fn new(capacity: u64, window_secs: u64, min_queries: u64) -> Self {
+        let entries = moka::sync::Cache::builder()
+            .max_capacity(capacity)
+            .time_to_live(Duration::from_secs(window_secs.max(1)))
+            .build();
+        Self { entries, min_queries, sample_rate: 1 }
+    }
+
+    /// 快速路径（缓存命中与静态应答）每 rate 次查询只记录一次，计数按 rate 加权（0 或 1 表示逐次记录）
+    /// Record only 1 in rate fast-path queries (cache hits and static answers), weighted by rate (0 or 1 records every one)
+    pub fn with_sample_rate(mut self, rate: u32) -> Self {
+        self.sample_rate = rate.max(1);
+        self
+    }
+
+    /// 记录一次查询 / Record a single query
+    pub fn record(&self, qname: &str, qtype: u16, rcode: ResponseCode) {
+        self.record_weighted(qname, qtype, rcode, 1);
+    }
+
+    /// 按采样率记录一次快速路径查询；未被采样的查询不触碰共享的计数表
+    /// Record a fast-path query subject to sampling; queries not sampled never touch the shared table
+    #[inline]
+    pub fn record_sampled(&self, qname: &str, qtype: u16, rcode: ResponseCode) {
+        let rate = self.sample_rate;
+        if rate > 1 {
+            let sampled = FAST_PATH_QUERIES.with(|n| {
+                let count = n.get().wrapping_add(1) % rate;
+                n.set(count);
+                count == 0
+            });
+            if !sampled {
+                return;
+            }
+        }
+        self.record_weighted(qname, qtype, rcode, u64::from(rate));
+    }
+
+    fn record_weighted(&self, qname: &str, qtype: u16, rcode: ResponseCode, weight: u64) {
+        let qname = qname.trim_end_matches('.');
+        if qname.is_empty() {
+            return;
+        }
+        let domain = registered_domain(qname);
+        let counters = self
+            .entries
+            .get_with(Arc::from(domain), || Arc::new(DomainCounters::default()));
+
+        counters.queries.fetch_add(weight, Ordering::Relaxed);
+        if rcode == ResponseCode::NXDomain {
+            counters.nxdomain.fetch_add(weight, Ordering::Relaxed);
+        }
+        *counters.qtypes.lock().entry(qtype).or_insert(0) += weight;
+
+        if qname.len() > domain.len() {
+            let subdomain = &qname[..qname.len() - domain.len() - 1];
+            counters
+                .subdomain_len_total
+                .fetch_add(subdomain.len() as u64 * weight, Ordering::Relaxed);
+            let mut hasher = FxHasher::default();
+            subdomain.hash(&mut hasher);
+            let mut set = counters.subdomains.lock();
+            if set.len() < MAX_TRACKED_SUBDOMAINS {
+                set.insert(hasher.finish());
+            }
+        }
+    }
+
+    /// 合并对端同步的查询量（覆盖上次的值）/ Merge a sync peer's query count (replacing the previous value)
+    pub fn merge_peer(&self, domain: &str, queries: u64) {
+        let domain = domain.trim_end_matches('.');
+        if domain.is_empty() {
+            return;
+        }
+        self.entries
+            .get_with(Arc::from(domain), || Arc::new(DomainCounters::default()))
+            .peer_queries
+            .store(queries, Ordering::Relaxed);
+    }
+
+    /// 本地查询量最高的域名，供热备同步 / Domains with the most local queries, for peer sync
+    pub fn hot_domains(&self, limit: usize) -> Vec<(String, u64)> {
+        let mut out: Vec<(String, u64)> = self
+            .entries
+            .iter()
+            .map(|(domain, c)| (domain.to_string(), c.queries.load(Ordering::Relaxed)))
+            .filter(|(_, queries)| *queries > 0)
+            .collect();
+        out.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        out.truncate(limit);
+        out
+    }
+
+    /// 生成统计报告，按查询量（含对端）降序 / Build the report sorted by query count, peer counts included (descending)
+    ///
+    /// 隐私模式下省略查询数低于 min_count（隐私配置的 min_qname_count）的域名。
+    /// In privacy mode, domains queried fewer than min_count (the privacy min_qname_count) times are omitted.
+    pub fn report(&self, flagged_only: bool, limit: usize, min_count: u64) -> Vec<DomainStatsReport> {
+        let mut out: Vec<DomainStatsReport> = self
+            .entries
+            .iter()
+            .map(|(domain, c)| self.build_report(&domain, &c))
+            .filter(|r| !flagged_only || !r.flags.is_empty())
+            .filter(|r| r.queries + r.peer_queries >= min_count)
+            .collect();
+        out.sort_by(|a, b| {
+            (b.queries + b.peer_queries)
+                .cmp(&(a.queries + a.peer_queries))
+                .then_with(|| a.domain.cmp(&b.domain))
+        });
+        out.truncate(limit);
+        out
+    }
+
+    fn build_report(&self, domain: &str, c: &DomainCounters) -> DomainStatsReport {
+        let queries = c.queries.load(Ordering::Relaxed);
+        let nxdomain = c.nxdomain.load(Ordering::Relaxed);
+        let unique_subdomains = c.subdomains.lock().len() as u64;
+        let qtype_counts = c.qtypes.lock().clone();
+
+        let q = queries.max(1) as f64;
+        let nxdomain_ratio = nxdomain as f64 / q;
+        let unique_ratio = unique_subdomains as f64 / q;
+        let avg_subdomain_len = c.subdomain_len_total.load(Ordering::Relaxed) as f64 / q;
+        let tunnel_qtypes: u64 = qtype_counts
+            .iter()
+            .filter(|(t, _)| {
+                matches!(
+                    RecordType::from(**t),
+                    RecordType::TXT | RecordType::NULL | RecordType::ANY
+                )
+            })
+            .map(|(_, n)| *n)
+            .sum();
+        let tunnel_qtype_ratio = tunnel_qtypes as f64 / q;
+
+        let mut flags = Vec::new();
+        if queries >= self.min_queries && unique_ratio >= UNIQUE_RATIO_THRESHOLD {
+            if nxdomain_ratio >= NXDOMAIN_RATIO_THRESHOLD {
+                flags.push("random_subdomain_attack");
+            }
+            if avg_subdomain_len >= TUNNEL_SUBDOMAIN_LEN_THRESHOLD
+                || tunnel_qtype_ratio >= TUNNEL_QTYPE_RATIO_THRESHOLD
+            {
+                flags.push("tunneling_suspect");
+            }
+        }
+
+        DomainStatsReport {
+            domain: domain.to_string(),
+            queries,
+            peer_queries: c.peer_queries.load(Ordering::Relaxed),
+            unique_subdomains,
+            nxdomain_ratio,
+            avg_subdomain_len,
+            qtypes: qtype_counts
+                .into_iter()
+                .map(|(t, n)| (RecordType::from(t).to_string(), n))
+                .collect(),
+            flags,
+        }
+    }
+}
+
+/// 近似注册域名：取最后两个标签（未使用公共后缀列表）
+/// Approximate registered domain: the last two labels (no public suffix list)
+#[inline]
+pub fn registered_domain(qname: &str) -> &str {
+    let mut dots = qname.rmatch_indices('.');
+    dots.next();
+    match dots.next() {
+        Some((idx, _)) => &qname[idx + 1..],
+        None => qname,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registered_domain() {
+        assert_eq!(registered_domain("a.b.example.com"), "example.com");
+        assert_eq!(registered_domain("example.com"), "example.com");
+        assert_eq!(registered_domain("localhost"), "localhost");
+    }
+
+    #[test]
+    fn test_random_subdomain_attack_flagged() {
+        // Arrange
+        let stats = DomainStats::new(100, 60, 10);
+
+        // Act
+        for i in 0..20 {
+            stats.record(&format!("r{}.victim.com", i), 1, ResponseCode::NXDomain);
+        }
+        for _ in 0..20 {
+            stats.record("www.normal.com", 1, ResponseCode::NoError);
+        }
+        stats.entries.run_pending_tasks();
+        let flagged = stats.report(true, 10, 0);
+
+        // Assert
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].domain, "victim.com");
+        assert!(flagged[0].flags.contains(&"random_subdomain_attack"));
+    }
+
+    #[test]
+    fn test_tunneling_suspect_flagged() {
+        // Arrange
+        let stats = DomainStats::new(100, 60, 10);
+
+        // Act
+        for i in 0..20 {
+            stats.record(
+                &format!("{:032x}.t.tunnel.net", i * 7919),
+                u16::from(RecordType::TXT),
+                ResponseCode::NoError,
+            );
+        }
+        let report = stats.report(false, 10, 0);
+
+        // Assert
+        assert_eq!(report[0].domain, "tunnel.net");
+        assert_eq!(report[0].unique_subdomains, 20);
+        assert!(report[0].flags.contains(&"tunneling_suspect"));
+        assert!(!report[0].flags.contains(&"random_subdomain_attack"));
+    }
+
+    #[test]
+    fn test_sampled_fast_path_counts_are_weighted() {
+        // Arrange
+        let stats = DomainStats::new(100, 60, 10).with_sample_rate(10);
+
+        // Act
+        for _ in 0..100 {
+            stats.record_sampled("www.hot.com", 1, ResponseCode::NoError);
+        }
+        stats.record("www.cold.com", 1, ResponseCode::NoError);
+
+        // Assert
+        assert_eq!(stats.hot_domains(10), [("hot.com".to_string(), 100), ("cold.com".to_string(), 1)]);
+    }
+}
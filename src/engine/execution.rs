@@ -1,10 +1,10 @@
 use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
 
@@ -25,17 +25,21 @@ use tracing::warn;
 
 use crate::cache::CacheEntry;
 use crate::matcher::advanced_rule::{compile_pipelines, fast_static_match};
-use crate::config::Transport;
+use crate::config::{GlobalSettings, ListenerTransport, MalformedQueryReply, MultiQuestionPolicy, OverloadReply, QclassPolicy, Transport, UdpBufferSettings, ZeroTtlPolicy};
 use crate::matcher::RuntimePipelineConfig;
 use crate::proto_utils::parse_quick;
 
-use super::response::build_fast_static_response;
-use super::types::{EngineInner, FastPathResponse};
+use super::response::{CacheTtl, build_fast_static_response};
+use super::types::{EngineInner, FastPathResponse, MalformedQuery};
+use super::udp_batch::UdpBatch;
 use super::utils::{
     is_refreshing,
     engine_helpers,
 };
 use crate::engine::rules::{ResponseContext, calculate_rule_hash, Decision};
+use crate::engine::mdns::MdnsBridge;
+use crate::engine::{ddr, private_ptr, ptr_rewrite};
+use crate::engine::runtime_rules::RUNTIME_PIPELINE_ID;
 
 /// Pre-parsed data from handle_packet_fast to avoid re-parsing
 /// 来自 handle_packet_fast 的预解析数据，避免重新解析
@@ -46,6 +50,7 @@ pub struct PreParsedData {
     qclass: DNSClass,
     tx_id: u16,
     edns_present: bool,
+    dnssec_flags: u8,
     pipeline_id: Arc<str>,
 }
 
@@ -82,12 +87,20 @@ impl Engine {
     /// Reload configuration and update compiled pipelines / 重新加载配置并更新编译后的管线
     pub fn reload(&self, new_cfg: RuntimePipelineConfig) {
         let compiled = compile_pipelines(&new_cfg);
+        self.privacy.apply(&new_cfg.settings.privacy);
+        super::log_limit::set_log_rate_limit(&new_cfg.settings.log_rate_limit);
+        self.apply_settings(&self.state.load().pipeline.settings, &new_cfg.settings);
         self.state.store(Arc::new(EngineInner {
             pipeline: new_cfg,
             compiled_pipelines: compiled,
         }));
         // Clear rule cache to ensure new rules take effect immediately / 清除规则缓存以确保新规则立即生效
         self.rule_cache.invalidate_all();
+        // 规则可能已移除 Delay 动作 / Rules may no longer contain the Delay action
+        self.tarpit.invalidate_all();
+        self.tarpit_active.store(false, Ordering::Relaxed);
+        // 移除标记为 until_reload 的临时规则 / Drop temporary rules marked until_reload
+        self.runtime_rules.on_reload();
         // Reset background refresh rule to allow re-initialization with new config
         // 重置后台刷新规则以允许使用新配置重新初始化
         // Note: OnceLock cannot be reset, so we rely on the fact that the rule is
@@ -136,24 +149,7 @@ impl Engine {
     /// Otherwise auto-calculate as upstream_timeout_ms * 2.5
     #[inline]
     pub fn get_request_timeout_ms(&self) -> u64 {
-        let state = self.state.load();
-        let settings = &state.pipeline.settings;
-
-        // 如果用户显式配置了 request_timeout，使用配置值
-        // If user explicitly configured request_timeout, use that value
-        if let Some(timeout) = settings.request_timeout_ms {
-            timeout
-        } else {
-            // 自动计算：hedge(1/3) + full(1x) + tcp_fallback(1x) + 余量
-            // - hedge 通常提前返回，不计入最大时间
-            // - 实际路径：hedge 尝试 → full 尝试 → tcp fallback
-            // - 最大时间：upstream * 2.5（保守估计）
-            // Auto-calculate: hedge(1/3) + full(1x) + tcp_fallback(1x) + margin
-            // - hedge usually returns early, not counted in max time
-            // - Actual path: hedge attempt → full attempt → tcp fallback
-            // - Max time: upstream * 2.5 (conservative estimate)
-            settings.upstream_timeout_ms * 5 / 2  // * 2.5
-        }
+        self.state.load().pipeline.settings.effective_request_timeout_ms()
     }
 
     /// Get parse_quick failure statistics
@@ -203,7 +199,7 @@ impl Engine {
 
 
     #[inline]
-    pub fn calculate_cache_hash_for_dedupe(pipeline_id: &str, qname: &[u8], qtype: hickory_proto::rr::RecordType, qclass: hickory_proto::rr::DNSClass) -> u64 {
+    pub fn calculate_cache_hash_for_dedupe(pipeline_id: &str, qname: &[u8], qtype: hickory_proto::rr::RecordType, qclass: hickory_proto::rr::DNSClass, dnssec_flags: u8) -> u64 {
         let mut h = FxHasher::default();
         pipeline_id.hash(&mut h);
         // Hash qname case-insensitively without allocation / 不分配内存地进行不区分大小写的 qname 哈希
@@ -215,9 +211,95 @@ impl Engine {
         u16::from(qtype).hash(&mut h);
         // DNSClass implements Copy+Debug, hash by its u16 representation / DNSClass 实现了 Copy+Debug，使用其 u16 表示进行哈希
         u16::from(qclass).hash(&mut h);
+        // DO/CD 不同的请求分开缓存，剥离 DNSSEC 的应答不会发给验证方，反之亦然
+        // Requests with different DO/CD bits are cached apart, so a DNSSEC-stripped answer is never served to a validator and vice versa
+        h.write_u8(dnssec_flags);
         h.finish()
     }
 
+    /// 按 non_in_qclass 与 multi_question 配置应直接拒绝的查询所用的 rcode
+    /// The rcode for a query rejected outright by the non_in_qclass or multi_question settings
+    #[inline]
+    fn policy_rejection(settings: &GlobalSettings, qclass: DNSClass, packet: &[u8]) -> Option<ResponseCode> {
+        if settings.multi_question == MultiQuestionPolicy::Formerr
+            && packet.len() >= 6
+            && u16::from_be_bytes([packet[4], packet[5]]) > 1
+        {
+            return Some(ResponseCode::FormErr);
+        }
+        if qclass != DNSClass::IN && settings.non_in_qclass == QclassPolicy::Refuse {
+            return Some(ResponseCode::Refused);
+        }
+        None
+    }
+
+    /// pipeline 启用 AAAA 抑制且探测不到 IPv6 连通时，AAAA 查询直接以 NODATA 应答
+    /// AAAA queries get NODATA right away when the pipeline suppresses them and IPv6 is unreachable
+    #[inline]
+    fn suppresses_aaaa(&self, cfg: &RuntimePipelineConfig, pipeline_id: &str, qtype: hickory_proto::rr::RecordType) -> bool {
+        qtype == hickory_proto::rr::RecordType::AAAA
+            && cfg.suppress_aaaa_for(pipeline_id)
+            && !self.ipv6_probe.reachable(&cfg.settings.ipv6_probe_addr)
+    }
+
+    /// 按监听器配置为无法解析的请求生成 FORMERR/REFUSED 回复，配置为丢弃时返回 None
+    /// The FORMERR/REFUSED reply for an unparseable request per the listener's setting, None when it is set to drop
+    pub fn malformed_reply(&self, packet: &[u8], tcp: bool) -> Option<Bytes> {
+        let settings = &self.state.load().pipeline.settings.malformed_query;
+        let rcode = match if tcp { settings.tcp } else { settings.udp } {
+            MalformedQueryReply::Drop => return None,
+            MalformedQueryReply::Formerr => ResponseCode::FormErr,
+            MalformedQueryReply::Refused => ResponseCode::Refused,
+        };
+        crate::proto_utils::header_only_reply(packet, u16::from(rcode) as u8).map(Bytes::from)
+    }
+
+    /// 超出并发上限的请求的 SERVFAIL/REFUSED 回复，配置为丢弃时返回 None
+    /// The SERVFAIL/REFUSED reply for a request over the concurrency limit, None when set to drop
+    pub fn overload_reply(&self, packet: &[u8]) -> Option<Bytes> {
+        let rcode = match self.state.load().pipeline.settings.overload_reply {
+            OverloadReply::Drop => return None,
+            OverloadReply::Servfail => ResponseCode::ServFail,
+            OverloadReply::Refused => ResponseCode::Refused,
+        };
+        crate::proto_utils::header_only_reply(packet, u16::from(rcode) as u8).map(Bytes::from)
+    }
+
+    /// UDP socket 与报文缓冲区大小 / UDP socket and packet buffer sizes
+    pub fn udp_buffers(&self) -> UdpBufferSettings {
+        self.state.load().pipeline.settings.udp_buffers
+    }
+
+    /// UDP worker 的批量接收状态 / Batched receive state for a UDP worker
+    pub fn udp_batch(&self, worker: &str) -> UdpBatch {
+        UdpBatch::new(&self.udp_worker_stats, Arc::clone(&self.tunables), worker)
+    }
+
+    /// 共享全部状态、按给定监听器传输协议选择 pipeline 的句柄
+    /// A handle sharing all state that selects pipelines for the given listener transport
+    pub fn for_transport(&self, transport: ListenerTransport) -> Engine {
+        Engine { listener_transport: transport, ..self.clone() }
+    }
+
+    /// 插入 DNS 缓存并计数 / Insert into the DNS cache and count the insertion
+    #[inline]
+    pub(crate) fn cache_insert(&self, cache_hash: u64, entry: Arc<CacheEntry>) {
+        self.cache_metrics.record_insert();
+        self.cache.insert(cache_hash, entry);
+    }
+
+    /// 执行 Delay 动作并记住缓存键，使之后的缓存命中同样被延迟
+    /// Carry out a Delay action and remember the cache key so later cache hits are delayed too
+    pub(crate) async fn tarpit(&self, cache_hash: u64, ms: u64, already_waited: bool) {
+        self.tarpit.insert(cache_hash, ms);
+        self.tarpit_active.store(true, Ordering::Relaxed);
+        if !already_waited {
+            // 等待期间不占用并发许可 / Do not hold a concurrency permit while waiting
+            crate::engine::concurrency::release_request_permit();
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+        }
+    }
+
     /// Helper: create and insert DNS cache entry / 辅助函数：创建并插入 DNS 缓存条目
     /// Eliminate duplicate CacheEntry construction code / 消除重复的 CacheEntry 构造代码
     #[inline]
@@ -231,22 +313,162 @@ impl Engine {
         qname: &str,
         pipeline_id: Arc<str>,
         qtype: hickory_proto::rr::RecordType,
+        qclass: DNSClass,
         original_ttl: u32,
         refresh_ttl: u32,
     ) {
+        // TC=1 的应答不完整，不缓存；完整应答由 TCP 重试取得 / TC=1 answers are incomplete and not cached; the full answer comes from the TCP retry
+        if bytes.len() > 2 && bytes[2] & 0x02 != 0 {
+            return;
+        }
+        let (original_ttl, inserted_at) = self.cache_entry_timing(&pipeline_id, original_ttl);
         let entry = CacheEntry {
             bytes,
+            compressed: false,
             rcode,
             source,
             upstream,
             qname: Arc::from(qname),  // 一次 Arc::from，避免多次
             pipeline_id,
             qtype: u16::from(qtype),
-            inserted_at: Instant::now(),
+            qclass: u16::from(qclass),
+            inserted_at,
             original_ttl,
             refresh_ttl,
+            aged: Default::default(),
+        };
+        self.cache_insert(cache_hash, Arc::new(entry.compress_above(self.tunables.cache_compress_threshold())));
+    }
+
+    /// 计算响应的缓存 TTL：否定响应按 negative_cache 配置，其余沿用应答 TTL
+    /// Cache TTL of a response: negative responses follow negative_cache, others keep the answer TTL
+    pub(crate) fn cache_ttl(&self, raw: &[u8], rcode: ResponseCode, answer_ttl: u64) -> CacheTtl {
+        let state = self.state.load();
+        let policy = &state.pipeline.settings.negative_cache;
+        let bounds = match rcode {
+            ResponseCode::NXDomain => policy.nxdomain,
+            ResponseCode::NoError if raw.len() >= 8 && raw[6] == 0 && raw[7] == 0 => policy.nodata,
+            ResponseCode::ServFail => return CacheTtl::Negative(policy.servfail_ttl as u64),
+            _ => return CacheTtl::Answer(answer_ttl),
+        };
+        // 未配置的否定响应不缓存 / Unconfigured negative responses are not cached
+        let ttl = bounds.map_or(0, |b| {
+            let ttl = crate::proto_utils::negative_ttl(raw).unwrap_or(b.min_ttl);
+            ttl.clamp(b.min_ttl, b.max_ttl.max(b.min_ttl))
+        });
+        CacheTtl::Negative(ttl as u64)
+    }
+
+    /// 响应的缓存时长：否定响应按 negative_cache 原样使用；应答 TTL 为 0 时按 pipeline 的 zero_ttl 策略，其余不低于 min_ttl
+    /// How long a response is cached: negative_cache TTLs are used as is; answer TTLs of 0 follow the
+    /// pipeline's zero_ttl policy, others last at least min_ttl
+    pub(crate) fn cache_lifetime(&self, pipeline_id: &str, ttl: CacheTtl, min_ttl: Duration) -> Duration {
+        let ttl_secs = match ttl {
+            CacheTtl::Negative(ttl_secs) => return Duration::from_secs(ttl_secs),
+            CacheTtl::Answer(ttl_secs) => ttl_secs,
+        };
+        if ttl_secs > 0 {
+            return Duration::from_secs(ttl_secs.max(min_ttl.as_secs()));
+        }
+        match self.state.load().pipeline.zero_ttl_for(pipeline_id) {
+            ZeroTtlPolicy::MinTtl => min_ttl,
+            ZeroTtlPolicy::NoCache => Duration::ZERO,
+            ZeroTtlPolicy::MicroCache { ms } => Duration::from_millis(ms as u64),
+        }
+    }
+
+    /// 缓存条目的过期秒数与插入时间。TTL 为 0 的条目按 zero_ttl 策略取时长；缓存按整秒判断过期，
+    /// 不足一秒的部分通过提前插入时间补足
+    /// Expiry seconds and insertion time of a cache entry. TTL 0 entries take their lifetime from
+    /// the zero_ttl policy; expiry is checked in whole seconds, so the insertion time is moved back
+    /// by the missing fraction of a second
+    pub(crate) fn cache_entry_timing(&self, pipeline_id: &str, original_ttl: u32) -> (u32, Instant) {
+        if original_ttl > 0 {
+            return (original_ttl, Instant::now());
+        }
+        let lifetime = self.cache_lifetime(pipeline_id, CacheTtl::Answer(0), self.state.load().pipeline.min_ttl());
+        let secs = lifetime.as_millis().div_ceil(1000) as u64;
+        let now = Instant::now();
+        (secs as u32, now.checked_sub(Duration::from_secs(secs) - lifetime).unwrap_or(now))
+    }
+
+    /// 上游响应的改写：按 EDNS 选项策略去掉不返回给客户端的选项、改写 PTR 名称，并按 max_ttl 截断 TTL
+    /// Rewrites of an upstream response: drop the EDNS options not returned to clients, rewrite
+    /// PTR names and cap TTLs at max_ttl
+    #[inline]
+    pub(crate) fn transform_upstream_response(&self, pipeline_id: &str, raw: Bytes) -> Bytes {
+        let state = self.state.load();
+        let raw = match crate::proto_utils::filter_edns_options(&raw, &state.pipeline.edns_options_for(pipeline_id).to_client) {
+            Some(filtered) => Bytes::from(filtered),
+            None => raw,
+        };
+        let raw = match ptr_rewrite::rewrite(&state.pipeline.settings.ptr_rewrite, &raw) {
+            Some(rewritten) => Bytes::from(rewritten),
+            None => raw,
         };
-        self.cache.insert(cache_hash, Arc::new(entry));
+        let raw = match state.pipeline.settings.minimal_answers.then(|| super::minimal_answers::minimize(&raw)).flatten() {
+            Some(minimal) => Bytes::from(minimal),
+            None => raw,
+        };
+        let max_ttl = state.pipeline.max_ttl_for(pipeline_id);
+        if max_ttl == 0 {
+            return raw;
+        }
+        match crate::proto_utils::cap_all_ttls(&raw, max_ttl) {
+            Some(capped) => Bytes::from(capped),
+            None => raw,
+        }
+    }
+
+    /// 按 EDNS 选项策略去掉不转发给上游的客户端选项 / Drop the client EDNS options not forwarded upstream
+    #[inline]
+    pub(crate) fn upstream_query<'a>(&self, pipeline_id: &str, packet: &'a [u8]) -> std::borrow::Cow<'a, [u8]> {
+        let state = self.state.load();
+        match crate::proto_utils::filter_edns_options(packet, &state.pipeline.edns_options_for(pipeline_id).to_upstream) {
+            Some(filtered) => std::borrow::Cow::Owned(filtered),
+            None => std::borrow::Cow::Borrowed(packet),
+        }
+    }
+
+    /// 记录按注册域名的查询统计与按客户端的 NXDOMAIN 计数，并发布实时查询事件
+    /// Record per-registered-domain query statistics and per-client NXDOMAIN counts, and publish the live query event
+    #[inline]
+    fn record_domain_stats(&self, client: IpAddr, qname: &str, qtype: u16, rcode: ResponseCode, fast_path: bool) {
+        self.record_nxdomain(client, rcode);
+        if let Some(stats) = &self.domain_stats {
+            if fast_path {
+                stats.record_sampled(qname, qtype, rcode);
+            } else {
+                stats.record(qname, qtype, rcode);
+            }
+        }
+        self.live_queries.publish(client, qname, qtype, rcode, fast_path);
+    }
+
+    /// 有规则使用 nxdomain_burst 时记录发往客户端的 NXDOMAIN
+    /// Record an NXDOMAIN sent to the client when a rule uses nxdomain_burst
+    #[inline]
+    fn record_nxdomain(&self, client: IpAddr, rcode: ResponseCode) {
+        if rcode == ResponseCode::NXDomain && self.state.load().pipeline.tracks_nxdomain {
+            crate::matcher::nxdomain_burst::record(client);
+        }
+    }
+
+    /// 从响应报文中提取问题与 rcode 并记录统计 / Extract question and rcode from a response and record statistics
+    #[inline]
+    fn record_domain_stats_from_response(&self, client: IpAddr, resp: &[u8]) {
+        if resp.len() < 12 {
+            return;
+        }
+        let rcode = ResponseCode::from_low(resp[3] & 0x0F);
+        if self.domain_stats.is_none() && !self.live_queries.active() {
+            self.record_nxdomain(client, rcode);
+            return;
+        }
+        let mut qname_buf = [0u8; 256];
+        if let Some(q) = parse_quick(resp, &mut qname_buf) {
+            self.record_domain_stats(client, q.qname_str_unchecked(), q.qtype, rcode, false);
+        }
     }
 
     #[allow(dead_code)]
@@ -287,6 +509,13 @@ impl Engine {
                 return Ok(None);
             }
         };
+        // 命中运行时临时规则或 mDNS 桥接的查询走完整处理路径
+        // Queries hitting runtime rules or the mDNS bridge take the full path
+        if self.runtime_rules.matches(q.qname_str_unchecked())
+            || (self.mdns.is_some() && MdnsBridge::is_local_name(q.qname_str_unchecked()))
+        {
+            return Ok(None);
+        }
         // Count incoming quick-parsed requests / 计数进入的快速解析请求
         self.incr_total_requests();
         
@@ -300,6 +529,28 @@ impl Engine {
         // Use unchecked conversion for performance (qname_bytes is validated UTF-8)
         // 使用未检查转换以提高性能（qname_bytes 是已验证的 UTF-8）
         let qname_str = q.qname_str_unchecked();
+
+        // 按配置拒绝非 IN 类查询与多问题查询 / Refuse non-IN class and multi-question queries when configured
+        if let Some(rcode) = Self::policy_rejection(&cfg.settings, qclass, packet) {
+            let resp = build_fast_static_response(q.tx_id, qname_str, q.qtype, q.qclass, rcode, &Vec::new())?;
+            self.incr_fastpath_hits();
+            self.record_domain_stats(peer.ip(), qname_str, q.qtype, rcode, true);
+            return Ok(Some(FastPathResponse::Direct(resp)));
+        }
+
+        // 本地记录、私有地址反向查询与 DDR 查询在本地应答 / Local records, private-space reverse lookups and DDR queries are answered locally
+        if let Some((rcode, answers)) = cfg
+            .local_records
+            .answer(qname_str, q.qtype, &self.local_record_health)
+            .or_else(|| private_ptr::answer(&cfg.settings.private_ptr, qname_str, q.qtype))
+            .or_else(|| ddr::answer(&cfg.settings.ddr, qname_str, q.qtype))
+        {
+            let resp = build_fast_static_response(q.tx_id, qname_str, q.qtype, q.qclass, rcode, &answers)?;
+            self.incr_fastpath_hits();
+            self.record_domain_stats(peer.ip(), qname_str, q.qtype, rcode, true);
+            return Ok(Some(FastPathResponse::Direct(resp)));
+        }
+
         let (pipeline_opt, pipeline_id) = select_pipeline(
             cfg,
             qname_str,
@@ -308,22 +559,35 @@ impl Engine {
             q.edns_present,
             qtype,
             &self.listener_label,
+            self.listener_transport,
             Some(&self.geosite_manager),
             Some(&self.geoip_manager),
         );
         
+        if self.suppresses_aaaa(cfg, &pipeline_id, qtype) {
+            let resp = build_fast_static_response(q.tx_id, qname_str, q.qtype, q.qclass, ResponseCode::NoError, &Vec::new())?;
+            self.incr_fastpath_hits();
+            self.record_domain_stats(peer.ip(), qname_str, q.qtype, ResponseCode::NoError, true);
+            return Ok(Some(FastPathResponse::Direct(resp)));
+        }
+
         // 1. Check Response Cache (L2) / 1. 检查响应缓存（L2）
-        let cache_hash = Self::calculate_cache_hash_for_dedupe(&pipeline_id, q.qname_bytes, qtype, qclass);
+        let cache_hash = Self::calculate_cache_hash_for_dedupe(&pipeline_id, q.qname_bytes, qtype, qclass, q.dnssec_flags);
+
+        // 被 Delay 的缓存键交给慢路径等待 / Delayed cache keys go to the slow path to wait
+        if self.tarpit_active.load(Ordering::Relaxed) && self.tarpit.contains_key(&cache_hash) {
+            return Ok(None);
+        }
 
         if let Some(hit) = self.cache.get(&cache_hash) {
             // Verify collision / 验证冲突
-            if hit.qtype == u16::from(qtype) && q.qname_matches(hit.qname.as_ref()) && hit.pipeline_id == pipeline_id {
+            if hit.qtype == u16::from(qtype) && hit.qclass == q.qclass && q.qname_matches(hit.qname.as_ref()) && hit.pipeline_id == pipeline_id {
                 // Check if expired / 检查是否已过期
                 let elapsed_secs = hit.inserted_at.elapsed().as_secs() as u32;
                 if elapsed_secs >= hit.original_ttl {
                     // RFC 8767: When serve_stale is enabled, keep stale entries for fallback
                     // RFC 8767: 当 serve_stale 启用时，保留过期条目以便 fallback
-                    if !self.serve_stale {
+                    if !self.tunables.serve_stale() {
                         self.cache.invalidate(&cache_hash);
                     }
                 } else {
@@ -331,14 +595,19 @@ impl Engine {
                     // 缓存后台刷新：当TTL < 阈值百分比时，触发异步刷新
                     // Only refresh cache entries that came from an upstream server
                     // 只有来自 upstream 的缓存条目才进行预取刷新
-                    if self.cache_background_refresh
+                    let prefetch_policy = cfg.prefetch_qtypes.get(&qtype);
+                    let refresh_min_ttl = prefetch_policy
+                        .and_then(|p| p.min_ttl)
+                        .unwrap_or_else(|| self.tunables.cache_refresh_min_ttl());
+                    if self.tunables.cache_background_refresh()
                         && hit.upstream.is_some()
-                        && hit.refresh_ttl >= self.cache_refresh_min_ttl
+                        && hit.refresh_ttl >= refresh_min_ttl
+                        && super::prefetch::allows(prefetch_policy, &self.ipv6_probe, &cfg.settings.ipv6_probe_addr)
                     {
                         // Calculate remaining TTL and refresh threshold
                         // 计算剩余 TTL 和刷新阈值
                         let remaining_ttl = hit.refresh_ttl.saturating_sub(elapsed_secs);
-                        let threshold = (hit.refresh_ttl as u64 * self.cache_refresh_threshold_percent as u64) / 100;
+                        let threshold = (hit.refresh_ttl as u64 * self.tunables.cache_refresh_threshold_percent() as u64) / 100;
 
                         // OPTIMIZATION: Zero-lock check using bitmap / 优化：使用位图进行零锁检查
                         let is_refreshing = is_refreshing(&self.refreshing_bitmap, cache_hash);
@@ -348,9 +617,9 @@ impl Engine {
                             refresh_ttl = hit.refresh_ttl,
                             elapsed_secs = elapsed_secs,
                             remaining_ttl = remaining_ttl,
-                            threshold_percent = self.cache_refresh_threshold_percent,
+                            threshold_percent = self.tunables.cache_refresh_threshold_percent(),
                             threshold_value = threshold,
-                            min_ttl = self.cache_refresh_min_ttl,
+                            min_ttl = refresh_min_ttl,
                             is_refreshing = is_refreshing,
                             should_trigger = !is_refreshing && remaining_ttl as u64 <= threshold,
                             upstream = ?hit.upstream,
@@ -377,8 +646,25 @@ impl Engine {
                                 qname_str,
                                 qtype,
                                 qclass,
+                                q.dnssec_flags,
                                 hit.upstream.as_deref(),
                             );
+                            // 连带刷新同名的其它类型 / Refresh the other types of the same name alongside
+                            for &with in prefetch_policy.map_or(&[][..], |p| &p.with[..]) {
+                                if !super::prefetch::allows(cfg.prefetch_qtypes.get(&with), &self.ipv6_probe, &cfg.settings.ipv6_probe_addr) {
+                                    continue;
+                                }
+                                let with_hash = Self::calculate_cache_hash_for_dedupe(&pipeline_id, q.qname_bytes, with, qclass, q.dnssec_flags);
+                                self.spawn_background_refresh(
+                                    with_hash,
+                                    &pipeline_id,
+                                    qname_str,
+                                    with,
+                                    qclass,
+                                    q.dnssec_flags,
+                                    None,
+                                );
+                            }
                         }
                     }
 
@@ -387,17 +673,22 @@ impl Engine {
                     // Next query will automatically use refreshed new cache (if completed)
                     // 下次查询时会自动使用刷新后的新缓存（如果已完成）
                     self.incr_fastpath_hits();
+                    self.record_domain_stats(peer.ip(), q.qname_str_unchecked(), q.qtype, hit.rcode, true);
+                    self.upstream_stats.record_cache_hit(hit.upstream.as_deref());
                     return Ok(Some(FastPathResponse::CacheHit {
-                        cached: hit.bytes.clone(),
+                        cached: hit.aged_payload_for_client(q.edns_present, q.dnssec_flags),
                         tx_id: q.tx_id,
-                        inserted_at: hit.inserted_at,
                     }));
                 }
             }
         }
 
         // 2. Compiled rule fast-path for static decisions / 2. 编译规则的静态决策快速路径
-        if let Some(compiled) = self.compiled_for(&state, &pipeline_id) {
+        // 放行名单中的域名交给 apply_rules 处理 / Allowlisted names are left to apply_rules
+        let compiled_opt = self
+            .compiled_for(&state, &pipeline_id)
+            .filter(|_| !state.pipeline.allowlist.contains(q.qname_str_unchecked()));
+        if let Some(compiled) = compiled_opt {
             let qclass = DNSClass::from(q.qclass);
             let qname_str = q.qname_str_unchecked();  // Zero-allocation / 零分配
             if let Some(decision) = fast_static_match(
@@ -418,6 +709,7 @@ impl Engine {
                         &answers,
                     )?;
                     self.incr_fastpath_hits();
+                    self.record_domain_stats(peer.ip(), qname_str, q.qtype, rcode, true);
                     return Ok(Some(FastPathResponse::Direct(resp)));
                 }
             }
@@ -428,7 +720,7 @@ impl Engine {
         if let Some(p) = pipeline_opt {
             // Optimization: only include IP in hash when rule uses client_ip matcher or config requires it
             // 优化：仅当规则使用client_ip匹配器或配置要求时才包含IP在哈希中
-            let include_ip_in_hash = p.uses_client_ip || self.cache_background_refresh;
+            let include_ip_in_hash = p.uses_client_ip || self.tunables.cache_background_refresh();
             let rule_hash = calculate_rule_hash(
                 &pipeline_id,
                 qname_str,
@@ -462,6 +754,7 @@ impl Engine {
                             answers,
                         )?;
                         self.incr_fastpath_hits();
+                        self.record_domain_stats(peer.ip(), qname_str, q.qtype, *rcode, true);
                         return Ok(Some(FastPathResponse::Direct(resp)));
                     }
                 }
@@ -477,6 +770,7 @@ impl Engine {
             qclass: q.qclass,
             tx_id: q.tx_id,
             edns_present: q.edns_present,
+            dnssec_flags: q.dnssec_flags,
             pipeline_id,
         }))
     }
@@ -484,7 +778,9 @@ impl Engine {
 
 
     pub async fn handle_packet(&self, packet: &[u8], peer: SocketAddr) -> anyhow::Result<Bytes> {
-        self.handle_packet_internal(packet, peer, false, None).await
+        let resp = self.handle_packet_internal(packet, peer, false, None).await?;
+        self.record_domain_stats_from_response(peer.ip(), &resp);
+        Ok(resp)
     }
 
     /// Public wrapper for handle_packet_internal with pre-parsed data
@@ -502,6 +798,7 @@ impl Engine {
         qclass: u16,
         tx_id: u16,
         edns_present: bool,
+        dnssec_flags: u8,
         pipeline_id: Arc<str>,
     ) -> anyhow::Result<Bytes> {
         let pre_parsed = PreParsedData {
@@ -510,9 +807,14 @@ impl Engine {
             qclass: DNSClass::from(qclass),
             tx_id,
             edns_present,
+            dnssec_flags,
             pipeline_id,
         };
-        self.handle_packet_internal(packet, peer, skip_cache, Some(pre_parsed)).await
+        let resp = self.handle_packet_internal(packet, peer, skip_cache, Some(pre_parsed)).await?;
+        if !skip_cache {
+            self.record_domain_stats_from_response(peer.ip(), &resp);
+        }
+        Ok(resp)
     }
 
     /// Internal handle_packet implementation with skip_cache option
@@ -551,16 +853,16 @@ impl Engine {
         let cfg = &state.pipeline;
         let min_ttl = cfg.min_ttl();
         let upstream_timeout = cfg.upstream_timeout();
-        let response_jump_limit = cfg.settings.response_jump_limit as usize;
 
         // Use pre-parsed data if available, otherwise parse / 如果有预解析数据则使用，否则解析
-        let (qname_cow, qtype, qclass, tx_id, edns_present, pipeline_id) = if let Some(pre) = pre_parsed {
+        let (qname_cow, qtype, qclass, tx_id, edns_present, dnssec_flags, pipeline_id) = if let Some(pre) = pre_parsed {
             (
                 std::borrow::Cow::Owned(pre.qname),
                 pre.qtype,
                 pre.qclass,
                 pre.tx_id,
                 pre.edns_present,
+                pre.dnssec_flags,
                 pre.pipeline_id,
             )
         } else {
@@ -570,7 +872,7 @@ impl Engine {
             let mut qname_buf = [0u8; 256];
             let q = parse_quick(packet, &mut qname_buf);
 
-            let (qname_cow, qtype, qclass, tx_id, edns_present) = if let Some(q) = q {
+            let (qname_cow, qtype, qclass, tx_id, edns_present, dnssec_flags) = if let Some(q) = q {
                 // Use unchecked conversion to avoid double allocation / 使用未检查转换避免双重分配
                 // SAFETY: qname_bytes is validated ASCII from parse_quick()
                 // ASCII is always valid UTF-8, so this is safe
@@ -579,17 +881,22 @@ impl Engine {
                 let qname_str = unsafe {
                     std::str::from_utf8_unchecked(q.qname_bytes)
                 };
-                (std::borrow::Cow::Owned(qname_str.to_string()), hickory_proto::rr::RecordType::from(q.qtype), DNSClass::from(q.qclass), q.tx_id, q.edns_present)
+                (std::borrow::Cow::Owned(qname_str.to_string()), hickory_proto::rr::RecordType::from(q.qtype), DNSClass::from(q.qclass), q.tx_id, q.edns_present, q.dnssec_flags)
             } else {
                 // Fallback to full parse if quick parse fails (unlikely for standard queries) / 如果快速解析失败则回退到完整解析（对于标准查询不太可能）
-                let req = Message::from_bytes(packet).context("parse request")?;
-                let question = req.queries().first().context("empty question")?;
+                let req = Message::from_bytes(packet)
+                    .map_err(|e| anyhow::Error::new(MalformedQuery::new(anyhow::Error::new(e).context("parse request"))))?;
+                let question = req
+                    .queries()
+                    .first()
+                    .ok_or_else(|| anyhow::Error::new(MalformedQuery::new(anyhow::anyhow!("empty question"))))?;
                 (
                     std::borrow::Cow::Owned(question.name().to_lowercase().to_string()),
                     question.query_type(),
                     question.query_class(),
                     req.id(),
                     req.extensions().is_some(),
+                    crate::proto_utils::dnssec_flags_of(&req),
                 )
             };
 
@@ -606,15 +913,53 @@ impl Engine {
                     edns_present,
                     qtype,
                     &self.listener_label,
+                    self.listener_transport,
                     Some(&self.geosite_manager),
                     Some(&self.geoip_manager),
                 );
                 pipeline_id
             }; // geosite_mgr 在这里释放 / geosite_mgr released here
 
-            (qname_cow, qtype, qclass, tx_id, edns_present, pipeline_id)
+            (qname_cow, qtype, qclass, tx_id, edns_present, dnssec_flags, pipeline_id)
+        };
+
+        // .local 查询交给 mDNS 桥接，不转发到上游 / .local queries go to the mDNS bridge, never upstream
+        if let Some(mdns) = &self.mdns
+            && MdnsBridge::is_local_name(&qname_cow)
+        {
+            return mdns.query(packet).await;
+        }
+
+        // 运行时临时规则优先于配置规则，并使用独立的缓存命名空间
+        // Runtime rules take precedence over configured rules and use a separate cache namespace
+        let runtime_decision = self
+            .runtime_rules
+            .decision_for(&qname_cow, &cfg.settings.default_upstream);
+        let pipeline_id = if runtime_decision.is_some() {
+            Arc::from(RUNTIME_PIPELINE_ID)
+        } else {
+            pipeline_id
         };
 
+        if let Some(rcode) = Self::policy_rejection(&cfg.settings, qclass, packet) {
+            let resp = build_fast_static_response(tx_id, &qname_cow, u16::from(qtype), u16::from(qclass), rcode, &Vec::new())?;
+            return Ok(resp);
+        }
+        if runtime_decision.is_none()
+            && let Some((rcode, answers)) = cfg
+                .local_records
+                .answer(&qname_cow, u16::from(qtype), &self.local_record_health)
+                .or_else(|| private_ptr::answer(&cfg.settings.private_ptr, &qname_cow, u16::from(qtype)))
+                    .or_else(|| ddr::answer(&cfg.settings.ddr, &qname_cow, u16::from(qtype)))
+        {
+            let resp = build_fast_static_response(tx_id, &qname_cow, u16::from(qtype), u16::from(qclass), rcode, &answers)?;
+            return Ok(resp);
+        }
+        if runtime_decision.is_none() && self.suppresses_aaaa(cfg, &pipeline_id, qtype) {
+            let resp = build_fast_static_response(tx_id, &qname_cow, u16::from(qtype), u16::from(qclass), ResponseCode::NoError, &Vec::new())?;
+            return Ok(resp);
+        }
+
         let qname_ref = &qname_cow;
         let start = std::time::Instant::now();
 
@@ -623,8 +968,18 @@ impl Engine {
 
         // Convert qname_ref to bytes for hash calculation / 将 qname_ref 转换为 bytes 进行哈希计算
         let qname_bytes = qname_ref.as_bytes();
-        let dedupe_hash = Self::calculate_cache_hash_for_dedupe(&pipeline_id, qname_bytes, qtype, qclass);
+        let dedupe_hash = Self::calculate_cache_hash_for_dedupe(&pipeline_id, qname_bytes, qtype, qclass, dnssec_flags);
         
+        // Delay 动作命中过的键，缓存命中前同样等待 / Keys hit by a Delay action wait before cache hits too
+        let mut tarpit_waited = skip_cache;
+        if !skip_cache
+            && self.tarpit_active.load(Ordering::Relaxed)
+            && let Some(ms) = self.tarpit.get(&dedupe_hash)
+        {
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+            tarpit_waited = true;
+        }
+
         // Background refresh: Skip cache lookup when skip_cache=true
         // 后台刷新：当 skip_cache=true 时跳过缓存查找
         if !skip_cache {
@@ -635,6 +990,8 @@ impl Engine {
                 qclass,
                 &pipeline_id,
                 dedupe_hash,
+                edns_present,
+                dnssec_flags,
                 tx_id,
                 start,
                 &peer,
@@ -654,10 +1011,11 @@ impl Engine {
         // when the background refresh completes. If client_timeout expires, serve stale.
         // 设计：check_cache() 在 client_timeout > 0 且缓存过期时已触发 spawn_background_refresh。
         // 这里以 5ms 间隔轮询缓存，检测后台刷新是否完成。超时则返回过期数据。
-        if !skip_cache && self.serve_stale && self.serve_stale_client_timeout_ms > 0 {
+        if !skip_cache && self.tunables.serve_stale() && self.tunables.serve_stale_client_timeout_ms() > 0 {
             let has_stale = self.cache.get(&dedupe_hash)
                 .filter(|h| {
                     h.qtype == u16::from(qtype)
+                    && h.qclass == u16::from(qclass)
                     && h.pipeline_id.as_ref() == pipeline_id.as_ref()
                     && h.qname.as_ref() == qname_ref
                     && h.inserted_at.elapsed().as_secs() >= h.original_ttl as u64
@@ -667,7 +1025,7 @@ impl Engine {
                 .is_some();
 
             if has_stale {
-                let client_timeout = std::time::Duration::from_millis(self.serve_stale_client_timeout_ms);
+                let client_timeout = std::time::Duration::from_millis(self.tunables.serve_stale_client_timeout_ms());
                 let poll_interval = std::time::Duration::from_millis(5);
                 let wait_start = Instant::now();
 
@@ -681,7 +1039,7 @@ impl Engine {
                             // Fresh data available! Serve it.
                             if let Some(fresh_bytes) = phases::check_cache(
                                 self, qname_ref, qtype, qclass, &pipeline_id,
-                                dedupe_hash, tx_id, start, &peer,
+                                dedupe_hash, edns_present, dnssec_flags, tx_id, start, &peer,
                             ) {
                                 tracing::debug!(
                                     event = "serve_fresh_after_client_wait",
@@ -698,14 +1056,14 @@ impl Engine {
                 // Client timeout expired - serve stale response
                 // 客户端超时 - 返回过期缓存响应
                 if let Some(stale_bytes) = phases::check_stale_cache(
-                    self, qname_ref, qtype, qclass, &pipeline_id, dedupe_hash, tx_id, &peer,
+                    self, qname_ref, qtype, qclass, &pipeline_id, dedupe_hash, edns_present, dnssec_flags, tx_id, &peer,
                 ) {
                     tracing::debug!(
                         event = "serve_stale_on_client_timeout",
                         qname = %qname_ref,
                         qtype = ?qtype,
-                        timeout_ms = self.serve_stale_client_timeout_ms,
-                        client_ip = %peer.ip(),
+                        timeout_ms = self.tunables.serve_stale_client_timeout_ms(),
+                        client_ip = %self.privacy.client(peer.ip()),
                         pipeline = %pipeline_id,
                         "RFC 8767: client timeout expired, serving stale"
                     );
@@ -722,14 +1080,16 @@ impl Engine {
         let qname = qname_cow;
         let mut skip_rules: HashSet<Arc<str>> = HashSet::new();
         let mut current_pipeline_id = pipeline_id.clone();
+        let response_jump_limit = cfg.response_jump_limit_for(&pipeline_id);
         // Convert qname to bytes for hash calculation / 将 qname 转换为 bytes 进行哈希计算
         let qname_bytes = qname.as_bytes();
-        let mut dedupe_hash = Self::calculate_cache_hash_for_dedupe(&current_pipeline_id, qname_bytes, qtype, qclass);
+        let mut dedupe_hash = Self::calculate_cache_hash_for_dedupe(&current_pipeline_id, qname_bytes, qtype, qclass, dnssec_flags);
         let mut reused_response: Option<ResponseContext> = None;
 
-        let mut decision = match pipeline_opt {
-            Some(p) => self.apply_rules(&state, p, peer.ip(), &qname, qtype, qclass, edns_present, None, skip_cache),
-            None => {
+        let mut decision = match (runtime_decision, pipeline_opt) {
+            (Some(d), _) => d,
+            (None, Some(p)) => self.apply_rules(&state, p, peer.ip(), &qname, qtype, qclass, edns_present, None, skip_cache),
+            (None, None) => {
                 // 使用预分割的默认 upstream 以支持并发查询 / Use pre-split default upstream for concurrent queries
                 let (upstream, pre_split) = if let Some(pre) = &cfg.settings.default_upstream_pre_split {
                     (Arc::from(cfg.settings.default_upstream.as_str()), Some(pre.clone()))
@@ -772,13 +1132,22 @@ impl Engine {
         // This is a standard Rust RAII pattern that is safe and idiomatic.
         // 这是标准的 Rust RAII 模式，安全且符合惯用法。
 
+        let mut tarpit_ms = 0u64;
         'decision_loop: loop {
             let mut jump_count = 0;
+            // 本轮经过的 pipeline，跳转次数耗尽时输出 / Pipelines visited in this round, logged when the jumps run out
+            let mut jump_chain: Vec<Arc<str>> = vec![current_pipeline_id.clone()];
             loop {
+                if let Decision::Delay { ms, then } = decision {
+                    tarpit_ms += ms;
+                    decision = *then;
+                    continue;
+                }
                 if let Decision::Jump { pipeline } = &decision {
                     jump_count += 1;
+                    jump_chain.push(pipeline.clone());
                     if jump_count > response_jump_limit {
-                        warn!("max jump limit reached");
+                        crate::engine::rules::warn_jump_limit("request", &qname, &jump_chain);
                         decision = Decision::Static {
                             rcode: ResponseCode::ServFail,
                             answers: Vec::new(),
@@ -787,7 +1156,7 @@ impl Engine {
                     }
                     if let Some(p) = cfg.pipelines.iter().find(|p| p.id.as_ref() == pipeline.as_ref()) {
                         current_pipeline_id = p.id.clone();
-                        dedupe_hash = Self::calculate_cache_hash_for_dedupe(&current_pipeline_id, qname_bytes, qtype, qclass);
+                        dedupe_hash = Self::calculate_cache_hash_for_dedupe(&current_pipeline_id, qname_bytes, qtype, qclass, dnssec_flags);
                         skip_rules.clear();
                         decision = self.apply_rules(
                             &state,
@@ -814,16 +1183,26 @@ impl Engine {
                 }
             }
 
+            if tarpit_ms > 0 {
+                self.tarpit(dedupe_hash, tarpit_ms, tarpit_waited).await;
+                tarpit_ms = 0;
+                tarpit_waited = skip_cache;
+            }
+
             match decision {
             Decision::Jump { .. } => {
                 anyhow::bail!("unresolved pipeline jump");
             }
+            Decision::Delay { .. } => {
+                anyhow::bail!("unresolved delay");
+            }
             Decision::Static { rcode, answers } => {
                 return phases::handle_static_decision(
                     self,
                     packet,
                     &qname,
                     qtype,
+                    qclass,
                     &current_pipeline_id,
                     dedupe_hash,
                     min_ttl,
@@ -846,9 +1225,10 @@ impl Engine {
                 continue_on_miss: _,
                 allow_reuse,
             } => {
+                let upstream_packet = self.upstream_query(&current_pipeline_id, packet);
                 let res = phases::handle_forward_decision(
                     self,
-                    packet,
+                    &upstream_packet,
                     &qname,
                     qtype,
                     qclass,
@@ -856,6 +1236,7 @@ impl Engine {
                     &current_pipeline_id,
                     &rule_name,
                     dedupe_hash,
+                    dnssec_flags,
                     min_ttl,
                     upstream_timeout,
                     start,
@@ -932,6 +1313,7 @@ impl Engine {
     /// 1. 检查 refresh_ttl >= cache_refresh_min_ttl（默认5秒）
     /// 2. 使用与正常请求相同的 Singleflight 机制（inflight map）
     /// 3. 刷新失败不会删除现有缓存条目
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn spawn_background_refresh(
         &self,
         cache_hash: u64,
@@ -939,6 +1321,7 @@ impl Engine {
         qname: &str,
         qtype: hickory_proto::rr::RecordType,
         qclass: DNSClass,
+        dnssec_flags: u8,
         upstream: Option<&str>,  // Reserved for future use
     ) {
         crate::engine::refresh::spawn_background_refresh(
@@ -948,10 +1331,32 @@ impl Engine {
             qname,
             qtype,
             qclass,
+            dnssec_flags,
             upstream
         )
     }
 
+    /// 以任务所属 pipeline 执行预取查询（跳过缓存查找，结果写回缓存）
+    /// Resolve a prefetch query through the job's pipeline (skips cache lookup, result is cached)
+    pub(crate) async fn resolve_for_prefetch(
+        &self,
+        packet: &[u8],
+        job: &super::prefetch::PrefetchJob,
+    ) -> anyhow::Result<Bytes> {
+        // 后台预取是内部请求，使用回环地址作为 peer / Prefetch is internal, use loopback as peer
+        let peer = SocketAddr::new(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 53);
+        let pre_parsed = PreParsedData {
+            qname: job.qname.clone(),
+            qtype: job.qtype,
+            qclass: job.qclass,
+            tx_id: u16::from_be_bytes([packet[0], packet[1]]),
+            edns_present: job.dnssec_flags & crate::proto_utils::DNSSEC_FLAG_DO != 0,
+            dnssec_flags: job.dnssec_flags,
+            pipeline_id: job.pipeline_id.clone(),
+        };
+        self.handle_packet_internal(packet, peer, true, Some(pre_parsed)).await
+    }
+
     /// Construct standard DNS query packet using hickory_proto
     /// 使用 hickory_proto 构造标准 DNS 查询包
     /// 
@@ -968,6 +1373,7 @@ impl Engine {
         qname: &str,
         qtype: hickory_proto::rr::RecordType,
         _qclass: DNSClass,  // Currently defaults to IN class, parameter reserved for future use
+        dnssec_flags: u8,
     ) -> anyhow::Result<Bytes> {
         use hickory_proto::op::{Message, MessageType, Query};
         use hickory_proto::rr::Name;
@@ -982,6 +1388,14 @@ impl Engine {
         msg.set_id(tx_id);
         msg.set_message_type(MessageType::Query);
         msg.set_recursion_desired(true);
+        // 保留原请求的 DO/CD 位，使应答与缓存键一致 / Keep the original DO/CD bits so the answer matches the cache key
+        msg.set_checking_disabled(dnssec_flags & crate::proto_utils::DNSSEC_FLAG_CD != 0);
+        if dnssec_flags & crate::proto_utils::DNSSEC_FLAG_DO != 0 {
+            let mut edns = hickory_proto::op::Edns::new();
+            edns.set_max_payload(1232);
+            edns.set_dnssec_ok(true);
+            msg.set_edns(edns);
+        }
         
         // Add question section
         // 添加问题部分
@@ -1172,6 +1586,7 @@ mod tests {
             false,
             hickory_proto::rr::RecordType::A,
             "edge",
+            ListenerTransport::Udp,
             None,
             None,
         );
@@ -1181,6 +1596,187 @@ mod tests {
         assert_eq!(id.as_ref(), "p2", "Should select p2 pipeline for edge listener");
     }
 
+    #[test]
+    fn pipeline_select_matches_client_group() {
+        // Arrange: Define a client group and select a pipeline by it
+        let raw = serde_json::json!({
+            "client_groups": { "kids": ["192.168.10.0/24", "10.0.0.7"] },
+            "pipelines": [
+                { "id": "main", "rules": [] },
+                { "id": "kids", "rules": [] }
+            ],
+            "pipeline_select": [
+                { "pipeline": "kids", "matchers": [ { "type": "client_group", "name": "kids" } ] }
+            ]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+        let runtime = RuntimePipelineConfig::from_config(cfg).expect("runtime");
+        let select = |ip: &str| {
+            select_pipeline(
+                &runtime,
+                "example.com",
+                ip.parse().unwrap(),
+                hickory_proto::rr::DNSClass::IN,
+                false,
+                hickory_proto::rr::RecordType::A,
+                "lbl",
+                ListenerTransport::Udp,
+                None,
+                None,
+            )
+            .0
+            .map(|p| p.id.to_string())
+        };
+
+        // Act & Assert
+        assert_eq!(select("192.168.10.20").as_deref(), Some("kids"));
+        assert_eq!(select("10.0.0.7").as_deref(), Some("kids"));
+        assert_ne!(select("10.0.0.8").as_deref(), Some("kids"));
+    }
+
+    #[test]
+    fn domain_set_is_compiled_once_and_shared() {
+        // Arrange: Two pipelines reference the same named set
+        let raw = serde_json::json!({
+            "sets": { "cn_domains": { "domains": ["cn", "full:example.com"] } },
+            "pipelines": [
+                { "id": "a", "rules": [ { "name": "r1", "matchers": [ { "type": "domain_set_ref", "name": "cn_domains" } ], "actions": [ { "type": "deny" } ] } ] },
+                { "id": "b", "rules": [ { "name": "r2", "matchers": [ { "type": "domain_set_ref", "name": "cn_domains" } ], "actions": [ { "type": "deny" } ] } ] }
+            ],
+            "pipeline_select": [
+                { "pipeline": "b", "matchers": [ { "type": "domain_set_ref", "name": "cn_domains" } ] }
+            ]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+
+        // Act
+        let runtime = RuntimePipelineConfig::from_config(cfg).expect("runtime");
+        let set_of = |p: usize| match &runtime.pipelines[p].rules[0].matchers[0].matcher {
+            crate::matcher::RuntimeMatcher::DomainSet { set, .. } => set.clone(),
+            other => panic!("unexpected matcher {:?}", other),
+        };
+        let select = |qname: &str| {
+            select_pipeline(
+                &runtime,
+                qname,
+                "127.0.0.1".parse().unwrap(),
+                hickory_proto::rr::DNSClass::IN,
+                false,
+                hickory_proto::rr::RecordType::A,
+                "lbl",
+                ListenerTransport::Udp,
+                None,
+                None,
+            )
+            .1
+            .to_string()
+        };
+
+        // Assert
+        assert!(Arc::ptr_eq(&set_of(0), &set_of(1)));
+        assert!(set_of(0).contains("www.baidu.cn"));
+        assert!(!set_of(0).contains("www.example.com"));
+        assert_eq!(select("example.com"), "b");
+        assert_eq!(select("example.org"), "a");
+    }
+
+    #[test]
+    fn domain_set_ref_to_unknown_set_is_rejected() {
+        // Arrange
+        let raw = serde_json::json!({
+            "pipelines": [
+                { "id": "a", "rules": [ { "name": "r1", "matchers": [ { "type": "domain_set_ref", "name": "missing" } ], "actions": [ { "type": "deny" } ] } ] }
+            ]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+
+        // Act
+        let err = RuntimePipelineConfig::from_config(cfg).expect_err("unknown set");
+
+        // Assert
+        assert!(format!("{:#}", err).contains("unknown domain set: missing"));
+    }
+
+    #[test]
+    fn cidr_file_is_loaded_once_and_shared() {
+        // Arrange: Two rules reference the same CIDR list file, one adds an inline CIDR
+        let path = std::env::temp_dir().join(format!("kixdns-cidr-{}.txt", std::process::id()));
+        std::fs::write(&path, "# chnroute\n1.0.1.0/24\n\n223.5.5.5 # single ip\n").expect("write");
+        let file = path.to_string_lossy().to_string();
+        let rule = |name: &str, cidr: &str| serde_json::json!({
+            "name": name,
+            "matchers": [ { "type": "any" } ],
+            "actions": [ { "type": "forward", "upstream": "223.5.5.5:53" } ],
+            "response_matchers": [ { "type": "response_answer_ip", "cidr": cidr, "file": file } ]
+        });
+        let raw = serde_json::json!({
+            "pipelines": [ { "id": "p", "rules": [ rule("a", ""), rule("b", ""), rule("c", "10.0.0.0/8") ] } ]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+
+        // Act
+        let runtime = RuntimePipelineConfig::from_config(cfg);
+        let _ = std::fs::remove_file(&path);
+        let runtime = runtime.expect("runtime");
+        let nets_of = |r: usize| match &runtime.pipelines[0].rules[r].response_matchers[0].matcher {
+            crate::matcher::RuntimeResponseMatcher::ResponseAnswerIp { nets } => nets.clone(),
+            other => panic!("unexpected matcher {:?}", other),
+        };
+
+        // Assert
+        assert!(Arc::ptr_eq(&nets_of(0), &nets_of(1)));
+        assert_eq!(nets_of(0).len(), 2);
+        assert!(nets_of(0).contains(&"1.0.1.9".parse().unwrap()));
+        assert!(nets_of(0).contains(&"223.5.5.5".parse().unwrap()));
+        assert!(!nets_of(0).contains(&"10.1.1.1".parse().unwrap()));
+        assert!(nets_of(2).contains(&"10.1.1.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn strict_config_rejects_unknown_fields() {
+        // Arrange: A misspelled rule field and a misspelled matcher field
+        let raw = serde_json::json!({
+            "strict": true,
+            "pipelines": [ { "id": "p", "rules": [ {
+                "name": "r",
+                "matcher_opertor": "or",
+                "matchers": [ { "type": "domain_suffix", "value": "a.test", "vlaue": "b.test" } ],
+                "actions": [ { "type": "deny" } ]
+            } ] } ]
+        });
+        let path = std::env::temp_dir().join(format!("kixdns-strict-{}.json", std::process::id()));
+        std::fs::write(&path, raw.to_string()).expect("write");
+
+        // Act
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw.clone()).expect("parse");
+        let unknown = crate::config::unknown_fields(&raw, &cfg);
+        let loaded = crate::config::load_config(&path);
+        let _ = std::fs::remove_file(&path);
+
+        // Assert
+        assert_eq!(
+            unknown,
+            vec!["pipelines[0].rules[0].matcher_opertor", "pipelines[0].rules[0].matchers[0].vlaue"]
+        );
+        assert!(format!("{:#}", loaded.expect_err("strict")).contains("matcher_opertor"));
+    }
+
+    #[test]
+    fn strict_config_accepts_field_aliases() {
+        // Arrange: serve_stale_max_secs is an alias of serve_stale_expire_ttl
+        let raw = serde_json::json!({
+            "strict": true,
+            "settings": { "serve_stale_max_secs": 600 },
+            "pipelines": []
+        });
+
+        // Act
+        let loaded = crate::config::parse_config(&raw.to_string(), "alias.json");
+
+        // Assert
+        assert_eq!(loaded.expect("strict config with alias").settings.serve_stale_expire_ttl, 600);
+    }
+
     #[test]
     fn pipeline_select_respects_match_operator_or() {
         // Arrange: Create configuration with OR operator in pipeline selector
@@ -1214,6 +1810,7 @@ mod tests {
             false,
             hickory_proto::rr::RecordType::A,
             "edge",
+            ListenerTransport::Udp,
             None,
             None,
         );
@@ -1223,6 +1820,110 @@ mod tests {
         assert_eq!(id.as_ref(), "p2", "Should select p2 pipeline for edge listener");
     }
 
+    #[test]
+    fn disabled_rules_and_pipelines_are_validated_but_skipped() {
+        // Arrange
+        let raw = serde_json::json!({
+            "pipelines": [
+                { "id": "off", "enabled": false, "description": "maintenance", "rules": [] },
+                { "id": "main", "rules": [
+                    { "name": "old", "enabled": false, "description": "kept for reference",
+                      "matchers": [ { "type": "any" } ], "actions": [ { "type": "deny" } ] },
+                    { "name": "live", "matchers": [ { "type": "any" } ], "actions": [ { "type": "allow" } ] }
+                ] }
+            ],
+            "pipeline_select": [
+                { "pipeline": "off", "matchers": [ { "type": "listener_label", "value": "edge" } ] }
+            ]
+        });
+        let invalid = serde_json::json!({
+            "pipelines": [ { "id": "main", "rules": [
+                { "name": "broken", "enabled": false, "matchers": [ { "type": "domain_regex", "value": "(" } ] }
+            ] } ]
+        });
+        let jump_to_disabled = serde_json::json!({
+            "pipelines": [
+                { "id": "off", "enabled": false, "rules": [] },
+                { "id": "main", "rules": [
+                    { "name": "parked", "enabled": false, "matchers": [ { "type": "any" } ], "actions": [ { "type": "jump_to_pipeline", "pipeline": "off" } ] },
+                    { "name": "jump", "matchers": [ { "type": "any" } ], "actions": [ { "type": "jump_to_pipeline", "pipeline": "off" } ] }
+                ] }
+            ]
+        });
+
+        // Act
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+        let runtime = RuntimePipelineConfig::from_config(cfg).expect("runtime");
+        let (_, id) = select_pipeline(
+            &runtime,
+            "any.example.com",
+            "127.0.0.1".parse().unwrap(),
+            hickory_proto::rr::DNSClass::IN,
+            false,
+            hickory_proto::rr::RecordType::A,
+            "edge",
+            ListenerTransport::Udp,
+            None,
+            None,
+        );
+        let invalid: crate::config::PipelineConfig = serde_json::from_value(invalid).expect("parse");
+        let jump_to_disabled: crate::config::PipelineConfig = serde_json::from_value(jump_to_disabled).expect("parse");
+
+        // Assert
+        assert_eq!(runtime.pipelines.len(), 1);
+        assert_eq!(id.as_ref(), "main", "disabled pipeline must not be selected");
+        let names: Vec<&str> = runtime.pipelines[0].rules.iter().map(|r| r.name.as_ref()).collect();
+        assert_eq!(names, vec!["live"]);
+        assert!(RuntimePipelineConfig::from_config(invalid).is_err(), "disabled rules are still validated");
+        assert_eq!(
+            crate::engine::validation::cross_reference_errors(&jump_to_disabled),
+            vec!["pipeline main rule jump actions: jump to disabled pipeline off".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn nxdomain_burst_decisions_are_not_rule_cached() {
+        // Arrange
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let raw = serde_json::json!({
+            "settings": { "default_upstream": "1.1.1.1:53" },
+            "pipelines": [ { "id": "p", "rules": [
+                {
+                    "name": "burst",
+                    "matchers": [ { "type": "nxdomain_burst", "threshold": 1 } ],
+                    "actions": [ { "type": "static_response", "rcode": "REFUSED" } ]
+                }
+            ] } ]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+        let engine = Engine::new(RuntimePipelineConfig::from_config(cfg).expect("runtime"), "lbl".to_string());
+        let state = engine.state.load();
+        let client: IpAddr = "192.0.2.77".parse().unwrap();
+        let apply = || {
+            engine.apply_rules(
+                &state,
+                &state.pipeline.pipelines[0],
+                client,
+                "burst.example.com",
+                RecordType::A,
+                DNSClass::IN,
+                false,
+                None,
+                false,
+            )
+        };
+
+        // Act
+        let before = apply();
+        crate::matcher::nxdomain_burst::record(client);
+        let after = apply();
+
+        // Assert
+        assert!(!state.pipeline.pipelines[0].rule_cacheable);
+        assert!(matches!(before, Decision::Forward { .. }));
+        assert!(matches!(after, Decision::Static { rcode: ResponseCode::Refused, .. }));
+    }
+
     #[allow(dead_code)]
     #[tokio::test]
     async fn apply_rules_static_and_forward_allow_jump() {
@@ -1350,7 +2051,7 @@ mod tests {
 
         // Arrange: Test JumpToPipeline action
         let raw4 = serde_json::json!({
-            "pipelines": [ { "id": "p4", "rules": [ { "name": "j", "matchers": [ { "type": "any" } ], "actions": [ { "type": "jump_to_pipeline", "pipeline": "other" } ] } ] } ]
+            "pipelines": [ { "id": "p4", "rules": [ { "name": "j", "matchers": [ { "type": "any" } ], "actions": [ { "type": "jump_to_pipeline", "pipeline": "other" } ] } ] }, { "id": "other", "rules": [] } ]
         });
         let cfg4: crate::config::PipelineConfig = serde_json::from_value(raw4).expect("parse");
         let runtime4 = RuntimePipelineConfig::from_config(cfg4.clone()).expect("runtime");
@@ -1377,22 +2078,473 @@ mod tests {
         }
     }
 
-    const TEST_UPSTREAM: &str = "1.1.1.1:53";
+    #[tokio::test]
+    async fn apply_rules_delay_wraps_decision_and_survives_rule_cache() {
+        // Arrange: Delay followed by a static response
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let raw = serde_json::json!({
+            "pipelines": [ { "id": "p", "rules": [ { "name": "slow", "matchers": [ { "type": "any" } ], "actions": [
+                { "type": "delay", "ms": 250 },
+                { "type": "static_response", "rcode": "NXDOMAIN" }
+            ] } ] } ]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+        let runtime = RuntimePipelineConfig::from_config(cfg).expect("runtime");
+        let engine = Engine::new(runtime, "lbl".to_string());
+        let state = engine.state.load();
+        let apply = || engine.apply_rules(
+            &state,
+            &state.pipeline.pipelines[0],
+            "127.0.0.1".parse().unwrap(),
+            "tracker.example.com",
+            hickory_proto::rr::RecordType::A,
+            hickory_proto::rr::DNSClass::IN,
+            false,
+            None,
+            false,
+        );
 
-    fn build_test_engine() -> Engine {
-        let runtime = RuntimePipelineConfig {
-            settings: GlobalSettings {
-                default_upstream: TEST_UPSTREAM.to_string(),
-                ..Default::default()
-            },
-            pipeline_select: Vec::new(),
-            pipelines: Vec::new(),
-        };
-        Engine::new(runtime, "lbl".to_string())
-    }
+        // Act: Second call is served from the rule cache
+        let first = apply();
+        let cached = apply();
 
-    fn build_response_context() -> ResponseContext {
-        let mut msg = Message::new();
+        // Assert
+        for decision in [first, cached] {
+            match decision {
+                Decision::Delay { ms, then } => {
+                    assert_eq!(ms, 250);
+                    assert!(matches!(*then, Decision::Static { rcode: ResponseCode::NXDomain, .. }));
+                }
+                _ => panic!("expected delay decision"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn tarpit_hands_back_the_permit_and_delays_stay_under_the_request_timeout() {
+        // Arrange
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(serde_json::json!({ "pipelines": [] })).expect("parse");
+        let engine = Engine::new(RuntimePipelineConfig::from_config(cfg).expect("runtime"), "lbl".to_string());
+        let permit = engine.permit_manager.try_acquire().expect("permit");
+        let too_long = serde_json::json!({
+            "settings": { "request_timeout_ms": 3000, "upstream_timeout_ms": 1000 },
+            "pipelines": [ { "id": "p", "rules": [ { "name": "slow", "matchers": [ { "type": "any" } ], "actions": [
+                { "type": "delay", "ms": 3000 },
+                { "type": "static_response", "rcode": "NXDOMAIN" }
+            ] } ] } ]
+        });
+        let too_long: crate::config::PipelineConfig = serde_json::from_value(too_long).expect("parse");
+
+        // Act
+        let inflight_while_waiting = crate::engine::concurrency::with_request_permit(permit, async {
+            engine.tarpit(1, 1, false).await;
+            engine.permit_manager.inflight()
+        })
+        .await;
+
+        // Assert
+        assert_eq!(inflight_while_waiting, 0);
+        assert_eq!(engine.permit_manager.inflight(), 0);
+        assert_eq!(
+            crate::engine::validation::cross_reference_errors(&too_long),
+            vec!["pipeline p rule slow actions: delay 3000ms must be shorter than the request timeout (3000ms)".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn apply_rules_reselect_pipeline_runs_selection_with_new_label() {
+        // Arrange: classify pipeline relabels lan clients, selection routes the label
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let raw = serde_json::json!({
+            "pipelines": [
+                { "id": "classify", "rules": [
+                    { "name": "lan", "matchers": [ { "type": "client_ip", "cidr": "192.168.0.0/16" } ], "actions": [
+                        { "type": "reselect_pipeline", "listener_label": "lan" }
+                    ] },
+                    { "name": "self", "matchers": [ { "type": "any" } ], "actions": [
+                        { "type": "reselect_pipeline", "listener_label": "wan" },
+                        { "type": "static_response", "rcode": "REFUSED" }
+                    ] }
+                ] },
+                { "id": "lan", "rules": [] }
+            ],
+            "pipeline_select": [
+                { "pipeline": "lan", "matchers": [ { "type": "listener_label", "value": "lan" } ] }
+            ]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+        let runtime = RuntimePipelineConfig::from_config(cfg).expect("runtime");
+        let engine = Engine::new(runtime, "lbl".to_string());
+        let state = engine.state.load();
+        let apply = |ip: &str| engine.apply_rules(
+            &state,
+            &state.pipeline.pipelines[0],
+            ip.parse().unwrap(),
+            "example.com",
+            hickory_proto::rr::RecordType::A,
+            hickory_proto::rr::DNSClass::IN,
+            false,
+            None,
+            false,
+        );
+
+        // Act
+        let lan = apply("192.168.1.10");
+        let wan = apply("203.0.113.7");
+
+        // Assert: reselecting the current pipeline falls through to later actions
+        assert!(matches!(lan, Decision::Jump { ref pipeline } if pipeline.as_ref() == "lan"));
+        assert!(matches!(wan, Decision::Static { rcode: ResponseCode::Refused, .. }));
+    }
+
+    #[tokio::test]
+    async fn reselect_pipeline_fall_through_is_not_rule_cached() {
+        // Arrange: selection depends on the client IP, the classify rules do not
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let raw = serde_json::json!({
+            "settings": { "default_upstream": "1.1.1.1:53" },
+            "pipelines": [
+                { "id": "classify", "rules": [
+                    { "name": "route", "matchers": [ { "type": "any" } ], "actions": [
+                        { "type": "reselect_pipeline", "listener_label": "lbl" }
+                    ] }
+                ] },
+                { "id": "kids", "rules": [] }
+            ],
+            "pipeline_select": [
+                { "pipeline": "kids", "matchers": [ { "type": "client_ip", "cidr": "10.0.0.0/8" } ] }
+            ]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+        let engine = Engine::new(RuntimePipelineConfig::from_config(cfg).expect("runtime"), "lbl".to_string());
+        let state = engine.state.load();
+        let apply = |ip: &str| engine.apply_rules(
+            &state,
+            &state.pipeline.pipelines[0],
+            ip.parse().unwrap(),
+            "example.com",
+            hickory_proto::rr::RecordType::A,
+            hickory_proto::rr::DNSClass::IN,
+            false,
+            None,
+            false,
+        );
+
+        // Act
+        let other = apply("203.0.113.7");
+        let kid = apply("10.0.0.5");
+
+        // Assert: the first client's fall-through must not be replayed for the second
+        assert!(matches!(other, Decision::Forward { .. }));
+        assert!(matches!(kid, Decision::Jump { ref pipeline } if pipeline.as_ref() == "kids"));
+    }
+
+    #[tokio::test]
+    async fn suffix_or_chain_is_indexed_and_matches() {
+        // Arrange: An OR list of suffixes, one written with a leading dot
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let raw = serde_json::json!({
+            "pipelines": [ { "id": "p", "rules": [ {
+                "name": "block",
+                "matcher_operator": "or",
+                "matchers": [
+                    { "type": "domain_suffix", "value": "ads.example" },
+                    { "type": "domain_suffix", "value": ".tracker.test" }
+                ],
+                "actions": [ { "type": "static_response", "rcode": "NXDOMAIN" } ]
+            } ] } ]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+        let runtime = RuntimePipelineConfig::from_config(cfg).expect("runtime");
+        let engine = Engine::new(runtime, "lbl".to_string());
+        let state = engine.state.load();
+        let pipeline = &state.pipeline.pipelines[0];
+        let apply = |qname: &str| engine.apply_rules(
+            &state,
+            pipeline,
+            "127.0.0.1".parse().unwrap(),
+            qname,
+            RecordType::A,
+            DNSClass::IN,
+            false,
+            None,
+            true,
+        );
+
+        // Act & Assert
+        assert!(pipeline.always_check_rules.is_empty());
+        assert!(matches!(apply("x.ads.example"), Decision::Static { rcode: ResponseCode::NXDomain, .. }));
+        assert!(matches!(apply("a.b.tracker.test"), Decision::Static { rcode: ResponseCode::NXDomain, .. }));
+        assert!(matches!(apply("example.org"), Decision::Forward { .. }));
+    }
+
+    #[tokio::test]
+    async fn domain_regex_rules_share_one_regex_set() {
+        // Arrange: Two regex rules in one pipeline, the second combined with a qtype
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let raw = serde_json::json!({
+            "pipelines": [ { "id": "p", "rules": [
+                {
+                    "name": "ads",
+                    "matchers": [ { "type": "domain_regex", "value": "^ads[0-9]+\\." } ],
+                    "actions": [ { "type": "static_response", "rcode": "NXDOMAIN" } ]
+                },
+                {
+                    "name": "tracker_aaaa",
+                    "matchers": [
+                        { "type": "domain_regex", "value": "tracker" },
+                        { "type": "qtype", "value": "AAAA" }
+                    ],
+                    "actions": [ { "type": "static_response", "rcode": "REFUSED" } ]
+                }
+            ] } ]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+        let runtime = RuntimePipelineConfig::from_config(cfg).expect("runtime");
+        let engine = Engine::new(runtime, "lbl".to_string());
+        let state = engine.state.load();
+        let pipeline = &state.pipeline.pipelines[0];
+        let apply = |qname: &str, qtype: RecordType| engine.apply_rules(
+            &state,
+            pipeline,
+            "127.0.0.1".parse().unwrap(),
+            qname,
+            qtype,
+            DNSClass::IN,
+            false,
+            None,
+            true,
+        );
+
+        // Act & Assert
+        assert_eq!(pipeline.regex_set.as_ref().map(|s| s.len()), Some(2));
+        assert!(matches!(apply("ads12.example.com", RecordType::A), Decision::Static { rcode: ResponseCode::NXDomain, .. }));
+        assert!(matches!(apply("my.tracker.net", RecordType::AAAA), Decision::Static { rcode: ResponseCode::Refused, .. }));
+        assert!(matches!(apply("my.tracker.net", RecordType::A), Decision::Forward { .. }));
+        assert!(matches!(apply("adsx.example.com", RecordType::A), Decision::Forward { .. }));
+    }
+
+    #[tokio::test]
+    async fn pipeline_jump_limit_overrides_global_limit() {
+        // Arrange: a -> b -> c, where c answers NXDOMAIN
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let engine_with = |limit: Option<u32>| {
+            let jump = |id: &str, target: &str| serde_json::json!({
+                "id": id,
+                "response_jump_limit": if id == "a" { limit } else { None },
+                "rules": [ { "name": "j", "matchers": [ { "type": "any" } ], "actions": [ { "type": "jump_to_pipeline", "pipeline": target } ] } ]
+            });
+            let raw = serde_json::json!({
+                "pipelines": [
+                    jump("a", "b"),
+                    jump("b", "c"),
+                    { "id": "c", "rules": [ { "name": "nx", "matchers": [ { "type": "any" } ], "actions": [ { "type": "static_response", "rcode": "NXDOMAIN" } ] } ] }
+                ]
+            });
+            let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+            Engine::new(RuntimePipelineConfig::from_config(cfg).expect("runtime"), "lbl".to_string())
+        };
+        let mut query = Message::new();
+        query.set_id(7).add_query(Query::query(Name::from_ascii("jump.test.").unwrap(), RecordType::A));
+        let packet = query.to_vec().unwrap();
+        let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+
+        // Act
+        let global = engine_with(None).handle_packet(&packet, peer).await.expect("global");
+        let limited = engine_with(Some(1)).handle_packet(&packet, peer).await.expect("limited");
+
+        // Assert
+        assert_eq!(Message::from_vec(&global).unwrap().response_code(), ResponseCode::NXDomain);
+        assert_eq!(Message::from_vec(&limited).unwrap().response_code(), ResponseCode::ServFail);
+    }
+
+    #[tokio::test]
+    async fn response_jump_carries_the_response_unless_reforward_is_set() {
+        // Arrange: a forwards and jumps on its answer; b flags answers from a's upstream
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let engine_with = |reforward: bool| {
+            let raw = serde_json::json!({
+                "pipelines": [
+                    { "id": "a", "rules": [ { "name": "first", "matchers": [ { "type": "any" } ],
+                        "actions": [ { "type": "forward", "upstream": "mock://a=192.0.2.1" } ],
+                        "response_matchers": [ { "type": "response_rcode", "value": "NOERROR" } ],
+                        "response_actions_on_match": [ { "type": "jump_to_pipeline", "pipeline": "b", "reforward": reforward } ] } ] },
+                    { "id": "b", "rules": [ { "name": "second", "matchers": [ { "type": "any" } ],
+                        "actions": [ { "type": "forward", "upstream": "mock://a=198.51.100.9" } ],
+                        "response_matchers": [ { "type": "response_answer_ip", "cidr": "192.0.2.0/24" } ],
+                        "response_actions_on_match": [ { "type": "static_response", "rcode": "REFUSED" } ] } ] }
+                ]
+            });
+            let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+            Engine::new(RuntimePipelineConfig::from_config(cfg).expect("runtime"), "lbl".to_string())
+        };
+        let mut query = Message::new();
+        query.set_id(7).add_query(Query::query(Name::from_ascii("carry.test.").unwrap(), RecordType::A));
+        let packet = query.to_vec().unwrap();
+        let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+
+        // Act
+        let carried = engine_with(false).handle_packet(&packet, peer).await.expect("carried");
+        let reforwarded = engine_with(true).handle_packet(&packet, peer).await.expect("reforwarded");
+
+        // Assert: b's matchers see a's answer, or b's own upstream's answer with reforward
+        assert_eq!(Message::from_vec(&carried).unwrap().response_code(), ResponseCode::Refused);
+        let reforwarded = Message::from_vec(&reforwarded).unwrap();
+        assert_eq!(reforwarded.response_code(), ResponseCode::NoError);
+        assert_eq!(reforwarded.answers()[0].data().and_then(|d| d.ip_addr()), Some("198.51.100.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn jump_cycles_are_rejected_at_compile_time() {
+        // Arrange: a -> b -> c -> b, and the same graph with the c -> b rule disabled
+        let config = |back_edge_enabled: bool| {
+            let jump = |id: &str, target: &str, enabled: bool| serde_json::json!({
+                "id": id,
+                "rules": [ { "name": "j", "enabled": enabled, "matchers": [ { "type": "any" } ], "actions": [ { "type": "jump_to_pipeline", "pipeline": target } ] } ]
+            });
+            let raw = serde_json::json!({
+                "pipelines": [ jump("a", "b", true), jump("b", "c", true), jump("c", "b", back_edge_enabled) ]
+            });
+            serde_json::from_value::<crate::config::PipelineConfig>(raw).expect("parse")
+        };
+
+        // Act
+        let cycle = config(true).jump_cycle();
+        let compiled = RuntimePipelineConfig::from_config(config(true));
+        let acyclic = RuntimePipelineConfig::from_config(config(false));
+
+        // Assert
+        assert_eq!(cycle, Some(vec!["b".to_string(), "c".to_string(), "b".to_string()]));
+        assert!(format!("{:#}", compiled.expect_err("cycle")).contains("b -> c -> b"));
+        assert!(acyclic.is_ok());
+    }
+
+    #[test]
+    fn cross_reference_errors_are_reported_together() {
+        // Arrange: one mistake of each kind, plus a valid rule
+        let raw = serde_json::json!({
+            "settings": { "default_upstream": "1.1.1.1:53,not-an-address" },
+            "pipeline_select": [ { "pipeline": "missing_select", "matchers": [ { "type": "any" } ] } ],
+            "pipelines": [ { "id": "p", "rules": [
+                { "name": "jump", "matchers": [ { "type": "any" } ], "actions": [ { "type": "jump_to_pipeline", "pipeline": "nowhere" } ] },
+                { "name": "regex", "matchers": [ { "type": "domain_regex", "value": "(" } ], "actions": [ { "type": "deny" } ] },
+                { "name": "doq", "matchers": [ { "type": "any" } ], "actions": [ { "type": "forward", "upstream": "doq://223.5.5.5:853" } ] },
+                { "name": "phase", "matchers": [ { "type": "any" } ], "actions": [ { "type": "retry_tcp" } ] },
+                { "name": "ok", "matchers": [ { "type": "any" } ], "actions": [ { "type": "forward", "upstream": "tls://dns.google,tcp://dns.google:53" } ] }
+            ] } ]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+
+        // Act
+        let errors = crate::engine::validation::cross_reference_errors(&cfg);
+        let compiled = RuntimePipelineConfig::from_config(cfg);
+
+        // Assert
+        assert_eq!(errors.len(), 6, "{:#?}", errors);
+        assert!(errors[0].contains("settings.default_upstream: invalid upstream not-an-address"));
+        assert!(errors[1].contains("pipeline_select missing_select: unknown pipeline"));
+        assert!(errors.iter().any(|e| e.contains("jump to unknown pipeline nowhere")));
+        assert!(errors.iter().any(|e| e.contains("rule regex: invalid regex")));
+        assert!(errors.iter().any(|e| e.contains("rule doq actions: invalid upstream doq://223.5.5.5:853")));
+        assert!(errors.iter().any(|e| e.contains("rule phase: retry_tcp is only valid in response actions")));
+        assert!(format!("{:#}", compiled.expect_err("invalid")).contains("config has 6 error(s)"));
+    }
+
+    #[tokio::test]
+    async fn reload_with_new_bind_notifies_listeners() {
+        // Arrange
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let config = |bind_udp: &str| {
+            let raw = serde_json::json!({ "settings": { "bind_udp": bind_udp }, "pipelines": [] });
+            let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+            RuntimePipelineConfig::from_config(cfg).expect("runtime")
+        };
+        let engine = Engine::new(config("0.0.0.0:5353"), "lbl".to_string());
+        let mut addrs = engine.watch_listen_addrs();
+
+        // Act
+        engine.reload(config("0.0.0.0:5353"));
+        let unchanged = addrs.has_changed().expect("sender alive");
+        engine.reload(config("127.0.0.1:5300"));
+
+        // Assert
+        assert!(!unchanged, "same bind addresses should not trigger a rebind");
+        assert!(addrs.has_changed().expect("sender alive"));
+        assert_eq!(addrs.borrow_and_update().udp, "127.0.0.1:5300");
+    }
+
+    #[tokio::test]
+    async fn prefetch_joins_inflight_live_query() {
+        // Arrange: A live query for the same key is already waiting on the upstream
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let engine = build_test_engine();
+        let job = crate::engine::prefetch::PrefetchJob {
+            cache_hash: 0,
+            pipeline_id: Arc::from("default"),
+            qname: "shared.example.com".to_string(),
+            qtype: RecordType::A,
+            qclass: DNSClass::IN,
+            dnssec_flags: 0,
+        };
+        let dedupe_hash = Engine::calculate_cache_hash_for_dedupe(
+            &job.pipeline_id,
+            job.qname.as_bytes(),
+            job.qtype,
+            job.qclass,
+            0,
+        );
+        let (tx, _rx) = tokio::sync::watch::channel(Err(Arc::new(anyhow::anyhow!("Pending"))));
+        engine.inflight.insert(dedupe_hash, tx);
+        let packet = engine
+            .construct_dns_packet(&job.qname, job.qtype, job.qclass, job.dnssec_flags)
+            .expect("packet");
+        let expected_id = [packet[0], packet[1]];
+
+        // Act: The prefetch subscribes, then the live exchange completes
+        let prefetch = {
+            let engine = engine.clone();
+            let job = job.clone();
+            tokio::spawn(async move { engine.resolve_for_prefetch(&packet, &job).await })
+        };
+        while engine.inflight.get(&dedupe_hash).is_some_and(|tx| tx.receiver_count() < 2) {
+            tokio::task::yield_now().await;
+        }
+        engine
+            .notify_inflight_waiters(dedupe_hash, &Bytes::from_static(b"\x00\x00\x81\x80shared"))
+            .await;
+        let resp = tokio::time::timeout(Duration::from_secs(1), prefetch)
+            .await
+            .expect("prefetch waits on the live exchange")
+            .expect("join")
+            .expect("response");
+
+        // Assert: The shared response is returned with the prefetch transaction id
+        assert_eq!(&resp[..2], &expected_id);
+        assert_eq!(engine.metrics_coalesced.load(Ordering::Relaxed), 1);
+        assert_eq!(&resp[2..], b"\x81\x80shared");
+    }
+
+    const TEST_UPSTREAM: &str = "1.1.1.1:53";
+
+    fn build_test_engine() -> Engine {
+        let runtime = RuntimePipelineConfig {
+            settings: GlobalSettings {
+                default_upstream: TEST_UPSTREAM.to_string(),
+                ..Default::default()
+            },
+            pipeline_select: Vec::new(),
+            pipelines: Vec::new(),
+            allowlist: Default::default(),
+            tracks_nxdomain: false,
+            prefetch_qtypes: Default::default(),
+            local_records: Default::default(),
+        };
+        Engine::new(runtime, "lbl".to_string())
+    }
+
+    fn build_response_context() -> ResponseContext {
+        let mut msg = Message::new();
         msg.set_response_code(ResponseCode::NoError);
         let name = Name::from_str("example.com").expect("name");
         let record = Record::from_rdata(name, 300, RData::A(A(Ipv4Addr::new(1, 2, 3, 4))));
@@ -1402,9 +2554,113 @@ mod tests {
             msg,
             upstream: Arc::from(TEST_UPSTREAM),
             transport: Transport::Udp,
+            latency: Duration::ZERO,
         }
     }
 
+    #[tokio::test]
+    async fn cache_hits_decay_ttls_by_residence_time() {
+        // Arrange: an answer with TTL 300 cached 40 seconds ago
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let engine = build_test_engine();
+        let name = Name::from_ascii("decay.example.com.").unwrap();
+        let mut resp = Message::new();
+        resp.set_id(1).set_message_type(hickory_proto::op::MessageType::Response);
+        resp.add_query(Query::query(name.clone(), RecordType::A));
+        resp.add_answer(Record::from_rdata(name, 300, RData::A(hickory_proto::rr::rdata::A::new(192, 0, 2, 1))));
+        let hash = Engine::calculate_cache_hash_for_dedupe("default", b"decay.example.com", RecordType::A, DNSClass::IN, 0);
+        engine.cache_insert(hash, Arc::new(crate::cache::CacheEntry {
+            bytes: Bytes::from(resp.to_vec().unwrap()),
+            compressed: false,
+            rcode: ResponseCode::NoError,
+            source: Arc::from(TEST_UPSTREAM),
+            upstream: None,
+            qname: Arc::from("decay.example.com"),
+            pipeline_id: Arc::from("default"),
+            qtype: u16::from(RecordType::A),
+            qclass: u16::from(DNSClass::IN),
+            inserted_at: Instant::now() - Duration::from_secs(40),
+            original_ttl: 300,
+            refresh_ttl: 300,
+            aged: Default::default(),
+        }));
+        let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+
+        // Act
+        let hit = crate::engine::phases::check_cache(
+            &engine, "decay.example.com", RecordType::A, DNSClass::IN, "default", hash, false, 0, 0x4242, Instant::now(), &peer,
+        )
+        .expect("cache hit");
+
+        // Assert: the TTL counts down and the transaction id is the requester's
+        let hit = Message::from_vec(&hit).unwrap();
+        assert_eq!(hit.id(), 0x4242);
+        assert_eq!(hit.answers()[0].ttl(), 260);
+    }
+
+    #[tokio::test]
+    async fn zero_ttl_answers_follow_the_pipeline_policy() {
+        // Arrange: micro-cache TTL 0 answers for 300 ms, and never cache them in pipeline "ddns"
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let raw = serde_json::json!({
+            "settings": { "default_upstream": TEST_UPSTREAM, "zero_ttl": { "type": "micro_cache", "ms": 300 } },
+            "pipelines": [{ "id": "ddns", "zero_ttl": { "type": "no_cache" }, "rules": [] }]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+        let engine = Engine::new(RuntimePipelineConfig::from_config(cfg).expect("runtime"), "lbl".to_string());
+        let name = Name::from_ascii("burst.example.com.").unwrap();
+        let mut resp = Message::new();
+        resp.set_id(1).set_message_type(hickory_proto::op::MessageType::Response);
+        resp.add_query(Query::query(name.clone(), RecordType::A));
+        resp.add_answer(Record::from_rdata(name, 0, RData::A(hickory_proto::rr::rdata::A::new(192, 0, 2, 1))));
+        let hash = Engine::calculate_cache_hash_for_dedupe("default", b"burst.example.com", RecordType::A, DNSClass::IN, 0);
+        let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let lookup = || crate::engine::phases::check_cache(
+            &engine, "burst.example.com", RecordType::A, DNSClass::IN, "default", hash, false, 0, 0x4242, Instant::now(), &peer,
+        );
+
+        // Act
+        let micro = engine.cache_lifetime("default", CacheTtl::Answer(0), Duration::ZERO);
+        let ddns = engine.cache_lifetime("ddns", CacheTtl::Answer(0), Duration::from_secs(30));
+        engine.insert_dns_cache_entry(hash, Bytes::from(resp.to_vec().unwrap()), ResponseCode::NoError, Arc::from(TEST_UPSTREAM),
+            None, "burst.example.com", Arc::from("default"), RecordType::A, DNSClass::IN, 0, 0);
+        let within = lookup();
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        let after = lookup();
+
+        // Assert
+        assert_eq!(micro, Duration::from_millis(300));
+        assert_eq!(ddns, Duration::ZERO);
+        assert_eq!(engine.cache_lifetime("default", CacheTtl::Answer(60), Duration::from_secs(5)), Duration::from_secs(60));
+        assert!(within.is_some());
+        assert!(after.is_none());
+    }
+
+    #[tokio::test]
+    async fn servfail_is_not_cached_when_servfail_ttl_is_zero() {
+        // Arrange: min_ttl and the zero_ttl micro-cache would both cache a TTL 0 answer
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let raw = serde_json::json!({
+            "settings": { "min_ttl": 30, "zero_ttl": { "type": "micro_cache", "ms": 1000 } },
+            "pipelines": [ { "id": "p", "rules": [ { "name": "fwd", "matchers": [ { "type": "any" } ],
+                "actions": [ { "type": "forward", "upstream": "mock://rcode=servfail" } ] } ] } ]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+        let engine = Engine::new(RuntimePipelineConfig::from_config(cfg).expect("runtime"), "lbl".to_string());
+        let mut query = Message::new();
+        query.set_id(9).add_query(Query::query(Name::from_ascii("fail.test.").unwrap(), RecordType::A));
+        let peer: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+
+        // Act
+        let resp = engine.handle_packet(&query.to_vec().unwrap(), peer).await.expect("response");
+        engine.cache.run_pending_tasks();
+
+        // Assert
+        assert_eq!(Message::from_vec(&resp).unwrap().response_code(), ResponseCode::ServFail);
+        assert_eq!(engine.cache.iter().count(), 0);
+        assert_eq!(engine.cache_lifetime("p", engine.cache_ttl(&resp, ResponseCode::ServFail, 0), Duration::from_secs(30)), Duration::ZERO);
+    }
+
     #[tokio::test]
     async fn response_actions_allow_returns_upstream_on_match() {
         // Arrange: Build test engine and response context
@@ -1436,6 +2692,7 @@ mod tests {
             pipeline_id: "pipeline",
             rule_name: "rule",
             remaining_jumps: 10,
+            cache_hash: 0,
         };
         let result = apply_response_actions(ctx)
             .await
@@ -1484,6 +2741,7 @@ mod tests {
             pipeline_id: "pipeline",
             rule_name: "rule",
             remaining_jumps: 10,
+            cache_hash: 0,
         };
         let result = apply_response_actions(ctx)
             .await
@@ -1523,6 +2781,7 @@ mod tests {
             pipeline_id: "pipeline",
             rule_name: "rule",
             remaining_jumps: 10,
+            cache_hash: 0,
         };
         let result = apply_response_actions(ctx)
             .await
@@ -1570,20 +2829,23 @@ mod tests {
         let qname = "expire.com";
         let qtype = RecordType::A;
         let qclass = DNSClass::IN;
-        let dedupe_hash = Engine::calculate_cache_hash_for_dedupe(&pipeline_id, qname.as_bytes(), qtype, qclass);
+        let dedupe_hash = Engine::calculate_cache_hash_for_dedupe(&pipeline_id, qname.as_bytes(), qtype, qclass, 0);
 
         // Insert an entry that expired 5 seconds ago
         let entry = CacheEntry {
             bytes: Bytes::from_static(b"old_resp"),
+            compressed: false,
             rcode: ResponseCode::NoError,
             source: Arc::from("old_source"),
             upstream: None,
             qname: Arc::from(qname),
             pipeline_id: pipeline_id.clone(),
             qtype: u16::from(qtype),
+            qclass: u16::from(qclass),
             inserted_at: Instant::now() - Duration::from_secs(10),
             original_ttl: 5, // Expired 5 seconds ago
             refresh_ttl: 5,
+            aged: Default::default(),
         };
         engine.cache.insert(dedupe_hash, Arc::new(entry));
 
@@ -1603,6 +2865,390 @@ mod tests {
         assert!(engine.cache.get(&dedupe_hash).is_none(), "Cache entry should be removed after expiration check");
     }
 
+    #[tokio::test]
+    async fn test_slow_path_private_ptr_answer_is_counted_once() {
+        // Arrange: A private reverse lookup taking the full path, with a live query subscriber
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let engine = {
+            let mut runtime = build_test_engine().state.load().pipeline.clone();
+            runtime.settings.private_ptr.mode = crate::config::PrivatePtrMode::Nxdomain;
+            Engine::new(runtime, "lbl".to_string())
+        };
+        let mut events = engine.live_queries.subscribe();
+        let mut packet = vec![0u8; 12];
+        packet[5] = 1; // QDCOUNT
+        packet.extend_from_slice(b"\x011\x010\x03168\x03192\x07in-addr\x04arpa\x00\x00\x0c\x00\x01");
+        let peer = "127.0.0.1:12345".parse().unwrap();
+
+        // Act
+        let resp = engine.handle_packet(&packet, peer).await.unwrap();
+        let published = std::iter::from_fn(|| events.try_recv().ok()).count();
+
+        // Assert
+        assert_eq!(Message::from_vec(&resp).unwrap().response_code(), ResponseCode::NXDomain);
+        assert_eq!(published, 1);
+    }
+
+    #[tokio::test]
+    async fn test_non_in_qclass_policy_and_cache_validation() {
+        // Arrange: A refusing engine, and a forwarding engine holding an IN entry under the CH key
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let refusing = {
+            let mut runtime = build_test_engine().state.load().pipeline.clone();
+            runtime.settings.non_in_qclass = QclassPolicy::Refuse;
+            Engine::new(runtime, "lbl".to_string())
+        };
+        let forwarding = build_test_engine();
+        let ch_hash = Engine::calculate_cache_hash_for_dedupe("default", b"version.bind", RecordType::TXT, DNSClass::CH, 0);
+        forwarding.cache.insert(ch_hash, Arc::new(CacheEntry {
+            bytes: Bytes::from_static(b"in_resp"),
+            compressed: false,
+            rcode: ResponseCode::NoError,
+            source: Arc::from("test"),
+            upstream: None,
+            qname: Arc::from("version.bind"),
+            pipeline_id: Arc::from("default"),
+            qtype: u16::from(RecordType::TXT),
+            qclass: u16::from(DNSClass::IN),
+            inserted_at: Instant::now(),
+            original_ttl: 60,
+            refresh_ttl: 60,
+            aged: Default::default(),
+        }));
+        let mut packet = vec![0u8; 12];
+        packet[5] = 1; // QDCOUNT
+        packet.extend_from_slice(b"\x07version\x04bind\x00\x00\x10\x00\x03");
+        let peer = "127.0.0.1:12345".parse().unwrap();
+        let mut events = refusing.live_queries.subscribe();
+
+        // Act
+        let refused = refusing.handle_packet_fast(&packet, peer).unwrap();
+        let forwarded = forwarding.handle_packet_fast(&packet, peer).unwrap();
+        let _ = std::iter::from_fn(|| events.try_recv().ok()).count();
+        let slow_refused = refusing.handle_packet(&packet, peer).await.unwrap();
+        let slow_published = std::iter::from_fn(|| events.try_recv().ok()).count();
+
+        // Assert
+        match refused {
+            Some(FastPathResponse::Direct(resp)) => {
+                assert_eq!(Message::from_vec(&resp).unwrap().response_code(), ResponseCode::Refused);
+            }
+            other => panic!("expected a direct REFUSED answer, got {:?}", other),
+        }
+        assert!(matches!(forwarded, Some(FastPathResponse::AsyncNeeded { .. })));
+        assert_eq!(Message::from_vec(&slow_refused).unwrap().response_code(), ResponseCode::Refused);
+        assert_eq!(slow_published, 1, "the full path counts the rejection once");
+    }
+
+    #[tokio::test]
+    async fn test_multi_question_policy_answers_formerr() {
+        // Arrange: Two questions in one query
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let engine = {
+            let mut runtime = build_test_engine().state.load().pipeline.clone();
+            runtime.settings.multi_question = MultiQuestionPolicy::Formerr;
+            Engine::new(runtime, "lbl".to_string())
+        };
+        let mut packet = vec![0u8; 12];
+        packet[5] = 2; // QDCOUNT
+        packet.extend_from_slice(b"\x01a\x07example\x03com\x00\x00\x01\x00\x01");
+        packet.extend_from_slice(b"\x01b\xc0\x0e\x00\x01\x00\x01");
+        let peer = "127.0.0.1:12345".parse().unwrap();
+
+        // Act
+        let resp = engine.handle_packet_fast(&packet, peer).unwrap();
+        let default_resp = build_test_engine().handle_packet_fast(&packet, peer).unwrap();
+
+        // Assert
+        match resp {
+            Some(FastPathResponse::Direct(resp)) => {
+                assert_eq!(Message::from_vec(&resp).unwrap().response_code(), ResponseCode::FormErr);
+            }
+            other => panic!("expected a direct FORMERR answer, got {:?}", other),
+        }
+        assert!(matches!(default_resp, Some(FastPathResponse::AsyncNeeded { ref qname, .. }) if qname == "a.example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_fast_path_survives_malformed_packets() {
+        // Arrange: Deterministic mutations of a valid query plus pointer loops and reserved label types
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let engine = build_test_engine();
+        let peer = "127.0.0.1:12345".parse().unwrap();
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let valid: Vec<u8> = [
+            &[0xab, 0xcd, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01][..],
+            b"\x03www\x07example\x03com\x00\x00\x01\x00\x01",
+            b"\x00\x00\x29\x04\xd0\x00\x00\x80\x00\x00\x00",
+        ]
+        .concat();
+        let mut packets = vec![
+            // 自指针 / Self-pointer
+            [&valid[..12], b"\xc0\x0c\x00\x01\x00\x01"].concat(),
+            // 保留标签类型 / Reserved label types
+            [&valid[..12], b"\x41a\x00\x00\x01\x00\x01"].concat(),
+            [&valid[..12], b"\x81a\x00\x00\x01\x00\x01"].concat(),
+            // 越界指针与截断的标签 / Out-of-range pointer and truncated label
+            [&valid[..12], b"\xff\xff\x00\x01\x00\x01"].concat(),
+            [&valid[..12], b"\x3fabc"].concat(),
+        ];
+        for _ in 0..5000 {
+            let mut p = valid.clone();
+            for _ in 0..(next() % 4 + 1) {
+                let i = (next() as usize) % p.len();
+                p[i] = next() as u8;
+            }
+            p.truncate((next() as usize) % (valid.len() + 1));
+            packets.push(p);
+        }
+
+        // Act & Assert: Every packet is either parsed or rejected without panicking
+        for p in &packets {
+            let mut buf = [0u8; 256];
+            if let Some(q) = parse_quick(p, &mut buf) {
+                assert!(q.qname_bytes.len() <= 256);
+            }
+            let _ = engine.handle_packet_fast(p, peer);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_query_reply_per_listener() {
+        // Arrange: FORMERR on UDP, default drop on TCP
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let engine = {
+            let mut runtime = build_test_engine().state.load().pipeline.clone();
+            runtime.settings.malformed_query.udp = MalformedQueryReply::Formerr;
+            Engine::new(runtime, "lbl".to_string())
+        };
+        // 头部声明一个问题但问题段被截断 / Header claims one question but the question is truncated
+        let packet = [0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, b'w'];
+        let mut response = packet;
+        response[2] |= 0x80;
+        let peer = "127.0.0.1:12345".parse().unwrap();
+
+        // Act
+        let err = engine.handle_packet(&packet, peer).await.unwrap_err();
+        let udp = engine.malformed_reply(&packet, false).unwrap();
+        let tcp = engine.malformed_reply(&packet, true);
+        let to_response = engine.malformed_reply(&response, false);
+
+        // Assert
+        assert!(err.downcast_ref::<MalformedQuery>().is_some());
+        assert_eq!(udp.len(), 12);
+        assert_eq!(&udp[..2], &[0x12, 0x34]);
+        assert_eq!(udp[2], 0x81);
+        assert_eq!(udp[3] & 0x0F, u16::from(ResponseCode::FormErr) as u8);
+        assert!(tcp.is_none());
+        assert!(to_response.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_set_header_flags_rewrites_response_header() {
+        // Arrange: A validated-looking answer from an upstream without RA
+        let engine = build_test_engine();
+        let mut ctx = build_response_context();
+        ctx.msg.set_message_type(hickory_proto::op::MessageType::Response).set_authentic_data(true);
+        ctx.raw = Bytes::from(ctx.msg.to_vec().unwrap());
+        let req = Message::new();
+        let actions = [Action::SetHeaderFlags {
+            set: vec![crate::config::HeaderFlag::Ra, crate::config::HeaderFlag::Aa],
+            clear: vec![crate::config::HeaderFlag::Ad],
+        }];
+        let apply_ctx = crate::engine::rules::ApplyResponseActionsContext {
+            engine: &engine,
+            actions: &actions,
+            ctx_opt: Some(ctx),
+            req: &req,
+            packet: &[0u8],
+            upstream_timeout: Duration::from_secs(1),
+            response_matchers: &[],
+            qname: "example.com",
+            qtype: RecordType::A,
+            qclass: DNSClass::IN,
+            client_ip: "10.0.0.1".parse().unwrap(),
+            upstream_default: TEST_UPSTREAM,
+            pipeline_id: "pipeline",
+            rule_name: "rule",
+            remaining_jumps: 10,
+            cache_hash: 0,
+        };
+
+        // Act
+        let result = apply_response_actions(apply_ctx).await.unwrap();
+
+        // Assert
+        let ResponseActionResult::Upstream { ctx, .. } = result else {
+            panic!("expected the upstream response to continue");
+        };
+        let sent = Message::from_vec(&ctx.raw).unwrap();
+        assert!(sent.recursion_available() && sent.authoritative());
+        assert!(!sent.authentic_data());
+        assert_eq!(sent.answers(), ctx.msg.answers());
+        assert!(!ctx.msg.authentic_data());
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_rcode_rewrites_matching_response() {
+        // Arrange: A SERVFAIL from the upstream, and a config with a misspelled rcode
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let engine = build_test_engine();
+        let mut ctx = build_response_context();
+        ctx.msg.set_message_type(hickory_proto::op::MessageType::Response).set_response_code(ResponseCode::ServFail);
+        ctx.raw = Bytes::from(ctx.msg.to_vec().unwrap());
+        let req = Message::new();
+        let rewrite = [Action::RewriteRcode { from: "SERVFAIL".to_string(), to: "NXDOMAIN".to_string() }];
+        let no_match = [
+            Action::RewriteRcode { from: "REFUSED".to_string(), to: "NXDOMAIN".to_string() },
+            Action::SetHeaderFlags { set: vec![crate::config::HeaderFlag::Ra], clear: vec![] },
+        ];
+        let apply = |actions, ctx| crate::engine::rules::ApplyResponseActionsContext {
+            engine: &engine,
+            actions,
+            ctx_opt: Some(ctx),
+            req: &req,
+            packet: &[0u8],
+            upstream_timeout: Duration::from_secs(1),
+            response_matchers: &[],
+            qname: "example.com",
+            qtype: RecordType::A,
+            qclass: DNSClass::IN,
+            client_ip: "10.0.0.1".parse().unwrap(),
+            upstream_default: TEST_UPSTREAM,
+            pipeline_id: "pipeline",
+            rule_name: "rule",
+            remaining_jumps: 10,
+            cache_hash: 0,
+        };
+        let typo: crate::config::PipelineConfig = serde_json::from_value(serde_json::json!({
+            "pipelines": [ { "id": "p", "rules": [
+                { "name": "fix", "matchers": [ { "type": "any" } ], "actions": [ { "type": "forward", "upstream": "8.8.8.8:53" } ],
+                  "response_actions_on_match": [ { "type": "rewrite_rcode", "from": "SERVFAL", "to": "NODATA" } ] }
+            ] } ]
+        }))
+        .expect("parse");
+
+        // Act
+        let rewritten = apply_response_actions(apply(&rewrite, ctx.clone())).await.unwrap();
+        let fell_through = apply_response_actions(apply(&no_match, ctx)).await.unwrap();
+        let errors = crate::engine::validation::cross_reference_errors(&typo);
+
+        // Assert
+        let ResponseActionResult::Static { bytes, rcode, source } = rewritten else {
+            panic!("expected the rewritten response");
+        };
+        assert_eq!(rcode, ResponseCode::NXDomain);
+        assert_eq!(source, "rewrite_rcode");
+        assert_eq!(Message::from_vec(&bytes).unwrap().response_code(), ResponseCode::NXDomain);
+        let ResponseActionResult::Upstream { ctx, .. } = fell_through else {
+            panic!("expected a non-matching rewrite to fall through");
+        };
+        assert_eq!(ctx.msg.response_code(), ResponseCode::ServFail);
+        assert!(ctx.msg.recursion_available(), "later actions still run");
+        assert_eq!(
+            errors,
+            vec![
+                "pipeline p rule fix response_actions_on_match: invalid rcode SERVFAL".to_string(),
+                "pipeline p rule fix response_actions_on_match: invalid rcode NODATA".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_authority_matchers_distinguish_soa_nxdomain_from_junk() {
+        // Arrange: NXDOMAIN with an SOA, an empty NXDOMAIN, and a referral to a provider's nameservers
+        let zone = Name::from_str("example.com.").unwrap();
+        let mut genuine = Message::new();
+        genuine.set_response_code(ResponseCode::NXDomain);
+        genuine.add_name_server(Record::from_rdata(zone.clone(), 300, RData::SOA(hickory_proto::rr::rdata::SOA::new(
+            Name::from_str("ns1.example.com.").unwrap(),
+            Name::from_str("hostmaster.example.com.").unwrap(),
+            1, 3600, 600, 86400, 300,
+        ))));
+        let mut junk = Message::new();
+        junk.set_response_code(ResponseCode::NXDomain);
+        let mut referral = Message::new();
+        referral.add_name_server(Record::from_rdata(zone, 300, RData::NS(hickory_proto::rr::rdata::NS(
+            Name::from_str("NS-12.AwsDns-01.com.").unwrap(),
+        ))));
+        let cidr_files = Default::default();
+        let matcher = |m: serde_json::Value| {
+            RuntimeResponseMatcher::from_config(serde_json::from_value(m).unwrap(), &cidr_files).unwrap()
+        };
+        let has_soa = matcher(serde_json::json!({ "type": "response_authority_soa", "expect": true }));
+        let aws_ns = matcher(serde_json::json!({ "type": "response_authority_ns_suffix", "value": "awsdns-01.com." }));
+        let matches = |m: &RuntimeResponseMatcher, msg: &Message| {
+            m.matches(TEST_UPSTREAM, "www.example.com", RecordType::A, DNSClass::IN, msg, None, None)
+        };
+
+        // Act & Assert
+        assert!(matches(&has_soa, &genuine));
+        assert!(!matches(&has_soa, &junk));
+        assert!(matches(&aws_ns, &referral));
+        assert!(!matches(&aws_ns, &genuine));
+    }
+
+    #[tokio::test]
+    async fn test_large_tcp_answer_is_cached_once_and_truncated_for_udp() {
+        // Arrange: A 100-record answer fetched over TCP, and a TC=1 answer from a UDP exchange
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let engine = build_test_engine();
+        let query = |payload: u16| {
+            let mut msg = Message::new();
+            msg.set_id(0x4242).set_recursion_desired(true);
+            msg.add_query(Query::query(Name::from_str("big.example.com.").unwrap(), RecordType::A));
+            let mut edns = hickory_proto::op::Edns::new();
+            edns.set_max_payload(payload);
+            msg.set_edns(edns);
+            msg.to_vec().unwrap()
+        };
+        let mut answer = Message::from_vec(&query(1232)).unwrap();
+        answer.set_message_type(hickory_proto::op::MessageType::Response);
+        for i in 0..100u8 {
+            answer.add_answer(Record::from_rdata(
+                Name::from_str("big.example.com.").unwrap(),
+                300,
+                RData::A(hickory_proto::rr::rdata::A::new(192, 0, 2, i)),
+            ));
+        }
+        let full = Bytes::from(answer.to_vec().unwrap());
+        let mut tc = full.to_vec();
+        tc[2] |= 0x02;
+        let cache_hash = Engine::calculate_cache_hash_for_dedupe("default", b"big.example.com", RecordType::A, DNSClass::IN, 0);
+        let tc_hash = Engine::calculate_cache_hash_for_dedupe("default", b"tc.example.com", RecordType::A, DNSClass::IN, 0);
+        let insert = |hash, bytes| {
+            engine.insert_dns_cache_entry(hash, bytes, ResponseCode::NoError, Arc::from("tcp:1.1.1.1:53"), None,
+                "big.example.com", Arc::from("default"), RecordType::A, DNSClass::IN, 300, 300);
+        };
+        insert(cache_hash, full.clone());
+        insert(tc_hash, Bytes::from(tc));
+        let peer = "127.0.0.1:12345".parse().unwrap();
+
+        // Act
+        let cached = match engine.handle_packet_fast(&query(1232), peer).unwrap() {
+            Some(FastPathResponse::CacheHit { cached, .. }) => cached,
+            other => panic!("expected a cache hit, got {:?}", other),
+        };
+        let udp = crate::proto_utils::truncate_for_udp(&query(1232), &cached).unwrap();
+        let large_udp = crate::proto_utils::truncate_for_udp(&query(4096), &cached);
+
+        // Assert
+        assert_eq!(Message::from_vec(&cached).unwrap().answers().len(), 100);
+        let udp = Message::from_vec(&udp).unwrap();
+        assert!(udp.truncated());
+        assert_eq!(udp.queries(), answer.queries());
+        assert!(udp.answers().is_empty());
+        assert_eq!(udp.extensions().as_ref().map(|e| e.max_payload()), Some(crate::proto_utils::RESPONSE_UDP_PAYLOAD));
+        assert!(large_udp.is_none());
+        assert!(engine.cache.get(&tc_hash).is_none());
+    }
+
     #[test]
     fn test_rule_cache_entry_matches_respects_uses_client_ip() {
         // Arrange: Define test data with different IPs
@@ -1714,6 +3360,84 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_cached_answer_opt_follows_current_client() {
+        // Arrange: A cached answer carrying the upstream's OPT (4096 bytes, a cookie option)
+        let mut msg = Message::new();
+        msg.set_message_type(hickory_proto::op::MessageType::Response);
+        msg.add_query(hickory_proto::op::Query::query(
+            hickory_proto::rr::Name::from_ascii("example.com.").unwrap(),
+            RecordType::A,
+        ));
+        msg.add_answer(hickory_proto::rr::Record::from_rdata(
+            hickory_proto::rr::Name::from_ascii("example.com.").unwrap(),
+            60,
+            hickory_proto::rr::RData::A(hickory_proto::rr::rdata::A::new(192, 0, 2, 1)),
+        ));
+        let plain = msg.to_vec().unwrap();
+        let mut edns = hickory_proto::op::Edns::new();
+        edns.set_max_payload(4096);
+        edns.options_mut().insert(hickory_proto::rr::rdata::opt::EdnsOption::Unknown(10, vec![1; 8]));
+        msg.set_edns(edns);
+        let cached = msg.to_vec().unwrap();
+
+        // Act
+        let for_validator = crate::proto_utils::echo_client_opt(&cached, true, true).unwrap();
+        let for_legacy = crate::proto_utils::echo_client_opt(&cached, false, false).unwrap();
+        let added = crate::proto_utils::echo_client_opt(&plain, true, false).unwrap();
+        let unchanged = crate::proto_utils::echo_client_opt(&for_validator, true, true);
+
+        // Assert
+        let validator = Message::from_vec(&for_validator).unwrap();
+        let opt = validator.extensions().as_ref().unwrap();
+        assert_eq!(opt.max_payload(), crate::proto_utils::RESPONSE_UDP_PAYLOAD);
+        assert!(opt.dnssec_ok());
+        assert!(opt.options().as_ref().is_empty());
+        assert_eq!(validator.answers().len(), 1);
+        let legacy = Message::from_vec(&for_legacy).unwrap();
+        assert!(legacy.extensions().is_none());
+        assert_eq!(legacy.answers().len(), 1);
+        let added = Message::from_vec(&added).unwrap();
+        assert!(!added.extensions().as_ref().unwrap().dnssec_ok());
+        assert!(unchanged.is_none());
+    }
+
+    #[test]
+    fn test_cache_hash_separates_do_and_cd_queries() {
+        // Arrange: The same question sent plain, with DO and with CD
+        let build = |dnssec_ok: bool, checking_disabled: bool| {
+            let mut msg = Message::new();
+            msg.add_query(hickory_proto::op::Query::query(
+                hickory_proto::rr::Name::from_ascii("example.com.").unwrap(),
+                RecordType::A,
+            ));
+            msg.set_checking_disabled(checking_disabled);
+            let mut edns = hickory_proto::op::Edns::new();
+            edns.set_dnssec_ok(dnssec_ok);
+            msg.set_edns(edns);
+            msg.to_vec().unwrap()
+        };
+        let hash = |packet: &[u8]| {
+            let mut buf = [0u8; 256];
+            let q = parse_quick(packet, &mut buf).unwrap();
+            let hash = Engine::calculate_cache_hash_for_dedupe("default", q.qname_bytes, RecordType::A, DNSClass::IN, q.dnssec_flags);
+            (q.dnssec_flags, hash)
+        };
+
+        // Act
+        let (plain_flags, plain) = hash(&build(false, false));
+        let (do_flags, with_do) = hash(&build(true, false));
+        let (cd_flags, with_cd) = hash(&build(false, true));
+
+        // Assert
+        assert_eq!(plain_flags, 0);
+        assert_eq!(do_flags, crate::proto_utils::DNSSEC_FLAG_DO);
+        assert_eq!(cd_flags, crate::proto_utils::DNSSEC_FLAG_CD);
+        assert_ne!(plain, with_do);
+        assert_ne!(plain, with_cd);
+        assert_ne!(with_do, with_cd);
+    }
+
     /// 测试 DNS 响应缓存键包含 QCLASS
     #[test]
     fn test_cache_hash_includes_qclass() {
@@ -1729,13 +3453,15 @@ mod tests {
             pipeline_id, 
             qname.as_bytes(), 
             qtype, 
-            qclass_in
+            qclass_in,
+            0
         );
         let hash_ch = Engine::calculate_cache_hash_for_dedupe(
             pipeline_id, 
             qname.as_bytes(), 
             qtype, 
-            qclass_ch
+            qclass_ch,
+            0
         );
 
         // Assert: Verify different QCLASS produces different hashes
@@ -1750,7 +3476,8 @@ mod tests {
             pipeline_id, 
             qname.as_bytes(), 
             qtype, 
-            qclass_in
+            qclass_in,
+            0
         );
 
         // Assert: Verify same QCLASS produces same hash
@@ -1781,19 +3508,22 @@ mod tests {
             pipeline_id, 
             qname_lower.to_lowercase().as_bytes(), 
             qtype, 
-            qclass
+            qclass,
+            0
         );
         let hash2 = Engine::calculate_cache_hash_for_dedupe(
             pipeline_id, 
             qname_upper.to_lowercase().as_bytes(), 
             qtype, 
-            qclass
+            qclass,
+            0
         );
         let hash3 = Engine::calculate_cache_hash_for_dedupe(
             pipeline_id, 
             qname_mixed.to_lowercase().as_bytes(), 
             qtype, 
-            qclass
+            qclass,
+            0
         );
 
         // Assert: Verify case-insensitive hashing produces same results
@@ -1824,13 +3554,15 @@ mod tests {
             pipeline_id, 
             qname.as_bytes(), 
             qtype_a, 
-            qclass
+            qclass,
+            0
         );
         let hash_aaaa = Engine::calculate_cache_hash_for_dedupe(
             pipeline_id, 
             qname.as_bytes(), 
             qtype_aaaa, 
-            qclass
+            qclass,
+            0
         );
 
         // Assert: Verify different QTYPE produces different hashes
@@ -1856,13 +3588,15 @@ mod tests {
             pipeline_id, 
             qname1.as_bytes(), 
             qtype, 
-            qclass
+            qclass,
+            0
         );
         let hash2 = Engine::calculate_cache_hash_for_dedupe(
             pipeline_id, 
             qname2.as_bytes(), 
             qtype, 
-            qclass
+            qclass,
+            0
         );
 
         // Assert: Verify different QNAME produces different hashes
@@ -1956,11 +3690,225 @@ mod tests {
             _ => panic!("应该返回 Static 决策"),
         }
     }
-}
 
+    #[tokio::test]
+    async fn test_edns_options_filtered_per_pipeline_policy() {
+        // Arrange: Messages carrying ECS (8), COOKIE (10) and EDE (15) options
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(serde_json::json!({
+            "settings": { "default_upstream": TEST_UPSTREAM },
+            "pipelines": [
+                { "id": "default", "rules": [] },
+                { "id": "geo", "edns_options": { "to_upstream": [8], "to_client": [8, 15] }, "rules": [] }
+            ]
+        }))
+        .unwrap();
+        let engine = Engine::new(RuntimePipelineConfig::from_config(cfg).unwrap(), "lbl".to_string());
+        let with_options = |response: bool| {
+            let mut msg = Message::new();
+            msg.add_query(hickory_proto::op::Query::query(Name::from_ascii("example.com.").unwrap(), hickory_proto::rr::RecordType::A));
+            msg.set_message_type(if response { hickory_proto::op::MessageType::Response } else { hickory_proto::op::MessageType::Query });
+            let mut packet = msg.to_vec().unwrap();
+            packet[11] = 1;
+            let options: &[u8] = &[
+                0, 8, 0, 7, 0, 1, 24, 0, 192, 0, 2, // ECS 192.0.2.0/24
+                0, 10, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8, // client cookie
+                0, 15, 0, 2, 0, 3, // EDE "stale answer"
+            ];
+            packet.extend_from_slice(&[0, 0, 41, 0x04, 0xd0, 0, 0, 0, 0, 0, options.len() as u8]);
+            packet.extend_from_slice(options);
+            packet
+        };
+        let option_codes = |packet: &[u8]| -> Vec<u16> {
+            let msg = Message::from_vec(packet).unwrap();
+            let mut codes: Vec<u16> = msg.extensions().as_ref().unwrap().options().as_ref().keys().map(|c| u16::from(*c)).collect();
+            codes.sort_unstable();
+            codes
+        };
+
+        // Act
+        let default_query = engine.upstream_query("default", &with_options(false)).into_owned();
+        let geo_query = engine.upstream_query("geo", &with_options(false)).into_owned();
+        let default_answer = engine.transform_upstream_response("default", Bytes::from(with_options(true)));
+        let geo_answer = engine.transform_upstream_response("geo", Bytes::from(with_options(true)));
+
+        // Assert
+        assert_eq!(option_codes(&default_query), Vec::<u16>::new());
+        assert_eq!(option_codes(&geo_query), vec![8]);
+        assert_eq!(option_codes(&default_answer), vec![15]);
+        assert_eq!(option_codes(&geo_answer), vec![8, 15]);
+    }
+
+    #[tokio::test]
+    async fn test_outstanding_queries_capped_without_flow_control() {
+        // Arrange
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let config = |limit: usize| -> RuntimePipelineConfig {
+            let cfg: crate::config::PipelineConfig = serde_json::from_value(serde_json::json!({
+                "settings": {
+                    "default_upstream": TEST_UPSTREAM,
+                    "max_outstanding_queries": limit,
+                    "overload_reply": "servfail"
+                },
+                "pipelines": [{ "id": "default", "rules": [] }]
+            }))
+            .unwrap();
+            RuntimePipelineConfig::from_config(cfg).unwrap()
+        };
+        let engine = Engine::new(config(2), "lbl".to_string());
+        let mut query = Message::new();
+        query.set_id(0x1234);
+        query.add_query(hickory_proto::op::Query::query(Name::from_ascii("example.com.").unwrap(), hickory_proto::rr::RecordType::A));
+        let packet = query.to_vec().unwrap();
+
+        // Act
+        let held = [engine.permit_manager.try_acquire(), engine.permit_manager.try_acquire()];
+        let over_limit = engine.permit_manager.try_acquire();
+        let reply = engine.overload_reply(&packet).unwrap();
+        engine.reload(config(0));
+        let after_reload = engine.permit_manager.try_acquire();
+
+        // Assert
+        assert!(held.iter().all(Option::is_some));
+        assert!(over_limit.is_none());
+        let reply = Message::from_vec(&reply).unwrap();
+        assert_eq!((reply.id(), reply.response_code()), (0x1234, ResponseCode::ServFail));
+        assert!(after_reload.is_some());
+    }
+
+    #[test]
+    fn test_pipeline_selected_by_listener_transport() {
+        // Arrange
+        let raw = serde_json::json!({
+            "pipelines": [ { "id": "plain", "rules": [] }, { "id": "encrypted", "rules": [] } ],
+            "pipeline_select": [
+                { "pipeline": "encrypted", "matchers": [ { "type": "transport", "value": "dot" } ] }
+            ]
+        });
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(raw).expect("parse");
+        let runtime = RuntimePipelineConfig::from_config(cfg).expect("runtime");
+        let select = |transport: ListenerTransport| {
+            select_pipeline(
+                &runtime,
+                "example.com",
+                "127.0.0.1".parse().unwrap(),
+                hickory_proto::rr::DNSClass::IN,
+                false,
+                hickory_proto::rr::RecordType::A,
+                "lbl",
+                transport,
+                None,
+                None,
+            )
+            .1
+        };
 
+        // Act
+        let selected = [ListenerTransport::Udp, ListenerTransport::Tcp, ListenerTransport::Dot].map(select);
 
+        // Assert
+        assert_eq!(selected.map(|id| id.to_string()), ["plain", "plain", "encrypted"]);
+    }
 
+    #[tokio::test]
+    async fn test_aaaa_answered_with_nodata_while_ipv6_is_unreachable() {
+        // Arrange: An unusable probe address makes IPv6 count as unreachable
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let engine = {
+            let mut runtime = build_test_engine().state.load().pipeline.clone();
+            runtime.settings.suppress_aaaa_without_ipv6 = true;
+            runtime.settings.ipv6_probe_addr = "unreachable".to_string();
+            Engine::new(runtime, "lbl".to_string())
+        };
+        let query = |qtype: u8| {
+            let mut packet = vec![0u8; 12];
+            packet[5] = 1; // QDCOUNT
+            packet.extend_from_slice(b"\x07example\x03com\x00\x00");
+            packet.extend_from_slice(&[qtype, 0, 1]);
+            packet
+        };
+        let peer = "127.0.0.1:12345".parse().unwrap();
+
+        // Act
+        let aaaa = engine.handle_packet_fast(&query(28), peer).unwrap();
+        let a = engine.handle_packet_fast(&query(1), peer).unwrap();
+        let mut events = engine.live_queries.subscribe();
+        let slow_aaaa = engine.handle_packet(&query(28), peer).await.unwrap();
+        let slow_published = std::iter::from_fn(|| events.try_recv().ok()).count();
+
+        // Assert
+        match aaaa {
+            Some(FastPathResponse::Direct(resp)) => {
+                let msg = Message::from_vec(&resp).unwrap();
+                assert_eq!(msg.response_code(), ResponseCode::NoError);
+                assert!(msg.answers().is_empty());
+            }
+            other => panic!("expected a direct NODATA answer, got {:?}", other),
+        }
+        assert!(matches!(a, Some(FastPathResponse::AsyncNeeded { .. })));
+        assert_eq!(engine.ipv6_probe.last(), Some(false));
+        assert!(Message::from_vec(&slow_aaaa).unwrap().answers().is_empty());
+        assert_eq!(slow_published, 1, "the full path counts the NODATA answer once");
+    }
+
+    #[test]
+    fn test_svc_param_matcher_inspects_https_records() {
+        // Arrange: An HTTPS record advertising h2/h3 with an IPv4 hint
+        use hickory_proto::rr::rdata::svcb::{Alpn, IpHint, SVCB, SvcParamKey, SvcParamValue};
+        let svcb = SVCB::new(1, Name::root(), vec![
+            (SvcParamKey::Alpn, SvcParamValue::Alpn(Alpn(vec!["h2".to_string(), "h3".to_string()]))),
+            (SvcParamKey::Ipv4Hint, SvcParamValue::Ipv4Hint(IpHint(vec![hickory_proto::rr::rdata::A::new(192, 0, 2, 1)]))),
+        ]);
+        let mut msg = Message::new();
+        msg.add_answer(Record::from_rdata(
+            Name::from_str("www.example.com.").unwrap(),
+            300,
+            RData::HTTPS(hickory_proto::rr::rdata::HTTPS(svcb)),
+        ));
+        let cidr_files = Default::default();
+        let matcher = |m: serde_json::Value| {
+            RuntimeResponseMatcher::from_config(serde_json::from_value(m).unwrap(), &cidr_files).unwrap()
+        };
+        let matches = |m: serde_json::Value| {
+            matcher(m).matches(TEST_UPSTREAM, "www.example.com", RecordType::HTTPS, DNSClass::IN, &msg, None, None)
+        };
+
+        // Act & Assert
+        assert!(matches(serde_json::json!({ "type": "response_svc_param", "key": "alpn", "value": "h3" })));
+        assert!(!matches(serde_json::json!({ "type": "response_svc_param", "key": "alpn", "value": "http/1.1" })));
+        assert!(matches(serde_json::json!({ "type": "response_svc_param", "key": "ipv4hint", "value": "192.0.2.0/24" })));
+        assert!(!matches(serde_json::json!({ "type": "response_svc_param", "key": "ipv6hint" })));
+        assert!(!matches(serde_json::json!({ "type": "response_svc_param", "key": "ech" })));
+        assert!(RuntimeResponseMatcher::from_config(
+            serde_json::from_value(serde_json::json!({ "type": "response_svc_param", "key": "ech", "value": "x" })).unwrap(),
+            &cidr_files,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_txt_content_matcher_finds_substrings() {
+        // Arrange: A TXT answer carrying a tracking token
+        let mut msg = Message::new();
+        msg.add_answer(Record::from_rdata(
+            Name::from_str("beacon.example.com.").unwrap(),
+            60,
+            RData::TXT(hickory_proto::rr::rdata::TXT::new(vec!["v=1 id=track-42 ts=1700000000".to_string()])),
+        ));
+        let matcher = |mode: &str, value: &str| {
+            let m = serde_json::json!({ "type": "response_txt_content", "mode": mode, "value": value });
+            RuntimeResponseMatcher::from_config(serde_json::from_value(m).unwrap(), &Default::default()).unwrap()
+        };
+        let matches = |m: RuntimeResponseMatcher| {
+            m.matches(TEST_UPSTREAM, "beacon.example.com", RecordType::TXT, DNSClass::IN, &msg, None, None)
+        };
+
+        // Act & Assert
+        assert!(matches(matcher("contains", "id=track-")));
+        assert!(!matches(matcher("contains", "id=other")));
+        assert!(!matches(matcher("prefix", "id=track-")));
+    }
+}
 
 // Multi-upstream parsing tests / 多上游解析测试
 // Tests for parsing and validating multi-upstream configurations
@@ -0,0 +1,163 @@
+//! 双栈上游的 Happy Eyeballs 连接 / Happy Eyeballs connections for dual-stack upstreams
+//!
+//! 上游主机名同时解析出 A 和 AAAA 时，按 RFC 8305 交替排列两个地址族，
+//! 每隔 250ms 发起下一个连接尝试，先成功者胜出；并按上游记住胜出的地址族，下次优先使用。
+//! DoH 由 reqwest/hyper 的连接器自行处理 Happy Eyeballs，不经过此模块。
+//! When an upstream host name resolves to both A and AAAA, addresses of the two families
+//! are interleaved per RFC 8305 and a new connection attempt starts every 250ms; the first
+//! to succeed wins, and the winning family is remembered per upstream and tried first next
+//! time. DoH relies on the reqwest/hyper connector's own Happy Eyeballs and skips this module.
+//!
+//! 调用方（TCP/DoT 连接池）按引擎的 `tcp_fast_open` 传入 fast_open，启用后出站连接设置
+//! TCP_FASTOPEN_CONNECT，首个写入（DNS 查询或 TLS ClientHello）随 SYN 发送。
+//! Callers (the TCP/DoT pools) pass fast_open from their engine's `tcp_fast_open`; when set,
+//! outbound connections use TCP_FASTOPEN_CONNECT so the first write (the DNS query or TLS
+//! ClientHello) rides in the SYN.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::task::JoinSet;
+
+/// RFC 8305 推荐的连接尝试间隔 / Connection Attempt Delay recommended by RFC 8305
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// 每个上游胜出的地址族（true 为 IPv6）/ Winning address family per upstream (true = IPv6)
+static PREFERRED_FAMILY: LazyLock<DashMap<String, bool>> = LazyLock::new(DashMap::new);
+
+/// 连接单个地址 / Connect to a single address
+async fn connect_addr(addr: SocketAddr, fast_open: bool) -> io::Result<TcpStream> {
+    if !fast_open {
+        return TcpStream::connect(addr).await;
+    }
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    // 系统不支持时退化为普通连接 / Falls back to a regular connect when unsupported
+    if let Err(e) = crate::socket_utils::set_tcp_fastopen_connect(&socket2::SockRef::from(&socket), true) {
+        tracing::debug!(error = %e, "tcp fast open unavailable for outbound connection");
+    }
+    socket.connect(addr).await
+}
+
+/// 记住上游胜出的地址 / Remember the winning address for an upstream
+#[inline]
+pub fn remember(upstream: &str, addr: SocketAddr) {
+    let is_v6 = addr.is_ipv6();
+    if PREFERRED_FAMILY.get(upstream).map(|v| *v) != Some(is_v6) {
+        PREFERRED_FAMILY.insert(upstream.to_string(), is_v6);
+    }
+}
+
+/// 按偏好交替排列地址族 / Interleave address families by preference
+///
+/// 优先使用记住的地址族；没有记录时使用 prefer_v6_by_default 决定的地址族。
+/// Uses the remembered family first; falls back to prefer_v6_by_default when none is recorded.
+pub fn order_addrs(upstream: &str, addrs: Vec<SocketAddr>, prefer_v6_by_default: bool) -> Vec<SocketAddr> {
+    let prefer_v6 = PREFERRED_FAMILY
+        .get(upstream)
+        .map(|v| *v)
+        .unwrap_or(prefer_v6_by_default);
+    let (preferred, other): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv6() == prefer_v6);
+
+    let mut out = Vec::with_capacity(preferred.len() + other.len());
+    let mut p = preferred.into_iter();
+    let mut o = other.into_iter();
+    loop {
+        match (p.next(), o.next()) {
+            (None, None) => break,
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+    out
+}
+
+/// 解析并排序上游地址 / Resolve and order upstream addresses
+pub async fn resolve(upstream: &str, prefer_v6_by_default: bool) -> io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host(upstream).await?.collect();
+    if addrs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "upstream resolved to no addresses"));
+    }
+    Ok(order_addrs(upstream, addrs, prefer_v6_by_default))
+}
+
+/// 以 Happy Eyeballs 方式建立 TCP 连接 / Establish a TCP connection with Happy Eyeballs
+pub async fn connect_tcp(upstream: &str, fast_open: bool) -> io::Result<TcpStream> {
+    let addrs = resolve(upstream, true).await?;
+    if addrs.len() == 1 {
+        return connect_addr(addrs[0], fast_open).await;
+    }
+
+    let mut pending = addrs.into_iter().peekable();
+    let mut attempts = JoinSet::new();
+    let mut last_err: Option<io::Error> = None;
+    loop {
+        if let Some(addr) = pending.next() {
+            attempts.spawn(async move { (addr, connect_addr(addr, fast_open).await) });
+        }
+        if attempts.is_empty() {
+            break;
+        }
+        let has_more = pending.peek().is_some();
+        // 等待任一尝试结束；还有剩余地址时最多等待一个尝试间隔
+        // Wait for any attempt to finish; with addresses left, wait at most one attempt delay
+        tokio::select! {
+            res = attempts.join_next() => match res {
+                Some(Ok((addr, Ok(stream)))) => {
+                    remember(upstream, addr);
+                    return Ok(stream);
+                }
+                Some(Ok((_, Err(e)))) => last_err = Some(e),
+                Some(Err(e)) => last_err = Some(io::Error::other(e)),
+                None => break,
+            },
+            _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if has_more => {}
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::other("all connection attempts failed")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_addrs_interleaves_and_remembers_family() {
+        // Arrange
+        let v4a: SocketAddr = "192.0.2.1:853".parse().unwrap();
+        let v4b: SocketAddr = "192.0.2.2:853".parse().unwrap();
+        let v6a: SocketAddr = "[2001:db8::1]:853".parse().unwrap();
+        let addrs = vec![v4a, v4b, v6a];
+
+        // Act
+        let default_order = order_addrs("he-test.example:853", addrs.clone(), true);
+        remember("he-test.example:853", v4b);
+        let remembered_order = order_addrs("he-test.example:853", addrs, true);
+
+        // Assert
+        assert_eq!(default_order, vec![v6a, v4a, v4b]);
+        assert_eq!(remembered_order, vec![v4a, v6a, v4b]);
+    }
+
+    #[tokio::test]
+    async fn test_connect_tcp_reaches_local_listener() {
+        // Arrange
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Act
+        let stream = connect_tcp(&addr.to_string(), false).await;
+        let fast_open_stream = connect_tcp(&addr.to_string(), true).await;
+
+        // Assert
+        assert!(stream.is_ok());
+        assert!(fast_open_stream.is_ok());
+    }
+}
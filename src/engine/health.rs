@@ -0,0 +1,168 @@
+//! 存活与就绪状态 / Liveness and readiness state
+//!
+//! 管理接口的 `/healthz` 只表示进程仍在响应；`/readyz` 要求配置已编译、DNS 监听已绑定，且最近至少有一个
+//! 上游可用：从未出现上游失败、最近一次成功晚于最近一次失败，或成功发生在配置的时间窗口内。
+//! 可选的自查询探测经由本机 UDP 监听器解析一个域名，覆盖从 socket 到上游的完整路径。
+//! The admin `/healthz` only says the process still answers; `/readyz` requires the config
+//! to be compiled, the DNS listeners to be bound and at least one upstream to be usable
+//! recently: no upstream failure yet, the last success newer than the last failure, or a
+//! success within the configured window. The optional self-query probe resolves a name
+//! through this process's own UDP listener, covering the whole path from socket to upstream.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use crypto_box::aead::OsRng;
+use crypto_box::aead::rand_core::RngCore;
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use tokio::net::UdpSocket;
+
+/// 进程内的健康状态 / In-process health state
+#[derive(Debug)]
+pub struct Health {
+    started: Instant,
+    listeners_bound: AtomicBool,
+    /// 自启动起的毫秒数加 1，0 表示从未发生 / Milliseconds since start plus one, 0 meaning never
+    last_upstream_success: AtomicU64,
+    last_upstream_failure: AtomicU64,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            listeners_bound: AtomicBool::new(false),
+            last_upstream_success: AtomicU64::new(0),
+            last_upstream_failure: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    fn now_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64 + 1
+    }
+
+    /// DNS 监听器已绑定 / The DNS listeners are bound
+    pub fn mark_listeners_bound(&self) {
+        self.listeners_bound.store(true, Ordering::Release);
+    }
+
+    pub fn listeners_bound(&self) -> bool {
+        self.listeners_bound.load(Ordering::Acquire)
+    }
+
+    /// 记录一次上游调用结果 / Record the outcome of an upstream call
+    #[inline]
+    pub fn record_upstream(&self, ok: bool) {
+        let slot = if ok {
+            &self.last_upstream_success
+        } else {
+            &self.last_upstream_failure
+        };
+        slot.store(self.now_ms(), Ordering::Relaxed);
+    }
+
+    /// 最近是否至少有一个上游可用 / Whether at least one upstream was usable recently
+    pub fn upstream_healthy(&self, window: Duration) -> bool {
+        let success = self.last_upstream_success.load(Ordering::Relaxed);
+        let failure = self.last_upstream_failure.load(Ordering::Relaxed);
+        failure == 0
+            || success >= failure
+            || (success != 0 && self.now_ms().saturating_sub(success) <= window.as_millis() as u64)
+    }
+}
+
+/// 探测目标：未指定地址改用回环地址 / Probe target: unspecified addresses become loopback
+fn probe_target(bind: SocketAddr) -> SocketAddr {
+    match bind.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), bind.port()),
+        IpAddr::V6(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), bind.port()),
+        _ => bind,
+    }
+}
+
+/// 经由本机 UDP 监听器解析 `domain`，NOERROR 或 NXDOMAIN 视为成功，返回响应码
+/// Resolve `domain` through this process's UDP listener; NOERROR or NXDOMAIN count as
+/// success, and the response code is returned
+pub async fn self_query(bind: SocketAddr, domain: &str, timeout: Duration) -> anyhow::Result<ResponseCode> {
+    let target = probe_target(bind);
+    let local: SocketAddr = if target.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let id = OsRng.next_u32() as u16;
+    let mut msg = Message::new();
+    msg.set_id(id)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true)
+        .add_query(Query::query(Name::from_ascii(domain).context("probe domain")?, RecordType::A));
+    let packet = msg.to_vec()?;
+
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(target).await?;
+    socket.send(&packet).await?;
+    let mut buf = [0u8; 4096];
+    let resp = tokio::time::timeout(timeout, async {
+        loop {
+            let n = socket.recv(&mut buf).await?;
+            if let Ok(resp) = Message::from_vec(&buf[..n])
+                && resp.id() == id
+            {
+                return anyhow::Ok(resp);
+            }
+        }
+    })
+    .await
+    .context("probe timed out")??;
+    match resp.response_code() {
+        rcode @ (ResponseCode::NoError | ResponseCode::NXDomain) => Ok(rcode),
+        rcode => anyhow::bail!("probe answered {}", rcode),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_upstream_health_and_self_query() {
+        // Arrange: A listener that answers every query with NXDOMAIN
+        let health = Health::new();
+        let window = Duration::from_secs(60);
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let bind = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (n, peer) = server.recv_from(&mut buf).await.unwrap();
+            let mut resp = Message::from_vec(&buf[..n]).unwrap();
+            resp.set_message_type(MessageType::Response).set_response_code(ResponseCode::NXDomain);
+            server.send_to(&resp.to_vec().unwrap(), peer).await.unwrap();
+        });
+
+        // Act
+        let fresh = health.upstream_healthy(window);
+        health.record_upstream(false);
+        let failed = health.upstream_healthy(window);
+        health.record_upstream(true);
+        health.record_upstream(false);
+        let recent_success = health.upstream_healthy(window);
+        let probe = self_query(bind, "probe.example.", Duration::from_secs(2)).await.unwrap();
+
+        // Assert
+        assert!(fresh);
+        assert!(!failed);
+        assert!(recent_success);
+        assert_eq!(probe, ResponseCode::NXDomain);
+    }
+}
@@ -0,0 +1,101 @@
+//! IPv6 连通性探测 / IPv6 connectivity probe
+//!
+//! 对 `ipv6_probe_addr` 做 UDP connect（不发送数据），没有 IPv6 路由时失败。结果复用一分钟，
+//! 过期后由下一次使用者重新探测，因此网络恢复 IPv6 后依赖它的功能会自动恢复。首次探测前视为连通。
+//! UDP-connects to `ipv6_probe_addr` (nothing is sent), which fails without an IPv6 route. The
+//! result is reused for a minute and re-probed by the next caller after that, so features that
+//! depend on it recover by themselves once IPv6 returns. IPv6 counts as reachable until the
+//! first probe.
+
+use std::net::{Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+/// 探测结果的复用时间 / How long a probe result is reused
+const PROBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// IPv6 连通性探测器 / IPv6 connectivity prober
+#[derive(Debug)]
+pub struct Ipv6Probe {
+    started: Instant,
+    /// 最近一次探测相对 started 的毫秒数加一，0 表示尚未探测
+    /// Milliseconds since `started` plus one at the last probe, 0 before the first probe
+    probed_at_ms: AtomicU64,
+    reachable: AtomicBool,
+}
+
+impl Default for Ipv6Probe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ipv6Probe {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            probed_at_ms: AtomicU64::new(0),
+            reachable: AtomicBool::new(true),
+        }
+    }
+
+    /// IPv6 是否连通，结果过期时重新探测 / Whether IPv6 is reachable, re-probing once the result expires
+    pub fn reachable(&self, probe_addr: &str) -> bool {
+        let now = self.started.elapsed().as_millis() as u64 + 1;
+        let last = self.probed_at_ms.load(Ordering::Relaxed);
+        if last != 0 && now - last < PROBE_INTERVAL.as_millis() as u64 {
+            return self.reachable.load(Ordering::Relaxed);
+        }
+        // 只有一个调用者执行探测，其余沿用上次结果 / Only one caller probes, the others keep the last result
+        if self
+            .probed_at_ms
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return self.reachable.load(Ordering::Relaxed);
+        }
+        let reachable = probe(probe_addr);
+        if self.reachable.swap(reachable, Ordering::Relaxed) != reachable || last == 0 {
+            info!(event = "ipv6_probe", probe_addr = %probe_addr, reachable, "ipv6 connectivity changed");
+        }
+        reachable
+    }
+
+    /// 最近一次探测结果，尚未探测时为 None / Last probe result, None before the first probe
+    pub fn last(&self) -> Option<bool> {
+        (self.probed_at_ms.load(Ordering::Relaxed) != 0).then(|| self.reachable.load(Ordering::Relaxed))
+    }
+}
+
+fn probe(probe_addr: &str) -> bool {
+    let Ok(addr) = probe_addr.parse::<SocketAddr>() else {
+        return false;
+    };
+    UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))
+        .and_then(|socket| socket.connect(addr))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_result_is_reused_until_it_expires() {
+        // Arrange
+        let probe = Ipv6Probe::new();
+        let before = probe.last();
+
+        // Act
+        let first = probe.reachable("not-an-address");
+        let reused = probe.reachable("[::1]:53");
+
+        // Assert
+        assert_eq!(before, None);
+        assert!(!first);
+        assert!(!reused);
+        assert_eq!(probe.last(), Some(false));
+    }
+}
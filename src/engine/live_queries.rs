@@ -0,0 +1,175 @@
+//! 实时查询事件流 / Live query event stream
+//!
+//! 每个应答发往客户端时发布一条查询事件，管理接口以 SSE 推送给订阅者，可用于实时查询日志面板而无需轮询。
+//! 没有订阅者时仅做一次计数判断；订阅者各自按客户端网段、域名后缀与采样率过滤，
+//! 跟不上时丢弃最旧的事件而不阻塞查询处理。
+//! A query event is published for every answer sent to a client and pushed to subscribers
+//! by the admin API over SSE, for real-time query log dashboards without polling. With no
+//! subscribers publishing costs a single count check; each subscriber filters by client
+//! network, domain suffix and sample rate, and a subscriber that falls behind loses the
+//! oldest events instead of blocking query handling.
+
+use std::net::IpAddr;
+
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::RecordType;
+use ipnet::IpNet;
+use serde_json::{Value, json};
+use tokio::sync::broadcast;
+
+use super::privacy::Privacy;
+
+/// 订阅者缓冲的事件数 / Events buffered per subscriber
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// 一次已应答的查询 / One answered query
+#[derive(Debug, Clone)]
+pub struct QueryEvent {
+    /// Unix 毫秒 / Unix milliseconds
+    pub timestamp_ms: u64,
+    pub client: IpAddr,
+    pub qname: String,
+    pub qtype: u16,
+    pub rcode: ResponseCode,
+    /// 是否由快速路径（缓存或静态规则）直接应答 / Whether the fast path (cache or static rule) answered directly
+    pub fast_path: bool,
+}
+
+impl QueryEvent {
+    /// 输出为 JSON，客户端地址按隐私配置处理 / Render as JSON with the client address per the privacy settings
+    pub fn to_json(&self, privacy: &Privacy) -> Value {
+        json!({
+            "timestamp_ms": self.timestamp_ms,
+            "client": privacy.client(self.client).to_string(),
+            "qname": self.qname,
+            "qtype": RecordType::from(self.qtype).to_string(),
+            "rcode": format!("{:?}", self.rcode),
+            "fast_path": self.fast_path,
+        })
+    }
+}
+
+/// 订阅者的过滤条件 / Subscriber filter
+#[derive(Debug, Clone)]
+pub struct QueryFilter {
+    pub client: Option<IpNet>,
+    /// 域名后缀（含自身）/ Domain suffix (including the domain itself)
+    pub domain: Option<String>,
+    /// 每 N 条匹配事件取 1 条（1 表示全部）/ Keep 1 of every N matching events (1 keeps all)
+    pub sample: u64,
+}
+
+impl Default for QueryFilter {
+    fn default() -> Self {
+        Self {
+            client: None,
+            domain: None,
+            sample: 1,
+        }
+    }
+}
+
+impl QueryFilter {
+    pub fn matches(&self, event: &QueryEvent) -> bool {
+        if let Some(net) = &self.client
+            && !net.contains(&event.client)
+        {
+            return false;
+        }
+        if let Some(domain) = &self.domain {
+            let qname = event.qname.trim_end_matches('.');
+            let domain = domain.trim_end_matches('.');
+            let is_sub = qname.len() > domain.len()
+                && qname.as_bytes()[qname.len() - domain.len()..].eq_ignore_ascii_case(domain.as_bytes())
+                && qname.as_bytes()[qname.len() - domain.len() - 1] == b'.';
+            if !qname.eq_ignore_ascii_case(domain) && !is_sub {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// 查询事件广播 / Query event broadcast
+#[derive(Debug)]
+pub struct LiveQueries {
+    tx: broadcast::Sender<QueryEvent>,
+}
+
+impl Default for LiveQueries {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl LiveQueries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 是否有订阅者 / Whether anyone is subscribed
+    #[inline]
+    pub fn active(&self) -> bool {
+        self.tx.receiver_count() > 0
+    }
+
+    /// 发布事件（无订阅者时忽略）/ Publish an event (ignored without subscribers)
+    #[inline]
+    pub fn publish(&self, client: IpAddr, qname: &str, qtype: u16, rcode: ResponseCode, fast_path: bool) {
+        if !self.active() {
+            return;
+        }
+        let timestamp_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let _ = self.tx.send(QueryEvent {
+            timestamp_ms,
+            client,
+            qname: qname.to_string(),
+            qtype,
+            rcode,
+            fast_path,
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<QueryEvent> {
+        self.tx.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscriber_filters_by_client_and_domain() {
+        // Arrange
+        let live = LiveQueries::new();
+        let mut rx = live.subscribe();
+        let filter = QueryFilter {
+            client: Some("10.0.0.0/8".parse().unwrap()),
+            domain: Some("Example.com".to_string()),
+            sample: 1,
+        };
+        let client: IpAddr = "10.1.2.3".parse().unwrap();
+        let other: IpAddr = "192.168.1.1".parse().unwrap();
+
+        // Act
+        live.publish(client, "www.example.com.", 1, ResponseCode::NoError, true);
+        live.publish(client, "badexample.com.", 1, ResponseCode::NoError, false);
+        live.publish(other, "example.com.", 28, ResponseCode::NXDomain, false);
+        let kept: Vec<QueryEvent> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter(|e| filter.matches(e))
+            .collect();
+
+        // Assert
+        assert_eq!(kept.len(), 1);
+        let privacy = Privacy::new(&crate::config::PrivacySettings::default());
+        assert_eq!(kept[0].to_json(&privacy)["qname"], "www.example.com.");
+        assert_eq!(kept[0].to_json(&privacy)["qtype"], "A");
+        assert_eq!(kept[0].to_json(&privacy)["client"], "10.1.2.3");
+    }
+}
@@ -0,0 +1,297 @@
+//! 配置内的本地记录 / Local records declared in the configuration
+//!
+//! `local_records` 中的少量记录（如局域网设备的地址）无需外部区域文件，直接编译为按名称索引的
+//! 记录表，与私有地址反向解析、DDR 一样在本地应答。记录表随配置重载重新编译。
+//!
+//! 同名同类的多条记录全部返回，ANY 查询按 RFC 8482 只返回该名称第一个记录类型的记录集；`local_records_rotate` 启用时每次应答轮换顺序。设置了 probe_port 的
+//! A/AAAA 记录由后台任务定期做 TCP 连接探测，探测失败的地址在同名仍有其他可用地址时不返回，
+//! 相当于面向家庭实验室服务的简易 GSLB。
+//! Small record sets in `local_records` (such as the addresses of LAN devices) need no
+//! external zone file: they are compiled into a table indexed by name and answered locally,
+//! like private-space reverse lookups and DDR. The table is recompiled on every reload.
+//!
+//! All records of the queried name and type are returned, and ANY queries get only the
+//! RRset of the name's first record type (RFC 8482); records rotate when
+//! `local_records_rotate` is set. A/AAAA records with a probe_port get periodic TCP connect
+//! probes from a background task, and addresses failing them are left out while the name has
+//! other usable ones, a small GSLB for home-lab services.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::{A, AAAA, CNAME, MX, NS, PTR, SRV, TXT};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use rustc_hash::{FxBuildHasher, FxHashMap};
+use tokio::task::JoinSet;
+
+use super::Engine;
+use crate::config::LocalRecord;
+
+/// 跟随本地 CNAME 的最大层数 / Max local CNAME hops followed
+const MAX_CNAME_HOPS: usize = 8;
+/// 单次 TCP 探测的超时 / Timeout of one TCP probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 本地记录及其探测目标 / A local record and its probe target
+#[derive(Debug)]
+struct Entry {
+    record: Record,
+    probe: Option<SocketAddr>,
+}
+
+/// 按名称（小写、去掉末尾的点）索引的本地记录 / Local records indexed by name (lowercase, without the trailing dot)
+#[derive(Debug, Default)]
+pub struct LocalRecords {
+    names: FxHashMap<String, Vec<Entry>>,
+    rotate: bool,
+    /// 轮换计数 / Rotation counter
+    turn: AtomicUsize,
+}
+
+/// 探测失败的本地记录地址 / Local record addresses failing their probe
+#[derive(Debug, Default)]
+pub struct LocalRecordHealth {
+    down: dashmap::DashSet<SocketAddr, FxBuildHasher>,
+}
+
+impl LocalRecordHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn is_down(&self, probe: Option<SocketAddr>) -> bool {
+        !self.down.is_empty() && probe.is_some_and(|addr| self.down.contains(&addr))
+    }
+
+    /// 探测失败的地址数 / Number of addresses failing their probe
+    pub fn down(&self) -> usize {
+        self.down.len()
+    }
+}
+
+fn key(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn fqdn(value: &str) -> anyhow::Result<Name> {
+    let mut name = Name::from_str(value.trim()).with_context(|| format!("invalid name {:?}", value))?;
+    name.set_fqdn(true);
+    Ok(name)
+}
+
+/// 把一条配置记录解析为 DNS 记录 / Parse one configured record into a DNS record
+pub(crate) fn parse(record: &LocalRecord) -> anyhow::Result<Record> {
+    let owner = fqdn(&record.name)?;
+    let value = record.value.trim();
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    let rdata = match record.record_type.to_ascii_uppercase().as_str() {
+        "A" => RData::A(A(Ipv4Addr::from_str(value).with_context(|| format!("invalid ipv4 address {:?}", value))?)),
+        "AAAA" => RData::AAAA(AAAA(Ipv6Addr::from_str(value).with_context(|| format!("invalid ipv6 address {:?}", value))?)),
+        "CNAME" => RData::CNAME(CNAME(fqdn(value)?)),
+        "NS" => RData::NS(NS(fqdn(value)?)),
+        "PTR" => RData::PTR(PTR(fqdn(value)?)),
+        "MX" => match fields[..] {
+            [preference, exchange] => RData::MX(MX::new(preference.parse().context("invalid MX preference")?, fqdn(exchange)?)),
+            _ => bail!("MX value must be \"preference name\""),
+        },
+        "SRV" => match fields[..] {
+            [priority, weight, port, target] => RData::SRV(SRV::new(
+                priority.parse().context("invalid SRV priority")?,
+                weight.parse().context("invalid SRV weight")?,
+                port.parse().context("invalid SRV port")?,
+                fqdn(target)?,
+            )),
+            _ => bail!("SRV value must be \"priority weight port name\""),
+        },
+        // 单个字符串最长 255 字节，超出部分拆成多个字符串 / Strings are at most 255 bytes, longer text is split
+        "TXT" => RData::TXT(TXT::from_bytes(record.value.as_bytes().chunks(255).collect())),
+        other => bail!("unsupported record type {:?}", other),
+    };
+    Ok(Record::from_rdata(owner, record.ttl, rdata))
+}
+
+impl LocalRecords {
+    /// 编译配置中的记录 / Compile the configured records
+    pub fn compile(records: &[LocalRecord], rotate: bool) -> anyhow::Result<Self> {
+        let mut names: FxHashMap<String, Vec<Entry>> = FxHashMap::default();
+        for (i, record) in records.iter().enumerate() {
+            let parsed = parse(record).with_context(|| format!("settings.local_records[{}]", i))?;
+            let probe = match (parsed.data(), record.probe_port) {
+                (Some(RData::A(A(ip))), Some(port)) => Some(SocketAddr::new(IpAddr::V4(*ip), port)),
+                (Some(RData::AAAA(AAAA(ip))), Some(port)) => Some(SocketAddr::new(IpAddr::V6(*ip), port)),
+                _ => None,
+            };
+            names.entry(key(&record.name)).or_default().push(Entry { record: parsed, probe });
+        }
+        Ok(Self { names, rotate, turn: AtomicUsize::new(0) })
+    }
+
+    /// 需要探测的地址 / Addresses to probe
+    pub fn probe_targets(&self) -> Vec<SocketAddr> {
+        let mut targets: Vec<SocketAddr> = self.names.values().flatten().filter_map(|e| e.probe).collect();
+        targets.sort_unstable();
+        targets.dedup();
+        targets
+    }
+
+    /// 名称的本地应答，名称没有本地记录时返回 None
+    /// Local answer for a name, or None when the name has no local records
+    pub(crate) fn answer(&self, qname: &str, qtype: u16, health: &LocalRecordHealth) -> Option<(ResponseCode, Vec<Record>)> {
+        if self.names.is_empty() {
+            return None;
+        }
+        let mut entries = self.names.get(&key(qname))?;
+        // ANY 只返回一个记录集（RFC 8482 最小应答）/ ANY gets a single RRset (RFC 8482 minimal answer)
+        let qtype = match RecordType::from(qtype) {
+            RecordType::ANY => entries.first().map_or(RecordType::ANY, |e| e.record.record_type()),
+            qtype => qtype,
+        };
+        let mut answers = Vec::new();
+        for _ in 0..MAX_CNAME_HOPS {
+            let matching: Vec<&Entry> = entries
+                .iter()
+                .filter(|e| e.record.record_type() == qtype)
+                .collect();
+            if !matching.is_empty() {
+                // 全部探测失败时照常返回 / Everything is returned when all of them fail their probe
+                let up: Vec<&Entry> = matching.iter().copied().filter(|e| !health.is_down(e.probe)).collect();
+                let mut chosen: Vec<Record> =
+                    if up.is_empty() { matching } else { up }.into_iter().map(|e| e.record.clone()).collect();
+                if self.rotate && chosen.len() > 1 {
+                    let turn = self.turn.fetch_add(1, Ordering::Relaxed) % chosen.len();
+                    chosen.rotate_left(turn);
+                }
+                answers.extend(chosen);
+                break;
+            }
+            let Some(cname) = entries.iter().map(|e| &e.record).find(|r| r.record_type() == RecordType::CNAME) else {
+                break;
+            };
+            answers.push(cname.clone());
+            let Some(RData::CNAME(CNAME(target))) = cname.data() else {
+                break;
+            };
+            match self.names.get(&key(&target.to_ascii())) {
+                Some(next) => entries = next,
+                None => break,
+            }
+        }
+        Some((ResponseCode::NoError, answers))
+    }
+}
+
+/// 对所有探测目标执行一轮 TCP 连接探测 / Run one round of TCP connect probes over every target
+pub async fn probe_all(engine: &Engine) {
+    let targets = engine.state.load().pipeline.local_records.probe_targets();
+    let health = &engine.local_record_health;
+    health.down.retain(|addr| targets.contains(addr));
+    let mut tasks = JoinSet::new();
+    for addr in targets {
+        tasks.spawn(async move {
+            let ok = matches!(tokio::time::timeout(PROBE_TIMEOUT, tokio::net::TcpStream::connect(addr)).await, Ok(Ok(_)));
+            (addr, ok)
+        });
+    }
+    while let Some(Ok((addr, ok))) = tasks.join_next().await {
+        let changed = if ok { health.down.remove(&addr).is_some() } else { health.down.insert(addr) };
+        if changed {
+            tracing::info!(event = "local_record_probe", target = %addr, up = ok, "local record probe state changed");
+        }
+    }
+}
+
+/// 启动后台探测任务 / Spawn the background probe task
+pub fn spawn(engine: &Engine) {
+    let engine = engine.clone();
+    tokio::spawn(async move {
+        loop {
+            probe_all(&engine).await;
+            let interval = engine.state.load().pipeline.settings.local_records_probe_interval_secs;
+            tokio::time::sleep(Duration::from_secs(interval.max(1))).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, record_type: &str, value: &str) -> LocalRecord {
+        LocalRecord {
+            name: name.to_string(),
+            record_type: record_type.to_string(),
+            value: value.to_string(),
+            ttl: 300,
+            probe_port: None,
+        }
+    }
+
+    #[test]
+    fn test_local_records_answer_exact_names() {
+        // Arrange
+        let records = LocalRecords::compile(&[
+            record("nas.home", "A", "192.168.1.10"),
+            record("nas.home", "A", "192.168.1.11"),
+            record("nas.home", "TXT", "rack 2"),
+            record("files.home.", "CNAME", "nas.home"),
+            record("home", "MX", "10 nas.home"),
+        ], false)
+        .unwrap();
+        let invalid = LocalRecords::compile(&[record("nas.home", "A", "not-an-ip")], false);
+        let health = LocalRecordHealth::new();
+
+        // Act
+        let a = records.answer("NAS.home.", u16::from(RecordType::A), &health).unwrap();
+        let nodata = records.answer("nas.home", u16::from(RecordType::AAAA), &health).unwrap();
+        let chased = records.answer("files.home", u16::from(RecordType::A), &health).unwrap();
+        let mx = records.answer("home.", u16::from(RecordType::MX), &health).unwrap();
+        let other = records.answer("printer.home", u16::from(RecordType::A), &health);
+        let any = records.answer("nas.home", u16::from(RecordType::ANY), &health).unwrap();
+
+        // Assert
+        assert_eq!(a.0, ResponseCode::NoError);
+        assert_eq!(a.1.len(), 2);
+        assert_eq!(a.1[0].data(), Some(&RData::A(A(Ipv4Addr::new(192, 168, 1, 10)))));
+        assert!(nodata.1.is_empty());
+        let types: Vec<RecordType> = chased.1.iter().map(|r| r.record_type()).collect();
+        assert_eq!(types, vec![RecordType::CNAME, RecordType::A, RecordType::A]);
+        assert_eq!(mx.1.len(), 1);
+        assert!(other.is_none());
+        let any_types: Vec<RecordType> = any.1.iter().map(|r| r.record_type()).collect();
+        assert_eq!(any_types, vec![RecordType::A, RecordType::A], "ANY gets only the first RRset");
+        assert!(format!("{:#}", invalid.unwrap_err()).contains("local_records[0]"));
+    }
+
+    #[test]
+    fn test_local_records_rotate_and_skip_failed_probes() {
+        // Arrange
+        let probed = |value: &str| LocalRecord { probe_port: Some(8080), ..record("app.home", "A", value) };
+        let records = LocalRecords::compile(&[probed("10.0.0.1"), probed("10.0.0.2"), probed("10.0.0.3")], true).unwrap();
+        let health = LocalRecordHealth::new();
+        let first_ip = |health: &LocalRecordHealth| {
+            let (_, answers) = records.answer("app.home", u16::from(RecordType::A), health).unwrap();
+            let ips: Vec<String> = answers.iter().map(|r| r.data().unwrap().to_string()).collect();
+            ips
+        };
+
+        // Act
+        let first = first_ip(&health);
+        let second = first_ip(&health);
+        health.down.insert("10.0.0.1:8080".parse().unwrap());
+        health.down.insert("10.0.0.2:8080".parse().unwrap());
+        let filtered = first_ip(&health);
+        health.down.insert("10.0.0.3:8080".parse().unwrap());
+        let all_down = first_ip(&health);
+
+        // Assert
+        assert_eq!(records.probe_targets().len(), 3);
+        assert_eq!(first, vec!["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+        assert_eq!(second, vec!["10.0.0.2", "10.0.0.3", "10.0.0.1"]);
+        assert_eq!(filtered, vec!["10.0.0.3"]);
+        assert_eq!(all_down.len(), 3);
+    }
+}
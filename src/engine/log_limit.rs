@@ -0,0 +1,92 @@
+//! 告警日志限速 / Warning log rate limits
+//!
+//! 网络故障时请求超时、上游失败、连接重试等告警会随流量成倍出现，大量写日志反过来拖慢 kixdns。
+//! `log_rate_limit` 按类别限制每秒输出的条数，超出部分按 `sample_every` 抽样输出（0 为全部丢弃），
+//! 被省略的条数记在下一条输出日志的 `suppressed` 字段中，总数见 `/stats` 的 `log_suppressed`。
+//! During network incidents warnings such as request timeouts, upstream failures and connection
+//! retries multiply with traffic, and writing them all slows kixdns down in turn.
+//! `log_rate_limit` caps the lines per category per second; beyond the cap one line in
+//! `sample_every` is still written (0 drops them all). The number of omitted lines is carried in
+//! the `suppressed` field of the next written line, and the total is `log_suppressed` in `/stats`.
+
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Instant;
+
+use dashmap::DashMap;
+
+use crate::config::LogRateLimitSettings;
+
+/// 每类每秒最多输出的条数，0 为不限 / Max lines per category per second, 0 = unlimited
+static PER_SEC: AtomicU32 = AtomicU32::new(0);
+/// 超出上限后每 N 条输出一条 / One line in N is written beyond the cap
+static SAMPLE_EVERY: AtomicU32 = AtomicU32::new(0);
+/// 被省略的总条数 / Total omitted lines
+static SUPPRESSED: AtomicU64 = AtomicU64::new(0);
+
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+static WINDOWS: LazyLock<DashMap<&'static str, Window>> = LazyLock::new(DashMap::new);
+
+/// 一个类别当前一秒的计数 / Counts of one category in the current second
+#[derive(Default)]
+struct Window {
+    second: u64,
+    count: u32,
+    suppressed: u64,
+}
+
+/// 应用限速配置（启动与重载时调用）/ Apply the rate limit settings (called on start and reload)
+pub fn set_log_rate_limit(settings: &LogRateLimitSettings) {
+    PER_SEC.store(settings.per_category_per_sec, Ordering::Relaxed);
+    SAMPLE_EVERY.store(settings.sample_every, Ordering::Relaxed);
+}
+
+/// 是否输出该类别的一条日志；输出时返回此前省略的条数
+/// Whether to write one line of the category; when written, returns the lines omitted before it
+pub fn allow(category: &'static str) -> Option<u64> {
+    let per_sec = PER_SEC.load(Ordering::Relaxed);
+    if per_sec == 0 {
+        return Some(0);
+    }
+    allow_at(category, STARTED.elapsed().as_secs(), per_sec, SAMPLE_EVERY.load(Ordering::Relaxed))
+}
+
+fn allow_at(category: &'static str, second: u64, per_sec: u32, sample_every: u32) -> Option<u64> {
+    let mut window = WINDOWS.entry(category).or_default();
+    if window.second != second {
+        window.second = second;
+        window.count = 0;
+    }
+    window.count = window.count.saturating_add(1);
+    let over = window.count.saturating_sub(per_sec);
+    if over == 0 || (sample_every > 0 && over.is_multiple_of(sample_every)) {
+        return Some(std::mem::take(&mut window.suppressed));
+    }
+    window.suppressed += 1;
+    SUPPRESSED.fetch_add(1, Ordering::Relaxed);
+    None
+}
+
+/// 被省略的总条数 / Total omitted lines
+pub fn suppressed_total() -> u64 {
+    SUPPRESSED.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_beyond_the_cap_are_sampled_and_counted() {
+        // Arrange
+        let category = "test_category";
+
+        // Act
+        let first_second: Vec<Option<u64>> = (0..7).map(|_| allow_at(category, 1, 2, 3)).collect();
+        let next_second = allow_at(category, 2, 2, 3);
+
+        // Assert
+        assert_eq!(first_second, vec![Some(0), Some(0), None, None, Some(2), None, None]);
+        assert_eq!(next_second, Some(2));
+    }
+}
@@ -1,13 +1,18 @@
+use std::cell::OnceCell;
 use std::sync::Arc;
 use std::net::IpAddr;
 use hickory_proto::rr::DNSClass;
 use hickory_proto::rr::RecordType;
+use regex::SetMatches;
 use tracing;
 
 use crate::lock::RwLock;
 use crate::matcher::RuntimeMatcher;
 use crate::matcher::geoip::GeoIpManager;
 use crate::matcher::geosite::GeoSiteManager;
+use crate::matcher::regex_set::DomainRegexSet;
+
+use super::privacy::ClientIp;
 
 /// Context for matcher evaluation
 /// Groups related parameters to reduce function argument count
@@ -19,9 +24,19 @@ pub struct MatcherContext<'a> {
     pub qtype: RecordType,
     pub geoip_manager: Option<&'a Arc<RwLock<GeoIpManager>>>,
     pub geosite_manager: Option<&'a Arc<RwLock<GeoSiteManager>>>,
+    /// pipeline 的正则集合 / The pipeline regex set
+    pub regex_set: Option<&'a DomainRegexSet>,
+    /// 正则集合的结果，首次用到时计算 / Regex set result, computed on first use
+    pub regex_hits: OnceCell<SetMatches>,
 }
 
 pub fn matcher_matches(matcher: &RuntimeMatcher, ctx: &MatcherContext<'_>) -> bool {
+    // 已合并进正则集合的 domain_regex：每个查询只扫描一次 / Batched domain_regex: one scan per query
+    if let RuntimeMatcher::DomainRegex { slot: Some(slot), .. } = matcher
+        && let Some(set) = ctx.regex_set
+    {
+        return ctx.regex_hits.get_or_init(|| set.matches(ctx.qname)).matched(*slot);
+    }
     // 直接传递 Arc<RwLock<T>>，让 matches_with_qtype 内部按需获取锁
     // Pass Arc<RwLock<T>> directly, let matches_with_qtype acquire locks on-demand
     matcher.matches_with_qtype(
@@ -35,7 +50,7 @@ pub fn matcher_matches(matcher: &RuntimeMatcher, ctx: &MatcherContext<'_>) -> bo
     )
 }
 
-pub fn log_match(level: Option<&str>, rule_name: &str, qname: &str, client_ip: IpAddr) {
+pub fn log_match(level: Option<&str>, rule_name: &str, qname: &str, client_ip: ClientIp<'_>) {
     match level.unwrap_or("info") {
         "trace" => {
             tracing::trace!(event = "matcher_log", rule = %rule_name, qname = %qname, client_ip = %client_ip, level = "trace")
@@ -0,0 +1,131 @@
+//! `.local` 的 mDNS 桥接 / mDNS bridge for `.local`
+//!
+//! 启用后，`.local` 查询不再转发到上游，而是以 RFC 6762 §6.7 的“传统单播”一次性查询方式
+//! 从临时端口发往 224.0.0.251:5353，取第一个匹配的响应返回给客户端；超时则返回 NXDOMAIN。
+//! 这样只支持单播 DNS 的设备也能发现仅通过 mDNS 公告的主机。目前仅支持 IPv4 组播。
+//! When enabled, `.local` queries are never forwarded upstream. Instead they are sent as
+//! RFC 6762 §6.7 "legacy unicast" one-shot queries from an ephemeral port to
+//! 224.0.0.251:5353, and the first matching response is returned to the client; on
+//! timeout the client gets NXDOMAIN. This lets unicast-only devices discover hosts that
+//! only announce via mDNS. Only IPv4 multicast is supported for now.
+
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+
+use anyhow::Context;
+use bytes::Bytes;
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::serialize::binary::BinDecodable;
+use tokio::net::UdpSocket;
+
+use crate::engine::utils::engine_helpers::build_response;
+
+/// mDNS 组播地址 / mDNS multicast group
+const MDNS_GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353);
+
+/// mDNS 桥接器 / mDNS bridge
+#[derive(Debug)]
+pub struct MdnsBridge {
+    timeout: Duration,
+    interface: Option<Ipv4Addr>,
+}
+
+impl MdnsBridge {
+    /// interface: 发送组播所用的本地 IPv4 地址（缺省由系统路由决定）
+    /// interface: local IPv4 address used for sending multicast (system routing if absent)
+    pub fn new(timeout_ms: u64, interface: Option<&str>) -> anyhow::Result<Self> {
+        let interface = interface
+            .map(|s| s.parse::<Ipv4Addr>().context("parse mdns_interface"))
+            .transpose()?;
+        Ok(Self {
+            timeout: Duration::from_millis(timeout_ms.max(1)),
+            interface,
+        })
+    }
+
+    /// 是否为 `.local` 名称 / Whether the name is under `.local`
+    #[inline]
+    pub fn is_local_name(qname: &str) -> bool {
+        let qname = qname.trim_end_matches('.');
+        qname.eq_ignore_ascii_case("local")
+            || (qname.len() > 6 && qname[qname.len() - 6..].eq_ignore_ascii_case(".local"))
+    }
+
+    /// 通过 mDNS 解析请求 / Resolve the request via mDNS
+    pub async fn query(&self, packet: &[u8]) -> anyhow::Result<Bytes> {
+        let req = Message::from_bytes(packet).context("parse request for mdns")?;
+        match self.query_multicast(packet).await {
+            Ok(Some(resp)) => Ok(resp),
+            Ok(None) => build_response(&req, ResponseCode::NXDomain, Vec::new()),
+            Err(e) => {
+                tracing::debug!(error = %e, "mdns query failed");
+                build_response(&req, ResponseCode::ServFail, Vec::new())
+            }
+        }
+    }
+
+    async fn query_multicast(&self, packet: &[u8]) -> anyhow::Result<Option<Bytes>> {
+        if packet.len() < 12 {
+            anyhow::bail!("packet too short");
+        }
+        let bind_ip = self.interface.unwrap_or(Ipv4Addr::UNSPECIFIED);
+        let socket = UdpSocket::bind(SocketAddr::from((bind_ip, 0)))
+            .await
+            .context("bind mdns socket")?;
+        socket.set_multicast_ttl_v4(255)?;
+        if let Some(iface) = self.interface {
+            socket2::SockRef::from(&socket).set_multicast_if_v4(&iface)?;
+        }
+        socket
+            .send_to(packet, SocketAddr::V4(MDNS_GROUP))
+            .await
+            .context("send mdns query")?;
+
+        let tx_id = &packet[..2];
+        let question = &packet[12..];
+        let mut buf = vec![0u8; 9000];
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        loop {
+            let recv = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await;
+            let (n, _from) = match recv {
+                Ok(r) => r?,
+                Err(_) => return Ok(None),
+            };
+            let resp = &buf[..n];
+            // 传统单播响应会回显 ID 与问题 / Legacy unicast responses echo the ID and question
+            if n >= 12 && &resp[..2] == tx_id && resp[2] & 0x80 != 0 && echoes_question(resp, question) {
+                return Ok(Some(Bytes::copy_from_slice(resp)));
+            }
+        }
+    }
+}
+
+/// 响应的问题段是否与请求一致（忽略 QU 位）/ Whether the response question matches the request (QU bit ignored)
+fn echoes_question(resp: &[u8], req_question: &[u8]) -> bool {
+    // 问题段以 QNAME 结尾的 0 字节后接 QTYPE/QCLASS / QNAME ends with a zero byte followed by QTYPE/QCLASS
+    let Some(end) = req_question.iter().position(|&b| b == 0) else {
+        return false;
+    };
+    let q_len = end + 5;
+    if req_question.len() < q_len || resp.len() < 12 + q_len {
+        return false;
+    }
+    let body = &resp[12..12 + q_len];
+    body[..end + 3].eq_ignore_ascii_case(&req_question[..end + 3])
+        && body[end + 3] & 0x7F == req_question[end + 3] & 0x7F
+        && body[end + 4] == req_question[end + 4]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_local_name() {
+        assert!(MdnsBridge::is_local_name("printer.local."));
+        assert!(MdnsBridge::is_local_name("NAS.LOCAL"));
+        assert!(MdnsBridge::is_local_name("local"));
+        assert!(!MdnsBridge::is_local_name("notlocal"));
+        assert!(!MdnsBridge::is_local_name("example.localhost"));
+    }
+}
@@ -0,0 +1,95 @@
+//! 最小化应答 / Minimal answers
+//!
+//! 启用 `minimal_answers` 后，上游应答的 Answer 段只保留从查询名出发的 CNAME 链以及链上与查询类型
+//! 相同的记录，其余记录（上游顺带返回的其它类型或其它名称）被去掉，客户端收到的应答与缓存条目都更小。
+//! ANY / CNAME 查询以及带 RRSIG 的应答保持原样，以免破坏 DNSSEC 验证。
+//! With `minimal_answers` enabled, the answer section of upstream responses keeps only the CNAME
+//! chain starting at the query name and the records of the query type along that chain; other
+//! records (extra types or names the upstream sent along) are removed, so both client responses
+//! and cache entries get smaller. ANY / CNAME queries and responses carrying RRSIGs are left as
+//! they are so DNSSEC validation keeps working.
+
+use hickory_proto::op::Message;
+use hickory_proto::rr::{Name, RData, RecordType};
+
+/// CNAME 链的最大长度 / Max CNAME chain length
+const MAX_CHAIN: usize = 16;
+
+/// 去掉与查询类型无关的 Answer 记录，无需改动时返回 None
+/// Remove answer records unrelated to the query type, or None when nothing changes
+pub(crate) fn minimize(raw: &[u8]) -> Option<Vec<u8>> {
+    let mut msg = Message::from_vec(raw).ok()?;
+    let query = msg.queries().first()?;
+    let qtype = query.query_type();
+    if matches!(qtype, RecordType::ANY | RecordType::CNAME)
+        || msg.answers().iter().any(|r| r.record_type() == RecordType::RRSIG)
+    {
+        return None;
+    }
+
+    let mut chain: Vec<Name> = vec![query.name().clone()];
+    while chain.len() < MAX_CHAIN {
+        let current = chain.last()?;
+        let next = msg.answers().iter().find_map(|r| match r.data() {
+            Some(RData::CNAME(cname)) if r.name() == current => Some(cname.0.clone()),
+            _ => None,
+        });
+        match next {
+            Some(next) if !chain.contains(&next) => chain.push(next),
+            _ => break,
+        }
+    }
+
+    let answers = msg.take_answers();
+    let before = answers.len();
+    let kept: Vec<_> = answers
+        .into_iter()
+        .filter(|r| {
+            let rtype = r.record_type();
+            (rtype == qtype || rtype == RecordType::CNAME) && chain.contains(r.name())
+        })
+        .collect();
+    if kept.len() == before {
+        return None;
+    }
+    msg.insert_answers(kept);
+    msg.to_vec().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{MessageType, Query};
+    use hickory_proto::rr::Record;
+    use hickory_proto::rr::rdata::{A, AAAA, CNAME, TXT};
+
+    #[test]
+    fn test_answers_are_stripped_to_the_cname_chain_and_query_type() {
+        // Arrange
+        let name = |s: &str| Name::from_ascii(s).unwrap();
+        let mut msg = Message::new();
+        msg.set_message_type(MessageType::Response);
+        msg.add_query(Query::query(name("www.example.com."), RecordType::A));
+        msg.add_answer(Record::from_rdata(name("www.example.com."), 60, RData::CNAME(CNAME(name("edge.cdn.net.")))));
+        msg.add_answer(Record::from_rdata(name("edge.cdn.net."), 60, RData::A(A::new(192, 0, 2, 1))));
+        msg.add_answer(Record::from_rdata(name("edge.cdn.net."), 60, RData::AAAA(AAAA::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))));
+        msg.add_answer(Record::from_rdata(name("other.example.com."), 60, RData::A(A::new(192, 0, 2, 9))));
+        msg.add_answer(Record::from_rdata(name("www.example.com."), 60, RData::TXT(TXT::new(vec!["v=spf1".to_string()]))));
+        let raw = msg.to_vec().unwrap();
+        let mut minimal = Message::new();
+        minimal.set_message_type(MessageType::Response);
+        minimal.add_query(Query::query(name("www.example.com."), RecordType::A));
+        minimal.add_answer(Record::from_rdata(name("www.example.com."), 60, RData::A(A::new(192, 0, 2, 1))));
+
+        // Act
+        let stripped = minimize(&raw).unwrap();
+        let unchanged = minimize(&minimal.to_vec().unwrap());
+
+        // Assert
+        let stripped = Message::from_vec(&stripped).unwrap();
+        let types: Vec<RecordType> = stripped.answers().iter().map(|r| r.record_type()).collect();
+        assert_eq!(types, vec![RecordType::CNAME, RecordType::A]);
+        assert_eq!(stripped.answers()[1].name(), &name("edge.cdn.net."));
+        assert!(unchanged.is_none());
+    }
+}
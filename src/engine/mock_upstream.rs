@@ -0,0 +1,177 @@
+//! 内置模拟上游 / Built-in mock upstream
+//!
+//! `mock://a=1.2.3.4&ttl=60` 形式的上游不访问网络，按参数合成确定的应答，并可模拟延迟、抖动与丢包，
+//! 便于在没有真实上游的环境中压测或集成测试 pipeline、缓存与流控。参数以 `&` 分隔（逗号用于分隔
+//! 多个上游）：`a` / `aaaa` 可重复，`ttl`（默认 60）、`rcode`（默认 noerror）、`latency_ms`、
+//! `jitter_ms` 与 `loss`（0 到 1 的丢包概率，丢弃的查询在超时后失败）。
+//! Upstreams of the form `mock://a=1.2.3.4&ttl=60` never touch the network: they synthesize
+//! deterministic answers from their parameters and can simulate latency, jitter and loss, so
+//! pipelines, caching and flow control can be load-tested or integration-tested without real
+//! upstreams. Parameters are separated by `&` (commas separate upstreams): `a` / `aaaa` may
+//! repeat, plus `ttl` (default 60), `rcode` (default noerror), `latency_ms`, `jitter_ms` and
+//! `loss` (drop probability from 0 to 1; dropped queries fail after the timeout).
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use bytes::Bytes;
+use crypto_box::aead::OsRng;
+use crypto_box::aead::rand_core::RngCore;
+use hickory_proto::op::{Edns, Message, MessageType, ResponseCode};
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{RData, Record, RecordType};
+
+use super::rules::parse_rcode;
+
+/// 解析后的模拟上游参数 / Parsed mock upstream parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockSpec {
+    pub a: Vec<Ipv4Addr>,
+    pub aaaa: Vec<Ipv6Addr>,
+    pub ttl: u32,
+    pub rcode: ResponseCode,
+    pub latency: Duration,
+    pub jitter: Duration,
+    pub loss: f64,
+}
+
+impl MockSpec {
+    pub fn parse(addr: &str) -> anyhow::Result<Self> {
+        let mut spec = MockSpec {
+            a: Vec::new(),
+            aaaa: Vec::new(),
+            ttl: 60,
+            rcode: ResponseCode::NoError,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            loss: 0.0,
+        };
+        for param in addr.trim_start_matches('?').split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = param
+                .split_once('=')
+                .with_context(|| format!("mock parameter {:?} is not key=value", param))?;
+            match key {
+                "a" => spec.a.push(value.parse().context("mock a")?),
+                "aaaa" => spec.aaaa.push(value.parse().context("mock aaaa")?),
+                "ttl" => spec.ttl = value.parse().context("mock ttl")?,
+                "rcode" => spec.rcode = parse_rcode(value).with_context(|| format!("unknown mock rcode {:?}", value))?,
+                "latency_ms" => spec.latency = Duration::from_millis(value.parse().context("mock latency_ms")?),
+                "jitter_ms" => spec.jitter = Duration::from_millis(value.parse().context("mock jitter_ms")?),
+                "loss" => {
+                    spec.loss = value.parse().context("mock loss")?;
+                    if !(0.0..=1.0).contains(&spec.loss) {
+                        bail!("mock loss must be between 0 and 1");
+                    }
+                }
+                _ => bail!("unknown mock parameter {:?}", key),
+            }
+        }
+        Ok(spec)
+    }
+
+    /// 对请求合成应答：A/AAAA 查询返回配置的地址，其他类型无记录
+    /// Synthesize the answer to a query: A/AAAA get the configured addresses, other types get no records
+    pub fn answer(&self, packet: &[u8]) -> anyhow::Result<Bytes> {
+        let req = Message::from_vec(packet).context("parse query for mock upstream")?;
+        let mut resp = Message::new();
+        resp.set_id(req.id())
+            .set_message_type(MessageType::Response)
+            .set_op_code(req.op_code())
+            .set_recursion_desired(req.recursion_desired())
+            .set_recursion_available(true)
+            .set_checking_disabled(req.checking_disabled())
+            .set_response_code(self.rcode)
+            .add_queries(req.queries().to_vec());
+        if req.extensions().is_some() {
+            let mut edns = Edns::new();
+            edns.set_max_payload(crate::proto_utils::RESPONSE_UDP_PAYLOAD);
+            resp.set_edns(edns);
+        }
+        if self.rcode == ResponseCode::NoError
+            && let Some(q) = req.queries().first()
+        {
+            let rdatas: Vec<RData> = match q.query_type() {
+                RecordType::A => self.a.iter().map(|ip| RData::A(A(*ip))).collect(),
+                RecordType::AAAA => self.aaaa.iter().map(|ip| RData::AAAA(AAAA(*ip))).collect(),
+                _ => Vec::new(),
+            };
+            for rdata in rdatas {
+                resp.add_answer(Record::from_rdata(q.name().clone(), self.ttl, rdata));
+            }
+        }
+        Ok(Bytes::from(resp.to_vec()?))
+    }
+
+    /// 本次查询的模拟延迟 / Simulated delay of this query
+    fn delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.latency;
+        }
+        let jitter_us = self.jitter.as_micros() as u64;
+        self.latency + Duration::from_micros(OsRng.next_u64() % (jitter_us + 1))
+    }
+
+    fn dropped(&self) -> bool {
+        self.loss > 0.0 && (OsRng.next_u64() as f64 / u64::MAX as f64) < self.loss
+    }
+}
+
+/// 向模拟上游发送查询 / Send a query to a mock upstream
+pub async fn send(packet: &[u8], addr: &str, timeout_dur: Duration) -> anyhow::Result<Bytes> {
+    let spec = MockSpec::parse(addr)?;
+    if spec.dropped() {
+        tokio::time::sleep(timeout_dur).await;
+        bail!("mock upstream dropped the query");
+    }
+    let delay = spec.delay();
+    if delay > timeout_dur {
+        tokio::time::sleep(timeout_dur).await;
+        bail!("mock upstream timeout");
+    }
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    spec.answer(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::Query;
+    use hickory_proto::rr::Name;
+
+    fn query(qtype: RecordType) -> Vec<u8> {
+        let mut msg = Message::new();
+        msg.set_id(7).set_recursion_desired(true);
+        msg.add_query(Query::query(Name::from_ascii("bench.example.").unwrap(), qtype));
+        msg.to_vec().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mock_upstream_synthesizes_answers() {
+        // Arrange
+        let addr = "a=192.0.2.1&a=192.0.2.2&ttl=30&latency_ms=20";
+        let timeout_dur = Duration::from_secs(1);
+
+        // Act
+        let started = std::time::Instant::now();
+        let a = Message::from_vec(&send(&query(RecordType::A), addr, timeout_dur).await.unwrap()).unwrap();
+        let elapsed = started.elapsed();
+        let txt = Message::from_vec(&send(&query(RecordType::TXT), addr, timeout_dur).await.unwrap()).unwrap();
+        let nx = Message::from_vec(&send(&query(RecordType::A), "rcode=nxdomain", timeout_dur).await.unwrap()).unwrap();
+        let lost = send(&query(RecordType::A), "loss=1", Duration::from_millis(10)).await;
+        let invalid = MockSpec::parse("a=1.2.3.4&speed=9");
+
+        // Assert
+        assert_eq!(a.id(), 7);
+        let ips: Vec<String> = a.answers().iter().map(|r| r.data().unwrap().to_string()).collect();
+        assert_eq!(ips, ["192.0.2.1", "192.0.2.2"]);
+        assert!(a.answers().iter().all(|r| r.ttl() == 30));
+        assert!(elapsed >= Duration::from_millis(20));
+        assert!(txt.answers().is_empty());
+        assert_eq!(nx.response_code(), ResponseCode::NXDomain);
+        assert!(lost.is_err());
+        assert!(invalid.is_err());
+    }
+}
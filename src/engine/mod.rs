@@ -1,21 +1,52 @@
 pub mod concurrency;
 pub mod core;
+pub mod ddr;
+pub mod dnscrypt;
+pub mod domain_stats;
 pub mod execution;
+pub mod happy_eyeballs;
+pub mod health;
+pub mod ipv6_probe;
+pub mod live_queries;
+pub mod log_limit;
+pub mod local_records;
 pub mod matcher_adapter;
+pub mod mdns;
+pub mod minimal_answers;
+pub mod mock_upstream;
+pub mod netns;
+pub mod odoh;
+pub mod peer_sync;
 pub mod phases;
 pub mod pipeline;
+pub mod prefetch;
+pub mod qname_limit;
+pub mod quarantine;
+pub mod reload_events;
+pub mod privacy;
+pub mod private_ptr;
+pub mod ptr_rewrite;
 pub mod response;
+pub mod rule_log;
 pub mod rules;
+pub mod runtime_rules;
+pub mod stats;
 pub mod transport;
+pub mod tunables;
 pub mod types;
+pub mod udp_batch;
 pub mod utils;
+pub mod validation;
 pub mod upstream;
+pub mod upstream_stats;
+pub mod upstream_health;
+pub mod upstream_log;
 pub mod refresh;
 
 pub use core::Engine;
 pub use matcher_adapter::*;
 pub use pipeline::select_pipeline;
-pub use types::{EngineInner, FastPathResponse};
+pub use types::{EngineInner, FastPathResponse, MalformedQuery};
 pub use concurrency::PermitManager;
 
 pub use rules::Decision;
@@ -0,0 +1,218 @@
+//! 经命名网络命名空间访问的上游 / Upstreams reached through named network namespaces
+//!
+//! `udp://10.8.0.1:53?netns=vpn` 或 `tcp://…?netns=vpn` 形式的上游在指定的网络命名空间中创建 socket，
+//! 便于把 VPN 隧道隔离在命名空间中的路由器把部分查询经隧道转发。每个命名空间有一个专用线程，
+//! 该线程通过 setns 进入 `/run/netns/<名称>`（以 `/` 开头时按路径处理）后只负责创建 socket；
+//! socket 创建后即绑定在该命名空间，收发仍在 tokio 中进行。这类上游每次查询使用新的 socket，
+//! 不经过连接池，且地址必须为 `IP:端口`。仅支持 Linux。
+//! Upstreams of the form `udp://10.8.0.1:53?netns=vpn` or `tcp://…?netns=vpn` create their
+//! sockets inside the given network namespace, so routers that isolate VPN tunnels in
+//! namespaces can forward some queries through the tunnel. Each namespace gets a dedicated
+//! thread that enters `/run/netns/<name>` (a path when it starts with `/`) with setns and only
+//! creates sockets; a socket stays in its namespace once created, and the I/O still runs on
+//! tokio. These upstreams use a fresh socket per query, bypass the connection pools, and need an
+//! `ip:port` address. Linux only.
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use bytes::Bytes;
+use dashmap::DashMap;
+use rustc_hash::FxBuildHasher;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::oneshot;
+
+use crate::config::Transport;
+
+/// 上游地址中的命名空间参数 / Namespace parameter of an upstream address
+const NETNS_PARAM: &str = "?netns=";
+
+/// 拆分 `IP:端口?netns=名称`，没有命名空间参数时返回 None
+/// Split `ip:port?netns=name`; None when there is no namespace parameter
+pub(crate) fn split(addr: &str) -> Option<(&str, &str)> {
+    addr.split_once(NETNS_PARAM)
+}
+
+/// 校验命名空间上游的地址与传输 / Check the address and transport of a namespace upstream
+pub(crate) fn check(addr: &str, transport: Transport) -> anyhow::Result<()> {
+    let Some((target, name)) = split(addr) else {
+        return Ok(());
+    };
+    if !matches!(transport, Transport::Udp | Transport::Tcp) {
+        bail!("netns is only supported for udp and tcp upstreams");
+    }
+    if name.is_empty() || (!name.starts_with('/') && name.contains('/')) {
+        bail!("invalid netns {:?}", name);
+    }
+    target.parse::<SocketAddr>().map_err(|_| anyhow::anyhow!("netns upstreams expect ip:port"))?;
+    Ok(())
+}
+
+type SocketRequest = (Domain, Type, oneshot::Sender<io::Result<Socket>>);
+
+/// 按命名空间缓存的 socket 创建线程 / Socket-creating threads cached by namespace
+#[derive(Default)]
+pub struct NetnsSockets {
+    threads: DashMap<Arc<str>, mpsc::Sender<SocketRequest>, FxBuildHasher>,
+}
+
+impl NetnsSockets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 在命名空间中创建 socket / Create a socket inside the namespace
+    async fn socket(&self, name: &str, domain: Domain, ty: Type) -> anyhow::Result<Socket> {
+        let (tx, rx) = oneshot::channel();
+        let thread = self
+            .threads
+            .entry(Arc::from(name))
+            .or_insert_with(|| spawn_thread(name))
+            .clone();
+        if thread.send((domain, ty, tx)).is_err() {
+            self.threads.remove(name);
+            bail!("netns {} thread exited", name);
+        }
+        rx.await
+            .context("netns thread dropped the request")?
+            .with_context(|| format!("create socket in netns {}", name))
+    }
+
+    /// 经命名空间向上游发送查询，addr 为 `IP:端口?netns=名称`
+    /// Send a query to an upstream through its namespace; addr is `ip:port?netns=name`
+    pub async fn send(&self, packet: &[u8], addr: &str, transport: Transport, timeout_dur: Duration) -> anyhow::Result<Bytes> {
+        let (target, name) = split(addr).context("missing netns parameter")?;
+        let target: SocketAddr = target.parse().context("netns upstreams expect ip:port")?;
+        let domain = Domain::for_address(target);
+        match transport {
+            Transport::Tcp => {
+                let socket = self.socket(name, domain, Type::STREAM).await?;
+                let socket = tokio::net::TcpSocket::from_std_stream(socket.into());
+                tokio::time::timeout(timeout_dur, async {
+                    let mut stream = socket.connect(target).await?;
+                    let mut frame = Vec::with_capacity(packet.len() + 2);
+                    frame.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+                    frame.extend_from_slice(packet);
+                    stream.write_all(&frame).await?;
+                    let len = stream.read_u16().await? as usize;
+                    let mut resp = vec![0u8; len];
+                    stream.read_exact(&mut resp).await?;
+                    Ok::<_, io::Error>(Bytes::from(resp))
+                })
+                .await
+                .context("netns tcp upstream timeout")?
+                .context("netns tcp upstream")
+            }
+            _ => {
+                let socket = self.socket(name, domain, Type::DGRAM).await?;
+                let socket = tokio::net::UdpSocket::from_std(socket.into())?;
+                socket.connect(target).await?;
+                socket.send(packet).await?;
+                tokio::time::timeout(timeout_dur, async {
+                    let mut buf = vec![0u8; 65535];
+                    loop {
+                        let len = socket.recv(&mut buf).await?;
+                        // 只接受事务 ID 相同的应答 / Only accept answers with the same transaction id
+                        if len >= 2 && packet.len() >= 2 && buf[..2] == packet[..2] {
+                            return Ok::<_, io::Error>(Bytes::copy_from_slice(&buf[..len]));
+                        }
+                    }
+                })
+                .await
+                .context("netns udp upstream timeout")?
+                .context("netns udp upstream")
+            }
+        }
+    }
+}
+
+/// 启动进入命名空间的线程 / Spawn the thread that enters the namespace
+fn spawn_thread(name: &str) -> mpsc::Sender<SocketRequest> {
+    let (tx, rx) = mpsc::channel::<SocketRequest>();
+    let path = if name.starts_with('/') { name.to_string() } else { format!("/run/netns/{}", name) };
+    let spawned = std::thread::Builder::new()
+        .name(format!("kixdns-netns-{}", name.trim_start_matches('/').replace('/', "-")))
+        .spawn(move || {
+            let entered = enter(&path);
+            if let Err(err) = &entered {
+                tracing::warn!(netns = %path, error = %err, "failed to enter network namespace");
+            }
+            for (domain, ty, reply) in rx {
+                let result = match &entered {
+                    Ok(()) => new_socket(domain, ty),
+                    Err(err) => Err(io::Error::new(err.kind(), err.to_string())),
+                };
+                let _ = reply.send(result);
+            }
+        });
+    if let Err(err) = spawned {
+        tracing::warn!(netns = %name, error = %err, "failed to spawn netns thread");
+    }
+    tx
+}
+
+/// 创建非阻塞 socket，UDP 绑定到临时端口 / Create a non-blocking socket, UDP bound to an ephemeral port
+fn new_socket(domain: Domain, ty: Type) -> io::Result<Socket> {
+    let protocol = if ty == Type::STREAM { Protocol::TCP } else { Protocol::UDP };
+    let socket = Socket::new(domain, ty, Some(protocol))?;
+    socket.set_nonblocking(true)?;
+    if ty == Type::DGRAM {
+        let any: SocketAddr = if domain == Domain::IPV6 {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        };
+        socket.bind(&any.into())?;
+    }
+    Ok(socket)
+}
+
+/// 当前线程进入命名空间 / Move the current thread into the namespace
+#[cfg(target_os = "linux")]
+fn enter(path: &str) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let file = std::fs::File::open(path)?;
+    // SAFETY: setns 只作用于当前线程，文件描述符在调用期间有效
+    // SAFETY: setns only affects the calling thread and the descriptor is valid for the call
+    if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn enter(_path: &str) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "network namespaces are only supported on Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_netns_upstream_addresses_are_checked() {
+        // Arrange
+        let valid = "10.8.0.1:53?netns=vpn";
+
+        // Act
+        let parts = split(valid);
+        let udp = check(valid, Transport::Udp);
+        let dot = check(valid, Transport::Dot);
+        let hostname = check("dns.example:53?netns=vpn", Transport::Tcp);
+        let nested = check("10.8.0.1:53?netns=a/b", Transport::Udp);
+        let plain = check("dns.example:853", Transport::Dot);
+
+        // Assert
+        assert_eq!(parts, Some(("10.8.0.1:53", "vpn")));
+        assert!(udp.is_ok());
+        assert!(dot.is_err());
+        assert!(hostname.is_err());
+        assert!(nested.is_err());
+        assert!(plain.is_ok());
+    }
+}
@@ -0,0 +1,433 @@
+//! Oblivious DoH（RFC 9230）上游客户端 / Oblivious DoH (RFC 9230) upstream client
+//!
+//! 上游写为 `odoh://target.example/dns-query?relay=https://relay.example/proxy`。
+//! 查询以目标解析器公布的 HPKE 公钥加密后发往中继，中继只看到客户端地址，
+//! 目标解析器只看到中继地址。目标配置从 `https://目标/.well-known/odohconfigs` 获取并缓存一小时，
+//! 目标拒绝密钥或解密失败时立即重新获取。
+//! Upstreams are written as `odoh://target.example/dns-query?relay=https://relay.example/proxy`.
+//! Queries are encrypted to the target's published HPKE key and posted to the relay, so the
+//! relay only sees the client address and the target only sees the relay. Target configs are
+//! fetched from `https://target/.well-known/odohconfigs`, cached for an hour, and refetched
+//! immediately when the target rejects the key or decryption fails.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, OsRng, Payload};
+use aes_gcm::{Aes128Gcm, KeyInit, Nonce};
+use anyhow::{Context, Result, anyhow, bail};
+use bytes::Bytes;
+use dashmap::DashMap;
+use hkdf::Hkdf;
+use reqwest::Client as HttpClient;
+use reqwest::StatusCode;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use sha2::Sha256;
+use tokio::time::timeout;
+use url::Url;
+use x25519_dalek::{X25519_BASEPOINT_BYTES, x25519};
+
+/// ODoH 消息媒体类型 / ODoH message media type
+const ODOH_CONTENT_TYPE: &str = "application/oblivious-dns-message";
+/// 配置版本 / Config version
+const ODOH_VERSION: u16 = 0x0001;
+/// DHKEM(X25519, HKDF-SHA256)
+const KEM_X25519_SHA256: u16 = 0x0020;
+/// HKDF-SHA256
+const KDF_SHA256: u16 = 0x0001;
+/// AES-128-GCM
+const AEAD_AES128GCM: u16 = 0x0001;
+/// AEAD 密钥长度 / AEAD key length
+const NK: usize = 16;
+/// AEAD nonce 长度 / AEAD nonce length
+const NN: usize = 12;
+/// 消息类型 / Message types
+const MSG_QUERY: u8 = 0x01;
+const MSG_RESPONSE: u8 = 0x02;
+/// 明文填充块大小 / Plaintext padding block size
+const PADDING_BLOCK: usize = 128;
+/// 配置刷新间隔 / Config refresh interval
+const CONFIG_REFRESH: Duration = Duration::from_secs(3600);
+
+// ===================== HPKE (RFC 9180, mode_base) =====================
+
+/// KEM 套件标识 / KEM suite id
+const KEM_SUITE_ID: [u8; 5] = [b'K', b'E', b'M', 0x00, 0x20];
+/// HPKE 套件标识 / HPKE suite id
+const HPKE_SUITE_ID: [u8; 10] = [b'H', b'P', b'K', b'E', 0x00, 0x20, 0x00, 0x01, 0x00, 0x01];
+
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> [u8; 32] {
+    let labeled_ikm = [b"HPKE-v1".as_slice(), suite_id, label, ikm].concat();
+    Hkdf::<Sha256>::extract(Some(salt), &labeled_ikm).0.into()
+}
+
+fn labeled_expand(suite_id: &[u8], prk: &[u8; 32], label: &[u8], info: &[u8], out: &mut [u8]) {
+    let len = (out.len() as u16).to_be_bytes();
+    let labeled_info = [len.as_slice(), b"HPKE-v1", suite_id, label, info].concat();
+    Hkdf::<Sha256>::from_prk(prk)
+        .expect("prk has hash length")
+        .expand(&labeled_info, out)
+        .expect("output within hkdf limit");
+}
+
+/// DHKEM 共享密钥 / DHKEM shared secret
+fn extract_and_expand(dh: &[u8; 32], kem_context: &[u8]) -> [u8; 32] {
+    let eae_prk = labeled_extract(&KEM_SUITE_ID, b"", b"eae_prk", dh);
+    let mut shared = [0u8; 32];
+    labeled_expand(&KEM_SUITE_ID, &eae_prk, b"shared_secret", kem_context, &mut shared);
+    shared
+}
+
+/// HPKE 发送方上下文（只使用序号 0）/ HPKE sender context (sequence number 0 only)
+struct HpkeContext {
+    key: [u8; NK],
+    base_nonce: [u8; NN],
+    exporter_secret: [u8; 32],
+}
+
+impl HpkeContext {
+    fn key_schedule(shared_secret: &[u8; 32], info: &[u8]) -> Self {
+        let psk_id_hash = labeled_extract(&HPKE_SUITE_ID, b"", b"psk_id_hash", b"");
+        let info_hash = labeled_extract(&HPKE_SUITE_ID, b"", b"info_hash", info);
+        let context = [[0u8].as_slice(), &psk_id_hash, &info_hash].concat();
+        let secret = labeled_extract(&HPKE_SUITE_ID, shared_secret, b"secret", b"");
+        let mut ctx = Self { key: [0; NK], base_nonce: [0; NN], exporter_secret: [0; 32] };
+        labeled_expand(&HPKE_SUITE_ID, &secret, b"key", &context, &mut ctx.key);
+        labeled_expand(&HPKE_SUITE_ID, &secret, b"base_nonce", &context, &mut ctx.base_nonce);
+        labeled_expand(&HPKE_SUITE_ID, &secret, b"exp", &context, &mut ctx.exporter_secret);
+        ctx
+    }
+
+    /// SetupBaseS：返回封装密钥与上下文 / SetupBaseS: returns the encapsulated key and context
+    fn setup_sender(sk_e: [u8; 32], pk_r: &[u8; 32], info: &[u8]) -> Result<([u8; 32], Self)> {
+        let enc = x25519(sk_e, X25519_BASEPOINT_BYTES);
+        let dh = x25519(sk_e, *pk_r);
+        if dh == [0u8; 32] {
+            bail!("odoh target public key is invalid");
+        }
+        let kem_context = [enc.as_slice(), pk_r].concat();
+        Ok((enc, Self::key_schedule(&extract_and_expand(&dh, &kem_context), info)))
+    }
+
+    fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        aead_seal(&self.key, &self.base_nonce, aad, plaintext)
+    }
+
+    fn export(&self, exporter_context: &[u8], out: &mut [u8]) {
+        labeled_expand(&HPKE_SUITE_ID, &self.exporter_secret, b"sec", exporter_context, out);
+    }
+}
+
+fn aead_seal(key: &[u8; NK], nonce: &[u8; NN], aad: &[u8], msg: &[u8]) -> Result<Vec<u8>> {
+    Aes128Gcm::new(key.into())
+        .encrypt(Nonce::from_slice(nonce), Payload { msg, aad })
+        .map_err(|_| anyhow!("odoh encrypt failed"))
+}
+
+fn aead_open(key: &[u8; NK], nonce: &[u8; NN], aad: &[u8], msg: &[u8]) -> Result<Vec<u8>> {
+    Aes128Gcm::new(key.into())
+        .decrypt(Nonce::from_slice(nonce), Payload { msg, aad })
+        .map_err(|_| anyhow!("odoh response authentication failed"))
+}
+
+// ===================== ODoH messages =====================
+
+/// 目标解析器配置 / Target resolver config
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TargetConfig {
+    key_id: [u8; 32],
+    public_key: [u8; 32],
+}
+
+/// 读取 2 字节长度前缀字段 / Read a 2-byte length-prefixed field
+fn take_u16_lp<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8]> {
+    if buf.len() < 2 {
+        bail!("odoh message truncated");
+    }
+    let len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    if buf.len() < 2 + len {
+        bail!("odoh message truncated");
+    }
+    let field = &buf[2..2 + len];
+    *buf = &buf[2 + len..];
+    Ok(field)
+}
+
+fn push_u16_lp(out: &mut Vec<u8>, field: &[u8]) {
+    out.extend_from_slice(&(field.len() as u16).to_be_bytes());
+    out.extend_from_slice(field);
+}
+
+/// 从 ObliviousDoHConfigs 中选取支持的配置 / Pick a supported config from ObliviousDoHConfigs
+fn parse_configs(body: &[u8]) -> Result<TargetConfig> {
+    let mut rest = body;
+    let mut configs = take_u16_lp(&mut rest)?;
+    while !configs.is_empty() {
+        if configs.len() < 2 {
+            bail!("odoh configs truncated");
+        }
+        let version = u16::from_be_bytes([configs[0], configs[1]]);
+        configs = &configs[2..];
+        let contents = take_u16_lp(&mut configs)?;
+        if version != ODOH_VERSION || contents.len() < 8 {
+            continue;
+        }
+        let suite = |i: usize| u16::from_be_bytes([contents[i], contents[i + 1]]);
+        if (suite(0), suite(2), suite(4)) != (KEM_X25519_SHA256, KDF_SHA256, AEAD_AES128GCM) {
+            continue;
+        }
+        let mut pk = &contents[6..];
+        let Ok(public_key) = <[u8; 32]>::try_from(take_u16_lp(&mut pk)?) else {
+            continue;
+        };
+        let mut key_id = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(b""), contents)
+            .expand(b"odoh key id", &mut key_id)
+            .expect("output within hkdf limit");
+        return Ok(TargetConfig { key_id, public_key });
+    }
+    bail!("odoh target offers no supported config (X25519/HKDF-SHA256/AES-128-GCM)")
+}
+
+/// 已发送查询的解密状态 / Decryption state of a sent query
+struct PendingQuery {
+    context: HpkeContext,
+    plaintext: Vec<u8>,
+}
+
+/// 加密查询 / Encrypt a query
+fn encrypt_query(config: &TargetConfig, dns: &[u8], sk_e: [u8; 32]) -> Result<(Vec<u8>, PendingQuery)> {
+    let padding = (PADDING_BLOCK - (dns.len() + 4) % PADDING_BLOCK) % PADDING_BLOCK;
+    let mut plaintext = Vec::with_capacity(dns.len() + 4 + padding);
+    push_u16_lp(&mut plaintext, dns);
+    push_u16_lp(&mut plaintext, &vec![0u8; padding]);
+
+    let (enc, context) = HpkeContext::setup_sender(sk_e, &config.public_key, b"odoh query")?;
+    let mut aad = vec![MSG_QUERY];
+    push_u16_lp(&mut aad, &config.key_id);
+    let ct = context.seal(&aad, &plaintext)?;
+
+    let mut wire = aad;
+    push_u16_lp(&mut wire, &[enc.as_slice(), &ct].concat());
+    Ok((wire, PendingQuery { context, plaintext }))
+}
+
+/// 解密响应 / Decrypt a response
+fn decrypt_response(pending: &PendingQuery, wire: &[u8]) -> Result<Bytes> {
+    let (&msg_type, mut rest) = wire.split_first().ok_or_else(|| anyhow!("empty odoh response"))?;
+    if msg_type != MSG_RESPONSE {
+        bail!("unexpected odoh message type {}", msg_type);
+    }
+    let resp_nonce = take_u16_lp(&mut rest)?;
+    let ct = take_u16_lp(&mut rest)?;
+
+    let mut secret = [0u8; NK];
+    pending.context.export(b"odoh response", &mut secret);
+    let mut salt = pending.plaintext.clone();
+    push_u16_lp(&mut salt, resp_nonce);
+    let prk = Hkdf::<Sha256>::new(Some(&salt), &secret);
+    let mut key = [0u8; NK];
+    let mut nonce = [0u8; NN];
+    prk.expand(b"odoh key", &mut key).expect("output within hkdf limit");
+    prk.expand(b"odoh nonce", &mut nonce).expect("output within hkdf limit");
+
+    let mut aad = vec![MSG_RESPONSE];
+    push_u16_lp(&mut aad, resp_nonce);
+    let plaintext = aead_open(&key, &nonce, &aad, ct)?;
+    let mut plain = plaintext.as_slice();
+    Ok(Bytes::copy_from_slice(take_u16_lp(&mut plain)?))
+}
+
+// ===================== Client =====================
+
+/// 解析后的上游 / Parsed upstream
+pub(crate) struct OdohUpstream {
+    target_host: String,
+    target_path: String,
+    relay: Url,
+}
+
+pub(crate) fn parse_upstream(upstream: &str) -> Result<OdohUpstream> {
+    let rest = upstream.strip_prefix("odoh://").unwrap_or(upstream);
+    let url = Url::parse(&format!("https://{}", rest)).context("invalid odoh upstream")?;
+    let relay = url
+        .query_pairs()
+        .find(|(k, _)| k == "relay")
+        .map(|(_, v)| v.into_owned())
+        .ok_or_else(|| anyhow!("odoh upstream requires a relay (?relay=https://...)"))?;
+    let mut relay = Url::parse(&relay).context("invalid odoh relay url")?;
+    let target_host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    let target_path = match url.path() {
+        "" | "/" => "/dns-query".to_string(),
+        p => p.to_string(),
+    };
+    relay
+        .query_pairs_mut()
+        .append_pair("targethost", &target_host)
+        .append_pair("targetpath", &target_path);
+    Ok(OdohUpstream { target_host, target_path, relay })
+}
+
+/// ODoH 客户端，按目标缓存配置 / ODoH client caching configs per target
+pub struct OdohClient {
+    client: HttpClient,
+    configs: DashMap<String, (Arc<TargetConfig>, Instant)>,
+}
+
+impl OdohClient {
+    pub fn new(pool_max_idle_per_host: usize) -> Result<Self> {
+        let client = HttpClient::builder()
+            .http2_adaptive_window(true)
+            .tcp_keepalive(Duration::from_secs(60))
+            .pool_idle_timeout(Duration::from_secs(90))
+            .pool_max_idle_per_host(pool_max_idle_per_host.max(1))
+            .build()
+            .context("build odoh http client")?;
+        Ok(Self { client, configs: DashMap::new() })
+    }
+
+    /// 发送查询 / Send a query
+    pub async fn send(&self, packet: &[u8], upstream: &str, timeout_dur: Duration) -> Result<Bytes> {
+        let up = parse_upstream(upstream)?;
+        timeout(timeout_dur, async {
+            let config = self.config(&up.target_host).await?;
+            let mut sk_e = [0u8; 32];
+            OsRng.fill_bytes(&mut sk_e);
+            let (wire, pending) = encrypt_query(&config, packet, sk_e)?;
+
+            let resp = self
+                .client
+                .post(up.relay.clone())
+                .header(ACCEPT, ODOH_CONTENT_TYPE)
+                .header(CONTENT_TYPE, ODOH_CONTENT_TYPE)
+                .body(wire)
+                .send()
+                .await
+                .context("odoh relay request failed")?;
+            let status = resp.status();
+            if status == StatusCode::UNAUTHORIZED || status == StatusCode::BAD_REQUEST {
+                // 目标可能已轮换密钥 / The target may have rotated its key
+                self.configs.remove(&up.target_host);
+            }
+            if !status.is_success() {
+                bail!("odoh relay http status {status}");
+            }
+            let body = resp.bytes().await.context("read odoh response body")?;
+            decrypt_response(&pending, &body).inspect_err(|_| {
+                self.configs.remove(&up.target_host);
+            })
+        })
+        .await
+        .map_err(|_| anyhow!("odoh request timeout to {}{}", up.target_host, up.target_path))?
+    }
+
+    /// 预先获取目标配置 / Fetch the target config ahead of time
+    pub async fn prewarm(&self, upstream: &str, timeout_dur: Duration) -> Result<()> {
+        let up = parse_upstream(upstream)?;
+        timeout(timeout_dur, self.config(&up.target_host))
+            .await
+            .map_err(|_| anyhow!("odoh config fetch timeout"))?
+            .map(|_| ())
+    }
+
+    /// 取得目标配置，过期时重新获取 / Get the target config, refetching when stale
+    async fn config(&self, target_host: &str) -> Result<Arc<TargetConfig>> {
+        if let Some(entry) = self.configs.get(target_host)
+            && entry.1.elapsed() < CONFIG_REFRESH
+        {
+            return Ok(entry.0.clone());
+        }
+        let url = format!("https://{}/.well-known/odohconfigs", target_host);
+        let resp = self.client.get(&url).send().await.context("fetch odoh configs")?;
+        if !resp.status().is_success() {
+            bail!("odoh configs http status {}", resp.status());
+        }
+        let body = resp.bytes().await.context("read odoh configs")?;
+        let config = Arc::new(parse_configs(&body)?);
+        self.configs.insert(target_host.to_string(), (config.clone(), Instant::now()));
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_hpke_base_matches_rfc9180_vector() {
+        // Arrange: RFC 9180 A.1.1 (DHKEM(X25519), HKDF-SHA256, AES-128-GCM, mode_base)
+        let sk_e: [u8; 32] = hex("52c4a758a802cd8b936eceea314432798d5baf2d7e9235dc084ab1b9cfa2f736").try_into().unwrap();
+        let sk_r: [u8; 32] = hex("4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8").try_into().unwrap();
+        let pk_r = x25519(sk_r, X25519_BASEPOINT_BYTES);
+        let info = hex("4f6465206f6e2061204772656369616e2055726e");
+
+        // Act
+        let (enc, ctx) = HpkeContext::setup_sender(sk_e, &pk_r, &info).unwrap();
+
+        // Assert
+        assert_eq!(enc.to_vec(), hex("37fda3567bdbd628e88668c3c8d7e97d1d1253b6d4ea6d44c150f741f1bf4431"));
+        assert_eq!(ctx.key.to_vec(), hex("4531685d41d65f03dc48f6b8302c05b0"));
+        assert_eq!(ctx.base_nonce.to_vec(), hex("56d890e5accaaf011cff4b7d"));
+    }
+
+    #[test]
+    fn test_query_and_response_round_trip_through_target() {
+        // Arrange: target key pair and its published config
+        let sk_r = [3u8; 32];
+        let pk_r = x25519(sk_r, X25519_BASEPOINT_BYTES);
+        let mut contents = Vec::new();
+        for id in [KEM_X25519_SHA256, KDF_SHA256, AEAD_AES128GCM] {
+            contents.extend_from_slice(&id.to_be_bytes());
+        }
+        push_u16_lp(&mut contents, &pk_r);
+        let mut config_list = ODOH_VERSION.to_be_bytes().to_vec();
+        push_u16_lp(&mut config_list, &contents);
+        let mut configs = Vec::new();
+        push_u16_lp(&mut configs, &config_list);
+
+        // Act: client encrypts, target decrypts with SetupBaseR and answers
+        let config = parse_configs(&configs).unwrap();
+        let (wire, pending) = encrypt_query(&config, b"dns-query", [5u8; 32]).unwrap();
+        let mut rest = &wire[1..];
+        let key_id = take_u16_lp(&mut rest).unwrap();
+        let encrypted = take_u16_lp(&mut rest).unwrap();
+        let (enc, ct) = encrypted.split_at(32);
+        let dh = x25519(sk_r, enc.try_into().unwrap());
+        let shared = extract_and_expand(&dh, &[enc, &pk_r].concat());
+        let target_ctx = HpkeContext::key_schedule(&shared, b"odoh query");
+        let mut aad = vec![MSG_QUERY];
+        push_u16_lp(&mut aad, key_id);
+        let q_plain = aead_open(&target_ctx.key, &target_ctx.base_nonce, &aad, ct).unwrap();
+
+        let resp_nonce = [9u8; 16];
+        let mut secret = [0u8; NK];
+        target_ctx.export(b"odoh response", &mut secret);
+        let mut salt = q_plain.clone();
+        push_u16_lp(&mut salt, &resp_nonce);
+        let prk = Hkdf::<Sha256>::new(Some(&salt), &secret);
+        let (mut key, mut nonce) = ([0u8; NK], [0u8; NN]);
+        prk.expand(b"odoh key", &mut key).unwrap();
+        prk.expand(b"odoh nonce", &mut nonce).unwrap();
+        let mut r_aad = vec![MSG_RESPONSE];
+        push_u16_lp(&mut r_aad, &resp_nonce);
+        let mut r_plain = Vec::new();
+        push_u16_lp(&mut r_plain, b"dns-answer");
+        push_u16_lp(&mut r_plain, &[0u8; 6]);
+        let mut response = r_aad.clone();
+        push_u16_lp(&mut response, &aead_seal(&key, &nonce, &r_aad, &r_plain).unwrap());
+
+        // Assert
+        assert_eq!(key_id, config.key_id);
+        assert_eq!(q_plain.len() % PADDING_BLOCK, 0);
+        assert_eq!(&q_plain[2..11], b"dns-query");
+        assert_eq!(decrypt_response(&pending, &response).unwrap().as_ref(), b"dns-answer");
+    }
+}
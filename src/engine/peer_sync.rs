@@ -0,0 +1,573 @@
+//! 热备实例间的缓存与热点域名同步 / Cache and hot-domain sync between hot-standby instances
+//!
+//! 每隔 interval_secs 把最近写入的缓存条目（剩余 TTL 与原始报文）和本地查询量最高的域名推送给 peers；
+//! 在 listen 上接收对端推送，把本地尚无的条目按剩余 TTL 写入缓存，热点域名计入按域名统计的 peer_queries。
+//! 这样切换到备机时不必从冷缓存开始。缓存键直接沿用对端的哈希，两端应使用相同的 pipeline 配置。
+//! 接收端必须以共享 token 或双向 TLS 认证对端：使用 token 时每次推送带时间戳与随机 nonce，定长头与
+//! 快照各附一个以 token 为密钥的 HMAC-SHA256，接收端先校验头再读取按 max_entries 限制长度的快照，
+//! 拒绝时间偏差超过 60 秒或 nonce 重复的推送，因此截获的推送无法重放；报文的问题与条目声明的
+//! qname/qtype/qclass 不一致的条目丢弃，同步来的 TTL 不超过本地的 max_ttl。仅用 token 时快照为明文，
+//! 两端时钟需同步。
+//! Every interval_secs the most recently written cache entries (remaining TTL and raw packet)
+//! and the domains with the most local queries are pushed to the peers; pushes received on
+//! listen add entries the local cache lacks with their remaining TTL, and hot domains feed
+//! peer_queries of the per-domain statistics, so a failover does not start from a cold
+//! cache. Cache keys are the peer's hashes as-is, so both sides should run the same pipeline
+//! config. The receiver must authenticate the peer with a shared token or mutual TLS: with a
+//! token every push carries a timestamp and a random nonce, and both the fixed-size header and
+//! the snapshot carry an HMAC-SHA256 keyed with the token. The header is checked before the
+//! snapshot (size-limited by max_entries) is read, and pushes more than 60 seconds off or with
+//! a nonce already seen are rejected, so a captured push cannot be replayed. Entries whose
+//! packet question differs from their qname/qtype/qclass are dropped, and synced TTLs never
+//! exceed the local max_ttl. With only a token the snapshot travels in the clear, and both
+//! sides need synchronized clocks.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use bytes::Bytes;
+use crypto_box::aead::OsRng;
+use crypto_box::aead::rand_core::RngCore;
+use hickory_proto::op::ResponseCode;
+use moka::sync::Cache;
+use rustls::pki_types::ServerName;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::{debug, info, warn};
+
+use crate::cache::CacheEntry;
+use crate::config::PeerSyncSettings;
+use crate::tls_server;
+
+use super::Engine;
+
+/// 单个快照的最大长度 / Max size of one snapshot
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+/// 快照中缓存条目以外部分（热点域名等）的长度预算 / Size budget of a snapshot beyond its cache entries (hot domains and so on)
+const FRAME_BASE_SIZE: usize = 512 * 1024;
+/// 每个缓存条目的长度预算 / Size budget per cache entry
+const ENTRY_SIZE: usize = 4096;
+/// 同步的最大响应报文长度，更大的条目不推送 / Max synced response size, larger entries are not pushed
+const MAX_SYNCED_PACKET: usize = 2048;
+/// 定长头的长度：时间戳（8）、nonce（16）与快照长度（4）/ Length of the fixed-size header: timestamp (8), nonce (16) and snapshot length (4)
+const HEADER_LEN: usize = 28;
+/// HMAC-SHA256 标签的长度 / Length of an HMAC-SHA256 tag
+const TAG_LEN: usize = 32;
+/// 推送时间戳允许的最大偏差 / Max allowed skew of a push timestamp
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+/// 连接与读写超时 / Connect and I/O timeout
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+/// 每次同步的热点域名数 / Hot domains per sync
+const HOT_DOMAINS: usize = 1000;
+
+/// 一次同步推送 / One sync push
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PeerSnapshot {
+    #[serde(default)]
+    pub entries: Vec<PeerCacheEntry>,
+    /// (注册域名, 本地查询量) / (registered domain, local query count)
+    #[serde(default)]
+    pub hot_domains: Vec<(String, u64)>,
+}
+
+/// 同步的缓存条目 / A synced cache entry
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeerCacheEntry {
+    pub key: u64,
+    pub qname: String,
+    pub pipeline_id: String,
+    pub qtype: u16,
+    pub qclass: u16,
+    pub rcode: u16,
+    pub source: String,
+    pub upstream: Option<String>,
+    /// 在对端缓存中已停留的秒数 / Seconds already spent in the peer's cache
+    pub age_secs: u64,
+    pub original_ttl: u32,
+    pub refresh_ttl: u32,
+    /// Base64 编码的响应报文 / Base64-encoded response packet
+    pub packet: String,
+}
+
+/// 取最近写入且未过期的至多 max_entries 个缓存条目与热点域名
+/// Take up to max_entries of the most recently written unexpired cache entries plus the hot domains
+pub fn snapshot(engine: &Engine, max_entries: usize) -> PeerSnapshot {
+    let mut live: Vec<(u64, Arc<CacheEntry>)> = engine
+        .cache
+        .iter()
+        .filter(|(_, e)| e.inserted_at.elapsed().as_secs() < e.original_ttl as u64)
+        .map(|(k, e)| (*k, e))
+        .collect();
+    live.sort_by_key(|(_, e)| e.inserted_at.elapsed());
+    live.truncate(max_entries);
+    let entries = live
+        .into_iter()
+        .filter_map(|(key, e)| {
+            let packet = e.payload();
+            (packet.len() <= MAX_SYNCED_PACKET).then(|| (key, e, STANDARD.encode(packet)))
+        })
+        .map(|(key, e, packet)| PeerCacheEntry {
+            key,
+            qname: e.qname.to_string(),
+            pipeline_id: e.pipeline_id.to_string(),
+            qtype: e.qtype,
+            qclass: e.qclass,
+            rcode: u16::from(e.rcode),
+            source: e.source.to_string(),
+            upstream: e.upstream.as_deref().map(str::to_string),
+            age_secs: e.inserted_at.elapsed().as_secs(),
+            original_ttl: e.original_ttl,
+            refresh_ttl: e.refresh_ttl,
+            packet,
+        })
+        .collect();
+    let hot_domains = engine
+        .domain_stats
+        .as_ref()
+        .map(|s| s.hot_domains(HOT_DOMAINS))
+        .unwrap_or_default();
+    PeerSnapshot {
+        entries,
+        hot_domains,
+    }
+}
+
+/// 应用对端快照，返回写入的缓存条目数；TTL 截断到本地的 max_ttl（未设置时为 cache_max_ttl），
+/// 本地已有的键与剩余 TTL 为 0 的条目跳过，至多写入本地 max_entries 条
+/// Apply a peer snapshot and return the number of cache entries written; TTLs are capped at the
+/// local max_ttl (cache_max_ttl when unset), keys already cached locally and entries with no
+/// TTL left are skipped, and at most the local max_entries are written
+pub fn apply(engine: &Engine, mut snapshot: PeerSnapshot) -> usize {
+    let state = engine.state.load();
+    let cfg = &state.pipeline;
+    let threshold = engine.tunables.cache_compress_threshold();
+    snapshot.entries.truncate(cfg.settings.peer_sync.max_entries);
+    let mut written = 0;
+    for e in snapshot.entries {
+        let cap = match cfg.max_ttl_for(&e.pipeline_id) {
+            0 => cfg.settings.cache_max_ttl.min(u32::MAX as u64) as u32,
+            max_ttl => max_ttl,
+        };
+        let original_ttl = e.original_ttl.min(cap);
+        if e.age_secs >= original_ttl as u64 || engine.cache.contains_key(&e.key) {
+            continue;
+        }
+        let Ok(mut packet) = STANDARD.decode(&e.packet) else {
+            continue;
+        };
+        if !question_matches(&packet, &e) {
+            debug!(qname = %e.qname, "peer sync entry dropped: packet question differs");
+            continue;
+        }
+        if let Some(capped) = crate::proto_utils::cap_all_ttls(&packet, cap) {
+            packet = capped;
+        }
+        // 以对端的停留时间回拨插入时间，TTL 修正与过期判断与对端一致
+        // Back-date the insertion by the peer's residence time so TTL patching and expiry match the peer
+        let inserted_at = Instant::now()
+            .checked_sub(Duration::from_secs(e.age_secs))
+            .unwrap_or_else(Instant::now);
+        let entry = CacheEntry {
+            bytes: Bytes::from(packet),
+            compressed: false,
+            rcode: ResponseCode::from((e.rcode >> 4) as u8, (e.rcode & 0x0F) as u8),
+            source: Arc::from(e.source),
+            upstream: e.upstream.map(Arc::from),
+            qname: Arc::from(e.qname),
+            pipeline_id: Arc::from(e.pipeline_id),
+            qtype: e.qtype,
+            qclass: e.qclass,
+            inserted_at,
+            original_ttl,
+            refresh_ttl: e.refresh_ttl.min(cap),
+            aged: Default::default(),
+        };
+        engine.cache_insert(e.key, Arc::new(entry.compress_above(threshold)));
+        written += 1;
+    }
+    if let Some(stats) = engine.domain_stats.as_ref() {
+        for (domain, queries) in &snapshot.hot_domains {
+            stats.merge_peer(domain, *queries);
+        }
+    }
+    written
+}
+
+/// 报文的问题是否与条目声明的 qname/qtype/qclass 一致 / Whether the packet question matches the entry's qname/qtype/qclass
+fn question_matches(packet: &[u8], e: &PeerCacheEntry) -> bool {
+    let mut qname_buf = [0u8; 256];
+    crate::proto_utils::parse_quick(packet, &mut qname_buf).is_some_and(|q| {
+        q.qtype == e.qtype
+            && q.qclass == e.qclass
+            && q.qname_str_unchecked().trim_end_matches('.').eq_ignore_ascii_case(e.qname.trim_end_matches('.'))
+    })
+}
+
+/// 按配置启动同步监听与定时推送 / Start the sync listener and the periodic push per the settings
+pub fn spawn(engine: &Engine) -> anyhow::Result<()> {
+    let settings = engine.state.load().pipeline.settings.peer_sync.clone();
+    if let Some(listen) = settings.listen.as_deref() {
+        if settings.token.is_none() && settings.tls.is_none() {
+            anyhow::bail!("peer_sync.listen requires token or tls");
+        }
+        let bind: SocketAddr = listen.parse().context("parse peer_sync.listen")?;
+        let acceptor = match settings.tls.as_ref() {
+            Some(tls) => Some(tls_server::mutual(&tls.cert_path, &tls.key_path, &tls.ca_path)?.0),
+            None => None,
+        };
+        let engine = engine.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve(bind, acceptor, engine).await {
+                warn!(error = %err, "peer sync listener exited");
+            }
+        });
+    }
+    let engine = engine.clone();
+    tokio::spawn(async move {
+        loop {
+            let settings = engine.state.load().pipeline.settings.peer_sync.clone();
+            tokio::time::sleep(Duration::from_secs(settings.interval_secs.max(1))).await;
+            if !settings.peers.is_empty() {
+                push_all(&engine, &settings).await;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// 向所有对端推送一次快照 / Push one snapshot to every peer
+async fn push_all(engine: &Engine, settings: &PeerSyncSettings) {
+    let connector = match settings.tls.as_ref().map(|tls| tls_server::mutual(&tls.cert_path, &tls.key_path, &tls.ca_path)) {
+        Some(Ok((_, connector))) => Some(connector),
+        Some(Err(e)) => {
+            warn!(error = %format!("{:#}", e), "peer sync tls setup failed");
+            return;
+        }
+        None => None,
+    };
+    let snapshot = snapshot(engine, settings.max_entries);
+    let entries = snapshot.entries.len();
+    let body = match serde_json::to_vec(&snapshot) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!(error = %e, "peer sync snapshot encoding failed");
+            return;
+        }
+    };
+    let frame = encode_frame(settings.token.as_deref(), unix_secs(), &body);
+    for peer in &settings.peers {
+        match tokio::time::timeout(IO_TIMEOUT, push(peer, &frame, connector.as_ref())).await {
+            Ok(Ok(())) => debug!(peer = %peer, entries, "peer sync pushed"),
+            Ok(Err(e)) => warn!(peer = %peer, error = %e, "peer sync push failed"),
+            Err(_) => warn!(peer = %peer, "peer sync push timed out"),
+        }
+    }
+}
+
+async fn push(peer: &str, frame: &[u8], connector: Option<&TlsConnector>) -> anyhow::Result<()> {
+    let stream = TcpStream::connect(peer).await.with_context(|| format!("connect {}", peer))?;
+    let Some(connector) = connector else {
+        return write_frame(stream, frame).await;
+    };
+    let host = peer.rsplit_once(':').map_or(peer, |(host, _)| host).trim_matches(['[', ']']);
+    let name = ServerName::try_from(host.to_string()).with_context(|| format!("peer name {}", host))?;
+    let stream = connector.connect(name, stream).await.with_context(|| format!("tls handshake {}", peer))?;
+    write_frame(stream, frame).await
+}
+
+async fn write_frame<S: AsyncWrite + Unpin>(mut stream: S, frame: &[u8]) -> anyhow::Result<()> {
+    stream.write_all(frame).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// 接收对端推送 / Accept pushes from peers
+pub async fn serve(bind: SocketAddr, acceptor: Option<TlsAcceptor>, engine: Engine) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("bind peer sync {}", bind))?;
+    info!(bind = %bind, tls = acceptor.is_some(), "peer sync listener started");
+    let seen = seen_nonces();
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let engine = engine.clone();
+        let acceptor = acceptor.clone();
+        let seen = seen.clone();
+        tokio::spawn(async move {
+            let result = tokio::time::timeout(IO_TIMEOUT, async {
+                match acceptor {
+                    Some(acceptor) => receive(acceptor.accept(stream).await.context("tls handshake")?, &engine, &seen).await,
+                    None => receive(stream, &engine, &seen).await,
+                }
+            })
+            .await;
+            match result {
+                Ok(Ok(written)) => info!(peer = %peer, written, "peer sync applied"),
+                Ok(Err(e)) => warn!(peer = %peer, error = %e, "peer sync rejected"),
+                Err(_) => warn!(peer = %peer, "peer sync receive timed out"),
+            }
+        });
+    }
+}
+
+/// 已接受推送的 nonce，保留到其时间戳超出允许偏差 / Nonces of accepted pushes, kept until their timestamps fall outside the allowed skew
+type SeenNonces = Cache<[u8; 16], ()>;
+
+fn seen_nonces() -> SeenNonces {
+    Cache::builder()
+        .max_capacity(65536)
+        .time_to_live(MAX_CLOCK_SKEW * 2)
+        .build()
+}
+
+/// 推送帧：定长头、头标签、快照与快照标签；未配置 token 时标签全零（由双向 TLS 认证）
+/// Push frame: fixed-size header, header tag, snapshot and snapshot tag; without a token the tags are all zeros (mutual TLS authenticates instead)
+fn encode_frame(token: Option<&str>, timestamp: u64, body: &[u8]) -> Vec<u8> {
+    let mut nonce = [0u8; 16];
+    OsRng.fill_bytes(&mut nonce);
+    let mut header = [0u8; HEADER_LEN];
+    header[..8].copy_from_slice(&timestamp.to_be_bytes());
+    header[8..24].copy_from_slice(&nonce);
+    header[24..].copy_from_slice(&(body.len() as u32).to_be_bytes());
+    let (head_tag, body_tag) = match token {
+        Some(token) => (
+            hmac_sha256(token.as_bytes(), &[b"head", &header]),
+            hmac_sha256(token.as_bytes(), &[b"body", &header, body]),
+        ),
+        None => ([0u8; TAG_LEN], [0u8; TAG_LEN]),
+    };
+    let mut frame = Vec::with_capacity(HEADER_LEN + 2 * TAG_LEN + body.len());
+    frame.extend_from_slice(&header);
+    frame.extend_from_slice(&head_tag);
+    frame.extend_from_slice(body);
+    frame.extend_from_slice(&body_tag);
+    frame
+}
+
+/// 先校验定长头的标签、时间戳与 nonce，再读取长度受 max_entries 限制的快照并校验其标签
+/// Check the header tag, timestamp and nonce first, then read a snapshot whose size is limited by max_entries and check its tag
+async fn receive<S: AsyncRead + Unpin>(mut stream: S, engine: &Engine, seen: &SeenNonces) -> anyhow::Result<usize> {
+    let settings = engine.state.load().pipeline.settings.peer_sync.clone();
+    let token = settings.token.as_deref();
+    let mut header = [0u8; HEADER_LEN];
+    let mut tag = [0u8; TAG_LEN];
+    stream.read_exact(&mut header).await?;
+    stream.read_exact(&mut tag).await?;
+    let nonce: [u8; 16] = header[8..24].try_into().expect("nonce is 16 bytes");
+    if let Some(token) = token {
+        if !tag_matches(&hmac_sha256(token.as_bytes(), &[b"head", &header]), &tag) {
+            anyhow::bail!("token mismatch");
+        }
+        let timestamp = u64::from_be_bytes(header[..8].try_into().expect("timestamp is 8 bytes"));
+        if unix_secs().abs_diff(timestamp) > MAX_CLOCK_SKEW.as_secs() {
+            anyhow::bail!("timestamp outside the allowed clock skew");
+        }
+        if seen.contains_key(&nonce) {
+            anyhow::bail!("replayed push");
+        }
+    }
+    let len = u32::from_be_bytes(header[24..].try_into().expect("length is 4 bytes")) as usize;
+    let limit = max_frame_size(settings.max_entries);
+    if len > limit {
+        anyhow::bail!("snapshot too large: {} bytes (limit {})", len, limit);
+    }
+    let mut frame = vec![0u8; len];
+    stream.read_exact(&mut frame).await?;
+    stream.read_exact(&mut tag).await?;
+    if let Some(token) = token {
+        if !tag_matches(&hmac_sha256(token.as_bytes(), &[b"body", &header, &frame]), &tag) {
+            anyhow::bail!("snapshot tag mismatch");
+        }
+        seen.insert(nonce, ());
+    }
+    let snapshot: PeerSnapshot = serde_json::from_slice(&frame).context("decode snapshot")?;
+    Ok(apply(engine, snapshot))
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// 快照的长度上限 / Size limit of a snapshot
+fn max_frame_size(max_entries: usize) -> usize {
+    max_entries.saturating_mul(ENTRY_SIZE).saturating_add(FRAME_BASE_SIZE).min(MAX_FRAME_SIZE)
+}
+
+/// HMAC-SHA256（RFC 2104），消息为各部分依次拼接 / HMAC-SHA256 (RFC 2104) over the concatenated parts
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; TAG_LEN] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    for part in parts {
+        inner.update(part);
+    }
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/// 常数时间比较标签 / Constant-time comparison of tags
+fn tag_matches(expected: &[u8; TAG_LEN], got: &[u8; TAG_LEN]) -> bool {
+    expected.iter().zip(got).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::RuntimePipelineConfig;
+
+    fn engine() -> Engine {
+        let cfg: crate::config::PipelineConfig = serde_json::from_value(serde_json::json!({
+            "settings": { "domain_stats_enabled": true, "max_ttl": 120, "peer_sync": { "token": "secret" } },
+            "pipelines": []
+        }))
+        .unwrap();
+        Engine::new(RuntimePipelineConfig::from_config(cfg).unwrap(), "lbl".to_string())
+    }
+
+    /// NXDOMAIN 响应，问题为 qname/A/IN / NXDOMAIN response whose question is qname/A/IN
+    fn response(qname: &str) -> Bytes {
+        let mut packet = vec![0x12, 0x34, 0x81, 0x83, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in qname.split('.') {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+        packet.extend_from_slice(&[0, 0, 1, 0, 1]);
+        Bytes::from(packet)
+    }
+
+    fn entry(qname: &str, age_secs: u64) -> Arc<CacheEntry> {
+        Arc::new(CacheEntry {
+            bytes: response(qname),
+            compressed: false,
+            rcode: ResponseCode::NXDomain,
+            source: Arc::from("rule"),
+            upstream: Some(Arc::from("udp:1.1.1.1:53")),
+            qname: Arc::from(qname),
+            pipeline_id: Arc::from("main"),
+            qtype: 1,
+            qclass: 1,
+            inserted_at: Instant::now() - Duration::from_secs(age_secs),
+            original_ttl: 300,
+            refresh_ttl: 300,
+            aged: Default::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_warms_standby_cache() {
+        // Arrange
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let primary = engine();
+        let standby = engine();
+        primary.cache.insert(1, entry("fresh.example.com", 10));
+        primary.cache.insert(2, entry("expired.example.com", 400));
+        standby.cache.insert(3, entry("local.example.com", 0));
+        primary.domain_stats.as_ref().unwrap().record("www.example.com", 1, ResponseCode::NoError);
+        let mut snapshot = snapshot(&primary, 100);
+        let clone = |key: u64| -> PeerCacheEntry {
+            PeerCacheEntry { key, ..serde_json::from_value(serde_json::to_value(&snapshot.entries[0]).unwrap()).unwrap() }
+        };
+        let mut forged = clone(4);
+        forged.qname = "bank.example.com".to_string();
+        let (local, mut wrong_type) = (clone(3), clone(5));
+        wrong_type.qtype = 28;
+        snapshot.entries.extend([local, forged, wrong_type]);
+
+        // Act
+        let wire: PeerSnapshot = serde_json::from_slice(&serde_json::to_vec(&snapshot).unwrap()).unwrap();
+        let written = apply(&standby, wire);
+
+        // Assert
+        assert_eq!(written, 1);
+        let synced = standby.cache.get(&1).unwrap();
+        assert_eq!(&*synced.qname, "fresh.example.com");
+        assert_eq!(synced.rcode, ResponseCode::NXDomain);
+        assert!(synced.inserted_at.elapsed() >= Duration::from_secs(10));
+        assert_eq!(synced.original_ttl, 120);
+        assert!(standby.cache.get(&2).is_none());
+        assert_eq!(&*standby.cache.get(&3).unwrap().qname, "local.example.com");
+        assert!(standby.cache.get(&4).is_none());
+        assert!(standby.cache.get(&5).is_none());
+        assert_eq!(standby.domain_stats.as_ref().unwrap().report(false, 10, 0)[0].peer_queries, 1);
+    }
+
+    async fn push(frame: &[u8], engine: &Engine, seen: &SeenNonces) -> anyhow::Result<usize> {
+        let (mut tx, rx) = tokio::io::duplex(frame.len().max(64));
+        tx.write_all(frame).await.unwrap();
+        receive(rx, engine, seen).await
+    }
+
+    #[tokio::test]
+    async fn test_receive_checks_the_token_before_reading_the_snapshot() {
+        // Arrange: a wrong token, and a valid header announcing a huge snapshot that is never sent
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let standby = engine();
+        let seen = seen_nonces();
+        let wrong = encode_frame(Some("guess"), unix_secs(), b"{}");
+        let mut big = encode_frame(Some("secret"), unix_secs(), b"");
+        big[24..HEADER_LEN].copy_from_slice(&u32::MAX.to_be_bytes());
+        let tag = hmac_sha256(b"secret", &[b"head", &big[..HEADER_LEN]]);
+        big[HEADER_LEN..HEADER_LEN + TAG_LEN].copy_from_slice(&tag);
+
+        // Act
+        let wrong = push(&wrong[..HEADER_LEN + TAG_LEN], &standby, &seen).await;
+        let big = push(&big[..HEADER_LEN + TAG_LEN], &standby, &seen).await;
+
+        // Assert
+        assert_eq!(wrong.unwrap_err().to_string(), "token mismatch");
+        assert!(big.unwrap_err().to_string().starts_with("snapshot too large"));
+        assert_eq!(max_frame_size(10_000), 10_000 * ENTRY_SIZE + FRAME_BASE_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_receive_rejects_replayed_stale_and_tampered_pushes() {
+        // Arrange
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let standby = engine();
+        let seen = seen_nonces();
+        let body = serde_json::to_vec(&snapshot(&engine(), 10)).unwrap();
+        let fresh = encode_frame(Some("secret"), unix_secs(), &body);
+        let stale = encode_frame(Some("secret"), unix_secs() - 3600, &body);
+        let mut tampered = encode_frame(Some("secret"), unix_secs(), &body);
+        tampered[HEADER_LEN + TAG_LEN] ^= 1;
+
+        // Act
+        let first = push(&fresh, &standby, &seen).await;
+        let replayed = push(&fresh, &standby, &seen).await;
+        let stale = push(&stale, &standby, &seen).await;
+        let tampered = push(&tampered, &standby, &seen).await;
+
+        // Assert
+        assert_eq!(first.unwrap(), 0);
+        assert_eq!(replayed.unwrap_err().to_string(), "replayed push");
+        assert_eq!(stale.unwrap_err().to_string(), "timestamp outside the allowed clock skew");
+        assert_eq!(tampered.unwrap_err().to_string(), "snapshot tag mismatch");
+    }
+
+    #[test]
+    fn test_hmac_sha256_matches_rfc4231() {
+        // Arrange: RFC 4231 test case 2
+        let parts: [&[u8]; 2] = [b"what do ya want ", b"for nothing?"];
+
+        // Act
+        let tag = hmac_sha256(b"Jefe", &parts);
+
+        // Assert
+        let hex: String = tag.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+}
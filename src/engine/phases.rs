@@ -1,5 +1,6 @@
 use std::time::{Instant, Duration};
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use bytes::{Bytes, BytesMut};
 use hickory_proto::rr::{DNSClass, RecordType, Record};
 use hickory_proto::op::{Message, ResponseCode};
@@ -35,6 +36,8 @@ pub fn check_cache(
     qclass: DNSClass,
     pipeline_id: &str,
     dedupe_hash: u64,
+    edns_present: bool,
+    dnssec_flags: u8,
     tx_id: u16,
     start: Instant,
     peer: &std::net::SocketAddr,
@@ -42,13 +45,13 @@ pub fn check_cache(
     // moka 同步缓存自动处理过期，无需检查 expires_at / moka sync cache automatically handles expiration, no need to check expires_at
     if let Some(hit) = engine.cache.get(&dedupe_hash) {
         // Validate hit against query parameters to avoid collisions
-        if hit.qtype == u16::from(qtype) && hit.pipeline_id.as_ref() == pipeline_id && hit.qname.as_ref() == qname_ref {
+        if hit.qtype == u16::from(qtype) && hit.qclass == u16::from(qclass) && hit.pipeline_id.as_ref() == pipeline_id && hit.qname.as_ref() == qname_ref {
             let elapsed_secs = hit.inserted_at.elapsed().as_secs();
             
             // Check manual expiration (in case moka hasn't evicted it yet or for strict TTL compliance)
             if elapsed_secs >= hit.original_ttl as u64 {
                 // serve_stale disabled → invalidate and miss
-                if !engine.serve_stale {
+                if !engine.tunables.serve_stale() {
                     engine.cache.invalidate(&dedupe_hash);
                     return None;
                 }
@@ -61,7 +64,7 @@ pub fn check_cache(
                 // Check serve_stale_expire_ttl: how long past original TTL has this been stale?
                 // 检查 serve_stale_expire_ttl：此条目已过期多长时间？
                 let stale_age = elapsed_secs - hit.original_ttl as u64;
-                if engine.serve_stale_expire_ttl > 0 && stale_age > engine.serve_stale_expire_ttl {
+                if engine.tunables.serve_stale_expire_ttl() > 0 && stale_age > engine.tunables.serve_stale_expire_ttl() {
                     // Stale entry has exceeded the maximum stale window
                     // 过期条目已超过最大过期窗口
                     engine.cache.invalidate(&dedupe_hash);
@@ -70,7 +73,7 @@ pub fn check_cache(
                         qname = %qname_ref,
                         qtype = ?qtype,
                         stale_age = stale_age,
-                        serve_stale_expire_ttl = engine.serve_stale_expire_ttl,
+                        serve_stale_expire_ttl = engine.tunables.serve_stale_expire_ttl(),
                         "stale entry exceeded serve_stale_expire_ttl, invalidating"
                     );
                     return None;
@@ -80,7 +83,7 @@ pub fn check_cache(
                 // try upstream first with a short timeout (handled in handle_packet_internal).
                 // serve_stale_client_timeout_ms > 0: 不在此处返回 stale，
                 // 让调用者先尝试上游查询（在 handle_packet_internal 中处理）。
-                if engine.serve_stale_client_timeout_ms > 0 {
+                if engine.tunables.serve_stale_client_timeout_ms() > 0 {
                     // Don't invalidate - we still need the stale entry for the client_timeout path.
                     // Spawn background refresh proactively.
                     if let Some(upstream_ref) = hit.upstream.as_deref() {
@@ -90,6 +93,7 @@ pub fn check_cache(
                             qname_ref,
                             qtype,
                             qclass,
+                            dnssec_flags,
                             Some(upstream_ref),
                         );
                     }
@@ -98,9 +102,10 @@ pub fn check_cache(
 
                 // serve_stale_client_timeout_ms == 0: Serve stale immediately (optimistic mode)
                 // RFC 8767: 立即返回 stale 数据 + 后台刷新
-                let stale_ttl = engine.serve_stale_ttl;
-                let mut resp_bytes = BytesMut::with_capacity(hit.bytes.len());
-                resp_bytes.extend_from_slice(&hit.bytes);
+                let stale_ttl = engine.tunables.serve_stale_ttl();
+                let payload = hit.payload_for_client(edns_present, dnssec_flags);
+                let mut resp_bytes = BytesMut::with_capacity(payload.len());
+                resp_bytes.extend_from_slice(&payload);
                 
                 // Set all TTLs to serve_stale_ttl
                 crate::proto_utils::set_all_ttls(&mut resp_bytes, stale_ttl);
@@ -114,22 +119,25 @@ pub fn check_cache(
                 
                 // serve_stale_ttl_reset: reset stale expiry timer by re-inserting with shifted inserted_at
                 // 重置过期计时器：通过重新插入条目并将 inserted_at 设置为"刚过期"的时间点
-                if engine.serve_stale_ttl_reset {
+                if engine.tunables.serve_stale_ttl_reset() {
                     let new_entry = crate::cache::CacheEntry {
                         bytes: hit.bytes.clone(),
+                        compressed: hit.compressed,
                         rcode: hit.rcode,
                         source: hit.source.clone(),
                         upstream: hit.upstream.clone(),
                         qname: hit.qname.clone(),
                         pipeline_id: hit.pipeline_id.clone(),
                         qtype: hit.qtype,
+                        qclass: hit.qclass,
                         // Reset inserted_at so that stale_age starts from 0 again
                         // 重置 inserted_at 使 stale_age 从 0 重新开始
                         inserted_at: Instant::now() - Duration::from_secs(hit.original_ttl as u64),
                         original_ttl: hit.original_ttl,
                         refresh_ttl: hit.refresh_ttl,
+                        aged: Default::default(),
                     };
-                    engine.cache.insert(dedupe_hash, std::sync::Arc::new(new_entry));
+                    engine.cache_insert(dedupe_hash, std::sync::Arc::new(new_entry));
                 }
 
                 // Trigger background refresh to get fresh data
@@ -140,6 +148,7 @@ pub fn check_cache(
                         qname_ref,
                         qtype,
                         qclass,
+                        dnssec_flags,
                         Some(upstream_ref),
                     );
                 }
@@ -153,20 +162,22 @@ pub fn check_cache(
                     elapsed_secs = elapsed_secs,
                     stale_ttl = stale_ttl,
                     stale_age = stale_age,
-                    ttl_reset = engine.serve_stale_ttl_reset,
-                    client_ip = %peer.ip(),
+                    ttl_reset = engine.tunables.serve_stale_ttl_reset(),
+                    client_ip = %engine.privacy.client(peer.ip()),
                     pipeline = %pipeline_id,
                     "RFC 8767: serving stale cache entry on TTL expiry"
                 );
                 
+                engine.upstream_stats.record_cache_hit(hit.upstream.as_deref());
                 return Some(resp_bytes.freeze());
             } else {
                 // Cache hit is valid
                 let latency = start.elapsed();
                 
                 // clone bytes and rewrite transaction ID to match requester / 克隆字节并重写事务 ID 以匹配请求者
-                let mut resp_bytes = BytesMut::with_capacity(hit.bytes.len());
-                resp_bytes.extend_from_slice(&hit.bytes);
+                let payload = hit.payload_for_client(edns_present, dnssec_flags);
+                let mut resp_bytes = BytesMut::with_capacity(payload.len());
+                resp_bytes.extend_from_slice(&payload);
 
                 // RFC 1035 §5.2: Patch TTL based on residence time / 根据停留时间修正 TTL
                 let elapsed = elapsed_secs as u32;
@@ -216,6 +227,7 @@ pub fn check_cache(
                         qname_ref,
                         qtype,
                         qclass,
+                        dnssec_flags,
                         hit.upstream.as_deref(), // Pass upstream if available
                     );
                 }
@@ -230,12 +242,13 @@ pub fn check_cache(
                     refresh_ttl = hit.refresh_ttl,
                     elapsed_secs = elapsed,
                     latency_ms = latency.as_millis() as u64,
-                    client_ip = %peer.ip(),
+                    client_ip = %engine.privacy.client(peer.ip()),
                     pipeline = %pipeline_id,
                     cache = true,
                     "cache hit"
                 );
 
+                engine.upstream_stats.record_cache_hit(hit.upstream.as_deref());
                 return Some(resp_bytes);
             }
         }
@@ -254,15 +267,18 @@ pub fn check_stale_cache(
     qclass: DNSClass,
     pipeline_id: &str,
     dedupe_hash: u64,
+    edns_present: bool,
+    dnssec_flags: u8,
     tx_id: u16,
     peer: &std::net::SocketAddr,
 ) -> Option<Bytes> {
-    if !engine.serve_stale {
+    if !engine.tunables.serve_stale() {
         return None;
     }
 
     if let Some(hit) = engine.cache.get(&dedupe_hash) {
         if hit.qtype == u16::from(qtype)
+            && hit.qclass == u16::from(qclass)
             && hit.pipeline_id.as_ref() == pipeline_id
             && hit.qname.as_ref() == qname_ref
         {
@@ -279,14 +295,15 @@ pub fn check_stale_cache(
                 // Check serve_stale_expire_ttl: max stale age window
                 // 检查 serve_stale_expire_ttl：过期数据的最大可用窗口
                 let stale_age = elapsed_secs - hit.original_ttl as u64;
-                if engine.serve_stale_expire_ttl > 0 && stale_age > engine.serve_stale_expire_ttl {
+                if engine.tunables.serve_stale_expire_ttl() > 0 && stale_age > engine.tunables.serve_stale_expire_ttl() {
                     return None;
                 }
 
-                let stale_ttl = engine.serve_stale_ttl;
+                let stale_ttl = engine.tunables.serve_stale_ttl();
 
-                let mut resp_bytes = BytesMut::with_capacity(hit.bytes.len());
-                resp_bytes.extend_from_slice(&hit.bytes);
+                let payload = hit.payload_for_client(edns_present, dnssec_flags);
+                let mut resp_bytes = BytesMut::with_capacity(payload.len());
+                resp_bytes.extend_from_slice(&payload);
 
                 // RFC 8767 §4: Set all TTLs to serve_stale_ttl
                 crate::proto_utils::set_all_ttls(&mut resp_bytes, stale_ttl);
@@ -300,20 +317,23 @@ pub fn check_stale_cache(
 
                 // serve_stale_ttl_reset: reset stale expiry timer
                 // 重置过期计时器
-                if engine.serve_stale_ttl_reset {
+                if engine.tunables.serve_stale_ttl_reset() {
                     let new_entry = crate::cache::CacheEntry {
                         bytes: hit.bytes.clone(),
+                        compressed: hit.compressed,
                         rcode: hit.rcode,
                         source: hit.source.clone(),
                         upstream: hit.upstream.clone(),
                         qname: hit.qname.clone(),
                         pipeline_id: hit.pipeline_id.clone(),
                         qtype: hit.qtype,
+                        qclass: hit.qclass,
                         inserted_at: Instant::now() - Duration::from_secs(hit.original_ttl as u64),
                         original_ttl: hit.original_ttl,
                         refresh_ttl: hit.refresh_ttl,
+                        aged: Default::default(),
                     };
-                    engine.cache.insert(dedupe_hash, std::sync::Arc::new(new_entry));
+                    engine.cache_insert(dedupe_hash, std::sync::Arc::new(new_entry));
                 }
 
                 debug!(
@@ -325,7 +345,7 @@ pub fn check_stale_cache(
                     elapsed_secs = elapsed_secs,
                     stale_age = stale_age,
                     stale_ttl = stale_ttl,
-                    client_ip = %peer.ip(),
+                    client_ip = %engine.privacy.client(peer.ip()),
                     pipeline = %pipeline_id,
                     "RFC 8767: serving stale cache entry on upstream failure"
                 );
@@ -339,10 +359,12 @@ pub fn check_stale_cache(
                         qname_ref,
                         qtype,
                         qclass,
+                        dnssec_flags,
                         Some(upstream_ref),
                     );
                 }
 
+                engine.upstream_stats.record_cache_hit(hit.upstream.as_deref());
                 return Some(resp_bytes.freeze());
             }
         }
@@ -359,6 +381,7 @@ pub fn handle_static_decision(
     packet: &[u8],
     qname: &str,
     qtype: RecordType,
+    qclass: DNSClass,
     current_pipeline_id: &Arc<str>,
     dedupe_hash: u64,
     min_ttl: Duration,
@@ -374,17 +397,20 @@ pub fn handle_static_decision(
     if min_ttl > Duration::from_secs(0) {
         let entry = CacheEntry {
             bytes: resp_bytes.clone(),
+            compressed: false,
             rcode,
             source: Arc::from("static"),
             upstream: None,  // Static responses have no upstream
             qname: Arc::from(qname),
             pipeline_id: current_pipeline_id.clone(),
             qtype: u16::from(qtype),
+            qclass: u16::from(qclass),
             inserted_at: Instant::now(),
             original_ttl: min_ttl.as_secs() as u32,
             refresh_ttl: min_ttl.as_secs() as u32,
+            aged: Default::default(),
         };
-        engine.cache.insert(dedupe_hash, Arc::new(entry));
+        engine.cache_insert(dedupe_hash, Arc::new(entry));
     }
     
     let latency = start.elapsed();
@@ -395,7 +421,7 @@ pub fn handle_static_decision(
         qtype = ?qtype,
         rcode = ?rcode,
         latency_ms = latency.as_millis() as u64,
-        client_ip = %peer.ip(),
+        client_ip = %engine.privacy.client(peer.ip()),
         pipeline = %current_pipeline_id,
         cache = false,
         "static response"
@@ -416,6 +442,7 @@ pub async fn handle_forward_decision(
     pipeline_id: &str,
     rule_name: &str,
     dedupe_hash: u64,
+    dnssec_flags: u8,
     min_ttl: Duration,
     upstream_timeout: Duration,
     start: Instant,
@@ -433,18 +460,27 @@ pub async fn handle_forward_decision(
     reused_response: &mut Option<ResponseContext>,
 ) -> anyhow::Result<ForwardResult> {
     let mut cleanup_guard = None;
+    // 预取（skip_cache）与实时查询共享同一次上游交换，避免重复请求上游；预取会跳过响应动作，
+    // 因此仅在规则没有响应动作时才作为发起方，否则只加入已有的交换
+    // Prefetch (skip_cache) shares upstream exchanges with live queries so it never doubles
+    // upstream traffic. Prefetch skips response actions, so it only leads an exchange when the
+    // rule has none and otherwise just joins one already in flight
+    let may_lead_inflight =
+        !skip_cache || (response_actions_on_match.is_empty() && response_actions_on_miss.is_empty());
 
     let resp = if allow_reuse {
         if let Some(ctx) = reused_response.take() {
             Ok((ctx.raw, ctx.upstream.to_string()))
         } else {
-            if !skip_cache {
+            {
                 use dashmap::mapref::entry::Entry;
                 let rx = match engine.inflight.entry(dedupe_hash) {
                     Entry::Vacant(entry) => {
-                        let (tx, _rx) = tokio::sync::watch::channel(Err(Arc::new(anyhow::anyhow!("Pending"))));
-                        entry.insert(tx);
-                        cleanup_guard = Some(InflightCleanupGuard::new(engine.inflight.clone(), dedupe_hash));
+                        if may_lead_inflight {
+                            let (tx, _rx) = tokio::sync::watch::channel(Err(Arc::new(anyhow::anyhow!("Pending"))));
+                            entry.insert(tx);
+                            cleanup_guard = Some(InflightCleanupGuard::new(engine.inflight.clone(), dedupe_hash));
+                        }
                         None
                     }
                     Entry::Occupied(entry) => {
@@ -458,6 +494,7 @@ pub async fn handle_forward_decision(
                         let result = rx.borrow().clone();
                         match &result {
                             Ok(bytes) => {
+                                engine.metrics_coalesced.fetch_add(1, Ordering::Relaxed);
                                 let mut resp_mut = BytesMut::from(bytes.as_ref());
                                 if resp_mut.len() >= 2 {
                                     let id_bytes = tx_id.to_be_bytes();
@@ -474,13 +511,15 @@ pub async fn handle_forward_decision(
             crate::engine::upstream::forward_upstream(engine, packet, upstream, upstream_timeout, transport, pre_split_upstreams).await
         }
     } else {
-        if !skip_cache {
+        {
             use dashmap::mapref::entry::Entry;
              let rx = match engine.inflight.entry(dedupe_hash) {
                 Entry::Vacant(entry) => {
-                    let (tx, _rx) = tokio::sync::watch::channel(Err(Arc::new(anyhow::anyhow!("Pending"))));
-                    entry.insert(tx);
-                    cleanup_guard = Some(InflightCleanupGuard::new(engine.inflight.clone(), dedupe_hash));
+                    if may_lead_inflight {
+                        let (tx, _rx) = tokio::sync::watch::channel(Err(Arc::new(anyhow::anyhow!("Pending"))));
+                        entry.insert(tx);
+                        cleanup_guard = Some(InflightCleanupGuard::new(engine.inflight.clone(), dedupe_hash));
+                    }
                     None
                 }
                 Entry::Occupied(entry) => {
@@ -495,6 +534,7 @@ pub async fn handle_forward_decision(
                     let result = rx.borrow().clone();
                     match &result {
                         Ok(bytes) => {
+                            engine.metrics_coalesced.fetch_add(1, Ordering::Relaxed);
                             let mut resp_mut = BytesMut::from(bytes.as_ref());
                             if resp_mut.len() >= 2 {
                                 let id_bytes = tx_id.to_be_bytes();
@@ -513,37 +553,27 @@ pub async fn handle_forward_decision(
 
     match resp {
         Ok((raw, actual_upstream)) => {
-            let (rcode, ttl_secs_cache, ttl_secs_refresh, msg_opt, truncated) = if response_matchers.is_empty() && response_actions_on_match.is_empty() && response_actions_on_miss.is_empty() {
+            let raw = engine.transform_upstream_response(pipeline_id, raw);
+            let (rcode, ttl_secs_cache, ttl_secs_refresh, msg_opt) = if response_matchers.is_empty() && response_actions_on_match.is_empty() && response_actions_on_miss.is_empty() {
                 if let Some(qr) = proto_utils::parse_response_quick(&raw) {
-                    (qr.rcode, qr.min_ttl as u64, qr.max_ttl as u64, None, qr.truncated)
+                    (qr.rcode, qr.min_ttl as u64, qr.max_ttl as u64, None)
                 } else {
                     let msg = Message::from_bytes(&raw).context("parse upstream response")?;
                     let ttl_cache = extract_ttl(&msg);
                     let ttl_refresh = extract_ttl_for_refresh(&msg);
-                    let tc = raw.len() >= 3 && (raw[2] & 0x02) != 0;
-                    (msg.response_code(), ttl_cache, ttl_refresh, Some(msg), tc)
+                    (msg.response_code(), ttl_cache, ttl_refresh, Some(msg))
                 }
             } else {
                 let msg = Message::from_bytes(&raw).context("parse upstream response")?;
                 let ttl_cache = extract_ttl(&msg);
                 let ttl_refresh = extract_ttl_for_refresh(&msg);
-                let tc = raw.len() >= 3 && (raw[2] & 0x02) != 0;
-                (msg.response_code(), ttl_cache, ttl_refresh, Some(msg), tc)
+                (msg.response_code(), ttl_cache, ttl_refresh, Some(msg))
             };
 
-            // 检查 TCP fallback 配置 / Check TCP fallback configuration
-            let enable_tcp_fallback = engine.state.load().pipeline.settings.enable_tcp_fallback;
-            if truncated && transport == Some(Transport::Udp) && enable_tcp_fallback {
-                tracing::debug!(event = "tc_flag_retry", upstream = %upstream, "response truncated, retrying with tcp");
-                drop(cleanup_guard);
-                let (tcp_resp, _) = crate::engine::upstream::forward_upstream(engine, packet, upstream, upstream_timeout, Some(Transport::Tcp), pre_split_upstreams).await?;
-                if let Some(_g) = engine.inflight.get(&dedupe_hash) {
-                    engine.notify_inflight_waiters(dedupe_hash, &tcp_resp).await;
-                }
-                return Ok(ForwardResult::Success(tcp_resp));
-            }
+            let cache_ttl = engine.cache_ttl(&raw, rcode, ttl_secs_cache);
+            let ttl_secs_cache = cache_ttl.secs();
 
-            let effective_ttl = Duration::from_secs(ttl_secs_cache.max(min_ttl.as_secs()));
+            let effective_ttl = engine.cache_lifetime(pipeline_id, cache_ttl, min_ttl);
 
             // Try to acquire read locks non-blockingly (fast path for concurrent reads)
             // 尝试非阻塞获取读锁（并发读的快速路径）
@@ -602,6 +632,7 @@ pub async fn handle_forward_decision(
                         qname,
                         Arc::from(pipeline_id),
                         qtype,
+                        qclass,
                         ttl_secs_cache as u32,
                         ttl_secs_refresh as u32,
                     );
@@ -616,7 +647,7 @@ pub async fn handle_forward_decision(
                     qtype = ?qtype,
                     rcode = ?rcode,
                     latency_ms = start.elapsed().as_millis() as u64,
-                    client_ip = %peer.ip(),
+                    client_ip = %engine.privacy.client(peer.ip()),
                     pipeline = %pipeline_id,
                     cache = effective_ttl > Duration::from_secs(0),
                     resp_match = resp_match_ok,
@@ -634,11 +665,12 @@ pub async fn handle_forward_decision(
                 msg,
                 upstream: Arc::from(actual_upstream.as_str()),
                 transport: transport.unwrap_or(Transport::Udp),
+                latency: start.elapsed(),
             };
 
             let state = engine.state.load(); // Load state for config access
             let default_upstream = state.pipeline.settings.default_upstream.as_str();
-            let response_jump_limit = state.pipeline.settings.response_jump_limit as usize;
+            let response_jump_limit = state.pipeline.response_jump_limit_for(pipeline_id);
 
             let ctx = rules::ApplyResponseActionsContext {
                 engine,
@@ -656,15 +688,17 @@ pub async fn handle_forward_decision(
                 pipeline_id,
                 rule_name,
                 remaining_jumps: response_jump_limit,
+                cache_hash: dedupe_hash,
             };
 
             let action_result = rules::apply_response_actions(ctx).await?;
 
             match action_result {
                 ResponseActionResult::Upstream { ctx, resp_match: _ } => {
-                    let ttl_secs_cache = extract_ttl(&ctx.msg); 
+                    let cache_ttl = engine.cache_ttl(&ctx.raw, ctx.msg.response_code(), extract_ttl(&ctx.msg));
+                    let ttl_secs_cache = cache_ttl.secs();
                     let ttl_secs_refresh = extract_ttl_for_refresh(&ctx.msg);
-                    let effective_ttl = Duration::from_secs(ttl_secs_cache.max(min_ttl.as_secs()));
+                    let effective_ttl = engine.cache_lifetime(pipeline_id, cache_ttl, min_ttl);
                      if effective_ttl > Duration::from_secs(0) {
                         engine.insert_dns_cache_entry(
                             dedupe_hash,
@@ -675,6 +709,7 @@ pub async fn handle_forward_decision(
                             qname,
                             Arc::from(pipeline_id),
                             qtype,
+                            qclass,
                             ttl_secs_cache as u32,
                             ttl_secs_refresh as u32,
                         );
@@ -694,6 +729,7 @@ pub async fn handle_forward_decision(
                             qname,
                             Arc::from(pipeline_id),
                             qtype,
+                            qclass,
                             min_ttl.as_secs() as u32,
                             min_ttl.as_secs() as u32,
                         );
@@ -702,7 +738,7 @@ pub async fn handle_forward_decision(
                     engine.notify_inflight_waiters(dedupe_hash, &bytes).await;
                     Ok(ForwardResult::Success(bytes))
                 },
-                ResponseActionResult::Jump { pipeline, remaining_jumps } => {
+                ResponseActionResult::Jump { pipeline, remaining_jumps, ctx } => {
                      let edns_present = proto_utils::parse_quick(packet, &mut [0u8; 256]).map(|p| p.edns_present).unwrap_or(false);
                      
                      let resp_bytes = rules::process_response_jump(
@@ -719,7 +755,8 @@ pub async fn handle_forward_decision(
                         edns_present,
                         min_ttl,
                         upstream_timeout,
-                        skip_cache
+                        skip_cache,
+                        ctx,
                      ).await?;
                      
                      if let Some(g) = cleanup_guard.as_mut() { g.defuse(); }
@@ -749,6 +786,8 @@ pub async fn handle_forward_decision(
                      qclass,
                      pipeline_id,
                      dedupe_hash,
+                     proto_utils::parse_quick(packet, &mut [0u8; 256]).is_some_and(|p| p.edns_present),
+                     dnssec_flags,
                      tx_id,
                      peer,
                  ) {
@@ -757,7 +796,7 @@ pub async fn handle_forward_decision(
                          upstream = %upstream,
                          qname = %qname,
                          qtype = ?qtype,
-                         client_ip = %peer.ip(),
+                         client_ip = %engine.privacy.client(peer.ip()),
                          pipeline = %pipeline_id,
                          error = %e,
                          "RFC 8767: upstream failed, serving stale cache"
@@ -774,7 +813,7 @@ pub async fn handle_forward_decision(
                     qname = %qname,
                     qtype = ?qtype,
                     rcode = ?rcode,
-                    client_ip = %peer.ip(),
+                    client_ip = %engine.privacy.client(peer.ip()),
                     error = %e,
                     pipeline = %pipeline_id,
                     transport = ?transport,
@@ -811,7 +850,7 @@ pub async fn handle_forward_decision(
                  };
                  let state = engine.state.load();
                  let default_upstream = state.pipeline.settings.default_upstream.as_str();
-                 let response_jump_limit = state.pipeline.settings.response_jump_limit as usize;
+                 let response_jump_limit = state.pipeline.response_jump_limit_for(pipeline_id);
 
                  let ctx = rules::ApplyResponseActionsContext {
                      engine,
@@ -829,14 +868,16 @@ pub async fn handle_forward_decision(
                      pipeline_id,
                      rule_name,
                      remaining_jumps: response_jump_limit,
+                     cache_hash: dedupe_hash,
                  };
                  let action_result = rules::apply_response_actions(ctx).await?;
 
                 match action_result {
                     ResponseActionResult::Upstream { ctx, resp_match: _ } => {
-                        let ttl_secs_cache = extract_ttl(&ctx.msg); 
+                        let cache_ttl = engine.cache_ttl(&ctx.raw, ctx.msg.response_code(), extract_ttl(&ctx.msg));
+                        let ttl_secs_cache = cache_ttl.secs();
                         let ttl_secs_refresh = extract_ttl_for_refresh(&ctx.msg);
-                        let effective_ttl = Duration::from_secs(ttl_secs_cache.max(min_ttl.as_secs()));
+                        let effective_ttl = engine.cache_lifetime(pipeline_id, cache_ttl, min_ttl);
                         if effective_ttl > Duration::from_secs(0) {
                             engine.insert_dns_cache_entry(
                                 dedupe_hash,
@@ -847,6 +888,7 @@ pub async fn handle_forward_decision(
                                 qname,
                                 Arc::from(pipeline_id),
                                 qtype,
+                                qclass,
                                 ttl_secs_cache as u32,
                                 ttl_secs_refresh as u32,
                             );
@@ -866,6 +908,7 @@ pub async fn handle_forward_decision(
                                 qname,
                                 Arc::from(pipeline_id),
                                 qtype,
+                                qclass,
                                 min_ttl.as_secs() as u32,
                                 min_ttl.as_secs() as u32,
                             );
@@ -874,7 +917,7 @@ pub async fn handle_forward_decision(
                         engine.notify_inflight_waiters(dedupe_hash, &bytes).await;
                         Ok(ForwardResult::Success(bytes))
                     },
-                    ResponseActionResult::Jump { pipeline, remaining_jumps } => {
+                    ResponseActionResult::Jump { pipeline, remaining_jumps, ctx } => {
                         let edns_present = proto_utils::parse_quick(packet, &mut [0u8; 256]).map(|p| p.edns_present).unwrap_or(false);
                         let req = if let Ok(r) = Message::from_bytes(packet) { r } else { Message::new() };
 
@@ -892,7 +935,8 @@ pub async fn handle_forward_decision(
                             edns_present,
                             min_ttl,
                             upstream_timeout,
-                            skip_cache
+                            skip_cache,
+                            ctx,
                         ).await?;
                         
                         if let Some(g) = cleanup_guard.as_mut() { g.defuse(); }
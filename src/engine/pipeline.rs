@@ -7,9 +7,9 @@ use smallvec::SmallVec;
 use hickory_proto::rr::{DNSClass, RData, Record, RecordType};
 use hickory_proto::rr::rdata::{A, AAAA, TXT};
 use hickory_proto::op::ResponseCode;
-use tracing::info;
+use tracing::{debug, info};
 
-use crate::config::{Action, Transport};
+use crate::config::{Action, ListenerTransport, Transport};
 use crate::lock::RwLock;
 use crate::matcher::{RuntimePipeline, RuntimePipelineConfig, eval_match_chain};
 use crate::matcher::advanced_rule::CompiledPipeline;
@@ -30,6 +30,7 @@ pub fn select_pipeline<'a>(
     edns_present: bool,
     qtype: RecordType,
     listener_label: &str,
+    transport: ListenerTransport,
     geosite_manager: Option<&Arc<RwLock<GeoSiteManager>>>,
     geoip_manager: Option<&Arc<RwLock<GeoIpManager>>>,
 ) -> (Option<&'a RuntimePipeline>, Arc<str>) {
@@ -47,6 +48,7 @@ pub fn select_pipeline<'a>(
             |m| {
                 m.matcher.matches_with_ready_managers(
                     listener_label,
+                    transport,
                     client_ip,
                     qname,
                     qclass,
@@ -89,34 +91,10 @@ impl Engine {
         include_ip: bool,
     ) {
         let state = self.state.load();
-        let ttl = match &decision {
-            Decision::Static { answers, .. } => {
-                let min_ttl = answers.iter().map(|r| r.ttl()).min();
-                min_ttl.map(|t| Duration::from_secs(t as u64))
-            }
-            Decision::Forward {
-                response_matchers,
-                response_actions_on_match,
-                response_actions_on_miss,
-                ..
-            } => {
-                // If it has response-phase logic, it is not "static" in the user's terms.
-                // It should expire based on the configured min_ttl.
-                if !response_matchers.is_empty()
-                    || !response_actions_on_match.is_empty()
-                    || !response_actions_on_miss.is_empty()
-                {
-                    Some(Duration::from_secs(state.pipeline.settings.min_ttl as u64))
-                } else {
-                    None // Permanent
-                }
-            }
-            _ => {
-                // Jump, Allow, Deny: 120秒 TTL（之前是永久）
-                // Jump, Allow, Deny: 120 second TTL (previously permanent)
-                Some(Duration::from_secs(120))
-            }
-        };
+        if !state.pipeline.rule_cacheable_for(&pipeline_id) {
+            return;
+        }
+        let ttl = rule_cache_ttl(&decision, state.pipeline.settings.min_ttl);
 
         // If TTL is 0, do not cache / 如果 TTL 为 0，则不缓存
         if let Some(d) = ttl {
@@ -155,9 +133,49 @@ impl Engine {
         skip_rules: Option<&HashSet<Arc<str>>>,
         skip_cache: bool,
     ) -> Decision {
+        let (decision, delay_ms) = self.evaluate_rules(
+            state,
+            pipeline,
+            client_ip,
+            qname,
+            qtype,
+            qclass,
+            edns_present,
+            skip_rules,
+            skip_cache,
+        );
+        if delay_ms == 0 {
+            return decision;
+        }
+        // 用带延迟的决策覆盖规则缓存，缓存命中时同样延迟
+        // Overwrite the rule cache with the delayed decision so cache hits are delayed too
+        let d = Decision::Delay {
+            ms: delay_ms,
+            then: Box::new(decision),
+        };
+        let include_ip = pipeline.uses_client_ip || self.tunables.cache_background_refresh();
+        let rule_hash = calculate_rule_hash(&pipeline.id, qname, qtype, qclass, client_ip, include_ip);
+        self.insert_rule_cache(rule_hash, pipeline.id.clone(), qname, qtype, qclass, client_ip, d.clone(), include_ip);
+        d
+    }
+
+    /// 执行规则，同时返回 Delay 动作累加的毫秒数 / Run the rules, returning the milliseconds added up by Delay actions
+    #[allow(clippy::too_many_arguments)]
+    fn evaluate_rules(
+        &self,
+        state: &EngineInner,
+        pipeline: &RuntimePipeline,
+        client_ip: IpAddr,
+        qname: &str,
+        qtype: RecordType,
+        qclass: DNSClass,
+        edns_present: bool,
+        skip_rules: Option<&HashSet<Arc<str>>>,
+        skip_cache: bool,
+    ) -> (Decision, u64) {
         // 1. Check Rule Cache
         // Use hash for lookup to avoid cloning String for key on every lookup
-        let include_ip = pipeline.uses_client_ip || self.cache_background_refresh;
+        let include_ip = pipeline.uses_client_ip || self.tunables.cache_background_refresh();
         let rule_hash = calculate_rule_hash(&pipeline.id, qname, qtype, qclass, client_ip, include_ip);
         let allow_rule_cache_lookup = !skip_cache && skip_rules.is_none_or(|set| set.is_empty());
         
@@ -168,12 +186,13 @@ impl Engine {
                 if !entry.is_valid() {
                     self.rule_cache.remove(&rule_hash);
                 } else if entry.matches(&pipeline.id, qname, qtype, qclass, client_ip, include_ip) {
-                    return (*entry.decision).clone();
+                    return ((*entry.decision).clone(), 0);
                 }
             }
         }
 
         let upstream_default = state.pipeline.settings.default_upstream.clone();
+        let mut delay_ms = 0u64;
 
         // 2. Candidate Selection (compiled index if available)
         // SmallVec<[usize; 32]> avoids heap allocation for typical rule sets (<= 32 candidates)
@@ -198,19 +217,8 @@ impl Engine {
                 candidate_indices.extend_from_slice(indices);
             }
 
-            let mut search_name = qname;
-            loop {
-                // 零拷贝优化：Arc<str>可以通过&str查找 / Zero-copy: Arc<str> can be looked up by &str
-                if let Some(indices) = pipeline.domain_suffix_index.get(search_name) {
-                    candidate_indices.extend_from_slice(indices);
-                }
-
-                if let Some(idx) = search_name.find('.') {
-                    search_name = &search_name[idx + 1..];
-                } else {
-                    break;
-                }
-            }
+            // 后缀字典树：一次遍历 qname 的标签 / Suffix trie: one pass over the qname labels
+            pipeline.domain_suffix_index.collect_into(qname, &mut candidate_indices);
 
             candidate_indices.sort_unstable();
             candidate_indices.dedup();
@@ -227,7 +235,11 @@ impl Engine {
             qtype,
             geoip_manager: Some(&self.geoip_manager),
             geosite_manager: Some(&self.geosite_manager),
+            regex_set: pipeline.regex_set.as_deref(),
+            regex_hits: Default::default(),
         };
+        // 放行名单只需检查一次 / Allowlist only needs to be checked once
+        let allowlisted = state.pipeline.allowlist.contains(qname);
 
         'rules: for idx in candidate_indices {
             let rule = match pipeline.rules.get(idx) {
@@ -248,6 +260,17 @@ impl Engine {
             );
 
             if req_match {
+                // 放行名单优先于拦截规则 / Allowlist overrides blocking rules
+                if allowlisted && rule.actions.iter().any(Action::is_block) {
+                    debug!(
+                        event = "allowlist_override",
+                        rule = %rule.name,
+                        qname = %qname,
+                        "block rule skipped by allowlist"
+                    );
+                    continue 'rules;
+                }
+
                 // 检查是否有多个 forward action / Check for multiple forward actions
                 let forward_actions: Vec<_> = rule.actions.iter()
                     .filter_map(|a| match a {
@@ -265,6 +288,9 @@ impl Engine {
                     let mut doh_upstreams: HashSet<String> = HashSet::new();
                     let mut dot_upstreams: HashSet<String> = HashSet::new();
                     let mut doq_upstreams: HashSet<String> = HashSet::new();
+                    let mut dnscrypt_upstreams: HashSet<String> = HashSet::new();
+                    let mut odoh_upstreams: HashSet<String> = HashSet::new();
+                    let mut mock_upstreams: HashSet<String> = HashSet::new();
 
                     // 收集并按 transport 分组，同时去重
                     for (upstream_opt, transport_opt, _pre_split) in forward_actions.iter() {
@@ -289,6 +315,12 @@ impl Engine {
                                         dot_upstreams.insert(addr.to_string());
                                     } else if addr.starts_with("doq://") || addr.starts_with("quic://") {
                                         doq_upstreams.insert(addr.to_string());
+                                    } else if addr.starts_with("dnscrypt://") || addr.starts_with("sdns://") {
+                                        dnscrypt_upstreams.insert(addr.to_string());
+                                    } else if addr.starts_with("odoh://") {
+                                        odoh_upstreams.insert(addr.to_string());
+                                    } else if addr.starts_with("mock://") {
+                                        mock_upstreams.insert(addr.to_string());
                                     }
                                 } else {
                                     // 添加协议前缀
@@ -313,6 +345,15 @@ impl Engine {
                                         Transport::Doq => {
                                             doq_upstreams.insert(format!("doq://{}", addr));
                                         }
+                                        Transport::Dnscrypt => {
+                                            dnscrypt_upstreams.insert(format!("dnscrypt://{}", addr));
+                                        }
+                                        Transport::Odoh => {
+                                            odoh_upstreams.insert(format!("odoh://{}", addr));
+                                        }
+                                        Transport::Mock => {
+                                            mock_upstreams.insert(format!("mock://{}", addr));
+                                        }
                                     }
                                 }
                             }
@@ -326,6 +367,9 @@ impl Engine {
                     all_upstreams.extend(doh_upstreams.iter().map(|s| std::sync::Arc::from(s.as_str())));
                     all_upstreams.extend(dot_upstreams.iter().map(|s| std::sync::Arc::from(s.as_str())));
                     all_upstreams.extend(doq_upstreams.iter().map(|s| std::sync::Arc::from(s.as_str())));
+                    all_upstreams.extend(dnscrypt_upstreams.iter().map(|s| std::sync::Arc::from(s.as_str())));
+                    all_upstreams.extend(odoh_upstreams.iter().map(|s| std::sync::Arc::from(s.as_str())));
+                    all_upstreams.extend(mock_upstreams.iter().map(|s| std::sync::Arc::from(s.as_str())));
 
                     if all_upstreams.is_empty() {
                         // 所有 upstream 都为空，使用默认
@@ -343,12 +387,17 @@ impl Engine {
                             doh_count = doh_upstreams.len(),
                             dot_count = dot_upstreams.len(),
                             doq_count = doq_upstreams.len(),
+                            dnscrypt_count = dnscrypt_upstreams.len(),
+                            odoh_count = odoh_upstreams.len(),
+                            mock_count = mock_upstreams.len(),
                             total_upstreams = all_upstreams.len(),
                             tcp_upstreams = ?tcp_upstreams,
                             udp_upstreams = ?udp_upstreams,
                             doh_upstreams = ?doh_upstreams,
                             dot_upstreams = ?dot_upstreams,
                             doq_upstreams = ?doq_upstreams,
+                            dnscrypt_upstreams = ?dnscrypt_upstreams,
+                            odoh_upstreams = ?odoh_upstreams,
                             "merged multiple forward actions with transport-specific deduplication"
                         );
                     }
@@ -382,7 +431,7 @@ impl Engine {
                         d.clone(),
                         include_ip,
                     );
-                    return d;
+                    return (d, delay_ms);
                 }
 
                 // 单个 forward 或其他 action：按原逻辑处理 / Single forward or other actions: use original logic
@@ -404,7 +453,7 @@ impl Engine {
                                 d.clone(),
                                 include_ip,
                             );
-                            return d;
+                            return (d, delay_ms);
                         }
                         Action::StaticIpResponse { ip } => {
                             if let Ok(ip_addr) = ip.parse::<IpAddr>() {
@@ -428,7 +477,7 @@ impl Engine {
                                         d.clone(),
                                         include_ip,
                                     );
-                                    return d;
+                                    return (d, delay_ms);
                                 }
                             }
                             let d = Decision::Static {
@@ -445,9 +494,9 @@ impl Engine {
                                 d.clone(),
                                 include_ip,
                             );
-                            return d;
+                            return (d, delay_ms);
                         }
-                        Action::JumpToPipeline { pipeline: target } => {
+                        Action::JumpToPipeline { pipeline: target, .. } => {
                             let d = Decision::Jump {
                                 pipeline: Arc::from(target.as_str()),
                             };
@@ -461,7 +510,7 @@ impl Engine {
                                 d.clone(),
                                 include_ip,
                             );
-                            return d;
+                            return (d, delay_ms);
                         }
                         Action::Allow => {
                             let d = Decision::Forward {
@@ -487,7 +536,7 @@ impl Engine {
                                 d.clone(),
                                 include_ip,
                             );
-                            return d;
+                            return (d, delay_ms);
                         }
                         Action::Deny => {
                             let d = Decision::Static {
@@ -504,7 +553,7 @@ impl Engine {
                                 d.clone(),
                                 include_ip,
                             );
-                            return d;
+                            return (d, delay_ms);
                         }
                         Action::Forward {
                             upstream,
@@ -540,10 +589,27 @@ impl Engine {
                                 d.clone(),
                                 include_ip,
                             );
-                            return d;
+                            return (d, delay_ms);
                         }
-                        Action::Log { level } => {
-                                super::matcher_adapter::log_match(level.as_deref(), &rule.name, qname, client_ip);
+                        Action::Log { level, template, target, mark } => {
+                            let fields = super::rule_log::LogFields {
+                                qname,
+                                qtype,
+                                client: self.privacy.client(client_ip),
+                                upstream: None,
+                                rcode: None,
+                                latency_ms: None,
+                                mark: mark.as_deref(),
+                                matched_rule: &rule.name,
+                            };
+                            super::rule_log::log_rule(
+                                level.as_deref(),
+                                template.as_deref(),
+                                target,
+                                &fields,
+                                Some(&pipeline.query_log),
+                                rule.actions.iter().any(Action::is_block),
+                            );
                         }
                         Action::StaticTxtResponse { text, ttl } => {
                             if let Ok(name) = std::str::FromStr::from_str(qname) {
@@ -564,7 +630,7 @@ impl Engine {
                                     d.clone(),
                                     include_ip,
                                 );
-                                return d;
+                                return (d, delay_ms);
                             }
                             let d = Decision::Static {
                                 rcode: ResponseCode::ServFail,
@@ -580,11 +646,37 @@ impl Engine {
                                 d.clone(),
                                 include_ip,
                             );
-                            return d;
+                            return (d, delay_ms);
                         }
                         Action::ReplaceTxtResponse { .. } => {
                             continue 'rules;
                         }
+                        Action::RewriteRcode { .. }
+                        | Action::RetryTcp
+                        | Action::QuarantineUpstream { .. }
+                        | Action::SetHeaderFlags { .. } => {}
+                        Action::Delay { ms } => {
+                            delay_ms += ms;
+                        }
+                        Action::ReselectPipeline { listener_label } => {
+                            // 选择结果取决于全部 pipeline_select 条件，不写入规则缓存
+                            // The selection depends on every pipeline_select condition, so it is not rule-cached
+                            let (_, selected) = select_pipeline(
+                                &state.pipeline,
+                                qname,
+                                client_ip,
+                                qclass,
+                                edns_present,
+                                qtype,
+                                listener_label,
+                                self.listener_transport,
+                                Some(&self.geosite_manager),
+                                Some(&self.geoip_manager),
+                            );
+                            if selected != pipeline.id {
+                                return (Decision::Jump { pipeline: selected }, delay_ms);
+                            }
+                        }
                         Action::Continue => {
                             continue 'rules;
                         }
@@ -616,7 +708,40 @@ impl Engine {
             d.clone(),
             include_ip,
         );
-        d
+        (d, delay_ms)
+    }
+}
+
+/// 规则缓存条目的有效期，None 表示永久 / Lifetime of a rule cache entry, None means permanent
+fn rule_cache_ttl(decision: &Decision, min_ttl: u32) -> Option<Duration> {
+    match decision {
+        Decision::Static { answers, .. } => {
+            let min_ttl = answers.iter().map(|r| r.ttl()).min();
+            min_ttl.map(|t| Duration::from_secs(t as u64))
+        }
+        Decision::Forward {
+            response_matchers,
+            response_actions_on_match,
+            response_actions_on_miss,
+            ..
+        } => {
+            // If it has response-phase logic, it is not "static" in the user's terms.
+            // It should expire based on the configured min_ttl.
+            if !response_matchers.is_empty()
+                || !response_actions_on_match.is_empty()
+                || !response_actions_on_miss.is_empty()
+            {
+                Some(Duration::from_secs(min_ttl as u64))
+            } else {
+                None // Permanent
+            }
+        }
+        Decision::Delay { then, .. } => rule_cache_ttl(then, min_ttl),
+        _ => {
+            // Jump, Allow, Deny: 120秒 TTL（之前是永久）
+            // Jump, Allow, Deny: 120 second TTL (previously permanent)
+            Some(Duration::from_secs(120))
+        }
     }
 }
 
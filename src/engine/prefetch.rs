@@ -0,0 +1,274 @@
+//! 预取执行器 / Prefetch executor
+//!
+//! 后台刷新产生的 PrefetchJob 进入有界队列，由固定数量的工作任务执行：按任务所属 pipeline
+//! （而不是按回环地址重新选择）执行规则并查询上游，结果随正常路径写入缓存。
+//! 队列已满时丢弃新任务，各结果计入统计。
+//! PrefetchJobs produced by background refresh go into a bounded queue drained by a fixed
+//! number of worker tasks. Each job is resolved through its owning pipeline (instead of
+//! re-selecting one for the loopback address), so its rules apply, and the result lands in
+//! the cache through the normal path. New jobs are dropped when the queue is full, and every
+//! outcome is counted in the stats.
+//!
+//! `prefetch_qtypes` 可按查询类型关闭刷新、覆盖最小 TTL、连带刷新同名的其它类型（如随 A 刷新
+//! HTTPS），或只在探测到 IPv6 连通时刷新（如 AAAA）。
+//! `prefetch_qtypes` can turn refreshing off per query type, override the minimum TTL, refresh
+//! other types of the same name alongside (e.g. HTTPS with A), or only refresh while IPv6
+//! connectivity is detected (e.g. AAAA).
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::{DNSClass, RecordType};
+use serde_json::{Value, json};
+use tokio::sync::{Mutex, mpsc};
+use tracing::{debug, warn};
+
+use crate::config::PrefetchMode;
+
+use super::Engine;
+use super::ipv6_probe::Ipv6Probe;
+use super::utils::RefreshingGuard;
+
+/// 编译后的单个查询类型预取策略 / Compiled prefetch policy of one query type
+#[derive(Debug, Clone)]
+pub struct PrefetchPolicy {
+    pub mode: PrefetchMode,
+    pub min_ttl: Option<u32>,
+    /// 连带刷新的类型 / Types refreshed alongside
+    pub with: Vec<RecordType>,
+}
+
+/// 一次预取任务 / A single prefetch job
+#[derive(Debug, Clone)]
+pub struct PrefetchJob {
+    pub cache_hash: u64,
+    /// 所属 pipeline / Owning pipeline
+    pub pipeline_id: Arc<str>,
+    pub qname: String,
+    pub qtype: RecordType,
+    pub qclass: DNSClass,
+    /// 原请求的 DO/CD 标志位，预取结果写回同一缓存键 / DO/CD bits of the original request, so the result lands in the same cache key
+    pub dnssec_flags: u8,
+}
+
+/// 预取任务结果 / Outcome of a prefetch job
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefetchOutcome {
+    /// 得到 NOERROR / NXDOMAIN 响应 / Got a NOERROR / NXDOMAIN response
+    Refreshed,
+    /// 上游返回其它 rcode / Upstream returned another rcode
+    ErrorResponse,
+    /// 构造或执行失败 / Building or resolving failed
+    Failed,
+}
+
+type QueuedJob = (Engine, PrefetchJob, RefreshingGuard);
+
+/// 预取执行器 / Prefetch executor
+#[derive(Debug)]
+pub struct PrefetchExecutor {
+    workers: usize,
+    queue_size: usize,
+    /// 首次提交时启动工作任务 / Workers are started on the first submission
+    tx: OnceLock<mpsc::Sender<QueuedJob>>,
+    submitted: AtomicU64,
+    dropped: AtomicU64,
+    refreshed: AtomicU64,
+    error_responses: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl PrefetchExecutor {
+    pub fn new(workers: usize, queue_size: usize) -> Self {
+        Self {
+            workers: workers.max(1),
+            queue_size: queue_size.max(1),
+            tx: OnceLock::new(),
+            submitted: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            refreshed: AtomicU64::new(0),
+            error_responses: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+
+    /// 提交任务；队列已满时丢弃并返回 false
+    /// Submit a job; drops it and returns false when the queue is full
+    ///
+    /// 任务携带引擎副本，因此工作任务不持有引擎，引擎释放后队列排空即退出。
+    /// Jobs carry the engine, so workers never hold one themselves and exit once the
+    /// engine is gone and the queue has drained.
+    pub fn submit(&self, engine: &Engine, job: PrefetchJob, guard: RefreshingGuard) -> bool {
+        let tx = self.tx.get_or_init(|| self.start_workers());
+        match tx.try_send((engine.clone(), job, guard)) {
+            Ok(()) => {
+                self.submitted.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(e) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                let (_, job, _) = e.into_inner();
+                debug!(
+                    event = "prefetch_job_dropped",
+                    qname = %job.qname,
+                    qtype = ?job.qtype,
+                    pipeline_id = %job.pipeline_id,
+                    "prefetch queue full"
+                );
+                false
+            }
+        }
+    }
+
+    fn start_workers(&self) -> mpsc::Sender<QueuedJob> {
+        let (tx, rx) = mpsc::channel::<QueuedJob>(self.queue_size);
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..self.workers {
+            let rx = Arc::clone(&rx);
+            tokio::spawn(async move {
+                loop {
+                    let next = rx.lock().await.recv().await;
+                    let Some((engine, job, _guard)) = next else {
+                        break;
+                    };
+                    let outcome = engine.run_prefetch_job(&job).await;
+                    engine.prefetch.record(outcome);
+                    // _guard 在此处 drop，清除刷新标记 / _guard dropped here, clearing the refresh mark
+                }
+            });
+        }
+        tx
+    }
+
+    fn record(&self, outcome: PrefetchOutcome) {
+        let counter = match outcome {
+            PrefetchOutcome::Refreshed => &self.refreshed,
+            PrefetchOutcome::ErrorResponse => &self.error_responses,
+            PrefetchOutcome::Failed => &self.failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 统计快照 / Statistics snapshot
+    pub fn snapshot(&self) -> Value {
+        let queued = self
+            .tx
+            .get()
+            .map_or(0, |tx| tx.max_capacity() - tx.capacity());
+        json!({
+            "workers": self.workers,
+            "queue_size": self.queue_size,
+            "queued": queued,
+            "submitted": self.submitted.load(Ordering::Relaxed),
+            "dropped": self.dropped.load(Ordering::Relaxed),
+            "refreshed": self.refreshed.load(Ordering::Relaxed),
+            "error_responses": self.error_responses.load(Ordering::Relaxed),
+            "failed": self.failed.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// 按查询类型策略判断当前是否允许后台刷新 / Whether the qtype policy currently allows a background refresh
+pub fn allows(policy: Option<&PrefetchPolicy>, ipv6: &Ipv6Probe, ipv6_probe_addr: &str) -> bool {
+    match policy.map_or(PrefetchMode::Always, |p| p.mode) {
+        PrefetchMode::Always => true,
+        PrefetchMode::Never => false,
+        PrefetchMode::Ipv6Reachable => ipv6.reachable(ipv6_probe_addr),
+    }
+}
+
+impl Engine {
+    /// 通过所属 pipeline 执行一次预取 / Resolve a prefetch job through its owning pipeline
+    pub(crate) async fn run_prefetch_job(&self, job: &PrefetchJob) -> PrefetchOutcome {
+        let packet = match self.construct_dns_packet(&job.qname, job.qtype, job.qclass, job.dnssec_flags) {
+            Ok(pkt) => pkt,
+            Err(e) => {
+                warn!(
+                    event = "prefetch_construct_packet_failed",
+                    qname = %job.qname,
+                    qtype = ?job.qtype,
+                    error = %e,
+                    "failed to construct DNS packet for prefetch"
+                );
+                return PrefetchOutcome::Failed;
+            }
+        };
+
+        match self.resolve_for_prefetch(&packet, job).await {
+            Ok(resp) => {
+                let rcode = resp
+                    .get(3)
+                    .map_or(ResponseCode::ServFail, |b| ResponseCode::from_low(b & 0x0F));
+                debug!(
+                    event = "prefetch_done",
+                    qname = %job.qname,
+                    qtype = ?job.qtype,
+                    pipeline_id = %job.pipeline_id,
+                    rcode = ?rcode,
+                    "prefetch completed"
+                );
+                if matches!(rcode, ResponseCode::NoError | ResponseCode::NXDomain) {
+                    PrefetchOutcome::Refreshed
+                } else {
+                    PrefetchOutcome::ErrorResponse
+                }
+            }
+            Err(e) => {
+                warn!(
+                    event = "prefetch_failed",
+                    qname = %job.qname,
+                    qtype = ?job.qtype,
+                    pipeline_id = %job.pipeline_id,
+                    error = %e,
+                    "prefetch failed"
+                );
+                PrefetchOutcome::Failed
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outcomes_are_counted_separately() {
+        // Arrange
+        let executor = PrefetchExecutor::new(0, 0);
+
+        // Act
+        executor.record(PrefetchOutcome::Refreshed);
+        executor.record(PrefetchOutcome::Refreshed);
+        executor.record(PrefetchOutcome::ErrorResponse);
+        executor.record(PrefetchOutcome::Failed);
+        let snapshot = executor.snapshot();
+
+        // Assert
+        assert_eq!(snapshot["workers"], 1);
+        assert_eq!(snapshot["queue_size"], 1);
+        assert_eq!(snapshot["queued"], 0);
+        assert_eq!(snapshot["refreshed"], 2);
+        assert_eq!(snapshot["error_responses"], 1);
+        assert_eq!(snapshot["failed"], 1);
+    }
+
+    #[test]
+    fn test_qtype_policies_gate_refreshes() {
+        // Arrange
+        let ipv6 = Ipv6Probe::new();
+        let policy = |mode| PrefetchPolicy { mode, min_ttl: None, with: Vec::new() };
+
+        // Act
+        let default = allows(None, &ipv6, "[::1]:53");
+        let never = allows(Some(&policy(PrefetchMode::Never)), &ipv6, "[::1]:53");
+        let unreachable = allows(Some(&policy(PrefetchMode::Ipv6Reachable)), &ipv6, "not-an-address");
+
+        // Assert
+        assert!(default);
+        assert!(!never);
+        assert!(!unreachable);
+    }
+}
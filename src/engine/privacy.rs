@@ -0,0 +1,145 @@
+//! 隐私模式 / Privacy mode
+//!
+//! 日志中的客户端地址按配置原样输出、截断为网段或加盐哈希；哈希盐值在引擎创建时随机生成，
+//! 同一引擎内同一地址的哈希保持一致，便于关联而无法还原。统计报告可省略低频域名。
+//! Client addresses in logs are written as is, truncated to a prefix, or salted-hashed per
+//! configuration. The salt is random per engine, so hashes stay stable within an engine for
+//! correlation but cannot be reversed. Statistics reports can omit rare domains.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use aes_gcm::aead::OsRng;
+use aes_gcm::aead::rand_core::RngCore;
+use arc_swap::ArcSwap;
+use ipnet::IpNet;
+use sha2::{Digest, Sha256};
+
+use crate::config::{ClientIpPrivacy, PrivacySettings};
+
+/// 引擎的隐私配置与哈希盐值 / Privacy settings and hash salt of an engine
+#[derive(Debug)]
+pub struct Privacy {
+    settings: ArcSwap<PrivacySettings>,
+    salt: [u8; 16],
+}
+
+impl Privacy {
+    pub fn new(settings: &PrivacySettings) -> Self {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        Self {
+            settings: ArcSwap::from_pointee(settings.clone()),
+            salt,
+        }
+    }
+
+    /// 重载时应用新的隐私配置，盐值保持不变 / Apply new privacy settings on reload; the salt is kept
+    pub fn apply(&self, settings: &PrivacySettings) {
+        self.settings.store(Arc::new(settings.clone()));
+    }
+
+    /// 统计报告中域名的最小查询数 / Minimum queries for a domain to appear in reports
+    #[inline]
+    pub fn min_qname_count(&self) -> u64 {
+        self.settings.load().min_qname_count
+    }
+
+    /// 包装客户端地址用于日志 / Wrap a client address for logging
+    #[inline]
+    pub fn client(&self, ip: IpAddr) -> ClientIp<'_> {
+        ClientIp(ip, self)
+    }
+}
+
+/// 按隐私配置输出的客户端地址，仅在日志实际格式化时计算
+/// Client address rendered per the privacy settings, computed only when a log line is formatted
+#[derive(Clone, Copy)]
+pub struct ClientIp<'a>(pub IpAddr, &'a Privacy);
+
+impl fmt::Display for ClientIp<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_client(f, self.0, &self.1.settings.load(), &self.1.salt)
+    }
+}
+
+fn write_client(f: &mut impl fmt::Write, ip: IpAddr, settings: &PrivacySettings, salt: &[u8; 16]) -> fmt::Result {
+    match settings.client_ip {
+        ClientIpPrivacy::Full => write!(f, "{}", ip),
+        ClientIpPrivacy::Truncate => {
+            let prefix = match ip {
+                IpAddr::V4(_) => settings.ipv4_prefix.min(32),
+                IpAddr::V6(_) => settings.ipv6_prefix.min(128),
+            };
+            match IpNet::new(ip, prefix) {
+                Ok(net) => write!(f, "{}", net.trunc()),
+                Err(_) => write!(f, "{}", ip),
+            }
+        }
+        ClientIpPrivacy::Hash => {
+            let mut hasher = Sha256::new();
+            hasher.update(salt);
+            match ip {
+                IpAddr::V4(v4) => hasher.update(v4.octets()),
+                IpAddr::V6(v6) => hasher.update(v6.octets()),
+            }
+            for b in &hasher.finalize()[..8] {
+                write!(f, "{:02x}", b)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(ip: &str, settings: &PrivacySettings) -> String {
+        let mut out = String::new();
+        write_client(&mut out, ip.parse().unwrap(), settings, &[7u8; 16]).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_client_ip_modes() {
+        // Arrange
+        let full = PrivacySettings::default();
+        let truncate = PrivacySettings { client_ip: ClientIpPrivacy::Truncate, ..PrivacySettings::default() };
+        let hash = PrivacySettings { client_ip: ClientIpPrivacy::Hash, ..PrivacySettings::default() };
+
+        // Act
+        let hashed = render("192.0.2.77", &hash);
+
+        // Assert
+        assert_eq!(render("192.0.2.77", &full), "192.0.2.77");
+        assert_eq!(render("192.0.2.77", &truncate), "192.0.2.0/24");
+        assert_eq!(render("2001:db8:aa:bbcc::1", &truncate), "2001:db8:aa:bb00::/56");
+        assert_eq!(hashed, render("192.0.2.77", &hash));
+        assert_ne!(hashed, render("192.0.2.78", &hash));
+        assert_eq!(hashed.len(), 16);
+    }
+
+    #[test]
+    fn test_each_instance_keeps_its_own_settings() {
+        // Arrange
+        let ip: IpAddr = "192.0.2.77".parse().unwrap();
+        let full = Privacy::new(&PrivacySettings::default());
+        let hashed = Privacy::new(&PrivacySettings { client_ip: ClientIpPrivacy::Hash, ..PrivacySettings::default() });
+        let before = hashed.client(ip).to_string();
+
+        // Act
+        hashed.apply(&PrivacySettings {
+            client_ip: ClientIpPrivacy::Hash,
+            min_qname_count: 5,
+            ..PrivacySettings::default()
+        });
+
+        // Assert
+        assert_eq!(full.client(ip).to_string(), "192.0.2.77");
+        assert_eq!(full.min_qname_count(), PrivacySettings::default().min_qname_count);
+        assert_eq!(hashed.client(ip).to_string(), before);
+        assert_eq!(hashed.min_qname_count(), 5);
+    }
+}
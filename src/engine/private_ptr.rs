@@ -0,0 +1,155 @@
+//! 私有地址反向解析本地应答 / Local answers for private-space reverse lookups
+//!
+//! RFC 1918、CGNAT (100.64.0.0/10)、回环、链路本地与 IPv6 ULA 地址的 PTR 查询转发到公共解析器
+//! 既泄露内网地址又白白增加延迟（RFC 6303）。启用后此类 in-addr.arpa / ip6.arpa 查询在本地应答：
+//! 直接返回 NXDOMAIN，或为完整地址合成形如 `ip-10-0-0-1.<suffix>` 的名称。
+//! PTR queries for RFC 1918, CGNAT (100.64.0.0/10), loopback, link-local and IPv6 ULA
+//! addresses leak internal addressing to public resolvers and only add latency (RFC 6303).
+//! When enabled, such in-addr.arpa / ip6.arpa queries are answered locally, either with
+//! NXDOMAIN or with a name synthesized as `ip-10-0-0-1.<suffix>` for complete addresses.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::PTR;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+
+use crate::config::{PrivatePtrMode, PrivatePtrSettings};
+
+/// 本地应答的地址段 / Address ranges answered locally
+fn private_nets() -> [IpNet; 9] {
+    [
+        IpNet::V4(Ipv4Net::new(Ipv4Addr::new(10, 0, 0, 0), 8).unwrap()),
+        IpNet::V4(Ipv4Net::new(Ipv4Addr::new(172, 16, 0, 0), 12).unwrap()),
+        IpNet::V4(Ipv4Net::new(Ipv4Addr::new(192, 168, 0, 0), 16).unwrap()),
+        IpNet::V4(Ipv4Net::new(Ipv4Addr::new(100, 64, 0, 0), 10).unwrap()),
+        IpNet::V4(Ipv4Net::new(Ipv4Addr::new(127, 0, 0, 0), 8).unwrap()),
+        IpNet::V4(Ipv4Net::new(Ipv4Addr::new(169, 254, 0, 0), 16).unwrap()),
+        IpNet::V6(Ipv6Net::new(Ipv6Addr::LOCALHOST, 128).unwrap()),
+        IpNet::V6(Ipv6Net::new(Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0), 7).unwrap()),
+        IpNet::V6(Ipv6Net::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10).unwrap()),
+    ]
+}
+
+/// 解析反向域名为网段；完整地址的前缀长度为 32/128
+/// Parse a reverse name into a network; complete addresses have a /32 or /128 prefix
+pub(crate) fn parse_reverse(qname: &str) -> Option<IpNet> {
+    let name = qname.trim_end_matches('.').to_ascii_lowercase();
+    if let Some(rest) = name.strip_suffix(".in-addr.arpa") {
+        let mut octets = [0u8; 4];
+        let labels: Vec<&str> = rest.split('.').collect();
+        if labels.len() > 4 {
+            return None;
+        }
+        for (i, label) in labels.iter().rev().enumerate() {
+            if label.is_empty() || (label.len() > 1 && label.starts_with('0')) {
+                return None;
+            }
+            octets[i] = label.parse().ok()?;
+        }
+        return Ipv4Net::new(Ipv4Addr::from(octets), (labels.len() * 8) as u8)
+            .ok()
+            .map(IpNet::V4);
+    }
+    if let Some(rest) = name.strip_suffix(".ip6.arpa") {
+        let mut addr = 0u128;
+        let labels: Vec<&str> = rest.split('.').collect();
+        if labels.len() > 32 {
+            return None;
+        }
+        for (i, label) in labels.iter().rev().enumerate() {
+            if label.len() != 1 {
+                return None;
+            }
+            let nibble = u8::from_str_radix(label, 16).ok()? as u128;
+            addr |= nibble << (124 - i * 4);
+        }
+        return Ipv6Net::new(Ipv6Addr::from(addr), (labels.len() * 4) as u8)
+            .ok()
+            .map(IpNet::V6);
+    }
+    None
+}
+
+/// 以 '-' 连接的地址，可用作标签 / The address joined by '-', usable as a label
+pub(crate) fn dashed(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => v4.to_string().replace('.', "-"),
+        IpAddr::V6(v6) => v6.to_string().replace(':', "-"),
+    }
+}
+
+/// 合成名称 / Synthesized name
+fn synthesized_name(ip: IpAddr, suffix: &str) -> Option<Name> {
+    let host = dashed(ip);
+    Name::from_str(&format!("ip-{}.{}.", host, suffix.trim_end_matches('.'))).ok()
+}
+
+/// 私有地址反向查询的本地应答，不适用时返回 None
+/// Local answer for a private-space reverse query, or None when the query is not one
+pub(crate) fn answer(
+    settings: &PrivatePtrSettings,
+    qname: &str,
+    qtype: u16,
+) -> Option<(ResponseCode, Vec<Record>)> {
+    if settings.mode == PrivatePtrMode::Off {
+        return None;
+    }
+    let net = parse_reverse(qname)?;
+    if !private_nets().iter().any(|private| private.contains(&net)) {
+        return None;
+    }
+    let complete = net.prefix_len() == net.max_prefix_len();
+    match settings.mode {
+        PrivatePtrMode::Synthesize if complete => {
+            if RecordType::from(qtype) != RecordType::PTR {
+                // 名称存在但无此类型记录 / The name exists but has no records of this type
+                return Some((ResponseCode::NoError, Vec::new()));
+            }
+            let target = synthesized_name(net.addr(), &settings.suffix)?;
+            let owner = Name::from_str(qname).ok()?;
+            let record = Record::from_rdata(owner, settings.ttl, RData::PTR(PTR(target)));
+            Some((ResponseCode::NoError, vec![record]))
+        }
+        _ => Some((ResponseCode::NXDomain, Vec::new())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_private_reverse_names_are_answered_locally() {
+        // Arrange
+        let synthesize = PrivatePtrSettings {
+            mode: PrivatePtrMode::Synthesize,
+            suffix: "lan.".to_string(),
+            ttl: 60,
+        };
+        let nxdomain = PrivatePtrSettings {
+            mode: PrivatePtrMode::Nxdomain,
+            ..synthesize.clone()
+        };
+        let ptr = u16::from(RecordType::PTR);
+
+        // Act
+        let cgnat = answer(&synthesize, "9.8.64.100.in-addr.arpa.", ptr).unwrap();
+        let ula = answer(&synthesize, &format!("{}d.f.ip6.arpa.", "1.0.".repeat(15)), ptr).unwrap();
+        let partial = answer(&synthesize, "168.192.in-addr.arpa.", ptr).unwrap();
+        let blocked = answer(&nxdomain, "1.0.0.10.in-addr.arpa.", ptr).unwrap();
+        let public = answer(&synthesize, "8.8.8.8.in-addr.arpa.", ptr);
+        let outside_cgnat = answer(&synthesize, "1.0.128.100.in-addr.arpa.", ptr);
+
+        // Assert
+        assert_eq!(cgnat.0, ResponseCode::NoError);
+        assert_eq!(cgnat.1[0].data().unwrap().to_string(), "ip-100-64-8-9.lan.");
+        assert_eq!(ula.1[0].data().unwrap().to_string(), "ip-fd01-101-101-101-101-101-101-101.lan.");
+        assert_eq!(partial.0, ResponseCode::NXDomain);
+        assert_eq!(blocked.0, ResponseCode::NXDomain);
+        assert!(public.is_none());
+        assert!(outside_cgnat.is_none());
+    }
+}
@@ -0,0 +1,111 @@
+//! PTR 应答改写 / PTR answer rewrite
+//!
+//! 反向查询的地址落在配置的地址段内时，把上游 PTR 记录的目标替换为运维定义的名称，例如把云厂商的
+//! `ec2-203-0-113-7.compute.amazonaws.com.` 显示为 `web-{ip}.prod.example.` 。只改写已有的 PTR
+//! 记录，NXDOMAIN 与无记录的应答保持原样。
+//! When the address of a reverse lookup falls inside a configured range, the target of the
+//! upstream PTR records is replaced with an operator-defined name, e.g. a cloud provider's
+//! `ec2-203-0-113-7.compute.amazonaws.com.` shows up as `web-{ip}.prod.example.`. Only existing
+//! PTR records are rewritten; NXDOMAIN and empty answers are left as they are.
+
+use std::str::FromStr;
+
+use hickory_proto::op::Message;
+use hickory_proto::rr::rdata::PTR;
+use hickory_proto::rr::{Name, RData, RecordType};
+use ipnet::IpNet;
+
+use super::private_ptr::{dashed, parse_reverse};
+use crate::config::PtrRewrite;
+use crate::proto_utils::parse_quick;
+
+/// 为地址生成改写后的名称 / Build the rewritten name for an address
+fn target(rule: &PtrRewrite, ip: std::net::IpAddr) -> Option<Name> {
+    Name::from_str(&rule.name.replace("{ip}", &dashed(ip))).ok().map(|mut name| {
+        name.set_fqdn(true);
+        name
+    })
+}
+
+/// 改写项是否有效，无效时返回原因 / Whether a rewrite entry is valid, with the reason when it is not
+pub(crate) fn check(rule: &PtrRewrite) -> Result<(), String> {
+    let net = IpNet::from_str(rule.cidr.trim()).map_err(|e| format!("invalid cidr {:?}: {}", rule.cidr, e))?;
+    target(rule, net.addr()).ok_or_else(|| format!("invalid name {:?}", rule.name))?;
+    Ok(())
+}
+
+/// 改写 PTR 应答中的名称，无需改写时返回 None
+/// Rewrite the names in a PTR answer, or None when nothing needs rewriting
+pub(crate) fn rewrite(rules: &[PtrRewrite], raw: &[u8]) -> Option<Vec<u8>> {
+    if rules.is_empty() {
+        return None;
+    }
+    let mut qname_buf = [0u8; 256];
+    let q = parse_quick(raw, &mut qname_buf)?;
+    if RecordType::from(q.qtype) != RecordType::PTR {
+        return None;
+    }
+    let net = parse_reverse(q.qname_str_unchecked())?;
+    if net.prefix_len() != net.max_prefix_len() {
+        return None;
+    }
+    let ip = net.addr();
+    let rule = rules
+        .iter()
+        .find(|r| IpNet::from_str(r.cidr.trim()).is_ok_and(|cidr| cidr.contains(&ip)))?;
+    let name = target(rule, ip)?;
+
+    let mut msg = Message::from_vec(raw).ok()?;
+    let mut answers = msg.take_answers();
+    let mut changed = false;
+    for record in &mut answers {
+        if record.record_type() == RecordType::PTR {
+            record.set_data(Some(RData::PTR(PTR(name.clone()))));
+            changed = true;
+        }
+    }
+    msg.insert_answers(answers);
+    if !changed {
+        return None;
+    }
+    msg.to_vec().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{MessageType, Query};
+    use hickory_proto::rr::Record;
+
+    fn ptr_answer(qname: &str, target: &str) -> Vec<u8> {
+        let owner = Name::from_ascii(qname).unwrap();
+        let mut msg = Message::new();
+        msg.set_message_type(MessageType::Response);
+        msg.add_query(Query::query(owner.clone(), RecordType::PTR));
+        msg.add_answer(Record::from_rdata(owner, 3600, RData::PTR(PTR(Name::from_ascii(target).unwrap()))));
+        msg.to_vec().unwrap()
+    }
+
+    #[test]
+    fn test_ptr_answers_rewritten_for_configured_ranges() {
+        // Arrange
+        let rules = vec![
+            PtrRewrite { cidr: "203.0.113.0/28".to_string(), name: "web-{ip}.prod.example".to_string() },
+            PtrRewrite { cidr: "203.0.113.0/24".to_string(), name: "edge.prod.example.".to_string() },
+        ];
+        let generic = "ec2-203-0-113-7.compute.amazonaws.com.";
+
+        // Act
+        let web = rewrite(&rules, &ptr_answer("7.113.0.203.in-addr.arpa.", generic)).unwrap();
+        let edge = rewrite(&rules, &ptr_answer("200.113.0.203.in-addr.arpa.", generic)).unwrap();
+        let outside = rewrite(&rules, &ptr_answer("7.114.0.203.in-addr.arpa.", generic));
+
+        // Assert
+        let target_of = |raw: &[u8]| Message::from_vec(raw).unwrap().answers()[0].data().unwrap().to_string();
+        assert_eq!(target_of(&web), "web-203-0-113-7.prod.example.");
+        assert_eq!(target_of(&edge), "edge.prod.example.");
+        assert_eq!(Message::from_vec(&web).unwrap().answers()[0].ttl(), 3600);
+        assert!(outside.is_none());
+        assert!(check(&PtrRewrite { cidr: "203.0.113.0/33".to_string(), name: "x.".to_string() }).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! 上游唯一 qname 速率限制 / Per-upstream unique qname rate limit
+//!
+//! 随机子域名攻击（`<random>.victim.com`）每个查询都不命中缓存，会被原样转发给上游。
+//! 按上游统计每秒转发的不同 qname 数量，超出上限的新 qname 不再转发，交由 serve-stale
+//! 或 SERVFAIL 处理；同一 qname 在同一秒内的重复查询不重复计数。
+//! Random-subdomain floods (`<random>.victim.com`) miss the cache on every query and would
+//! be relayed to the upstream wholesale. The number of distinct qnames forwarded to each
+//! upstream per second is counted and new qnames beyond the limit are not forwarded, falling
+//! back to serve-stale or SERVFAIL; repeats of a qname within the same second are not counted
+//! again.
+
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use rustc_hash::{FxBuildHasher, FxHashSet, FxHasher};
+
+use super::Engine;
+use super::upstream::UpstreamFailure;
+
+/// 一个上游在当前秒内见过的 qname / Qnames seen by one upstream in the current second
+#[derive(Debug, Default)]
+struct Window {
+    second: u64,
+    seen: FxHashSet<u64>,
+}
+
+/// 按上游的唯一 qname 计数器 / Per-upstream unique qname counter
+#[derive(Debug)]
+pub struct UniqueQnameLimiter {
+    epoch: Instant,
+    windows: DashMap<Arc<str>, Mutex<Window>, FxBuildHasher>,
+    limited: AtomicU64,
+}
+
+impl Default for UniqueQnameLimiter {
+    fn default() -> Self {
+        Self {
+            epoch: Instant::now(),
+            windows: DashMap::with_hasher(FxBuildHasher),
+            limited: AtomicU64::new(0),
+        }
+    }
+}
+
+impl UniqueQnameLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 是否允许向该上游转发此 qname / Whether this qname may be forwarded to the upstream
+    pub fn admit(&self, upstream: &Arc<str>, qname_hash: u64, limit: u32) -> bool {
+        let second = self.epoch.elapsed().as_secs();
+        let window = self.windows.entry(upstream.clone()).or_default();
+        let mut window = window.lock();
+        if window.second != second {
+            window.second = second;
+            window.seen.clear();
+        }
+        if window.seen.contains(&qname_hash) {
+            return true;
+        }
+        if window.seen.len() >= limit as usize {
+            self.limited.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        window.seen.insert(qname_hash);
+        true
+    }
+
+    /// 因超限未转发的查询数 / Queries not forwarded because of the limit
+    pub fn limited(&self) -> u64 {
+        self.limited.load(Ordering::Relaxed)
+    }
+}
+
+/// 查询 qname 的哈希（忽略大小写）/ Case-insensitive hash of the query's qname
+pub(super) fn qname_hash(packet: &[u8]) -> Option<u64> {
+    let question = crate::proto_utils::question_bytes(packet)?;
+    let name = &question[..question.len() - 4];
+    let mut hasher = FxHasher::default();
+    for b in name {
+        b.to_ascii_lowercase().hash(&mut hasher);
+    }
+    Some(hasher.finish())
+}
+
+impl Engine {
+    /// 去掉本秒唯一 qname 数已达上限的上游；全部超限时返回 UpstreamFailure，由调用方回退到
+    /// serve-stale 或 SERVFAIL
+    /// Drop upstreams that reached their unique qname limit for this second; when all of them
+    /// did, return UpstreamFailure so the caller falls back to serve-stale or SERVFAIL
+    pub(crate) fn admit_unique_qname(
+        &self,
+        packet: &[u8],
+        upstreams: Vec<Arc<str>>,
+    ) -> anyhow::Result<Vec<Arc<str>>> {
+        let limit = self.state.load().pipeline.settings.upstream_unique_qname_limit;
+        if limit == 0 {
+            return Ok(upstreams);
+        }
+        let Some(hash) = qname_hash(packet) else {
+            return Ok(upstreams);
+        };
+        let admitted: Vec<Arc<str>> = upstreams
+            .into_iter()
+            .filter(|up| self.qname_limiter.admit(up, hash, limit))
+            .collect();
+        if admitted.is_empty() {
+            tracing::debug!(limit, "unique qname limit reached for every upstream, query not forwarded");
+            return Err(anyhow::Error::new(UpstreamFailure::new(anyhow::anyhow!(
+                "upstream unique qname limit exceeded"
+            ))));
+        }
+        Ok(admitted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_counts_distinct_qnames_per_upstream() {
+        // Arrange
+        let limiter = UniqueQnameLimiter::new();
+        let a: Arc<str> = Arc::from("1.1.1.1:53");
+        let b: Arc<str> = Arc::from("8.8.8.8:53");
+        let query = |qname: &str| {
+            let mut msg = hickory_proto::op::Message::new();
+            msg.add_query(hickory_proto::op::Query::query(
+                hickory_proto::rr::Name::from_ascii(qname).unwrap(),
+                hickory_proto::rr::RecordType::A,
+            ));
+            qname_hash(&msg.to_vec().unwrap()).unwrap()
+        };
+
+        // Act
+        let first = limiter.admit(&a, query("x1.victim.test."), 2);
+        let second = limiter.admit(&a, query("x2.victim.test."), 2);
+        let third = limiter.admit(&a, query("x3.victim.test."), 2);
+        let repeat = limiter.admit(&a, query("X1.Victim.test."), 2);
+        let other_upstream = limiter.admit(&b, query("x3.victim.test."), 2);
+
+        // Assert
+        assert!(first && second && repeat && other_upstream);
+        assert!(!third);
+        assert_eq!(limiter.limited(), 1);
+    }
+}
@@ -0,0 +1,148 @@
+//! 被污染上游的按域名隔离 / Per-qname quarantine of poisoned upstreams
+//!
+//! 响应动作 `quarantine_upstream` 命中时（如应答 IP 落在已知的污染网段），把（qname, 上游）记为隔离
+//! minutes 分钟；期间同一 qname 的转发跳过该上游，无需每次都先收到污染响应再过滤。隔离的上游不是该
+//! qname 的全部上游时才会被跳过，全部被隔离时照常使用原列表，由规则继续过滤。
+//! When the `quarantine_upstream` response action fires (e.g. an answer IP inside a known
+//! poisoned range), the (qname, upstream) pair is quarantined for minutes; meanwhile forwards
+//! of that qname skip the upstream instead of receiving and filtering the poisoned answer
+//! every time. Quarantined upstreams are only skipped while others remain; when every upstream
+//! of the query is quarantined the original list is used and the rules keep filtering.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use moka::sync::Cache;
+
+use super::Engine;
+use super::qname_limit::qname_hash;
+use super::upstream::parse_upstream_addr;
+use crate::config::Transport;
+
+/// 最多记录的隔离条目 / Max quarantined pairs tracked
+const MAX_ENTRIES: u64 = 100_000;
+/// 隔离时长上限 / Longest quarantine
+const MAX_QUARANTINE: Duration = Duration::from_secs(24 * 3600);
+
+/// 隔离表：(qname 哈希, 上游地址) -> 解除时间 / Quarantine table: (qname hash, upstream address) -> release time
+pub struct UpstreamQuarantine {
+    entries: Cache<(u64, Arc<str>), Instant>,
+    /// 是否隔离过任何上游（热路径上无锁跳过）/ Whether anything was ever quarantined (lock-free skip on the hot path)
+    used: AtomicBool,
+}
+
+impl Default for UpstreamQuarantine {
+    fn default() -> Self {
+        Self {
+            entries: Cache::builder()
+                .max_capacity(MAX_ENTRIES)
+                .time_to_live(MAX_QUARANTINE)
+                .build(),
+            used: AtomicBool::new(false),
+        }
+    }
+}
+
+impl UpstreamQuarantine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 隔离条目数（含尚未清理的过期条目）/ Number of quarantined pairs (including expired ones not yet cleaned up)
+    pub fn count(&self) -> u64 {
+        self.entries.entry_count()
+    }
+
+    fn quarantine(&self, qname_hash: u64, addr: &str, duration: Duration) {
+        let until = Instant::now() + duration.min(MAX_QUARANTINE);
+        self.entries.insert((qname_hash, Arc::from(addr)), until);
+        self.used.store(true, Ordering::Relaxed);
+    }
+
+    fn is_quarantined(&self, qname_hash: u64, addr: &str) -> bool {
+        let key = (qname_hash, Arc::from(addr));
+        match self.entries.get(&key) {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                self.entries.invalidate(&key);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+impl Engine {
+    /// 隔离给出此响应的上游；upstream 为响应上下文中的 `协议:地址`
+    /// Quarantine the upstream that gave this response; upstream is the `proto:addr` of the response context
+    pub(crate) fn quarantine_upstream(&self, packet: &[u8], upstream: &str, minutes: u64) {
+        let Some(hash) = qname_hash(packet) else {
+            return;
+        };
+        let addr = upstream.split_once(':').map_or(upstream, |(_, addr)| addr);
+        self.quarantine.quarantine(hash, addr, Duration::from_secs(minutes.saturating_mul(60)));
+        tracing::info!(event = "upstream_quarantined", upstream = %addr, minutes, "upstream quarantined for qname");
+    }
+
+    /// 去掉对该 qname 处于隔离期的上游；全部被隔离时返回原列表
+    /// Drop upstreams quarantined for this qname; the original list is kept when all of them are
+    pub(crate) fn skip_quarantined(
+        &self,
+        packet: &[u8],
+        upstreams: Vec<Arc<str>>,
+        default_transport: Transport,
+    ) -> Vec<Arc<str>> {
+        if !self.quarantine.used.load(Ordering::Relaxed) {
+            return upstreams;
+        }
+        let Some(hash) = qname_hash(packet) else {
+            return upstreams;
+        };
+        let kept: Vec<Arc<str>> = upstreams
+            .iter()
+            .filter(|up| !self.quarantine.is_quarantined(hash, parse_upstream_addr(up, default_transport).0))
+            .cloned()
+            .collect();
+        if kept.is_empty() { upstreams } else { kept }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::RuntimePipelineConfig;
+
+    fn query(qname: &str) -> Vec<u8> {
+        let mut msg = hickory_proto::op::Message::new();
+        msg.add_query(hickory_proto::op::Query::query(
+            hickory_proto::rr::Name::from_ascii(qname).unwrap(),
+            hickory_proto::rr::RecordType::A,
+        ));
+        msg.to_vec().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_quarantined_upstream_is_skipped_per_qname() {
+        // Arrange
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let cfg: crate::config::PipelineConfig =
+            serde_json::from_value(serde_json::json!({ "pipelines": [] })).unwrap();
+        let engine = Engine::new(RuntimePipelineConfig::from_config(cfg).unwrap(), "lbl".to_string());
+        let poisoned = query("blocked.example.com");
+        let other = query("www.example.com");
+        let upstreams: Vec<Arc<str>> = vec![Arc::from("1.1.1.1:53"), Arc::from("tcp://8.8.8.8:53")];
+
+        // Act
+        engine.quarantine_upstream(&poisoned, "udp:1.1.1.1:53", 10);
+        let skipped = engine.skip_quarantined(&poisoned, upstreams.clone(), Transport::Udp);
+        let unaffected = engine.skip_quarantined(&other, upstreams.clone(), Transport::Udp);
+        engine.quarantine_upstream(&poisoned, "tcp:8.8.8.8:53", 10);
+        let all_quarantined = engine.skip_quarantined(&poisoned, upstreams.clone(), Transport::Udp);
+
+        // Assert
+        assert_eq!(skipped, vec![Arc::<str>::from("tcp://8.8.8.8:53")]);
+        assert_eq!(unaffected, upstreams);
+        assert_eq!(all_quarantined, upstreams);
+    }
+}
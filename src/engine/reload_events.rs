@@ -0,0 +1,261 @@
+//! 热重载差异与事件 / Hot-reload diffs and events
+//!
+//! 每次重载都与上一份配置比较，得出新增/删除/变更的 pipeline、变更的 settings 字段与
+//! 规则/匹配器数量，写入结构化日志；重载事件（时间、version 字段、成功或失败原因）保留最近
+//! 若干条并出现在统计快照中，便于审计每次重载实际改了什么。
+//! Every reload is compared with the previous config to find the pipelines added, removed
+//! and changed, the settings fields changed and the rule/matcher counts, which are written
+//! to a structured log line. Reload events (time, version field, success or failure reason)
+//! keep the most recent entries and appear in the stats snapshot so operators can audit what
+//! each reload actually changed.
+
+use std::collections::{BTreeSet, VecDeque};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::{Value, json};
+use tracing::info;
+
+use crate::config::PipelineConfig;
+
+/// 保留的重载事件数 / Number of reload events kept
+const MAX_EVENTS: usize = 32;
+
+/// 前后两份配置的差异 / Differences between two configs
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct ConfigDiff {
+    pub pipelines_added: Vec<String>,
+    pub pipelines_removed: Vec<String>,
+    pub pipelines_changed: Vec<String>,
+    /// 变更的 settings 字段 / Changed settings fields
+    pub settings_changed: Vec<String>,
+    /// 其它变更的顶层段（pipeline_select、client_groups 等）/ Other changed top-level sections (pipeline_select, client_groups, ...)
+    pub sections_changed: Vec<String>,
+    /// [重载前, 重载后] / [before, after]
+    pub rules: [usize; 2],
+    pub matchers: [usize; 2],
+}
+
+impl ConfigDiff {
+    pub fn between(old: &PipelineConfig, new: &PipelineConfig) -> Self {
+        let old_value = serde_json::to_value(old).unwrap_or_default();
+        let new_value = serde_json::to_value(new).unwrap_or_default();
+
+        let pipelines = |cfg: &PipelineConfig| -> Vec<(String, Value)> {
+            cfg.pipelines
+                .iter()
+                .map(|p| (p.id.clone(), serde_json::to_value(p).unwrap_or_default()))
+                .collect()
+        };
+        let old_pipelines = pipelines(old);
+        let new_pipelines = pipelines(new);
+        let find = |list: &[(String, Value)], id: &str| {
+            list.iter().find(|(pid, _)| pid == id).map(|(_, v)| v.clone())
+        };
+
+        let mut diff = Self::default();
+        for (id, value) in &new_pipelines {
+            match find(&old_pipelines, id) {
+                None => diff.pipelines_added.push(id.clone()),
+                Some(old) if old != *value => diff.pipelines_changed.push(id.clone()),
+                Some(_) => {}
+            }
+        }
+        for (id, _) in &old_pipelines {
+            if find(&new_pipelines, id).is_none() {
+                diff.pipelines_removed.push(id.clone());
+            }
+        }
+
+        diff.settings_changed = changed_keys(&old_value["settings"], &new_value["settings"]);
+        diff.sections_changed = changed_keys(&old_value, &new_value)
+            .into_iter()
+            .filter(|k| k != "settings" && k != "pipelines")
+            .collect();
+        diff.rules = [rule_count(old), rule_count(new)];
+        diff.matchers = [matcher_count(old), matcher_count(new)];
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines_added.is_empty()
+            && self.pipelines_removed.is_empty()
+            && self.pipelines_changed.is_empty()
+            && self.settings_changed.is_empty()
+            && self.sections_changed.is_empty()
+    }
+}
+
+/// 两个 JSON 对象中取值不同的键 / Keys whose values differ between two JSON objects
+pub(crate) fn changed_keys(old: &Value, new: &Value) -> Vec<String> {
+    let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+        return Vec::new();
+    };
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .filter(|k| old.get(*k) != new.get(*k))
+        .cloned()
+        .collect()
+}
+
+fn rule_count(cfg: &PipelineConfig) -> usize {
+    cfg.pipelines.iter().map(|p| p.rules.len()).sum()
+}
+
+fn matcher_count(cfg: &PipelineConfig) -> usize {
+    cfg.pipelines
+        .iter()
+        .flat_map(|p| &p.rules)
+        .map(|r| r.matchers.len() + r.response_matchers.len())
+        .sum()
+}
+
+/// 一次重载的记录 / Record of one reload
+#[derive(Debug, Clone, Serialize)]
+pub struct ReloadEvent {
+    /// Unix 秒 / Unix seconds
+    pub timestamp: u64,
+    pub version: Option<String>,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<ConfigDiff>,
+}
+
+/// 最近的重载事件，以及最近一次生效的配置（用于计算下次重载的差异）
+/// Recent reload events, plus the config currently in effect (the base for the next reload's diff)
+#[derive(Debug, Default)]
+pub struct ReloadEvents {
+    events: Mutex<VecDeque<ReloadEvent>>,
+    current: Mutex<Option<PipelineConfig>>,
+}
+
+impl ReloadEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置当前生效的配置（启动时）/ Set the config currently in effect (at startup)
+    pub fn set_current(&self, cfg: PipelineConfig) {
+        *self.current.lock() = Some(cfg);
+    }
+
+    /// 当前生效的配置，尚未加载时为 None / The config currently in effect, None before it is known
+    pub fn current(&self) -> Option<PipelineConfig> {
+        self.current.lock().clone()
+    }
+
+    /// 记录一次成功的重载并输出与上一份配置的差异日志，返回该差异
+    /// Record a successful reload, log its diff against the previous config and return the diff
+    pub fn record_success(&self, new: &PipelineConfig) -> Option<ConfigDiff> {
+        let old = self.current.lock().replace(new.clone());
+        let diff = old.map(|old| ConfigDiff::between(&old, new));
+        if let Some(diff) = &diff {
+            info!(
+                target = "watcher",
+                event = "config_reload_diff",
+                version = new.version.as_deref().unwrap_or("-"),
+                pipelines_added = ?diff.pipelines_added,
+                pipelines_removed = ?diff.pipelines_removed,
+                pipelines_changed = ?diff.pipelines_changed,
+                settings_changed = ?diff.settings_changed,
+                sections_changed = ?diff.sections_changed,
+                rules_before = diff.rules[0],
+                rules_after = diff.rules[1],
+                matchers_before = diff.matchers[0],
+                matchers_after = diff.matchers[1],
+                unchanged = diff.is_empty(),
+                "config reload diff"
+            );
+        }
+        self.push(ReloadEvent {
+            timestamp: now_secs(),
+            version: new.version.clone(),
+            success: true,
+            error: None,
+            diff: diff.clone(),
+        });
+        diff
+    }
+
+    /// 记录一次失败的重载 / Record a failed reload
+    pub fn record_failure(&self, error: &anyhow::Error) {
+        self.push(ReloadEvent {
+            timestamp: now_secs(),
+            version: None,
+            success: false,
+            error: Some(format!("{:#}", error)),
+            diff: None,
+        });
+    }
+
+    fn push(&self, event: ReloadEvent) {
+        let mut events = self.events.lock();
+        if events.len() == MAX_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// 最近的事件，按时间先后 / Recent events, oldest first
+    pub fn snapshot(&self) -> Value {
+        let events = self.events.lock();
+        json!(events.iter().collect::<Vec<_>>())
+    }
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_reports_pipelines_settings_and_counts() {
+        // Arrange
+        let old: PipelineConfig = serde_json::from_value(json!({
+            "settings": { "min_ttl": 0 },
+            "pipelines": [
+                { "id": "a", "rules": [ { "name": "r", "matchers": [ { "type": "any" } ], "actions": [ { "type": "deny" } ] } ] },
+                { "id": "b", "rules": [] }
+            ]
+        }))
+        .unwrap();
+        let new: PipelineConfig = serde_json::from_value(json!({
+            "version": "2",
+            "settings": { "min_ttl": 30 },
+            "client_groups": { "kids": ["10.0.0.0/8"] },
+            "pipelines": [
+                { "id": "a", "rules": [ { "name": "r", "matchers": [ { "type": "any" }, { "type": "qtype", "value": "A" } ], "actions": [ { "type": "deny" } ] } ] },
+                { "id": "c", "rules": [] }
+            ]
+        }))
+        .unwrap();
+        let events = ReloadEvents::new();
+
+        // Act
+        let diff = ConfigDiff::between(&old, &new);
+        events.set_current(old.clone());
+        events.record_success(&new);
+        events.record_failure(&anyhow::anyhow!("bad json"));
+        let snapshot = events.snapshot();
+
+        // Assert
+        assert_eq!(diff.pipelines_added, vec!["c"]);
+        assert_eq!(diff.pipelines_removed, vec!["b"]);
+        assert_eq!(diff.pipelines_changed, vec!["a"]);
+        assert_eq!(diff.settings_changed, vec!["min_ttl"]);
+        assert_eq!(diff.sections_changed, vec!["client_groups", "version"]);
+        assert_eq!(diff.matchers, [1, 2]);
+        assert_eq!(snapshot[0]["version"], "2");
+        assert_eq!(snapshot[1]["success"], false);
+        assert_eq!(snapshot[1]["error"], "bad json");
+        assert_eq!(events.current().and_then(|cfg| cfg.version).as_deref(), Some("2"));
+    }
+}
@@ -105,6 +105,24 @@ pub(crate) fn make_static_txt_answer(
     (ResponseCode::ServFail, Vec::new())
 }
 
+/// 响应的缓存 TTL / Cache TTL of a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CacheTtl {
+    /// 应答 TTL，受 min_ttl 下限与 zero_ttl 策略影响 / The answer TTL, subject to the min_ttl floor and the zero_ttl policy
+    Answer(u64),
+    /// negative_cache 给出的 TTL，原样使用，0 表示不缓存 / A TTL from negative_cache, used as is; 0 = not cached
+    Negative(u64),
+}
+
+impl CacheTtl {
+    #[inline]
+    pub(crate) fn secs(self) -> u64 {
+        match self {
+            CacheTtl::Answer(ttl) | CacheTtl::Negative(ttl) => ttl,
+        }
+    }
+}
+
 /// 从 DNS 响应中提取最大 TTL 用于后台刷新时机 / Extract maximum TTL from DNS response for background refresh timing
 #[inline]
 pub fn extract_ttl_for_refresh(msg: &Message) -> u64 {
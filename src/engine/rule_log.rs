@@ -0,0 +1,316 @@
+//! 规则日志模板 / Rule log templates
+//!
+//! `Log` 动作可配置模板与输出目标。模板中的 `{qname}` 等占位符按当前请求替换，
+//! 当前阶段没有的值（如请求阶段的 rcode）输出为 `-`，未知占位符原样保留。
+//! The `Log` action accepts a template and an output target. Placeholders such as `{qname}`
+//! are filled from the current request; values unavailable in the current phase (e.g. rcode
+//! in the request phase) render as `-`, and unknown placeholders are kept verbatim.
+//!
+//! 写入 `query` 与 `file` 目标的日志还受所在 pipeline 的 `query_log` 策略约束：可排除域名、
+//! 对指定客户端分组省略 qname，或只记录拦截规则。
+//! Lines written to the `query` and `file` targets also follow the `query_log` policy of the
+//! pipeline: names can be excluded, qnames left out for some client groups, or only blocking
+//! rules logged.
+//!
+//! `file` 目标由后台线程写入，请求路径只把行放入有界队列；队列满时丢弃新行。
+//! The `file` target is written by a background thread; the request path only queues the
+//! line on a bounded queue, and new lines are dropped while it is full.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write as _};
+use std::net::IpAddr;
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, LazyLock};
+
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::RecordType;
+
+use crate::config::LogTarget;
+use crate::matcher::allowlist::DomainSet;
+use crate::matcher::ip_trie::IpPrefixSet;
+
+use super::matcher_adapter::log_match;
+use super::privacy::ClientIp;
+
+/// 未配置模板但目标不是主日志时使用的模板 / Template used when a non-main target has no template
+const DEFAULT_TEMPLATE: &str = "rule={matched_rule} qname={qname} qtype={qtype} client={client} upstream={upstream} rcode={rcode} latency_ms={latency} mark={mark}";
+
+/// 文件日志队列的容量 / Capacity of the file log queue
+const FILE_QUEUE_CAPACITY: usize = 8192;
+
+/// 后台文件写入线程的队列：(路径, 行) / Queue of the background file writer: (path, line)
+static FILE_WRITER: LazyLock<SyncSender<(String, String)>> = LazyLock::new(|| {
+    let (tx, rx) = std::sync::mpsc::sync_channel(FILE_QUEUE_CAPACITY);
+    std::thread::Builder::new()
+        .name("kixdns-log-file".to_string())
+        .spawn(move || write_files(rx))
+        .expect("spawn log file writer");
+    tx
+});
+
+/// 模板可用的字段 / Fields available to templates
+#[derive(Clone, Copy)]
+pub struct LogFields<'a> {
+    pub qname: &'a str,
+    pub qtype: RecordType,
+    pub client: ClientIp<'a>,
+    pub upstream: Option<&'a str>,
+    pub rcode: Option<ResponseCode>,
+    pub latency_ms: Option<u64>,
+    pub mark: Option<&'a str>,
+    pub matched_rule: &'a str,
+}
+
+/// 编译后的 pipeline 查询日志策略 / Compiled query log policy of a pipeline
+#[derive(Debug, Default)]
+pub struct QueryLogFilter {
+    exclude: DomainSet,
+    redact: Vec<Arc<IpPrefixSet>>,
+    blocked_only: bool,
+}
+
+impl QueryLogFilter {
+    pub fn new(exclude: DomainSet, redact: Vec<Arc<IpPrefixSet>>, blocked_only: bool) -> Self {
+        Self { exclude, redact, blocked_only }
+    }
+
+    /// 是否记录该查询：None 表示不记录，Some(true) 表示记录但省略 qname
+    /// Whether to log the query: None skips it, Some(true) logs it without the qname
+    pub fn decide(&self, qname: &str, client: IpAddr, blocked: bool) -> Option<bool> {
+        if self.blocked_only && !blocked {
+            return None;
+        }
+        if self.exclude.contains(qname) {
+            return None;
+        }
+        Some(self.redact.iter().any(|nets| nets.contains(&client)))
+    }
+}
+
+/// 按级别写入指定 tracing target / Emit at the given level to a tracing target
+macro_rules! emit {
+    ($target:literal, $level:expr, $line:expr) => {
+        match $level.unwrap_or("info") {
+            "trace" => tracing::trace!(target: $target, event = "matcher_log", "{}", $line),
+            "debug" => tracing::debug!(target: $target, event = "matcher_log", "{}", $line),
+            "warn" => tracing::warn!(target: $target, event = "matcher_log", "{}", $line),
+            "error" => tracing::error!(target: $target, event = "matcher_log", "{}", $line),
+            _ => tracing::info!(target: $target, event = "matcher_log", "{}", $line),
+        }
+    };
+}
+
+/// 执行 Log 动作 / Execute a Log action
+///
+/// 未配置模板且输出到主日志时保持原有的结构化日志格式。
+/// Without a template and with the main target, the original structured log line is kept.
+/// `blocked` 表示规则是否含拦截动作，供 `policy` 的 blocked_only 使用。
+/// `blocked` tells whether the rule has a blocking action, for the blocked_only option of `policy`.
+pub fn log_rule(
+    level: Option<&str>,
+    template: Option<&str>,
+    target: &LogTarget,
+    fields: &LogFields<'_>,
+    policy: Option<&QueryLogFilter>,
+    blocked: bool,
+) {
+    if template.is_none() && *target == LogTarget::Main {
+        log_match(level, fields.matched_rule, fields.qname, fields.client);
+        return;
+    }
+    let mut fields = *fields;
+    if *target != LogTarget::Main
+        && let Some(policy) = policy
+    {
+        match policy.decide(fields.qname, fields.client.0, blocked) {
+            None => return,
+            Some(true) => fields.qname = "-",
+            Some(false) => {}
+        }
+    }
+    let line = render(template.unwrap_or(DEFAULT_TEMPLATE), &fields);
+    match target {
+        LogTarget::Main => emit!("kixdns::rule_log", level, line),
+        LogTarget::Query => emit!("query_log", level, line),
+        LogTarget::File { path } => append_line(path, line),
+    }
+}
+
+/// 替换模板占位符 / Substitute template placeholders
+pub fn render(template: &str, fields: &LogFields<'_>) -> String {
+    let mut out = String::with_capacity(template.len() + 64);
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let after = &rest[open + 1..];
+        let Some(close) = after.find('}') else {
+            out.push_str(&rest[open..]);
+            return out;
+        };
+        let key = &after[..close];
+        if !write_field(&mut out, key, fields) {
+            out.push_str(&rest[open..open + close + 2]);
+        }
+        rest = &after[close + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 写入单个字段，未知字段返回 false / Write one field; returns false for unknown keys
+fn write_field(out: &mut String, key: &str, fields: &LogFields<'_>) -> bool {
+    let _ = match key {
+        "qname" => write!(out, "{}", fields.qname),
+        "qtype" => write!(out, "{}", fields.qtype),
+        "client" => write!(out, "{}", fields.client),
+        "upstream" => write!(out, "{}", fields.upstream.unwrap_or("-")),
+        "rcode" => match fields.rcode {
+            Some(rcode) => write!(out, "{:?}", rcode),
+            None => write!(out, "-"),
+        },
+        "latency" => match fields.latency_ms {
+            Some(ms) => write!(out, "{}", ms),
+            None => write!(out, "-"),
+        },
+        "mark" => write!(out, "{}", fields.mark.unwrap_or("-")),
+        "matched_rule" => write!(out, "{}", fields.matched_rule),
+        _ => return false,
+    };
+    true
+}
+
+/// 把一行交给后台线程追加到日志文件 / Hand one line to the background thread to append to a log file
+pub(crate) fn append_line(path: &str, line: String) {
+    match FILE_WRITER.try_send((path.to_string(), line)) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) => {
+            if let Some(suppressed) = super::log_limit::allow("log_file_queue_full") {
+                tracing::warn!(path = %path, suppressed, "log file queue full, line dropped");
+            }
+        }
+        Err(TrySendError::Disconnected(_)) => {
+            if let Some(suppressed) = super::log_limit::allow("log_file_writer_gone") {
+                tracing::warn!(path = %path, suppressed, "log file writer stopped, line dropped");
+            }
+        }
+    }
+}
+
+/// 后台写入循环：排空队列后统一 flush / Background write loop: flush once the queue is drained
+fn write_files(rx: Receiver<(String, String)>) {
+    let mut files: HashMap<String, BufWriter<File>> = HashMap::new();
+    while let Ok(first) = rx.recv() {
+        let mut next = Some(first);
+        while let Some((path, line)) = next {
+            if let Err(e) = write_line(&mut files, &path, &line) {
+                // 下次写入时重新打开 / Reopen on the next write
+                files.remove(&path);
+                tracing::warn!(path = %path, error = %e, "write log file failed");
+            }
+            next = rx.try_recv().ok();
+        }
+        files.retain(|path, file| match file.flush() {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!(path = %path, error = %e, "flush log file failed");
+                false
+            }
+        });
+    }
+}
+
+fn write_line(files: &mut HashMap<String, BufWriter<File>>, path: &str, line: &str) -> std::io::Result<()> {
+    let file = match files.get_mut(path) {
+        Some(f) => f,
+        None => {
+            let f = OpenOptions::new().create(true).append(true).open(path)?;
+            files.entry(path.to_string()).or_insert(BufWriter::new(f))
+        }
+    };
+    file.write_all(line.as_bytes())?;
+    file.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PrivacySettings;
+    use crate::engine::privacy::Privacy;
+
+    #[test]
+    fn test_render_fills_known_and_keeps_unknown_placeholders() {
+        // Arrange
+        let privacy = Privacy::new(&PrivacySettings::default());
+        let fields = LogFields {
+            qname: "ads.example.com",
+            qtype: RecordType::AAAA,
+            client: privacy.client("192.0.2.7".parse().unwrap()),
+            upstream: Some("1.1.1.1:53"),
+            rcode: None,
+            latency_ms: Some(12),
+            mark: Some("ads"),
+            matched_rule: "block_ads",
+        };
+
+        // Act
+        let line = render("[{mark}] {matched_rule}: {qname} {qtype} from {client} via {upstream} rcode={rcode} {latency}ms {other} {", &fields);
+
+        // Assert
+        assert_eq!(
+            line,
+            "[ads] block_ads: ads.example.com AAAA from 192.0.2.7 via 1.1.1.1:53 rcode=- 12ms {other} {"
+        );
+    }
+
+    #[test]
+    fn test_query_log_filter_excludes_redacts_and_keeps_blocked_only() {
+        // Arrange
+        let exclude = DomainSet::load(&["health.example".to_string()], &[]).unwrap();
+        let mut kids = IpPrefixSet::new();
+        kids.insert("192.0.2.0/24".parse().unwrap());
+        let filter = QueryLogFilter::new(exclude, vec![Arc::new(kids)], false);
+        let blocked_only = QueryLogFilter::new(DomainSet::default(), Vec::new(), true);
+        let kid: IpAddr = "192.0.2.7".parse().unwrap();
+        let adult: IpAddr = "198.51.100.1".parse().unwrap();
+
+        // Act
+        let excluded = filter.decide("clinic.health.example", adult, false);
+        let redacted = filter.decide("games.example", kid, false);
+        let plain = filter.decide("games.example", adult, false);
+        let allowed = blocked_only.decide("games.example", adult, false);
+        let blocked = blocked_only.decide("ads.example", adult, true);
+
+        // Assert
+        assert_eq!(excluded, None);
+        assert_eq!(redacted, Some(true));
+        assert_eq!(plain, Some(false));
+        assert_eq!(allowed, None);
+        assert_eq!(blocked, Some(false));
+    }
+
+    #[test]
+    fn test_file_lines_are_written_by_the_background_writer() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!("kixdns-rule-log-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path_str = path.to_str().unwrap();
+
+        // Act
+        append_line(path_str, "first".to_string());
+        append_line(path_str, "second".to_string());
+        let mut written = String::new();
+        for _ in 0..100 {
+            written = std::fs::read_to_string(&path).unwrap_or_default();
+            if written.lines().count() == 2 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let _ = std::fs::remove_file(&path);
+
+        // Assert
+        assert_eq!(written, "first\nsecond\n");
+    }
+}
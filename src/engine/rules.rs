@@ -19,10 +19,10 @@ use crate::engine::types::EngineInner;
 use crate::engine::types::InflightMap;
 use crate::engine::utils::engine_helpers::{self, build_response};
 use crate::engine::response::{make_static_ip_answer, make_static_txt_answer, extract_ttl, extract_ttl_for_refresh};
-use crate::engine::matcher_adapter::log_match;
+use crate::engine::rule_log::{LogFields, log_rule};
 use crate::matcher::eval_match_chain;
 use crate::cache::CacheEntry;
-use crate::engine::upstream::UpstreamFailure;
+use crate::engine::upstream::{UpstreamFailure, parse_upstream_addr};
 
 #[derive(Debug, Clone)]
 pub enum Decision {
@@ -50,6 +50,11 @@ pub enum Decision {
     Jump {
         pipeline: Arc<str>,
     },
+    /// 等待 ms 毫秒后再执行内部决策 / Carry out the inner decision after waiting ms milliseconds
+    Delay {
+        ms: u64,
+        then: Box<Decision>,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -58,6 +63,8 @@ pub struct ResponseContext {
     pub msg: Message,
     pub upstream: Arc<str>,
     pub transport: Transport,
+    /// 收到该响应时已耗费的时间 / Time elapsed when this response arrived
+    pub latency: Duration,
 }
 
 #[derive(Debug)]
@@ -74,6 +81,9 @@ pub enum ResponseActionResult {
     Jump {
         pipeline: Arc<str>,
         remaining_jumps: usize,
+        /// 交给目标 pipeline 的已取得响应，None 表示重新转发
+        /// Response handed to the target pipeline, None to forward again
+        ctx: Option<ResponseContext>,
     },
     Continue {
         ctx: Option<ResponseContext>,
@@ -219,7 +229,7 @@ pub fn contains_continue(actions: &[Action]) -> bool {
     actions.iter().any(|action| matches!(action, Action::Continue))
 }
 
-fn parse_rcode(rcode: &str) -> Option<ResponseCode> {
+pub(crate) fn parse_rcode(rcode: &str) -> Option<ResponseCode> {
     match rcode.to_ascii_uppercase().as_str() {
         "NOERROR" => Some(ResponseCode::NoError),
         "FORMERR" => Some(ResponseCode::FormErr),
@@ -247,6 +257,8 @@ pub struct ApplyResponseActionsContext<'a> {
     pub pipeline_id: &'a str,
     pub rule_name: &'a str,
     pub remaining_jumps: usize,
+    /// 响应写入缓存所用的键 / Key the response is cached under
+    pub cache_hash: u64,
 }
 
 pub(crate) async fn apply_response_actions(
@@ -257,8 +269,28 @@ pub(crate) async fn apply_response_actions(
 
     for action in ctx.actions {
         match action {
-            Action::Log { level } => {
-                log_match(level.as_deref(), ctx.rule_name, ctx.qname, ctx.client_ip);
+            Action::Log { level, template, target, mark } => {
+                let resp = ctx.ctx_opt.as_ref();
+                let fields = LogFields {
+                    qname: ctx.qname,
+                    qtype: ctx.qtype,
+                    client: ctx.engine.privacy.client(ctx.client_ip),
+                    upstream: resp.map(|c| c.upstream.as_ref()),
+                    rcode: resp.map(|c| c.msg.response_code()),
+                    latency_ms: resp.map(|c| c.latency.as_millis() as u64),
+                    mark: mark.as_deref(),
+                    matched_rule: ctx.rule_name,
+                };
+                let state = ctx.engine.state.load();
+                let policy = state.pipeline.pipelines.iter().find(|p| p.id.as_ref() == ctx.pipeline_id);
+                log_rule(
+                    level.as_deref(),
+                    template.as_deref(),
+                    target,
+                    &fields,
+                    policy.map(|p| p.query_log.as_ref()),
+                    ctx.actions.iter().any(Action::is_block),
+                );
             }
             Action::StaticResponse { rcode } => {
                 let code = parse_rcode(rcode).unwrap_or(ResponseCode::NXDomain);
@@ -288,8 +320,9 @@ pub(crate) async fn apply_response_actions(
                     source: "response_action",
                 });
             }
-            Action::JumpToPipeline { pipeline } => {
+            Action::JumpToPipeline { pipeline, reforward } => {
                 if ctx.remaining_jumps == 0 {
+                    warn_jump_limit("response", ctx.qname, &[Arc::from(ctx.pipeline_id), Arc::from(pipeline.as_str())]);
                     let bytes = engine_helpers::build_servfail_response(ctx.req)?;
                     return Ok(ResponseActionResult::Static {
                         bytes,
@@ -300,6 +333,7 @@ pub(crate) async fn apply_response_actions(
                 return Ok(ResponseActionResult::Jump {
                     pipeline: Arc::from(pipeline.as_str()),
                     remaining_jumps: ctx.remaining_jumps - 1,
+                    ctx: if *reforward { None } else { ctx.ctx_opt.take() },
                 });
             }
             Action::Allow => {
@@ -377,6 +411,88 @@ pub(crate) async fn apply_response_actions(
                     source: "response_action",
                 });
             }
+            Action::RetryTcp => {
+                let truncated = ctx.ctx_opt.as_ref().is_some_and(|c| {
+                    c.msg.truncated() && matches!(c.transport, Transport::Udp | Transport::TcpUdp)
+                });
+                if truncated && let Some(resp_ctx) = ctx.ctx_opt.take() {
+                    let (addr, _) = parse_upstream_addr(&resp_ctx.upstream, Transport::Udp);
+                    let retry_start = Instant::now();
+                    match ctx.engine.tcp_mux.send(ctx.packet, addr, ctx.upstream_timeout).await {
+                        Ok(raw) => {
+                            let raw = ctx.engine.transform_upstream_response(ctx.pipeline_id, raw);
+                            let msg = Message::from_bytes(&raw).context("parse tcp retry response")?;
+                            ctx.ctx_opt = Some(ResponseContext {
+                                raw,
+                                msg,
+                                upstream: resp_ctx.upstream.clone(),
+                                transport: Transport::Tcp,
+                                latency: resp_ctx.latency + retry_start.elapsed(),
+                            });
+                        }
+                        Err(err) => {
+                            // 重试失败时保留截断响应，客户端可自行改用 TCP
+                            // Keep the truncated response on failure; the client can switch to TCP itself
+                            warn!(
+                                event = "dns_response",
+                                upstream = %resp_ctx.upstream,
+                                qname = %ctx.qname,
+                                rule = %ctx.rule_name,
+                                error = %err,
+                                "tcp retry of truncated response failed"
+                            );
+                            ctx.ctx_opt = Some(resp_ctx);
+                        }
+                    }
+                }
+            }
+            Action::Delay { ms } => {
+                ctx.engine.tarpit(ctx.cache_hash, *ms, false).await;
+            }
+            // 仅请求阶段，校验时拒绝 / Request phase only, rejected by validation
+            Action::ReselectPipeline { .. } => {}
+            Action::QuarantineUpstream { minutes } => {
+                if let Some(ref resp_ctx) = ctx.ctx_opt {
+                    ctx.engine.quarantine_upstream(ctx.packet, &resp_ctx.upstream, *minutes);
+                }
+            }
+            Action::SetHeaderFlags { set, clear } => {
+                if let Some(ref mut resp_ctx) = ctx.ctx_opt
+                    && resp_ctx.raw.len() >= 12
+                {
+                    let mut raw = BytesMut::from(&resp_ctx.raw[..]);
+                    for flag in set {
+                        let (byte, mask) = flag.position();
+                        raw[byte] |= mask;
+                    }
+                    for flag in clear {
+                        let (byte, mask) = flag.position();
+                        raw[byte] &= !mask;
+                    }
+                    // 同步解析后的报文，供后续动作与响应匹配器使用 / Keep the parsed message in sync for later actions and response matchers
+                    let msg = &mut resp_ctx.msg;
+                    msg.set_authoritative(raw[2] & 0x04 != 0)
+                        .set_recursion_available(raw[3] & 0x80 != 0)
+                        .set_authentic_data(raw[3] & 0x20 != 0)
+                        .set_checking_disabled(raw[3] & 0x10 != 0);
+                    resp_ctx.raw = raw.freeze();
+                }
+            }
+            Action::RewriteRcode { from, to } => {
+                if let Some(ref resp_ctx) = ctx.ctx_opt
+                    && let (Some(from), Some(to)) = (parse_rcode(from), parse_rcode(to))
+                    && resp_ctx.msg.response_code() == from
+                {
+                    let mut msg = resp_ctx.msg.clone();
+                    msg.set_response_code(to);
+                    let bytes = Bytes::from(msg.to_vec().context("encode rewritten response")?);
+                    return Ok(ResponseActionResult::Static {
+                        bytes,
+                        rcode: to,
+                        source: "rewrite_rcode",
+                    });
+                }
+            }
             Action::Forward {
                 upstream,
                 transport,
@@ -388,7 +504,7 @@ pub(crate) async fn apply_response_actions(
                         event = "dns_response",
                         qname = %ctx.qname,
                         qtype = ?ctx.qtype,
-                        client_ip = %ctx.client_ip,
+                        client_ip = %ctx.engine.privacy.client(ctx.client_ip),
                         pipeline = %ctx.pipeline_id,
                         rule = %ctx.rule_name,
                         "response actions exceeded forward limit"
@@ -408,6 +524,7 @@ pub(crate) async fn apply_response_actions(
                         .unwrap_or_else(|| Arc::from(ctx.upstream_default))
                 });
                 let use_transport = transport.unwrap_or(Transport::Udp);
+                let forward_start = Instant::now();
                 let (raw, actual_upstream) = match crate::engine::upstream::forward_upstream(ctx.engine, ctx.packet, &upstream_addr, ctx.upstream_timeout, Some(use_transport), pre_split_upstreams.as_ref())
                     .await
                 {
@@ -418,7 +535,7 @@ pub(crate) async fn apply_response_actions(
                             upstream = %upstream_addr,
                             qname = %ctx.qname,
                             qtype = ?ctx.qtype,
-                            client_ip = %ctx.client_ip,
+                            client_ip = %ctx.engine.privacy.client(ctx.client_ip),
                             pipeline = %ctx.pipeline_id,
                             rule = %ctx.rule_name,
                             error = %err,
@@ -435,12 +552,14 @@ pub(crate) async fn apply_response_actions(
                         });
                     }
                 };
+                let raw = ctx.engine.transform_upstream_response(ctx.pipeline_id, raw);
                 let msg = Message::from_bytes(&raw).context("parse upstream response")?;
                 ctx.ctx_opt = Some(ResponseContext {
                     raw,
                     msg,
                     upstream: Arc::from(actual_upstream.as_str()),  // Use actual responding upstream
                     transport: use_transport,
+                    latency: forward_start.elapsed(),
                 });
             }
         }
@@ -469,6 +588,20 @@ pub(crate) async fn apply_response_actions(
     })
 }
 
+/// 跳转次数耗尽时记录跳转链；链中 pipeline 重复出现时标记为循环
+/// Log the jump chain when the jump limit is exhausted, flagging a loop when a pipeline repeats
+pub(crate) fn warn_jump_limit(phase: &'static str, qname: &str, chain: &[Arc<str>]) {
+    let loop_detected = chain.iter().enumerate().any(|(i, p)| chain[..i].contains(p));
+    warn!(
+        event = "jump_limit_reached",
+        phase,
+        qname = %qname,
+        chain = %chain.join(" -> "),
+        loop_detected,
+        "pipeline jump limit reached, returning SERVFAIL"
+    );
+}
+
 #[allow(clippy::too_many_arguments)]
 pub(crate) async fn process_response_jump(
     engine: &Engine,
@@ -485,7 +618,9 @@ pub(crate) async fn process_response_jump(
     min_ttl: Duration,
     upstream_timeout: Duration,
     skip_cache: bool,
+    mut carried: Option<ResponseContext>,
 ) -> anyhow::Result<Bytes> {
+    let jump_start = Instant::now();
     let cfg = &state.pipeline;
     struct InflightCleanupGuard {
         inflight: Arc<InflightMap>,
@@ -515,9 +650,13 @@ pub(crate) async fn process_response_jump(
     let mut reused_response: Option<ResponseContext> = None;
     let mut inflight_hashes = Vec::new();
     let mut cleanup_guards: Vec<InflightCleanupGuard> = Vec::new();
+    // 经过的 pipeline，耗尽跳转次数时输出 / Pipelines visited, logged when the jumps run out
+    let mut chain: Vec<Arc<str>> = vec![pipeline_id.clone()];
+    let dnssec_flags = crate::proto_utils::dnssec_flags_of(req);
 
     loop {
         if remaining_jumps == 0 {
+            warn_jump_limit("response", qname, &chain);
             let resp_bytes = engine_helpers::build_servfail_response(req)?;
             for g in &mut cleanup_guards { g.defuse(); }
             for h in &inflight_hashes { engine.notify_inflight_waiters(*h, &resp_bytes).await; }
@@ -531,7 +670,7 @@ pub(crate) async fn process_response_jump(
             return Ok(resp_bytes);
         };
 
-        let dedupe_hash = Engine::calculate_cache_hash_for_dedupe(&pipeline_id, qname.as_bytes(), qtype, qclass);
+        let dedupe_hash = Engine::calculate_cache_hash_for_dedupe(&pipeline_id, qname.as_bytes(), qtype, qclass, dnssec_flags);
         
         let mut decision = engine.apply_rules(
             state,
@@ -552,8 +691,15 @@ pub(crate) async fn process_response_jump(
         // Resolve nested rule-level jumps first
         let mut local_jumps = remaining_jumps;
         loop {
+            if let Decision::Delay { ms, then } = decision {
+                engine.tarpit(dedupe_hash, ms, skip_cache).await;
+                decision = *then;
+                continue;
+            }
             if let Decision::Jump { pipeline } = decision {
+                chain.push(pipeline.clone());
                 if local_jumps == 0 {
+                    warn_jump_limit("response", qname, &chain);
                     let resp_bytes = engine_helpers::build_servfail_response(req)?;
                     for g in &mut cleanup_guards { g.defuse(); }
                     for h in &inflight_hashes { engine.notify_inflight_waiters(*h, &resp_bytes).await; }
@@ -592,17 +738,20 @@ pub(crate) async fn process_response_jump(
                 let resp_bytes = build_response(req, rcode, answers)?;
                 let entry = CacheEntry {
                     bytes: resp_bytes.clone(),
+                    compressed: false,
                     rcode,
                     source: Arc::from("static"),
                     upstream: None,  // Static responses have no upstream
                     qname: Arc::from(qname),
                     pipeline_id: pipeline_id.clone(),
                     qtype: u16::from(qtype),
+                    qclass: u16::from(qclass),
                     inserted_at: Instant::now(),
                     original_ttl: min_ttl.as_secs() as u32,
                     refresh_ttl: min_ttl.as_secs() as u32,
+                    aged: Default::default(),
                 };
-                engine.cache.insert(dedupe_hash, Arc::new(entry));
+                engine.cache_insert(dedupe_hash, Arc::new(entry));
                 for g in &mut cleanup_guards { g.defuse(); }
                 for h in &inflight_hashes { engine.notify_inflight_waiters(*h, &resp_bytes).await; }
                 return Ok(resp_bytes);
@@ -620,13 +769,17 @@ pub(crate) async fn process_response_jump(
                 continue_on_miss: _,
                 allow_reuse,
             } => {
-                let resp = if allow_reuse {
+                // 响应阶段跳转带来的响应直接交给本规则的响应匹配器与动作
+                // A response carried over by a response-phase jump goes straight to this rule's response matchers and actions
+                let resp = if let Some(ctx) = carried.take() {
+                    Ok((ctx.raw, ctx.upstream.to_string()))
+                } else if allow_reuse {
                     if let Some(ctx) = reused_response.take() {
                         Ok((ctx.raw, ctx.upstream.to_string()))
                     } else {
-                        // FIX: Background refresh must skip inflight check
-                        // 修复：后台刷新必须跳过 inflight 检查
-                        if !skip_cache {
+                        // 预取与实时查询共享同一次上游交换（此路径对两者执行相同的响应动作）
+                        // Prefetch shares upstream exchanges with live queries (this path runs the same response actions for both)
+                        {
                             use dashmap::mapref::entry::Entry;
                             let rx = match engine.inflight.entry(dedupe_hash) {
                                 Entry::Vacant(entry) => {
@@ -684,9 +837,9 @@ pub(crate) async fn process_response_jump(
                     // If reuse is not allowed (e.g. explicit Forward action), we must clear any reused response
                     // and force a new request.
                     
-                    // FIX: Background refresh must skip inflight check
-                    // 修复：后台刷新必须跳过 inflight 检查
-                    if !skip_cache {
+                    // 预取与实时查询共享同一次上游交换（此路径对两者执行相同的响应动作）
+                    // Prefetch shares upstream exchanges with live queries (this path runs the same response actions for both)
+                    {
                         use dashmap::mapref::entry::Entry;
                         let rx = match engine.inflight.entry(dedupe_hash) {
                             Entry::Vacant(entry) => {
@@ -743,14 +896,16 @@ pub(crate) async fn process_response_jump(
 
                 match resp {
                     Ok((raw, actual_upstream)) => {
+                        let raw = engine.transform_upstream_response(&pipeline_id, raw);
                         let msg = Message::from_bytes(&raw).context("parse upstream response")?;
                         // Extract TTL for cache entry (use min for RFC 1035 compliance)
                         // 提取 TTL 用于缓存条目 (使用最小值符合 RFC 1035)
-                        let ttl_secs_cache = extract_ttl(&msg);
+                        let cache_ttl = engine.cache_ttl(&raw, msg.response_code(), extract_ttl(&msg));
+                        let ttl_secs_cache = cache_ttl.secs();
                         // Extract TTL for refresh timing (use max to avoid premature refresh)
                         // 提取 TTL 用于刷新时机 (使用最大值避免过早刷新)
                         let ttl_secs_refresh = extract_ttl_for_refresh(&msg);
-                        let effective_ttl = Duration::from_secs(ttl_secs_cache.max(min_ttl.as_secs()));
+                        let effective_ttl = engine.cache_lifetime(&pipeline_id, cache_ttl, min_ttl);
 
                         // Get manager references for GeoIP/GeoSite matching in response matchers
                         // Try to acquire read locks non-blockingly (fast path for concurrent reads)
@@ -794,19 +949,23 @@ pub(crate) async fn process_response_jump(
 
                         if actions_to_run.is_empty() {
                             if resp_match_ok && effective_ttl > Duration::from_secs(0) {
+                                let (original_ttl, inserted_at) = engine.cache_entry_timing(&pipeline_id, ttl_secs_cache as u32);
                                 let entry = CacheEntry {
                                     bytes: raw.clone(),
+                                    compressed: false,
                                     rcode: msg.response_code(),
                                     source: Arc::from(actual_upstream.as_str()),
                                     upstream: Some(Arc::from(actual_upstream.as_str())),
                                     qname: Arc::from(qname),
                                     pipeline_id: pipeline_id.clone(),
                                     qtype: u16::from(qtype),
-                                    inserted_at: Instant::now(),
-                                    original_ttl: ttl_secs_cache as u32,  // Use min TTL for cache expiration / 使用最小 TTL 作为缓存过期
+                                    qclass: u16::from(qclass),
+                                    inserted_at,
+                                    original_ttl,  // Use min TTL for cache expiration / 使用最小 TTL 作为缓存过期
                                     refresh_ttl: ttl_secs_refresh as u32,   // Use max TTL for refresh timing / 使用最大 TTL 作为刷新时机
+                                    aged: Default::default(),
                                 };
-                                engine.cache.insert(dedupe_hash, Arc::new(entry));
+                                engine.cache_insert(dedupe_hash, Arc::new(entry.compress_above(engine.tunables.cache_compress_threshold())));
                             }
                             for g in &mut cleanup_guards { g.defuse(); }
                             for h in &inflight_hashes { engine.notify_inflight_waiters(*h, &raw).await; }
@@ -818,6 +977,7 @@ pub(crate) async fn process_response_jump(
                             msg,
                             upstream: Arc::from(actual_upstream.as_str()),  // Use actual responding upstream
                             transport: transport.unwrap_or(Transport::Udp),
+                            latency: jump_start.elapsed(),
                         };
                         let apply_ctx = ApplyResponseActionsContext {
                             engine,
@@ -835,6 +995,7 @@ pub(crate) async fn process_response_jump(
                             pipeline_id: &pipeline_id,
                             rule_name: &rule_name,
                             remaining_jumps,
+                            cache_hash: dedupe_hash,
                         };
                         let action_result = apply_response_actions(apply_ctx)
                             .await?;
@@ -843,26 +1004,30 @@ pub(crate) async fn process_response_jump(
                             ResponseActionResult::Upstream { ctx, resp_match } => {
                                 // Extract TTL for cache entry (use min for RFC 1035 compliance)
                                 // 提取 TTL 用于缓存条目 (使用最小值符合 RFC 1035)
-                                let ttl_secs_cache = extract_ttl(&ctx.msg);
+                                let cache_ttl = engine.cache_ttl(&ctx.raw, ctx.msg.response_code(), extract_ttl(&ctx.msg));
+                                let ttl_secs_cache = cache_ttl.secs();
                                 // Extract TTL for refresh timing (use max to avoid premature refresh)
                                 // 提取 TTL 用于刷新时机 (使用最大值避免过早刷新)
                                 let ttl_secs_refresh = extract_ttl_for_refresh(&ctx.msg);
-                                let effective_ttl =
-                                    Duration::from_secs(ttl_secs_cache.max(min_ttl.as_secs()));
+                                let effective_ttl = engine.cache_lifetime(&pipeline_id, cache_ttl, min_ttl);
                                 if resp_match && effective_ttl > Duration::from_secs(0) {
+                                    let (original_ttl, inserted_at) = engine.cache_entry_timing(&pipeline_id, ttl_secs_cache as u32);
                                     let entry = CacheEntry {
                                         bytes: ctx.raw.clone(),
+                                        compressed: false,
                                         rcode: ctx.msg.response_code(),
                                         source: ctx.upstream.clone(),
                                         upstream: Some(ctx.upstream.clone()),
                                         qname: Arc::from(qname),
                                         pipeline_id: pipeline_id.clone(),
                                         qtype: u16::from(qtype),
-                                        inserted_at: Instant::now(),
-                                        original_ttl: ttl_secs_cache as u32,  // Use min TTL for cache expiration / 使用最小 TTL 作为缓存过期
+                                        qclass: u16::from(qclass),
+                                        inserted_at,
+                                        original_ttl,  // Use min TTL for cache expiration / 使用最小 TTL 作为缓存过期
                                         refresh_ttl: ttl_secs_refresh as u32,  // Use max TTL for refresh timing / 使用最大 TTL 作为刷新时机
+                                        aged: Default::default(),
                                     };
-                                    engine.cache.insert(dedupe_hash, Arc::new(entry));
+                                    engine.cache_insert(dedupe_hash, Arc::new(entry.compress_above(engine.tunables.cache_compress_threshold())));
                                 }
                                 for g in &mut cleanup_guards { g.defuse(); }
                                 for h in &inflight_hashes { engine.notify_inflight_waiters(*h, &ctx.raw).await; }
@@ -873,9 +1038,11 @@ pub(crate) async fn process_response_jump(
                                 for h in &inflight_hashes { engine.notify_inflight_waiters(*h, &bytes).await; }
                                 return Ok(bytes);
                             }
-                            ResponseActionResult::Jump { pipeline, remaining_jumps: next_remaining } => {
+                            ResponseActionResult::Jump { pipeline, remaining_jumps: next_remaining, ctx } => {
+                                chain.push(pipeline.clone());
                                 pipeline_id = pipeline;
                                 remaining_jumps = next_remaining;
+                                carried = ctx;
                                 continue;
                             }
                             ResponseActionResult::Continue { ctx } => {
@@ -896,12 +1063,17 @@ pub(crate) async fn process_response_jump(
                     }
                 }
             }
+            Decision::Delay { .. } => {
+                anyhow::bail!("unresolved delay");
+            }
             Decision::Jump { pipeline } => {
+                chain.push(pipeline.clone());
                 pipeline_id = pipeline;
                 if remaining_jumps > 0 {
                     remaining_jumps -= 1;
                     continue;
                 } else {
+                    warn_jump_limit("response", qname, &chain);
                     let resp_bytes = engine_helpers::build_servfail_response(req)?;
                     return Ok(resp_bytes);
                 }
@@ -0,0 +1,382 @@
+//! 运行时临时规则覆盖层 / Runtime overlay of temporary rules
+//!
+//! 通过管理接口添加的临时规则（如“拦截 example.com 2 小时”、“重载前将域名 X 强制转发到上游 Y”）。
+//! 规则保存在内存中：配置文件热重载后仍然保留（标记 until_reload 的除外），进程重启后丢失。
+//! 命中覆盖层的查询跳过配置规则，且使用独立的缓存命名空间，规则过期或删除后不会残留缓存结果。
+//! Temporary rules added through the admin API (e.g. "block example.com for 2 hours",
+//! "force domain X to upstream Y until reload"). Rules live in memory: they survive hot
+//! reloads of the file config (except those marked until_reload) but not restarts.
+//! Queries hitting the overlay bypass the configured rules and use a separate cache
+//! namespace, so nothing lingers in the cache once a rule expires or is removed.
+//!
+//! 集群复制来的规则按（来源节点, ID）区分，与本节点自己分配 ID 的规则互不覆盖。
+//! Rules replicated from other nodes are keyed by (origin node, ID), so they never replace
+//! rules whose IDs this node assigned itself.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use hickory_proto::op::ResponseCode;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Action, MatchOperator, Transport};
+use crate::engine::make_static_ip_answer;
+use crate::engine::rules::{Decision, parse_rcode};
+
+/// 覆盖层命中时使用的 pipeline ID（用于缓存命名空间）/ Pipeline ID used for overlay hits (cache namespace)
+pub const RUNTIME_PIPELINE_ID: &str = "@runtime";
+
+/// 添加临时规则的请求 / Request to add a temporary rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuntimeRuleSpec {
+    /// 来源节点分配的规则 ID，仅由集群复制与 origin 一同设置；缺省由本节点分配
+    /// Rule ID assigned by the origin node, only set by cluster replication together with origin; assigned here if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    /// 复制来源节点，同一来源的同 ID 旧规则被替换 / Origin node of a replicated rule; an older rule with the same origin and ID is replaced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// 匹配的域名（包括子域名）/ Domain to match (including subdomains)
+    pub domain: String,
+    /// 动作：deny / static_response / static_ip_response / forward / allow
+    /// Action: deny / static_response / static_ip_response / forward / allow
+    pub action: serde_json::Value,
+    /// 有效期（秒），缺省永久（直到删除或重启）/ Lifetime in seconds, permanent (until removed or restart) if absent
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+    /// 下次配置重载时移除 / Remove on the next config reload
+    #[serde(default)]
+    pub until_reload: bool,
+    /// 备注 / Note
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+/// 单条临时规则 / A single temporary rule
+#[derive(Debug)]
+struct RuntimeRule {
+    id: u64,
+    /// 复制来源节点，本节点添加的规则为 None / Origin node of a replicated rule, None for rules added here
+    origin: Option<Arc<str>>,
+    domain: Arc<str>,
+    action: Action,
+    action_raw: serde_json::Value,
+    created_at: Instant,
+    expires_at: Option<Instant>,
+    until_reload: bool,
+    note: Option<String>,
+}
+
+impl RuntimeRule {
+    #[inline]
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|t| now >= t)
+    }
+
+    #[inline]
+    fn matches(&self, qname: &str) -> bool {
+        let domain = self.domain.as_ref();
+        qname == domain
+            || (qname.len() > domain.len()
+                && qname.ends_with(domain)
+                && qname.as_bytes()[qname.len() - domain.len() - 1] == b'.')
+    }
+}
+
+/// 临时规则的对外视图 / Public view of a temporary rule
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeRuleView {
+    pub id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    pub domain: String,
+    pub action: serde_json::Value,
+    pub age_secs: u64,
+    pub expires_in_secs: Option<u64>,
+    pub until_reload: bool,
+    pub note: Option<String>,
+}
+
+/// 临时规则集合 / Set of temporary rules
+#[derive(Debug, Default)]
+pub struct RuntimeRules {
+    rules: RwLock<Vec<Arc<RuntimeRule>>>,
+    /// 规则数量（热路径上无锁判断是否为空）/ Rule count (lock-free emptiness check on the hot path)
+    active: AtomicUsize,
+    next_id: AtomicU64,
+}
+
+impl RuntimeRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 添加规则，返回规则 ID / Add a rule, returning its ID
+    pub fn add(&self, spec: RuntimeRuleSpec) -> anyhow::Result<u64> {
+        let domain = spec.domain.trim().trim_matches('.').to_ascii_lowercase();
+        if domain.is_empty() {
+            anyhow::bail!("domain must not be empty");
+        }
+        let mut action: Action = serde_json::from_value(spec.action.clone())
+            .map_err(|e| anyhow::anyhow!("invalid action: {}", e))?;
+        match &action {
+            Action::StaticResponse { rcode } if parse_rcode(rcode).is_none() => {
+                anyhow::bail!("unknown rcode {:?}", rcode)
+            }
+            Action::Deny
+            | Action::Allow
+            | Action::StaticResponse { .. }
+            | Action::StaticIpResponse { .. }
+            | Action::Forward { .. } => {}
+            _ => anyhow::bail!(
+                "unsupported runtime action, expected deny/allow/static_response/static_ip_response/forward"
+            ),
+        }
+        action.pre_split_upstreams();
+
+        let now = Instant::now();
+        let (origin, id): (Option<Arc<str>>, u64) = match (spec.origin.as_deref(), spec.id) {
+            (Some(origin), Some(id)) => (Some(Arc::from(origin)), id),
+            (None, None) => (None, self.next_id.fetch_add(1, Ordering::Relaxed) + 1),
+            _ => anyhow::bail!("id and origin must be given together"),
+        };
+        let rule = Arc::new(RuntimeRule {
+            id,
+            origin: origin.clone(),
+            domain: Arc::from(domain),
+            action,
+            action_raw: spec.action,
+            created_at: now,
+            expires_at: spec.ttl_secs.map(|s| now + Duration::from_secs(s)),
+            until_reload: spec.until_reload,
+            note: spec.note,
+        });
+
+        let mut rules = self.rules.write();
+        rules.retain(|r| !(r.is_expired(now) || (r.id == id && r.origin == origin)));
+        rules.push(rule);
+        self.active.store(rules.len(), Ordering::Relaxed);
+        Ok(id)
+    }
+
+    /// 删除本节点添加的规则 / Remove a rule added on this node
+    pub fn remove(&self, id: u64) -> bool {
+        self.remove_from(None, id)
+    }
+
+    /// 删除规则，origin 为复制来源节点（本节点添加的规则为 None）
+    /// Remove a rule; origin is the node it was replicated from (None for rules added here)
+    pub fn remove_from(&self, origin: Option<&str>, id: u64) -> bool {
+        let mut rules = self.rules.write();
+        let before = rules.len();
+        rules.retain(|r| !(r.id == id && r.origin.as_deref() == origin));
+        self.active.store(rules.len(), Ordering::Relaxed);
+        rules.len() != before
+    }
+
+    /// 配置重载时调用：移除 until_reload 规则 / Called on config reload: drop until_reload rules
+    pub fn on_reload(&self) {
+        if self.active.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut rules = self.rules.write();
+        rules.retain(|r| !r.until_reload);
+        self.active.store(rules.len(), Ordering::Relaxed);
+    }
+
+    /// 列出有效规则 / List live rules
+    pub fn list(&self) -> Vec<RuntimeRuleView> {
+        let now = Instant::now();
+        self.rules
+            .read()
+            .iter()
+            .filter(|r| !r.is_expired(now))
+            .map(|r| RuntimeRuleView {
+                id: r.id,
+                origin: r.origin.as_deref().map(str::to_string),
+                domain: r.domain.to_string(),
+                action: r.action_raw.clone(),
+                age_secs: now.duration_since(r.created_at).as_secs(),
+                expires_in_secs: r.expires_at.map(|t| t.duration_since(now).as_secs()),
+                until_reload: r.until_reload,
+                note: r.note.clone(),
+            })
+            .collect()
+    }
+
+    /// 是否有规则命中该域名（后添加的优先）/ Whether any rule matches the name (latest added wins)
+    #[inline]
+    pub fn matches(&self, qname: &str) -> bool {
+        self.lookup(qname).is_some()
+    }
+
+    fn lookup(&self, qname: &str) -> Option<Arc<RuntimeRule>> {
+        if self.active.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        let qname = qname.trim_end_matches('.');
+        let now = Instant::now();
+        self.rules
+            .read()
+            .iter()
+            .rev()
+            .find(|r| !r.is_expired(now) && r.matches(qname))
+            .cloned()
+    }
+
+    /// 计算命中规则的决策 / Build the decision for a matching rule
+    pub fn decision_for(&self, qname: &str, upstream_default: &str) -> Option<Decision> {
+        let rule = self.lookup(qname)?;
+        let forward = |upstream: Arc<str>,
+                       pre_split_upstreams: Option<Arc<Vec<Arc<str>>>>,
+                       transport: Transport| Decision::Forward {
+            upstream,
+            pre_split_upstreams,
+            response_matchers: Vec::new(),
+            response_matcher_operator: MatchOperator::And,
+            response_actions_on_match: Vec::new(),
+            response_actions_on_miss: Vec::new(),
+            rule_name: Arc::from(match &rule.origin {
+                Some(origin) => format!("runtime:{}:{}", origin, rule.id),
+                None => format!("runtime:{}", rule.id),
+            }),
+            transport: Some(transport),
+            continue_on_match: false,
+            continue_on_miss: false,
+            allow_reuse: false,
+        };
+        let d = match &rule.action {
+            Action::Deny => Decision::Static {
+                rcode: ResponseCode::Refused,
+                answers: Vec::new(),
+            },
+            Action::StaticResponse { rcode } => Decision::Static {
+                rcode: parse_rcode(rcode).unwrap_or(ResponseCode::NXDomain),
+                answers: Vec::new(),
+            },
+            Action::StaticIpResponse { ip } => {
+                let (rcode, answers) = make_static_ip_answer(qname, ip);
+                Decision::Static { rcode, answers }
+            }
+            Action::Forward {
+                upstream,
+                transport,
+                pre_split_upstreams,
+            } => forward(
+                Arc::from(upstream.as_deref().unwrap_or(upstream_default)),
+                pre_split_upstreams.clone(),
+                transport.unwrap_or(Transport::Udp),
+            ),
+            _ => forward(Arc::from(upstream_default), None, Transport::Udp),
+        };
+        Some(d)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(domain: &str, action: serde_json::Value) -> RuntimeRuleSpec {
+        RuntimeRuleSpec {
+            id: None,
+            origin: None,
+            domain: domain.to_string(),
+            action,
+            ttl_secs: None,
+            until_reload: false,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn test_runtime_rule_block_and_remove() {
+        // Arrange
+        let rules = RuntimeRules::new();
+        let id = rules
+            .add(spec("Example.com.", serde_json::json!({ "type": "deny" })))
+            .unwrap();
+
+        // Act
+        let hit = rules.decision_for("www.example.com", "1.1.1.1:53");
+        let miss = rules.decision_for("notexample.com", "1.1.1.1:53");
+
+        // Assert
+        assert!(matches!(
+            hit,
+            Some(Decision::Static { rcode: ResponseCode::Refused, .. })
+        ));
+        assert!(miss.is_none());
+        assert!(rules.remove(id));
+        assert!(!rules.matches("www.example.com"));
+    }
+
+    #[test]
+    fn test_runtime_rule_until_reload_and_expiry() {
+        // Arrange
+        let rules = RuntimeRules::new();
+        let mut forced = spec(
+            "x.test",
+            serde_json::json!({ "type": "forward", "upstream": "9.9.9.9:53" }),
+        );
+        forced.until_reload = true;
+        rules.add(forced).unwrap();
+        let mut expired = spec("y.test", serde_json::json!({ "type": "deny" }));
+        expired.ttl_secs = Some(0);
+        rules.add(expired).unwrap();
+
+        // Act & Assert
+        match rules.decision_for("x.test", "1.1.1.1:53") {
+            Some(Decision::Forward { upstream, .. }) => assert_eq!(upstream.as_ref(), "9.9.9.9:53"),
+            other => panic!("expected forward decision, got {:?}", other),
+        }
+        assert!(!rules.matches("y.test"));
+        rules.on_reload();
+        assert!(!rules.matches("x.test"));
+    }
+
+    #[test]
+    fn test_replicated_rules_do_not_replace_local_rules() {
+        // Arrange: a local rule and a rule replicated from node "a" with the same ID
+        let rules = RuntimeRules::new();
+        let local = rules.add(spec("local.test", serde_json::json!({ "type": "deny" }))).unwrap();
+        let mut replicated = spec("remote.test", serde_json::json!({ "type": "deny" }));
+        replicated.id = Some(local);
+        replicated.origin = Some("a".to_string());
+        let mut half = spec("half.test", serde_json::json!({ "type": "deny" }));
+        half.id = Some(7);
+
+        // Act
+        rules.add(replicated).unwrap();
+        let rejected = rules.add(half);
+        let removed = rules.remove_from(Some("a"), local);
+
+        // Assert
+        assert!(rejected.is_err());
+        assert!(removed);
+        assert!(rules.matches("local.test"));
+        assert!(!rules.matches("remote.test"));
+    }
+
+    #[test]
+    fn test_runtime_rule_rejects_unsupported_action() {
+        // Arrange
+        let rules = RuntimeRules::new();
+
+        // Act
+        let res = rules.add(spec("a.test", serde_json::json!({ "type": "continue" })));
+        let bad_rcode = rules.add(spec("a.test", serde_json::json!({ "type": "static_response", "rcode": "NXDOMIAN" })));
+        let servfail = rules.add(spec("b.test", serde_json::json!({ "type": "static_response", "rcode": "servfail" })));
+
+        // Assert
+        assert!(res.is_err());
+        assert!(bad_rcode.is_err());
+        assert!(servfail.is_ok());
+        assert!(matches!(
+            rules.decision_for("b.test", "1.1.1.1:53"),
+            Some(Decision::Static { rcode: ResponseCode::ServFail, .. })
+        ));
+    }
+}
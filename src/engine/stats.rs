@@ -0,0 +1,90 @@
+//! 运行时统计快照 / Runtime statistics snapshot
+//!
+//! 汇总请求计数、上游延迟、流控、缓存与上游连接池状态为一个 JSON 对象，
+//! 可通过 SIGUSR1 或管理接口写入文件或日志，适用于没有 Prometheus 的环境。
+//! Gathers request counters, upstream latency, flow control, cache and upstream pool
+//! state into one JSON object that can be dumped to a file or the log via SIGUSR1 or
+//! the admin API, for environments without Prometheus.
+
+use std::sync::atomic::Ordering;
+
+use anyhow::Context;
+use serde_json::{Value, json};
+use tracing::info;
+
+use super::Engine;
+
+impl Engine {
+    /// 生成运行时统计快照 / Build a runtime statistics snapshot
+    pub fn stats_snapshot(&self) -> Value {
+        let upstream_calls = self.metrics_upstream_calls.load(Ordering::Relaxed);
+        let upstream_ns = self.metrics_upstream_ns_total.load(Ordering::Relaxed);
+        let avg_upstream_us = upstream_ns.checked_div(upstream_calls).unwrap_or(0) / 1000;
+
+        let mut pools = self.tcp_mux.pool_stats();
+        pools.extend(self.dot_mux.pool_stats());
+        pools.extend(self.doq_client.pool_stats());
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        json!({
+            "timestamp": timestamp,
+            "listener": self.listener_label.as_ref(),
+            "requests": {
+                "total": self.metrics_total_requests.load(Ordering::Relaxed),
+                "fastpath_hits": self.metrics_fastpath_hits.load(Ordering::Relaxed),
+                "parse_quick_failures": self.metrics_parse_quick_failures.load(Ordering::Relaxed),
+                "inflight": self.metrics_inflight.load(Ordering::Relaxed),
+            },
+            "upstream": {
+                "calls": upstream_calls,
+                "avg_latency_us": avg_upstream_us,
+                "last_latency_us": self.metrics_last_upstream_latency_ns.load(Ordering::Relaxed) / 1000,
+                "tc_retries": self.metrics_tc_retries.load(Ordering::Relaxed),
+                "coalesced": self.metrics_coalesced.load(Ordering::Relaxed),
+                "qname_limited": self.qname_limiter.limited(),
+                "quarantined": self.quarantine.count(),
+                "per_upstream": self.upstream_stats.report(),
+                "health": self.upstream_health.report(),
+                "udp_pool": {
+                    "unsolicited": self.udp_client.counters().unsolicited(),
+                    "mismatched": self.udp_client.counters().mismatched(),
+                },
+                "pools": pools,
+            },
+            "flow_control": {
+                "enabled": self.flow_control_state.is_some(),
+                "inflight_permits": self.permit_manager.inflight(),
+                "max_permits": self.permit_manager.max_permits(),
+                "dropped_requests": self.permit_manager.dropped_requests(),
+            },
+            "udp_workers": self.udp_worker_stats.report(),
+            "cache": self.cache_metrics.snapshot(&self.cache),
+            "prefetch": self.prefetch.snapshot(),
+            "ipv6_reachable": self.ipv6_probe.last(),
+            "local_records_down": self.local_record_health.down(),
+            "log_suppressed": super::log_limit::suppressed_total(),
+            "reloads": self.reload_events.snapshot(),
+            "runtime_rules": self.runtime_rules.list().len(),
+        })
+    }
+
+    /// 输出统计快照：配置了 stats_dump_path 时写入文件，否则写入日志
+    /// Dump the statistics snapshot: to stats_dump_path when configured, otherwise to the log
+    pub fn dump_stats(&self) -> anyhow::Result<()> {
+        let snapshot = self.stats_snapshot();
+        let path = self.state.load().pipeline.settings.stats_dump_path.clone();
+        match path.as_deref() {
+            Some(path) => {
+                let body = serde_json::to_vec_pretty(&snapshot).context("serialize stats")?;
+                std::fs::write(path, body).with_context(|| format!("write stats dump to {}", path))?;
+                info!(event = "stats_dump", path = %path, "runtime stats written");
+            }
+            None => info!(event = "stats_dump", stats = %snapshot, "runtime stats"),
+        }
+        Ok(())
+    }
+}
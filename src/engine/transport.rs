@@ -7,6 +7,7 @@ use bytes::{Bytes, BytesMut};
 use dashmap::DashMap;
 use dashmap::mapref::entry;
 use rustc_hash::FxBuildHasher;
+use serde::Serialize;
 use socket2::{Domain, Protocol, Socket, Type, SockRef, TcpKeepalive};
 use reqwest::header::{ACCEPT, CONTENT_TYPE, HOST};
 use reqwest::Client as DohHttpClient;
@@ -24,10 +25,61 @@ use tokio::time::timeout;
 use tracing::{debug, info, warn};
 
 use super::concurrency::{PermitManager, PermitGuard};
+use super::happy_eyeballs;
+
+/// 等待 UDP 响应的查询 / A query waiting for its UDP response
+///
+/// 响应须来自同一上游地址、带有 QR 标志且问题段（qname/qtype/qclass，qname 忽略大小写）
+/// 与查询一致才会交付，否则视为无关报文丢弃并计数。上游对无法解析的查询常以不带问题段的
+/// FORMERR 等错误应答，这类 RCODE 非 NOERROR 且问题段为空的响应同样交付。
+/// A response is delivered only when it comes from the same upstream address, has the QR
+/// flag set and carries the same question (qname/qtype/qclass, qname case-insensitive);
+/// anything else is dropped and counted as unsolicited. Upstreams often answer queries they
+/// cannot parse with FORMERR and the like without a question section, so responses with an
+/// empty question section and an RCODE other than NOERROR are delivered as well.
+struct UdpPending {
+    original_id: u16,
+    addr: SocketAddr,
+    question: Bytes,
+    tx: oneshot::Sender<anyhow::Result<Bytes>>,
+}
+
+impl UdpPending {
+    #[inline]
+    fn accepts(&self, src: SocketAddr, response: &[u8]) -> bool {
+        if src != self.addr || response.len() < 12 || response[2] & 0x80 == 0 {
+            return false;
+        }
+        let qd_count = u16::from_be_bytes([response[4], response[5]]);
+        if qd_count == 0 {
+            return response[3] & 0x0F != 0;
+        }
+        crate::proto_utils::question_bytes(response).is_some_and(|q| q.eq_ignore_ascii_case(&self.question))
+    }
+}
 
 /// Type alias for UDP inflight request tracking
-/// ID -> (OriginalID, ExpectedAddr, Sender)
-type UdpInflightMap = DashMap<u16, (u16, SocketAddr, oneshot::Sender<anyhow::Result<Bytes>>), FxBuildHasher>;
+/// ID -> pending query
+type UdpInflightMap = DashMap<u16, UdpPending, FxBuildHasher>;
+
+/// UDP 连接池丢弃的报文计数 / Packets dropped by the UDP pool
+#[derive(Debug, Default)]
+pub struct UdpPoolCounters {
+    /// 没有对应查询的报文（ID 未在等待）/ Packets with no outstanding query (ID not waiting)
+    unsolicited: AtomicU64,
+    /// ID 匹配但地址或问题段不一致的报文 / Packets whose ID matched but address or question did not
+    mismatched: AtomicU64,
+}
+
+impl UdpPoolCounters {
+    pub fn unsolicited(&self) -> u64 {
+        self.unsolicited.load(Ordering::Relaxed)
+    }
+
+    pub fn mismatched(&self) -> u64 {
+        self.mismatched.load(Ordering::Relaxed)
+    }
+}
 
 /// RAII Guard to ensure inflight entries are removed even on cancellation/panic
 /// RAII Guard 确保即使在取消或 panic 时也能移除 inflight 条目
@@ -44,7 +96,7 @@ impl Drop for InflightGuard {
 
 struct UdpSocketState {
     socket: Arc<tokio::net::UdpSocket>,
-    /// Inflight map: ID -> (OriginalID, ExpectedAddr, Sender)
+    /// Inflight map: ID -> pending query
     /// Note: Using FxBuildHasher for performance
     inflight: Arc<UdpInflightMap>,
     next_id: AtomicU16,
@@ -53,6 +105,7 @@ struct UdpSocketState {
 pub struct UdpClient {
     pool: Vec<UdpSocketState>,
     next_idx: AtomicUsize,
+    counters: Arc<UdpPoolCounters>,
 }
 
 impl UdpClient {
@@ -60,6 +113,7 @@ impl UdpClient {
         // Prevent port exhaustion by enforcing minimum pool size
         let effective_size = if size == 0 { 1 } else { size };
         let mut pool = Vec::with_capacity(effective_size);
+        let counters = Arc::new(UdpPoolCounters::default());
         for idx in 0..effective_size {
             // Use socket2 to set buffer sizes
             let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).expect("create socket");
@@ -86,6 +140,7 @@ impl UdpClient {
 
             let socket_clone = socket.clone();
             let inflight_clone = inflight.clone();
+            let counters = counters.clone();
             tokio::spawn(async move {
                 // Use BytesMut for efficient buffer management
                 let mut buf = BytesMut::with_capacity(4096);
@@ -108,13 +163,12 @@ impl UdpClient {
                                 let id = u16::from_be_bytes([buf[0], buf[1]]);
                                 // 修复：使用 Entry API 原子操作，避免 remove-then-insert 导致的竞态条件
                                 // Fix: Use Entry API for atomic operations to avoid remove-then-insert race condition
-                                if let entry::Entry::Occupied(entry) = inflight_clone.entry(id) {
-                                    let (_, expected_addr, _) = entry.get();
-                                    if src == *expected_addr {
-                                        let (_, (original_id, _, tx)) = entry.remove_entry();
+                                match inflight_clone.entry(id) {
+                                    entry::Entry::Occupied(entry) if entry.get().accepts(src, &buf) => {
+                                        let pending = entry.remove();
 
                                         // Restore original TXID
-                                        let orig_bytes = original_id.to_be_bytes();
+                                        let orig_bytes = pending.original_id.to_be_bytes();
                                         buf[0] = orig_bytes[0];
                                         buf[1] = orig_bytes[1];
 
@@ -122,10 +176,10 @@ impl UdpClient {
                                         let response = buf.split_to(len).freeze();
                                         let resp_len = response.len();
 
-                                        if tx.send(Ok(response)).is_err() {
+                                        if pending.tx.send(Ok(response)).is_err() {
                                             tracing::debug!(
                                                 socket_idx = idx,
-                                                original_id = original_id,
+                                                original_id = pending.original_id,
                                                 response_id = id,
                                                 response_len = resp_len,
                                                 "Failed to send UDP response, channel already closed"
@@ -133,24 +187,38 @@ impl UdpClient {
                                         } else {
                                             tracing::trace!(
                                                 socket_idx = idx,
-                                                original_id = original_id,
+                                                original_id = pending.original_id,
                                                 response_id = id,
                                                 response_len = resp_len,
                                                 "UDP response sent successfully"
                                             );
                                         }
-                                    } else {
-                                        // Address mismatch: keep entry and wait for correct response
-                                        // 地址不匹配：保留条目等待正确响应（可能是网络攻击或路由异常）
-                                        tracing::warn!(
+                                    }
+                                    entry::Entry::Occupied(entry) => {
+                                        // 地址或问题段不匹配：保留条目等待正确响应（可能是伪造报文或迟到的旧响应）
+                                        // Address or question mismatch: keep the entry and wait for the right response
+                                        // (possibly spoofed or a late response to an earlier query)
+                                        counters.mismatched.fetch_add(1, Ordering::Relaxed);
+                                        tracing::debug!(
                                             socket_idx = idx,
                                             response_id = id,
-                                            expected_addr = %expected_addr,
+                                            expected_addr = %entry.get().addr,
                                             actual_addr = %src,
-                                            "UDP response address mismatch, possible spoofing or routing anomaly"
+                                            "UDP response does not match the outstanding query, dropped"
+                                        );
+                                    }
+                                    entry::Entry::Vacant(_) => {
+                                        counters.unsolicited.fetch_add(1, Ordering::Relaxed);
+                                        tracing::debug!(
+                                            socket_idx = idx,
+                                            response_id = id,
+                                            actual_addr = %src,
+                                            "unsolicited UDP packet on pool socket, dropped"
                                         );
                                     }
                                 }
+                            } else {
+                                counters.unsolicited.fetch_add(1, Ordering::Relaxed);
                             }
                         }
                         Err(e) => {
@@ -164,9 +232,15 @@ impl UdpClient {
         Self {
             pool,
             next_idx: AtomicUsize::new(0),
+            counters,
         }
     }
 
+    /// 丢弃报文计数 / Dropped packet counters
+    pub fn counters(&self) -> &UdpPoolCounters {
+        &self.counters
+    }
+
     #[inline]
     pub async fn send(
         &self,
@@ -187,6 +261,9 @@ impl UdpClient {
             return Err(anyhow::anyhow!("packet too short"));
         }
         let original_id = u16::from_be_bytes([packet[0], packet[1]]);
+        let question = crate::proto_utils::question_bytes(packet)
+            .map(Bytes::copy_from_slice)
+            .context("query has no question")?;
 
         // Find a free ID using atomic entry API
         // 使用原子 Entry API 查找空闲 ID
@@ -198,13 +275,20 @@ impl UdpClient {
             new_id = state.next_id.fetch_add(1, Ordering::Relaxed);
             match state.inflight.entry(new_id) {
                 entry::Entry::Vacant(e) => {
-                    e.insert((original_id, addr, tx));
+                    e.insert(UdpPending {
+                        original_id,
+                        addr,
+                        question: question.clone(),
+                        tx,
+                    });
                     break;
                 }
                 entry::Entry::Occupied(_) => {
                     attempts += 1;
                     if attempts > 100 {
-                        warn!("udp pool exhausted: socket_idx={} inflight_count={}", idx, state.inflight.len());
+                        if let Some(suppressed) = super::log_limit::allow("udp_pool_exhausted") {
+                            warn!(suppressed, "udp pool exhausted: socket_idx={} inflight_count={}", idx, state.inflight.len());
+                        }
                         return Err(anyhow::anyhow!("udp pool exhausted (too many inflight requests)"));
                     }
                 }
@@ -244,6 +328,25 @@ impl UdpClient {
     }
 }
 
+/// 上游连接池状态 / Upstream connection pool state
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStats {
+    pub upstream: String,
+    pub transport: &'static str,
+    /// 池中客户端数量 / Number of clients in the pool
+    pub clients: usize,
+    /// 持有已建立连接的客户端数量 / Number of clients holding an established connection
+    pub connected: usize,
+}
+
+/// 统计已建立的连接；锁被占用说明正在收发或建连，按已连接计
+/// Count established connections; a held lock means a send or connect is in flight, counted as connected
+fn count_connected<'a, T: 'a>(conns: impl Iterator<Item = &'a Mutex<Option<T>>>) -> usize {
+    conns
+        .filter(|c| c.try_lock().map(|g| g.is_some()).unwrap_or(true))
+        .count()
+}
+
 /// TCP 连接复用器，使用 DashMap 管理连接池 / TCP connection multiplexer, managing connection pool with DashMap
 pub struct TcpMultiplexer {
     pools: dashmap::DashMap<Arc<str>, Arc<TcpConnectionPool>, FxBuildHasher>,
@@ -254,6 +357,8 @@ pub struct TcpMultiplexer {
     health_error_threshold: usize,
     max_age_secs: u64,
     idle_timeout_secs: u64,
+    /// 出站连接是否启用 TCP Fast Open / Whether outbound connections use TCP Fast Open
+    fast_open: bool,
 }
 
 pub struct TcpConnectionPool {
@@ -274,9 +379,16 @@ impl TcpMultiplexer {
             health_error_threshold,
             max_age_secs,
             idle_timeout_secs,
+            fast_open: false,
         }
     }
 
+    /// 设置出站 TCP Fast Open / Configure outbound TCP Fast Open
+    pub fn with_fast_open(mut self, enabled: bool) -> Self {
+        self.fast_open = enabled;
+        self
+    }
+
     /// Warm up connection pools for given upstreams.
     /// 为给定的 upstream 预热连接池。
     ///
@@ -307,6 +419,7 @@ impl TcpMultiplexer {
                 let client = Arc::new(TcpMuxClient::new(
                     upstream_key.clone(),
                     Arc::clone(&permit_mgr),
+                    self.fast_open,
                 ));
                 client.set_health_check_config(
                     self.health_error_threshold,
@@ -329,6 +442,19 @@ impl TcpMultiplexer {
         );
     }
 
+    /// 各上游连接池状态 / Pool state per upstream
+    pub fn pool_stats(&self) -> Vec<PoolStats> {
+        self.pools
+            .iter()
+            .map(|e| PoolStats {
+                upstream: e.key().to_string(),
+                transport: "tcp",
+                clients: e.clients.len(),
+                connected: count_connected(e.clients.iter().map(|c| c.conn.as_ref())),
+            })
+            .collect()
+    }
+
     /// Test-only helper to initialize or get a pool without network operations
     /// This mirrors the production pool initialization logic used in send().
     #[cfg(test)]
@@ -345,6 +471,7 @@ impl TcpMultiplexer {
                     let client = Arc::new(TcpMuxClient::new(
                         upstream_key.clone(),
                         Arc::clone(&permit_mgr),
+                        self.fast_open,
                     ));
                     client.set_health_check_config(
                         self.health_error_threshold,
@@ -382,6 +509,7 @@ impl TcpMultiplexer {
                     let client = Arc::new(TcpMuxClient::new(
                         upstream_key.clone(),
                         Arc::clone(&permit_mgr),
+                        self.fast_open,
                     ));
                     // 设置健康检查配置
                     client.set_health_check_config(
@@ -478,6 +606,8 @@ pub struct TcpMuxClient {
     idle_timeout_ms: AtomicU64,
     /// 性能优化：上次健康检查时间（毫秒）/ Performance: last health check time (ms)
     last_health_check_time: AtomicU64,
+    /// 出站连接是否启用 TCP Fast Open / Whether outbound connections use TCP Fast Open
+    fast_open: bool,
 }
 
 struct Pending {
@@ -498,7 +628,7 @@ impl Drop for TcpPendingGuard {
 }
 
 impl TcpMuxClient {
-    fn new(upstream: Arc<str>, permit_manager: Arc<PermitManager>) -> Self {
+    fn new(upstream: Arc<str>, permit_manager: Arc<PermitManager>, fast_open: bool) -> Self {
         Self {
             upstream,
             conn: Arc::new(Mutex::new(None)),
@@ -515,6 +645,7 @@ impl TcpMuxClient {
             last_request_time: AtomicU64::new(0),
             idle_timeout_ms: AtomicU64::new(60_000),  // 1 分钟
             last_health_check_time: AtomicU64::new(0),
+            fast_open,
         }
     }
 
@@ -756,12 +887,15 @@ impl TcpMuxClient {
                         remaining
                     };
 
-                    tracing::warn!(
-                        upstream = %self.upstream,
-                        error = %err,
-                        retry_timeout_ms = retry_timeout.as_millis(),
-                        "Connection reuse failed, performing transparent retry with fresh connection"
-                    );
+                    if let Some(suppressed) = super::log_limit::allow("connection_retry") {
+                        tracing::warn!(
+                            upstream = %self.upstream,
+                            error = %err,
+                            retry_timeout_ms = retry_timeout.as_millis(),
+                            suppressed,
+                            "Connection reuse failed, performing transparent retry with fresh connection"
+                        );
+                    }
 
                     // Connection should have been reset by send_attempt already upon error
                     // send_attempt 出错时连接应该已经被重置
@@ -979,7 +1113,7 @@ impl TcpMuxClient {
 
             // Establish TCP connection
             // 建立 TCP 连接
-            let stream = TcpStream::connect(&*self.upstream).await
+            let stream = happy_eyeballs::connect_tcp(&self.upstream, self.fast_open).await
                 .map_err(|e| anyhow::anyhow!("tcp connect failed: {}", e))?;
 
             // Configure socket options for robustness
@@ -1139,8 +1273,14 @@ pub struct DohClient {
 
 impl DohClient {
     pub fn new(pool_max_idle_per_host: usize) -> anyhow::Result<Self> {
+        // HTTP/2 PING 保持空闲连接存活，避免空闲后的首个查询重新握手
+        // HTTP/2 PINGs keep idle connections alive so the first query after idling skips a new handshake
         let client = DohHttpClient::builder()
             .http2_adaptive_window(true)
+            .http2_keep_alive_interval(Duration::from_secs(30))
+            .http2_keep_alive_timeout(Duration::from_secs(10))
+            .http2_keep_alive_while_idle(true)
+            .tcp_keepalive(Duration::from_secs(60))
             .pool_idle_timeout(Duration::from_secs(90))
             .pool_max_idle_per_host(pool_max_idle_per_host.max(1))
             .build()
@@ -1184,9 +1324,20 @@ impl DohClient {
 
         Ok(Bytes::from(bytes))
     }
+
+    /// 预热：发送根 NS 查询以建立 HTTP/2 连接 / Prewarm: send a root NS query to establish the HTTP/2 connection
+    pub async fn prewarm(&self, upstream: &str, timeout_dur: Duration) -> anyhow::Result<()> {
+        self.send(&PREWARM_QUERY, upstream, timeout_dur).await.map(|_| ())
+    }
 }
 
-fn build_doh_url(upstream: &str) -> anyhow::Result<(Url, Option<String>)> {
+/// 预热用查询：`. IN NS`，ID 为 0（RFC 8484 §4.1 推荐）/ Prewarm query: `. IN NS` with ID 0 (recommended by RFC 8484 §4.1)
+const PREWARM_QUERY: [u8; 17] = [
+    0x00, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // header, RD
+    0x00, 0x00, 0x02, 0x00, 0x01, // root, NS, IN
+];
+
+pub(crate) fn build_doh_url(upstream: &str) -> anyhow::Result<(Url, Option<String>)> {
     let url_str = if upstream.starts_with("http://") || upstream.starts_with("https://") {
         upstream.to_string()
     } else if upstream.starts_with("doh://") {
@@ -1232,7 +1383,7 @@ type DotReadHalf = tokio::io::ReadHalf<DotTlsStream>;
 type DotWriteHalf = tokio::io::WriteHalf<DotTlsStream>;
 
 #[derive(Clone)]
-struct DotTarget {
+pub(crate) struct DotTarget {
     connect_addr: Arc<str>,
     sni: Arc<str>,
 }
@@ -1244,6 +1395,8 @@ pub struct DotMultiplexer {
     health_error_threshold: usize,
     max_age_secs: u64,
     idle_timeout_secs: u64,
+    /// 出站连接是否启用 TCP Fast Open / Whether outbound connections use TCP Fast Open
+    fast_open: bool,
 }
 
 pub struct DotConnectionPool {
@@ -1268,6 +1421,7 @@ pub struct DotMuxClient {
     last_request_time: AtomicU64,
     idle_timeout_ms: AtomicU64,
     last_health_check_time: AtomicU64,
+    fast_open: bool,
 }
 
 impl DotMultiplexer {
@@ -1285,18 +1439,19 @@ impl DotMultiplexer {
             health_error_threshold,
             max_age_secs,
             idle_timeout_secs,
+            fast_open: false,
         })
     }
 
-    #[inline]
-    pub async fn send(
-        &self,
-        packet: &[u8],
-        upstream: &str,
-        timeout_dur: Duration,
-    ) -> anyhow::Result<Bytes> {
+    /// 设置出站 TCP Fast Open / Configure outbound TCP Fast Open
+    pub fn with_fast_open(mut self, enabled: bool) -> Self {
+        self.fast_open = enabled;
+        self
+    }
+
+    fn get_or_init_pool(&self, upstream: &str) -> Arc<DotConnectionPool> {
         let upstream_key: Arc<str> = Arc::from(upstream);
-        let pool = self
+        self
             .pools
             .entry(upstream_key.clone())
             .or_insert_with(|| {
@@ -1308,6 +1463,7 @@ impl DotMultiplexer {
                         upstream_key.clone(),
                         Arc::clone(&self.tls_config),
                         Arc::clone(&permit_mgr),
+                        self.fast_open,
                     ));
                     client.set_health_check_config(
                         self.health_error_threshold,
@@ -1321,15 +1477,48 @@ impl DotMultiplexer {
                     next_idx: AtomicUsize::new(0),
                 })
             })
-            .clone();
+            .clone()
+    }
 
+    #[inline]
+    pub async fn send(
+        &self,
+        packet: &[u8],
+        upstream: &str,
+        timeout_dur: Duration,
+    ) -> anyhow::Result<Bytes> {
+        let pool = self.get_or_init_pool(upstream);
         let idx = pool.next_idx.fetch_add(1, Ordering::Relaxed) % pool.clients.len();
         pool.clients[idx].send(packet, timeout_dur).await
     }
+
+    /// 预热：为上游建立一条 TLS 连接 / Prewarm: establish one TLS connection to the upstream
+    pub async fn prewarm(&self, upstream: &str) -> anyhow::Result<()> {
+        let pool = self.get_or_init_pool(upstream);
+        pool.clients[0].ensure_connection().await
+    }
+
+    /// 各上游连接池状态 / Pool state per upstream
+    pub fn pool_stats(&self) -> Vec<PoolStats> {
+        self.pools
+            .iter()
+            .map(|e| PoolStats {
+                upstream: e.key().to_string(),
+                transport: "dot",
+                clients: e.clients.len(),
+                connected: count_connected(e.clients.iter().map(|c| c.conn.as_ref())),
+            })
+            .collect()
+    }
 }
 
 impl DotMuxClient {
-    fn new(upstream: Arc<str>, tls_config: Arc<ClientConfig>, permit_manager: Arc<PermitManager>) -> Self {
+    fn new(
+        upstream: Arc<str>,
+        tls_config: Arc<ClientConfig>,
+        permit_manager: Arc<PermitManager>,
+        fast_open: bool,
+    ) -> Self {
         Self {
             upstream,
             target: Mutex::new(None),
@@ -1347,6 +1536,7 @@ impl DotMuxClient {
             last_request_time: AtomicU64::new(0),
             idle_timeout_ms: AtomicU64::new(60_000),
             last_health_check_time: AtomicU64::new(0),
+            fast_open,
         }
     }
 
@@ -1513,12 +1703,15 @@ impl DotMuxClient {
                     } else {
                         remaining
                     };
-                    tracing::warn!(
-                        upstream = %self.upstream,
-                        error = %err,
-                        retry_timeout_ms = retry_timeout.as_millis(),
-                        "DoT reuse failed, retrying with fresh connection"
-                    );
+                    if let Some(suppressed) = super::log_limit::allow("connection_retry") {
+                        tracing::warn!(
+                            upstream = %self.upstream,
+                            error = %err,
+                            retry_timeout_ms = retry_timeout.as_millis(),
+                            suppressed,
+                            "DoT reuse failed, retrying with fresh connection"
+                        );
+                    }
                     return self.send_attempt(packet, retry_timeout).await;
                 }
                 Err(err)
@@ -1668,7 +1861,7 @@ impl DotMuxClient {
                 guard.as_ref().expect("dot target must be initialized").clone()
             };
 
-            let stream = TcpStream::connect(&*target.connect_addr).await
+            let stream = happy_eyeballs::connect_tcp(&target.connect_addr, self.fast_open).await
                 .map_err(|e| anyhow::anyhow!("dot connect failed: {}", e))?;
 
             let _ = stream.set_nodelay(true);
@@ -1766,12 +1959,18 @@ impl DotMuxClient {
     }
 }
 
+/// TLS 会话缓存容量（每个服务器名最多保留少量票据）/ TLS session cache capacity (a few tickets per server name)
+const TLS_SESSION_CACHE_SIZE: usize = 1024;
+
 fn build_tls_client_config() -> anyhow::Result<ClientConfig> {
     let mut root_store = RootCertStore::empty();
     root_store.extend(TLS_SERVER_ROOTS.iter().cloned());
-    let config = ClientConfig::builder()
+    let mut config = ClientConfig::builder()
         .with_root_certificates(root_store)
         .with_no_client_auth();
+    // 缓存会话票据，重连时走 TLS 会话恢复而非完整握手
+    // Cache session tickets so reconnects use TLS resumption instead of a full handshake
+    config.resumption = rustls::client::Resumption::in_memory_sessions(TLS_SESSION_CACHE_SIZE);
     Ok(config)
 }
 
@@ -1785,7 +1984,7 @@ fn build_server_name(name: &str) -> anyhow::Result<ServerName<'static>> {
     }
 }
 
-fn parse_dot_target(upstream: &str) -> anyhow::Result<DotTarget> {
+pub(crate) fn parse_dot_target(upstream: &str) -> anyhow::Result<DotTarget> {
     let url = if upstream.contains("://") {
         Url::parse(upstream)
     } else {
@@ -1830,7 +2029,7 @@ fn parse_dot_target(upstream: &str) -> anyhow::Result<DotTarget> {
 const MAX_DNS_MESSAGE_SIZE: usize = 65_535;
 
 #[derive(Clone)]
-struct DoqTarget {
+pub(crate) struct DoqTarget {
     host: Arc<str>,
     port: u16,
     sni: Arc<str>,
@@ -1889,6 +2088,7 @@ impl DoqClient {
             .with_no_client_auth();
         tls.alpn_protocols = vec![b"doq".to_vec()];
         tls.enable_early_data = enable_0rtt;
+        tls.resumption = rustls::client::Resumption::in_memory_sessions(TLS_SESSION_CACHE_SIZE);
 
         let quic_crypto = QuicClientConfig::try_from(tls)
             .context("build quic client config")?;
@@ -1945,14 +2145,9 @@ impl DoqClient {
         })
     }
 
-    pub async fn send(
-        &self,
-        packet: &[u8],
-        upstream: &str,
-        timeout_dur: Duration,
-    ) -> anyhow::Result<Bytes> {
+    fn get_or_init_pool(&self, upstream: &str) -> Arc<DoqConnectionPool> {
         let upstream_key: Arc<str> = Arc::from(upstream);
-        let pool = self
+        self
             .pools
             .entry(upstream_key.clone())
             .or_insert_with(|| {
@@ -1968,11 +2163,38 @@ impl DoqClient {
                     next_idx: AtomicUsize::new(0),
                 })
             })
-            .clone();
+            .clone()
+    }
 
+    pub async fn send(
+        &self,
+        packet: &[u8],
+        upstream: &str,
+        timeout_dur: Duration,
+    ) -> anyhow::Result<Bytes> {
+        let pool = self.get_or_init_pool(upstream);
         let idx = pool.next_idx.fetch_add(1, Ordering::Relaxed) % pool.clients.len();
         pool.clients[idx].send(packet, timeout_dur).await
     }
+
+    /// 预热：为上游建立一条 QUIC 连接 / Prewarm: establish one QUIC connection to the upstream
+    pub async fn prewarm(&self, upstream: &str, timeout_dur: Duration) -> anyhow::Result<()> {
+        let pool = self.get_or_init_pool(upstream);
+        pool.clients[0].prewarm(timeout_dur).await
+    }
+
+    /// 各上游连接池状态 / Pool state per upstream
+    pub fn pool_stats(&self) -> Vec<PoolStats> {
+        self.pools
+            .iter()
+            .map(|e| PoolStats {
+                upstream: e.key().to_string(),
+                transport: "doq",
+                clients: e.clients.len(),
+                connected: count_connected(e.clients.iter().map(|c| &c.connection)),
+            })
+            .collect()
+    }
 }
 
 impl DoqMuxClient {
@@ -2006,6 +2228,17 @@ impl DoqMuxClient {
         self.send_with_retry(&target, packet, timeout_dur, true).await
     }
 
+    async fn prewarm(&self, timeout_dur: Duration) -> anyhow::Result<()> {
+        let target = {
+            let mut guard = self.target.lock().await;
+            if guard.is_none() {
+                *guard = Some(parse_doq_target(&self.upstream)?);
+            }
+            guard.as_ref().expect("doq target must be initialized").clone()
+        };
+        self.get_or_connect(&target, timeout_dur).await.map(|_| ())
+    }
+
     async fn send_with_retry(
         &self,
         target: &DoqTarget,
@@ -2219,22 +2452,17 @@ impl DoqMuxClient {
 
     async fn connect_new(&self, target: &DoqTarget, timeout_dur: Duration) -> anyhow::Result<(QuicConnection, bool)> {
         let addr_str = format!("{}:{}", target.host, target.port);
-        let addrs = tokio::net::lookup_host(&addr_str).await
-            .context("doq resolve failed")?;
 
-        // 优先使用 IPv4 地址，避免 IPv6 连接问题
-        // Prefer IPv4 addresses to avoid IPv6 connection issues
+        // 没有记录时优先使用 IPv4 地址，避免 IPv6 连接问题；之后优先使用上次连接成功的地址族
+        // Prefer IPv4 when nothing is recorded to avoid IPv6 connection issues; afterwards prefer the family that last succeeded
         // 某些网络的 IPv6 连接不稳定或 MTU 限制导致 QUIC Initial 数据包发送失败
         // Some networks have unstable IPv6 or MTU limits causing QUIC Initial packet send failures
-        let addrs_vec: Vec<_> = addrs.collect();
-        let addr = addrs_vec
-            .iter()
-            .find(|a| a.is_ipv4())
-            .or_else(|| addrs_vec.first())
+        let addrs_vec = happy_eyeballs::resolve(&addr_str, false).await
+            .context("doq resolve failed")?;
+        let addr = *addrs_vec
+            .first()
             .context("doq resolve returned no addresses")?;
 
-        let addr = *addr;
-
         let endpoint = if addr.is_ipv6() { &self.runtime.endpoint_v6 } else { &self.runtime.endpoint_v4 };
         let connecting = endpoint.connect(addr, target.sni.as_ref())
             .context("doq connect failed")?;
@@ -2270,6 +2498,7 @@ impl DoqMuxClient {
                     // 0-RTT 不可用（无先前会话），回退到正常连接
                     let connection = timeout(timeout_dur, connecting).await
                         .context("doq connect timeout")??;
+                    happy_eyeballs::remember(&addr_str, addr);
                     return Ok((connection, false));
                 }
             }
@@ -2277,6 +2506,7 @@ impl DoqMuxClient {
 
         let connection = timeout(timeout_dur, connecting).await
             .context("doq connect timeout")??;
+        happy_eyeballs::remember(&addr_str, addr);
 
         Ok((connection, false))
     }
@@ -2308,7 +2538,7 @@ fn restore_doq_response_id(response: &[u8], original_id: u16) -> anyhow::Result<
     Ok(restored.freeze())
 }
 
-fn parse_doq_target(upstream: &str) -> anyhow::Result<DoqTarget> {
+pub(crate) fn parse_doq_target(upstream: &str) -> anyhow::Result<DoqTarget> {
     let url = if upstream.contains("://") {
         Url::parse(upstream)
     } else {
@@ -2397,11 +2627,81 @@ mod tests {
         assert!(parse_doq_target("doq://dns.alidns.com:853").is_ok());
     }
 
+    #[test]
+    fn udp_pending_accepts_error_responses_without_a_question() {
+        // Arrange
+        let addr: SocketAddr = "192.0.2.53:53".parse().unwrap();
+        let (tx, _rx) = oneshot::channel();
+        let pending = UdpPending {
+            original_id: 0x4242,
+            addr,
+            question: Bytes::from_static(&[3, b'w', b'w', b'w', 0, 0, 1, 0, 1]),
+            tx,
+        };
+        let header = |rcode: u8| vec![0x42, 0x42, 0x81, 0x80 | rcode, 0, 0, 0, 0, 0, 0, 0, 0];
+
+        // Act
+        let formerr = pending.accepts(addr, &header(1));
+        let servfail = pending.accepts(addr, &header(2));
+        let noerror = pending.accepts(addr, &header(0));
+        let elsewhere = pending.accepts("192.0.2.54:53".parse().unwrap(), &header(1));
+
+        // Assert
+        assert!(formerr);
+        assert!(servfail);
+        assert!(!noerror, "an empty NOERROR answer still has to carry the question");
+        assert!(!elsewhere);
+    }
+
+    #[tokio::test]
+    async fn udp_pool_only_delivers_responses_matching_the_query() {
+        // Arrange: A fake upstream that answers with a wrong question and a stray ID first
+        use hickory_proto::op::{Message, MessageType, Query};
+        use hickory_proto::rr::{Name, RecordType};
+        let upstream = tokio::net::UdpSocket::bind("127.0.0.1:0").await.expect("bind");
+        let upstream_addr = upstream.local_addr().expect("addr").to_string();
+        let client = UdpClient::new(1);
+        let message = |id: u16, qname: &str, kind: MessageType| {
+            let mut msg = Message::new();
+            msg.set_id(id)
+                .set_message_type(kind)
+                .add_query(Query::query(Name::from_ascii(qname).unwrap(), RecordType::A));
+            msg.to_vec().unwrap()
+        };
+        let fake = tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (_, peer) = upstream.recv_from(&mut buf).await.expect("recv");
+            let id = u16::from_be_bytes([buf[0], buf[1]]);
+            for packet in [
+                message(id, "other.example.", MessageType::Response),
+                message(id.wrapping_add(1), "www.example.", MessageType::Response),
+                message(id, "www.example.", MessageType::Query),
+                message(id, "WWW.Example.", MessageType::Response),
+            ] {
+                upstream.send_to(&packet, peer).await.expect("send");
+            }
+        });
+
+        // Act
+        let response = client
+            .send(&message(0x4242, "www.example.", MessageType::Query), &upstream_addr, Duration::from_secs(2))
+            .await
+            .expect("response");
+        fake.await.expect("fake upstream");
+
+        // Assert
+        let response = Message::from_vec(&response).expect("parse");
+        assert_eq!(response.id(), 0x4242);
+        assert_eq!(response.queries()[0].name().to_ascii(), "WWW.Example.");
+        assert_eq!(client.counters().mismatched(), 2);
+        assert_eq!(client.counters().unsolicited(), 1);
+    }
+
     #[tokio::test]
     async fn tcp_mux_rewrite_id_no_deadlock_under_contention() {
         // Arrange: Prepare a TCP client with many pending IDs to force contention
         let permit_manager = Arc::new(PermitManager::new(128)); // Default TCP limit
-        let client = Arc::new(TcpMuxClient::new(Arc::from("127.0.0.1:0"), permit_manager));
+        let client = Arc::new(TcpMuxClient::new(Arc::from("127.0.0.1:0"), permit_manager, false));
         for id in 1u16..200u16 {
             client.pending.insert(
                 id,
@@ -2465,4 +2765,21 @@ mod tests {
         assert_eq!(permit_a.max_permits(), 2, "Permit manager should match pool size for upstream A");
         assert_eq!(permit_b.max_permits(), 2, "Permit manager should match pool size for upstream B");
     }
+
+    #[test]
+    fn bare_hostnames_get_default_doh_path_and_dot_port() {
+        // Arrange: `{"upstream":"dns.google","transport":"doh"|"dot"}`
+        let upstream = "dns.google";
+
+        // Act
+        let (url, host_override) = build_doh_url(upstream).unwrap();
+        let dot = parse_dot_target(upstream).unwrap();
+        let pinned = parse_dot_target("dot://8.8.8.8?sni=dns.google").unwrap();
+
+        // Assert
+        assert_eq!(url.as_str(), "https://dns.google/dns-query");
+        assert!(host_override.is_none());
+        assert_eq!((&*dot.connect_addr, &*dot.sni), ("dns.google:853", "dns.google"));
+        assert_eq!((&*pinned.connect_addr, &*pinned.sni), ("8.8.8.8:853", "dns.google"));
+    }
 }
@@ -0,0 +1,269 @@
+//! 可热重载的运行时参数 / Hot-reloadable runtime tunables
+//!
+//! 大部分 settings 在查询时从当前配置读取，重载后立即生效（如超时、min_ttl、否定缓存）。
+//! 启动时被拷贝的参数分两类：缓存刷新 / serve-stale / 流控限制等保存在原子变量中，重载时更新；
+//! 监听地址变更时通知主程序先绑定新 socket 再停止旧 socket；
+//! 连接池、缓存容量等在启动时构建对象，修改后需要重启，重载时逐项告警。
+//! Most settings are read from the current config at query time and apply right after a
+//! reload (timeouts, min_ttl, negative caching, ...). Settings copied at startup fall into
+//! two groups: cache refresh / serve-stale / flow control limits live in atomics updated on
+//! reload, listen address changes notify the server to bind the new sockets before stopping
+//! the old ones, while connection pools, cache capacity and the like build objects at startup
+//! and need a restart, which the reload logs for each changed field.
+
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use anyhow::Context;
+
+use crate::config::{GlobalSettings, Ipv6ListenMode};
+use crate::socket_utils::{ListenSocket, parse_bind_list, plan_listen_sockets};
+
+use super::Engine;
+
+/// 修改后需要重启才能生效的 settings 字段 / Settings fields that need a restart to take effect
+pub const RESTART_REQUIRED: &[&str] = &[
+    "admin_bind",
+    "grpc_admin_bind",
+    "tcp_fast_open",
+    "bind_dot",
+    "bind_doh",
+    "doh_path",
+    "doh_max_body_bytes",
+    "doh_max_concurrent_streams",
+    "doh_json_errors",
+    "tls_cert_path",
+    "tls_key_path",
+    "cache_capacity",
+    "cache_max_bytes",
+    "cache_max_ttl",
+    "dashmap_shards",
+    "udp_pool_size",
+    "udp_buffers",
+    "tcp_pool_size",
+    "doh_pool_size",
+    "dot_pool_size",
+    "doq_pool_size",
+    "doq_connection_idle_timeout_seconds",
+    "doq_keepalive_interval_ms",
+    "doq_enable_0rtt",
+    "tcp_health_check_error_threshold",
+    "tcp_connection_max_age_seconds",
+    "tcp_connection_idle_timeout_seconds",
+    "upstream_prewarm",
+    "upstream_prewarm_interval_secs",
+    "flow_control_enabled",
+    "flow_control_initial_permits",
+    "prefetch_workers",
+    "prefetch_queue_size",
+    "domain_stats_enabled",
+    "domain_stats_capacity",
+    "domain_stats_window_secs",
+    "domain_stats_min_queries",
+    "domain_stats_sample_rate",
+    "mdns_bridge",
+    "mdns_timeout_ms",
+    "mdns_interface",
+    "geoip_db_path",
+    "geoip_dat_path",
+    "geosite_data_paths",
+];
+
+/// 启动时拷贝、重载时更新的参数 / Parameters copied at startup and updated on reload
+#[derive(Debug, Default)]
+pub struct RuntimeTunables {
+    cache_background_refresh: AtomicBool,
+    cache_refresh_threshold_percent: AtomicU8,
+    cache_refresh_min_ttl: AtomicU32,
+    serve_stale: AtomicBool,
+    serve_stale_ttl: AtomicU32,
+    serve_stale_expire_ttl: AtomicU64,
+    serve_stale_ttl_reset: AtomicBool,
+    serve_stale_client_timeout_ms: AtomicU64,
+    cache_compress_threshold: AtomicUsize,
+    udp_batch_max: AtomicUsize,
+    udp_batch_adaptive: AtomicBool,
+}
+
+impl RuntimeTunables {
+    pub fn new(settings: &GlobalSettings) -> Self {
+        let tunables = Self::default();
+        tunables.apply(settings);
+        tunables
+    }
+
+    pub fn apply(&self, s: &GlobalSettings) {
+        self.cache_background_refresh.store(s.cache_background_refresh, Ordering::Relaxed);
+        self.cache_refresh_threshold_percent.store(s.cache_refresh_threshold_percent, Ordering::Relaxed);
+        self.cache_refresh_min_ttl.store(s.cache_refresh_min_ttl, Ordering::Relaxed);
+        self.serve_stale.store(s.serve_stale, Ordering::Relaxed);
+        self.serve_stale_ttl.store(s.serve_stale_ttl, Ordering::Relaxed);
+        self.serve_stale_expire_ttl.store(s.serve_stale_expire_ttl, Ordering::Relaxed);
+        self.serve_stale_ttl_reset.store(s.serve_stale_ttl_reset, Ordering::Relaxed);
+        self.serve_stale_client_timeout_ms.store(s.serve_stale_client_timeout_ms, Ordering::Relaxed);
+        self.cache_compress_threshold.store(s.cache_compress_threshold, Ordering::Relaxed);
+        self.udp_batch_max.store(s.udp_batch_max.max(1), Ordering::Relaxed);
+        self.udp_batch_adaptive.store(s.udp_batch_adaptive, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn cache_background_refresh(&self) -> bool {
+        self.cache_background_refresh.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn cache_refresh_threshold_percent(&self) -> u8 {
+        self.cache_refresh_threshold_percent.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn cache_refresh_min_ttl(&self) -> u32 {
+        self.cache_refresh_min_ttl.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn serve_stale(&self) -> bool {
+        self.serve_stale.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn serve_stale_ttl(&self) -> u32 {
+        self.serve_stale_ttl.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn serve_stale_expire_ttl(&self) -> u64 {
+        self.serve_stale_expire_ttl.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn serve_stale_ttl_reset(&self) -> bool {
+        self.serve_stale_ttl_reset.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn serve_stale_client_timeout_ms(&self) -> u64 {
+        self.serve_stale_client_timeout_ms.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn cache_compress_threshold(&self) -> usize {
+        self.cache_compress_threshold.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn udp_batch_max(&self) -> usize {
+        self.udp_batch_max.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn udp_batch_adaptive(&self) -> bool {
+        self.udp_batch_adaptive.load(Ordering::Relaxed)
+    }
+}
+
+/// UDP/TCP 监听地址 / UDP/TCP listen addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenAddrs {
+    pub udp: String,
+    pub tcp: String,
+    pub ipv6_mode: Ipv6ListenMode,
+}
+
+impl ListenAddrs {
+    pub fn from_settings(s: &GlobalSettings) -> Self {
+        Self {
+            udp: s.bind_udp.clone(),
+            tcp: s.bind_tcp.clone(),
+            ipv6_mode: s.ipv6_listen_mode,
+        }
+    }
+
+    /// 需要创建的 UDP socket / UDP sockets to create
+    pub fn udp_sockets(&self) -> anyhow::Result<Vec<ListenSocket>> {
+        Ok(plan_listen_sockets(&parse_bind_list(&self.udp).context("parse bind_udp")?, self.ipv6_mode))
+    }
+
+    /// 需要创建的 TCP socket / TCP sockets to create
+    pub fn tcp_sockets(&self) -> anyhow::Result<Vec<ListenSocket>> {
+        Ok(plan_listen_sockets(&parse_bind_list(&self.tcp).context("parse bind_tcp")?, self.ipv6_mode))
+    }
+}
+
+/// 变更的 settings 字段，分为已生效与需要重启两组
+/// Changed settings fields, split into applied and restart-required
+pub fn classify_changes(old: &GlobalSettings, new: &GlobalSettings) -> (Vec<String>, Vec<String>) {
+    let old = serde_json::to_value(old).unwrap_or_default();
+    let new = serde_json::to_value(new).unwrap_or_default();
+    let changed = super::reload_events::changed_keys(&old, &new);
+    changed
+        .into_iter()
+        .partition(|k| !RESTART_REQUIRED.contains(&k.as_str()))
+}
+
+impl Engine {
+    /// 重载时应用新的 settings，并告警需要重启的字段
+    /// Apply new settings on reload and warn about fields that need a restart
+    pub(crate) fn apply_settings(&self, old: &GlobalSettings, new: &GlobalSettings) {
+        self.tunables.apply(new);
+        if let Some(state) = &self.flow_control_state {
+            state.apply(new, &self.permit_manager);
+        }
+        self.permit_manager.set_hard_limit(new.max_outstanding_queries);
+        let addrs = ListenAddrs::from_settings(new);
+        self.listen_addrs.send_if_modified(|current| {
+            if *current == addrs {
+                return false;
+            }
+            *current = addrs;
+            true
+        });
+
+        let (applied, restart_required) = classify_changes(old, new);
+        if !applied.is_empty() {
+            info!(target = "watcher", event = "settings_applied", fields = ?applied, "settings applied on reload");
+        }
+        for field in &restart_required {
+            warn!(
+                target = "watcher",
+                event = "settings_restart_required",
+                field = %field,
+                "setting changed but only takes effect after a restart"
+            );
+        }
+    }
+}
+
+impl Engine {
+    /// 订阅监听地址变更，主程序据此重新绑定 / Subscribe to listen address changes so the server can rebind
+    pub fn watch_listen_addrs(&self) -> watch::Receiver<ListenAddrs> {
+        self.listen_addrs.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes_are_split_into_applied_and_restart_required() {
+        // Arrange
+        let old = GlobalSettings::default();
+        let mut new = GlobalSettings::default();
+        new.upstream_timeout_ms += 500;
+        new.serve_stale = !old.serve_stale;
+        new.cache_capacity += 1;
+        let tunables = RuntimeTunables::new(&old);
+
+        // Act
+        let (applied, restart_required) = classify_changes(&old, &new);
+        tunables.apply(&new);
+
+        // Assert
+        assert_eq!(applied, vec!["serve_stale", "upstream_timeout_ms"]);
+        assert_eq!(restart_required, vec!["cache_capacity"]);
+        assert_eq!(tunables.serve_stale(), new.serve_stale);
+    }
+}
@@ -1,4 +1,3 @@
-use std::time::Instant;
 use std::sync::Arc;
 use bytes::Bytes;
 use dashmap::DashMap;
@@ -9,22 +8,47 @@ use crate::matcher::advanced_rule::CompiledPipeline;
 
 pub type InflightMap = DashMap<u64, watch::Sender<Result<Bytes, Arc<anyhow::Error>>>, FxBuildHasher>;
 
+/// 请求报文无法解析（或没有问题），监听器据此决定是否回复 FORMERR/REFUSED
+/// The request packet could not be parsed (or has no question); listeners use this to decide whether to answer FORMERR/REFUSED
+#[derive(Debug)]
+pub struct MalformedQuery {
+    source: anyhow::Error,
+}
+
+impl MalformedQuery {
+    pub fn new(source: anyhow::Error) -> Self {
+        Self { source }
+    }
+}
+
+impl std::fmt::Display for MalformedQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "malformed query")
+    }
+}
+
+impl std::error::Error for MalformedQuery {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
 // ============================================================================
 // Fast-path Response / 快速路径响应
 // ============================================================================
 ///
 /// - `Direct`: already has correct TXID and can be sent as-is.
 /// - `CacheHit`: carries cached bytes (with an old TXID) and the request TXID to patch.
-///   Also includes insertion time and original TTL for RFC 1035 §5.2 compliance.
+///   TTLs are already decremented by the residence time per RFC 1035 §5.2.
 /// - `AsyncNeeded`: cache miss, needs async processing. Contains pre-parsed data to avoid re-parsing.
 #[derive(Debug, Clone)]
 pub enum FastPathResponse {
     Direct(Bytes),
     CacheHit {
+        /// 已按停留时间递减 TTL，与其他命中共享，不可原地修改
+        /// TTLs already aged by residence time; shared with other hits, never patched in place
         cached: Bytes,
         tx_id: u16,
-        /// Insertion time for TTL calculation / 用于TTL计算的插入时间
-        inserted_at: Instant,
     },
     /// Cache miss, needs async processing. Contains pre-parsed data to avoid re-parsing in handle_packet.
     /// 缓存未命中，需要异步处理。包含预解析数据以避免在 handle_packet 中重新解析。
@@ -35,6 +59,8 @@ pub enum FastPathResponse {
         qclass: u16,
         tx_id: u16,
         edns_present: bool,
+        /// 请求的 DO/CD 标志位 / The request's DO/CD bits
+        dnssec_flags: u8,
         /// Pre-selected pipeline ID to avoid re-selecting / 预选择的 pipeline ID，避免重新选择
         pipeline_id: Arc<str>,
     },
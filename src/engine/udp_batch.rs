@@ -0,0 +1,209 @@
+//! UDP worker 的自适应批量接收 / Adaptive batched receive for UDP workers
+//!
+//! 每次唤醒后 worker 先处理等待到的报文，再以非阻塞方式继续读取 socket 中积压的报文，直到批量上限
+//! 或 socket 读空，然后让出调度。批量上限在 1 与 `udp_batch_max` 之间自适应：批次被填满（socket
+//! 仍有积压）且处理耗时在预算内时加倍，批次远未填满或处理过慢时减半，使 10G 链路能一次清空积压，
+//! 树莓派之类的小设备也不会因长批次饿死其他任务。每个 worker 的批次、报文与过载丢弃计数计入统计。
+//! After each wakeup a worker handles the packet it waited for, then keeps reading the
+//! socket backlog without blocking until the batch limit is reached or the socket is empty,
+//! and then yields. The limit adapts between 1 and `udp_batch_max`: it doubles when a batch
+//! fills up (the socket still has a backlog) within the latency budget and halves when
+//! batches stay mostly empty or run too slow, so 10G links drain their backlog in one go
+//! while small devices like a Raspberry Pi do not starve other tasks with long batches.
+//! Per-worker batch, packet and overload-drop counters feed the statistics.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use rustc_hash::FxBuildHasher;
+use serde::Serialize;
+
+use super::tunables::RuntimeTunables;
+
+/// 一个批次的处理耗时预算，超出时缩小批量 / Processing time budget of one batch; the limit shrinks when exceeded
+const BATCH_LATENCY_BUDGET: Duration = Duration::from_millis(2);
+/// 自适应模式的初始批量 / Initial batch limit in adaptive mode
+const INITIAL_BATCH: usize = 8;
+
+#[derive(Default)]
+struct WorkerCounters {
+    packets: AtomicU64,
+    batches: AtomicU64,
+    full_batches: AtomicU64,
+    dropped: AtomicU64,
+    batch_limit: AtomicUsize,
+}
+
+/// 单个 UDP worker 的统计报告 / Statistics report for a single UDP worker
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct UdpWorkerReport {
+    pub packets: u64,
+    pub batches: u64,
+    /// 被填满（socket 仍有积压）的批次 / Batches that filled up (the socket still had a backlog)
+    pub full_batches: u64,
+    /// 因超出并发上限而未处理的报文 / Packets not handled because of the concurrency limit
+    pub dropped: u64,
+    pub avg_batch: u64,
+    /// 当前批量上限 / Current batch limit
+    pub batch_limit: usize,
+}
+
+/// 按 worker 聚合的统计 / Statistics aggregated by worker
+#[derive(Default)]
+pub struct UdpWorkerStats {
+    workers: DashMap<Arc<str>, Arc<WorkerCounters>, FxBuildHasher>,
+}
+
+impl UdpWorkerStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 按 worker 排序的报告 / Reports keyed by worker
+    pub fn report(&self) -> BTreeMap<String, UdpWorkerReport> {
+        self.workers
+            .iter()
+            .map(|e| {
+                let c = e.value();
+                let packets = c.packets.load(Ordering::Relaxed);
+                let batches = c.batches.load(Ordering::Relaxed);
+                let report = UdpWorkerReport {
+                    packets,
+                    batches,
+                    full_batches: c.full_batches.load(Ordering::Relaxed),
+                    dropped: c.dropped.load(Ordering::Relaxed),
+                    avg_batch: packets.checked_div(batches).unwrap_or(0),
+                    batch_limit: c.batch_limit.load(Ordering::Relaxed),
+                };
+                (e.key().to_string(), report)
+            })
+            .collect()
+    }
+}
+
+/// 一个 UDP worker 的批量接收状态 / Batched receive state of one UDP worker
+pub struct UdpBatch {
+    tunables: Arc<RuntimeTunables>,
+    counters: Arc<WorkerCounters>,
+    limit: usize,
+    received: usize,
+    started: Instant,
+}
+
+impl UdpBatch {
+    /// 为 worker 创建批量状态，同名 worker（重新绑定后）沿用原有计数
+    /// Create the batch state for a worker; a worker of the same name (after a rebind) keeps its counters
+    pub(crate) fn new(stats: &UdpWorkerStats, tunables: Arc<RuntimeTunables>, worker: &str) -> Self {
+        let counters = Arc::clone(&stats.workers.entry(Arc::from(worker)).or_default());
+        let max = tunables.udp_batch_max();
+        let limit = if tunables.udp_batch_adaptive() { INITIAL_BATCH.min(max) } else { max };
+        counters.batch_limit.store(limit, Ordering::Relaxed);
+        Self {
+            tunables,
+            counters,
+            limit,
+            received: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// 阻塞等待到一个报文，开始新批次 / A packet arrived after waiting; start a new batch
+    #[inline]
+    pub fn begin(&mut self) {
+        self.received = 1;
+        self.started = Instant::now();
+    }
+
+    /// 当前批次是否还可以非阻塞地继续读取 / Whether the current batch may keep reading without blocking
+    #[inline]
+    pub fn wants_more(&self) -> bool {
+        self.received > 0 && self.received < self.limit
+    }
+
+    /// 批次中非阻塞读到一个报文 / A packet was read without blocking within the batch
+    #[inline]
+    pub fn record_packet(&mut self) {
+        self.received += 1;
+    }
+
+    /// 记录一个因过载未处理的报文 / Record a packet left unhandled because of overload
+    #[inline]
+    pub fn record_drop(&self) {
+        self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 结束当前批次并调整批量上限，返回批次是否被填满（调用方应让出调度）
+    /// Finish the current batch and adapt the limit; returns whether it filled up (the caller should yield)
+    pub fn finish(&mut self) -> bool {
+        if self.received == 0 {
+            return false;
+        }
+        let full = self.received >= self.limit;
+        self.counters.packets.fetch_add(self.received as u64, Ordering::Relaxed);
+        self.counters.batches.fetch_add(1, Ordering::Relaxed);
+        if full {
+            self.counters.full_batches.fetch_add(1, Ordering::Relaxed);
+        }
+        self.limit = self.next_limit(full, self.started.elapsed());
+        self.counters.batch_limit.store(self.limit, Ordering::Relaxed);
+        self.received = 0;
+        full
+    }
+
+    fn next_limit(&self, full: bool, elapsed: Duration) -> usize {
+        let max = self.tunables.udp_batch_max();
+        if !self.tunables.udp_batch_adaptive() {
+            return max;
+        }
+        let limit = if elapsed > BATCH_LATENCY_BUDGET || (!full && self.received <= self.limit / 4) {
+            self.limit / 2
+        } else if full {
+            self.limit.saturating_mul(2)
+        } else {
+            self.limit
+        };
+        limit.clamp(1, max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GlobalSettings;
+
+    #[test]
+    fn test_batch_limit_follows_backlog() {
+        // Arrange
+        let stats = UdpWorkerStats::new();
+        let settings = GlobalSettings { udp_batch_max: 32, ..GlobalSettings::default() };
+        let mut batch = UdpBatch::new(&stats, Arc::new(RuntimeTunables::new(&settings)), "udp:0.0.0.0:53#0");
+        let run = |batch: &mut UdpBatch, backlog: usize| {
+            batch.begin();
+            let mut left = backlog;
+            while batch.wants_more() && left > 0 {
+                batch.record_packet();
+                left -= 1;
+            }
+            batch.finish()
+        };
+
+        // Act
+        let filled = (0..4).map(|_| run(&mut batch, 1000)).collect::<Vec<_>>();
+        let grown = batch.limit;
+        let drained = run(&mut batch, 0);
+        batch.record_drop();
+        let report = stats.report();
+
+        // Assert
+        assert_eq!(filled, [true; 4]);
+        assert_eq!(grown, 32);
+        assert!(!drained);
+        assert_eq!(batch.limit, 16);
+        let worker = &report["udp:0.0.0.0:53#0"];
+        assert_eq!((worker.packets, worker.batches, worker.full_batches), (8 + 16 + 32 + 32 + 1, 5, 4));
+        assert_eq!((worker.dropped, worker.batch_limit), (1, 16));
+    }
+}
@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::time::Duration;
 use std::sync::atomic::Ordering;
 
@@ -9,7 +11,7 @@ use tracing::{debug};
 use hickory_proto::op::ResponseCode;
 use futures::future::select;
 
-use crate::config::Transport;
+use crate::config::{Transport, UpstreamOptions};
 use super::Engine;
 
 /// Error indicating that all upstream attempts have been exhausted.
@@ -50,8 +52,12 @@ impl std::error::Error for UpstreamFailure {
 /// - "doq://dns.example.com:853" -> ("dns.example.com:853", Transport::Doq)
 /// - "doh://dns.example.com/dns-query" -> ("dns.example.com/dns-query", Transport::Doh)
 /// - "https://dns.example.com/dns-query" -> ("dns.example.com/dns-query", Transport::Doh)
+/// - "dnscrypt://1.2.3.4:443?provider=..&pk=.." -> ("1.2.3.4:443?provider=..&pk=..", Transport::Dnscrypt)
+/// - "sdns://AQ..." -> ("sdns://AQ...", Transport::Dnscrypt)
+/// - "odoh://target.example/dns-query?relay=https://relay.example/proxy" -> ("target.example/dns-query?relay=https://relay.example/proxy", Transport::Odoh)
+/// - "mock://a=1.2.3.4&ttl=60" -> ("a=1.2.3.4&ttl=60", Transport::Mock)
 /// - "1.1.1.1:53" -> ("1.1.1.1:53", default_transport)
-fn parse_upstream_addr(addr: &str, default_transport: Transport) -> (&str, Transport) {
+pub(crate) fn parse_upstream_addr(addr: &str, default_transport: Transport) -> (&str, Transport) {
     if let Some(idx) = addr.find("://") {
         let protocol = &addr[..idx];
         let address = &addr[idx + 3..];
@@ -62,6 +68,11 @@ fn parse_upstream_addr(addr: &str, default_transport: Transport) -> (&str, Trans
             "doh" | "https" => Transport::Doh,
             "dot" | "tls" => Transport::Dot,
             "doq" | "quic" => Transport::Doq,
+            "dnscrypt" => Transport::Dnscrypt,
+            "odoh" => Transport::Odoh,
+            "mock" => Transport::Mock,
+            // 印章需要保留前缀以区分参数形式 / Stamps keep their prefix to tell them from the parameter form
+            "sdns" => return (addr, Transport::Dnscrypt),
             _ => default_transport,
         };
         (address, transport)
@@ -70,6 +81,129 @@ fn parse_upstream_addr(addr: &str, default_transport: Transport) -> (&str, Trans
     }
 }
 
+/// 校验上游地址能否被对应的传输解析，不做网络访问
+/// Check that an upstream address parses for its transport, without touching the network
+///
+/// UDP（含 tcp_udp 的 UDP 部分）只接受 `IP:端口`；TCP 接受 `主机:端口`，主机名在连接时解析。
+/// UDP (including the UDP half of tcp_udp) only accepts `ip:port`; TCP accepts `host:port`
+/// with the host name resolved when connecting.
+pub(crate) fn check_upstream(spec: &str, default_transport: Transport) -> anyhow::Result<()> {
+    let (addr, transport) = parse_upstream_addr(spec, default_transport);
+    if super::netns::split(addr).is_some() {
+        return super::netns::check(addr, transport);
+    }
+    match transport {
+        Transport::Udp | Transport::TcpUdp => {
+            addr.parse::<std::net::SocketAddr>()
+                .map_err(|_| anyhow::anyhow!("expected ip:port"))?;
+        }
+        Transport::Tcp => {
+            let (host, port) = addr
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow::anyhow!("expected host:port"))?;
+            if host.is_empty() || port.parse::<u16>().is_err() {
+                anyhow::bail!("expected host:port");
+            }
+        }
+        Transport::Dot => {
+            super::transport::parse_dot_target(addr)?;
+        }
+        Transport::Doq => {
+            super::transport::parse_doq_target(addr)?;
+        }
+        Transport::Doh => {
+            super::transport::build_doh_url(addr)?;
+        }
+        Transport::Dnscrypt => {
+            super::dnscrypt::ServerSpec::parse(addr)?;
+        }
+        Transport::Odoh => {
+            super::odoh::parse_upstream(addr)?;
+        }
+        Transport::Mock => {
+            super::mock_upstream::MockSpec::parse(addr)?;
+        }
+    }
+    Ok(())
+}
+
+/// 预热加密上游（DoT/DoH/DoQ/DNSCrypt/ODoH）连接 / Prewarm encrypted upstream (DoT/DoH/DoQ/DNSCrypt/ODoH) connections
+///
+/// 提前完成握手并取得 TLS 会话票据（DNSCrypt 与 ODoH 则提前获取证书或目标配置），空闲后的首个查询无需完整握手；失败仅记录日志。
+/// Completes handshakes ahead of time and obtains TLS session tickets (or the DNSCrypt
+/// certificate / ODoH target config) so the first query after an idle period skips a full
+/// handshake; failures are only logged.
+pub async fn prewarm_encrypted_upstreams(engine: &Engine) {
+    let (upstreams, timeout_dur) = {
+        let state = engine.state.load();
+        (
+            state.pipeline.collect_encrypted_upstreams(),
+            state.pipeline.upstream_timeout(),
+        )
+    };
+    let mut tasks = JoinSet::new();
+    for (transport, addr) in upstreams {
+        let engine = engine.clone();
+        tasks.spawn(async move {
+            let res = timeout(timeout_dur, async {
+                match transport {
+                    Transport::Dot => engine.dot_mux.prewarm(&addr).await,
+                    Transport::Doh => engine.doh_client.prewarm(&addr, timeout_dur).await,
+                    Transport::Doq => engine.doq_client.prewarm(&addr, timeout_dur).await,
+                    Transport::Dnscrypt => engine.dnscrypt_client.prewarm(&addr, timeout_dur).await,
+                    Transport::Odoh => engine.odoh_client.prewarm(&addr, timeout_dur).await,
+                    _ => Ok(()),
+                }
+            })
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("prewarm timeout")));
+            if let Err(e) = res {
+                debug!(upstream = %addr, transport = ?transport, error = %e, "upstream prewarm failed");
+            }
+        });
+    }
+    while tasks.join_next().await.is_some() {}
+}
+
+/// 对发往 DoT/DoH/DoQ 上游的查询做 EDNS 填充 / EDNS-pad queries sent to DoT/DoH/DoQ upstreams
+///
+/// DNSCrypt 与 ODoH 在加密层自行填充，不在此处理。
+/// DNSCrypt and ODoH pad inside their own encryption layer and are skipped here.
+fn padded_query<'a>(engine: &Engine, packet: &'a [u8], transport: Transport) -> Cow<'a, [u8]> {
+    if !matches!(transport, Transport::Dot | Transport::Doh | Transport::Doq) {
+        return Cow::Borrowed(packet);
+    }
+    let block = engine.state.load().pipeline.settings.edns_padding_block_size as usize;
+    match crate::proto_utils::pad_edns(packet, block) {
+        Some(padded) => Cow::Owned(padded),
+        None => Cow::Borrowed(packet),
+    }
+}
+
+/// 按 upstream_options 调整经 UDP 发送的查询：改用 TCP，或压低 EDNS 声明的 UDP 负载
+/// Apply upstream_options to queries sent over UDP: switch to TCP, or lower the EDNS UDP payload they advertise
+fn apply_upstream_options<'a>(
+    options: &HashMap<String, UpstreamOptions>,
+    addr: &str,
+    transport: Transport,
+    packet: &'a [u8],
+) -> (Transport, Cow<'a, [u8]>) {
+    let Some(opts) = options.get(addr).filter(|_| matches!(transport, Transport::Udp | Transport::TcpUdp)) else {
+        return (transport, Cow::Borrowed(packet));
+    };
+    if opts.tcp_only {
+        return (Transport::Tcp, Cow::Borrowed(packet));
+    }
+    match opts.max_udp_size {
+        Some(max) if packet.len() > max as usize => (Transport::Tcp, Cow::Borrowed(packet)),
+        Some(max) => match crate::proto_utils::clamp_udp_payload(packet, max) {
+            Some(clamped) => (transport, Cow::Owned(clamped)),
+            None => (transport, Cow::Borrowed(packet)),
+        },
+        None => (transport, Cow::Borrowed(packet)),
+    }
+}
+
 /// Hedge 超时除数：第一次尝试使用 1/N 的时间，为 TCP fallback 预留时间 / Hedge timeout divisor: first attempt uses 1/N of the budget to reserve time for TCP fallback
 const HEDGE_TIMEOUT_DIVISOR: u32 = 3;
 
@@ -143,7 +277,7 @@ async fn forward_tcp_udp_dual(
 
     let udp_task = tokio::spawn(async move {
         // Disable TCP fallback here to avoid duplicate TCP sends when dual-send is enabled.
-        forward_udp_smart(&engine_udp, &packet_udp, &addr_udp, timeout_dur, false).await
+        forward_udp_smart(&engine_udp, &packet_udp, &addr_udp, timeout_dur, false, &mut 0).await
     });
 
     let tcp_task = tokio::spawn(async move {
@@ -156,6 +290,16 @@ async fn forward_tcp_udp_dual(
     match select(udp_task, tcp_task).await {
         futures::future::Either::Left((result, tcp_task)) => {
             match result {
+                Ok(Ok(bytes)) if is_truncated(&bytes) => {
+                    // 截断的 UDP 响应不采用，等待并行的 TCP 响应
+                    // A truncated UDP response is not used; wait for the parallel TCP response
+                    engine.metrics_tc_retries.fetch_add(1, Ordering::Relaxed);
+                    let remaining = timeout_dur.saturating_sub(start.elapsed());
+                    match timeout(remaining, tcp_task).await {
+                        Ok(Ok(Ok(tcp_bytes))) => Ok((tcp_bytes, "tcp")),
+                        _ => Ok((bytes, "udp")),
+                    }
+                }
                 Ok(Ok(bytes)) => {
                     tcp_task.abort();
                     Ok((bytes, "udp"))
@@ -232,6 +376,71 @@ async fn forward_tcp_udp_dual(
 /// 默认最小 hedge 超时毫秒数（当计算值过小时使用） / Default minimum hedge timeout in milliseconds (used when calculated value is too small)
 const DEFAULT_HEDGE_TIMEOUT_MS: u64 = 100;
 
+/// 以指定传输向单个上游发送查询，返回实际使用的协议名与结果
+/// Send a query to one upstream over the given transport, returning the protocol actually used and the result
+///
+/// `udp_tcp_fallback` 控制 UDP 截断时是否改用 TCP 重试。
+/// `udp_tcp_fallback` controls whether a truncated UDP answer is retried over TCP.
+pub(crate) async fn exchange(
+    engine: &Engine,
+    packet: &[u8],
+    addr: &str,
+    transport: Transport,
+    timeout_dur: Duration,
+    udp_tcp_fallback: bool,
+) -> (&'static str, anyhow::Result<Bytes>) {
+    let (transport, limited, log_target) = {
+        let state = engine.state.load();
+        let settings = &state.pipeline.settings;
+        let (transport, limited) = apply_upstream_options(&settings.upstream_options, addr, transport, packet);
+        (transport, limited, settings.upstream_log.clone())
+    };
+    let packet = limited.as_ref();
+    let start = std::time::Instant::now();
+    let mut retries = 0;
+    let (proto, result) = send_exchange(engine, packet, addr, transport, timeout_dur, udp_tcp_fallback, &mut retries).await;
+    if let Some(target) = &log_target {
+        super::upstream_log::record(
+            target,
+            &super::upstream_log::UpstreamExchange { upstream: addr, proto, query: packet, rtt: start.elapsed(), retries, result: &result },
+        );
+    }
+    (proto, result)
+}
+
+async fn send_exchange(
+    engine: &Engine,
+    packet: &[u8],
+    addr: &str,
+    transport: Transport,
+    timeout_dur: Duration,
+    udp_tcp_fallback: bool,
+    retries: &mut u8,
+) -> (&'static str, anyhow::Result<Bytes>) {
+    match transport {
+        Transport::Udp | Transport::Tcp if super::netns::split(addr).is_some() => {
+            let r = engine.netns.send(packet, addr, transport, timeout_dur).await;
+            (if transport == Transport::Tcp { "tcp" } else { "udp" }, r)
+        }
+        Transport::Udp => ("udp", forward_udp_smart(engine, packet, addr, timeout_dur, udp_tcp_fallback, retries).await),
+        Transport::Tcp => ("tcp", engine.tcp_mux.send(packet, addr, timeout_dur).await),
+        Transport::TcpUdp => {
+            // Dual-send: spawn both TCP and UDP concurrently, use first response
+            // 双发：同时发送 TCP 和 UDP，使用第一个响应
+            match forward_tcp_udp_dual(engine, packet, addr, timeout_dur).await {
+                Ok((bytes, proto)) => (proto, Ok(bytes)),
+                Err(e) => ("udp", Err(e)),
+            }
+        }
+        Transport::Doh => ("doh", engine.doh_client.send(packet, addr, timeout_dur).await),
+        Transport::Dot => ("dot", engine.dot_mux.send(packet, addr, timeout_dur).await),
+        Transport::Doq => ("doq", engine.doq_client.send(packet, addr, timeout_dur).await),
+        Transport::Dnscrypt => ("dnscrypt", engine.dnscrypt_client.send(packet, addr, timeout_dur).await),
+        Transport::Odoh => ("odoh", engine.odoh_client.send(packet, addr, timeout_dur).await),
+        Transport::Mock => ("mock", super::mock_upstream::send(packet, addr, timeout_dur).await),
+    }
+}
+
 /// Forward DNS request to multiple upstreams concurrently (Happy Eyeballs / Hedged Request)
 /// 并发转发 DNS 请求到多个上游 (Happy Eyeballs / Hedged Request)
 ///
@@ -258,6 +467,9 @@ pub async fn forward_upstream(
     } else {
         upstream.split(',').map(|s| s.trim()).map(|s| std::sync::Arc::from(s)).filter(|s: &std::sync::Arc<str>| !s.is_empty()).collect()
     };
+    let upstreams = engine.skip_quarantined(packet, upstreams, default_transport);
+    let upstreams = engine.upstream_health.skip_unhealthy(upstreams, default_transport);
+    let upstreams = engine.admit_unique_qname(packet, upstreams)?;
 
     // 快速路径：只有一个上游时，直接调用避免 spawn 开销
     // Fast path: direct call when only one upstream, avoiding spawn overhead
@@ -266,38 +478,11 @@ pub async fn forward_upstream(
 
         // 解析地址中的协议前缀 / Parse protocol prefix from address
         let (addr, transport_for_addr) = parse_upstream_addr(up, default_transport);
+        let padded = padded_query(engine, packet, transport_for_addr);
+        let packet = padded.as_ref();
 
         let start = std::time::Instant::now();
-        let (res, proto): (anyhow::Result<Bytes>, &str) = match transport_for_addr {
-            Transport::Udp => {
-                let r = forward_udp_smart(engine, packet, addr, timeout_dur, true).await;
-                (r, "udp")
-            }
-            Transport::Tcp => {
-                let r = engine.tcp_mux.send(packet, addr, timeout_dur).await;
-                (r, "tcp")
-            }
-            Transport::TcpUdp => {
-                // Dual-send: spawn both TCP and UDP concurrently, use first response
-                // 双发：同时发送 TCP 和 UDP，使用第一个响应
-                forward_tcp_udp_dual(engine, packet, addr, timeout_dur)
-                    .await
-                    .map(|(bytes, proto)| (Ok(bytes), proto))
-                    .unwrap_or_else(|e| (Err(e), "udp"))
-            }
-            Transport::Doh => {
-                let r = engine.doh_client.send(packet, addr, timeout_dur).await;
-                (r, "doh")
-            }
-            Transport::Dot => {
-                let r = engine.dot_mux.send(packet, addr, timeout_dur).await;
-                (r, "dot")
-            }
-            Transport::Doq => {
-                let r = engine.doq_client.send(packet, addr, timeout_dur).await;
-                (r, "doq")
-            }
-        };
+        let (proto, res) = exchange(engine, packet, addr, transport_for_addr, timeout_dur, true).await;
         let dur = start.elapsed();
 
         let upstream_with_proto = format!("{}:{}", proto, addr);
@@ -309,6 +494,8 @@ pub async fn forward_upstream(
              engine.metrics_upstream_calls.fetch_add(1, Ordering::Relaxed);
              engine.metrics_upstream_ns_total.fetch_add(dur.as_nanos() as u64, Ordering::Relaxed);
              engine.metrics_last_upstream_latency_ns.store(dur.as_nanos() as u64, Ordering::Relaxed);
+             engine.health.record_upstream(true);
+             engine.upstream_stats.record_call(&upstream_with_proto, dur);
              
              // Quick check rcode logging
              if let Some(qr) = crate::proto_utils::parse_response_quick(bytes) {
@@ -318,7 +505,10 @@ pub async fn forward_upstream(
             }
             Err(err) => {
                 // 失败时不构造 prefix，只 warn
-                tracing::warn!(upstream=%up, error=%err, elapsed_ns = dur.as_nanos() as u64, "single upstream call failed");
+                if let Some(suppressed) = super::log_limit::allow("upstream_failed") {
+                    tracing::warn!(upstream=%up, error=%err, elapsed_ns = dur.as_nanos() as u64, suppressed, "single upstream call failed");
+                }
+                engine.health.record_upstream(false);
                 return Err(anyhow::Error::new(UpstreamFailure::new(err)));
             }
         }
@@ -342,41 +532,12 @@ pub async fn forward_upstream(
         let (addr, transport_for_task) = parse_upstream_addr(&up, default_transport);
 
         let engine = engine.clone();
-        let packet = packet_owned.clone();
+        let packet = padded_query(&engine, &packet_owned, transport_for_task).into_owned();
         let addr_owned = addr.to_string();
 
         tasks.spawn(async move {
             let start = std::time::Instant::now();
-            let (proto, res) = match transport_for_task {
-                Transport::Udp => {
-                    let r = forward_udp_smart(&engine, &packet, &addr_owned, timeout_dur, !has_tcp_task).await;
-                    ("udp", r)
-                }
-                Transport::Tcp => {
-                    let r = engine.tcp_mux.send(&packet, &addr_owned, timeout_dur).await;
-                    ("tcp", r)
-                }
-                Transport::TcpUdp => {
-                    // Dual-send: spawn both TCP and UDP concurrently, use first response
-                    // 双发：同时发送 TCP 和 UDP，使用第一个响应
-                    match forward_tcp_udp_dual(&engine, &packet, &addr_owned, timeout_dur).await {
-                        Ok((bytes, proto)) => (proto, Ok(bytes)),
-                        Err(e) => ("udp", Err(e)),
-                    }
-                }
-                Transport::Doh => {
-                    let r = engine.doh_client.send(&packet, &addr_owned, timeout_dur).await;
-                    ("doh", r)
-                }
-                Transport::Dot => {
-                    let r = engine.dot_mux.send(&packet, &addr_owned, timeout_dur).await;
-                    ("dot", r)
-                }
-                Transport::Doq => {
-                    let r = engine.doq_client.send(&packet, &addr_owned, timeout_dur).await;
-                    ("doq", r)
-                }
-            };
+            let (proto, res) = exchange(&engine, &packet, &addr_owned, transport_for_task, timeout_dur, !has_tcp_task).await;
 
             // Note: for TcpUdp, timing includes both tasks' spawn/abort overhead
             // 注意：对于 TcpUdp，计时包含两个任务的 spawn/abort 开销
@@ -386,28 +547,37 @@ pub async fn forward_upstream(
         });
     }
 
+    // 截断的 UDP 响应仅在没有其他可用响应时使用 / Truncated UDP responses are used only when nothing else succeeds
+    let mut truncated_fallback: Option<(Bytes, String)> = None;
+    // race_prefer_noerror 时保留的 NXDOMAIN 应答 / NXDOMAIN answer held back under race_prefer_noerror
+    let prefer_noerror = engine.state.load().pipeline.settings.race_prefer_noerror;
+    let mut nxdomain_fallback: Option<(Bytes, String, Duration)> = None;
+
     // 等待第一个成功响应 / Wait for first successful response
     while let Some(result) = tasks.join_next().await {
         match result {
             Ok((up_proto, res, dur)) => {
                 match res {
+                    Ok(bytes) if is_truncated(&bytes) => {
+                        truncated_fallback.get_or_insert((bytes, up_proto));
+                    }
                     Ok(bytes) => {
 
                     // 快速解析响应码 / Quick parse response code
-                    let should_accept = if let Some(qr) = crate::proto_utils::parse_response_quick(&bytes) {
-                         match qr.rcode {
-                            ResponseCode::NoError => true,
-                            ResponseCode::ServFail | ResponseCode::Refused => false,
-                            _ => true,
-                         }
-                    } else {
-                        true
-                    };
+                    let rcode = crate::proto_utils::parse_response_quick(&bytes).map(|qr| qr.rcode);
+                    let should_accept = !matches!(rcode, Some(ResponseCode::ServFail | ResponseCode::Refused));
+
+                    if should_accept && prefer_noerror && rcode == Some(ResponseCode::NXDomain) && !tasks.is_empty() {
+                        nxdomain_fallback.get_or_insert((bytes, up_proto, dur));
+                        continue;
+                    }
 
                     if should_accept {
                          engine.metrics_upstream_calls.fetch_add(1, Ordering::Relaxed);
                          engine.metrics_upstream_ns_total.fetch_add(dur.as_nanos() as u64, Ordering::Relaxed);
                          engine.metrics_last_upstream_latency_ns.store(dur.as_nanos() as u64, Ordering::Relaxed);
+                         engine.health.record_upstream(true);
+                         engine.upstream_stats.record_call(&up_proto, dur);
 
                         // 显式取消其他正在进行的任务
                         if !tasks.is_empty() {
@@ -418,7 +588,9 @@ pub async fn forward_upstream(
                     }
                 }
                     Err(err) => {
-                        tracing::warn!(upstream=%up_proto, error=%err, elapsed_ns = dur.as_nanos() as u64, "upstream call failed, waiting for others");
+                        if let Some(suppressed) = super::log_limit::allow("upstream_failed") {
+                            tracing::warn!(upstream=%up_proto, error=%err, elapsed_ns = dur.as_nanos() as u64, suppressed, "upstream call failed, waiting for others");
+                        }
                         last_err = Some(err);
                     }
                 }
@@ -430,12 +602,53 @@ pub async fn forward_upstream(
         }
     }
 
+    if let Some((bytes, up_proto, dur)) = nxdomain_fallback {
+        engine.metrics_upstream_calls.fetch_add(1, Ordering::Relaxed);
+        engine.metrics_upstream_ns_total.fetch_add(dur.as_nanos() as u64, Ordering::Relaxed);
+        engine.metrics_last_upstream_latency_ns.store(dur.as_nanos() as u64, Ordering::Relaxed);
+        engine.health.record_upstream(true);
+        engine.upstream_stats.record_call(&up_proto, dur);
+        return Ok((bytes, up_proto));
+    }
+
+    if let Some(fallback) = truncated_fallback {
+        engine.health.record_upstream(true);
+        return Ok(fallback);
+    }
+
     // 所有上游都失败 / All upstreams failed
+    engine.health.record_upstream(false);
     let err = last_err.unwrap_or_else(|| anyhow::anyhow!("all upstreams failed"));
     Err(anyhow::Error::new(UpstreamFailure::new(err)))
 }
 
 
+/// 响应是否带 TC 标志 / Whether the response has the TC flag set
+#[inline]
+fn is_truncated(bytes: &[u8]) -> bool {
+    crate::proto_utils::parse_response_quick(bytes).is_some_and(|qr| qr.truncated)
+}
+
+/// 截断的 UDP 响应改用 TCP 重新查询；TCP 失败时返回原截断响应，由客户端自行重试
+/// Re-query a truncated UDP response over TCP; on TCP failure the truncated response is
+/// returned so the client can retry on its own
+async fn retry_over_tcp(
+    engine: &Engine,
+    packet: &[u8],
+    upstream: &str,
+    timeout_dur: Duration,
+    truncated: Bytes,
+) -> Bytes {
+    engine.metrics_tc_retries.fetch_add(1, Ordering::Relaxed);
+    match engine.tcp_mux.send(packet, upstream, timeout_dur).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            debug!(event = "tc_flag_fallback", upstream = %upstream, error = %err, "tcp retry failed, returning truncated response");
+            truncated
+        }
+    }
+}
+
 /// UDP forwarder with hedged retry and TCP fallback for better tail latency.
 async fn forward_udp_smart(
     engine: &Engine,
@@ -443,11 +656,18 @@ async fn forward_udp_smart(
     upstream: &str,
     timeout_dur: Duration,
     allow_tcp_fallback: bool,
+    retries: &mut u8,
 ) -> anyhow::Result<Bytes> {
     // 获取 TCP fallback 配置（Copy bool 值，避免持有 Guard 跨 await）
     // Get TCP fallback config (Copy bool value to avoid holding Guard across await)
-    let enable_tcp_fallback = allow_tcp_fallback
-        && engine.state.load().pipeline.settings.enable_tcp_fallback;
+    let (enable_tcp_fallback, retry_truncated) = {
+        let state = engine.state.load();
+        let settings = &state.pipeline.settings;
+        (
+            allow_tcp_fallback && settings.enable_tcp_fallback,
+            allow_tcp_fallback && settings.tcp_retry_on_truncation,
+        )
+    };
 
     // Split timeout: first attempt uses 1/N budget (leaving room for TCP fallback)
     // 分割超时：第一次尝试使用 1/N 时间（为 TCP fallback 留出空间）
@@ -457,14 +677,14 @@ async fn forward_udp_smart(
     let attempts = [hedge_timeout, timeout_dur];
 
     for (idx, dur) in attempts.iter().enumerate() {
+        *retries = idx as u8;
         match engine.udp_client.send(packet, upstream, *dur).await {
             Ok(bytes) => {
                 // RFC 1035: Check TC (Truncated) flag using quick parse - 使用快速解析检查 TC 标志
-                if let Some(qr) = crate::proto_utils::parse_response_quick(&bytes) {
-                    if qr.truncated && enable_tcp_fallback {
-                        debug!(event = "tc_flag_fallback", upstream = %upstream, "udp response truncated, retrying with tcp");
-                        return engine.tcp_mux.send(packet, upstream, timeout_dur).await;
-                    }
+                if retry_truncated && is_truncated(&bytes) {
+                    debug!(event = "tc_flag_fallback", upstream = %upstream, "udp response truncated, retrying with tcp");
+                    *retries += 1;
+                    return Ok(retry_over_tcp(engine, packet, upstream, timeout_dur, bytes).await);
                 }
                 return Ok(bytes);
             }
@@ -481,6 +701,7 @@ async fn forward_udp_smart(
                     if enable_tcp_fallback {
                         // Last UDP attempt, try TCP fallback before failing.
                         debug!(event = "udp_forward_fallback_tcp", upstream = %upstream, "falling back to tcp");
+                        *retries += 1;
                         return engine.tcp_mux.send(packet, upstream, timeout_dur).await;
                     }
                 }
@@ -506,7 +727,7 @@ mod tests {
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
     use std::time::Duration;
-    use tokio::io::AsyncReadExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
     #[test]
     fn test_parse_upstream_addr_with_protocol_prefix() {
@@ -632,6 +853,10 @@ mod tests {
             settings,
             pipeline_select: Vec::new(),
             pipelines: Vec::new(),
+            allowlist: Default::default(),
+            tracks_nxdomain: false,
+            prefetch_qtypes: Default::default(),
+            local_records: Default::default(),
         };
         Engine::new(runtime, "test".to_string())
     }
@@ -700,6 +925,7 @@ mod tests {
             &upstream_addr.to_string(),
             Duration::from_millis(500),
             false,
+            &mut 0,
         )
         .await
         .expect("udp response");
@@ -720,4 +946,142 @@ mod tests {
             "tcp fallback should be disabled in dual-send udp path"
         );
     }
+
+    #[tokio::test]
+    async fn udp_truncated_response_is_retried_over_tcp() {
+        let _ = ring::default_provider().install_default();
+
+        // Arrange: UDP answers with TC=1, TCP answers in full on the same port
+        let tcp_listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind tcp");
+        let tcp_addr = tcp_listener.local_addr().expect("tcp addr");
+        let udp_socket = tokio::net::UdpSocket::bind(tcp_addr)
+            .await
+            .expect("bind udp");
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (len, peer) = udp_socket.recv_from(&mut buf).await.expect("udp recv");
+            let mut resp = buf[..len].to_vec();
+            resp[2] = 0x82; // QR=1, TC=1
+            resp[3] = 0x00;
+            let _ = udp_socket.send_to(&resp, peer).await;
+        });
+        tokio::spawn(async move {
+            let (mut stream, _) = tcp_listener.accept().await.expect("tcp accept");
+            let mut len_buf = [0u8; 2];
+            stream.read_exact(&mut len_buf).await.expect("read len");
+            let mut query = vec![0u8; u16::from_be_bytes(len_buf) as usize];
+            stream.read_exact(&mut query).await.expect("read query");
+            query[2] = 0x80; // QR=1, TC=0
+            query[3] = 0x00;
+            stream.write_all(&len_buf).await.expect("write len");
+            stream.write_all(&query).await.expect("write resp");
+            let _ = stream.read(&mut len_buf).await;
+        });
+
+        let engine = build_test_engine(true);
+        let packet = build_dns_query_packet("example.com");
+
+        // Act
+        let resp = forward_udp_smart(&engine, &packet, &tcp_addr.to_string(), Duration::from_millis(1000), true, &mut 0)
+            .await
+            .expect("response");
+
+        // Assert
+        assert_eq!(resp[2], 0x80, "tcp answer should replace the truncated udp answer");
+        assert_eq!(engine.metrics_tc_retries.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_pad_edns_rounds_up_and_replaces_existing_padding() {
+        // Arrange
+        let mut msg = Message::new();
+        msg.set_message_type(MessageType::Query);
+        msg.add_query(Query::query(Name::from_str("example.com.").unwrap(), RecordType::A));
+        let plain = msg.to_vec().unwrap();
+        let mut edns = hickory_proto::op::Edns::new();
+        edns.set_max_payload(1232);
+        msg.set_edns(edns);
+        let with_opt = msg.to_vec().unwrap();
+
+        // Act
+        let padded = crate::proto_utils::pad_edns(&with_opt, 128).unwrap();
+        let repadded = crate::proto_utils::pad_edns(&padded, 128).unwrap();
+
+        // Assert
+        assert!(crate::proto_utils::pad_edns(&plain, 128).is_none());
+        assert_eq!(padded.len(), 128);
+        assert_eq!(repadded, padded);
+        assert_eq!(Message::from_vec(&padded).unwrap().queries(), msg.queries());
+    }
+
+    #[tokio::test]
+    async fn test_race_prefers_noerror_over_earlier_nxdomain() {
+        // Arrange
+        let engine = build_test_engine(true);
+        let preferring = Engine::new(
+            RuntimePipelineConfig {
+                settings: GlobalSettings { race_prefer_noerror: true, ..Default::default() },
+                pipeline_select: Vec::new(),
+                pipelines: Vec::new(),
+                allowlist: Default::default(),
+                tracks_nxdomain: false,
+                prefetch_qtypes: Default::default(),
+                local_records: Default::default(),
+            },
+            "test".to_string(),
+        );
+        let packet = build_dns_query_packet("example.com");
+        let upstreams = "mock://rcode=nxdomain,mock://a=192.0.2.1&latency_ms=50";
+        let rcode = |bytes: &Bytes| crate::proto_utils::parse_response_quick(bytes).unwrap().rcode;
+
+        // Act
+        let (first, _) = forward_upstream(&engine, &packet, upstreams, Duration::from_millis(1000), None, None)
+            .await
+            .expect("first answer");
+        let (preferred, upstream) = forward_upstream(&preferring, &packet, upstreams, Duration::from_millis(1000), None, None)
+            .await
+            .expect("preferred answer");
+
+        // Assert
+        assert_eq!(rcode(&first), ResponseCode::NXDomain);
+        assert_eq!(rcode(&preferred), ResponseCode::NoError);
+        assert_eq!(upstream, "mock:a=192.0.2.1&latency_ms=50");
+    }
+
+    #[test]
+    fn test_upstream_options_pick_tcp_or_lower_udp_payload() {
+        // Arrange
+        let options: HashMap<String, UpstreamOptions> = serde_json::from_value(serde_json::json!({
+            "10.0.0.1:53": { "tcp_only": true },
+            "10.0.0.2:53": { "max_udp_size": 1232 },
+            "10.0.0.3:53": { "max_udp_size": 512 }
+        }))
+        .unwrap();
+        let mut msg = Message::new();
+        msg.add_query(Query::query(Name::from_str("example.com.").unwrap(), RecordType::A));
+        let mut edns = hickory_proto::op::Edns::new();
+        edns.set_max_payload(4096);
+        msg.set_edns(edns);
+        let query = msg.to_vec().unwrap();
+        let large = vec![0u8; 600];
+
+        // Act
+        let (tcp_only, _) = apply_upstream_options(&options, "10.0.0.1:53", Transport::Udp, &query);
+        let (clamped_transport, clamped) = apply_upstream_options(&options, "10.0.0.2:53", Transport::Udp, &query);
+        let (too_large, _) = apply_upstream_options(&options, "10.0.0.3:53", Transport::Udp, &large);
+        let (encrypted, _) = apply_upstream_options(&options, "10.0.0.1:53", Transport::Dot, &query);
+        let (unlisted, untouched) = apply_upstream_options(&options, "10.0.0.9:53", Transport::Udp, &query);
+
+        // Assert
+        assert_eq!(tcp_only, Transport::Tcp);
+        assert_eq!(clamped_transport, Transport::Udp);
+        assert_eq!(crate::proto_utils::udp_payload_limit(&clamped), 1232);
+        assert_eq!(too_large, Transport::Tcp);
+        assert_eq!(encrypted, Transport::Dot);
+        assert_eq!(unlisted, Transport::Udp);
+        assert!(matches!(untouched, Cow::Borrowed(_)));
+    }
 }
@@ -0,0 +1,294 @@
+//! 上游后台健康检查 / Background upstream health checks
+//!
+//! 启用 `upstream_health_check` 后，后台任务每隔 interval_secs 向配置中出现的每个上游（含
+//! default_upstream）发送 `query_name IN NS` 探测；超时、出错或应答为 SERVFAIL/REFUSED 记为失败。
+//! 连续失败达到阈值的上游被标记为不健康，转发时从一组上游中跳过，由其余上游接替；连续成功达到
+//! 阈值后恢复。一组上游全部不健康时照常使用原列表，不会因此拒绝服务。设置在每轮探测前重新读取，
+//! 关闭后清空所有状态。
+//! With `upstream_health_check` enabled, a background task sends a `query_name IN NS` probe to
+//! every upstream found in the configuration (default_upstream included) every interval_secs;
+//! a timeout, an error or a SERVFAIL/REFUSED answer counts as a failure. Upstreams reaching the
+//! failure threshold are marked unhealthy and skipped within their group, the remaining
+//! upstreams taking over, until they reach the success threshold again. A group whose upstreams
+//! are all unhealthy is still used as is, so health checks never refuse service by themselves.
+//! The settings are re-read before every round and disabling them clears all state.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crypto_box::aead::OsRng;
+use crypto_box::aead::rand_core::RngCore;
+use dashmap::DashMap;
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use rustc_hash::FxBuildHasher;
+use serde::Serialize;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+use super::Engine;
+use super::upstream::{exchange, parse_upstream_addr};
+use crate::config::{Transport, UpstreamHealthCheckSettings};
+
+#[derive(Default)]
+struct HealthEntry {
+    unhealthy: bool,
+    consecutive_failures: u32,
+    consecutive_successes: u32,
+    checks: u64,
+    last_rtt_ms: Option<u64>,
+    last_error: Option<String>,
+}
+
+/// 单个上游的健康状态报告 / Health report for a single upstream
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct UpstreamHealthReport {
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    pub consecutive_successes: u32,
+    pub checks: u64,
+    pub last_rtt_ms: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// 各上游的健康状态，键为（传输, 去除前缀的地址）
+/// Health state of each upstream, keyed by (transport, address without prefix)
+#[derive(Default)]
+pub struct UpstreamHealth {
+    entries: DashMap<(Transport, Arc<str>), HealthEntry, FxBuildHasher>,
+    /// 不健康的上游数量（热路径上无锁跳过）/ Number of unhealthy upstreams (lock-free skip on the hot path)
+    unhealthy: AtomicUsize,
+}
+
+impl UpstreamHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录一次探测结果 / Record the result of one probe
+    pub(crate) fn record(
+        &self,
+        transport: Transport,
+        addr: &str,
+        result: Result<Duration, String>,
+        settings: &UpstreamHealthCheckSettings,
+    ) {
+        let mut entry = self.entries.entry((transport, Arc::from(addr))).or_default();
+        entry.checks += 1;
+        let was_unhealthy = entry.unhealthy;
+        match result {
+            Ok(rtt) => {
+                entry.consecutive_failures = 0;
+                entry.consecutive_successes = entry.consecutive_successes.saturating_add(1);
+                entry.last_rtt_ms = Some(rtt.as_millis() as u64);
+                entry.last_error = None;
+                if entry.consecutive_successes >= settings.success_threshold {
+                    entry.unhealthy = false;
+                }
+            }
+            Err(err) => {
+                entry.consecutive_successes = 0;
+                entry.consecutive_failures = entry.consecutive_failures.saturating_add(1);
+                entry.last_error = Some(err);
+                if entry.consecutive_failures >= settings.failure_threshold {
+                    entry.unhealthy = true;
+                }
+            }
+        }
+        if entry.unhealthy != was_unhealthy {
+            if entry.unhealthy {
+                self.unhealthy.fetch_add(1, Ordering::Relaxed);
+                warn!(event = "upstream_unhealthy", upstream = %addr, transport = ?transport, error = ?entry.last_error, "upstream marked unhealthy");
+            } else {
+                self.unhealthy.fetch_sub(1, Ordering::Relaxed);
+                info!(event = "upstream_healthy", upstream = %addr, transport = ?transport, "upstream healthy again");
+            }
+        }
+    }
+
+    /// 上游是否被标记为不健康 / Whether the upstream is marked unhealthy
+    pub(crate) fn is_unhealthy(&self, transport: Transport, addr: &str) -> bool {
+        self.unhealthy.load(Ordering::Relaxed) > 0
+            && self
+                .entries
+                .get(&(transport, Arc::from(addr)))
+                .is_some_and(|e| e.unhealthy)
+    }
+
+    /// 去掉不健康的上游；全部不健康时返回原列表
+    /// Drop unhealthy upstreams; the original list is kept when all of them are unhealthy
+    pub(crate) fn skip_unhealthy(&self, upstreams: Vec<Arc<str>>, default_transport: Transport) -> Vec<Arc<str>> {
+        if self.unhealthy.load(Ordering::Relaxed) == 0 {
+            return upstreams;
+        }
+        let kept: Vec<Arc<str>> = upstreams
+            .iter()
+            .filter(|up| {
+                let (addr, transport) = parse_upstream_addr(up, default_transport);
+                !self.is_unhealthy(transport, addr)
+            })
+            .cloned()
+            .collect();
+        if kept.is_empty() { upstreams } else { kept }
+    }
+
+    /// 丢弃不在配置中的上游 / Forget upstreams no longer in the configuration
+    fn retain(&self, keep: impl Fn(Transport, &str) -> bool) {
+        self.entries.retain(|(transport, addr), entry| {
+            let kept = keep(*transport, addr);
+            if !kept && entry.unhealthy {
+                self.unhealthy.fetch_sub(1, Ordering::Relaxed);
+            }
+            kept
+        });
+    }
+
+    /// 按 `协议:地址` 排序的报告 / Reports keyed by `proto:addr`
+    pub fn report(&self) -> BTreeMap<String, UpstreamHealthReport> {
+        self.entries
+            .iter()
+            .map(|e| {
+                let (transport, addr) = e.key();
+                let report = UpstreamHealthReport {
+                    healthy: !e.unhealthy,
+                    consecutive_failures: e.consecutive_failures,
+                    consecutive_successes: e.consecutive_successes,
+                    checks: e.checks,
+                    last_rtt_ms: e.last_rtt_ms,
+                    last_error: e.last_error.clone(),
+                };
+                (format!("{}:{}", proto_name(*transport), addr), report)
+            })
+            .collect()
+    }
+}
+
+fn proto_name(transport: Transport) -> &'static str {
+    match transport {
+        Transport::Udp => "udp",
+        Transport::Tcp => "tcp",
+        Transport::TcpUdp => "tcp_udp",
+        Transport::Doh => "doh",
+        Transport::Dot => "dot",
+        Transport::Doq => "doq",
+        Transport::Dnscrypt => "dnscrypt",
+        Transport::Odoh => "odoh",
+        Transport::Mock => "mock",
+    }
+}
+
+/// 构造探测查询 / Build the probe query
+fn probe_query(name: &str) -> anyhow::Result<Vec<u8>> {
+    let mut msg = Message::new();
+    msg.set_id(OsRng.next_u32() as u16)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true)
+        .add_query(Query::query(Name::from_ascii(name)?, RecordType::NS));
+    Ok(msg.to_vec()?)
+}
+
+/// 探测一个上游，返回往返时间或失败原因 / Probe one upstream, returning the round trip or why it failed
+async fn probe(engine: &Engine, packet: &[u8], addr: &str, transport: Transport, timeout_dur: Duration) -> Result<Duration, String> {
+    let start = Instant::now();
+    let (_, res) = tokio::time::timeout(timeout_dur, exchange(engine, packet, addr, transport, timeout_dur, true))
+        .await
+        .map_err(|_| "timeout".to_string())?;
+    let bytes = res.map_err(|e| format!("{:#}", e))?;
+    let rtt = start.elapsed();
+    let resp = Message::from_vec(&bytes).map_err(|e| format!("bad response: {}", e))?;
+    match resp.response_code() {
+        ResponseCode::ServFail | ResponseCode::Refused => Err(format!("rcode {}", resp.response_code())),
+        _ => Ok(rtt),
+    }
+}
+
+/// 对所有配置中的上游执行一轮探测 / Run one probe round over every configured upstream
+pub async fn check_all(engine: &Engine) {
+    let (settings, upstreams) = {
+        let state = engine.state.load();
+        (
+            state.pipeline.settings.upstream_health_check.clone(),
+            state.pipeline.collect_forward_upstreams(),
+        )
+    };
+    engine
+        .upstream_health
+        .retain(|transport, addr| upstreams.contains(&(transport, addr.to_string())));
+    let packet = match probe_query(&settings.query_name) {
+        Ok(packet) => Arc::new(packet),
+        Err(err) => {
+            warn!(error = %err, "invalid upstream health check query name");
+            return;
+        }
+    };
+    let settings = Arc::new(settings);
+    let timeout_dur = Duration::from_millis(settings.timeout_ms.max(1));
+    let mut tasks = JoinSet::new();
+    for (transport, addr) in upstreams {
+        let engine = engine.clone();
+        let packet = Arc::clone(&packet);
+        let settings = Arc::clone(&settings);
+        tasks.spawn(async move {
+            let result = probe(&engine, &packet, &addr, transport, timeout_dur).await;
+            engine.upstream_health.record(transport, &addr, result, &settings);
+        });
+    }
+    while tasks.join_next().await.is_some() {}
+}
+
+/// 启动后台健康检查任务 / Spawn the background health check task
+pub fn spawn(engine: &Engine) {
+    let engine = engine.clone();
+    tokio::spawn(async move {
+        loop {
+            let settings = engine.state.load().pipeline.settings.upstream_health_check.clone();
+            if settings.enabled {
+                check_all(&engine).await;
+            } else {
+                engine.upstream_health.retain(|_, _| false);
+            }
+            tokio::time::sleep(Duration::from_secs(settings.interval_secs.max(1))).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unhealthy_upstreams_are_skipped_until_they_recover() {
+        // Arrange
+        let health = UpstreamHealth::new();
+        let settings = UpstreamHealthCheckSettings { failure_threshold: 2, success_threshold: 2, ..Default::default() };
+        let upstreams: Vec<Arc<str>> = vec![Arc::from("1.1.1.1:53"), Arc::from("tcp://8.8.8.8:53")];
+        let fail = || Err("timeout".to_string());
+        let ok = || Ok(Duration::from_millis(5));
+
+        // Act
+        health.record(Transport::Udp, "1.1.1.1:53", fail(), &settings);
+        let after_one_failure = health.skip_unhealthy(upstreams.clone(), Transport::Udp);
+        health.record(Transport::Udp, "1.1.1.1:53", fail(), &settings);
+        let after_two_failures = health.skip_unhealthy(upstreams.clone(), Transport::Udp);
+        health.record(Transport::Tcp, "8.8.8.8:53", fail(), &settings);
+        health.record(Transport::Tcp, "8.8.8.8:53", fail(), &settings);
+        let all_unhealthy = health.skip_unhealthy(upstreams.clone(), Transport::Udp);
+        health.record(Transport::Udp, "1.1.1.1:53", ok(), &settings);
+        health.record(Transport::Udp, "1.1.1.1:53", ok(), &settings);
+        let recovered = health.skip_unhealthy(upstreams.clone(), Transport::Udp);
+        let report = health.report();
+
+        // Assert
+        assert_eq!(after_one_failure, upstreams);
+        assert_eq!(after_two_failures, vec![Arc::<str>::from("tcp://8.8.8.8:53")]);
+        assert_eq!(all_unhealthy, upstreams);
+        assert_eq!(recovered, vec![Arc::<str>::from("1.1.1.1:53")]);
+        assert!(report["udp:1.1.1.1:53"].healthy);
+        assert_eq!(report["udp:1.1.1.1:53"].last_rtt_ms, Some(5));
+        assert!(!report["tcp:8.8.8.8:53"].healthy);
+        assert_eq!(report["tcp:8.8.8.8:53"].last_error.as_deref(), Some("timeout"));
+    }
+}
@@ -0,0 +1,112 @@
+//! 上游交互日志 / Upstream exchange log
+//!
+//! 与客户端查询日志分开，`upstream_log` 为每次实际发往上游的查询写一行 JSON：上游地址、实际传输、
+//! 发出的事务 ID、查询名与类型、往返耗时、重试次数以及 rcode 或错误，便于核对客户端的查询与
+//! kixdns 实际发出的查询是否一致。输出目标与 `log` 动作相同（main / query / file）。
+//! Separate from client query logs, `upstream_log` writes one JSON line for every query actually
+//! sent upstream: the upstream address, the transport used, the transaction id sent, the query
+//! name and type, the round trip time, the number of retries and the rcode or error, so what
+//! clients asked can be audited against what kixdns sent. Targets are the same as for the `log`
+//! action (main / query / file).
+
+use std::time::Duration;
+
+use hickory_proto::rr::RecordType;
+
+use crate::config::LogTarget;
+
+use super::rule_log::append_line;
+
+/// 一次上游交互 / One upstream exchange
+pub(crate) struct UpstreamExchange<'a> {
+    pub upstream: &'a str,
+    pub proto: &'static str,
+    /// 发往上游的查询报文 / Query sent upstream
+    pub query: &'a [u8],
+    pub rtt: Duration,
+    /// UDP 超时重发与截断后改用 TCP 的次数 / UDP resends after timeouts and TCP retries after truncation
+    pub retries: u8,
+    pub result: &'a anyhow::Result<bytes::Bytes>,
+}
+
+/// 渲染为一行 JSON / Render as one JSON line
+pub(crate) fn render(exchange: &UpstreamExchange<'_>) -> String {
+    let mut buf = [0u8; 256];
+    let question = crate::proto_utils::parse_quick(exchange.query, &mut buf);
+    let tx_id = (exchange.query.len() >= 2).then(|| u16::from_be_bytes([exchange.query[0], exchange.query[1]]));
+    let mut line = serde_json::json!({
+        "upstream": exchange.upstream,
+        "proto": exchange.proto,
+        "tx_id": tx_id,
+        "qname": question.as_ref().map(|q| q.qname_str()),
+        "qtype": question.as_ref().map(|q| RecordType::from(q.qtype).to_string()),
+        "rtt_ms": exchange.rtt.as_secs_f64() * 1000.0,
+        "retries": exchange.retries,
+    });
+    match exchange.result {
+        Ok(resp) => {
+            line["rcode"] = crate::proto_utils::parse_response_quick(resp)
+                .map(|qr| format!("{:?}", qr.rcode))
+                .into();
+        }
+        Err(err) => line["error"] = err.to_string().into(),
+    }
+    line.to_string()
+}
+
+/// 写入一次上游交互 / Write one upstream exchange
+pub(crate) fn record(target: &LogTarget, exchange: &UpstreamExchange<'_>) {
+    let line = render(exchange);
+    match target {
+        LogTarget::Main => tracing::info!(target: "kixdns::upstream_log", event = "upstream_exchange", "{}", line),
+        LogTarget::Query => tracing::info!(target: "query_log", event = "upstream_exchange", "{}", line),
+        LogTarget::File { path } => append_line(path, line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_describes_the_exchange_as_json() {
+        // Arrange
+        let query = [0x12u8, 0x34, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0, 0, 28, 0, 1];
+        let mut answer = query.to_vec();
+        answer[2] = 0x81;
+        answer[3] = 0x83;
+        let ok = Ok(bytes::Bytes::from(answer));
+        let failed = Err(anyhow::anyhow!("udp upstream timeout"));
+
+        // Act
+        let ok_line: serde_json::Value = serde_json::from_str(&render(&UpstreamExchange {
+            upstream: "10.0.0.1:53",
+            proto: "tcp",
+            query: &query,
+            rtt: Duration::from_millis(12),
+            retries: 1,
+            result: &ok,
+        }))
+        .unwrap();
+        let failed_line: serde_json::Value = serde_json::from_str(&render(&UpstreamExchange {
+            upstream: "10.0.0.1:53",
+            proto: "udp",
+            query: &query,
+            rtt: Duration::from_millis(500),
+            retries: 1,
+            result: &failed,
+        }))
+        .unwrap();
+
+        // Assert
+        assert_eq!(ok_line["tx_id"], 0x1234);
+        assert_eq!(ok_line["qname"], "example");
+        assert_eq!(ok_line["qtype"], "AAAA");
+        assert_eq!(ok_line["proto"], "tcp");
+        assert_eq!(ok_line["retries"], 1);
+        assert_eq!(ok_line["rtt_ms"], 12.0);
+        assert_eq!(ok_line["rcode"], "NXDomain");
+        assert_eq!(failed_line["error"], "udp upstream timeout");
+        assert!(failed_line.get("rcode").is_none());
+    }
+}
@@ -0,0 +1,129 @@
+//! 按上游聚合的流量统计 / Per-upstream traffic statistics
+//!
+//! 记录每个上游（`协议:地址`）承担的转发次数、平均延迟，以及由它产生的缓存条目被命中的次数（含快速路径
+//! 与 serve-stale），使“哪个上游在服务我的流量”也能覆盖缓存流量，而不只是缓存未命中。
+//! Records, per upstream (`proto:addr`), the forwards it served, their average latency and how
+//! often cache entries it produced were hit (fast path and serve-stale included), so "which
+//! upstream is serving my traffic" covers cached traffic rather than only misses.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use dashmap::DashMap;
+use rustc_hash::FxBuildHasher;
+use serde::Serialize;
+
+/// 最多跟踪的上游数量（限制内存）/ Max upstreams tracked (bounds memory)
+const MAX_TRACKED_UPSTREAMS: usize = 1024;
+
+#[derive(Default)]
+struct UpstreamCounters {
+    calls: AtomicU64,
+    latency_ns_total: AtomicU64,
+    last_latency_ns: AtomicU64,
+    cache_hits: AtomicU64,
+}
+
+/// 单个上游的统计报告 / Statistics report for a single upstream
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct UpstreamTrafficReport {
+    pub calls: u64,
+    pub avg_latency_us: u64,
+    pub last_latency_us: u64,
+    pub cache_hits: u64,
+    /// 转发与缓存命中合计 / Forwards plus cache hits
+    pub responses: u64,
+}
+
+/// 按上游聚合的统计 / Statistics aggregated by upstream
+#[derive(Default)]
+pub struct UpstreamStats {
+    upstreams: DashMap<Arc<str>, UpstreamCounters, FxBuildHasher>,
+}
+
+impl UpstreamStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_counters(&self, upstream: &str, f: impl FnOnce(&UpstreamCounters)) {
+        if let Some(counters) = self.upstreams.get(upstream) {
+            f(&counters);
+            return;
+        }
+        if self.upstreams.len() >= MAX_TRACKED_UPSTREAMS {
+            return;
+        }
+        f(&self.upstreams.entry(Arc::from(upstream)).or_default());
+    }
+
+    /// 记录一次成功的上游调用 / Record a successful upstream call
+    #[inline]
+    pub fn record_call(&self, upstream: &str, latency: Duration) {
+        let ns = latency.as_nanos() as u64;
+        self.with_counters(upstream, |c| {
+            c.calls.fetch_add(1, Ordering::Relaxed);
+            c.latency_ns_total.fetch_add(ns, Ordering::Relaxed);
+            c.last_latency_ns.store(ns, Ordering::Relaxed);
+        });
+    }
+
+    /// 记录一次由该上游产生的缓存条目的命中 / Record a hit on a cache entry produced by this upstream
+    #[inline]
+    pub fn record_cache_hit(&self, upstream: Option<&str>) {
+        if let Some(upstream) = upstream {
+            self.with_counters(upstream, |c| {
+                c.cache_hits.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+    }
+
+    /// 按上游排序的报告 / Reports keyed by upstream
+    pub fn report(&self) -> BTreeMap<String, UpstreamTrafficReport> {
+        self.upstreams
+            .iter()
+            .map(|e| {
+                let c = e.value();
+                let calls = c.calls.load(Ordering::Relaxed);
+                let cache_hits = c.cache_hits.load(Ordering::Relaxed);
+                let avg_latency_us = c.latency_ns_total.load(Ordering::Relaxed).checked_div(calls).unwrap_or(0) / 1000;
+                let report = UpstreamTrafficReport {
+                    calls,
+                    avg_latency_us,
+                    last_latency_us: c.last_latency_ns.load(Ordering::Relaxed) / 1000,
+                    cache_hits,
+                    responses: calls + cache_hits,
+                };
+                (e.key().to_string(), report)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_hits_are_attributed_to_producing_upstream() {
+        // Arrange
+        let stats = UpstreamStats::new();
+
+        // Act
+        stats.record_call("udp:1.1.1.1:53", Duration::from_micros(300));
+        stats.record_call("udp:1.1.1.1:53", Duration::from_micros(100));
+        stats.record_cache_hit(Some("udp:1.1.1.1:53"));
+        stats.record_cache_hit(Some("tcp:8.8.8.8:53"));
+        stats.record_cache_hit(None);
+        let report = stats.report();
+
+        // Assert
+        let primary = &report["udp:1.1.1.1:53"];
+        assert_eq!((primary.calls, primary.cache_hits, primary.responses), (2, 1, 3));
+        assert_eq!((primary.avg_latency_us, primary.last_latency_us), (200, 100));
+        assert_eq!(report["tcp:8.8.8.8:53"].responses, 1);
+        assert_eq!(report.len(), 2);
+    }
+}
@@ -0,0 +1,225 @@
+//! 配置交叉引用校验 / Config cross-reference validation
+//!
+//! 编译规则前一次性检查配置中的引用：跳转目标与 pipeline_select 目标是否存在、上游地址能否解析、
+//! 正则能否编译、rewrite_rcode 的 rcode 名称是否有效、仅响应阶段的动作是否出现在请求阶段、delay 是否短于请求超时，以及请求阶段的跳转环。所有问题汇总后一起
+//! 返回，修改配置时无需逐条重载排错；禁用的 pipeline 与规则同样参与校验，启用的规则不能跳转到禁用的
+//! pipeline（禁用的 pipeline 不会被编译，跳转到它的查询只能得到 SERVFAIL）。
+//! Before rules are compiled, the references in a config are checked in one pass: jump and
+//! pipeline_select targets exist, upstream addresses parse, regexes compile, rewrite_rcode
+//! names are known rcodes, response-only
+//! actions do not appear in the request phase, delays are shorter than the request timeout,
+//! and request-phase jumps form no cycle. All
+//! problems are collected and returned together so fixing a config does not take one reload
+//! per mistake; disabled pipelines and rules are checked as well, and enabled rules may not
+//! jump to a disabled pipeline (disabled pipelines are not compiled, so such queries could
+//! only get SERVFAIL).
+
+use std::collections::HashSet;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::config::{
+    Action, Matcher, PipelineConfig, PipelineSelectorMatcher, ResponseMatcher, Transport,
+};
+
+use super::rules::parse_rcode;
+use super::upstream::check_upstream;
+
+/// 配置中的全部交叉引用错误，无错误时为空 / All cross-reference errors in a config, empty when there are none
+pub fn cross_reference_errors(cfg: &PipelineConfig) -> Vec<String> {
+    let ids: HashSet<&str> = cfg.pipelines.iter().map(|p| p.id.as_str()).collect();
+    let disabled: HashSet<&str> = cfg.pipelines.iter().filter(|p| !p.enabled).map(|p| p.id.as_str()).collect();
+    let request_timeout_ms = cfg.settings.effective_request_timeout_ms();
+    let mut errors = Vec::new();
+
+    check_upstream_list(&cfg.settings.default_upstream, Transport::Udp, "settings.default_upstream", &mut errors);
+    for (i, d) in cfg.settings.ddr.designations.iter().enumerate() {
+        if let Err(e) = super::ddr::check_designation(d) {
+            errors.push(format!("settings.ddr.designations[{}]: {}", i, e));
+        }
+    }
+    for (i, r) in cfg.settings.local_records.iter().enumerate() {
+        if let Err(e) = super::local_records::parse(r) {
+            errors.push(format!("settings.local_records[{}]: {:#}", i, e));
+        }
+    }
+    for (i, r) in cfg.settings.ptr_rewrite.iter().enumerate() {
+        if let Err(e) = super::ptr_rewrite::check(r) {
+            errors.push(format!("settings.ptr_rewrite[{}]: {}", i, e));
+        }
+    }
+    for (field, list) in [("bind_udp", &cfg.settings.bind_udp), ("bind_tcp", &cfg.settings.bind_tcp)] {
+        if let Err(e) = crate::socket_utils::parse_bind_list(list) {
+            errors.push(format!("settings.{}: {:#}", field, e));
+        }
+    }
+    for (field, list) in [("bind_dot", &cfg.settings.bind_dot), ("bind_doh", &cfg.settings.bind_doh)] {
+        let Some(list) = list else { continue };
+        if let Err(e) = crate::socket_utils::parse_bind_list(list) {
+            errors.push(format!("settings.{}: {:#}", field, e));
+        }
+        if cfg.settings.tls_cert_path.is_none() || cfg.settings.tls_key_path.is_none() {
+            errors.push(format!("settings.{}: tls_cert_path and tls_key_path are required", field));
+        }
+    }
+    if !cfg.settings.doh_path.starts_with('/') {
+        errors.push(format!("settings.doh_path: {:?} must start with /", cfg.settings.doh_path));
+    }
+    if !(12..=65535).contains(&cfg.settings.doh_max_body_bytes) {
+        errors.push(format!("settings.doh_max_body_bytes: {} is outside 12..=65535", cfg.settings.doh_max_body_bytes));
+    }
+    if cfg.settings.doh_max_concurrent_streams == 0 {
+        errors.push("settings.doh_max_concurrent_streams: must be at least 1".to_string());
+    }
+    if !cfg
+        .settings
+        .ipv6_probe_addr
+        .parse::<std::net::SocketAddr>()
+        .is_ok_and(|addr| addr.is_ipv6())
+    {
+        errors.push(format!("settings.ipv6_probe_addr: {:?} is not an [ipv6]:port address", cfg.settings.ipv6_probe_addr));
+    }
+    let peer_sync = &cfg.settings.peer_sync;
+    if peer_sync.listen.is_some() && peer_sync.token.is_none() && peer_sync.tls.is_none() {
+        errors.push("settings.peer_sync: listen requires token or tls".to_string());
+    }
+    if peer_sync.token.as_deref().is_some_and(str::is_empty) {
+        errors.push("settings.peer_sync.token: must not be empty".to_string());
+    }
+    for (addr, opts) in &cfg.settings.upstream_options {
+        if opts.max_udp_size.is_some_and(|max| max < 512) {
+            errors.push(format!("settings.upstream_options.{}: max_udp_size must be at least 512", addr));
+        }
+    }
+    let health = &cfg.settings.upstream_health_check;
+    if hickory_proto::rr::Name::from_ascii(&health.query_name).is_err() {
+        errors.push(format!("settings.upstream_health_check.query_name: {:?} is not a domain name", health.query_name));
+    }
+    if health.failure_threshold == 0 || health.success_threshold == 0 {
+        errors.push("settings.upstream_health_check: thresholds must be at least 1".to_string());
+    }
+    let packet_bytes = cfg.settings.udp_buffers.packet_bytes;
+    if !(512..=65535).contains(&packet_bytes) {
+        errors.push(format!("settings.udp_buffers.packet_bytes: {} is outside 512..=65535", packet_bytes));
+    }
+
+    for sel in &cfg.pipeline_select {
+        let at = format!("pipeline_select {}", sel.pipeline);
+        if !ids.contains(sel.pipeline.as_str()) {
+            errors.push(format!("{}: unknown pipeline", at));
+        }
+        for m in &sel.matchers {
+            if let PipelineSelectorMatcher::DomainRegex { value } = &m.matcher {
+                check_regex(value, &at, &mut errors);
+            }
+        }
+    }
+
+    for pipeline in &cfg.pipelines {
+        for rule in &pipeline.rules {
+            let at = format!("pipeline {} rule {}", pipeline.id, rule.name);
+            for m in &rule.matchers {
+                if let Matcher::DomainRegex { value } = &m.matcher {
+                    check_regex(value, &at, &mut errors);
+                }
+            }
+            for m in &rule.response_matchers {
+                match &m.matcher {
+                    ResponseMatcher::RequestDomainRegex { value } => check_regex(value, &at, &mut errors),
+                    ResponseMatcher::ResponseTxtContent { mode, value } if mode.eq_ignore_ascii_case("regex") => {
+                        // 与匹配器编译时的 ReDoS 限制一致 / Same ReDoS limits as when the matcher is compiled
+                        if let Err(e) = RegexBuilder::new(value).size_limit(1000).dfa_size_limit(1000).build() {
+                            errors.push(format!("{}: invalid regex {:?}: {}", at, value, e));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            for action in &rule.actions {
+                if matches!(
+                    action,
+                    Action::RetryTcp
+                        | Action::RewriteRcode { .. }
+                        | Action::ReplaceTxtResponse { .. }
+                        | Action::QuarantineUpstream { .. }
+                        | Action::SetHeaderFlags { .. }
+                ) {
+                    errors.push(format!("{}: {} is only valid in response actions", at, action_name(action)));
+                }
+            }
+            let phases = [
+                ("actions", &rule.actions),
+                ("response_actions_on_match", &rule.response_actions_on_match),
+                ("response_actions_on_miss", &rule.response_actions_on_miss),
+            ];
+            for (phase, actions) in phases {
+                for action in actions {
+                    match action {
+                        Action::JumpToPipeline { pipeline, .. } if !ids.contains(pipeline.as_str()) => {
+                            errors.push(format!("{} {}: jump to unknown pipeline {}", at, phase, pipeline));
+                        }
+                        Action::JumpToPipeline { pipeline: target, .. }
+                            if pipeline.enabled && rule.enabled && disabled.contains(target.as_str()) =>
+                        {
+                            errors.push(format!("{} {}: jump to disabled pipeline {}", at, phase, target));
+                        }
+                        // 延迟到请求超时的查询得不到任何应答 / A query delayed past the request timeout gets no answer at all
+                        Action::Delay { ms } if *ms >= request_timeout_ms => {
+                            errors.push(format!(
+                                "{} {}: delay {}ms must be shorter than the request timeout ({}ms)",
+                                at, phase, ms, request_timeout_ms
+                            ));
+                        }
+                        // 未知的 rcode 名称会让动作在运行时静默失效 / An unknown rcode name would make the action a silent no-op
+                        Action::RewriteRcode { from, to } => {
+                            for name in [from, to] {
+                                if parse_rcode(name).is_none() {
+                                    errors.push(format!("{} {}: invalid rcode {}", at, phase, name));
+                                }
+                            }
+                        }
+                        Action::ReselectPipeline { .. } if phase != "actions" => {
+                            errors.push(format!("{} {}: reselect_pipeline is only valid in request actions", at, phase));
+                        }
+                        Action::Forward { upstream: Some(upstream), transport, .. } => {
+                            let at = format!("{} {}", at, phase);
+                            check_upstream_list(upstream, transport.unwrap_or(Transport::Udp), &at, &mut errors);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(cycle) = cfg.jump_cycle() {
+        errors.push(format!("jump_to_pipeline cycle: {}", cycle.join(" -> ")));
+    }
+    errors
+}
+
+fn check_upstream_list(list: &str, transport: Transport, at: &str, errors: &mut Vec<String>) {
+    for spec in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if let Err(e) = check_upstream(spec, transport) {
+            errors.push(format!("{}: invalid upstream {}: {:#}", at, spec, e));
+        }
+    }
+}
+
+fn check_regex(value: &str, at: &str, errors: &mut Vec<String>) {
+    if let Err(e) = Regex::new(value) {
+        errors.push(format!("{}: invalid regex {:?}: {}", at, value, e));
+    }
+}
+
+fn action_name(action: &Action) -> &'static str {
+    match action {
+        Action::RetryTcp => "retry_tcp",
+        Action::RewriteRcode { .. } => "rewrite_rcode",
+        Action::ReplaceTxtResponse { .. } => "replace_txt_response",
+        Action::QuarantineUpstream { .. } => "quarantine_upstream",
+        Action::SetHeaderFlags { .. } => "set_header_flags",
+        _ => "action",
+    }
+}
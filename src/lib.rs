@@ -1,10 +1,18 @@
+pub mod admin;
+#[cfg(feature = "grpc")]
+pub mod admin_grpc;
 pub mod cache;
+pub mod cluster;
 pub mod config;
+pub mod doh_server;
 pub mod engine;
 pub mod lock;
 pub mod matcher;
 pub mod proto_utils;
 pub mod watcher;
 pub mod socket_utils;
+pub mod tls_server;
 pub mod error_utils;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
 
@@ -5,13 +5,19 @@ use std::time::Duration;
 
 use anyhow::Context;
 use clap::{Parser, Subcommand};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio_rustls::TlsAcceptor;
+use tokio::sync::watch;
 use tracing::{error, info, debug, warn};
 use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
-use kixdns::config::load_config;
-use kixdns::engine::{Engine, FastPathResponse};
+use kixdns::config::{GlobalSettings, ListenerTransport, UdpBufferSettings, load_config};
+use kixdns::engine::{Engine, FastPathResponse, MalformedQuery};
+use kixdns::engine::concurrency::with_request_permit;
+use kixdns::engine::tunables::ListenAddrs;
+use kixdns::socket_utils::{ListenSocket, canonical_peer, parse_bind_list, plan_listen_sockets};
+use kixdns::{doh_server, tls_server};
 use kixdns::matcher::RuntimePipelineConfig;
 use kixdns::watcher;
 
@@ -51,6 +57,23 @@ enum Commands {
         #[arg(short = 'f', long = "filter")]
         filter: Option<String>,
     },
+    /// Print the JSON Schema of the config file / 输出配置文件的 JSON Schema
+    Schema,
+    /// Configuration tools / 配置工具
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the effective configuration as JSON / 以 JSON 输出实际生效的配置
+    Dump {
+        /// 配置文件路径（JSON） / Config file path (JSON)
+        #[arg(short = 'c', long = "config", default_value = "config/pipeline.json")]
+        config: PathBuf,
+    },
 }
 
 #[tokio::main]
@@ -76,6 +99,11 @@ async fn main() -> anyhow::Result<()> {
                 }
             }
         }
+        Some(Commands::Schema) => {
+            println!("{}", serde_json::to_string_pretty(&kixdns::config::schema()).context("serialize schema")?);
+            Ok(())
+        }
+        Some(Commands::Config { action: ConfigCommand::Dump { config } }) => dump_config(config),
         Some(Commands::Run { config, listener_label, debug, udp_workers_count }) => {
             run_dns_server(config, listener_label, debug, udp_workers_count).await
         }
@@ -91,6 +119,15 @@ async fn main() -> anyhow::Result<()> {
     }
 }
 
+/// 输出补全默认值并通过校验后的配置 / Print the configuration after defaults and validation
+fn dump_config(config: PathBuf) -> anyhow::Result<()> {
+    let cfg = load_config(&config).context("load config")?;
+    // 编译一次规则以执行与启动时相同的校验 / Compile once to run the same validation as startup
+    RuntimePipelineConfig::from_config(cfg.clone()).context("compile matchers")?;
+    println!("{}", serde_json::to_string_pretty(&cfg).context("serialize config")?);
+    Ok(())
+}
+
 /// 运行 DNS 服务器 / Run DNS server
 /// 提取公共逻辑以消除代码重复 / Extract common logic to eliminate code duplication
 async fn run_dns_server(
@@ -112,12 +149,26 @@ async fn run_dns_server(
 
     let cfg = load_config(&config).context("load initial config")?;
             let cfg = RuntimePipelineConfig::from_config(cfg).context("compile matchers")?;
-            let bind_addr: SocketAddr = cfg.settings.bind_udp.parse().context("parse bind addr")?;
-            let bind_tcp: SocketAddr = cfg
+            let listen_addrs = ListenAddrs::from_settings(&cfg.settings);
+            let dot_listener = tls_listener(&cfg.settings, "bind_dot", cfg.settings.bind_dot.as_deref(), tls_server::DOT_ALPN)?;
+            let doh_listener = tls_listener(&cfg.settings, "bind_doh", cfg.settings.bind_doh.as_deref(), doh_server::DOH_ALPN)?;
+            let doh_options = Arc::new(doh_server::DohOptions::from_settings(&cfg.settings));
+            let admin_bind: Option<SocketAddr> = cfg
+                .settings
+                .admin_bind
+                .as_deref()
+                .map(|s| s.parse().context("parse admin bind addr"))
+                .transpose()?;
+            let grpc_admin_bind: Option<SocketAddr> = cfg
                 .settings
-                .bind_tcp
-                .parse()
-                .context("parse tcp bind addr")?;
+                .grpc_admin_bind
+                .as_deref()
+                .map(|s| s.parse().context("parse grpc admin bind addr"))
+                .transpose()?;
+
+            let upstream_prewarm = cfg.settings.upstream_prewarm;
+            let upstream_prewarm_interval_secs = cfg.settings.upstream_prewarm_interval_secs;
+            let tcp_fast_open = cfg.settings.tcp_fast_open;
 
             let engine = Engine::new(cfg, listener_label.clone());
 
@@ -130,146 +181,108 @@ async fn run_dns_server(
                 num_cpus::get()
             };
 
-            info!(bind_udp = %bind_addr, bind_tcp = %bind_tcp, udp_workers_count = udp_workers_final, "dns server started");
+            info!(bind_udp = %listen_addrs.udp, bind_tcp = %listen_addrs.tcp, ipv6_listen_mode = ?listen_addrs.ipv6_mode, udp_workers_count = udp_workers_final, "dns server started");
 
             let mut all_handles: Vec<tokio::task::JoinHandle<()>> = Vec::new();
 
-            #[cfg(unix)]
-            {
-                // ✅ OpenBSD 兼容性方案：双 socket（IPv4 + IPv6）+ 零拷贝 recv_buf_from
-                // ✅ OpenBSD compatibility: dual sockets (IPv4 + IPv6) + zero-copy recv_buf_from
-                // 为每个地址族创建独立的 socket 和 workers，避免 sockaddr 大小断言失败
-                // Create separate sockets and workers for each address family to avoid sockaddr size assertion failures
-
-                // ✅ OpenBSD 兼容性方案：双 socket（IPv4 + IPv6）+ 零拷贝 recv_buf_from
-                // ✅ OpenBSD compatibility: dual sockets (IPv4 + IPv6) + zero-copy recv_buf_from
-                // 为每个地址族创建独立的 socket 和 workers，避免 sockaddr 大小断言失败
-                // Create separate sockets and workers for each address family to avoid sockaddr size assertion failures
-
-                // 根据配置地址决定创建哪种 socket / Determine which socket type to create based on config
-                // IPv6 unspecified address (::) 需要同时创建 IPv4 和 IPv6 socket
-                // IPv6 other addresses 只创建 IPv6 socket
-                // IPv4 addresses 只创建 IPv4 socket
-                let needs_ipv4 = bind_addr.is_ipv4() ||
-                    (bind_addr.is_ipv6() && bind_addr.ip().is_unspecified());
-                let needs_ipv6 = bind_addr.is_ipv6();
-
-                if needs_ipv4 {
-                    let workers_per_family = if needs_ipv6 {
-                        udp_workers_final.div_ceil(2)
-                    } else {
-                        udp_workers_final
-                    };
-                    spawn_ipv4_udp_workers(bind_addr, workers_per_family, engine.clone(), &mut all_handles)?;
-                }
-
-                if needs_ipv6 {
-                    let workers_per_family = if needs_ipv4 {
-                        udp_workers_final.div_ceil(2)
-                    } else {
-                        udp_workers_final
-                    };
-                    spawn_ipv6_udp_workers(bind_addr, workers_per_family, engine.clone(), &mut all_handles)?;
-                }
+            // --- 启动 UDP/TCP 监听，并在重载修改监听地址时重新绑定 ---
+            // --- Start UDP/TCP listeners and rebind when a reload changes the listen addresses ---
+            let mut listeners = Listeners::start(&listen_addrs, udp_workers_final, tcp_fast_open, &engine)?;
+            if let Some((sockets, acceptor)) = dot_listener {
+                start_dot_listener(&sockets, acceptor, tcp_fast_open, &engine)?;
             }
-
-            #[cfg(not(unix))]
+            if let Some((sockets, acceptor)) = doh_listener {
+                start_doh_listener(&sockets, acceptor, doh_options, tcp_fast_open, &engine)?;
+            }
+            engine.health.mark_listeners_bound();
             {
-                // Non-Unix: create a single shared socket and spawn workers that share it / 非 Unix：创建单个共享套接字并生成共享它的工作线程
-                // Use socket2 to set buffer sizes / 使用 socket2 设置缓冲区大小
-                use socket2::{Domain, Protocol, Socket, Type};
-                let domain = if bind_addr.is_ipv4() {
-                    Domain::IPV4
-                } else {
-                    Domain::IPV6
-                };
-                let socket =
-                    Socket::new(domain, Type::DGRAM, Some(Protocol::UDP)).context("create socket")?;
-
-                // ✅ Windows 上设置 IPV6_V6ONLY=0 以支持双栈，与 Linux 行为一致
-                // ✅ On Windows, set IPV6_V6ONLY=0 for dual-stack support, consistent with Linux behavior
-                if domain == Domain::IPV6 {
-                    if let Err(e) = socket.set_only_v6(false) {
-                        debug!("failed to set IPV6_V6ONLY=0: {}, IPv4 may not work on [::] bind", e);
-                    } else {
-                        info!("UDP IPv6 socket set to dual-stack mode (IPV6_V6ONLY=0)");
-                    }
-                }
-
-                // Set buffer sizes to prevent packet loss under load
-                // Try 4MB first, then fall back to 1MB if it fails
-                let desired_size = 4 * 1024 * 1024;
-                let fallback_size = 1024 * 1024;
-
-                if let Err(e) = socket.set_recv_buffer_size(desired_size) {
-                    debug!("failed to set udp recv buffer to {} bytes: {}, trying {}", desired_size, e, fallback_size);
-                    let _ = socket.set_recv_buffer_size(fallback_size);
-                }
-                if let Err(e) = socket.set_send_buffer_size(desired_size) {
-                    debug!("failed to set udp send buffer to {} bytes: {}, trying {}", desired_size, e, fallback_size);
-                    let _ = socket.set_send_buffer_size(fallback_size);
-                }
-
-                socket.set_nonblocking(true).context("set nonblocking")?;
-                socket.bind(&bind_addr.into()).context("bind socket")?;
-
-                let udp_socket = Arc::new(UdpSocket::from_std(socket.into()).context("from_std")?);
-                for worker_id in 0..udp_workers_final {
-                    let engine = engine.clone();
-                    let socket = Arc::clone(&udp_socket);
-                    let handle = tokio::spawn(async move {
-                        if let Err(err) = run_udp_worker(worker_id, socket, engine).await {
-                            error!(worker_id, error = %err, "udp worker exited");
+                let engine = engine.clone();
+                let mut addrs = engine.watch_listen_addrs();
+                // 覆盖订阅前已发生的重载 / Cover reloads that happened before subscribing
+                addrs.mark_changed();
+                let h = tokio::spawn(async move {
+                    while addrs.changed().await.is_ok() {
+                        let next = addrs.borrow_and_update().clone();
+                        if let Err(err) = listeners.rebind(&next, &engine) {
+                            error!(
+                                bind_udp = %next.udp,
+                                bind_tcp = %next.tcp,
+                                error = %err,
+                                "listener rebind failed, keeping old sockets"
+                            );
                         }
-                    });
-                    all_handles.push(handle);
-                }
+                    }
+                });
+                all_handles.push(h);
             }
 
-            // TCP listener / TCP 监听器
-            // ✅ 双 socket 方案，与 UDP 行为一致 / Dual-socket approach, consistent with UDP
-            let needs_ipv4_tcp = bind_tcp.is_ipv4() || (bind_tcp.is_ipv6() && bind_tcp.ip().is_unspecified());
-
-            // --- 启动 IPv4 TCP 监听 / Start IPv4 TCP listener ---
-            if needs_ipv4_tcp {
-                let addr = if bind_tcp.is_ipv4() {
-                    bind_tcp
-                } else {
-                    SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)), bind_tcp.port())
-                };
-                // 纯 IPv4 绑定，不受 bindv6only 影响 / Pure IPv4 bind, unaffected by bindv6only
-                let listener = TcpListener::bind(addr).await.context("bind ipv4 tcp")?;
+            // --- 启动管理接口 / Start admin API ---
+            if let Some(admin_bind) = admin_bind {
                 let engine = engine.clone();
+                let config = config.clone();
                 let h = tokio::spawn(async move {
-                    if let Err(err) = run_tcp(listener, engine).await {
-                        error!(error = %err, "ipv4 tcp server exited");
+                    if let Err(err) = kixdns::admin::serve(admin_bind, engine, config).await {
+                        error!(error = %err, "admin api exited");
                     }
                 });
                 all_handles.push(h);
             }
 
-            // --- 启动 IPv6 TCP 监听 / Start IPv6 TCP listener ---
-            if bind_tcp.is_ipv6() {
-                use socket2::{Domain, Protocol, Socket, Type};
-                let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+            // --- 启动 gRPC 管理接口 / Start gRPC admin API ---
+            #[cfg(feature = "grpc")]
+            if let Some(grpc_admin_bind) = grpc_admin_bind {
+                let engine = engine.clone();
+                let config = config.clone();
+                let h = tokio::spawn(async move {
+                    if let Err(err) = kixdns::admin_grpc::serve(grpc_admin_bind, engine, config).await {
+                        error!(error = %err, "grpc admin api exited");
+                    }
+                });
+                all_handles.push(h);
+            }
+            #[cfg(not(feature = "grpc"))]
+            if grpc_admin_bind.is_some() {
+                warn!("grpc_admin_bind is set but this build lacks the grpc feature, gRPC admin api not started");
+            }
 
-                // ⭐️ 核心：强制 IPV6_V6ONLY=1，避免和 IPv4 监听器冲突
-                // ⭐️ Key: force IPV6_V6ONLY=1 to avoid conflict with IPv4 listener
-                socket.set_only_v6(true).context("set ipv6 only for kixdns")?;
-                socket.set_reuse_address(true)?;
+            // --- 热备同步 / Hot-standby sync ---
+            kixdns::engine::peer_sync::spawn(&engine).context("start peer sync")?;
 
-                socket.bind(&bind_tcp.into()).context("bind ipv6 tcp socket")?;
-                socket.listen(128)?;
-                socket.set_nonblocking(true)?;
+            // --- 上游健康检查 / Upstream health checks ---
+            kixdns::engine::upstream_health::spawn(&engine);
+            kixdns::engine::local_records::spawn(&engine);
 
-                let listener = TcpListener::from_std(socket.into())?;
+            // --- 预热加密上游连接 / Prewarm encrypted upstream connections ---
+            if upstream_prewarm {
                 let engine = engine.clone();
-                let h = tokio::spawn(async move {
-                    if let Err(err) = run_tcp(listener, engine).await {
-                        error!(error = %err, "ipv6 tcp server exited");
+                tokio::spawn(async move {
+                    loop {
+                        kixdns::engine::upstream::prewarm_encrypted_upstreams(&engine).await;
+                        if upstream_prewarm_interval_secs == 0 {
+                            break;
+                        }
+                        tokio::time::sleep(Duration::from_secs(upstream_prewarm_interval_secs)).await;
                     }
                 });
-                all_handles.push(h);
+            }
+
+            // --- SIGUSR1 输出统计快照 / Dump stats snapshot on SIGUSR1 ---
+            #[cfg(unix)]
+            {
+                use tokio::signal::unix::{SignalKind, signal};
+                match signal(SignalKind::user_defined1()) {
+                    Ok(mut usr1) => {
+                        let engine = engine.clone();
+                        tokio::spawn(async move {
+                            while usr1.recv().await.is_some() {
+                                if let Err(err) = engine.dump_stats() {
+                                    warn!(error = %err, "stats dump failed");
+                                }
+                            }
+                        });
+                    }
+                    Err(err) => warn!(error = %err, "failed to install SIGUSR1 handler"),
+                }
             }
 
             // 等待所有任务 / Wait for all tasks
@@ -296,79 +309,267 @@ fn init_tracing(debug: bool) {
         .init();
 }
 
-// 为 IPv4 地址创建并启动 UDP workers / Create and spawn UDP workers for IPv4 address
-#[cfg(unix)]
-fn spawn_ipv4_udp_workers(
-    bind_addr: SocketAddr,
-    worker_count: usize,
-    engine: Engine,
-    all_handles: &mut Vec<tokio::task::JoinHandle<()>>,
-) -> anyhow::Result<()> {
-    let ipv4_addr: SocketAddr = if bind_addr.is_ipv4() {
-        bind_addr
-    } else {
-        // 预编译的常量地址，避免 unwrap / Precompiled constant address, avoid unwrap
-        // 使用配置中的端口号而非硬编码 / Use port from config instead of hardcoded
-        SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)), bind_addr.port())
-    };
+/// 当前的 UDP/TCP 监听器，每组持有一个停止信号
+/// Current UDP/TCP listeners, each group holding a stop signal
+///
+/// 重新绑定时先启动新 socket（借助 SO_REUSEPORT，端口相同也可与旧 socket 并存），成功后才通知
+/// 旧 worker 停止接收；已在处理的请求仍通过旧 socket 回复，旧 TCP 连接保持到客户端关闭。
+/// Rebinding brings the new sockets up first (SO_REUSEPORT lets them coexist with the old
+/// ones on the same port) and only then tells the old workers to stop receiving; requests
+/// already in flight still reply through the old sockets and existing TCP connections stay
+/// open until the client closes them.
+struct Listeners {
+    udp: Vec<ListenSocket>,
+    tcp: Vec<ListenSocket>,
+    udp_workers: usize,
+    tcp_fast_open: bool,
+    udp_stop: watch::Sender<bool>,
+    tcp_stop: watch::Sender<bool>,
+}
 
-    info!(bind_addr = %ipv4_addr, workers = worker_count, "Starting IPv4 UDP workers");
+impl Listeners {
+    fn start(
+        addrs: &ListenAddrs,
+        udp_workers: usize,
+        tcp_fast_open: bool,
+        engine: &Engine,
+    ) -> anyhow::Result<Self> {
+        let udp = addrs.udp_sockets()?;
+        let tcp = addrs.tcp_sockets()?;
+        let udp_stop = start_udp_listener(&udp, udp_workers, engine)?;
+        let tcp_stop = start_tcp_listener(&tcp, tcp_fast_open, engine)?;
+        Ok(Self {
+            udp,
+            tcp,
+            udp_workers,
+            tcp_fast_open,
+            udp_stop,
+            tcp_stop,
+        })
+    }
 
-    for worker_id in 0..worker_count {
-        let engine = engine.clone();
-        let std_socket = create_reuseport_udp_socket(ipv4_addr)
-            .with_context(|| format!("create ipv4 udp socket for worker {}", worker_id))?;
-        let socket = UdpSocket::from_std(std_socket)?;
-        let handle = tokio::spawn(async move {
-            if let Err(err) = run_udp_worker(worker_id, Arc::new(socket), engine).await {
-                error!(worker_id, error = %err, "IPv4 udp worker exited");
+    /// 按新地址重新绑定变化的监听器 / Rebind the listeners whose address changed
+    fn rebind(&mut self, addrs: &ListenAddrs, engine: &Engine) -> anyhow::Result<()> {
+        let udp = addrs.udp_sockets()?;
+        let tcp = addrs.tcp_sockets()?;
+
+        if udp != self.udp {
+            let stop = start_udp_listener(&udp, self.udp_workers, engine)?;
+            let _ = std::mem::replace(&mut self.udp_stop, stop).send(true);
+            info!(from = %describe_sockets(&self.udp), to = %describe_sockets(&udp), "udp listener rebound, old sockets draining");
+            self.udp = udp;
+        }
+        if tcp != self.tcp {
+            let stop = start_tcp_listener(&tcp, self.tcp_fast_open, engine)?;
+            let _ = std::mem::replace(&mut self.tcp_stop, stop).send(true);
+            info!(from = %describe_sockets(&self.tcp), to = %describe_sockets(&tcp), "tcp listener rebound, old listener closed");
+            self.tcp = tcp;
+        }
+        Ok(())
+    }
+}
+
+/// 监听 socket 的日志描述 / Log description of listening sockets
+fn describe_sockets(sockets: &[ListenSocket]) -> String {
+    sockets
+        .iter()
+        .map(|s| match s.v6only {
+            Some(false) => format!("{} (dual-stack)", s.addr),
+            _ => s.addr.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// 启动 UDP workers，返回其停止信号 / Start the UDP workers and return their stop signal
+fn start_udp_listener(
+    sockets: &[ListenSocket],
+    udp_workers_final: usize,
+    engine: &Engine,
+) -> anyhow::Result<watch::Sender<bool>> {
+    let (stop_tx, stop) = watch::channel(false);
+
+    // ✅ OpenBSD 兼容性方案：每个地址族使用独立的 socket（`[::]` 默认展开为 IPv4 + 仅 IPv6 两组）
+    // ✅ OpenBSD compatibility: separate sockets per address family (`[::]` expands to IPv4 + IPv6-only by default)
+    // 避免 sockaddr 大小断言失败；worker 按 socket 均分
+    // This avoids sockaddr size assertion failures; workers are split evenly across sockets
+    let workers_per_socket = udp_workers_final.div_ceil(sockets.len().max(1));
+    for listen in sockets {
+        spawn_udp_workers(*listen, workers_per_socket, engine, &stop)?;
+    }
+
+    Ok(stop_tx)
+}
+
+/// 启动 TCP 监听，返回其停止信号 / Start the TCP listeners and return their stop signal
+fn start_tcp_listener(
+    sockets: &[ListenSocket],
+    tcp_fast_open: bool,
+    engine: &Engine,
+) -> anyhow::Result<watch::Sender<bool>> {
+    let (stop_tx, stop) = watch::channel(false);
+
+    // ✅ 与 UDP 相同的 socket 划分 / Same socket layout as UDP
+    for listen in sockets {
+        let listener = bind_tcp_listener(*listen).with_context(|| format!("bind tcp {}", listen.addr))?;
+        if tcp_fast_open {
+            enable_tcp_fast_open(&listener);
+        }
+        let engine = engine.for_transport(ListenerTransport::Tcp);
+        let stop = stop.clone();
+        let addr = listen.addr;
+        tokio::spawn(async move {
+            if let Err(err) = run_tcp(listener, engine, stop).await {
+                error!(bind_addr = %addr, error = %err, "tcp server exited");
             }
         });
-        all_handles.push(handle);
     }
 
+    Ok(stop_tx)
+}
+
+/// 按配置规划 TLS 监听 socket 并加载证书，未配置监听地址时为 None
+/// Plan the TLS listening sockets and load the certificate per settings; None without a bind address
+fn tls_listener(
+    settings: &GlobalSettings,
+    field: &str,
+    bind: Option<&str>,
+    alpn: &[u8],
+) -> anyhow::Result<Option<(Vec<ListenSocket>, TlsAcceptor)>> {
+    let Some(list) = bind else {
+        return Ok(None);
+    };
+    let addrs = parse_bind_list(list).with_context(|| format!("parse {}", field))?;
+    let sockets = plan_listen_sockets(&addrs, settings.ipv6_listen_mode);
+    let cert = settings.tls_cert_path.as_deref().with_context(|| format!("{} requires tls_cert_path", field))?;
+    let key = settings.tls_key_path.as_deref().with_context(|| format!("{} requires tls_key_path", field))?;
+    let acceptor = tls_server::acceptor(cert, key, &[alpn])?;
+    Ok(Some((sockets, acceptor)))
+}
+
+/// 启动 DoT 监听；修改 `bind_dot` 与证书需要重启
+/// Start the DoT listeners; changing `bind_dot` or the certificate needs a restart
+fn start_dot_listener(
+    sockets: &[ListenSocket],
+    acceptor: TlsAcceptor,
+    tcp_fast_open: bool,
+    engine: &Engine,
+) -> anyhow::Result<()> {
+    for listen in sockets {
+        let listener = bind_tcp_listener(*listen).with_context(|| format!("bind dot {}", listen.addr))?;
+        if tcp_fast_open {
+            enable_tcp_fast_open(&listener);
+        }
+        info!(bind_addr = %listen.addr, "DoT listener started");
+        let engine = engine.for_transport(ListenerTransport::Dot);
+        let acceptor = acceptor.clone();
+        let addr = listen.addr;
+        tokio::spawn(async move {
+            if let Err(err) = run_dot(listener, acceptor, engine).await {
+                error!(bind_addr = %addr, error = %err, "dot server exited");
+            }
+        });
+    }
     Ok(())
 }
 
-// 为 IPv6 地址创建并启动 UDP workers / Create and spawn UDP workers for IPv6 address
-#[cfg(unix)]
-fn spawn_ipv6_udp_workers(
-    bind_addr: SocketAddr,
-    worker_count: usize,
-    engine: Engine,
-    all_handles: &mut Vec<tokio::task::JoinHandle<()>>,
+/// 启动 DoH 监听；修改 `bind_doh`、`doh_*` 选项与证书需要重启
+/// Start the DoH listeners; changing `bind_doh`, the `doh_*` options or the certificate needs a restart
+fn start_doh_listener(
+    sockets: &[ListenSocket],
+    acceptor: TlsAcceptor,
+    options: Arc<doh_server::DohOptions>,
+    tcp_fast_open: bool,
+    engine: &Engine,
 ) -> anyhow::Result<()> {
-    let ipv6_addr: SocketAddr = if bind_addr.is_ipv6() {
-        bind_addr
+    for listen in sockets {
+        let listener = bind_tcp_listener(*listen).with_context(|| format!("bind doh {}", listen.addr))?;
+        if tcp_fast_open {
+            enable_tcp_fast_open(&listener);
+        }
+        info!(bind_addr = %listen.addr, path = %options.path, "DoH listener started");
+        let engine = engine.clone();
+        let acceptor = acceptor.clone();
+        let options = Arc::clone(&options);
+        let addr = listen.addr;
+        tokio::spawn(async move {
+            if let Err(err) = doh_server::run(listener, acceptor, engine, options).await {
+                error!(bind_addr = %addr, error = %err, "doh server exited");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// 创建 TCP 监听 socket；SO_REUSEPORT 使新旧监听器在重新绑定期间可共用端口
+/// Create a TCP listening socket; SO_REUSEPORT lets the old and new listeners share the port while rebinding
+fn bind_tcp_listener(listen: ListenSocket) -> anyhow::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let domain = if listen.addr.is_ipv4() {
+        Domain::IPV4
     } else {
-        // 预编译的常量地址，避免 unwrap / Precompiled constant address, avoid unwrap
-        // 使用配置中的端口号而非硬编码 / Use port from config instead of hardcoded
-        SocketAddr::new(std::net::IpAddr::V6(std::net::Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0)), bind_addr.port())
+        Domain::IPV6
     };
+    let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
+
+    // ⭐️ 核心：显式设置 IPV6_V6ONLY，仅 IPv6 时避免和 IPv4 监听器冲突
+    // ⭐️ Key: set IPV6_V6ONLY explicitly; IPv6-only avoids conflicts with the IPv4 listener
+    if let Some(v6only) = listen.v6only {
+        socket.set_only_v6(v6only).context("set IPV6_V6ONLY for kixdns")?;
+    }
+    socket.set_reuse_address(true)?;
+    if let Err(e) = kixdns::socket_utils::set_reuseport(&socket, true) {
+        debug!("SO_REUSEPORT failed on tcp listener: {}", e);
+    }
+
+    socket.bind(&listen.addr.into())?;
+    socket.listen(128)?;
+    socket.set_nonblocking(true)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
 
-    info!(bind_addr = %ipv6_addr, workers = worker_count, "Starting IPv6 UDP workers");
+// 为一个监听 socket 创建并启动 UDP workers / Create and spawn the UDP workers of one listening socket
+// Unix 上每个 worker 持有独立的 SO_REUSEPORT socket 由内核分发，其他平台的 worker 共享一个 socket
+// On Unix each worker owns an SO_REUSEPORT socket and the kernel spreads packets; elsewhere workers share one socket
+fn spawn_udp_workers(
+    listen: ListenSocket,
+    worker_count: usize,
+    engine: &Engine,
+    stop: &watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    info!(bind_addr = %listen.addr, v6only = ?listen.v6only, workers = worker_count, "Starting UDP workers");
 
+    let buffers = engine.udp_buffers();
+    let shared = if cfg!(unix) {
+        None
+    } else {
+        Some(Arc::new(UdpSocket::from_std(create_udp_socket(listen, &buffers)?)?))
+    };
     for worker_id in 0..worker_count {
+        let socket = match &shared {
+            Some(socket) => Arc::clone(socket),
+            None => {
+                let std_socket = create_udp_socket(listen, &buffers)
+                    .with_context(|| format!("create udp socket {} for worker {}", listen.addr, worker_id))?;
+                Arc::new(UdpSocket::from_std(std_socket)?)
+            }
+        };
         let engine = engine.clone();
-        let std_socket = create_reuseport_udp_socket(ipv6_addr)
-            .with_context(|| format!("create ipv6 udp socket for worker {}", worker_id))?;
-        let socket = UdpSocket::from_std(std_socket)?;
-        let handle = tokio::spawn(async move {
-            if let Err(err) = run_udp_worker(worker_id, Arc::new(socket), engine).await {
-                error!(worker_id, error = %err, "IPv6 udp worker exited");
+        let stop = stop.clone();
+        tokio::spawn(async move {
+            if let Err(err) = run_udp_worker(worker_id, socket, engine, stop).await {
+                error!(worker_id, bind_addr = %listen.addr, error = %err, "udp worker exited");
             }
         });
-        all_handles.push(handle);
     }
 
     Ok(())
 }
 
-// 在 Unix 上创建带 SO_REUSEPORT 的 UDP socket；非 Unix 使用标准绑定 / Create UDP socket with SO_REUSEPORT on Unix; use standard binding on non-Unix
-#[cfg(unix)]
-fn create_reuseport_udp_socket(addr: SocketAddr) -> anyhow::Result<std::net::UdpSocket> {
+// 创建 UDP socket，Unix 上带 SO_REUSEPORT / Create a UDP socket, with SO_REUSEPORT on Unix
+fn create_udp_socket(listen: ListenSocket, buffers: &UdpBufferSettings) -> anyhow::Result<std::net::UdpSocket> {
     use socket2::{Domain, Protocol, Socket, Type};
-    let domain = if addr.is_ipv4() {
+    let domain = if listen.addr.is_ipv4() {
         Domain::IPV4
     } else {
         Domain::IPV6
@@ -376,41 +577,83 @@ fn create_reuseport_udp_socket(addr: SocketAddr) -> anyhow::Result<std::net::Udp
     let socket = Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
     socket.set_reuse_address(true)?;
 
-    // ✅ OpenBSD/FreeBSD 安全措施：为 IPv6 socket 显式设置 IPV6_V6ONLY=1
-    // ✅ OpenBSD/FreeBSD safety: explicitly set IPV6_V6ONLY=1 for IPv6 sockets
-    // 双 socket 方案下，IPv6 socket 只处理 IPv6 流量，确保地址族一致性，避免 sockaddr 大小断言失败
-    // With dual-socket approach, IPv6 socket only handles IPv6 traffic, ensuring address family consistency
-    // 这使得我们可以安全地使用零拷贝的 recv_buf_from
-    // This allows us to safely use zero-copy recv_buf_from
-    if domain == Domain::IPV6 {
-        if let Err(e) = kixdns::socket_utils::set_ipv6_v6only(&socket, true) {
-            tracing::warn!("Failed to set IPV6_V6ONLY=1: {}, this may cause issues on OpenBSD", e);
-        }
+    // ✅ OpenBSD/FreeBSD 安全措施：为 IPv6 socket 显式设置 IPV6_V6ONLY（split / v6_only 模式为 1）
+    // ✅ OpenBSD/FreeBSD safety: explicitly set IPV6_V6ONLY on IPv6 sockets (1 in split / v6_only mode)
+    // 仅 IPv6 的 socket 只处理 IPv6 流量，确保地址族一致性；dual_stack 模式的 IPv4 客户端以映射地址到达
+    // An IPv6-only socket handles IPv6 traffic only, keeping address families consistent; in dual_stack mode IPv4 clients arrive as mapped addresses
+    if let Some(v6only) = listen.v6only
+        && let Err(e) = socket.set_only_v6(v6only)
+    {
+        warn!("Failed to set IPV6_V6ONLY={}: {}, this may cause issues on OpenBSD", v6only as u8, e);
     }
 
     // Try to set SO_REUSEPORT via safe wrapper / 尝试通过安全封装设置 SO_REUSEPORT
+    #[cfg(unix)]
     if let Err(e) = kixdns::socket_utils::set_reuseport(&socket, true) {
         // Log warning if SO_REUSEPORT fails / SO_REUSEPORT 失败时记录警告
-        tracing::warn!("SO_REUSEPORT failed: {}, falling back to shared socket", e);
+        warn!("SO_REUSEPORT failed: {}, falling back to shared socket", e);
     }
 
-    // Set buffer sizes to prevent packet loss under load
-    // Try 4MB first, then fall back to 1MB if it fails
-    let desired_size = 4 * 1024 * 1024;
-    let fallback_size = 1024 * 1024;
+    // Set buffer sizes to prevent packet loss under load / 设置缓冲区大小以防止高负载下丢包
+    set_udp_buffer_sizes(&socket, buffers);
 
-    if let Err(e) = socket.set_recv_buffer_size(desired_size) {
-        debug!("failed to set udp recv buffer to {} bytes: {}, trying {}", desired_size, e, fallback_size);
-        let _ = socket.set_recv_buffer_size(fallback_size);
+    socket.set_nonblocking(true)?;
+    socket.bind(&listen.addr.into())?;
+    Ok(socket.into())
+}
+
+/// 按配置设置 UDP socket 的收发缓冲区，失败时回退到 1 MiB，0 表示保持系统默认
+/// Set the UDP socket buffers from settings, falling back to 1 MiB on failure; 0 keeps the system default
+fn set_udp_buffer_sizes(socket: &socket2::Socket, buffers: &UdpBufferSettings) {
+    let fallback_size = 1024 * 1024;
+    if buffers.recv_bytes > 0
+        && let Err(e) = socket.set_recv_buffer_size(buffers.recv_bytes)
+    {
+        debug!("failed to set udp recv buffer to {} bytes: {}, trying {}", buffers.recv_bytes, e, fallback_size);
+        let _ = socket.set_recv_buffer_size(fallback_size.min(buffers.recv_bytes));
     }
-    if let Err(e) = socket.set_send_buffer_size(desired_size) {
-        debug!("failed to set udp send buffer to {} bytes: {}, trying {}", desired_size, e, fallback_size);
-        let _ = socket.set_send_buffer_size(fallback_size);
+    if buffers.send_bytes > 0
+        && let Err(e) = socket.set_send_buffer_size(buffers.send_bytes)
+    {
+        debug!("failed to set udp send buffer to {} bytes: {}, trying {}", buffers.send_bytes, e, fallback_size);
+        let _ = socket.set_send_buffer_size(fallback_size.min(buffers.send_bytes));
     }
+}
 
-    socket.set_nonblocking(true)?;
-    socket.bind(&addr.into())?;
-    Ok(socket.into())
+/// 发送 UDP 应答，超出客户端 UDP 上限时改发 TC=1 的截断应答
+/// Send a UDP answer, replaced by a TC=1 truncated answer when over the client's UDP limit
+async fn send_udp_answer(socket: &UdpSocket, query: &[u8], resp: &[u8], peer: SocketAddr) {
+    let _ = match kixdns::proto_utils::truncate_for_udp(query, resp) {
+        Some(truncated) => socket.send_to(&truncated, peer).await,
+        None => socket.send_to(resp, peer).await,
+    };
+}
+
+/// 发送缓存应答：事务 ID 与共享的缓存报文分两段经 sendmsg 发出，热路径不拷贝报文；
+/// 需要截断时只生成并发送一次截断应答
+/// Send a cached answer: the transaction ID and the shared cached packet go out as two
+/// sendmsg segments, so the hot path never copies the packet; when it has to be truncated the
+/// truncated answer is built once and sent
+async fn send_cached_answer(socket: &UdpSocket, query: &[u8], cached: &[u8], tx_id: u16, peer: SocketAddr) {
+    let id = tx_id.to_be_bytes();
+    if let Some(mut truncated) = kixdns::proto_utils::truncate_for_udp(query, cached) {
+        truncated[..2].copy_from_slice(&id);
+        let _ = socket.send_to(&truncated, peer).await;
+        return;
+    }
+    if cached.len() < 12 {
+        let mut resp = cached.to_vec();
+        if resp.len() >= 2 {
+            resp[..2].copy_from_slice(&id);
+        }
+        let _ = socket.send_to(&resp, peer).await;
+        return;
+    }
+    let bufs = [std::io::IoSlice::new(&id), std::io::IoSlice::new(&cached[2..])];
+    let addr = socket2::SockAddr::from(peer);
+    let _ = socket
+        .async_io(tokio::io::Interest::WRITABLE, || socket2::SockRef::from(socket).send_to_vectored(&bufs, &addr))
+        .await;
 }
 
 /// 高性能 UDP worker：直接在接收循环中处理请求，避免 spawn 开销 / High-performance UDP worker: process requests directly in receive loop, avoiding spawn overhead
@@ -418,32 +661,72 @@ async fn run_udp_worker(
     worker_id: usize,
     socket: Arc<UdpSocket>,
     engine: Engine,
+    mut stop: watch::Receiver<bool>,
 ) -> anyhow::Result<()> {
     // 预分配缓冲区 / Pre-allocate buffer
     // 使用 BytesMut 避免 Bytes::copy_from_slice 的内存分配 / Use BytesMut to avoid memory allocation in Bytes::copy_from_slice
     use bytes::BytesMut;
-    let mut buf = BytesMut::with_capacity(4096);
-    // 复用发送缓冲区：用于缓存命中时 patch TXID，避免每包堆分配 / Reuse send buffer to patch TXID on cache hits, avoiding per-packet heap allocation
-    let mut send_buf = BytesMut::with_capacity(512);
+    let packet_bytes = engine.udp_buffers().packet_bytes;
+    let mut buf = BytesMut::with_capacity(packet_bytes);
 
     // 自适应流控：每 100 个请求检查一次是否需要调整 permits
     // Adaptive flow control: check if adjustment needed every 100 requests
     let mut request_count = 0u32;
-    
+    let mut batch = engine.udp_batch(&format!("udp:{}#{}", socket.local_addr()?, worker_id));
+
     info!(worker_id, "UDP worker started");
 
     loop {
         // 确保有足够的空间 / Ensure sufficient space
-        if buf.capacity() < 4096 {
-            buf.reserve(4096 - buf.len());
+        if buf.capacity() < packet_bytes {
+            buf.reserve(packet_bytes - buf.len());
         }
 
         // ✅ 使用 tokio 的 recv_buf_from 配合 BytesMut，实现零拷贝的高性能接收
         // ✅ Use tokio's recv_buf_from with BytesMut for zero-copy high-performance reception
-        // 由于使用双 socket 方案（IPv4 + IPv6 分离），不会出现混合地址族的 sockaddr 问题
-        // Since we use dual-socket approach (IPv4 + IPv6 separated), no mixed address family sockaddr issues
-        match socket.recv_buf_from(&mut buf).await {
+        // 默认使用双 socket 方案（IPv4 + IPv6 分离），dual_stack 模式的 IPv6 socket 统一返回 sockaddr_in6，
+        // 都不会出现混合地址族的 sockaddr 问题
+        // The default dual-socket approach (IPv4 + IPv6 separated) and the IPv6 socket of dual_stack mode,
+        // which always returns sockaddr_in6, both avoid mixed address family sockaddr issues
+        // 监听地址已变更：停止接收，已派发的请求仍通过本 socket 回复
+        // Listen address changed: stop receiving, dispatched requests still reply through this socket
+        // 批次未满时先非阻塞读取 socket 积压，读空或批次填满后再等待
+        // Drain the socket backlog without blocking while the batch has room, wait once it is empty or full
+        let pending = if batch.wants_more() {
+            match socket.try_recv_buf_from(&mut buf) {
+                Ok(res) => {
+                    batch.record_packet();
+                    Some(Ok(res))
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => None,
+                Err(e) => Some(Err(e)),
+            }
+        } else {
+            None
+        };
+        let received = match pending {
+            Some(res) => res,
+            None => {
+                if batch.finish() {
+                    tokio::task::yield_now().await;
+                }
+                let res = tokio::select! {
+                    res = socket.recv_buf_from(&mut buf) => res,
+                    _ = stop.changed() => {
+                        info!(worker_id, "UDP worker stopped after rebind");
+                        return Ok(());
+                    }
+                };
+                batch.begin();
+                res
+            }
+        };
+        match received {
             Ok((_len, peer)) => {
+                // 双栈 socket 上的 IPv4 客户端以映射地址到达，规则与日志使用其 IPv4 地址（回复仍发往原地址）
+                // IPv4 clients on a dual-stack socket arrive as mapped addresses; rules and logs see the
+                // IPv4 address while replies still go to the original one
+                let client = canonical_peer(peer);
                 // 零拷贝获取 Bytes / Zero-copy obtain Bytes
                 let packet_bytes = buf.split().freeze();
 
@@ -458,33 +741,15 @@ async fn run_udp_worker(
                 // ✅ Optimization: Use handle_packet_fast to avoid re-parsing
                 // 如果缓存命中，直接返回；如果缓存未命中，返回预解析的数据
                 // If cache hit, return directly; if cache miss, return pre-parsed data
-                match engine.handle_packet_fast(&packet_bytes, peer) {
+                match engine.handle_packet_fast(&packet_bytes, client) {
                     Ok(Some(FastPathResponse::Direct(bytes))) => {
                         // 已包含正确 TXID，可直接发送 / Already contains correct TXID
-                        let _ = socket.send_to(&bytes, peer).await;
+                        send_udp_answer(&socket, &packet_bytes, &bytes, peer).await;
                     }
-                    Ok(Some(FastPathResponse::CacheHit { cached, tx_id, inserted_at })) => {
-                        // 复用 send_buf：copy + patch TXID / Reuse send_buf: copy + patch TXID
-                        send_buf.clear();
-                        if send_buf.capacity() < cached.len() {
-                            send_buf.reserve(cached.len() - send_buf.capacity());
-                        }
-                        send_buf.extend_from_slice(&cached);
-
-                        // RFC 1035 §5.2: Patch TTL based on residence time / 根据停留时间修正 TTL
-                        let elapsed = inserted_at.elapsed().as_secs() as u32;
-                        if elapsed > 0 {
-                            kixdns::proto_utils::patch_all_ttls(&mut send_buf, elapsed);
-                        }
-
-                        if send_buf.len() >= 2 {
-                            let id_bytes = tx_id.to_be_bytes();
-                            send_buf[0] = id_bytes[0];
-                            send_buf[1] = id_bytes[1];
-                        }
-                        let _ = socket.send_to(&send_buf, peer).await;
+                    Ok(Some(FastPathResponse::CacheHit { cached, tx_id })) => {
+                        send_cached_answer(&socket, &packet_bytes, &cached, tx_id, peer).await;
                     }
-                    Ok(Some(FastPathResponse::AsyncNeeded { qname, qtype, qclass, tx_id, edns_present, pipeline_id })) => {
+                    Ok(Some(FastPathResponse::AsyncNeeded { qname, qtype, qclass, tx_id, edns_present, dnssec_flags, pipeline_id })) => {
                         // 缓存未命中，使用预解析的数据避免重复解析
                         // Cache miss, use pre-parsed data to avoid re-parsing
                         let permit_mgr = Arc::clone(&engine.permit_manager);
@@ -496,40 +761,49 @@ async fn run_udp_worker(
                             let engine = engine.clone();
                             let socket = Arc::clone(&socket);
                             let packet_bytes = packet_bytes.clone();
-                            tokio::spawn(async move {
-                                let _permit = permit; // 自动释放 / Auto-release on drop
-
+                            // permit 随任务结束释放，tarpit 会提前归还 / Released when the task ends, or early by a tarpit
+                            tokio::spawn(with_request_permit(permit, async move {
                                 // ✅ 传递预解析数据给 handle_packet_internal，避免重复解析
                                 // ✅ Pass pre-parsed data to handle_packet_internal to avoid re-parsing
                                 match tokio::time::timeout(
                                     timeout_dur,
                                     engine.handle_packet_internal_with_pre_parsed(
                                         &packet_bytes,
-                                        peer,
+                                        client,
                                         false,
                                         qname,
                                         qtype,
                                         qclass,
                                         tx_id,
                                         edns_present,
+                                        dnssec_flags,
                                         pipeline_id,
                                     )
                                 ).await {
                                     Ok(Ok(resp)) => {
-                                        let _ = socket.send_to(&resp, peer).await;
+                                        send_udp_answer(&socket, &packet_bytes, &resp, peer).await;
                                     }
                                     Ok(Err(e)) => {
                                         debug!(error = %e, "handle_packet error");
                                     }
                                     Err(_) => {
-                                        warn!(
-                                            timeout_ms,
-                                            upstream_timeout_ms = engine.get_upstream_timeout_ms(),
-                                            "request timeout after hedge and fallback exhausted"
-                                        );
+                                        if let Some(suppressed) = kixdns::engine::log_limit::allow("request_timeout") {
+                                            warn!(
+                                                timeout_ms,
+                                                upstream_timeout_ms = engine.get_upstream_timeout_ms(),
+                                                suppressed,
+                                                "request timeout after hedge and fallback exhausted"
+                                            );
+                                        }
                                     }
                                 }
-                            });
+                            }));
+                        } else {
+                            // 超出并发上限 / Over the concurrency limit
+                            batch.record_drop();
+                            if let Some(reply) = engine.overload_reply(&packet_bytes) {
+                                let _ = socket.send_to(&reply, peer).await;
+                            }
                         }
                     }
                     Ok(None) => {
@@ -544,28 +818,45 @@ async fn run_udp_worker(
                             let engine = engine.clone();
                             let socket = Arc::clone(&socket);
                             let packet_bytes = packet_bytes.clone();
-                            tokio::spawn(async move {
-                                let _permit = permit; // 自动释放 / Auto-release on drop
-                                match tokio::time::timeout(timeout_dur, engine.handle_packet(&packet_bytes, peer)).await {
+                            // permit 随任务结束释放，tarpit 会提前归还 / Released when the task ends, or early by a tarpit
+                            tokio::spawn(with_request_permit(permit, async move {
+                                match tokio::time::timeout(timeout_dur, engine.handle_packet(&packet_bytes, client)).await {
                                     Ok(Ok(resp)) => {
-                                        let _ = socket.send_to(&resp, peer).await;
+                                        send_udp_answer(&socket, &packet_bytes, &resp, peer).await;
                                     }
                                     Ok(Err(e)) => {
                                         debug!(error = %e, "handle_packet error");
+                                        if e.downcast_ref::<MalformedQuery>().is_some()
+                                            && let Some(reply) = engine.malformed_reply(&packet_bytes, false)
+                                        {
+                                            let _ = socket.send_to(&reply, peer).await;
+                                        }
                                     }
                                     Err(_) => {
-                                        warn!(
-                                            timeout_ms,
-                                            upstream_timeout_ms = engine.get_upstream_timeout_ms(),
-                                            "request timeout"
-                                        );
+                                        if let Some(suppressed) = kixdns::engine::log_limit::allow("request_timeout") {
+                                            warn!(
+                                                timeout_ms,
+                                                upstream_timeout_ms = engine.get_upstream_timeout_ms(),
+                                                suppressed,
+                                                "request timeout"
+                                            );
+                                        }
                                     }
                                 }
-                            });
+                            }));
+                        } else {
+                            // 超出并发上限 / Over the concurrency limit
+                            batch.record_drop();
+                            if let Some(reply) = engine.overload_reply(&packet_bytes) {
+                                let _ = socket.send_to(&reply, peer).await;
+                            }
                         }
                     }
                     Err(_) => {
-                        // 解析错误，忽略 / Parse error, ignore
+                        // 解析错误，按配置回复或忽略 / Parse error, reply per settings or ignore
+                        if let Some(reply) = engine.malformed_reply(&packet_bytes, false) {
+                            let _ = socket.send_to(&reply, peer).await;
+                        }
                     }
                 }
             }
@@ -578,18 +869,62 @@ async fn run_udp_worker(
     }
 }
 
-async fn run_tcp(listener: TcpListener, engine: Engine) -> anyhow::Result<()> {
+/// TCP Fast Open 等待队列长度 / TCP Fast Open pending queue length
+const TCP_FAST_OPEN_QUEUE: u32 = 256;
+
+/// 在 TCP 监听器上启用 TFO，失败仅告警 / Enable TFO on a TCP listener, warning on failure
+fn enable_tcp_fast_open(listener: &TcpListener) {
+    let sock = socket2::SockRef::from(listener);
+    match kixdns::socket_utils::set_tcp_fastopen(&sock, TCP_FAST_OPEN_QUEUE) {
+        Ok(()) => info!("tcp fast open enabled on listener"),
+        Err(e) => warn!(error = %e, "failed to enable tcp fast open on listener"),
+    }
+}
+
+async fn run_tcp(
+    listener: TcpListener,
+    engine: Engine,
+    mut stop: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    loop {
+        // 已建立的连接不受停止影响 / Established connections are unaffected by stopping
+        let (stream, peer) = tokio::select! {
+            res = listener.accept() => res?,
+            _ = stop.changed() => return Ok(()),
+        };
+        let engine = engine.clone();
+        tokio::spawn(async move {
+            let _ = handle_tcp_conn(stream, canonical_peer(peer), engine).await;
+        });
+    }
+}
+
+async fn run_dot(listener: TcpListener, acceptor: TlsAcceptor, engine: Engine) -> anyhow::Result<()> {
     loop {
         let (stream, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
         let engine = engine.clone();
         tokio::spawn(async move {
-            let _ = handle_tcp_conn(stream, peer, engine).await;
+            // 握手在连接任务内完成，慢客户端不阻塞 accept / The handshake runs in the connection task so slow clients do not block accept
+            let stream = match tokio::time::timeout(tls_server::HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(err)) => {
+                    debug!(peer = %peer, error = %err, "dot handshake failed");
+                    return;
+                }
+                Err(_) => {
+                    debug!(peer = %peer, "dot handshake timeout");
+                    return;
+                }
+            };
+            let _ = handle_tcp_conn(stream, canonical_peer(peer), engine).await;
         });
     }
 }
 
-async fn handle_tcp_conn(
-    mut stream: TcpStream,
+/// 处理一个长度前缀帧的 DNS 连接（TCP 或 DoT）/ Serve one length-prefixed DNS connection (TCP or DoT)
+async fn handle_tcp_conn<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
     peer: SocketAddr,
     engine: Engine,
 ) -> anyhow::Result<()> {
@@ -636,17 +971,11 @@ async fn handle_tcp_conn(
                 // 快速路径命中：直接返回 / Fast path hit: return directly
                 bytes
             }
-            Ok(Some(FastPathResponse::CacheHit { cached, tx_id, inserted_at })) => {
-                // 缓存命中：patch TXID / Cache hit: patch TXID
+            Ok(Some(FastPathResponse::CacheHit { cached, tx_id })) => {
+                // 缓存命中：patch TXID（TTL 已按停留时间修正）/ Cache hit: patch TXID (TTLs are already aged)
                 let mut resp_buf = bytes::BytesMut::with_capacity(cached.len());
                 resp_buf.extend_from_slice(&cached);
 
-                // RFC 1035 §5.2: Patch TTL based on residence time / 根据停留时间修正 TTL
-                let elapsed = inserted_at.elapsed().as_secs() as u32;
-                if elapsed > 0 {
-                    kixdns::proto_utils::patch_all_ttls(&mut resp_buf, elapsed);
-                }
-
                 if resp_buf.len() >= 2 {
                     let id_bytes = tx_id.to_be_bytes();
                     resp_buf[0] = id_bytes[0];
@@ -654,7 +983,7 @@ async fn handle_tcp_conn(
                 }
                 resp_buf.freeze()
             }
-            Ok(Some(FastPathResponse::AsyncNeeded { qname, qtype, qclass, tx_id, edns_present, pipeline_id })) => {
+            Ok(Some(FastPathResponse::AsyncNeeded { qname, qtype, qclass, tx_id, edns_present, dnssec_flags, pipeline_id })) => {
                 // 缓存未命中：使用预解析数据避免重复解析
                 // Cache miss: use pre-parsed data to avoid re-parsing
                 match tokio::time::timeout(
@@ -668,17 +997,21 @@ async fn handle_tcp_conn(
                         qclass,
                         tx_id,
                         edns_present,
+                        dnssec_flags,
                         pipeline_id,
                     )
                 ).await {
                     Ok(Ok(r)) => r,
                     Ok(Err(_)) => return Ok(()),
                     Err(_) => {
-                        warn!(
-                            timeout_ms,
-                            upstream_timeout_ms = engine.get_upstream_timeout_ms(),
-                            "TCP request timeout after hedge and fallback exhausted"
-                        );
+                        if let Some(suppressed) = kixdns::engine::log_limit::allow("request_timeout") {
+                            warn!(
+                                timeout_ms,
+                                upstream_timeout_ms = engine.get_upstream_timeout_ms(),
+                                suppressed,
+                                "TCP request timeout after hedge and fallback exhausted"
+                            );
+                        }
                         return Ok(()); // 关闭连接 / Close connection
                     }
                 }
@@ -688,20 +1021,32 @@ async fn handle_tcp_conn(
                 // Fast parse failed, fallback to full processing
                 match tokio::time::timeout(timeout_dur, engine.handle_packet(&packet_bytes, peer)).await {
                     Ok(Ok(r)) => r,
+                    Ok(Err(e)) if e.downcast_ref::<MalformedQuery>().is_some() => {
+                        match engine.malformed_reply(&packet_bytes, true) {
+                            Some(reply) => reply,
+                            None => return Ok(()),
+                        }
+                    }
                     Ok(Err(_)) => return Ok(()),
                     Err(_) => {
-                        warn!(
-                            timeout_ms,
-                            upstream_timeout_ms = engine.get_upstream_timeout_ms(),
-                            "TCP request timeout"
-                        );
+                        if let Some(suppressed) = kixdns::engine::log_limit::allow("request_timeout") {
+                            warn!(
+                                timeout_ms,
+                                upstream_timeout_ms = engine.get_upstream_timeout_ms(),
+                                suppressed,
+                                "TCP request timeout"
+                            );
+                        }
                         return Ok(()); // 关闭连接 / Close connection
                     }
                 }
             }
             Err(_) => {
-                // 解析错误，关闭连接 / Parse error, close connection
-                return Ok(());
+                // 解析错误：按配置回复，否则关闭连接 / Parse error: reply per settings, otherwise close the connection
+                match engine.malformed_reply(&packet_bytes, true) {
+                    Some(reply) => reply,
+                    None => return Ok(()),
+                }
             }
         };
 
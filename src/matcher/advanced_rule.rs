@@ -3,7 +3,6 @@ use std::sync::Arc;
 
 use hickory_proto::op::ResponseCode;
 use hickory_proto::rr::{DNSClass, RecordType};
-use ipnet::IpNet;
 use regex::Regex;
 use rustc_hash::FxHashMap;
 use smallvec::SmallVec;
@@ -11,6 +10,8 @@ use smallvec::SmallVec;
 use crate::config::{Action, MatchOperator};
 use crate::engine::{make_static_ip_answer, Decision};
 use crate::matcher::eval_match_chain;
+use crate::matcher::ip_trie::IpPrefixSet;
+use crate::matcher::suffix_trie::SuffixTrie;
 use crate::matcher::{RuntimeMatcher, RuntimePipeline, RuntimePipelineConfig, RuntimeRule};
 
 #[derive(Debug, Clone)]
@@ -46,7 +47,7 @@ pub enum CompiledMatcher {
         suffix: Arc<str>,
     },
     ClientIp {
-        net: IpNet,
+        nets: Arc<IpPrefixSet>,
     },
     #[allow(dead_code)]
     QueryType {
@@ -72,7 +73,7 @@ pub enum PrecomputedAction {
 #[derive(Debug, Clone, Default)]
 pub struct RuleIndex {
     pub domain_exact: FxHashMap<Arc<str>, Vec<usize>>,
-    pub domain_suffix: FxHashMap<Arc<str>, Vec<usize>>,
+    pub domain_suffix: SuffixTrie,
     pub query_type: FxHashMap<RecordType, Vec<usize>>,
     pub always_check: Vec<usize>,
 }
@@ -90,7 +91,9 @@ impl RuleIndex {
             .all(|m| matches!(m.operator, MatchOperator::And));
 
         if !and_chain {
-            self.always_check.push(rule_idx);
+            if !self.add_domain_or_chain(rule_idx, rule) {
+                self.always_check.push(rule_idx);
+            }
             return;
         }
 
@@ -105,11 +108,8 @@ impl RuleIndex {
                     indexed = true;
                     break;
                 }
-                CompiledMatcher::DomainSuffix { suffix } if !suffix.is_empty() => {
-                    self.domain_suffix
-                        .entry(suffix.clone())
-                        .or_default()
-                        .push(rule_idx);
+                CompiledMatcher::DomainSuffix { suffix } if !suffix.trim_matches('.').is_empty() => {
+                    self.domain_suffix.insert(suffix, rule_idx);
                     indexed = true;
                     break;
                 }
@@ -127,6 +127,31 @@ impl RuleIndex {
         }
     }
 
+    /// 纯域名 OR 链：登记每个域名 / Domain-only OR chain: register every name
+    fn add_domain_or_chain(&mut self, rule_idx: usize, rule: &CompiledRule) -> bool {
+        let domain_only = rule.matchers.iter().skip(1).all(|m| m.operator == MatchOperator::Or)
+            && rule.matchers.iter().all(|m| match &m.matcher {
+                CompiledMatcher::DomainExact { domain } => !domain.is_empty(),
+                CompiledMatcher::DomainSuffix { suffix } => !suffix.trim_matches('.').is_empty(),
+                _ => false,
+            });
+        if !domain_only {
+            return false;
+        }
+        for m in &rule.matchers {
+            match &m.matcher {
+                CompiledMatcher::DomainExact { domain } => {
+                    self.domain_exact.entry(domain.clone()).or_default().push(rule_idx);
+                }
+                CompiledMatcher::DomainSuffix { suffix } => {
+                    self.domain_suffix.insert(suffix, rule_idx);
+                }
+                _ => {}
+            }
+        }
+        true
+    }
+
     /// Get candidate rule indices into provided SmallVec to avoid allocation
     /// SmallVec<[usize; 32]> keeps up to 32 candidates on stack (typical case)
     pub fn get_candidates_into(
@@ -141,17 +166,7 @@ impl RuleIndex {
             out.extend_from_slice(indices);
         }
 
-        let mut search_name = qname;
-        loop {
-            if let Some(indices) = self.domain_suffix.get(search_name) {
-                out.extend_from_slice(indices);
-            }
-            if let Some(idx) = search_name.find('.') {
-                search_name = &search_name[idx + 1..];
-            } else {
-                break;
-            }
-        }
+        self.domain_suffix.collect_into(qname, out);
 
         if let Some(indices) = self.query_type.get(&qtype) {
             out.extend_from_slice(indices);
@@ -220,8 +235,14 @@ fn compile_matcher(m: &RuntimeMatcher) -> CompiledMatcher {
         RuntimeMatcher::DomainSuffix { value } => CompiledMatcher::DomainSuffix {
             suffix: value.clone(),
         },
-        RuntimeMatcher::ClientIp { net } => CompiledMatcher::ClientIp { net: *net },
-        RuntimeMatcher::DomainRegex { regex } => CompiledMatcher::Regex {
+        RuntimeMatcher::ClientIp { nets } => CompiledMatcher::ClientIp { nets: Arc::clone(nets) },
+        RuntimeMatcher::ClientGroup { name, nets } => CompiledMatcher::Complex {
+            matcher: RuntimeMatcher::ClientGroup {
+                name: name.clone(),
+                nets: nets.clone(),
+            },
+        },
+        RuntimeMatcher::DomainRegex { regex, .. } => CompiledMatcher::Regex {
             regex: regex.clone(),
         },
         RuntimeMatcher::GeoipCountry { country_codes } => CompiledMatcher::Complex {
@@ -243,6 +264,20 @@ fn compile_matcher(m: &RuntimeMatcher) -> CompiledMatcher {
             matcher: RuntimeMatcher::GeoSiteNot { tag: tag.clone() },
         },
         RuntimeMatcher::Qtype { value } => CompiledMatcher::QueryType { qtype: *value },
+        RuntimeMatcher::DomainHeuristics { scorer } => CompiledMatcher::Complex {
+            matcher: RuntimeMatcher::DomainHeuristics {
+                scorer: scorer.clone(),
+            },
+        },
+        RuntimeMatcher::DomainSet { name, set } => CompiledMatcher::Complex {
+            matcher: RuntimeMatcher::DomainSet {
+                name: name.clone(),
+                set: set.clone(),
+            },
+        },
+        RuntimeMatcher::NxdomainBurst { burst } => CompiledMatcher::Complex {
+            matcher: RuntimeMatcher::NxdomainBurst { burst: *burst },
+        },
     }
 }
 
@@ -322,7 +357,7 @@ fn compiled_matcher_matches(
                 qname.ends_with(suffix.as_ref())
             }
         }
-        CompiledMatcher::ClientIp { net } => net.contains(&client_ip),
+        CompiledMatcher::ClientIp { nets } => nets.contains(&client_ip),
         CompiledMatcher::QueryType { qtype: rt } => *rt == qtype,
         CompiledMatcher::Qclass { qclass: cls } => *cls == qclass,
         CompiledMatcher::Regex { regex } => regex.is_match(qname),
@@ -330,8 +365,9 @@ fn compiled_matcher_matches(
             RuntimeMatcher::Any => true,
             RuntimeMatcher::DomainExact { value } => qname.eq_ignore_ascii_case(value),
             RuntimeMatcher::DomainSuffix { value } => qname.ends_with(value.as_ref()),
-            RuntimeMatcher::ClientIp { net } => net.contains(&client_ip),
-            RuntimeMatcher::DomainRegex { regex } => regex.is_match(qname),
+            RuntimeMatcher::ClientIp { nets } => nets.contains(&client_ip),
+            RuntimeMatcher::ClientGroup { nets, .. } => nets.contains(&client_ip),
+            RuntimeMatcher::DomainRegex { regex, .. } => regex.is_match(qname),
             RuntimeMatcher::GeoipCountry { country_codes: _ } => {
                 // GeoIP matching requires GeoIpManager integration
                 // For now, return false (will be implemented in Engine integration)
@@ -355,6 +391,9 @@ fn compiled_matcher_matches(
                 false
             }
             RuntimeMatcher::Qtype { value } => *value == qtype,
+            RuntimeMatcher::DomainHeuristics { scorer } => scorer.matches(qname),
+            RuntimeMatcher::DomainSet { set, .. } => set.contains(qname),
+            RuntimeMatcher::NxdomainBurst { burst } => burst.matches(client_ip),
         },
     }
 }
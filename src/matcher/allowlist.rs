@@ -0,0 +1,130 @@
+//! 全局放行名单 / Global allowlist
+//!
+//! 在任何拦截类动作（Deny、StaticResponse、返回 0.0.0.0/:: 的 StaticIpResponse）生效前检查，
+//! 命中放行名单的域名会跳过该拦截规则，继续匹配后续规则或走默认上游。
+//! 这样误拦的域名只需加入放行名单，无需逐个排查是哪个拦截列表包含了它。
+//! Checked before any block action (Deny, StaticResponse, StaticIpResponse returning
+//! 0.0.0.0/::) takes effect: an allowlisted name skips the blocking rule and continues
+//! with later rules or the default upstream, so a false positive can be whitelisted
+//! without hunting down which blocklist contains it.
+//!
+//! 条目格式 / Entry format:
+//! - `example.com`：匹配该域名及所有子域名 / matches the domain and all subdomains
+//! - `full:example.com`：仅完全匹配 / exact match only
+//! - `domain:example.com`：同第一种 / same as the plain form
+//! - 文件中 `#` 之后为注释，空行忽略 / in files, `#` starts a comment and blank lines are ignored
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use rustc_hash::FxHashSet;
+
+/// 编译后的放行名单 / Compiled allowlist
+#[derive(Debug, Clone, Default)]
+pub struct DomainAllowlist {
+    exact: FxHashSet<Arc<str>>,
+    suffix: FxHashSet<Arc<str>>,
+}
+
+/// 命名域名集合（sets）与放行名单使用相同的条目格式与匹配方式
+/// Named domain sets (sets) share the allowlist's entry format and matching
+pub type DomainSet = DomainAllowlist;
+
+impl DomainAllowlist {
+    /// 从内联条目和文件构建 / Build from inline entries and files
+    pub fn load(entries: &[String], files: &[String]) -> anyhow::Result<Self> {
+        let mut list = Self::default();
+        for entry in entries {
+            list.insert(entry);
+        }
+        for path in files {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("read allowlist file {}", path))?;
+            for line in content.lines() {
+                let line = line.split('#').next().unwrap_or("");
+                list.insert(line);
+            }
+        }
+        Ok(list)
+    }
+
+    fn insert(&mut self, entry: &str) {
+        let entry = entry.trim();
+        let (exact, domain) = if let Some(d) = entry.strip_prefix("full:") {
+            (true, d)
+        } else if let Some(d) = entry.strip_prefix("domain:") {
+            (false, d)
+        } else {
+            (false, entry)
+        };
+        let domain = domain.trim().trim_matches('.').to_ascii_lowercase();
+        if domain.is_empty() {
+            return;
+        }
+        if exact {
+            self.exact.insert(Arc::from(domain));
+        } else {
+            self.suffix.insert(Arc::from(domain));
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty() && self.suffix.is_empty()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.exact.len() + self.suffix.len()
+    }
+
+    /// 域名是否在放行名单中（qname 需为小写，可带结尾点）
+    /// Whether the name is allowlisted (qname must be lowercase, trailing dot allowed)
+    pub fn contains(&self, qname: &str) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let qname = qname.trim_end_matches('.');
+        if self.exact.contains(qname) {
+            return true;
+        }
+        let mut search = qname;
+        loop {
+            if self.suffix.contains(search) {
+                return true;
+            }
+            match search.find('.') {
+                Some(idx) => search = &search[idx + 1..],
+                None => return false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowlist_suffix_and_exact() {
+        // Arrange
+        let list = DomainAllowlist::load(
+            &[
+                "Example.com".to_string(),
+                "full:only.test.net".to_string(),
+                "  ".to_string(),
+            ],
+            &[],
+        )
+        .unwrap();
+
+        // Act & Assert
+        assert_eq!(list.len(), 2);
+        assert!(list.contains("example.com"));
+        assert!(list.contains("cdn.example.com."));
+        assert!(!list.contains("badexample.com"));
+        assert!(list.contains("only.test.net"));
+        assert!(!list.contains("sub.only.test.net"));
+        assert!(!list.contains("test.net"));
+    }
+}
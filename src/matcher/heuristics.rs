@@ -0,0 +1,267 @@
+//! DGA / DNS 隧道启发式评分 / DGA / DNS-tunneling heuristic scoring
+//!
+//! 基于以下特征为查询域名打分（0.0 ~ 1.0）：
+//! Scores a query name (0.0 ~ 1.0) based on:
+//! - 字符熵 / Shannon entropy of the subject labels
+//! - 最长标签长度 / Longest label length
+//! - 数字比例 / Digit ratio
+//! - 同一后缀下唯一子域名的查询速率 / Query rate of unique subdomains under the same suffix
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use rustc_hash::{FxHashSet, FxHasher};
+use std::hash::{Hash, Hasher};
+
+/// 熵归一化基准（bits/char），超过此值视为满分 / Entropy normalization ceiling (bits/char)
+const ENTROPY_CEILING: f64 = 4.0;
+/// 标签长度归一化基准 / Label length normalization ceiling
+const LABEL_LEN_CEILING: f64 = 50.0;
+/// 数字比例归一化基准 / Digit ratio normalization ceiling
+const DIGIT_RATIO_CEILING: f64 = 0.5;
+/// 速率跟踪的最大后缀数量 / Maximum number of tracked suffixes for rate tracking
+const MAX_TRACKED_SUFFIXES: u64 = 50_000;
+
+/// 单个后缀的唯一子域名窗口 / Unique-subdomain window for a single suffix
+#[derive(Debug)]
+struct SubdomainWindow {
+    started_at: Instant,
+    seen: FxHashSet<u64>,
+}
+
+/// DGA / 隧道启发式评分器 / DGA / tunneling heuristic scorer
+pub struct DomainHeuristics {
+    /// 匹配阈值 / Match threshold
+    pub threshold: f64,
+    /// 限定的父域后缀（小写，无前导点）/ Restricting parent suffix (lowercase, no leading dot)
+    pub suffix: Option<Arc<str>>,
+    /// 窗口内唯一子域名上限（0 表示不计算速率分量）/ Unique-subdomain limit per window (0 disables the rate component)
+    pub max_unique_subdomains: u32,
+    /// 速率窗口 / Rate window
+    pub window: Duration,
+    windows: moka::sync::Cache<Arc<str>, Arc<Mutex<SubdomainWindow>>>,
+}
+
+impl std::fmt::Debug for DomainHeuristics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DomainHeuristics")
+            .field("threshold", &self.threshold)
+            .field("suffix", &self.suffix)
+            .field("max_unique_subdomains", &self.max_unique_subdomains)
+            .field("window", &self.window)
+            .finish()
+    }
+}
+
+impl DomainHeuristics {
+    pub fn new(
+        threshold: f64,
+        suffix: Option<String>,
+        max_unique_subdomains: u32,
+        window_secs: u64,
+    ) -> anyhow::Result<Self> {
+        if !(0.0..=1.0).contains(&threshold) {
+            anyhow::bail!("domain_heuristics threshold must be within 0.0..=1.0, got {}", threshold);
+        }
+        if window_secs == 0 {
+            anyhow::bail!("domain_heuristics window_secs must be greater than 0");
+        }
+        let suffix = suffix
+            .map(|s| s.trim_matches('.').to_ascii_lowercase())
+            .filter(|s| !s.is_empty())
+            .map(Arc::from);
+        let window = Duration::from_secs(window_secs);
+        let windows = moka::sync::Cache::builder()
+            .max_capacity(MAX_TRACKED_SUFFIXES)
+            .time_to_idle(window * 2)
+            .build();
+        Ok(Self {
+            threshold,
+            suffix,
+            max_unique_subdomains,
+            window,
+            windows,
+        })
+    }
+
+    /// 是否达到阈值 / Whether the score reaches the threshold
+    #[inline]
+    pub fn matches(&self, qname: &str) -> bool {
+        self.score(qname) >= self.threshold
+    }
+
+    /// 计算域名得分（同时记录速率窗口）/ Compute the score (also records into the rate window)
+    pub fn score(&self, qname: &str) -> f64 {
+        let qname = qname.trim_end_matches('.');
+        let Some((subject, parent)) = self.split_subject(qname) else {
+            return 0.0;
+        };
+        if subject.is_empty() {
+            return 0.0;
+        }
+
+        let lexical = lexical_score(subject);
+        if self.max_unique_subdomains == 0 {
+            return lexical;
+        }
+
+        let unique = self.observe(parent, subject);
+        let rate = (unique as f64 / self.max_unique_subdomains as f64).min(1.0);
+        // 词法特征占 80%，速率占 20% / Lexical features weigh 80%, rate 20%
+        lexical * 0.8 + rate * 0.2
+    }
+
+    /// 拆分出被评分的主体部分与父域 / Split into the scored subject and its parent domain
+    ///
+    /// 配置了 suffix 时，仅对该后缀下的名称评分；否则以去掉最后一个标签（TLD）的部分为主体，
+    /// 最后两个标签为父域。
+    /// With a configured suffix only names under it are scored; otherwise the subject is
+    /// the name without its last label (TLD) and the parent is the last two labels.
+    fn split_subject<'a>(&self, qname: &'a str) -> Option<(&'a str, &'a str)> {
+        match &self.suffix {
+            Some(suffix) => {
+                let suffix = suffix.as_ref();
+                if qname.len() <= suffix.len() || !qname.ends_with(suffix) {
+                    return None;
+                }
+                let cut = qname.len() - suffix.len();
+                if qname.as_bytes()[cut - 1] != b'.' {
+                    return None;
+                }
+                Some((&qname[..cut - 1], &qname[cut..]))
+            }
+            None => {
+                let tld_dot = qname.rfind('.')?;
+                let subject = &qname[..tld_dot];
+                let parent = match subject.rfind('.') {
+                    Some(idx) => &qname[idx + 1..],
+                    None => qname,
+                };
+                Some((subject, parent))
+            }
+        }
+    }
+
+    /// 记录子域名并返回当前窗口内的唯一数量 / Record a subdomain and return the unique count in the current window
+    fn observe(&self, parent: &str, subject: &str) -> usize {
+        let key: Arc<str> = Arc::from(parent);
+        let entry = self.windows.get_with(key, || {
+            Arc::new(Mutex::new(SubdomainWindow {
+                started_at: Instant::now(),
+                seen: FxHashSet::default(),
+            }))
+        });
+
+        let mut hasher = FxHasher::default();
+        subject.hash(&mut hasher);
+        let h = hasher.finish();
+
+        let mut w = entry.lock();
+        if w.started_at.elapsed() >= self.window {
+            w.started_at = Instant::now();
+            w.seen.clear();
+        }
+        // 达到上限后不再增长，限制内存 / Stop growing once the limit is reached to bound memory
+        if w.seen.len() < self.max_unique_subdomains as usize {
+            w.seen.insert(h);
+        }
+        w.seen.len()
+    }
+}
+
+/// 纯词法得分：熵 50%、最长标签长度 25%、数字比例 25%
+/// Pure lexical score: entropy 50%, longest label length 25%, digit ratio 25%
+pub fn lexical_score(subject: &str) -> f64 {
+    let mut counts = [0u32; 256];
+    let mut total = 0u32;
+    let mut digits = 0u32;
+    let mut longest = 0usize;
+
+    for label in subject.split('.') {
+        longest = longest.max(label.len());
+        for b in label.bytes() {
+            counts[b.to_ascii_lowercase() as usize] += 1;
+            total += 1;
+            if b.is_ascii_digit() {
+                digits += 1;
+            }
+        }
+    }
+
+    if total == 0 {
+        return 0.0;
+    }
+
+    let n = total as f64;
+    let entropy = counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / n;
+            -p * p.log2()
+        })
+        .sum::<f64>();
+
+    let entropy_score = (entropy / ENTROPY_CEILING).min(1.0);
+    let length_score = (longest as f64 / LABEL_LEN_CEILING).min(1.0);
+    let digit_score = ((digits as f64 / n) / DIGIT_RATIO_CEILING).min(1.0);
+
+    entropy_score * 0.5 + length_score * 0.25 + digit_score * 0.25
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lexical_score_common_names_are_low() {
+        // Arrange & Act
+        let www = lexical_score("www.google");
+        let mail = lexical_score("mail");
+
+        // Assert
+        assert!(www < 0.6, "www.google scored {}", www);
+        assert!(mail < 0.6, "mail scored {}", mail);
+    }
+
+    #[test]
+    fn test_lexical_score_tunnel_like_names_are_high() {
+        // Arrange
+        let subject = "4a7f9c2e81b3d05f6e9a1c7b3d2f8e0a4c6b9d1e3f5a7c9b2d4e6f8a0c1b3d5.x1";
+
+        // Act
+        let score = lexical_score(subject);
+
+        // Assert
+        assert!(score >= 0.8, "tunnel-like name scored {}", score);
+    }
+
+    #[test]
+    fn test_suffix_restricts_scoring() {
+        // Arrange
+        let h = DomainHeuristics::new(0.5, Some(".t.example.com".to_string()), 0, 60).unwrap();
+
+        // Act & Assert
+        assert_eq!(h.score("q8z7x6c5v4b3n2m1.other.com"), 0.0);
+        assert_eq!(h.score("t.example.com"), 0.0);
+        assert_eq!(h.score("xt.example.com"), 0.0);
+        assert!(h.score("q8z7x6c5v4b3n2m1a9s8d7f6.t.example.com") > 0.5);
+    }
+
+    #[test]
+    fn test_unique_subdomain_rate_raises_score() {
+        // Arrange
+        let h = DomainHeuristics::new(0.5, None, 4, 60).unwrap();
+        let first = h.score("a1.example.com");
+
+        // Act
+        for i in 2..10 {
+            h.score(&format!("a{}.example.com", i));
+        }
+        let later = h.score("a1.example.com");
+
+        // Assert
+        assert!(later > first, "rate component not applied: {} <= {}", later, first);
+    }
+}
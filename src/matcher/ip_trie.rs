@@ -0,0 +1,184 @@
+//! IP 前缀字典树 / IP prefix trie
+//!
+//! 以二进制字典树存储 CIDR 列表，查询时沿地址位走一遍即可判断是否被任一前缀覆盖，
+//! 耗时只与地址位数相关（IPv4 至多 32 步，IPv6 至多 128 步），与 CIDR 数量无关，
+//! 因此整份国家 IP 列表也不会拖慢每个查询。
+//! Stores a CIDR list in a binary trie. A lookup walks the address bits once to decide
+//! whether any prefix covers it, so its cost depends on the address width only (at most
+//! 32 steps for IPv4, 128 for IPv6) and not on the number of CIDRs, which keeps full
+//! country IP lists from slowing every query down.
+
+use std::net::IpAddr;
+
+use anyhow::Context;
+use ipnet::IpNet;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Node {
+    /// 子节点下标，0 表示不存在（0 号为根）/ Child indices, 0 means none (0 is the root)
+    children: [u32; 2],
+    /// 某个前缀在此结束 / A prefix ends here
+    terminal: bool,
+}
+
+#[derive(Debug, Clone)]
+struct BitTrie {
+    nodes: Vec<Node>,
+}
+
+impl Default for BitTrie {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node::default()],
+        }
+    }
+}
+
+impl BitTrie {
+    /// 插入前缀，bits 左对齐到 128 位；已被更短前缀覆盖时返回 false
+    /// Insert a prefix with bits left-aligned to 128; returns false when a shorter prefix already covers it
+    fn insert(&mut self, bits: u128, prefix_len: u8) -> bool {
+        let mut node = 0usize;
+        for i in 0..prefix_len {
+            if self.nodes[node].terminal {
+                return false;
+            }
+            let bit = ((bits >> (127 - i)) & 1) as usize;
+            node = match self.nodes[node].children[bit] {
+                0 => {
+                    let child = self.nodes.len();
+                    self.nodes.push(Node::default());
+                    self.nodes[node].children[bit] = child as u32;
+                    child
+                }
+                child => child as usize,
+            };
+        }
+        let node = &mut self.nodes[node];
+        if node.terminal {
+            return false;
+        }
+        // 更长的前缀已被覆盖，剪掉子树 / Longer prefixes are now covered, prune the subtree
+        node.terminal = true;
+        node.children = [0, 0];
+        true
+    }
+
+    fn contains(&self, bits: u128, width: u8) -> bool {
+        let mut node = &self.nodes[0];
+        for i in 0..width {
+            if node.terminal {
+                return true;
+            }
+            let bit = ((bits >> (127 - i)) & 1) as usize;
+            match node.children[bit] {
+                0 => return false,
+                child => node = &self.nodes[child as usize],
+            }
+        }
+        node.terminal
+    }
+}
+
+/// CIDR 集合 / A set of CIDRs
+#[derive(Debug, Clone, Default)]
+pub struct IpPrefixSet {
+    v4: BitTrie,
+    v6: BitTrie,
+    len: usize,
+}
+
+impl IpPrefixSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 登记的前缀数（插入时已被覆盖的不计）/ Number of registered prefixes (ones already covered on insert are not counted)
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn insert(&mut self, net: IpNet) {
+        let added = match net.trunc() {
+            IpNet::V4(n) => self
+                .v4
+                .insert((u32::from(n.addr()) as u128) << 96, n.prefix_len()),
+            IpNet::V6(n) => self.v6.insert(u128::from(n.addr()), n.prefix_len()),
+        };
+        if added {
+            self.len += 1;
+        }
+    }
+
+    /// 从 CIDR 列表文件加载（每行一个 CIDR 或 IP，`#` 之后为注释）
+    /// Load from a CIDR list file (one CIDR or IP per line, `#` starts a comment)
+    pub fn load_file(path: &str) -> anyhow::Result<Self> {
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("read CIDR file {}", path))?;
+        let mut set = Self::new();
+        for (lineno, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let net = crate::config::parse_client_net(line)
+                .with_context(|| format!("{}:{}", path, lineno + 1))?;
+            set.insert(net);
+        }
+        Ok(set)
+    }
+
+    /// 地址是否落在任一前缀内 / Whether the address falls in any prefix
+    #[inline]
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(v4) => self.v4.contains((u32::from(*v4) as u128) << 96, 32),
+            IpAddr::V6(v6) => self.v6.contains(u128::from(*v6), 128),
+        }
+    }
+}
+
+impl FromIterator<IpNet> for IpPrefixSet {
+    fn from_iter<I: IntoIterator<Item = IpNet>>(iter: I) -> Self {
+        let mut set = Self::new();
+        for net in iter {
+            set.insert(net);
+        }
+        set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contains_matches_linear_cidr_scan() {
+        // Arrange
+        let nets: Vec<IpNet> = ["10.0.0.0/8", "10.1.0.0/16", "192.168.1.7/32", "2001:db8::/32", "0.0.0.0/0"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let narrow: IpPrefixSet = nets[..4].iter().copied().collect();
+        let everything: IpPrefixSet = nets.iter().copied().collect();
+
+        // Act
+        let check = |set: &IpPrefixSet, ip: &str| set.contains(&ip.parse().unwrap());
+
+        // Assert
+        assert_eq!(narrow.len(), 3);
+        assert!(check(&narrow, "10.200.3.4"));
+        assert!(check(&narrow, "192.168.1.7"));
+        assert!(!check(&narrow, "192.168.1.8"));
+        assert!(check(&narrow, "2001:db8::1"));
+        assert!(!check(&narrow, "2001:db9::1"));
+        assert!(check(&everything, "8.8.8.8"));
+        assert!(!check(&everything, "::1"));
+    }
+}
@@ -1,13 +1,20 @@
 pub mod advanced_rule;
+pub mod allowlist;
 pub mod geoip;
 pub mod geoip_converter;
 pub mod geosite;
+pub mod heuristics;
+pub mod ip_trie;
+pub mod nxdomain_burst;
+pub mod regex_set;
+pub mod suffix_trie;
 
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
 use anyhow::Context;
 use hickory_proto::op::Message;
+use hickory_proto::rr::rdata::svcb::{SvcParamKey, SvcParamValue};
 use hickory_proto::rr::{DNSClass, RecordType};
 use ipnet::IpNet;
 use regex::{Regex, RegexBuilder};
@@ -26,6 +33,8 @@ pub enum TxtMatchMode {
     Exact,
     /// 前缀匹配 / Prefix match
     Prefix,
+    /// 包含子串 / Substring match
+    Contains,
     /// 正则匹配 / Regex match
     Regex,
 }
@@ -36,15 +45,55 @@ impl TxtMatchMode {
         match s.to_lowercase().as_str() {
             "exact" => Ok(TxtMatchMode::Exact),
             "prefix" => Ok(TxtMatchMode::Prefix),
+            "contains" => Ok(TxtMatchMode::Contains),
             "regex" => Ok(TxtMatchMode::Regex),
             _ => anyhow::bail!(
-                "invalid TXT match mode: {}, must be one of: exact, prefix, regex",
+                "invalid TXT match mode: {}, must be one of: exact, prefix, contains, regex",
                 s
             ),
         }
     }
 }
 
+/// SVCB/HTTPS 参数值的匹配条件 / Condition on the value of an SVCB/HTTPS parameter
+#[derive(Debug, Clone)]
+pub enum SvcParamMatch {
+    /// 参数存在即可 / The parameter only has to be present
+    Present,
+    /// alpn 含该协议标识 / alpn contains the protocol id
+    Alpn(Arc<str>),
+    /// 任一地址提示落在网段内 / Any address hint falls in the networks
+    Hint(Arc<ip_trie::IpPrefixSet>),
+    /// 端口相同 / Same port
+    Port(u16),
+}
+
+impl SvcParamMatch {
+    fn parse(key: SvcParamKey, value: &str) -> anyhow::Result<Self> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(Self::Present);
+        }
+        Ok(match key {
+            SvcParamKey::Alpn => Self::Alpn(Arc::from(value)),
+            SvcParamKey::Ipv4Hint | SvcParamKey::Ipv6Hint => Self::Hint(parse_cidr_list(value)?),
+            SvcParamKey::Port => Self::Port(value.parse().with_context(|| format!("invalid port: {}", value))?),
+            other => anyhow::bail!("svc param {} only supports presence matching", other),
+        })
+    }
+
+    fn matches(&self, value: &SvcParamValue) -> bool {
+        match (self, value) {
+            (Self::Present, _) => true,
+            (Self::Alpn(id), SvcParamValue::Alpn(alpn)) => alpn.0.iter().any(|p| p == id.as_ref()),
+            (Self::Hint(nets), SvcParamValue::Ipv4Hint(hint)) => hint.0.iter().any(|a| nets.contains(&IpAddr::V4(a.0))),
+            (Self::Hint(nets), SvcParamValue::Ipv6Hint(hint)) => hint.0.iter().any(|a| nets.contains(&IpAddr::V6(a.0))),
+            (Self::Port(port), SvcParamValue::Port(p)) => p == port,
+            _ => false,
+        }
+    }
+}
+
 // ============================================================================
 // Matcher Helper Functions / 匹配器辅助函数
 // ============================================================================
@@ -117,18 +166,18 @@ mod matcher_helpers {
     ///
     /// # 参数 / Parameters
     /// - `msg`: DNS 响应消息 / DNS response message
-    /// - `nets`: CIDR 网络列表 / CIDR network list
+    /// - `nets`: CIDR 前缀集合 / CIDR prefix set
     ///
     /// # 返回 / Returns
     /// - `true`: 至少有一个 IP 匹配 / At least one IP matches
     /// - `false`: 没有IP匹配 / No IP matches
-    pub fn any_ip_matches_nets(msg: &Message, nets: &[IpNet]) -> bool {
+    pub fn any_ip_matches_nets(msg: &Message, nets: &ip_trie::IpPrefixSet) -> bool {
         use hickory_proto::rr::RData;
 
         // 先检查 Answer / Check Answer first
         let found = msg.answers().iter().any(|record| match record.data() {
-            Some(RData::A(a)) => nets.iter().any(|net| net.contains(&IpAddr::V4(a.0))),
-            Some(RData::AAAA(aaaa)) => nets.iter().any(|net| net.contains(&IpAddr::V6(aaaa.0))),
+            Some(RData::A(a)) => nets.contains(&IpAddr::V4(a.0)),
+            Some(RData::AAAA(aaaa)) => nets.contains(&IpAddr::V6(aaaa.0)),
             _ => false,
         });
 
@@ -138,8 +187,8 @@ mod matcher_helpers {
 
         // 再检查 Additionals / Check Additionals
         msg.additionals().iter().any(|record| match record.data() {
-            Some(RData::A(a)) => nets.iter().any(|net| net.contains(&IpAddr::V4(a.0))),
-            Some(RData::AAAA(aaaa)) => nets.iter().any(|net| net.contains(&IpAddr::V6(aaaa.0))),
+            Some(RData::A(a)) => nets.contains(&IpAddr::V4(a.0)),
+            Some(RData::AAAA(aaaa)) => nets.contains(&IpAddr::V6(aaaa.0)),
             _ => false,
         })
     }
@@ -154,23 +203,50 @@ pub struct RuntimePipelineConfig {
     pub settings: config::GlobalSettings,
     pub pipeline_select: Vec<RuntimePipelineSelectRule>,
     pub pipelines: Vec<RuntimePipeline>,
+    /// 全局放行名单 / Global allowlist
+    pub allowlist: Arc<allowlist::DomainAllowlist>,
+    /// 是否有规则使用 nxdomain_burst，决定是否按客户端记录 NXDOMAIN
+    /// Whether any rule uses nxdomain_burst, deciding if NXDOMAINs are recorded per client
+    pub tracks_nxdomain: bool,
+    /// 按查询类型的预取策略 / Per-qtype prefetch policies
+    pub prefetch_qtypes: FxHashMap<RecordType, crate::engine::prefetch::PrefetchPolicy>,
+    /// 配置内的本地记录 / Local records declared in the configuration
+    pub local_records: Arc<crate::engine::local_records::LocalRecords>,
 }
 
 #[derive(Debug, Clone)]
 pub struct RuntimePipeline {
     pub id: Arc<str>,
     pub rules: Vec<RuntimeRule>,
+    /// 覆盖全局 max_ttl / Overrides the global max_ttl
+    pub max_ttl: Option<u32>,
+    /// 覆盖全局 response_jump_limit / Overrides the global response_jump_limit
+    pub response_jump_limit: Option<u32>,
+    /// 覆盖全局 edns_options / Overrides the global edns_options
+    pub edns_options: Option<crate::config::EdnsOptionPolicy>,
+    /// 覆盖全局 suppress_aaaa_without_ipv6 / Overrides the global suppress_aaaa_without_ipv6
+    pub suppress_aaaa_without_ipv6: Option<bool>,
+    /// 覆盖全局 zero_ttl / Overrides the global zero_ttl
+    pub zero_ttl: Option<crate::config::ZeroTtlPolicy>,
     /// 是否包含依赖客户端 IP 的匹配规则 / Whether it contains rules that match based on client IP
     pub uses_client_ip: bool,
+    /// 规则决策能否写入规则缓存（决策随时间变化的匹配器会关闭缓存）
+    /// Whether rule decisions may go into the rule cache (matchers whose result changes over time disable it)
+    pub rule_cacheable: bool,
+    /// 查询日志策略 / Query log policy
+    pub query_log: Arc<crate::engine::rule_log::QueryLogFilter>,
     // Indices for O(1) lookup
     // 完全域名匹配索引（最高优先级）/ Exact domain match index (highest priority)
     pub domain_exact_index: FxHashMap<Arc<str>, Vec<usize>>,
-    // Maps domain suffix -> list of rule indices that MUST be checked
-    pub domain_suffix_index: FxHashMap<Arc<str>, Vec<usize>>,
+    // Maps domain suffix -> list of rule indices that MUST be checked (reversed-label trie)
+    // 域名后缀 -> 必须检查的规则下标（按标签反向的字典树）
+    pub domain_suffix_index: suffix_trie::SuffixTrie,
     // Maps query type -> list of rule indices (高频过滤条件 / High-frequency filter)
     pub query_type_index: FxHashMap<RecordType, Vec<usize>>,
     // Rules that are NOT indexed by domain (must always be checked)
     pub always_check_rules: Vec<usize>,
+    /// 所有 domain_regex 匹配器合并成的正则集合 / All domain_regex matchers batched into one regex set
+    pub regex_set: Option<Arc<regex_set::DomainRegexSet>>,
 }
 
 #[derive(Debug, Clone)]
@@ -199,8 +275,11 @@ pub enum RuntimeMatcher {
     Any,
     DomainExact { value: Arc<str> },
     DomainSuffix { value: Arc<str> },
-    ClientIp { net: IpNet },
-    DomainRegex { regex: Regex },
+    ClientIp { nets: Arc<ip_trie::IpPrefixSet> },
+    /// 客户端分组（已编译为前缀字典树）/ Client group (compiled to a prefix trie)
+    ClientGroup { name: Arc<str>, nets: Arc<ip_trie::IpPrefixSet> },
+    /// slot 为其在 pipeline 正则集合中的编号 / slot is its index in the pipeline regex set
+    DomainRegex { regex: Regex, slot: Option<usize> },
     GeoipCountry { country_codes: Vec<Arc<str>> },
     GeoipPrivate { expect: bool },
     Qclass { value: DNSClass },
@@ -208,6 +287,12 @@ pub enum RuntimeMatcher {
     GeoSite { tag: Arc<str> },
     GeoSiteNot { tag: Arc<str> },
     Qtype { value: RecordType },
+    /// DGA / 隧道启发式评分 / DGA / tunneling heuristic score
+    DomainHeuristics { scorer: Arc<heuristics::DomainHeuristics> },
+    /// 命名域名集合（多个规则共享同一份）/ Named domain set (shared by all referencing rules)
+    DomainSet { name: Arc<str>, set: Arc<allowlist::DomainSet> },
+    /// 客户端 NXDOMAIN 突发 / Per-client NXDOMAIN burst
+    NxdomainBurst { burst: nxdomain_burst::NxdomainBurst },
 }
 
 #[derive(Debug, Clone)]
@@ -219,7 +304,9 @@ pub struct RuntimeMatcherWithOp {
 #[derive(Debug, Clone)]
 pub enum RuntimePipelineSelectorMatcher {
     ListenerLabel { value: Arc<str> },
-    ClientIp { net: IpNet },
+    Transport { value: config::ListenerTransport },
+    ClientIp { nets: Arc<ip_trie::IpPrefixSet> },
+    ClientGroup { name: Arc<str>, nets: Arc<ip_trie::IpPrefixSet> },
     DomainSuffix { value: Arc<str> },
     DomainRegex { regex: Regex },
     Any,
@@ -230,6 +317,7 @@ pub enum RuntimePipelineSelectorMatcher {
     GeoipCountry { country_codes: Vec<Arc<str>> },
     GeoipPrivate { expect: bool },
     Qtype { value: RecordType },
+    DomainSet { name: Arc<str>, set: Arc<allowlist::DomainSet> },
 }
 
 #[derive(Debug, Clone)]
@@ -250,11 +338,11 @@ pub enum RuntimeResponseMatcher {
         regex: Regex,
     },
     ResponseUpstreamIp {
-        nets: Vec<IpNet>,
+        nets: Arc<ip_trie::IpPrefixSet>,
     },
     /// 匹配 Answer 中任意 A/AAAA 记录的 IP / Match IPs of any A/AAAA records in the Answer
     ResponseAnswerIp {
-        nets: Vec<IpNet>,
+        nets: Arc<ip_trie::IpPrefixSet>,
     },
     ResponseType {
         value: Arc<str>,
@@ -290,6 +378,19 @@ pub enum RuntimeResponseMatcher {
         value: Arc<str>,
         regex: Option<Regex>,
     },
+    /// 匹配 Authority 段 NS 记录的名称服务器后缀 / Match nameserver suffixes of NS records in the Authority section
+    ResponseAuthorityNsSuffix {
+        value: Arc<str>,
+    },
+    /// Authority 段是否携带 SOA / Whether the Authority section carries an SOA
+    ResponseAuthoritySoa {
+        expect: bool,
+    },
+    /// Answer 中 SVCB/HTTPS 记录的参数 / A parameter of SVCB/HTTPS records in the Answer
+    ResponseSvcParam {
+        key: SvcParamKey,
+        value: SvcParamMatch,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -321,6 +422,21 @@ impl RuntimePipelineConfig {
             .validate_timeouts()
             .context("validate timeout configuration")?;
 
+        if let Some(iface) = &cfg.settings.mdns_interface {
+            iface
+                .parse::<std::net::Ipv4Addr>()
+                .with_context(|| format!("invalid mdns_interface: {}", iface))?;
+        }
+
+        let errors = crate::engine::validation::cross_reference_errors(&cfg);
+        if !errors.is_empty() {
+            anyhow::bail!("config has {} error(s):\n  {}", errors.len(), errors.join("\n  "));
+        }
+
+        let client_groups = compile_client_groups(&cfg.client_groups)?;
+        let domain_sets = compile_domain_sets(&cfg.sets)?;
+        let cidr_files = compile_cidr_files(&cfg.cidr_files())?;
+
         let mut pipelines = Vec::new();
         for p in cfg.pipelines {
             let mut rules = Vec::new();
@@ -333,7 +449,7 @@ impl RuntimePipelineConfig {
                     }
                     matchers.push(RuntimeMatcherWithOp {
                         operator: m.operator,
-                        matcher: RuntimeMatcher::from_config(m.matcher)?,
+                        matcher: RuntimeMatcher::from_config(m.matcher, &client_groups, &domain_sets)?,
                     });
                 }
                 if matchers_all_default
@@ -353,7 +469,7 @@ impl RuntimePipelineConfig {
                     }
                     response_matchers.push(RuntimeResponseMatcherWithOp {
                         operator: rm.operator,
-                        matcher: RuntimeResponseMatcher::from_config(rm.matcher)?,
+                        matcher: RuntimeResponseMatcher::from_config(rm.matcher, &cidr_files)?,
                     });
                 }
                 if resp_all_default
@@ -364,6 +480,11 @@ impl RuntimePipelineConfig {
                         rm.operator = r.response_matcher_operator;
                     }
                 }
+                // 禁用的规则在上面已完成校验，此处丢弃 / Disabled rules were validated above and are dropped here
+                if !r.enabled {
+                    tracing::debug!(pipeline = %p.id, rule = %r.name, "rule disabled");
+                    continue;
+                }
                 rules.push(RuntimeRule {
                     name: Arc::from(r.name),
                     matcher_operator: r.matcher_operator,
@@ -376,9 +497,11 @@ impl RuntimePipelineConfig {
                 });
             }
 
+            let regex_set = build_regex_set(&p.id, &mut rules);
+
             // Build Indices - 优化：索引所有可索引的匹配器，而不只是第一个
             // Build Indices - Optimized: index all indexable matchers, not just the first one
-            let mut domain_suffix_index: FxHashMap<Arc<str>, Vec<usize>> = FxHashMap::default();
+            let mut domain_suffix_index = suffix_trie::SuffixTrie::new();
             let mut domain_exact_index: FxHashMap<Arc<str>, Vec<usize>> = FxHashMap::default();
             let mut query_type_index: FxHashMap<RecordType, Vec<usize>> = FxHashMap::default();
             let mut always_check_rules = Vec::new();
@@ -386,9 +509,14 @@ impl RuntimePipelineConfig {
             for (idx, rule) in rules.iter().enumerate() {
                 let mut indexed = false;
 
-                // 性能优化：只在 AND 链中索引，OR 链放入 always_check
-                // Performance optimization: only index in AND chains, put OR chains in always_check
-                if rule.matcher_operator == MatchOperator::And {
+                // 性能优化：只在 AND 链与纯域名的 OR 链中索引，其它放入 always_check
+                // Performance optimization: only index AND chains and domain-only OR chains, others go to always_check
+                let and_chain = rule
+                    .matchers
+                    .iter()
+                    .skip(1)
+                    .all(|m| m.operator == MatchOperator::And);
+                if rule.matcher_operator == MatchOperator::And && and_chain {
                     // 索引所有可索引的匹配器 / Index all indexable matchers
                     for m in &rule.matchers {
                         match &m.matcher {
@@ -400,19 +528,22 @@ impl RuntimePipelineConfig {
                                 indexed = true;
                             }
                             RuntimeMatcher::DomainSuffix { value } => {
-                                domain_suffix_index
-                                    .entry(value.clone())
-                                    .or_default()
-                                    .push(idx);
-                                indexed = true;
+                                // 空后缀匹配所有域名，不能索引 / An empty suffix matches every name and cannot be indexed
+                                if domain_suffix_index.insert(value, idx) {
+                                    indexed = true;
+                                }
                             }
                             RuntimeMatcher::DomainRegex { .. }
                             | RuntimeMatcher::ClientIp { .. }
+                            | RuntimeMatcher::ClientGroup { .. }
                             | RuntimeMatcher::GeoipCountry { .. }
                             | RuntimeMatcher::GeoipPrivate { .. }
                             | RuntimeMatcher::GeoSite { .. }
                             | RuntimeMatcher::GeoSiteNot { .. }
-                            | RuntimeMatcher::EdnsPresent { .. } => {
+                            | RuntimeMatcher::EdnsPresent { .. }
+                            | RuntimeMatcher::DomainHeuristics { .. }
+                            | RuntimeMatcher::DomainSet { .. }
+                            | RuntimeMatcher::NxdomainBurst { .. } => {
                                 // 这些匹配器无法基于域名/类型索引，跳过
                                 // These matchers cannot be indexed by domain/type, skip
                             }
@@ -430,6 +561,21 @@ impl RuntimePipelineConfig {
                             }
                         }
                     }
+                } else if is_domain_or_chain(&rule.matchers) {
+                    // 每个域名都是独立的命中条件，全部登记即可覆盖整条 OR 链
+                    // Each name is an independent way to match, so registering all of them covers the OR chain
+                    for m in &rule.matchers {
+                        match &m.matcher {
+                            RuntimeMatcher::DomainExact { value } => {
+                                domain_exact_index.entry(value.clone()).or_default().push(idx);
+                            }
+                            RuntimeMatcher::DomainSuffix { value } => {
+                                domain_suffix_index.insert(value, idx);
+                            }
+                            _ => {}
+                        }
+                    }
+                    indexed = true;
                 }
 
                 if !indexed {
@@ -447,7 +593,12 @@ impl RuntimePipelineConfig {
             let mut pipeline_uses_geosite = false;
             for r in &rules {
                 for m in &r.matchers {
-                    if matches!(m.matcher, RuntimeMatcher::ClientIp { .. }) {
+                    if matches!(
+                        m.matcher,
+                        RuntimeMatcher::ClientIp { .. }
+                            | RuntimeMatcher::ClientGroup { .. }
+                            | RuntimeMatcher::NxdomainBurst { .. }
+                    ) {
                         pipeline_uses_client_ip = true;
                         break;
                     }
@@ -469,14 +620,47 @@ impl RuntimePipelineConfig {
                 }
             }
 
+            // nxdomain_burst 的结果随客户端近期的 NXDOMAIN 计数变化；reselect_pipeline 的结果取决于全部
+            // pipeline_select 条件而不只是缓存键，两者的决策都不能缓存
+            // nxdomain_burst depends on the client's recent NXDOMAIN count and reselect_pipeline on every
+            // pipeline_select condition rather than just the cache key, so neither decision can be cached
+            let pipeline_rule_cacheable = !rules.iter().any(|r| {
+                r.matchers
+                    .iter()
+                    .any(|m| matches!(m.matcher, RuntimeMatcher::NxdomainBurst { .. }))
+                    || r.actions.iter().any(|a| matches!(a, Action::ReselectPipeline { .. }))
+            });
+
+            if !p.enabled {
+                tracing::debug!(pipeline = %p.id, "pipeline disabled");
+                continue;
+            }
+            let query_log = crate::engine::rule_log::QueryLogFilter::new(
+                allowlist::DomainSet::load(&p.query_log.exclude_domains, &[])
+                    .with_context(|| format!("pipeline {} query_log.exclude_domains", p.id))?,
+                p.query_log
+                    .redact_client_groups
+                    .iter()
+                    .map(|name| lookup_client_group(&client_groups, name))
+                    .collect::<anyhow::Result<_>>()?,
+                p.query_log.blocked_only,
+            );
             pipelines.push(RuntimePipeline {
                 id: Arc::from(p.id),
                 rules,
+                max_ttl: p.max_ttl,
+                response_jump_limit: p.response_jump_limit,
+                edns_options: p.edns_options,
+                suppress_aaaa_without_ipv6: p.suppress_aaaa_without_ipv6,
+                zero_ttl: p.zero_ttl,
                 uses_client_ip: pipeline_uses_client_ip,
+                rule_cacheable: pipeline_rule_cacheable,
+                query_log: Arc::new(query_log),
                 domain_exact_index, // 添加完全匹配索引 / Add exact match index
                 domain_suffix_index,
                 query_type_index, // 添加 query_type 索引 / Add query_type index
                 always_check_rules,
+                regex_set,
             });
         }
 
@@ -490,7 +674,7 @@ impl RuntimePipelineConfig {
                 }
                 matchers.push(RuntimePipelineSelectorMatcherWithOp {
                     operator: m.operator,
-                    matcher: RuntimePipelineSelectorMatcher::from_config(m.matcher)?,
+                    matcher: RuntimePipelineSelectorMatcher::from_config(m.matcher, &client_groups, &domain_sets)?,
                 });
             }
             if all_default && !matchers.is_empty() && s.matcher_operator != MatchOperator::And {
@@ -531,7 +715,7 @@ impl RuntimePipelineConfig {
                 for m in rule.matchers {
                     matchers.push(RuntimeMatcherWithOp {
                         operator: m.operator,
-                        matcher: RuntimeMatcher::from_config(m.matcher)?,
+                        matcher: RuntimeMatcher::from_config(m.matcher, &client_groups, &domain_sets)?,
                     });
                 }
 
@@ -539,7 +723,7 @@ impl RuntimePipelineConfig {
                 for rm in rule.response_matchers {
                     response_matchers.push(RuntimeResponseMatcherWithOp {
                         operator: rm.operator,
-                        matcher: RuntimeResponseMatcher::from_config(rm.matcher)?,
+                        matcher: RuntimeResponseMatcher::from_config(rm.matcher, &cidr_files)?,
                     });
                 }
 
@@ -557,10 +741,43 @@ impl RuntimePipelineConfig {
             None => None, // 未配置，将在 Engine::new 中使用默认规则
         };
 
+        let allowlist = allowlist::DomainAllowlist::load(
+            &cfg.settings.allowlist,
+            &cfg.settings.allowlist_files,
+        )
+        .context("load allowlist")?;
+
+        let tracks_nxdomain = pipelines.iter().flat_map(|p| &p.rules).any(|r| {
+            r.matchers
+                .iter()
+                .any(|m| matches!(m.matcher, RuntimeMatcher::NxdomainBurst { .. }))
+        });
+
+        let mut prefetch_qtypes = FxHashMap::default();
+        for (qtype, policy) in &cfg.settings.prefetch_qtypes {
+            let context = || format!("settings.prefetch_qtypes.{}", qtype);
+            let with = policy
+                .with
+                .iter()
+                .map(|t| parse_dns_type(t))
+                .collect::<anyhow::Result<_>>()
+                .with_context(context)?;
+            prefetch_qtypes.insert(
+                parse_dns_type(qtype).with_context(context)?,
+                crate::engine::prefetch::PrefetchPolicy { mode: policy.mode, min_ttl: policy.min_ttl, with },
+            );
+        }
+
+        let local_records = crate::engine::local_records::LocalRecords::compile(&cfg.settings.local_records, cfg.settings.local_records_rotate)?;
+
         Ok(Self {
             settings: cfg.settings,
             pipeline_select,
             pipelines,
+            allowlist: Arc::new(allowlist),
+            tracks_nxdomain,
+            prefetch_qtypes,
+            local_records: Arc::new(local_records),
             // background_refresh_rule,  // ✅ 暂时注释，等待 RuntimePipelineConfig 结构更新
         })
     }
@@ -573,6 +790,59 @@ impl RuntimePipelineConfig {
         std::time::Duration::from_millis(self.settings.upstream_timeout_ms)
     }
 
+    /// pipeline 生效的 TTL 上限（0 表示不限制）/ Effective TTL cap for a pipeline (0 = no cap)
+    pub fn max_ttl_for(&self, pipeline_id: &str) -> u32 {
+        self.pipelines
+            .iter()
+            .find(|p| p.id.as_ref() == pipeline_id)
+            .and_then(|p| p.max_ttl)
+            .unwrap_or(self.settings.max_ttl)
+    }
+
+    /// 该 pipeline 的规则决策能否缓存 / Whether the pipeline's rule decisions may be cached
+    pub fn rule_cacheable_for(&self, pipeline_id: &str) -> bool {
+        self.pipelines
+            .iter()
+            .find(|p| p.id.as_ref() == pipeline_id)
+            .is_none_or(|p| p.rule_cacheable)
+    }
+
+    /// 该 pipeline 是否在 IPv6 不通时抑制 AAAA / Whether the pipeline suppresses AAAA while IPv6 is unreachable
+    pub fn suppress_aaaa_for(&self, pipeline_id: &str) -> bool {
+        self.pipelines
+            .iter()
+            .find(|p| p.id.as_ref() == pipeline_id)
+            .and_then(|p| p.suppress_aaaa_without_ipv6)
+            .unwrap_or(self.settings.suppress_aaaa_without_ipv6)
+    }
+
+    /// pipeline 生效的 0 TTL 应答缓存策略 / Effective caching policy for TTL 0 answers of a pipeline
+    pub fn zero_ttl_for(&self, pipeline_id: &str) -> crate::config::ZeroTtlPolicy {
+        self.pipelines
+            .iter()
+            .find(|p| p.id.as_ref() == pipeline_id)
+            .and_then(|p| p.zero_ttl)
+            .unwrap_or(self.settings.zero_ttl)
+    }
+
+    /// 从该 pipeline 开始的跳转链的跳转上限 / Jump limit for jump chains starting in a pipeline
+    pub fn response_jump_limit_for(&self, pipeline_id: &str) -> usize {
+        self.pipelines
+            .iter()
+            .find(|p| p.id.as_ref() == pipeline_id)
+            .and_then(|p| p.response_jump_limit)
+            .unwrap_or(self.settings.response_jump_limit) as usize
+    }
+
+    /// pipeline 生效的 EDNS 选项策略 / Effective EDNS option policy for a pipeline
+    pub fn edns_options_for(&self, pipeline_id: &str) -> &crate::config::EdnsOptionPolicy {
+        self.pipelines
+            .iter()
+            .find(|p| p.id.as_ref() == pipeline_id)
+            .and_then(|p| p.edns_options.as_ref())
+            .unwrap_or(&self.settings.edns_options)
+    }
+
     /// Collect all unique TCP upstreams from the configuration for warmup.
     /// 收集配置中所有唯一的 TCP upstream 用于预热。
     ///
@@ -626,6 +896,76 @@ impl RuntimePipelineConfig {
             }
         }
 
+        // 命名空间内的上游不经过连接池 / Upstreams inside a namespace bypass the pools
+        upstreams.retain(|addr| crate::engine::netns::split(addr).is_none());
+        upstreams
+    }
+
+    /// 收集配置中所有转发上游（含 default_upstream）用于健康检查。
+    /// Collect every forward upstream in the configuration (default_upstream included) for health checks.
+    ///
+    /// 返回 (传输协议, 去除前缀的地址)，与转发时跳过不健康上游使用的键一致。
+    /// Returns (transport, address without prefix), matching the keys used to skip unhealthy upstreams when forwarding.
+    pub fn collect_forward_upstreams(&self) -> std::collections::HashSet<(crate::config::Transport, String)> {
+        use crate::config::Transport;
+        let mut upstreams = std::collections::HashSet::new();
+        let mut add = |list: &str, default_transport: Transport| {
+            for addr in list.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+                let (addr, transport) = crate::engine::upstream::parse_upstream_addr(addr, default_transport);
+                upstreams.insert((transport, addr.to_string()));
+            }
+        };
+
+        add(&self.settings.default_upstream, Transport::Udp);
+        for pipeline in &self.pipelines {
+            for rule in &pipeline.rules {
+                let actions = rule
+                    .actions
+                    .iter()
+                    .chain(&rule.response_actions_on_match)
+                    .chain(&rule.response_actions_on_miss);
+                for action in actions {
+                    if let crate::config::Action::Forward { upstream: Some(u), transport, .. } = action {
+                        add(u, transport.unwrap_or(Transport::Udp));
+                    }
+                }
+            }
+        }
+
+        upstreams
+    }
+
+    /// 收集配置中所有加密上游（DoT/DoH/DoQ）用于预热。
+    /// Collect all encrypted upstreams (DoT/DoH/DoQ) from the configuration for prewarming.
+    ///
+    /// 返回 (传输协议, 去除前缀的地址)，与转发时连接池使用的键一致。
+    /// Returns (transport, address without prefix), matching the pool keys used when forwarding.
+    pub fn collect_encrypted_upstreams(&self) -> std::collections::HashSet<(crate::config::Transport, String)> {
+        use crate::config::Transport;
+        let mut upstreams = std::collections::HashSet::new();
+
+        for pipeline in &self.pipelines {
+            for rule in &pipeline.rules {
+                let actions = rule
+                    .actions
+                    .iter()
+                    .chain(&rule.response_actions_on_match)
+                    .chain(&rule.response_actions_on_miss);
+                for action in actions {
+                    if let crate::config::Action::Forward { upstream: Some(u), transport, .. } = action {
+                        let default_transport = transport.unwrap_or(Transport::Udp);
+                        for addr in u.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+                            let (addr, transport) =
+                                crate::engine::upstream::parse_upstream_addr(addr, default_transport);
+                            if matches!(transport, Transport::Dot | Transport::Doh | Transport::Doq | Transport::Dnscrypt | Transport::Odoh) {
+                                upstreams.insert((transport, addr.to_string()));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
         upstreams
     }
 }
@@ -650,16 +990,146 @@ fn normalize_upstream_addr(addr: &str) -> String {
     }
 }
 
+/// 把 pipeline 内的 domain_regex 合并为一个正则集合，并为各匹配器分配编号
+/// Batch the pipeline's domain_regex matchers into one regex set and assign each matcher its slot
+fn build_regex_set(
+    pipeline: &str,
+    rules: &mut [RuntimeRule],
+) -> Option<Arc<regex_set::DomainRegexSet>> {
+    let patterns: Vec<String> = rules
+        .iter()
+        .flat_map(|r| &r.matchers)
+        .filter_map(|m| match &m.matcher {
+            RuntimeMatcher::DomainRegex { regex, .. } => Some(regex.as_str().to_string()),
+            _ => None,
+        })
+        .collect();
+    let refs: Vec<&str> = patterns.iter().map(String::as_str).collect();
+    let set = regex_set::DomainRegexSet::build(pipeline, &refs)?;
+    let mut next = 0;
+    for m in rules.iter_mut().flat_map(|r| r.matchers.iter_mut()) {
+        if let RuntimeMatcher::DomainRegex { slot, .. } = &mut m.matcher {
+            *slot = Some(next);
+            next += 1;
+        }
+    }
+    Some(Arc::new(set))
+}
+
+/// 是否为仅由域名匹配器组成的 OR 链（空后缀除外）
+/// Whether the matchers form an OR chain made of domain matchers only (empty suffixes excluded)
+fn is_domain_or_chain(matchers: &[RuntimeMatcherWithOp]) -> bool {
+    !matchers.is_empty()
+        && matchers.iter().skip(1).all(|m| m.operator == MatchOperator::Or)
+        && matchers.iter().all(|m| match &m.matcher {
+            RuntimeMatcher::DomainExact { .. } => true,
+            RuntimeMatcher::DomainSuffix { value } => !value.trim_matches('.').is_empty(),
+            _ => false,
+        })
+}
+
+/// 编译后的客户端分组 / Compiled client groups
+pub type ClientGroups = FxHashMap<String, Arc<ip_trie::IpPrefixSet>>;
+
+/// 解析 client_groups 配置 / Parse the client_groups section
+pub fn compile_client_groups(
+    groups: &std::collections::HashMap<String, Vec<String>>,
+) -> anyhow::Result<ClientGroups> {
+    let mut out = ClientGroups::default();
+    for (name, entries) in groups {
+        let nets = entries
+            .iter()
+            .map(|e| config::parse_client_net(e))
+            .collect::<anyhow::Result<ip_trie::IpPrefixSet>>()
+            .with_context(|| format!("client_groups.{}", name))?;
+        out.insert(name.clone(), Arc::new(nets));
+    }
+    Ok(out)
+}
+
+fn lookup_client_group(
+    groups: &ClientGroups,
+    name: &str,
+) -> anyhow::Result<Arc<ip_trie::IpPrefixSet>> {
+    groups
+        .get(name)
+        .cloned()
+        .with_context(|| format!("unknown client group: {}", name))
+}
+
+/// 把逗号分隔的 CIDR 列表编译为前缀字典树 / Compile a comma-separated CIDR list into a prefix trie
+fn parse_cidr_list(cidr: &str) -> anyhow::Result<Arc<ip_trie::IpPrefixSet>> {
+    let mut nets = ip_trie::IpPrefixSet::new();
+    for part in cidr.split(',') {
+        let s = part.trim();
+        if s.is_empty() {
+            continue;
+        }
+        nets.insert(s.parse::<IpNet>()?);
+    }
+    Ok(Arc::new(nets))
+}
+
+/// 已加载的 CIDR 列表文件：路径 -> 前缀字典树 / Loaded CIDR list files: path -> prefix trie
+pub type CidrFiles = FxHashMap<String, Arc<ip_trie::IpPrefixSet>>;
+
+/// 加载规则引用的 CIDR 列表文件，每个文件只加载一次
+/// Load the CIDR list files referenced by rules, each file once
+pub fn compile_cidr_files(files: &[String]) -> anyhow::Result<CidrFiles> {
+    let mut out = CidrFiles::default();
+    for path in files {
+        let nets = ip_trie::IpPrefixSet::load_file(path)?;
+        tracing::info!(file = %path, prefixes = nets.len(), "CIDR file loaded");
+        out.insert(path.clone(), Arc::new(nets));
+    }
+    Ok(out)
+}
+
+fn lookup_cidr_file(files: &CidrFiles, path: &str) -> anyhow::Result<Arc<ip_trie::IpPrefixSet>> {
+    files
+        .get(path)
+        .cloned()
+        .with_context(|| format!("CIDR file not loaded: {}", path))
+}
+
+/// 编译后的命名域名集合 / Compiled named domain sets
+pub type DomainSets = FxHashMap<String, Arc<allowlist::DomainSet>>;
+
+/// 编译 sets 配置，每个集合只加载一次 / Compile the sets section, loading each set once
+pub fn compile_domain_sets(
+    sets: &std::collections::HashMap<String, config::DomainSetConfig>,
+) -> anyhow::Result<DomainSets> {
+    let mut out = DomainSets::default();
+    for (name, set) in sets {
+        let compiled = allowlist::DomainSet::load(&set.domains, &set.files)
+            .with_context(|| format!("sets.{}", name))?;
+        tracing::info!(set = %name, entries = compiled.len(), "domain set loaded");
+        out.insert(name.clone(), Arc::new(compiled));
+    }
+    Ok(out)
+}
+
+fn lookup_domain_set(sets: &DomainSets, name: &str) -> anyhow::Result<Arc<allowlist::DomainSet>> {
+    sets.get(name)
+        .cloned()
+        .with_context(|| format!("unknown domain set: {}", name))
+}
+
 impl RuntimeMatcher {
-    fn from_config(m: config::Matcher) -> anyhow::Result<Self> {
+    fn from_config(m: config::Matcher, groups: &ClientGroups, sets: &DomainSets) -> anyhow::Result<Self> {
         Ok(match m {
             config::Matcher::Any => RuntimeMatcher::Any,
             config::Matcher::DomainSuffix { value } => RuntimeMatcher::DomainSuffix {
                 value: Arc::from(value.to_ascii_lowercase()),
             },
-            config::Matcher::ClientIp { cidr } => RuntimeMatcher::ClientIp { net: cidr.parse()? },
+            config::Matcher::ClientIp { cidr } => RuntimeMatcher::ClientIp { nets: parse_cidr_list(&cidr)? },
+            config::Matcher::ClientGroup { name } => RuntimeMatcher::ClientGroup {
+                nets: lookup_client_group(groups, &name)?,
+                name: Arc::from(name),
+            },
             config::Matcher::DomainRegex { value } => RuntimeMatcher::DomainRegex {
                 regex: Regex::new(&value)?,
+                slot: None,
             },
             config::Matcher::GeoipCountry { country_codes } => RuntimeMatcher::GeoipCountry {
                 country_codes: country_codes.into_iter().map(Arc::from).collect(),
@@ -676,6 +1146,29 @@ impl RuntimeMatcher {
             config::Matcher::Qtype { value } => RuntimeMatcher::Qtype {
                 value: parse_dns_type(&value)?,
             },
+            config::Matcher::DomainHeuristics {
+                threshold,
+                suffix,
+                max_unique_subdomains,
+                window_secs,
+            } => RuntimeMatcher::DomainHeuristics {
+                scorer: Arc::new(heuristics::DomainHeuristics::new(
+                    threshold,
+                    suffix,
+                    max_unique_subdomains,
+                    window_secs,
+                )?),
+            },
+            config::Matcher::DomainSetRef { name } => RuntimeMatcher::DomainSet {
+                set: lookup_domain_set(sets, &name)?,
+                name: Arc::from(name),
+            },
+            config::Matcher::NxdomainBurst {
+                threshold,
+                window_secs,
+            } => RuntimeMatcher::NxdomainBurst {
+                burst: nxdomain_burst::NxdomainBurst::new(threshold, window_secs)?,
+            },
         })
     }
 
@@ -707,8 +1200,9 @@ impl RuntimeMatcher {
                 qname.eq_ignore_ascii_case(value)
             }
             RuntimeMatcher::DomainSuffix { value } => qname.ends_with(value.as_ref()),
-            RuntimeMatcher::ClientIp { net } => net.contains(&client_ip),
-            RuntimeMatcher::DomainRegex { regex } => regex.is_match(qname),
+            RuntimeMatcher::ClientIp { nets } => nets.contains(&client_ip),
+            RuntimeMatcher::ClientGroup { nets, .. } => nets.contains(&client_ip),
+            RuntimeMatcher::DomainRegex { regex, .. } => regex.is_match(qname),
             RuntimeMatcher::GeoipCountry { country_codes } => {
                 // 按需获取锁：只在GeoIP matcher时才获取 / On-demand lock: only acquire for GeoIP matcher
                 geoip_manager.and_then(|mgr| Some(mgr.read())).is_some_and(|guard| {
@@ -744,6 +1238,9 @@ impl RuntimeMatcher {
                 }
             }
             RuntimeMatcher::Qtype { .. } => false, // Qtype matching requires qtype parameter
+            RuntimeMatcher::DomainHeuristics { scorer } => scorer.matches(qname),
+            RuntimeMatcher::DomainSet { set, .. } => set.contains(qname),
+            RuntimeMatcher::NxdomainBurst { burst } => burst.matches(client_ip),
         }
     }
 
@@ -765,8 +1262,9 @@ impl RuntimeMatcher {
                 qname.eq_ignore_ascii_case(value)
             }
             RuntimeMatcher::DomainSuffix { value } => qname.ends_with(value.as_ref()),
-            RuntimeMatcher::ClientIp { net } => net.contains(&client_ip),
-            RuntimeMatcher::DomainRegex { regex } => regex.is_match(qname),
+            RuntimeMatcher::ClientIp { nets } => nets.contains(&client_ip),
+            RuntimeMatcher::ClientGroup { nets, .. } => nets.contains(&client_ip),
+            RuntimeMatcher::DomainRegex { regex, .. } => regex.is_match(qname),
             RuntimeMatcher::GeoipCountry { country_codes } => {
                 // 按需获取锁：只在GeoIP matcher时才获取 / On-demand lock: only acquire for GeoIP matcher
                 geoip_manager.and_then(|mgr| Some(mgr.read())).is_some_and(|guard| {
@@ -802,18 +1300,32 @@ impl RuntimeMatcher {
                 }
             }
             RuntimeMatcher::Qtype { value } => *value == qtype,
+            RuntimeMatcher::DomainHeuristics { scorer } => scorer.matches(qname),
+            RuntimeMatcher::DomainSet { set, .. } => set.contains(qname),
+            RuntimeMatcher::NxdomainBurst { burst } => burst.matches(client_ip),
         }
     }
 }
 
 impl RuntimePipelineSelectorMatcher {
-    fn from_config(m: config::PipelineSelectorMatcher) -> anyhow::Result<Self> {
+    fn from_config(
+        m: config::PipelineSelectorMatcher,
+        groups: &ClientGroups,
+        sets: &DomainSets,
+    ) -> anyhow::Result<Self> {
         Ok(match m {
             config::PipelineSelectorMatcher::ListenerLabel { value } => {
                 RuntimePipelineSelectorMatcher::ListenerLabel { value: Arc::from(value) }
             }
+            config::PipelineSelectorMatcher::Transport { value } => RuntimePipelineSelectorMatcher::Transport { value },
             config::PipelineSelectorMatcher::ClientIp { cidr } => {
-                RuntimePipelineSelectorMatcher::ClientIp { net: cidr.parse()? }
+                RuntimePipelineSelectorMatcher::ClientIp { nets: parse_cidr_list(&cidr)? }
+            }
+            config::PipelineSelectorMatcher::ClientGroup { name } => {
+                RuntimePipelineSelectorMatcher::ClientGroup {
+                    nets: lookup_client_group(groups, &name)?,
+                    name: Arc::from(name),
+                }
             }
             config::PipelineSelectorMatcher::DomainSuffix { value } => {
                 RuntimePipelineSelectorMatcher::DomainSuffix {
@@ -851,6 +1363,12 @@ impl RuntimePipelineSelectorMatcher {
                     value: parse_dns_type(&value)?,
                 }
             }
+            config::PipelineSelectorMatcher::DomainSetRef { name } => {
+                RuntimePipelineSelectorMatcher::DomainSet {
+                    set: lookup_domain_set(sets, &name)?,
+                    name: Arc::from(name),
+                }
+            }
         })
     }
 
@@ -858,6 +1376,7 @@ impl RuntimePipelineSelectorMatcher {
     pub fn matches(
         &self,
         listener_label: &str,
+        transport: config::ListenerTransport,
         client_ip: IpAddr,
         qname: &str,
         qclass: DNSClass,
@@ -867,6 +1386,7 @@ impl RuntimePipelineSelectorMatcher {
     ) -> bool {
         self.matches_with_qtype(
             listener_label,
+            transport,
             client_ip,
             qname,
             qclass,
@@ -881,6 +1401,7 @@ impl RuntimePipelineSelectorMatcher {
     pub fn matches_with_ready_managers(
         &self,
         listener_label: &str,
+        transport: config::ListenerTransport,
         client_ip: IpAddr,
         qname: &str,
         qclass: DNSClass,
@@ -893,7 +1414,9 @@ impl RuntimePipelineSelectorMatcher {
             RuntimePipelineSelectorMatcher::ListenerLabel { value } => {
                 value.eq_ignore_ascii_case(listener_label)
             }
-            RuntimePipelineSelectorMatcher::ClientIp { net } => net.contains(&client_ip),
+            RuntimePipelineSelectorMatcher::Transport { value } => *value == transport,
+            RuntimePipelineSelectorMatcher::ClientIp { nets } => nets.contains(&client_ip),
+            RuntimePipelineSelectorMatcher::ClientGroup { nets, .. } => nets.contains(&client_ip),
             RuntimePipelineSelectorMatcher::DomainSuffix { value } => qname.ends_with(value.as_ref()),
             RuntimePipelineSelectorMatcher::DomainRegex { regex } => regex.is_match(qname),
             RuntimePipelineSelectorMatcher::Any => true,
@@ -931,6 +1454,7 @@ impl RuntimePipelineSelectorMatcher {
                 }
             }
             RuntimePipelineSelectorMatcher::Qtype { value } => *value == qtype,
+            RuntimePipelineSelectorMatcher::DomainSet { set, .. } => set.contains(qname),
         }
     }
 
@@ -938,6 +1462,7 @@ impl RuntimePipelineSelectorMatcher {
     pub fn matches_with_qtype(
         &self,
         listener_label: &str,
+        transport: config::ListenerTransport,
         client_ip: IpAddr,
         qname: &str,
         qclass: DNSClass,
@@ -950,7 +1475,9 @@ impl RuntimePipelineSelectorMatcher {
             RuntimePipelineSelectorMatcher::ListenerLabel { value } => {
                 value.eq_ignore_ascii_case(listener_label)
             }
-            RuntimePipelineSelectorMatcher::ClientIp { net } => net.contains(&client_ip),
+            RuntimePipelineSelectorMatcher::Transport { value } => *value == transport,
+            RuntimePipelineSelectorMatcher::ClientIp { nets } => nets.contains(&client_ip),
+            RuntimePipelineSelectorMatcher::ClientGroup { nets, .. } => nets.contains(&client_ip),
             RuntimePipelineSelectorMatcher::DomainSuffix { value } => qname.ends_with(value.as_ref()),
             RuntimePipelineSelectorMatcher::DomainRegex { regex } => regex.is_match(qname),
             RuntimePipelineSelectorMatcher::Any => true,
@@ -994,6 +1521,7 @@ impl RuntimePipelineSelectorMatcher {
                 }
             }
             RuntimePipelineSelectorMatcher::Qtype { value } => *value == qtype,
+            RuntimePipelineSelectorMatcher::DomainSet { set, .. } => set.contains(qname),
         }
     }
 }
@@ -1071,7 +1599,7 @@ fn try_parse_upstream_ip(upstream: &str) -> Option<IpAddr> {
 }
 
 impl RuntimeResponseMatcher {
-    pub fn from_config(m: config::ResponseMatcher) -> anyhow::Result<Self> {
+    pub fn from_config(m: config::ResponseMatcher, cidr_files: &CidrFiles) -> anyhow::Result<Self> {
         Ok(match m {
             config::ResponseMatcher::UpstreamEquals { value } => {
                 RuntimeResponseMatcher::UpstreamEquals { value: Arc::from(value) }
@@ -1087,25 +1615,21 @@ impl RuntimeResponseMatcher {
                 }
             }
             config::ResponseMatcher::ResponseUpstreamIp { cidr } => {
-                let mut nets = Vec::new();
-                for part in cidr.split(',') {
-                    let s = part.trim();
-                    if s.is_empty() {
-                        continue;
-                    }
-                    nets.push(s.parse()?);
-                }
-                RuntimeResponseMatcher::ResponseUpstreamIp { nets }
-            }
-            config::ResponseMatcher::ResponseAnswerIp { cidr } => {
-                let mut nets = Vec::new();
-                for part in cidr.split(',') {
-                    let s = part.trim();
-                    if s.is_empty() {
-                        continue;
+                RuntimeResponseMatcher::ResponseUpstreamIp { nets: parse_cidr_list(&cidr)? }
+            }
+            config::ResponseMatcher::ResponseAnswerIp { cidr, file } => {
+                let nets = match file {
+                    // 仅引用文件时直接共享 / Share the file's trie when it is the only source
+                    Some(file) if cidr.trim().is_empty() => lookup_cidr_file(cidr_files, &file)?,
+                    Some(file) => {
+                        let mut nets = (*lookup_cidr_file(cidr_files, &file)?).clone();
+                        for part in cidr.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                            nets.insert(part.parse()?);
+                        }
+                        Arc::new(nets)
                     }
-                    nets.push(s.parse()?);
-                }
+                    None => parse_cidr_list(&cidr)?,
+                };
                 RuntimeResponseMatcher::ResponseAnswerIp { nets }
             }
             config::ResponseMatcher::ResponseType { value } => {
@@ -1165,6 +1689,24 @@ impl RuntimeResponseMatcher {
                 };
                 RuntimeResponseMatcher::ResponseTxtContent { mode, value: Arc::from(value), regex }
             }
+            config::ResponseMatcher::ResponseAuthorityNsSuffix { value } => {
+                RuntimeResponseMatcher::ResponseAuthorityNsSuffix {
+                    value: Arc::from(value.trim_end_matches('.').to_ascii_lowercase()),
+                }
+            }
+            config::ResponseMatcher::ResponseAuthoritySoa { expect } => {
+                RuntimeResponseMatcher::ResponseAuthoritySoa { expect }
+            }
+            config::ResponseMatcher::ResponseSvcParam { key, value } => {
+                // RFC 9460 称 key5 为 ech / RFC 9460 names key5 ech
+                let key = match key.to_ascii_lowercase().as_str() {
+                    "ech" => SvcParamKey::EchConfig,
+                    name => name
+                        .parse::<SvcParamKey>()
+                        .map_err(|e| anyhow::anyhow!("invalid svc param key {}: {}", key, e))?,
+                };
+                RuntimeResponseMatcher::ResponseSvcParam { key, value: SvcParamMatch::parse(key, &value)? }
+            }
         })
     }
 
@@ -1183,7 +1725,7 @@ impl RuntimeResponseMatcher {
             RuntimeResponseMatcher::RequestDomainSuffix { value } => qname.ends_with(value.as_ref()),
             RuntimeResponseMatcher::RequestDomainRegex { regex } => regex.is_match(qname),
             RuntimeResponseMatcher::ResponseUpstreamIp { nets } => try_parse_upstream_ip(upstream)
-                .map(|ip| nets.iter().any(|net| net.contains(&ip)))
+                .map(|ip| nets.contains(&ip))
                 .unwrap_or(false),
             RuntimeResponseMatcher::ResponseAnswerIp { nets } => {
                 // 使用辅助函数检查是否有任意 IP 匹配 CIDR / Use helper to check if any IP matches CIDR
@@ -1320,6 +1862,7 @@ impl RuntimeResponseMatcher {
                 match mode {
                     TxtMatchMode::Exact => txt_str == value.as_ref(),
                     TxtMatchMode::Prefix => txt_str.starts_with(value.as_ref()),
+                    TxtMatchMode::Contains => txt_str.contains(value.as_ref()),
                     TxtMatchMode::Regex => {
                         if let Some(re) = regex {
                             re.is_match(&txt_str)
@@ -1329,6 +1872,31 @@ impl RuntimeResponseMatcher {
                     }
                 }
             }
+            RuntimeResponseMatcher::ResponseAuthorityNsSuffix { value } => {
+                use hickory_proto::rr::RData;
+                msg.name_servers().iter().any(|record| match record.data() {
+                    Some(RData::NS(ns)) => {
+                        let name = ns.0.to_ascii();
+                        name.trim_end_matches('.').to_ascii_lowercase().ends_with(value.as_ref())
+                    }
+                    _ => false,
+                })
+            }
+            RuntimeResponseMatcher::ResponseAuthoritySoa { expect } => {
+                let has_soa = msg.name_servers().iter().any(|record| record.record_type() == RecordType::SOA);
+                has_soa == *expect
+            }
+            RuntimeResponseMatcher::ResponseSvcParam { key, value } => {
+                use hickory_proto::rr::RData;
+                msg.answers().iter().any(|record| {
+                    let svcb = match record.data() {
+                        Some(RData::SVCB(svcb)) => svcb,
+                        Some(RData::HTTPS(https)) => &https.0,
+                        _ => return false,
+                    };
+                    svcb.svc_params().iter().any(|(k, v)| k == key && value.matches(v))
+                })
+            }
         }
     }
 }
@@ -1357,7 +1925,15 @@ fn parse_dns_type(v: &str) -> anyhow::Result<RecordType> {
         "SOA" => RecordType::SOA,
         "SRV" => RecordType::SRV,
         "OPT" => RecordType::OPT,
-        _ => anyhow::bail!("unsupported qtype: {upper}"),
+        "SVCB" => RecordType::SVCB,
+        "HTTPS" => RecordType::HTTPS,
+        "CAA" => RecordType::CAA,
+        "ANY" => RecordType::ANY,
+        // RFC 3597 通用写法，如 TYPE65 / RFC 3597 generic form, e.g. TYPE65
+        other => match other.strip_prefix("TYPE").and_then(|n| n.parse::<u16>().ok()) {
+            Some(code) => RecordType::from(code),
+            None => anyhow::bail!("unsupported qtype: {upper}"),
+        },
     };
     Ok(parsed)
 }
@@ -0,0 +1,151 @@
+//! 按客户端统计的 NXDOMAIN 突发 / Per-client NXDOMAIN bursts
+//!
+//! 记录每个客户端最近收到的 NXDOMAIN 响应数（1 秒一格、最多 60 格的环形计数），
+//! nxdomain_burst 匹配器在窗口内计数达到阈值时命中，用于识别随机子域名攻击或配置错误的设备。
+//! 计数跨配置重载保留。
+//! Records how many NXDOMAIN responses each client received recently (a ring of one-second
+//! buckets, up to 60). The nxdomain_burst matcher fires once the count within its window
+//! reaches the threshold, catching random-subdomain attacks or misconfigured devices.
+//! Counts survive config reloads.
+
+use std::net::IpAddr;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// 环形计数的格数，即最大窗口秒数 / Number of ring buckets, i.e. the maximum window in seconds
+pub const MAX_WINDOW_SECS: u64 = 60;
+/// 跟踪的最大客户端数量 / Maximum number of tracked clients
+const MAX_TRACKED_CLIENTS: u64 = 100_000;
+
+const BUCKETS: usize = MAX_WINDOW_SECS as usize;
+
+/// 单个客户端的环形计数 / Ring counter for a single client
+#[derive(Debug)]
+struct Ring {
+    /// 每格对应的秒数 / Second each bucket belongs to
+    stamps: [u64; BUCKETS],
+    counts: [u32; BUCKETS],
+}
+
+impl Default for Ring {
+    fn default() -> Self {
+        Self {
+            stamps: [0; BUCKETS],
+            counts: [0; BUCKETS],
+        }
+    }
+}
+
+impl Ring {
+    fn add(&mut self, sec: u64) {
+        let slot = (sec % MAX_WINDOW_SECS) as usize;
+        if self.stamps[slot] != sec {
+            self.stamps[slot] = sec;
+            self.counts[slot] = 0;
+        }
+        self.counts[slot] = self.counts[slot].saturating_add(1);
+    }
+
+    fn sum(&self, window_secs: u64, sec: u64) -> u32 {
+        self.stamps
+            .iter()
+            .zip(self.counts.iter())
+            .filter(|&(&stamp, _)| stamp <= sec && sec - stamp < window_secs)
+            .fold(0u32, |acc, (_, &count)| acc.saturating_add(count))
+    }
+}
+
+static EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+static TRACKER: LazyLock<moka::sync::Cache<IpAddr, Arc<Mutex<Ring>>>> = LazyLock::new(|| {
+    moka::sync::Cache::builder()
+        .max_capacity(MAX_TRACKED_CLIENTS)
+        .time_to_idle(Duration::from_secs(MAX_WINDOW_SECS * 2))
+        .build()
+});
+
+/// 当前秒数（从 1 开始，0 表示空格）/ Current second (starts at 1, 0 marks an empty bucket)
+#[inline]
+fn now_sec() -> u64 {
+    EPOCH.elapsed().as_secs() + 1
+}
+
+/// 记录一次发往客户端的 NXDOMAIN / Record an NXDOMAIN sent to a client
+pub fn record(client: IpAddr) {
+    record_at(client, now_sec());
+}
+
+fn record_at(client: IpAddr, sec: u64) {
+    TRACKER
+        .get_with(client, || Arc::new(Mutex::new(Ring::default())))
+        .lock()
+        .add(sec);
+}
+
+/// 客户端在最近 window_secs 秒内的 NXDOMAIN 数 / NXDOMAINs a client received in the last window_secs seconds
+pub fn count(client: IpAddr, window_secs: u64) -> u32 {
+    count_at(client, window_secs, now_sec())
+}
+
+fn count_at(client: IpAddr, window_secs: u64, sec: u64) -> u32 {
+    TRACKER
+        .get(&client)
+        .map_or(0, |ring| ring.lock().sum(window_secs, sec))
+}
+
+/// NXDOMAIN 突发匹配条件 / NXDOMAIN burst match condition
+#[derive(Debug, Clone, Copy)]
+pub struct NxdomainBurst {
+    pub threshold: u32,
+    pub window_secs: u64,
+}
+
+impl NxdomainBurst {
+    pub fn new(threshold: u32, window_secs: u64) -> anyhow::Result<Self> {
+        if threshold == 0 {
+            anyhow::bail!("nxdomain_burst threshold must be greater than 0");
+        }
+        if !(1..=MAX_WINDOW_SECS).contains(&window_secs) {
+            anyhow::bail!(
+                "nxdomain_burst window_secs must be within 1..={}, got {}",
+                MAX_WINDOW_SECS,
+                window_secs
+            );
+        }
+        Ok(Self { threshold, window_secs })
+    }
+
+    /// 客户端是否达到阈值 / Whether the client reached the threshold
+    #[inline]
+    pub fn matches(&self, client: IpAddr) -> bool {
+        count(client, self.window_secs) >= self.threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_expire_with_the_window() {
+        // Arrange
+        let client: IpAddr = "198.51.100.200".parse().unwrap();
+        let other: IpAddr = "198.51.100.201".parse().unwrap();
+
+        // Act
+        for _ in 0..3 {
+            record_at(client, 1000);
+        }
+        record_at(client, 1005);
+
+        // Assert
+        assert_eq!(count_at(client, 10, 1005), 4);
+        assert_eq!(count_at(client, 5, 1005), 1);
+        assert_eq!(count_at(client, 10, 1000 + MAX_WINDOW_SECS + 5), 0);
+        assert_eq!(count_at(other, 10, 1005), 0);
+        assert!(NxdomainBurst::new(0, 10).is_err());
+        assert!(NxdomainBurst::new(5, MAX_WINDOW_SECS + 1).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+//! 域名正则批量匹配 / Batched domain regex matching
+//!
+//! 同一 pipeline 内的 domain_regex 匹配器编译为一个 `RegexSet`，每个查询只扫描一次 qname，
+//! 各匹配器按编号读取结果，避免对每个 qname 逐个运行几十个独立的正则引擎。
+//! The domain_regex matchers of a pipeline are compiled into one `RegexSet` so each query
+//! scans the qname once and every matcher reads its result by slot, instead of running
+//! dozens of independent regex engines per qname.
+
+use regex::{RegexSet, SetMatches};
+
+/// 少于该数量的正则不值得合并 / Fewer regexes than this are not worth batching
+pub const MIN_BATCHED_PATTERNS: usize = 2;
+
+/// pipeline 级别的正则集合 / Pipeline-level regex set
+#[derive(Debug, Clone)]
+pub struct DomainRegexSet {
+    set: RegexSet,
+}
+
+impl DomainRegexSet {
+    /// 编译正则集合；数量不足或编译失败（如超出大小限制）时返回 None，各匹配器单独执行
+    /// Compile the set; returns None when there are too few patterns or compiling fails
+    /// (e.g. size limits), in which case matchers run individually
+    pub fn build(pipeline: &str, patterns: &[&str]) -> Option<Self> {
+        if patterns.len() < MIN_BATCHED_PATTERNS {
+            return None;
+        }
+        match RegexSet::new(patterns) {
+            Ok(set) => Some(Self { set }),
+            Err(e) => {
+                tracing::warn!(
+                    pipeline = %pipeline,
+                    patterns = patterns.len(),
+                    error = %e,
+                    "domain regex set not built, falling back to per-rule regexes"
+                );
+                None
+            }
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    /// 一次扫描得到所有正则的匹配结果 / Match every pattern in a single scan
+    #[inline]
+    pub fn matches(&self, qname: &str) -> SetMatches {
+        self.set.matches(qname)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_reports_each_pattern() {
+        // Arrange
+        let set = DomainRegexSet::build("p", &[r"^ads\d+\.", r"\.example\.com$", r"tracker"]).unwrap();
+
+        // Act
+        let hits = set.matches("ads12.example.com");
+
+        // Assert
+        assert_eq!(set.len(), 3);
+        assert!(hits.matched(0));
+        assert!(hits.matched(1));
+        assert!(!hits.matched(2));
+        assert!(DomainRegexSet::build("p", &["only"]).is_none());
+        assert!(DomainRegexSet::build("p", &["ok", "(unclosed"]).is_none());
+    }
+}
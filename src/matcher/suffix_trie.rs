@@ -0,0 +1,131 @@
+//! 域名后缀字典树 / Domain suffix trie
+//!
+//! 按标签反向（从 TLD 开始）存储 domain_suffix 匹配器的值，每个节点记录在此结束的规则下标。
+//! 查询时沿 qname 的标签自右向左走一遍即可收集所有候选规则，耗时只与 qname 长度相关，
+//! 与后缀匹配器数量无关。
+//! Stores domain_suffix values by reversed labels (starting at the TLD); each node records
+//! the rule indices whose suffix ends there. A lookup walks the qname labels right to left
+//! once to collect every candidate rule, so its cost depends on the qname length only and
+//! not on the number of suffix matchers.
+
+use rustc_hash::FxHashMap;
+use smallvec::SmallVec;
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    children: FxHashMap<Box<str>, u32>,
+    rules: Vec<usize>,
+}
+
+/// 后缀 -> 规则下标的字典树 / Trie mapping suffixes to rule indices
+#[derive(Debug, Clone)]
+pub struct SuffixTrie {
+    nodes: Vec<Node>,
+    len: usize,
+}
+
+impl Default for SuffixTrie {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node::default()],
+            len: 0,
+        }
+    }
+}
+
+impl SuffixTrie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 已登记的（后缀, 规则）对数 / Number of registered (suffix, rule) pairs
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 登记后缀（忽略首尾的点与大小写）；空后缀返回 false
+    /// Register a suffix (leading/trailing dots and case are ignored); returns false for an empty suffix
+    pub fn insert(&mut self, suffix: &str, rule_idx: usize) -> bool {
+        let suffix = suffix.trim_matches('.');
+        if suffix.is_empty() {
+            return false;
+        }
+        let mut node = 0usize;
+        for label in suffix.rsplit('.') {
+            let label = label.to_ascii_lowercase();
+            node = match self.nodes[node].children.get(label.as_str()) {
+                Some(&child) => child as usize,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(Node::default());
+                    self.nodes[node]
+                        .children
+                        .insert(label.into_boxed_str(), child as u32);
+                    child
+                }
+            };
+        }
+        let rules = &mut self.nodes[node].rules;
+        if !rules.contains(&rule_idx) {
+            rules.push(rule_idx);
+            self.len += 1;
+        }
+        true
+    }
+
+    /// 收集后缀与 qname（小写，可带结尾点）在标签边界上匹配的规则
+    /// Collect rules whose suffix matches the qname (lowercase, trailing dot allowed) on a label boundary
+    pub fn collect_into(&self, qname: &str, out: &mut SmallVec<[usize; 32]>) {
+        if self.is_empty() {
+            return;
+        }
+        let qname = qname.trim_end_matches('.');
+        if qname.is_empty() {
+            return;
+        }
+        let mut node = &self.nodes[0];
+        for label in qname.rsplit('.') {
+            match node.children.get(label) {
+                Some(&child) => node = &self.nodes[child as usize],
+                None => return,
+            }
+            out.extend_from_slice(&node.rules);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collects_every_matching_suffix_on_label_boundaries() {
+        // Arrange
+        let mut trie = SuffixTrie::new();
+        trie.insert("com", 0);
+        trie.insert(".Example.com", 1);
+        trie.insert("cdn.example.com.", 2);
+        trie.insert("example.com", 1);
+        trie.insert("", 3);
+
+        // Act
+        let collect = |qname: &str| {
+            let mut out = SmallVec::new();
+            trie.collect_into(qname, &mut out);
+            out.into_vec()
+        };
+
+        // Assert
+        assert_eq!(trie.len(), 3);
+        assert_eq!(collect("a.cdn.example.com."), vec![0, 1, 2]);
+        assert_eq!(collect("example.com"), vec![0, 1]);
+        assert_eq!(collect("badexample.com"), vec![0]);
+        assert!(collect("example.org").is_empty());
+    }
+}
@@ -7,6 +7,26 @@ pub struct QuickQuery<'a> {
     pub qtype: u16,
     pub qclass: u16,
     pub edns_present: bool,
+    /// 请求的 DO/CD 标志位（见 DNSSEC_FLAG_DO / DNSSEC_FLAG_CD），参与缓存键
+    /// The request's DO/CD bits (see DNSSEC_FLAG_DO / DNSSEC_FLAG_CD), part of the cache key
+    pub dnssec_flags: u8,
+}
+
+/// 请求 OPT 记录中的 DO 位 / DO bit of the request's OPT record
+pub const DNSSEC_FLAG_DO: u8 = 0x01;
+/// 请求头部的 CD 位 / CD bit of the request header
+pub const DNSSEC_FLAG_CD: u8 = 0x02;
+
+/// 从完整解析的请求中取 DO/CD 标志位 / DO/CD bits of a fully parsed request
+pub fn dnssec_flags_of(msg: &hickory_proto::op::Message) -> u8 {
+    let mut flags = 0;
+    if msg.extensions().as_ref().is_some_and(|edns| edns.dnssec_ok()) {
+        flags |= DNSSEC_FLAG_DO;
+    }
+    if msg.checking_disabled() {
+        flags |= DNSSEC_FLAG_CD;
+    }
+    flags
 }
 
 /// 仅解析 DNS 头部和第一个 Query，用于快速缓存查找 / Parse only DNS header and first query for quick cache lookup
@@ -53,6 +73,11 @@ pub fn parse_quick<'a>(packet: &[u8], buf: &'a mut [u8]) -> Option<QuickQuery<'a
             break;
         }
 
+        if (len & 0xC0) == 0x40 || (len & 0xC0) == 0x80 {
+            // 保留的扩展标签类型（RFC 6891 §5）/ Reserved extended label types (RFC 6891 §5)
+            return None;
+        }
+
         if (len & 0xC0) == 0xC0 {
             // Compression pointer / 压缩指针
             if packet_len < current_pos + 2 {
@@ -87,6 +112,11 @@ pub fn parse_quick<'a>(packet: &[u8], buf: &'a mut [u8]) -> Option<QuickQuery<'a
         }
 
         let label_bytes = &packet[current_pos..current_pos + label_len];
+        if label_bytes.contains(&b'.') {
+            // 标签内的 '.' 会让 a.b 与 "a.b" 单标签得到同一 qname（共用缓存与规则）
+            // A '.' inside a label would give a.b and the single label "a.b" the same qname (shared cache and rules)
+            return None;
+        }
 
         // Performance optimization: Check if label needs lowercasing
         // Most DNS labels are already lowercase, so we can avoid the copy in common case
@@ -146,6 +176,7 @@ pub fn parse_quick<'a>(packet: &[u8], buf: &'a mut [u8]) -> Option<QuickQuery<'a
 
     // 5. Check for EDNS in Additional section / 在附加部分检查 EDNS
     let mut edns_present = false;
+    let mut dnssec_flags = if packet[3] & 0x10 != 0 { DNSSEC_FLAG_CD } else { 0 };
     if ar_count > 0 && an_count == 0 && ns_count == 0 {
         // Fast-path optimization: for standard query messages, AN and NS are expected to be 0.
         // We only scan the Additional section in this common case to keep parsing fast, which may miss EDNS
@@ -169,8 +200,11 @@ pub fn parse_quick<'a>(packet: &[u8], buf: &'a mut [u8]) -> Option<QuickQuery<'a
             }
             let rr_type = u16::from_be_bytes([packet[next_pos], packet[next_pos + 1]]);
             if rr_type == 41 {
-                // OPT
+                // OPT：TTL 字段的第三字节最高位为 DO / OPT: DO is the top bit of the third TTL byte
                 edns_present = true;
+                if packet[next_pos + 6] & 0x80 != 0 {
+                    dnssec_flags |= DNSSEC_FLAG_DO;
+                }
                 break;
             }
             let rd_len = u16::from_be_bytes([packet[next_pos + 8], packet[next_pos + 9]]);
@@ -204,6 +238,7 @@ pub fn parse_quick<'a>(packet: &[u8], buf: &'a mut [u8]) -> Option<QuickQuery<'a
         qtype,
         qclass,
         edns_present,
+        dnssec_flags,
     })
 }
 
@@ -229,6 +264,131 @@ fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
     }
 }
 
+/// 资源记录所在的段 / Section a resource record belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Answer,
+    Authority,
+    Additional,
+}
+
+/// 一条资源记录在报文中的位置 / Where one resource record sits in a packet
+#[derive(Debug, Clone, Copy)]
+struct RecordPos {
+    /// 记录（名称）起始 / Start of the record (its name)
+    start: usize,
+    /// 名称结束处，即 TYPE 字段 / End of the name, i.e. the TYPE field
+    name_end: usize,
+    /// RDATA 之后 / Just past the RDATA
+    end: usize,
+    section: Section,
+}
+
+impl RecordPos {
+    fn rtype(&self, packet: &[u8]) -> u16 {
+        u16::from_be_bytes([packet[self.name_end], packet[self.name_end + 1]])
+    }
+
+    fn ttl(&self, packet: &[u8]) -> u32 {
+        let at = self.name_end + 4;
+        u32::from_be_bytes([packet[at], packet[at + 1], packet[at + 2], packet[at + 3]])
+    }
+
+    fn set_ttl(&self, packet: &mut [u8], ttl: u32) {
+        let at = self.name_end + 4;
+        packet[at..at + 4].copy_from_slice(&ttl.to_be_bytes());
+    }
+
+    fn rdata(&self) -> usize {
+        self.name_end + 10
+    }
+
+    /// Additional 段的 OPT 伪记录 / The OPT pseudo-record of the additional section
+    fn is_opt(&self, packet: &[u8]) -> bool {
+        self.section == Section::Additional && self.rtype(packet) == 41
+    }
+}
+
+/// 问题段之后各资源记录的游标。游标不借用报文，遍历时可以就地改写 TTL；遇到越界的记录即停止并置
+/// `malformed`，需要完整报文的调用方据此放弃改写。
+/// Cursor over the resource records after the question section. It does not borrow the packet,
+/// so TTLs can be rewritten in place while walking; it stops at the first record running past the
+/// end and sets `malformed`, which callers needing a well-formed packet use to give up.
+struct RecordCursor {
+    pos: usize,
+    index: usize,
+    answers: usize,
+    authorities: usize,
+    total: usize,
+    malformed: bool,
+}
+
+impl RecordCursor {
+    /// 跳过问题段；不足一个头部或问题段无法解析时返回 None
+    /// Skip the question section; None when there is no full header or the questions do not parse
+    fn new(packet: &[u8]) -> Option<Self> {
+        if packet.len() < 12 {
+            return None;
+        }
+        let count = |at: usize| u16::from_be_bytes([packet[at], packet[at + 1]]) as usize;
+        let mut pos = 12;
+        for _ in 0..count(4) {
+            pos = skip_name(packet, pos)? + 4;
+        }
+        if pos > packet.len() {
+            return None;
+        }
+        let (answers, authorities) = (count(6), count(8));
+        Some(Self {
+            pos,
+            index: 0,
+            answers,
+            authorities,
+            total: answers + authorities + count(10),
+            malformed: false,
+        })
+    }
+
+    fn next(&mut self, packet: &[u8]) -> Option<RecordPos> {
+        if self.index >= self.total {
+            return None;
+        }
+        let bounds = skip_name(packet, self.pos)
+            .filter(|&name_end| name_end + 10 <= packet.len())
+            .map(|name_end| {
+                let rd_len = u16::from_be_bytes([packet[name_end + 8], packet[name_end + 9]]) as usize;
+                (name_end, name_end + 10 + rd_len)
+            })
+            .filter(|&(_, end)| end <= packet.len());
+        let Some((name_end, end)) = bounds else {
+            self.malformed = true;
+            self.index = self.total;
+            return None;
+        };
+        let section = if self.index < self.answers {
+            Section::Answer
+        } else if self.index < self.answers + self.authorities {
+            Section::Authority
+        } else {
+            Section::Additional
+        };
+        let record = RecordPos { start: self.pos, name_end, end, section };
+        self.pos = end;
+        self.index += 1;
+        Some(record)
+    }
+}
+
+/// 第一个问题（qname + qtype + qclass）的原始字节 / Raw bytes of the first question (qname + qtype + qclass)
+#[inline]
+pub fn question_bytes(packet: &[u8]) -> Option<&[u8]> {
+    if packet.len() < 12 || u16::from_be_bytes([packet[4], packet[5]]) == 0 {
+        return None;
+    }
+    let end = skip_name(packet, 12)? + 4;
+    packet.get(12..end)
+}
+
 impl QuickQuery<'_> {
     /// 检查 qname 是否匹配指定的域名（忽略大小写）
     /// Check if qname matches the specified domain name (case-insensitive)
@@ -236,7 +396,7 @@ impl QuickQuery<'_> {
     /// # Examples
     /// ```
     /// # use kixdns::proto_utils::QuickQuery;
-    /// let query = QuickQuery { qname_bytes: b"google.com", tx_id: 0, qtype: 1, qclass: 1, edns_present: false };
+    /// let query = QuickQuery { qname_bytes: b"google.com", tx_id: 0, qtype: 1, qclass: 1, edns_present: false, dnssec_flags: 0 };
     /// assert!(query.qname_matches("google.com"));  // 完全匹配
     /// assert!(query.qname_matches("GOOGLE.COM"));  // 大写匹配
     /// assert!(!query.qname_matches("example.com"));  // 不匹配
@@ -281,7 +441,7 @@ impl QuickQuery<'_> {
     /// # Examples
     /// ```
     /// # use kixdns::proto_utils::QuickQuery;
-    /// let query = QuickQuery { qname_bytes: b"google.com", tx_id: 0, qtype: 1, qclass: 1, edns_present: false };
+    /// let query = QuickQuery { qname_bytes: b"google.com", tx_id: 0, qtype: 1, qclass: 1, edns_present: false, dnssec_flags: 0 };
     /// println!("qname: {}", query.qname_str());  // "google.com"
     /// ```
     #[inline]
@@ -325,7 +485,7 @@ impl QuickQuery<'_> {
     /// # Examples
     /// ```
     /// # use kixdns::proto_utils::QuickQuery;
-    /// let query = QuickQuery { qname_bytes: b"google.com", tx_id: 0, qtype: 1, qclass: 1, edns_present: false };
+    /// let query = QuickQuery { qname_bytes: b"google.com", tx_id: 0, qtype: 1, qclass: 1, edns_present: false, dnssec_flags: 0 };
     /// let qname_str = query.qname_str_unchecked();  // &str
     /// ```
     #[inline]
@@ -353,7 +513,7 @@ impl QuickQuery<'_> {
     /// # Examples
     /// ```
     /// # use kixdns::proto_utils::QuickQuery;
-    /// let query = QuickQuery { qname_bytes: b"google.com", tx_id: 0, qtype: 1, qclass: 1, edns_present: false };
+    /// let query = QuickQuery { qname_bytes: b"google.com", tx_id: 0, qtype: 1, qclass: 1, edns_present: false, dnssec_flags: 0 };
     /// let hash = query.qname_hash();
     /// ```
     #[inline]
@@ -495,6 +655,10 @@ pub fn parse_response_quick(packet: &[u8]) -> Option<QuickResponse> {
 
         let rd_len = u16::from_be_bytes([packet[pos + 8], packet[pos + 9]]) as usize;
         pos += 10 + rd_len;
+        if pos > packet_len {
+            // RDATA 超出报文：截断的应答不可缓存 / RDATA runs past the packet: a truncated answer must not be cached
+            return None;
+        }
     }
 
     if min_ttl == u32::MAX {
@@ -512,75 +676,297 @@ pub fn parse_response_quick(packet: &[u8]) -> Option<QuickResponse> {
     })
 }
 
-/// 批量修正 DNS 响应包中的 TTL 值 / Batch patch TTL values in a DNS response packet
-/// decrement: 需要减少的秒数 / seconds to decrement
-pub fn patch_all_ttls(packet: &mut [u8], decrement: u32) {
-    if decrement == 0 || packet.len() < 12 {
-        return;
+/// 按块大小对带 OPT 的报文做 EDNS 填充（RFC 7830/8467）/ Apply EDNS padding (RFC 7830/8467) to a message carrying OPT
+///
+/// 移除已有的 Padding 选项后追加新的选项，使报文长度为 block 的整数倍。
+/// 没有 OPT 的报文不添加 OPT（否则上游会返回客户端未请求的 EDNS），返回 None 表示无需改写。
+/// Drops any existing Padding option and appends a new one so the message length becomes a
+/// multiple of block. Messages without OPT are left alone (adding one would make the upstream
+/// answer with EDNS the client never asked for); None means no rewrite is needed.
+pub fn pad_edns(packet: &[u8], block: usize) -> Option<Vec<u8>> {
+    const OPT_PADDING: u16 = 12;
+    if block == 0 {
+        return None;
     }
+    let (rd_start, rd_len) = opt_rdata(packet)?;
+
+    let mut rdata = Vec::with_capacity(rd_len + block);
+    let mut opt_pos = rd_start;
+    while opt_pos + 4 <= rd_start + rd_len {
+        let code = u16::from_be_bytes([packet[opt_pos], packet[opt_pos + 1]]);
+        let len = u16::from_be_bytes([packet[opt_pos + 2], packet[opt_pos + 3]]) as usize;
+        let end = (opt_pos + 4 + len).min(rd_start + rd_len);
+        if code != OPT_PADDING {
+            rdata.extend_from_slice(&packet[opt_pos..end]);
+        }
+        opt_pos = end;
+    }
+    let unpadded = packet.len() - rd_len + rdata.len() + 4;
+    let pad = (block - unpadded % block) % block;
+    rdata.extend_from_slice(&OPT_PADDING.to_be_bytes());
+    rdata.extend_from_slice(&(pad as u16).to_be_bytes());
+    rdata.resize(rdata.len() + pad, 0);
+    replace_opt_rdata(packet, rd_start, rd_len, &rdata)
+}
 
-    let qd_count = u16::from_be_bytes([packet[4], packet[5]]);
-    let an_count = u16::from_be_bytes([packet[6], packet[7]]);
-    let ns_count = u16::from_be_bytes([packet[8], packet[9]]);
-    let ar_count = u16::from_be_bytes([packet[10], packet[11]]);
-
-    let mut pos = 12;
-    let packet_len = packet.len();
+/// 把 OPT 声明的 UDP 负载大小压到 max / Lower the UDP payload size advertised by the OPT to max
+///
+/// 没有 OPT 或声明值不超过 max 时返回 None。 / None when there is no OPT or it already advertises at most max.
+pub fn clamp_udp_payload(packet: &[u8], max: u16) -> Option<Vec<u8>> {
+    let (rd_start, _) = opt_rdata(packet)?;
+    // CLASS 位于 RDATA 前 8 字节（CLASS、TTL、RDLENGTH）/ CLASS sits 8 bytes before RDATA (CLASS, TTL, RDLENGTH)
+    let class_pos = rd_start - 8;
+    let payload = u16::from_be_bytes([packet[class_pos], packet[class_pos + 1]]);
+    if payload <= max {
+        return None;
+    }
+    let mut out = packet.to_vec();
+    out[class_pos..class_pos + 2].copy_from_slice(&max.to_be_bytes());
+    Some(out)
+}
 
-    // 1. Skip Questions
-    for _ in 0..qd_count {
-        if let Some(next) = skip_name(packet, pos) {
-            pos = next + 4;
+/// 只保留 OPT 中选项码在 allowed 内的选项 / Keep only the OPT options whose code is in allowed
+///
+/// 没有 OPT 或无需删除任何选项时返回 None。 / None when there is no OPT or nothing needs removing.
+pub fn filter_edns_options(packet: &[u8], allowed: &[u16]) -> Option<Vec<u8>> {
+    let (rd_start, rd_len) = opt_rdata(packet)?;
+    let mut rdata = Vec::with_capacity(rd_len);
+    let mut removed = false;
+    let mut opt_pos = rd_start;
+    while opt_pos + 4 <= rd_start + rd_len {
+        let code = u16::from_be_bytes([packet[opt_pos], packet[opt_pos + 1]]);
+        let len = u16::from_be_bytes([packet[opt_pos + 2], packet[opt_pos + 3]]) as usize;
+        let end = (opt_pos + 4 + len).min(rd_start + rd_len);
+        if allowed.contains(&code) {
+            rdata.extend_from_slice(&packet[opt_pos..end]);
         } else {
-            return;
+            removed = true;
         }
+        opt_pos = end;
+    }
+    if !removed {
+        return None;
     }
+    replace_opt_rdata(packet, rd_start, rd_len, &rdata)
+}
 
-    // 2. Patch Answer, Authority, and Additional sections
-    let total_records = an_count as usize + ns_count as usize + ar_count as usize;
-    for _ in 0..total_records {
-        if let Some(next) = skip_name(packet, pos) {
-            pos = next;
-        } else {
-            return;
+/// 用 rdata 替换 OPT 的 RDATA / Replace the OPT RDATA with rdata
+fn replace_opt_rdata(packet: &[u8], rd_start: usize, rd_len: usize, rdata: &[u8]) -> Option<Vec<u8>> {
+    let new_rd_len = u16::try_from(rdata.len()).ok()?;
+    let mut out = Vec::with_capacity(packet.len() - rd_len + rdata.len());
+    out.extend_from_slice(&packet[..rd_start - 2]);
+    out.extend_from_slice(&new_rd_len.to_be_bytes());
+    out.extend_from_slice(rdata);
+    out.extend_from_slice(&packet[rd_start + rd_len..]);
+    Some(out)
+}
+
+/// OPT 记录 RDATA 的起始位置与长度 / Start and length of the OPT record's RDATA
+fn opt_rdata(packet: &[u8]) -> Option<(usize, usize)> {
+    let mut records = RecordCursor::new(packet)?;
+    let mut opt = None;
+    while let Some(rr) = records.next(packet) {
+        if rr.is_opt(packet) {
+            opt = Some((rr.rdata(), rr.end - rr.rdata()));
         }
+    }
+    if records.malformed {
+        return None;
+    }
+    opt
+}
 
-        if pos + 10 > packet_len {
-            return;
+/// 本服务在应答 OPT 中声明的 UDP 负载大小 / UDP payload size advertised in the OPT of our answers
+pub const RESPONSE_UDP_PAYLOAD: u16 = 1232;
+
+/// 按当前客户端的 EDNS 重建缓存应答的 OPT / Rebuild the OPT of a cached answer for the current client's EDNS
+///
+/// 缓存的报文携带的是首个请求者与上游交换时的 OPT（上游的负载大小、Cookie、Padding 等）。
+/// 客户端未使用 EDNS 时移除 OPT；使用 EDNS 时改为本服务的负载大小、回显客户端的 DO 位且不带选项，
+/// 扩展 RCODE 与版本保持不变。返回 None 表示报文已符合当前客户端，无需改写。
+/// A cached packet carries the OPT of the first requester's upstream exchange (the upstream's
+/// payload size, cookies, padding, ...). The OPT is removed for clients without EDNS; for EDNS
+/// clients it gets our payload size, echoes the client's DO bit and carries no options, keeping
+/// the extended RCODE and version. None means the packet already suits the client.
+pub fn echo_client_opt(packet: &[u8], edns_present: bool, dnssec_ok: bool) -> Option<Vec<u8>> {
+    let mut records = RecordCursor::new(packet)?;
+    let ar_count = u16::from_be_bytes([packet[10], packet[11]]);
+    // OPT 记录的起止位置与名称结束位置 / Start, end and name end of the OPT record
+    let mut opt: Option<(usize, usize, usize)> = None;
+    while let Some(rr) = records.next(packet) {
+        if rr.is_opt(packet) {
+            opt = Some((rr.start, rr.end, rr.name_end));
+        }
+    }
+    if records.malformed {
+        return None;
+    }
+
+    let flags: u16 = if dnssec_ok { 0x8000 } else { 0 };
+    let push_opt = |out: &mut Vec<u8>, ext_rcode: u8, version: u8| {
+        out.push(0);
+        out.extend_from_slice(&41u16.to_be_bytes());
+        out.extend_from_slice(&RESPONSE_UDP_PAYLOAD.to_be_bytes());
+        out.extend_from_slice(&[ext_rcode, version]);
+        out.extend_from_slice(&flags.to_be_bytes());
+        out.extend_from_slice(&0u16.to_be_bytes());
+    };
+    let mut out = Vec::with_capacity(packet.len() + 11);
+    match (opt, edns_present) {
+        (None, false) => return None,
+        (Some((start, end, name_end)), true) => {
+            let payload = u16::from_be_bytes([packet[name_end + 2], packet[name_end + 3]]);
+            let current_flags = u16::from_be_bytes([packet[name_end + 6], packet[name_end + 7]]);
+            if payload == RESPONSE_UDP_PAYLOAD && current_flags == flags && end == name_end + 10 {
+                return None;
+            }
+            out.extend_from_slice(&packet[..start]);
+            push_opt(&mut out, packet[name_end + 4], packet[name_end + 5]);
+            out.extend_from_slice(&packet[end..]);
+        }
+        (Some((start, end, _)), false) => {
+            out.extend_from_slice(&packet[..start]);
+            out.extend_from_slice(&packet[end..]);
+            out[10..12].copy_from_slice(&(ar_count - 1).to_be_bytes());
+        }
+        (None, true) => {
+            out.extend_from_slice(packet);
+            push_opt(&mut out, 0, 0);
+            out[10..12].copy_from_slice(&ar_count.checked_add(1)?.to_be_bytes());
         }
+    }
+    Some(out)
+}
+
+/// 仅含头部的错误应答：回显事务 ID、OPCODE 与 RD，计数全部为 0；报文不足 12 字节或本身是响应时返回 None
+/// A header-only error reply echoing the transaction ID, OPCODE and RD with all counts zero;
+/// None when the packet is shorter than a header or is itself a response
+pub fn header_only_reply(packet: &[u8], rcode: u8) -> Option<Vec<u8>> {
+    if packet.len() < 12 || packet[2] & 0x80 != 0 {
+        return None;
+    }
+    let mut out = vec![0u8; 12];
+    out[..2].copy_from_slice(&packet[..2]);
+    // QR=1，保留 OPCODE 与 RD / QR=1, keeping OPCODE and RD
+    out[2] = 0x80 | (packet[2] & 0x79);
+    // RA=1 与 RCODE / RA=1 and RCODE
+    out[3] = 0x80 | (rcode & 0x0F);
+    Some(out)
+}
 
-        // Type(2) Class(2) TTL(4) RDLen(2)
-        let rtype = u16::from_be_bytes([packet[pos], packet[pos + 1]]);
+/// 不带 EDNS 时的 UDP 报文上限（RFC 1035 §2.3.4）/ UDP message limit without EDNS (RFC 1035 §2.3.4)
+pub const CLASSIC_UDP_PAYLOAD: usize = 512;
+
+/// 客户端可接收的 UDP 应答大小：请求 OPT 声明的负载大小（不小于 512），没有 OPT 时为 512
+/// UDP answer size the client accepts: the payload size of the request's OPT (at least 512),
+/// or 512 without OPT
+pub fn udp_payload_limit(query: &[u8]) -> usize {
+    let Some(mut records) = RecordCursor::new(query) else {
+        return CLASSIC_UDP_PAYLOAD;
+    };
+    while let Some(rr) = records.next(query) {
+        if rr.rtype(query) == 41 {
+            let payload = u16::from_be_bytes([query[rr.name_end + 2], query[rr.name_end + 3]]) as usize;
+            return payload.max(CLASSIC_UDP_PAYLOAD);
+        }
+    }
+    CLASSIC_UDP_PAYLOAD
+}
 
+/// 超出客户端 UDP 上限的应答改为 TC=1 的截断应答 / Turn an answer over the client's UDP limit into a TC=1 truncated answer
+///
+/// 经 TCP 取得的大应答只缓存一份完整报文：TCP 客户端收到完整应答，UDP 客户端收到由同一条目生成的
+/// 截断应答（保留头部、问题段与 OPT，置 TC 位）后改用 TCP 重试。返回 None 表示无需截断。
+/// Large answers fetched over TCP are cached once, in full: TCP clients get the whole answer
+/// and UDP clients get a truncated answer built from the same entry (header, question and OPT
+/// kept, TC set) and retry over TCP. None means no truncation is needed.
+pub fn truncate_for_udp(query: &[u8], resp: &[u8]) -> Option<Vec<u8>> {
+    if resp.len() <= CLASSIC_UDP_PAYLOAD || resp.len() <= udp_payload_limit(query) || resp.len() < 12 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(CLASSIC_UDP_PAYLOAD);
+    out.extend_from_slice(&resp[..12]);
+    out[2] |= 0x02;
+    out[6..12].fill(0);
+    // 问题段无法解析时只回头部 / Header only when the question section cannot be parsed
+    let Some(mut records) = RecordCursor::new(resp) else {
+        out[4..6].fill(0);
+        return Some(out);
+    };
+    out.extend_from_slice(&resp[12..records.pos]);
+    while let Some(rr) = records.next(resp) {
+        if rr.is_opt(resp) {
+            out.extend_from_slice(&resp[rr.start..rr.end]);
+            out[11] = 1;
+            break;
+        }
+    }
+    Some(out)
+}
+
+/// 批量修正 DNS 响应包中的 TTL 值 / Batch patch TTL values in a DNS response packet
+/// decrement: 需要减少的秒数 / seconds to decrement
+pub fn patch_all_ttls(packet: &mut [u8], decrement: u32) {
+    if decrement == 0 {
+        return;
+    }
+    let Some(mut records) = RecordCursor::new(packet) else {
+        return;
+    };
+    while let Some(rr) = records.next(packet) {
         // Skip OPT (type 41) pseudo-records: their TTL field contains EDNS extended
         // RCODE and flags (including the DO bit), NOT an actual TTL value.
         // 跳过 OPT（类型 41）伪记录：其 TTL 字段包含 EDNS 扩展 RCODE 和标志（包括 DO 位），不是真正的 TTL。
-        if rtype != 41 {
-            let ttl_offset = pos + 4;
-            let old_ttl = u32::from_be_bytes([
-                packet[ttl_offset],
-                packet[ttl_offset + 1],
-                packet[ttl_offset + 2],
-                packet[ttl_offset + 3],
-            ]);
-
+        if rr.rtype(packet) != 41 {
             // RFC 1035: Decrement TTL, floor at 0
-            let new_ttl = old_ttl.saturating_sub(decrement);
-            let ttl_bytes = new_ttl.to_be_bytes();
-            packet[ttl_offset..ttl_offset + 4].copy_from_slice(&ttl_bytes);
+            let ttl = rr.ttl(packet).saturating_sub(decrement);
+            rr.set_ttl(packet, ttl);
         }
+    }
+}
 
-        let rd_len = u16::from_be_bytes([packet[pos + 8], packet[pos + 9]]) as usize;
-
-        // Ensure the entire record (10 bytes header + RData) fits within remaining packet
-        // and handle potential overflow for the next iteration's position
-        let record_total_len = 10usize.saturating_add(rd_len);
-        if pos.saturating_add(record_total_len) > packet_len {
-            return;
+/// Negative caching TTL of a response per RFC 2308 §5: min(SOA TTL, SOA MINIMUM)
+/// taken from the first SOA record in the authority section.
+/// 按 RFC 2308 §5 计算否定响应的缓存 TTL：取授权段第一条 SOA 的 min(TTL, MINIMUM)。
+pub fn negative_ttl(packet: &[u8]) -> Option<u32> {
+    let mut records = RecordCursor::new(packet)?;
+    while let Some(rr) = records.next(packet) {
+        match rr.section {
+            Section::Answer => continue,
+            Section::Additional => break,
+            Section::Authority => {}
+        }
+        // SOA RDATA 以 MINIMUM 字段结尾 / SOA RDATA ends with the MINIMUM field
+        if rr.rtype(packet) == 6 && rr.end - rr.rdata() >= 20 {
+            let minimum = u32::from_be_bytes([
+                packet[rr.end - 4],
+                packet[rr.end - 3],
+                packet[rr.end - 2],
+                packet[rr.end - 1],
+            ]);
+            return Some(rr.ttl(packet).min(minimum));
         }
+    }
+    None
+}
 
-        pos += record_total_len;
+/// Cap all record TTLs in a DNS packet at a maximum value.
+/// Used to enforce max_ttl on upstream responses.
+/// 将 DNS 报文中所有记录的 TTL 截断到上限。
+/// 用于对上游响应应用 max_ttl。
+///
+/// 只有存在超过上限的 TTL 时才拷贝报文，返回 None 表示无需改写。
+/// The packet is only copied when some TTL is over the cap; None means no rewrite is needed.
+pub fn cap_all_ttls(packet: &[u8], max_ttl: u32) -> Option<Vec<u8>> {
+    let mut records = RecordCursor::new(packet)?;
+    let mut capped: Option<Vec<u8>> = None;
+    while let Some(rr) = records.next(packet) {
+        // 跳过 OPT（类型 41）伪记录，其 TTL 字段为 EDNS 标志 / Skip OPT (type 41) pseudo-records, whose TTL field holds EDNS flags
+        if rr.rtype(packet) != 41 && rr.ttl(packet) > max_ttl {
+            rr.set_ttl(capped.get_or_insert_with(|| packet.to_vec()), max_ttl);
+        }
     }
+    capped
 }
 
 /// Set all record TTLs in a DNS packet to an absolute value.
@@ -588,58 +974,153 @@ pub fn patch_all_ttls(packet: &mut [u8], decrement: u32) {
 /// 将 DNS 报文中所有记录的 TTL 设为绝对值。
 /// 用于 RFC 8767 stale 服务，将 TTL 设置为 serve_stale_ttl。
 pub fn set_all_ttls(packet: &mut [u8], new_ttl: u32) {
-    if packet.len() < 12 {
+    let Some(mut records) = RecordCursor::new(packet) else {
         return;
+    };
+    while let Some(rr) = records.next(packet) {
+        // Skip OPT (type 41) pseudo-records: their TTL field contains EDNS extended
+        // RCODE and flags (including the DO bit), NOT an actual TTL value.
+        // 跳过 OPT（类型 41）伪记录：其 TTL 字段包含 EDNS 扩展 RCODE 和标志（包括 DO 位），不是真正的 TTL。
+        if rr.rtype(packet) != 41 {
+            rr.set_ttl(packet, new_ttl);
+        }
     }
+}
 
-    let qd_count = u16::from_be_bytes([packet[4], packet[5]]);
-    let an_count = u16::from_be_bytes([packet[6], packet[7]]);
-    let ns_count = u16::from_be_bytes([packet[8], packet[9]]);
-    let ar_count = u16::from_be_bytes([packet[10], packet[11]]);
-
-    let mut pos = 12;
-    let packet_len = packet.len();
-
-    // 1. Skip Questions
-    for _ in 0..qd_count {
-        if let Some(next) = skip_name(packet, pos) {
-            pos = next + 4;
-        } else {
-            return;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// 手工编码的查询：一个问题，可选带 DO 位的 OPT / Hand-encoded query: one question, optionally an OPT with DO set
+    fn query_packet(tx_id: u16, labels: &[Vec<u8>], qtype: u16, qclass: u16, opt: bool) -> Vec<u8> {
+        let mut p = tx_id.to_be_bytes().to_vec();
+        p.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, u8::from(opt)]);
+        for label in labels {
+            p.push(label.len() as u8);
+            p.extend_from_slice(label);
         }
+        p.push(0);
+        p.extend_from_slice(&qtype.to_be_bytes());
+        p.extend_from_slice(&qclass.to_be_bytes());
+        if opt {
+            p.extend_from_slice(&[0, 0, 41, 0x04, 0xd0, 0, 0, 0x80, 0, 0, 0]);
+        }
+        p
     }
 
-    // 2. Set TTL for Answer, Authority, and Additional sections
-    let total_records = an_count as usize + ns_count as usize + ar_count as usize;
-    let ttl_bytes = new_ttl.to_be_bytes();
-    for _ in 0..total_records {
-        if let Some(next) = skip_name(packet, pos) {
-            pos = next;
-        } else {
-            return;
+    /// 每个 TTL 对应一条 A 记录的应答 / Answer with one A record per TTL
+    fn response_packet(ttls: &[u32]) -> Vec<u8> {
+        let mut p = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, ttls.len() as u8, 0, 0, 0, 0];
+        p.extend_from_slice(&[1, b'a', 0, 0, 1, 0, 1]);
+        for ttl in ttls {
+            p.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1]);
+            p.extend_from_slice(&ttl.to_be_bytes());
+            p.extend_from_slice(&[0, 4, 192, 0, 2, 1]);
         }
+        p
+    }
 
-        if pos + 10 > packet_len {
-            return;
+    proptest! {
+        #[test]
+        fn prop_parse_quick_round_trips_queries(
+            tx_id in any::<u16>(),
+            labels in prop::collection::vec(prop::collection::vec(any::<u8>(), 1..=63), 0..4),
+            qtype in any::<u16>(),
+            qclass in any::<u16>(),
+            opt in any::<bool>(),
+        ) {
+            // Arrange
+            let packet = query_packet(tx_id, &labels, qtype, qclass, opt);
+            let mut buf = [0u8; 256];
+
+            // Act
+            let parsed = parse_quick(&packet, &mut buf);
+
+            // Assert: Non-ASCII labels and labels containing '.' take the full parser instead
+            if labels.iter().any(|l| !l.is_ascii() || l.contains(&b'.')) {
+                prop_assert!(parsed.is_none());
+            } else {
+                let q = parsed.unwrap();
+                let expected = labels.iter().map(|l| l.to_ascii_lowercase()).collect::<Vec<_>>().join(&b'.');
+                prop_assert_eq!(q.qname_bytes, &expected[..]);
+                prop_assert_eq!((q.tx_id, q.qtype, q.qclass, q.edns_present), (tx_id, qtype, qclass, opt));
+                prop_assert_eq!(q.dnssec_flags, if opt { DNSSEC_FLAG_DO } else { 0 });
+            }
         }
 
-        // Type(2) Class(2) TTL(4) RDLen(2)
-        let rtype = u16::from_be_bytes([packet[pos], packet[pos + 1]]);
+        #[test]
+        fn prop_parse_response_quick_reports_ttl_range(ttls in prop::collection::vec(any::<u32>(), 1..8), cut in 0usize..4) {
+            // Arrange
+            let mut packet = response_packet(&ttls);
+            packet.truncate(packet.len() - cut);
 
-        // Skip OPT (type 41) pseudo-records: their TTL field contains EDNS extended
-        // RCODE and flags (including the DO bit), NOT an actual TTL value.
-        // 跳过 OPT（类型 41）伪记录：其 TTL 字段包含 EDNS 扩展 RCODE 和标志（包括 DO 位），不是真正的 TTL。
-        if rtype != 41 {
-            let ttl_offset = pos + 4;
-            packet[ttl_offset..ttl_offset + 4].copy_from_slice(&ttl_bytes);
+            // Act
+            let parsed = parse_response_quick(&packet);
+
+            // Assert: A record cut short makes the whole response unusable
+            if cut > 0 {
+                prop_assert!(parsed.is_none());
+            } else {
+                let r = parsed.unwrap();
+                prop_assert_eq!(r.min_ttl, *ttls.iter().min().unwrap());
+                prop_assert_eq!(r.max_ttl, *ttls.iter().max().unwrap());
+            }
         }
 
-        let rd_len = u16::from_be_bytes([packet[pos + 8], packet[pos + 9]]) as usize;
-        let record_total_len = 10usize.saturating_add(rd_len);
-        if pos.saturating_add(record_total_len) > packet_len {
-            return;
+        #[test]
+        fn prop_cap_all_ttls_copies_only_when_a_ttl_is_over(ttls in prop::collection::vec(any::<u32>(), 1..8), max_ttl in any::<u32>()) {
+            // Arrange
+            let packet = response_packet(&ttls);
+
+            // Act
+            let capped = cap_all_ttls(&packet, max_ttl);
+
+            // Assert
+            let over = ttls.iter().any(|&ttl| ttl > max_ttl);
+            prop_assert_eq!(capped.is_some(), over);
+            if let Some(capped) = capped {
+                let r = parse_response_quick(&capped).unwrap();
+                prop_assert_eq!(r.max_ttl, max_ttl);
+                prop_assert_eq!(r.min_ttl, (*ttls.iter().min().unwrap()).min(max_ttl));
+            }
         }
 
-        pos += record_total_len;
+        #[test]
+        fn prop_quick_parsers_survive_arbitrary_input(
+            head in any::<[u8; 4]>(),
+            counts in any::<[u8; 4]>(),
+            body in prop::collection::vec(prop_oneof![any::<u8>(), Just(0xC0u8), Just(0u8), Just(41u8)], 0..512),
+        ) {
+            // Arrange: Small section counts so the record walkers actually run over the body
+            let mut packet = head.to_vec();
+            for count in counts {
+                packet.extend_from_slice(&[0, count % 5]);
+            }
+            packet.extend_from_slice(&body);
+            let mut buf = [0u8; 256];
+
+            // Act & Assert: Nothing panics, and rewritten packets are never longer than the input plus one OPT
+            let _ = parse_quick(&packet, &mut buf);
+            let _ = parse_response_quick(&packet);
+            let _ = question_bytes(&packet);
+            let _ = negative_ttl(&packet);
+            let _ = udp_payload_limit(&packet);
+            let _ = header_only_reply(&packet, 2);
+            if let Some(truncated) = truncate_for_udp(&packet, &packet) {
+                prop_assert!(truncated.len() <= packet.len());
+            }
+            if let Some(echoed) = echo_client_opt(&packet, true, true) {
+                prop_assert!(echoed.len() <= packet.len() + 11);
+            }
+            let _ = pad_edns(&packet, 128);
+            let mut patched = packet.clone();
+            patch_all_ttls(&mut patched, 30);
+            if let Some(capped) = cap_all_ttls(&patched, 60) {
+                prop_assert_eq!(capped.len(), patched.len());
+            }
+            set_all_ttls(&mut patched, 5);
+            prop_assert_eq!(patched.len(), packet.len());
+        }
     }
 }
@@ -4,6 +4,11 @@
 #[cfg(unix)]
 use socket2::Socket;
 use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use anyhow::Context;
+
+use crate::config::Ipv6ListenMode;
 
 #[cfg(unix)]
 use std::os::fd::AsRawFd;
@@ -78,6 +83,135 @@ pub fn set_reuseport(socket: &Socket, enabled: bool) -> io::Result<()> {
     }
 }
 
+/// Safely enable TCP_FASTOPEN on a listening socket
+/// 安全地在监听 socket 上启用 TCP_FASTOPEN
+///
+/// # Arguments
+/// * `socket` - The listening socket to configure
+/// * `queue_len` - Maximum number of pending TFO requests (0 disables)
+///
+/// # Returns
+/// * `Ok(())` - Option set successfully
+/// * `Err(io::Error)` - Failed to set option or not supported (non-fatal, logged as warning)
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[inline]
+pub fn set_tcp_fastopen(socket: &Socket, queue_len: u32) -> io::Result<()> {
+    use libc::{c_int, setsockopt, socklen_t, IPPROTO_TCP, TCP_FASTOPEN};
+
+    let val: c_int = queue_len.min(c_int::MAX as u32) as c_int;
+    let fd = socket.as_raw_fd();
+
+    let ret = unsafe {
+        setsockopt(
+            fd,
+            IPPROTO_TCP,
+            TCP_FASTOPEN,
+            &val as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&val) as socklen_t,
+        )
+    };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Safely enable TCP_FASTOPEN_CONNECT on an outbound socket (before connect)
+/// 安全地在出站 socket 上启用 TCP_FASTOPEN_CONNECT（须在 connect 之前）
+///
+/// With this option the first write is carried in the SYN once a TFO cookie is cached.
+/// 启用后，缓存 TFO cookie 后首次写入的数据随 SYN 一起发送。
+///
+/// # Returns
+/// * `Ok(())` - Option set successfully
+/// * `Err(io::Error)` - Failed to set option or not supported (Linux >= 4.11)
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[inline]
+pub fn set_tcp_fastopen_connect(socket: &Socket, enabled: bool) -> io::Result<()> {
+    use libc::{c_int, setsockopt, socklen_t, IPPROTO_TCP, TCP_FASTOPEN_CONNECT};
+
+    let val: c_int = if enabled { 1 } else { 0 };
+    let fd = socket.as_raw_fd();
+
+    let ret = unsafe {
+        setsockopt(
+            fd,
+            IPPROTO_TCP,
+            TCP_FASTOPEN_CONNECT,
+            &val as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&val) as socklen_t,
+        )
+    };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// 一个监听 socket：绑定地址与 IPV6_V6ONLY 取值（IPv4 socket 为 None）
+/// One listening socket: its bind address and IPV6_V6ONLY value (None for IPv4 sockets)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenSocket {
+    pub addr: SocketAddr,
+    pub v6only: Option<bool>,
+}
+
+/// 解析以逗号分隔的监听地址列表 / Parse a comma-separated list of listen addresses
+pub fn parse_bind_list(list: &str) -> anyhow::Result<Vec<SocketAddr>> {
+    let addrs = list
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().with_context(|| format!("invalid listen address {:?}", s)))
+        .collect::<anyhow::Result<Vec<SocketAddr>>>()?;
+    anyhow::ensure!(!addrs.is_empty(), "no listen address in {:?}", list);
+    Ok(addrs)
+}
+
+/// 按 IPv6 监听模式展开为需要创建的 socket（去重并保持顺序）
+/// Expand listen addresses into the sockets to create per the IPv6 listen mode (deduplicated, order kept)
+///
+/// `[::]` 在 split 模式下展开为 `0.0.0.0` 与仅 IPv6 的 `[::]` 两个 socket，dual_stack 模式下为一个
+/// 同时接收 IPv4（映射地址）的 socket，v6_only 模式下只监听 IPv6；具体的 IPv6 地址总是仅 IPv6。
+/// `[::]` expands to `0.0.0.0` plus an IPv6-only `[::]` in split mode, to one socket that also
+/// accepts IPv4 (as mapped addresses) in dual_stack mode and to IPv6 only in v6_only mode;
+/// specific IPv6 addresses are always IPv6-only.
+pub fn plan_listen_sockets(addrs: &[SocketAddr], mode: Ipv6ListenMode) -> Vec<ListenSocket> {
+    let mut out: Vec<ListenSocket> = Vec::with_capacity(addrs.len() + 1);
+    let mut push = |socket: ListenSocket| {
+        if !out.contains(&socket) {
+            out.push(socket);
+        }
+    };
+    for &addr in addrs {
+        match addr {
+            SocketAddr::V4(_) => push(ListenSocket { addr, v6only: None }),
+            SocketAddr::V6(v6) if v6.ip().is_unspecified() => match mode {
+                Ipv6ListenMode::Split => {
+                    let v4 = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), addr.port());
+                    push(ListenSocket { addr: v4, v6only: None });
+                    push(ListenSocket { addr, v6only: Some(true) });
+                }
+                Ipv6ListenMode::DualStack => push(ListenSocket { addr, v6only: Some(false) }),
+                Ipv6ListenMode::V6Only => push(ListenSocket { addr, v6only: Some(true) }),
+            },
+            SocketAddr::V6(_) => push(ListenSocket { addr, v6only: Some(true) }),
+        }
+    }
+    out
+}
+
+/// 把 IPv4 映射的 IPv6 对端地址还原为 IPv4，使双栈 socket 上的客户端与 IPv4 规则匹配
+/// Turn an IPv4-mapped IPv6 peer back into IPv4 so clients on dual-stack sockets match IPv4 rules
+#[inline]
+pub fn canonical_peer(peer: SocketAddr) -> SocketAddr {
+    SocketAddr::new(peer.ip().to_canonical(), peer.port())
+}
+
 /// Non-Unix stub implementations (Windows and other platforms)
 /// 非 Unix 系统的存根实现（Windows 和其他平台）
 #[cfg(not(unix))]
@@ -99,3 +233,47 @@ pub fn set_reuseport(_socket: &socket2::Socket, _enabled: bool) -> io::Result<()
         "SO_REUSEPORT not supported on this platform",
     ))
 }
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+#[allow(dead_code)] // Stub implementation for cross-platform compatibility
+#[inline]
+pub fn set_tcp_fastopen(_socket: &socket2::Socket, _queue_len: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "TCP_FASTOPEN not supported on this platform",
+    ))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+#[allow(dead_code)] // Stub implementation for cross-platform compatibility
+#[inline]
+pub fn set_tcp_fastopen_connect(_socket: &socket2::Socket, _enabled: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "TCP_FASTOPEN_CONNECT not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listen_sockets_follow_ipv6_mode() {
+        // Arrange
+        let addrs = parse_bind_list("0.0.0.0:53, [::]:53,[fd00::53]:53").unwrap();
+        let socket = |addr: &str, v6only| ListenSocket { addr: addr.parse().unwrap(), v6only };
+
+        // Act
+        let split = plan_listen_sockets(&addrs, Ipv6ListenMode::Split);
+        let dual = plan_listen_sockets(&addrs, Ipv6ListenMode::DualStack);
+        let v6_only = plan_listen_sockets(&addrs[1..2], Ipv6ListenMode::V6Only);
+
+        // Assert
+        assert_eq!(split, [socket("0.0.0.0:53", None), socket("[::]:53", Some(true)), socket("[fd00::53]:53", Some(true))]);
+        assert_eq!(dual, [socket("0.0.0.0:53", None), socket("[::]:53", Some(false)), socket("[fd00::53]:53", Some(true))]);
+        assert_eq!(v6_only, [socket("[::]:53", Some(true))]);
+        assert_eq!(canonical_peer("[::ffff:192.0.2.1]:5300".parse().unwrap()), "192.0.2.1:5300".parse().unwrap());
+        assert!(parse_bind_list(" , ").is_err());
+    }
+}
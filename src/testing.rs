@@ -0,0 +1,210 @@
+//! 配置回归测试工具 / Config regression-test harness
+//!
+//! 启用 `test-support` feature 后可用：在内存中的配置上运行真实的 Engine，上游用 `mock://` 脚本化
+//! 应答（见 `engine::mock_upstream`），不访问网络，结果可重复。每次查询返回所选 pipeline、请求匹配器
+//! 命中的规则、是否由缓存应答以及最终报文，便于为自己的配置编写回归测试。
+//! Available with the `test-support` feature: runs a real Engine over an in-memory config,
+//! with upstreams scripted as `mock://` answers (see `engine::mock_upstream`) so nothing
+//! touches the network and results are repeatable. Every query reports the selected pipeline,
+//! the rules whose request matchers matched, whether the cache answered, and the final packet,
+//! so users can regression-test their own configs.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+
+use anyhow::Context;
+use bytes::Bytes;
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{DNSClass, Name, RData, RecordType};
+
+use crate::config::{PipelineConfig, parse_config};
+use crate::engine::{Engine, FastPathResponse, MatcherContext, matcher_matches, select_pipeline};
+use crate::matcher::{RuntimePipelineConfig, eval_match_chain};
+
+/// 默认的模拟客户端地址 / Default simulated client address
+const DEFAULT_CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 10));
+
+/// 一次查询的结果 / Outcome of one query
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    /// 选中的 pipeline / The selected pipeline
+    pub pipeline: String,
+    /// 选中 pipeline 中请求匹配器命中的规则（按配置顺序）
+    /// Rules of the selected pipeline whose request matchers matched, in config order
+    pub matched_rules: Vec<String>,
+    /// 是否由快速路径缓存应答 / Whether the fast-path cache answered
+    pub cache_hit: bool,
+    /// 发给客户端的最终报文 / Final packet sent to the client
+    pub bytes: Bytes,
+    pub message: Message,
+}
+
+impl Outcome {
+    pub fn rcode(&self) -> ResponseCode {
+        self.message.response_code()
+    }
+
+    /// 应答区中的 A/AAAA 地址 / A/AAAA addresses in the answer section
+    pub fn answer_ips(&self) -> Vec<IpAddr> {
+        self.message
+            .answers()
+            .iter()
+            .filter_map(|r| match r.data() {
+                Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+                Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// 在内存配置上运行 Engine 的测试工具 / Harness running an Engine over an in-memory config
+pub struct Harness {
+    engine: Engine,
+    client: IpAddr,
+    next_id: AtomicU16,
+}
+
+impl Harness {
+    /// 从配置 JSON 构建，校验与从文件加载时相同 / Build from config JSON, validated like a config file
+    pub fn from_json(raw: &str) -> anyhow::Result<Self> {
+        Self::new(parse_config(raw, "<test-support>")?)
+    }
+
+    pub fn new(cfg: PipelineConfig) -> anyhow::Result<Self> {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let runtime = RuntimePipelineConfig::from_config(cfg).context("compile matchers")?;
+        Ok(Self {
+            engine: Engine::new(runtime, "default".to_string()),
+            client: DEFAULT_CLIENT,
+            next_id: AtomicU16::new(1),
+        })
+    }
+
+    /// 之后的查询使用该客户端地址 / Use this client address for later queries
+    pub fn with_client(mut self, client: IpAddr) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// 发送一次查询并收集结果 / Send one query and collect its outcome
+    pub async fn query(&self, qname: &str, qtype: RecordType) -> anyhow::Result<Outcome> {
+        let name = Name::from_ascii(qname).with_context(|| format!("invalid qname {:?}", qname))?;
+        let mut msg = Message::new();
+        msg.set_id(self.next_id.fetch_add(1, Ordering::Relaxed)).set_recursion_desired(true);
+        msg.add_query(Query::query(name, qtype));
+        let packet = msg.to_vec()?;
+        let peer = SocketAddr::new(self.client, 53000);
+
+        let (pipeline, matched_rules) = self.trace_rules(qname, qtype);
+        let (bytes, cache_hit) = match self.engine.handle_packet_fast(&packet, peer)? {
+            Some(FastPathResponse::Direct(bytes)) => (bytes, false),
+            Some(FastPathResponse::CacheHit { cached, tx_id }) => {
+                let mut bytes = cached.to_vec();
+                bytes[..2].copy_from_slice(&tx_id.to_be_bytes());
+                (Bytes::from(bytes), true)
+            }
+            Some(FastPathResponse::AsyncNeeded { .. }) | None => (self.engine.handle_packet(&packet, peer).await?, false),
+        };
+        let message = Message::from_vec(&bytes).context("parse final packet")?;
+        Ok(Outcome {
+            pipeline,
+            matched_rules,
+            cache_hit,
+            bytes,
+            message,
+        })
+    }
+
+    /// 所选 pipeline 与其中请求匹配器命中的规则 / Selected pipeline and its rules whose request matchers match
+    fn trace_rules(&self, qname: &str, qtype: RecordType) -> (String, Vec<String>) {
+        let state = self.engine.state.load();
+        let qname = qname.trim_end_matches('.').to_ascii_lowercase();
+        let (pipeline, id) = select_pipeline(
+            &state.pipeline,
+            &qname,
+            self.client,
+            DNSClass::IN,
+            false,
+            qtype,
+            &self.engine.listener_label,
+            self.engine.listener_transport,
+            Some(&self.engine.geosite_manager),
+            Some(&self.engine.geoip_manager),
+        );
+        let Some(pipeline) = pipeline else {
+            return (id.to_string(), Vec::new());
+        };
+        let ctx = MatcherContext {
+            qname: &qname,
+            qclass: DNSClass::IN,
+            client_ip: self.client,
+            edns_present: false,
+            qtype,
+            geoip_manager: Some(&self.engine.geoip_manager),
+            geosite_manager: Some(&self.engine.geosite_manager),
+            regex_set: pipeline.regex_set.as_deref(),
+            regex_hits: Default::default(),
+        };
+        let matched = pipeline
+            .rules
+            .iter()
+            .filter(|rule| eval_match_chain(&rule.matchers, |m| m.operator, |m| matcher_matches(&m.matcher, &ctx)))
+            .map(|rule| rule.name.to_string())
+            .collect();
+        (id.to_string(), matched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_harness_reports_pipeline_rules_and_cache() {
+        // Arrange
+        let harness = Harness::from_json(
+            r#"{
+                "pipeline_select": [
+                    { "pipeline": "corp", "matchers": [{ "type": "domain_suffix", "value": "corp.example" }] }
+                ],
+                "pipelines": [
+                    { "id": "main", "rules": [
+                        { "name": "ads", "matchers": [{ "type": "domain_suffix", "value": "ads.example" }],
+                          "actions": [{ "type": "deny" }] },
+                        { "name": "all", "matchers": [{ "type": "any" }],
+                          "actions": [{ "type": "forward", "upstream": "mock://a=192.0.2.1&ttl=300" }] }
+                    ]},
+                    { "id": "corp", "rules": [
+                        { "name": "internal", "matchers": [{ "type": "any" }],
+                          "actions": [{ "type": "forward", "upstream": "mock://a=10.0.0.1" }] }
+                    ]}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        // Act
+        let first = harness.query("www.example.com.", RecordType::A).await.unwrap();
+        let second = harness.query("www.example.com.", RecordType::A).await.unwrap();
+        let blocked = harness.query("x.ads.example.", RecordType::A).await.unwrap();
+        let corp = harness.query("wiki.corp.example.", RecordType::A).await.unwrap();
+
+        // Assert
+        assert_eq!(first.pipeline, "main");
+        assert_eq!(first.matched_rules, ["all"]);
+        assert!(!first.cache_hit);
+        assert_eq!(first.answer_ips(), [IpAddr::from([192, 0, 2, 1])]);
+        assert!(second.cache_hit);
+        assert_eq!(second.message.id(), 2);
+        assert_eq!(second.answer_ips(), first.answer_ips());
+        assert_eq!(blocked.matched_rules, ["ads", "all"]);
+        assert_eq!(blocked.rcode(), ResponseCode::Refused);
+        assert_eq!(corp.pipeline, "corp");
+        assert_eq!(corp.answer_ips(), [IpAddr::from([10, 0, 0, 1])]);
+    }
+}
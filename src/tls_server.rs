@@ -0,0 +1,68 @@
+// TLS listener configuration shared by the encrypted DNS listeners
+// 加密 DNS 监听器共用的 TLS 配置
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// DNS-over-TLS 的 ALPN 协议标识（RFC 7858）/ ALPN protocol id of DNS-over-TLS (RFC 7858)
+pub const DOT_ALPN: &[u8] = b"dot";
+
+/// TLS 握手的超时时间，避免半开连接长期占用 / TLS handshake timeout, so half-open connections are not held forever
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 从 PEM 证书链与私钥创建 TLS acceptor，并声明给定的 ALPN 协议
+/// Build a TLS acceptor from a PEM certificate chain and private key, advertising the given ALPN protocols
+pub fn acceptor(cert_path: &str, key_path: &str, alpn: &[&[u8]]) -> anyhow::Result<TlsAcceptor> {
+    let certs = load_certs(cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(key_path).with_context(|| format!("read tls private key {}", key_path))?;
+
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("build tls server config")?;
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// 双向 TLS 的 acceptor 与 connector：两端都出示本端证书，并以 ca_path 中的 CA 校验对端
+/// Mutual TLS acceptor and connector: both sides present their own certificate and verify the
+/// peer's against the CA in ca_path
+pub fn mutual(cert_path: &str, key_path: &str, ca_path: &str) -> anyhow::Result<(TlsAcceptor, TlsConnector)> {
+    let certs = load_certs(cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(key_path).with_context(|| format!("read tls private key {}", key_path))?;
+    let mut roots = RootCertStore::empty();
+    for ca in load_certs(ca_path)? {
+        roots.add(ca).with_context(|| format!("add tls ca {}", ca_path))?;
+    }
+    let roots = Arc::new(roots);
+
+    let verifier = WebPkiClientVerifier::builder(Arc::clone(&roots))
+        .build()
+        .context("build tls client verifier")?;
+    let server = ServerConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs.clone(), key.clone_key())
+        .context("build tls server config")?;
+    let client = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_client_auth_cert(certs, key)
+        .context("build tls client config")?;
+    Ok((TlsAcceptor::from(Arc::new(server)), TlsConnector::from(Arc::new(client))))
+}
+
+fn load_certs(path: &str) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("read tls certificate {}", path))?;
+    if certs.is_empty() {
+        anyhow::bail!("no certificate found in {}", path);
+    }
+    Ok(certs)
+}
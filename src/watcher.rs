@@ -1,11 +1,13 @@
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::thread;
 
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 use tracing::{error, info, warn};
 
-use crate::config;
+use crate::config::{self, PipelineConfig};
 use crate::engine::Engine;
+use crate::engine::reload_events::ConfigDiff;
 use crate::matcher::RuntimePipelineConfig;
 
 pub fn spawn(path: PathBuf, engine: Engine) {
@@ -17,12 +19,71 @@ pub fn spawn(path: PathBuf, engine: Engine) {
     });
 }
 
+/// 配置引用的数据文件（CIDR 列表）及其所在目录
+/// Data files referenced by the config (CIDR lists) and their directories
+///
+/// 监听目录而非文件本身，这样以重命名方式原子替换的文件也能触发重载。
+/// Directories are watched instead of the files themselves so files replaced atomically
+/// via rename still trigger a reload.
+#[derive(Default)]
+struct DataFiles {
+    files: HashSet<PathBuf>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl DataFiles {
+    /// 按新配置更新监听的目录 / Update the watched directories for a new config
+    fn sync(&mut self, watcher: &mut RecommendedWatcher, cidr_files: &[String]) {
+        let files: HashSet<PathBuf> = cidr_files
+            .iter()
+            .filter_map(|f| std::fs::canonicalize(f).ok())
+            .collect();
+        let dirs: HashSet<PathBuf> = files
+            .iter()
+            .filter_map(|f| f.parent().map(Path::to_path_buf))
+            .collect();
+        for dir in self.dirs.difference(&dirs) {
+            let _ = watcher.unwatch(dir);
+        }
+        for dir in dirs.difference(&self.dirs) {
+            if let Err(err) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+                warn!(target = "watcher", path = %dir.display(), error = %err, "failed to watch data file directory");
+            }
+        }
+        self.files = files;
+        self.dirs = dirs;
+    }
+
+    fn contains_any(&self, paths: &[PathBuf]) -> bool {
+        paths.iter().any(|p| self.files.contains(p))
+    }
+}
+
+/// 从文件加载、编译并应用配置，成功时记录重载事件并返回配置及其差异；失败时由调用方记录
+/// Load, compile and apply the config from file; on success the reload event is recorded and
+/// the config and its diff are returned, failures are left for the caller to record
+pub fn reload_from_file(path: &Path, engine: &Engine) -> anyhow::Result<(PipelineConfig, Option<ConfigDiff>)> {
+    let cfg = config::load_config(path)?;
+    let runtime = RuntimePipelineConfig::from_config(cfg.clone())?;
+    engine.reload(runtime);
+    let diff = engine.reload_events.record_success(&cfg);
+    Ok((cfg, diff))
+}
+
 fn run_watcher(path: PathBuf, engine: Engine) -> notify::Result<()> {
     let (tx, rx) = std::sync::mpsc::channel();
     let mut watcher: RecommendedWatcher = Watcher::new(tx, Config::default())?;
     watcher.watch(&path, RecursiveMode::NonRecursive)?;
 
-    info!(target = "watcher", path = %path.display(), "config watcher started");
+    let mut data_files = DataFiles::default();
+    // 启动时的配置作为第一次重载差异的基准 / The startup config is the base for the first reload diff
+    if let Ok(cfg) = config::load_config(&path) {
+        data_files.sync(&mut watcher, &cfg.cidr_files());
+        engine.reload_events.set_current(cfg);
+    }
+    let canonical_path = std::fs::canonicalize(&path).ok();
+
+    info!(target = "watcher", path = %path.display(), data_files = data_files.files.len(), "config watcher started");
 
     for res in rx {
         match res {
@@ -31,21 +92,28 @@ fn run_watcher(path: PathBuf, engine: Engine) -> notify::Result<()> {
                 if !event.kind.is_modify() && !event.kind.is_create() {
                     continue;
                 }
+                // 数据文件目录中的其它文件不触发重载 / Other files in data file directories do not trigger a reload
+                let is_config = event
+                    .paths
+                    .iter()
+                    .any(|p| p == &path || canonical_path.as_ref() == Some(p));
+                if !is_config && !data_files.contains_any(&event.paths) {
+                    continue;
+                }
 
                 // Simple retry mechanism to handle file write races (e.g. truncate+write) / 简单的重试机制来处理文件写入竞争（如截断+写入）
                 let mut retries = 5;
                 while retries > 0 {
-                    match config::load_config(&path)
-                        .and_then(RuntimePipelineConfig::from_config)
-                    {
-                        Ok(new_cfg) => {
-                            engine.reload(new_cfg);
+                    match reload_from_file(&path, &engine) {
+                        Ok((cfg, _)) => {
+                            data_files.sync(&mut watcher, &cfg.cidr_files());
                             info!(target = "watcher", path = %path.display(), "config reloaded");
                             break;
                         }
                         Err(err) => {
                             retries -= 1;
                             if retries == 0 {
+                                engine.reload_events.record_failure(&err);
                                 warn!(target = "watcher", path = %path.display(), error = %err, "config reload failed, keeping old config");
                             } else {
                                 // Wait a bit and retry / 稍等后重试